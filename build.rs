@@ -1,6 +1,11 @@
 use std::env;
 
 fn main() {
+    // EFI applications are linked as PE images by the target's own linker.
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("uefi") {
+        return
+    }
+
     let current_dir = env::current_dir().expect("Failed to get current directory");
     println!("cargo:rustc-link-search=native={}/build", current_dir.as_path().to_string_lossy());
     println!("cargo:rustc-link-arg=-T{}/src/arch/x86_64/linker.ld", current_dir.as_path().to_string_lossy());
@@ -0,0 +1,41 @@
+import re, struct, subprocess, sys, tempfile
+
+# Embeds the function symbols of the linked kernel into it's `.kallsyms` section.
+# The layout is described in src/kernel_components/kallsyms.rs.
+
+MAGIC = b'KSYM'
+HASH_SUFFIX = re.compile(r'::h[0-9a-f]{16}$')
+
+if len(sys.argv) != 2:
+    sys.exit("usage: kallsyms.py <kernel>")
+kernel = sys.argv[1]
+
+size = None
+for line in subprocess.run(['objdump', '-h', '-w', kernel], capture_output=True, text=True, check=True).stdout.splitlines():
+    fields = line.split()
+    if len(fields) > 2 and fields[1] == '.kallsyms':
+        size = int(fields[2], 16)
+if size is None:
+    sys.exit(f"{kernel} has no .kallsyms section")
+
+symbols = {}
+for line in subprocess.run(['nm', '--defined-only', '-C', kernel], capture_output=True, text=True, check=True).stdout.splitlines():
+    fields = line.split(' ', 2)
+    if len(fields) == 3 and fields[1] in 'tTwW':
+        symbols.setdefault(int(fields[0], 16), HASH_SUFFIX.sub('', fields[2]))
+
+entries, names = b'', b''
+for addr, name in sorted(symbols.items()):
+    name = name.encode()
+    entries += struct.pack('<QII', addr, len(names), len(name))
+    names += name
+
+table = MAGIC + struct.pack('<I', len(symbols)) + entries + names
+if len(table) > size:
+    sys.exit(f"symbol table takes {len(table)} bytes, but only {size} are reserved, raise KALLSYMS_SIZE")
+
+# The section keeps it's size, so no address of the image moves.
+with tempfile.NamedTemporaryFile() as file:
+    file.write(table.ljust(size, b'\0'))
+    file.flush()
+    subprocess.run(['objcopy', f'--update-section=.kallsyms={file.name}', kernel], check=True)
@@ -1,10 +1,19 @@
 global initiate
 extern long_mode_start
 
+; link address of physical memory, see memory::layout::KERNEL_OFFSET and linker.ld
+KERNEL_OFFSET equ 0xffffffff80000000
+; P4 and P3 entries of KERNEL_OFFSET
+KERNEL_P4_INDEX equ 511
+KERNEL_P3_INDEX equ 510
+; the last P4 entry holds the kernel, see memory::layout::RECURSIVE_INDEX
+RECURSIVE_INDEX equ 510
+
 section .loader
 bits 32
 initiate:
-    mov esp, stack_top
+    ; paging is off, so everything outside of the loader is reached by it's physical address
+    mov esp, stack_top - KERNEL_OFFSET
     mov edi, ebx
 
     call check_multiboot
@@ -79,20 +88,28 @@ check_long_mode:
     jmp error
 
 set_up_page_tables:
-    ; map first entry to P4 table
-    mov eax, p4_table
+    ; map recursive entry to P4 table
+    mov eax, p4_table - KERNEL_OFFSET
     or eax, 0b11 ; present + writable
-    mov [p4_table + 511 * 8], eax
+    mov [p4_table - KERNEL_OFFSET + RECURSIVE_INDEX * 8], eax
 
     ; map first P4 entry to P3 table
-    mov eax, p3_table
+    mov eax, p3_table - KERNEL_OFFSET
+    or eax, 0b11 ; present + writable
+    mov [p4_table - KERNEL_OFFSET], eax
+
+    ; map last P4 entry to the P3 table of the kernel
+    mov eax, p3_kernel_table - KERNEL_OFFSET
     or eax, 0b11 ; present + writable
-    mov [p4_table], eax
+    mov [p4_table - KERNEL_OFFSET + KERNEL_P4_INDEX * 8], eax
 
     ; map first P3 entry to P2 table
-    mov eax, p2_table
+    mov eax, p2_table - KERNEL_OFFSET
     or eax, 0b11 ; present + writable
-    mov [p3_table], eax
+    mov [p3_table - KERNEL_OFFSET], eax
+
+    ; the same P2 table maps the first GiB at KERNEL_OFFSET
+    mov [p3_kernel_table - KERNEL_OFFSET + KERNEL_P3_INDEX * 8], eax
 
     ; map each P2 entry to a huge 2MiB page
     mov ecx, 0         ; counter variable
@@ -102,7 +119,7 @@ set_up_page_tables:
     mov eax, 0x200000  ; 2MiB
     mul ecx            ; start address of ecx-th page
     or eax, 0b10000011 ; present + writable + huge
-    mov [p2_table + ecx * 8], eax ; map ecx-th entry
+    mov [p2_table - KERNEL_OFFSET + ecx * 8], eax ; map ecx-th entry
 
     inc ecx            ; increase counter
     cmp ecx, 512       ; if counter == 512, the whole P2 table is mapped
@@ -112,7 +129,7 @@ set_up_page_tables:
 
 enable_paging:
     ; load P4 to cr3 register (cpu uses this to access the P4 table)
-    mov eax, p4_table
+    mov eax, p4_table - KERNEL_OFFSET
     mov cr3, eax
 
     ; enable PAE-flag in cr4 (Physical Address Extension)
@@ -142,7 +159,7 @@ error:
     mov byte  [0xb800a], al
     hlt
 
-section .rodata
+; the GDT is loaded before paging, so it stays with the loader
 gdt64:
     dq 0 ; zero entry
 .code: equ $ - gdt64 ; new
@@ -157,6 +174,8 @@ p4_table:
     resb 4096
 p3_table:
     resb 4096
+p3_kernel_table:
+    resb 4096
 p2_table:
     resb 4096
 stack_bottom:
@@ -1,5 +1,8 @@
 global long_mode_start
 
+; link address of physical memory, see boot.asm
+KERNEL_OFFSET equ 0xffffffff80000000
+
 section .loader
 bits 64
 long_mode_start:
@@ -11,5 +14,11 @@ long_mode_start:
     mov fs, ax
     mov gs, ax
 
+    ; continue on the higher half alias of the boot stack
+    mov rax, KERNEL_OFFSET
+    add rsp, rax
+
+    ; the kernel is linked to the higher half, out of reach of a relative call
     extern _start
-    call _start
+    mov rax, _start
+    call rax
@@ -9,9 +9,18 @@ header_start:
     ; checksum
     dd 0x100000000 - (0xe85250d6 + 0 + (header_end - header_start))
 
-    ; insert optional multiboot tags here
+    ; framebuffer tag, optional so the text mode still boots
+    ; the mode itself is picked with gfxpayload in grub.cfg
+    align 8, db 0
+    dw 5    ; type
+    dw 1    ; flags (optional)
+    dd 20   ; size
+    dd 0    ; width (no preference)
+    dd 0    ; height (no preference)
+    dd 0    ; depth (no preference)
 
     ; required end tag
+    align 8, db 0
     dw 0    ; type
     dw 0    ; flags
     dd 8    ; size
@@ -5,7 +5,8 @@
 /// to handle, like the amount of running threads for example. ACPI contains of
 /// different tables like RSDP, BGRT, FADT etc.
 
-use core::{error::Error, fmt::Display};
+use core::{error::Error, fmt::Display, mem};
+use core::sync::atomic::{AtomicBool, Ordering};
 use alloc::{borrow::ToOwned, fmt, string::String};
 use crate::{
     bitflags, 
@@ -25,20 +26,58 @@ pub trait SystemDescriptionTable {
     /// own signature and it is written in memory in table headers.
     const SIGNATURE: &'static str;
 
+    /// Smallest valid length of the table. Shorter tables are treated as truncated.
+    const MIN_LENGTH: usize = mem::size_of::<ACPISDTHeader>();
+
     /// Performs full SDT validation and will provide info about any error that occur.
     ///
     /// Validate SDT based on it's checksum provided in ACPI DST Header. Errors can be 
     /// returned based on table type. All tables must be validated, even if they were obtained 
     /// by the link of other tables.
     fn validate(header: &ACPISDTHeader) -> Result<(), SDTValidationError> {
+        unsafe { Self::check(header, header as *const _ as *const u8) }
+    }
+
+    /// Validates the table at the address and returns a copy of its header.
+    ///
+    /// Unlike [`SystemDescriptionTable::validate`], the table may be unaligned. Failures are logged
+    /// and mark ACPI as degraded, so the caller only has to skip the table.
+    ///
+    /// # Safety
+    ///
+    /// The header must be readable. The rest of the table is only read if the length is sane.
+    unsafe fn validate_at(ptr: *const ACPISDTHeader) -> Result<ACPISDTHeader, SDTValidationError> {
+        let result = if ptr.is_null() {
+            Err(SDTValidationError::NullPointer)
+        } else {
+            let header = ptr.read_unaligned();
+            Self::check(&header, ptr.cast()).map(|_| header)
+        };
+
+        if let Err(err) = result {
+            report_invalid(Self::SIGNATURE, ptr as usize, err);
+        }
+        result
+    }
+
+    /// Checks the header and the checksum of the table bytes, which start with this header.
+    ///
+    /// # Safety
+    ///
+    /// The table must be readable for the length given by the header.
+    unsafe fn check(header: &ACPISDTHeader, table: *const u8) -> Result<(), SDTValidationError> {
         use SDTValidationError::*;
 
         // Signature check.
         if header.signature != *Self::SIGNATURE.as_bytes() {
             return Err(Signature(header.signature))
         }
+        // Length check, so that truncated tables are never read past their end.
+        if (header.length as usize) < Self::MIN_LENGTH || header.length > MAX_TABLE_LENGTH {
+            return Err(Length(header.length))
+        }
         // Checksum check.
-        if !Self::checksum(header) {
+        if checksum(table, header.length as usize) != 0 {
             return Err(CHECKSUM)
         }
 
@@ -47,18 +86,32 @@ pub trait SystemDescriptionTable {
 
     /// Makes checksum manually, Return true if everything is right.
     fn checksum(header: &ACPISDTHeader) -> bool {
-        let slice: &[u8] = unsafe {
-            core::slice::from_raw_parts(
-                header as *const _ as *const u8,
-                header.length as usize,
-            )
-        };
-
-        // All bytes in the structure must sum up to zero.
-        slice.iter().fold(0u8, |s, b| s.wrapping_add(*b)) == 0
+        unsafe { checksum(header as *const _ as *const u8, header.length as usize) == 0 }
     }
 }
 
+/// Upper bound for the length of a single table. Larger values come from corrupted headers.
+pub const MAX_TABLE_LENGTH: u32 = 0x100_0000;
+
+/// Set once any ACPI structure fails validation.
+static DEGRADED: AtomicBool = AtomicBool::new(false);
+
+/// Sums all bytes of the structure. All bytes of a valid structure, including its checksum field,
+/// sum up to zero.
+///
+/// # Safety
+///
+/// The memory must be readable for len bytes.
+pub unsafe fn checksum(ptr: *const u8, len: usize) -> u8 {
+    core::slice::from_raw_parts(ptr, len).iter().fold(0u8, |s, b| s.wrapping_add(*b))
+}
+
+/// Logs the structure which failed validation and marks ACPI as degraded.
+pub(crate) fn report_invalid(name: &str, addr: usize, err: impl Display) {
+    DEGRADED.store(true, Ordering::Relaxed);
+    crate::warn!("ACPI: {} at {:#x} is ignored: {}", name, addr, err);
+}
+
 /// Advanced Configuration and Power Interface.
 ///
 /// This struct virtualize all interactions with ACPI interface and it's tables by
@@ -85,6 +138,14 @@ pub mod acpi_service {
     /// return some custom type or ACPIError.
     type ACPIResult<T> = Result<T, ACPIError>;
 
+    /// Returns true if some ACPI structure failed validation.
+    ///
+    /// Invalid tables are skipped, so ACPI keeps working with the remaining ones, but features
+    /// described by the skipped tables are not available.
+    pub fn is_degraded() -> bool {
+        super::DEGRADED.load(core::sync::atomic::Ordering::Relaxed)
+    }
+
     /// Shutdowns the machine in a proper way.
     ///
     /// # Warn
@@ -183,6 +244,10 @@ pub enum SDTValidationError {
     CHECKSUM,
     /// The signature does not match the trait's signature and therefore wrong.
     Signature([UChar; 4]),
+    /// The length is shorter than the fixed part of the table or too big to be real.
+    Length(u32),
+    /// The table pointer is zero.
+    NullPointer,
 }
 
 impl Error for SDTValidationError {}
@@ -192,6 +257,8 @@ impl Display for SDTValidationError {
         match self {
             Self::CHECKSUM => write!(f, "Table's checksum doesn't match."),
             Self::Signature(s) => write!(f, "Table's signature doesn't match: {:?}", s),
+            Self::Length(l) => write!(f, "Table's length is invalid: {}", l),
+            Self::NullPointer => write!(f, "Table's pointer is null."),
         }
     }
 }
@@ -240,3 +307,45 @@ bitflags! {
         // Addresses 0x80 t0 0xbf are reserved.
     };
 }
+
+#[test_case]
+fn sdt_validation_rejects_broken_tables() {
+    use super::diff::DSDT;
+
+    #[repr(C, align(8))]
+    struct Table([u8; 512]);
+
+    // Writes the header and fixes the checksum.
+    fn table(signature: &[u8; 4], length: u32, fill: impl Fn(&mut [u8])) -> Table {
+        let mut table = Table([0; 512]);
+        table.0[..4].copy_from_slice(signature);
+        table.0[4..8].copy_from_slice(&length.to_le_bytes());
+        fill(&mut table.0);
+        table.0[9] = 0u8.wrapping_sub(unsafe { checksum(table.0.as_ptr(), length as usize) });
+        table
+    }
+    let header = |table: &Table| table.0.as_ptr().cast::<ACPISDTHeader>();
+
+    let mut dsdt = table(b"DSDT", 48, |bytes| bytes[40] = 0x10);
+    assert_eq!(unsafe { DSDT::validate_at(header(&dsdt)) }.map(|h| h.length), Ok(48));
+    dsdt.0[41] = 1;
+    assert_eq!(unsafe { DSDT::validate_at(header(&dsdt)) }.err(), Some(SDTValidationError::CHECKSUM));
+    let truncated = table(b"DSDT", 8, |_| ());
+    assert_eq!(unsafe { DSDT::validate_at(header(&truncated)) }.err(), Some(SDTValidationError::Length(8)));
+    assert!(matches!(unsafe { FADT::validate_at(header(&dsdt)) }, Err(SDTValidationError::Signature(s)) if s == *b"DSDT"));
+    assert_eq!(unsafe { DSDT::validate_at(core::ptr::null()) }.err(), Some(SDTValidationError::NullPointer));
+    assert!(acpi_service::is_degraded());
+
+    // X_DSDT is only read from tables which are long enough to contain it.
+    let fill = |bytes: &mut [u8]| {
+        bytes[40..44].copy_from_slice(&0x1000u32.to_le_bytes());
+        bytes[140..148].copy_from_slice(&0x2000u64.to_le_bytes());
+    };
+    let fadt = table(b"FACP", 148, fill);
+    let legacy_fadt = table(b"FACP", 116, fill);
+    let fadt = unsafe { &*header(&fadt).cast::<FADT>() };
+    let legacy_fadt = unsafe { &*header(&legacy_fadt).cast::<FADT>() };
+    assert_eq!(fadt.x_dsdt(), Some(0x2000));
+    assert_eq!(legacy_fadt.x_dsdt(), None);
+    assert_eq!(unsafe { FADT::validate_at(header(&table(b"FACP", 100, |_| ()))) }.err(), Some(SDTValidationError::Length(100)));
+}
@@ -43,6 +43,11 @@ impl AMLInterpreter {
         self.parser.parse(aml_stream)
     }
 
+    /// Returns the paths of all devices defined in the namespace.
+    pub fn devices(&self) -> alloc::vec::Vec<alloc::string::String> {
+        self.parser.devices()
+    }
+
     /// Creates a new AML interpreter with empty namespace.
     fn new_empty() -> Self {
         Self {
@@ -1,6 +1,6 @@
 /// Defines an ACPI namespace and it's components.
 
-use alloc::{collections::BTreeMap, vec::Vec};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
 use super::{
     aml::{NameSeg, DUAL_NAME_PREFIX, PARENT_PREFIX_CHAR, ROOT_CHAR}, objects::ACPIObject, parser::PkgLength, AMLParserError, AMLResult
 };
@@ -41,7 +41,6 @@ impl ACPINamespace {
                     if let Some(next_ns) = iter.next() {
                         match next_ns {
                             NamespacePath::NamePath(nameseg) => {
-                                crate::println!("test: {:#?}", nameseg);
                                 self.root().populate_layer(nameseg, ltype);
                                 self.current_seg = nameseg;    
                             },
@@ -63,7 +62,7 @@ impl ACPINamespace {
                     }
                 }
                 NamespacePath::ParentPrefixChar => {
-                    unimplemented!()
+                    return Err(AMLParserError::Unsupported)
                 },
                 NamespacePath::NamePath(nameseg) => {
                     if let Some(layer) = self.root().children.get_mut(&nameseg) {
@@ -92,6 +91,13 @@ impl ACPINamespace {
     pub fn root(&mut self) -> &mut NamespaceLayer {
         &mut self.root
     }
+
+    /// Returns the absolute paths of every device layer, i.e `\_SB_.PCI0`.
+    pub fn devices(&self) -> Vec<String> {
+        let mut devices = Vec::new();
+        self.root.collect_devices(&mut String::from("\\"), &mut devices);
+        devices
+    }
 }
 
 /// Defines a namespace layer.
@@ -129,6 +135,23 @@ impl NamespaceLayer {
         }
     }
 
+    /// Pushes the paths of device sub-layers into the vector. The path is the one of this layer.
+    fn collect_devices(&self, path: &mut String, devices: &mut Vec<String>) {
+        for (nameseg, layer) in self.children.iter() {
+            let len = path.len();
+            if len > 1 {
+                path.push('.');
+            }
+            nameseg.0.iter().for_each(|c| path.push(c.0 as char));
+
+            if layer.r#type == LayerType::Device {
+                devices.push(path.clone());
+            }
+            layer.collect_devices(path, devices);
+            path.truncate(len);
+        }
+    }
+
     /// Adds one new object to this particular layer.
     ///
     /// # Error
@@ -177,7 +200,6 @@ impl NameString {
         let mut separator = false;
 
         while let Some(byte) = iter.next() {
-            crate::println!("[{}]", byte);
             let path = match *byte {
                 0x0                 => {separator = false; NamespacePath::NullChar}, 
                 ROOT_CHAR           => {separator = true; NamespacePath::RootChar},
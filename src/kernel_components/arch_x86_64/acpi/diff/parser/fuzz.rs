@@ -0,0 +1,149 @@
+/// Deterministic fuzzing of the AML parser.
+///
+/// Firmware AML is the least trustworthy input parsed by the kernel, so the parser must reject
+/// malformed streams with an [`AMLParserError`] instead of panicking. A valid sample laid out like
+/// the beginning of the QEMU (i440fx) DSDT is mutated with a seeded xorshift generator, so every
+/// failure is reproducible from the seed and the iteration number.
+
+use core::ptr::addr_of_mut;
+
+use crate::kernel_components::arch_x86_64::acpi::diff::{aml::AMLStream, namespace::ACPINamespace};
+
+use super::aml_parser::Parsed;
+use super::{AMLParser, AMLParserError, PkgLength};
+
+/// Hand-assembled AML in the layout of the QEMU DSDT.
+///
+/// ```asl
+/// Scope (\_SB) {
+///     Device (PCI0) {
+///         Name (_HID, EisaId ("PNP0A03"))
+///         Name (_ADR, Zero)
+///         Name (_UID, Zero)
+///     }
+/// }
+/// Name (_S5, Package (0x04) { 0x05, 0x05, Zero, Zero })
+/// ```
+pub const QEMU_AML: [u8; 51] = [
+    0x10, 0x23, 0x5C, 0x5F, 0x53, 0x42, 0x5F,
+    0x5B, 0x82, 0x1B, 0x50, 0x43, 0x49, 0x30,
+    0x08, 0x5F, 0x48, 0x49, 0x44, 0x0C, 0x41, 0xD0, 0x0A, 0x03,
+    0x08, 0x5F, 0x41, 0x44, 0x52, 0x00,
+    0x08, 0x5F, 0x55, 0x49, 0x44, 0x00,
+    0x08, 0x5F, 0x53, 0x35, 0x5F, 0x12, 0x08, 0x04, 0x0A, 0x05, 0x0A, 0x05, 0x00, 0x00,
+    0xA3,
+];
+
+/// Bytes with a special meaning for the parser, which are more likely to hit edge cases.
+const INTERESTING: [u8; 10] = [0x00, 0x10, 0x2E, 0x5B, 0x5C, 0x5E, 0x40, 0x80, 0xC0, 0xFF];
+
+/// Maximal length of the mutated stream.
+const MAX_LEN: usize = 128;
+
+/// Mutated streams are stored here, because the parser only accepts static slices.
+static mut BUFFER: [u8; MAX_LEN] = [0; MAX_LEN];
+
+/// Xorshift64 generator. Not random at all, which is the point.
+#[derive(Debug, Clone)]
+pub struct XorShift(u64);
+
+impl XorShift {
+    /// Creates the generator. Zero seed would only produce zeroes, so it is replaced.
+    pub fn new(seed: u64) -> Self {
+        Self(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+
+    /// Returns the next number.
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in range 0..bound.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound.max(1) as u64) as usize
+    }
+}
+
+/// Writes a mutation of the sample into the static buffer and returns it.
+fn mutate(rng: &mut XorShift, sample: &[u8]) -> &'static [u8] {
+    let buffer = unsafe { &mut *addr_of_mut!(BUFFER) };
+    let mut len = sample.len().min(MAX_LEN);
+    buffer[..len].copy_from_slice(&sample[..len]);
+
+    for _ in 0..1 + rng.below(4) {
+        let at = rng.below(len);
+        match rng.below(5) {
+            // Flipping a single bit.
+            0 => buffer[at] ^= 1 << rng.below(8),
+            // Replacing with a special byte.
+            1 => buffer[at] = INTERESTING[rng.below(INTERESTING.len())],
+            // Inserting a random byte.
+            2 if len < MAX_LEN => {
+                buffer.copy_within(at..len, at + 1);
+                buffer[at] = rng.next() as u8;
+                len += 1;
+            },
+            // Removing a byte.
+            3 if len > 1 => {
+                buffer.copy_within(at + 1..len, at);
+                len -= 1;
+            },
+            // Truncating the stream.
+            _ => len = at.max(1),
+        }
+    }
+    unsafe { &(*addr_of_mut!(BUFFER))[..len] }
+}
+
+/// Feeds mutations of the sample to the parser.
+///
+/// Returns the amount of streams rejected with an error. Panics inside of the parser are the
+/// failures this harness is looking for.
+pub fn fuzz_parser(seed: u64, iterations: usize) -> usize {
+    let mut rng = XorShift::new(seed);
+    let mut parser = AMLParser::new();
+    parser.set_trace(false);
+
+    (0..iterations)
+        .filter(|_| parser.parse(&AMLStream(mutate(&mut rng, &QEMU_AML))).is_err())
+        .count()
+}
+
+/// Feeds random package length encodings to the decoder.
+pub fn fuzz_pkg_length(seed: u64, iterations: usize) {
+    let mut rng = XorShift::new(seed);
+    let mut namespace = ACPINamespace::blank();
+
+    for _ in 0..iterations {
+        let len = rng.below(6);
+        let bytes = unsafe { &mut *addr_of_mut!(BUFFER) };
+        bytes[..len].iter_mut().for_each(|byte| *byte = rng.next() as u8);
+
+        let mut ptr = 0;
+        if let Ok(pkg) = PkgLength::parse(&mut ptr, unsafe { &(*addr_of_mut!(BUFFER))[..len] }, &mut namespace) {
+            assert!(ptr <= len && pkg.len() < 1 << 28);
+        }
+    }
+}
+
+#[test_case]
+fn aml_parser_survives_mutations() {
+    let mut parser = AMLParser::new();
+    parser.set_trace(false);
+    assert_eq!(parser.parse(&AMLStream(&QEMU_AML)), Ok(()));
+
+    // Truncated extended opcode and package length.
+    assert_eq!(parser.parse(&AMLStream(&[0x5B])), Err(AMLParserError::UnexpectedEndOfStream));
+    assert_eq!(parser.parse(&AMLStream(&[0x10])), Err(AMLParserError::NotEnoughBytes));
+    assert_eq!(parser.parse(&AMLStream(&[0x10, 0xC0, 0x01])), Err(AMLParserError::NotEnoughBytes));
+    assert_eq!(parser.parse(&AMLStream(&[0x10, 0x50, 0x01])), Err(AMLParserError::InvalidResevedBits));
+    assert_eq!(parser.parse(&AMLStream(&[0x5B, 0x7F])), Err(AMLParserError::UnexpectedToken));
+
+    for seed in [1, 0xacb1, 0xdead_beef] {
+        fuzz_parser(seed, 512);
+        fuzz_pkg_length(seed, 512);
+    }
+}
@@ -17,9 +17,17 @@ use super::{aml_parser::Parsed, AMLParserError};
 #[derive(Debug, Clone)]
 pub struct PkgLength(u32);
 
+impl PkgLength {
+    /// Returns the encoded length in bytes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0 as usize
+    }
+}
+
 impl Parsed for PkgLength {
     fn parse(ptr: &mut usize, bytes: &'static [u8], _: &mut ACPINamespace) -> super::aml_parser::AMLParserResult<Self> {
-        let lead_byte = bytes[1]; // Starting from 1 because 0 is the opcode.
+        let lead_byte = *bytes.get(1).ok_or(AMLParserError::NotEnoughBytes)?; // Starting from 1 because 0 is the opcode.
         let follow_byte_count = (lead_byte >> 6) & 0b11; // Bits 7-6
         let reserved_bits = (lead_byte >> 4) & 0b11;     // Bits 5-4
         let mut lsb = (lead_byte & 0x0f) as u32;         // Bits 3-0
@@ -29,18 +37,18 @@ impl Parsed for PkgLength {
             lsb |= (reserved_bits as u32) << 4;
         } else {
             // Reserved bits must be zero for multi-byte encoding.
-            if reserved_bits != 0 && follow_byte_count > 0 {
+            if reserved_bits != 0 {
                 return Err(AMLParserError::InvalidResevedBits) 
             }
 
             // If length is not enough to read following bytes, then something went wrong.
-            if follow_byte_count as usize > bytes.len() - 1 {
+            if bytes.len() < follow_byte_count as usize + 2 {
                 return Err(AMLParserError::NotEnoughBytes)
             }
 
-            /// Bits 3-0 are LSB. Next 8 bits of n bytes are the next LSB.
-            for i in 2..follow_byte_count + 2 {
-                lsb |= (bytes[i as usize + 1] as u32) << (4 + ((i - 2) * 8)); // Each next byte pushed.
+            // Bits 3-0 are LSB. Next 8 bits of n bytes are the next LSB.
+            for i in 0..follow_byte_count as usize {
+                lsb |= (bytes[i + 2] as u32) << (4 + i * 8); // Each next byte pushed.
             }
         }
 
@@ -5,9 +5,11 @@
 /// required for locating one.
 
 use crate::bitflags;
-use super::acpi::{ACPISDTHeader, GenericAddressStructure, SDTValidationError, SystemDescriptionTable};
+use super::acpi::{ACPISDTHeader, GenericAddressStructure, SDTValidationError, SystemDescriptionTable, MAX_TABLE_LENGTH};
 use super::diff::DSDT;
+use super::rsdt::{RSDT, XSDT};
 use proc_macros::public;
+use core::mem;
 
 /// Fixed ACPI Description Table (FADT/FACP)
 ///
@@ -131,7 +133,7 @@ pub struct FADT {
     _reserved3: [u8; 3],
     /// Extended physical address of the FACS. 
     X_FIRMWARE_CONTROL: u64,
-    /// Extended physical addtess of DSDT.
+    /// Extended physical addtess of DSDT. Read with [`FADT::x_dsdt`].
     X_DSDT: u64,
 
     // Extended addresses of PM blocks.
@@ -146,42 +148,113 @@ pub struct FADT {
     X_GPE1_BLOCK: GenericAddressStructure,
 }
 
+/// Offset of X_DSDT within the table, as defined by the specification.
+///
+/// The field is read by offset, because the FADT of ACPI 1.0 ends before it and the layout of the
+/// structure above does not follow the packed layout of the specification.
+const X_FIRMWARE_CONTROL_OFFSET: usize = 132;
+const X_DSDT_OFFSET: usize = 140;
+
 impl FADT {
+    /// Finds the FADT through the XSDT, or the RSDT on ACPI 1.0 systems.
+    pub fn find() -> Option<&'static FADT> {
+        let found = match XSDT::try_new() {
+            Ok(xsdt) => xsdt.find::<FADT>().map(|fadt| fadt.map(|fadt| fadt as *const FADT)),
+            Err(_) => RSDT::try_new().ok()?.find::<FADT>().map(|fadt| fadt.map(|fadt| fadt as *const FADT)),
+        };
+        // Tables stay mapped in the firmware memory until the MMU reclaims it, no root table is
+        // found afterwards.
+        found.ok().flatten().map(|fadt| unsafe { &*fadt })
+    }
+
+    /// Returns the physical address of the FACS, preferring the 64-bit X_FIRMWARE_CONTROL field.
+    pub fn facs(&self) -> Option<usize> {
+        if (self.header.length as usize) >= X_FIRMWARE_CONTROL_OFFSET + 8 {
+            let addr = unsafe {
+                (self as *const Self).cast::<u8>().add(X_FIRMWARE_CONTROL_OFFSET).cast::<u64>().read_unaligned()
+            };
+            if addr != 0 {
+                return Some(addr as usize)
+            }
+        }
+        (self.firmware_ctrl != 0).then_some(self.firmware_ctrl as usize)
+    }
+
+    /// Returns the ports of the PM1a and the optional PM1b event register blocks.
+    pub fn pm1_event_blocks(&self) -> (u16, Option<u16>) {
+        (self.pm1a_event_block as u16, (self.pm1b_event_block != 0).then_some(self.pm1b_event_block as u16))
+    }
+
+    /// Returns the ports of the PM1a and the optional PM1b control register blocks.
+    pub fn pm1_control_blocks(&self) -> (u16, Option<u16>) {
+        (self.pm1a_control_block as u16, (self.pm1b_control_block != 0).then_some(self.pm1b_control_block as u16))
+    }
+
+    /// Returns the length of the PM1 event register blocks in bytes. The status register takes
+    /// the first half, the enable register the second one.
+    pub fn pm1_event_length(&self) -> u8 {
+        self.pm1_event_length
+    }
+
     /// Obtains the DSDT table from the legacy 32-bit pointer located in FADT.
     ///
-    /// This functions automatically maps DSDT's page to prevent page fault, validates the DSDT's
-    /// header and returns the table. DSDT is often corrupted, because it is included by vendor,
+    /// This functions automatically maps DSDT's pages to prevent page fault, validates the DSDT
+    /// and returns the table. DSDT is often corrupted, because it is included by vendor,
     /// therefore validation may fail. 
     pub fn dsdt_legacy(&self) -> Result<&DSDT, SDTValidationError> {
-        self._dsdt((self.dsdt as *mut u32).cast::<ACPISDTHeader>())
+        self._dsdt(self.dsdt as usize)
     }
 
-    /// Obtains the DSDT table from the 64-bit pointer located in FADT.
+    /// Obtains the DSDT table, preferring the 64-bit X_DSDT pointer over the legacy one.
     ///
-    /// This functions automatically maps DSDT's page to prevent page fault, validates the DSDT's
-    /// header and returns the table. DSDT is often corrupted, because it is included by vendor,
-    /// therefore validation may fail. 
+    /// X_DSDT is used when the FADT is long enough to contain it and it is not zero. If both
+    /// pointers exist but X_DSDT points to an invalid table, the legacy pointer is tried as well.
     pub fn dsdt(&self) -> Result<&DSDT, SDTValidationError> {
-        self._dsdt((self.X_DSDT as *mut usize).cast::<ACPISDTHeader>())
+        let legacy = self.dsdt as usize;
+        let Some(x_dsdt) = self.x_dsdt() else {
+            return self._dsdt(legacy)
+        };
+
+        if legacy == 0 || legacy == x_dsdt {
+            return self._dsdt(x_dsdt)
+        }
+
+        crate::warn!("ACPI: DSDT {:#x} and X_DSDT {:#x} differ, using X_DSDT.", legacy, x_dsdt);
+        self._dsdt(x_dsdt).or_else(|_| {
+            crate::warn!("ACPI: Falling back to the legacy DSDT.");
+            self._dsdt(legacy)
+        })
+    }
+
+    /// Returns the X_DSDT pointer, if the table contains one.
+    pub fn x_dsdt(&self) -> Option<usize> {
+        if (self.header.length as usize) < X_DSDT_OFFSET + 8 {
+            return None
+        }
+
+        let addr = unsafe {
+            (self as *const Self).cast::<u8>().add(X_DSDT_OFFSET).cast::<u64>().read_unaligned()
+        };
+        (addr != 0).then_some(addr as usize)
     }
 
     // Inner function that obtains DSDT after proper memory mapping.
-    fn _dsdt(&self, ptr: *mut ACPISDTHeader) -> Result<&DSDT, SDTValidationError> {
-        use crate::kernel_components::memory::{EntryFlags, MEMORY_MANAGEMENT_UNIT};
-        // Mapping DSDT's page to prevent unnecessary page fault.
-        unsafe{ MEMORY_MANAGEMENT_UNIT.map_ptr(ptr, EntryFlags::PRESENT) }; 
+    fn _dsdt(&self, addr: usize) -> Result<&DSDT, SDTValidationError> {
+        use crate::kernel_components::memory::MEMORY_MANAGEMENT_UNIT;
 
-        let header = unsafe { ptr.as_ref().unwrap() };
-        match DSDT::validate(header) {
-            Ok(_) => {
-                let sdt = unsafe {
-                    // Here we are free to cast the header pointer as the SDT.
-                    ptr.cast::<DSDT>().as_mut().unwrap()
-                };
-                Ok(sdt)
-            },
-            Err(e) => Err(e),
+        let ptr = addr as *const ACPISDTHeader;
+        if !ptr.is_null() {
+            // Mapping DSDT's pages to prevent unnecessary page fault. Header comes first, because
+            // it holds the length.
+            let mmu = unsafe { &mut *MEMORY_MANAGEMENT_UNIT };
+            let _ = mmu.map_firmware(addr, addr + mem::size_of::<ACPISDTHeader>());
+            let length = unsafe { ptr.read_unaligned() }.length.min(MAX_TABLE_LENGTH) as usize;
+            let _ = mmu.map_firmware(addr, addr + length);
         }
+
+        unsafe { DSDT::validate_at(ptr)? };
+        // Here we are free to cast the header pointer as the SDT.
+        unsafe { ptr.cast::<DSDT>().as_ref() }.ok_or(SDTValidationError::NullPointer)
     }
 }
 
@@ -208,6 +281,8 @@ pub enum PPMP {
 
 impl SystemDescriptionTable for FADT {
     const SIGNATURE: &'static str = "FACP";
+    /// Length of the ACPI 1.0 FADT. Every field up to the reset register is present.
+    const MIN_LENGTH: usize = 116;
 }
 
 bitflags! {
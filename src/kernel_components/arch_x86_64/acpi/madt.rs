@@ -0,0 +1,201 @@
+/// Module that implements the MADT table.
+///
+/// The Multiple APIC Description Table describes the interrupt controllers of the system: local
+/// APICs of every processor, IO APICs with the range of global system interrupts they handle, and
+/// the interrupt source overrides, which tell how the ISA IRQs are wired to the IO APIC inputs.
+/// As all other tables, RSDT or XSDT is required for locating one.
+
+use core::mem;
+
+use super::acpi::{ACPISDTHeader, SystemDescriptionTable};
+use super::rsdt::{RSDT, XSDT};
+use crate::bitflags;
+
+/// Multiple APIC Description Table (MADT/APIC)
+///
+/// The fixed part is followed by a list of variable length entries. Use [`MADT::entries`] to
+/// iterate over them.
+#[repr(C)]
+#[derive(Debug)]
+pub struct MADT {
+    /// Table header.
+    header: ACPISDTHeader,
+    /// Physical address of the local APIC registers.
+    local_apic_address: u32,
+    /// Multiple APIC flags.
+    flags: u32,
+}
+
+impl SystemDescriptionTable for MADT {
+    const SIGNATURE: &'static str = "APIC";
+    const MIN_LENGTH: usize = mem::size_of::<ACPISDTHeader>() + 8;
+}
+
+bitflags! {
+    /// Polarity and trigger mode flags of interrupt source overrides and NMI sources.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct MpsIntiFlags: u16 {
+        /// Active high polarity. Conforming to the bus if neither polarity bit is set.
+        const ACTIVE_HIGH =     0b01,
+        /// Active low polarity.
+        const ACTIVE_LOW =      0b11,
+        /// Edge triggered. Conforming to the bus if neither trigger bit is set.
+        const EDGE =            0b01 << 2,
+        /// Level triggered.
+        const LEVEL =           0b11 << 2,
+    };
+}
+
+/// Single entry of the MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MadtEntry {
+    /// Local APIC of a processor. Bit 0 of the flags is set if the processor is enabled, bit 1 if
+    /// it can be enabled later.
+    LocalApic { processor_id: u8, apic_id: u8, flags: u32 },
+    /// IO APIC, which handles the global system interrupts starting at the base.
+    IoApic { id: u8, address: u32, gsi_base: u32 },
+    /// ISA IRQ source wired to a different global system interrupt or with a non default
+    /// polarity and trigger mode.
+    InterruptSourceOverride { bus: u8, source: u8, gsi: u32, flags: u16 },
+    /// Global system interrupt which must be configured as a non maskable interrupt.
+    NmiSource { flags: u16, gsi: u32 },
+    /// LINT pin of the local APIC of a processor (0xff means all), which is wired to the NMI.
+    LocalApicNmi { processor_id: u8, flags: u16, lint: u8 },
+    /// 64-bit address of the local APIC registers, which replaces the one in the table header.
+    LocalApicAddressOverride { address: u64 },
+    /// Local x2APIC of a processor.
+    LocalX2Apic { apic_id: u32, flags: u32, processor_uid: u32 },
+    /// Entry of a type unknown to the kernel.
+    Unknown(u8),
+}
+
+/// Iterator over the entries of the MADT.
+#[derive(Debug, Clone)]
+pub struct MadtEntries<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for MadtEntries<'a> {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&typ, &len) = (self.bytes.first()?, self.bytes.get(1)?);
+        // Truncated or corrupted entries end the iteration.
+        if len < 2 || len as usize > self.bytes.len() {
+            self.bytes = &[];
+            return None
+        }
+        let (entry, rest) = self.bytes.split_at(len as usize);
+        self.bytes = rest;
+
+        Some(parse_entry(typ, entry).unwrap_or(MadtEntry::Unknown(typ)))
+    }
+}
+
+/// Parses the entry bytes, including the type and length. None if the entry is too short.
+fn parse_entry(typ: u8, entry: &[u8]) -> Option<MadtEntry> {
+    let u8_at = |at: usize| entry.get(at).copied();
+    let u16_at = |at: usize| entry.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |at: usize| entry.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+    Some(match typ {
+        0 => MadtEntry::LocalApic { processor_id: u8_at(2)?, apic_id: u8_at(3)?, flags: u32_at(4)? },
+        1 => MadtEntry::IoApic { id: u8_at(2)?, address: u32_at(4)?, gsi_base: u32_at(8)? },
+        2 => MadtEntry::InterruptSourceOverride {
+            bus: u8_at(2)?, source: u8_at(3)?, gsi: u32_at(4)?, flags: u16_at(8)?,
+        },
+        3 => MadtEntry::NmiSource { flags: u16_at(2)?, gsi: u32_at(4)? },
+        4 => MadtEntry::LocalApicNmi { processor_id: u8_at(2)?, flags: u16_at(3)?, lint: u8_at(5)? },
+        5 => MadtEntry::LocalApicAddressOverride {
+            address: u32_at(4)? as u64 | (u32_at(8)? as u64) << 32,
+        },
+        9 => MadtEntry::LocalX2Apic { apic_id: u32_at(4)?, flags: u32_at(8)?, processor_uid: u32_at(12)? },
+        typ => MadtEntry::Unknown(typ),
+    })
+}
+
+impl MADT {
+    /// Finds the MADT through the XSDT, or the RSDT on ACPI 1.0 systems.
+    pub fn find() -> Option<&'static MADT> {
+        let found = match XSDT::try_new() {
+            Ok(xsdt) => xsdt.find::<MADT>().map(|madt| madt.map(|madt| madt as *const MADT)),
+            Err(_) => RSDT::try_new().ok()?.find::<MADT>().map(|madt| madt.map(|madt| madt as *const MADT)),
+        };
+        // Tables stay mapped in the firmware memory until the MMU reclaims it, no root table is
+        // found afterwards.
+        found.ok().flatten().map(|madt| unsafe { &*madt })
+    }
+
+    /// Returns the physical address of the local APIC registers, with the 64-bit override
+    /// applied.
+    pub fn local_apic_address(&self) -> u64 {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address),
+                _ => None,
+            })
+            .unwrap_or(self.local_apic_address as u64)
+    }
+
+    /// Returns true if the system also has dual 8259 PICs, which must be masked before the IO
+    /// APIC is used.
+    pub fn has_legacy_pics(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    /// Iterates over the entries of the table.
+    pub fn entries(&self) -> MadtEntries<'_> {
+        let len = (self.header.length as usize).saturating_sub(mem::size_of::<Self>());
+        let start = unsafe { (self as *const Self).cast::<u8>().add(mem::size_of::<Self>()) };
+        MadtEntries { bytes: unsafe { core::slice::from_raw_parts(start, len) } }
+    }
+
+    /// Returns the global system interrupt and the flags of the ISA IRQ.
+    ///
+    /// Without an override ISA IRQs are identity mapped, active high and edge triggered.
+    pub fn isa_irq(&self, irq: u8) -> (u32, u16) {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::InterruptSourceOverride { bus: 0, source, gsi, flags } if source == irq => Some((gsi, flags)),
+                _ => None,
+            })
+            .unwrap_or((irq as u32, 0))
+    }
+}
+
+#[test_case]
+fn madt_entries_are_parsed() {
+    #[repr(C, align(8))]
+    struct Table([u8; 96]);
+
+    let mut table = Table([0; 96]);
+    let entries: [&[u8]; 4] = [
+        &[0, 8, 0, 0, 1, 0, 0, 0],
+        &[1, 12, 2, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0],
+        &[2, 10, 0, 0, 2, 0, 0, 0, 0, 0],
+        &[2, 10, 0, 9, 9, 0, 0, 0, 0x0f, 0],
+    ];
+    let mut at = 44;
+    for entry in entries {
+        table.0[at..at + entry.len()].copy_from_slice(entry);
+        at += entry.len();
+    }
+    // Truncated entry at the end is ignored.
+    table.0[at..at + 2].copy_from_slice(&[1, 12]);
+    table.0[..4].copy_from_slice(b"APIC");
+    table.0[4..8].copy_from_slice(&(at as u32 + 2).to_le_bytes());
+    table.0[36..40].copy_from_slice(&0xfee0_0000u32.to_le_bytes());
+    table.0[40] = 1;
+
+    let madt = unsafe { &*table.0.as_ptr().cast::<MADT>() };
+    assert_eq!(madt.entries().count(), 4);
+    assert_eq!(
+        madt.entries().nth(1),
+        Some(MadtEntry::IoApic { id: 2, address: 0xfec0_0000, gsi_base: 0 })
+    );
+    assert_eq!(madt.local_apic_address(), 0xfee0_0000);
+    assert!(madt.has_legacy_pics());
+    assert_eq!(madt.isa_irq(0), (2, 0));
+    assert_eq!(madt.isa_irq(9), (9, MpsIntiFlags::LEVEL.bits() | MpsIntiFlags::ACTIVE_LOW.bits()));
+    assert_eq!(madt.isa_irq(1), (1, 0));
+}
@@ -1,6 +1,6 @@
 //! Module that defines root system description pointers as both structure and tag.
 
-use super::acpi::{ACPISDTHeader, SystemDescriptionTable};
+use super::acpi::{checksum, report_invalid, SDTValidationError};
 use crate::kernel_components::memory::memory_module::MEMORY_MANAGEMENT_UNIT;
 use crate::kernel_components::memory::tags::{Tag, TagTrait, TagType, TagTypeId};
 use crate::kernel_components::os::UChar;
@@ -16,6 +16,10 @@ pub enum RootPointerError {
     SIGNATURE,
     /// Unable to retrieve the Tag.
     NOTAG,
+    /// The length of the extended pointer is wrong.
+    LENGTH,
+    /// The pointer is valid, but the root table it points to is not.
+    SDT(SDTValidationError),
 }
 
 /// Special pointer which points to the RSDT structure in ACPI version 1.0
@@ -42,7 +46,10 @@ impl RSDP {
             // Validating the rsdp right away.
             match rsdp.validate() {
                 Ok(_) => Ok(rsdp),
-                Err(e) => Err(e),
+                Err(e) => {
+                    report_invalid("RSDP", &tag.rsdp as *const _ as usize, e);
+                    Err(e)
+                },
             }
         } else {
             Err(RootPointerError::NOTAG)
@@ -51,15 +58,8 @@ impl RSDP {
 
     /// Returns true if checksum is valid.
     pub fn checksum(&self) -> bool {
-        let slice: &[u8] = unsafe {
-            core::slice::from_raw_parts(
-                self as *const _ as *const u8,
-                mem::size_of::<Self>(),
-            )
-        };
-
         // All bytes in the structure must sum up to zero.
-        slice.iter().fold(0u8, |s, b| s.wrapping_add(*b)) == 0
+        unsafe { checksum(self as *const _ as *const u8, mem::size_of::<Self>()) == 0 }
     }
 
     /// Validates the RSDP pointer.
@@ -108,27 +108,28 @@ impl XSDP {
         if let Some(tag) = unsafe { MEMORY_MANAGEMENT_UNIT.get_xsdp() } { 
             let xsdp = tag.xsdp.clone();
 
-            // Validating the rsdp right away.
+            // Validating the xsdp right away.
             match xsdp.validate() {
                 Ok(_) => Ok(xsdp),
-                Err(e) => Err(e),
+                Err(e) => {
+                    report_invalid("XSDP", &tag.xsdp as *const _ as usize, e);
+                    Err(e)
+                },
             }
         } else {
             Err(RootPointerError::NOTAG)
         }
     }
 
-    /// Returns true if checksum is valid.
+    /// Returns true if both checksums are valid.
+    ///
+    /// The legacy checksum covers the RSDP part, the extended one covers the whole structure.
     pub fn checksum(&self) -> bool {
-        let slice: &[u8] = unsafe {
-            core::slice::from_raw_parts(
-                self as *const _ as *const u8,
-                self.length as usize,
-            )
-        };
-
-        // All bytes in the structure must sum up to zero.
-        slice.iter().sum::<u8>() == 0
+        let ptr = self as *const _ as *const u8;
+        unsafe {
+            checksum(ptr, mem::size_of::<RSDP>()) == 0 &&
+            checksum(ptr, mem::size_of::<Self>()) == 0
+        }
     }
 
     /// Validates the XSDP pointer.
@@ -138,6 +139,11 @@ impl XSDP {
             return Err(RootPointerError::SIGNATURE)
         }
 
+        // Only the structure itself is copied, so a different length cannot be checksummed.
+        if self.length as usize != mem::size_of::<Self>() {
+            return Err(RootPointerError::LENGTH)
+        }
+
         // Checksum check.
         if !self.checksum() {
             return Err(RootPointerError::CHECKSUM)
@@ -147,6 +153,18 @@ impl XSDP {
     }
 }
 
+impl core::fmt::Display for RootPointerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::CHECKSUM => write!(f, "Pointer's checksum doesn't match."),
+            Self::SIGNATURE => write!(f, "Pointer's signature doesn't match."),
+            Self::NOTAG => write!(f, "Pointer is not provided by the bootloader."),
+            Self::LENGTH => write!(f, "Pointer's length is invalid."),
+            Self::SDT(err) => write!(f, "Root table is invalid: {}", err),
+        }
+    }
+}
+
 /// Tag which contains a copy of RSDP pointer for ACPI v1.0
 ///
 /// This is the tag from multiboot2 structure. It must be used to obtain the RSDP
@@ -161,7 +179,9 @@ pub struct ACPITagOld {
 
 impl TagTrait for ACPITagOld {
     const ID: TagType = TagType::AcpiOld;
-    fn dst_size(tag: &Tag) {}
+    fn dst_size(_: &Tag) -> Option<()> {
+        Some(())
+    }
 }
 
 /// Tag which contains a copy of XSDP pointer for ACPI v2.0
@@ -178,5 +198,7 @@ pub struct ACPITagNew {
 
 impl TagTrait for ACPITagNew {
     const ID: TagType = TagType::AcpiNew;
-    fn dst_size(tag: &Tag) {}
+    fn dst_size(_: &Tag) -> Option<()> {
+        Some(())
+    }
 }
@@ -49,12 +49,7 @@ impl RSDT {
     ///
     /// If ACPI < 2.0 is used, this version is required.
     pub fn new() -> Self {
-        // Critical section, because reading from the MMU.
-        let rsdp = critical_section!(|| {
-            RSDP::new()
-        }).expect("Unable to read from RSDP.");
-
-        RSDT::_ptrs_map(rsdp)
+        RSDT::try_new().expect("Unable to read the RSDT.")
     }
 
     /// Trying to obtain RSDT from the RSDP pointer given by multiboot2.
@@ -66,14 +61,14 @@ impl RSDT {
         match critical_section!(|| {
             RSDP::new()
         }) {
-            Ok(rsdp) => Ok(RSDT::_ptrs_map(rsdp)),
+            Ok(rsdp) => RSDT::_ptrs_map(rsdp).map_err(RootPointerError::SDT),
             Err(err) => Err(err),
         }
     }
 
-    /// Works the same as new, but can be used to fast things up when you already do aquire
+    /// Works the same as try_new, but can be used to fast things up when you already do aquire
     /// a proper RSDP.
-    pub(crate) unsafe fn from_rsdp(rsdp: RSDP) -> Self {
+    pub(crate) unsafe fn from_rsdp(rsdp: RSDP) -> Result<Self, SDTValidationError> {
         RSDT::_ptrs_map(rsdp)
     }
     
@@ -83,41 +78,13 @@ impl RSDT {
     ///
     /// # Returns
     ///
-    /// Will return an error if table was found but its validation failed. Will return
+    /// Will return an error if tables were found but all of them failed validation. Will return
     /// Ok(None) if table was not found for some reason. Will return Ok(&T), where T is
     /// expected to be another SDT. 
     pub fn find<T>(&self) -> Result<Option<&mut T>, SDTValidationError> where 
         T: SystemDescriptionTable
     {
-        for (mut p1, p2) in self.ptrs.iter().map(|(ptr)| unsafe { 
-            let p1 = (ptr.v1[0] as *mut u32).cast::<ACPISDTHeader>(); 
-            let p2 = (ptr.v1[1] as *mut u32).cast::<ACPISDTHeader>(); 
-            (p1, p2)
-        }) {
-            // Having to repeat this process twice, because legacy pointers are not aligned.
-            for _ in 0..2 {
-                if let Some(header) = unsafe { p1.as_ref() } {
-                    // If signature matches the header signature, checking the obtained SDT and
-                    // returning it if validated.
-                    if header.signature == *T::SIGNATURE.as_bytes() {
-                        // Now it is necessary to validate the header fully.
-                        match T::validate(header) {
-                            Ok(_) => {
-                                let sdt = unsafe {
-                                    // Here we are free to cast the header pointer as the SDT.
-                                    p1.cast::<T>().as_mut().unwrap()
-                                };
-                                return Ok(Some(sdt));
-                            },
-                            Err(e) => return Err(e),
-                        }
-                    }
-                }
-                p1 = p2;
-            }
-        }
-
-        Ok(None)
+        find_in(self.tables())
     }
 
     /// Just a getter function to obtain RSDT pointers as a reference to a slice.
@@ -130,19 +97,30 @@ impl RSDT {
         _ptrs_amount(self.header)
     }
 
-    fn _ptrs_map(rsdp: RSDP) -> Self {
+    /// Iterates over the non-null table pointers. Legacy pointers are 32-bit, so every
+    /// [`SDTPointer`] holds two of them.
+    pub fn tables(&self) -> impl Iterator<Item = *const ACPISDTHeader> + '_ {
+        self.ptrs.iter()
+            .flat_map(|ptr| unsafe { ptr.v1 })
+            .take(self.ptrs_amount())
+            .filter(|&addr| addr != 0)
+            .map(|addr| addr as usize as *const ACPISDTHeader)
+    }
+
+    fn _ptrs_map(rsdp: RSDP) -> Result<Self, SDTValidationError> {
         unsafe { 
-            // Getting the amount of pointers for RSDT.
-            let header = ptr::read_unaligned(rsdp.ptr as *mut ACPISDTHeader); 
+            // Validating the whole table before trusting the amount of pointers.
+            let header = RSDT::validate_at(rsdp.ptr as usize as *const ACPISDTHeader)?; 
             let ptrptr = rsdp.ptr as usize + mem::size_of::<ACPISDTHeader>();
-            let amount = _ptrs_amount(header);
+            // Two legacy pointers per entry, the last one may be a half.
+            let amount = _ptrs_amount(header).div_ceil(2);
 
-            RSDT {
+            Ok(RSDT {
                 header, 
                 ptrs: ptr::slice_from_raw_parts(
                     ptrptr as *const SDTPointer, amount
                 ).as_ref().unwrap()
-            }
+            })
         } 
     }
 }
@@ -171,12 +149,7 @@ impl XSDT {
     ///
     /// If ACPI 2.0 is used, this version is required.
     pub fn new() -> Self {
-        // Critical section, because reading from the MMU.
-        let xsdp = critical_section!(|| {
-            XSDP::new()
-        }).expect("Unable to read from XSDP.");
-
-        XSDT::_ptrs_map(xsdp)
+        XSDT::try_new().expect("Unable to read the XSDT.")
     }
 
     /// Trying to obtain XSDT from the XSDP pointer given by multiboot2.
@@ -189,14 +162,14 @@ impl XSDT {
         match critical_section!(|| {
             XSDP::new()
         }) {
-            Ok(xsdp) => Ok(XSDT::_ptrs_map(xsdp)),
+            Ok(xsdp) => XSDT::_ptrs_map(xsdp).map_err(RootPointerError::SDT),
             Err(err) => Err(err),
         }
     }
 
-    /// Works the same as new, but can be used to fast things up when you already do aquire
-    /// a proper RSDP.
-    pub(crate) unsafe fn from_xsdp(xsdp: XSDP) -> Self {
+    /// Works the same as try_new, but can be used to fast things up when you already do aquire
+    /// a proper XSDP.
+    pub(crate) unsafe fn from_xsdp(xsdp: XSDP) -> Result<Self, SDTValidationError> {
         XSDT::_ptrs_map(xsdp)
     }
 
@@ -206,33 +179,13 @@ impl XSDT {
     ///
     /// # Returns
     ///
-    /// Will return an error if table was found but its validation failed. Will return
+    /// Will return an error if tables were found but all of them failed validation. Will return
     /// Ok(None) if table was not found for some reason. Will return Ok(&T), where T is
     /// expected to be another SDT. 
     pub fn find<T>(&self) -> Result<Option<&mut T>, SDTValidationError> where 
         T: SystemDescriptionTable
     {
-        for ptr in self.ptrs.iter().map(|ptr| unsafe { (ptr.v2 as *mut usize).cast::<ACPISDTHeader>() }) {
-            if let Some(header) = unsafe { ptr.as_ref() } {
-                // If signature matches the header signature, checking the obtained SDT and
-                // returning it if validated.
-                if header.signature == *T::SIGNATURE.as_bytes() {
-                    // No it is necessary to validate the header fully.
-                    match T::validate(header) {
-                        Ok(_) => {
-                            let sdt = unsafe {
-                                // Here we are free to cast the header pointer as the SDT.
-                                ptr.cast::<T>().as_mut().unwrap()
-                            };
-                            return Ok(Some(sdt));
-                        },
-                        Err(e) => return Err(e),
-                    }
-                }
-            }
-        }
-
-        Ok(None)
+        find_in(self.tables())
     }
 
     /// Just a getter function to obtain RSDT pointers as a reference to a slice.
@@ -245,23 +198,54 @@ impl XSDT {
         _ptrs_amount(self.header) / 2
     }
 
-    fn _ptrs_map(xsdp: XSDP) -> Self {
+    /// Iterates over the non-null table pointers.
+    pub fn tables(&self) -> impl Iterator<Item = *const ACPISDTHeader> + '_ {
+        self.ptrs.iter()
+            .map(|ptr| unsafe { ptr.v2 })
+            .filter(|&addr| addr != 0)
+            .map(|addr| addr as *const ACPISDTHeader)
+    }
+
+    fn _ptrs_map(xsdp: XSDP) -> Result<Self, SDTValidationError> {
         unsafe { 
-            // Getting the amount of pointers for RSDT.
-            let header = ptr::read_unaligned(xsdp.ptr as *mut ACPISDTHeader); 
+            // Validating the whole table before trusting the amount of pointers.
+            let header = XSDT::validate_at(xsdp.ptr as usize as *const ACPISDTHeader)?; 
             let ptrptr = xsdp.ptr as usize + mem::size_of::<ACPISDTHeader>();
-            let amount = _ptrs_amount(header);
+            let amount = _ptrs_amount(header) / 2;
 
-            XSDT {
+            Ok(XSDT {
                 header,
                 ptrs: ptr::slice_from_raw_parts(
                     ptrptr as *const SDTPointer, amount
                 ).as_ref().unwrap()
-            }
+            })
         } 
     }
 }
 
 fn _ptrs_amount(h: ACPISDTHeader) -> usize { 
-    (h.length as usize - mem::size_of::<ACPISDTHeader>()) / 4 
+    (h.length as usize).saturating_sub(mem::size_of::<ACPISDTHeader>()) / 4 
+}
+
+/// Finds the first table with the signature of T among the pointed tables.
+///
+/// Tables with a matching signature, which fail validation, are logged and skipped, because
+/// firmware sometimes leaves a broken copy next to the valid one.
+fn find_in<'a, T>(tables: impl Iterator<Item = *const ACPISDTHeader>) -> Result<Option<&'a mut T>, SDTValidationError> where
+    T: SystemDescriptionTable
+{
+    let mut error = None;
+    for ptr in tables {
+        // Signature is checked first to not report tables of other types.
+        if unsafe { ptr.read_unaligned() }.signature != *T::SIGNATURE.as_bytes() {
+            continue
+        }
+        match unsafe { T::validate_at(ptr) } {
+            // Here we are free to cast the header pointer as the SDT.
+            Ok(_) => return Ok(unsafe { ptr.cast::<T>().cast_mut().as_mut() }),
+            Err(e) => error = Some(e),
+        }
+    }
+
+    error.map_or(Ok(None), Err)
 }
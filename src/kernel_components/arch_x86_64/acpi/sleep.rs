@@ -0,0 +1,316 @@
+/// ACPI sleep states.
+///
+/// Only the suspend to RAM (S3) is implemented. The machine is put to sleep by writing the sleep
+/// type and the SLP_EN bit into the PM1 control registers described by the FADT. The memory stays
+/// powered, but the processors and most of the chipset are reset, so on the wake up the firmware
+/// starts the BSP in real mode at the waking vector stored in the FACS.
+///
+/// The waking vector is the AP trampoline of the [`smp`] module, which brings the BSP back into
+/// long mode with the page tables used before the sleep and calls [`wakeup_entry`] on a separate
+/// stack. The control registers, the GDT, the TSS and the IDT are restored there, after which the
+/// callee saved registers and the stack pointer saved by [`acpi_sleep_enter`] are restored, so
+/// [`suspend_to_ram`] returns as if the sleep was a regular function call.
+///
+/// Drivers are suspended before the sleep and resumed after the wake up with the hooks of the
+/// [`Driver`] trait. APs are parked for the time of the sleep and started again afterwards.
+///
+/// # Sleep Type
+///
+/// The SLP_TYP value of S3 is defined by the \_S3 object of the DSDT. It is not evaluated yet,
+/// therefore the value used by QEMU is the default, which can be changed with
+/// [`set_s3_sleep_type`].
+///
+/// [`Driver`]: crate::kernel_components::drivers::Driver
+
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::fmt::Display;
+use core::error::Error;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use super::FADT;
+use crate::kernel_components::arch_x86_64::PrivilegeLevel;
+use crate::kernel_components::arch_x86_64::controllers::PROGRAMMABLE_INTERRUPT_CONTROLLER;
+use crate::kernel_components::arch_x86_64::controllers::apic::{LOCAL_APIC, SPURIOUS_VECTOR};
+use crate::kernel_components::arch_x86_64::controllers::ioapic::IO_APIC;
+use crate::kernel_components::arch_x86_64::interrupts::{interrupt, INTERRUPT_DESCRIPTOR_TABLE};
+use crate::kernel_components::arch_x86_64::ports::Port;
+use crate::kernel_components::arch_x86_64::segmentation::{GLOBAL_DESCRIPTOR_TABLE, TSS};
+use crate::kernel_components::arch_x86_64::smp::{self, SmpError};
+use crate::kernel_components::drivers::DRIVER_MANAGER;
+use crate::kernel_components::memory::{EntryFlags, Page, MEMORY_MANAGEMENT_UNIT};
+use crate::kernel_components::memory::memory_module::MemError;
+use crate::kernel_components::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use crate::kernel_components::registers::ms::{Msr, EFER};
+use crate::kernel_components::registers::segment_regs::{CodeSegment, Segment, SegmentSelector, StackSegment};
+
+/// SLP_TYP value of S3 used by QEMU.
+pub const DEFAULT_S3_SLEEP_TYPE: u8 = 1;
+
+/// Size of the stack used right after the wake up in pages.
+const WAKE_STACK_PAGES: usize = 2;
+/// Index of the TSS descriptor in the GDT of the BSP.
+const TSS_INDEX: u16 = 5;
+
+/// Wake status bit of the PM1 status register.
+const WAK_STS: u16 = 1 << 15;
+/// RTC alarm status bit of the PM1 status register.
+const RTC_STS: u16 = 1 << 10;
+/// RTC alarm wake enable bit of the PM1 enable register.
+const RTC_EN: u16 = 1 << 10;
+/// Sleep type field of the PM1 control register.
+const SLP_TYP: u16 = 0b111 << 10;
+/// Sleep enable bit of the PM1 control register.
+const SLP_EN: u16 = 1 << 13;
+
+/// Offset of the 32-bit waking vector in the FACS.
+const FACS_WAKING_VECTOR: usize = 12;
+/// Offset of the 64-bit waking vector in the FACS, which exists since version 1.
+const FACS_X_WAKING_VECTOR: usize = 24;
+
+/// SLP_TYP value written to enter S3.
+static S3_SLEEP_TYPE: AtomicU8 = AtomicU8::new(DEFAULT_S3_SLEEP_TYPE);
+/// Top of the stack used right after the wake up, zero until the first sleep.
+static WAKE_STACK: AtomicUsize = AtomicUsize::new(0);
+
+/// Error type for the sleep states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepError {
+    /// No FADT describes the power management registers.
+    NoFadt,
+    /// The FADT does not point to a valid FACS, which holds the waking vector.
+    NoFacs,
+    /// The FADT describes no PM1 control register.
+    NoPmControl,
+    /// The wake up trampoline cannot be used.
+    Trampoline(SmpError),
+    /// Unable to allocate the stack used after the wake up.
+    OutOfMemory(MemError),
+    /// The machine did not enter the sleep state.
+    NotEntered,
+}
+
+impl Display for SleepError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoFadt => write!(f, "No FADT describes the power management registers."),
+            Self::NoFacs => write!(f, "The FADT does not point to a valid FACS."),
+            Self::NoPmControl => write!(f, "The FADT describes no PM1 control register."),
+            Self::Trampoline(err) => write!(f, "Unable to prepare the wake up trampoline: {}", err),
+            Self::OutOfMemory(err) => write!(f, "Unable to allocate the wake up stack: {}", err),
+            Self::NotEntered => write!(f, "The machine did not enter the sleep state."),
+        }
+    }
+}
+
+impl Error for SleepError {}
+
+/// State of the BSP saved before the sleep.
+///
+/// The layout of the first fields is used by [`acpi_sleep_enter`] and [`acpi_sleep_resume`].
+#[repr(C)]
+struct SleepContext {
+    /// RBX, RBP, R12-R15 and RSP of the suspending code.
+    registers: [u64; 7],
+    cr0: Cr0Flags,
+    cr4: Cr4Flags,
+    efer: u64,
+}
+
+/// Changes the SLP_TYP value written to enter S3, as found in the \_S3 object of the DSDT.
+pub fn set_s3_sleep_type(typ: u8) {
+    S3_SLEEP_TYPE.store(typ & 0b111, Ordering::Relaxed);
+}
+
+/// Suspends the machine to RAM and returns after the wake up.
+///
+/// A wake source must be armed before, like the RTC alarm with
+/// [`RealTimeClock::set_alarm`], otherwise only the power button wakes the machine. Interrupts
+/// are disabled until the drivers are resumed.
+///
+/// [`RealTimeClock::set_alarm`]: crate::kernel_components::drivers::timers::RealTimeClock::set_alarm
+pub fn suspend_to_ram() -> Result<(), SleepError> {
+    let fadt = FADT::find().ok_or(SleepError::NoFadt)?;
+    let facs = unsafe { map_facs(fadt.facs().ok_or(SleepError::NoFacs)?) }?;
+    let (pm1a_control, pm1b_control) = match fadt.pm1_control_blocks() {
+        (0, _) => return Err(SleepError::NoPmControl),
+        blocks => blocks,
+    };
+    let stack = wake_stack()?;
+
+    let mut context = SleepContext {
+        registers: [0; 7],
+        cr0: Cr0::read(),
+        cr4: Cr4::read(),
+        efer: unsafe { EFER::read_raw() },
+    };
+    let vector = unsafe {
+        smp::prepare_wakeup(wakeup_entry, stack, &context as *const SleepContext as usize)
+    }.map_err(SleepError::Trampoline)?;
+
+    // The APs lose their state in the sleep as well.
+    let parked: Vec<usize> = (1..smp::cpu_count()).filter(|&cpu| smp::park(cpu)).collect();
+    let interrupts = interrupt::is_interrupts_enabled();
+    unsafe { interrupt::disable() };
+    unsafe { DRIVER_MANAGER.suspend_all() };
+
+    let pic_mask = PROGRAMMABLE_INTERRUPT_CONTROLLER.lock().as_mut().map(|pics| pics.get_mask());
+    let redirections = IO_APIC.lock().as_ref().map(|ioapics| ioapics.save());
+    let apic_enabled = LOCAL_APIC.is_enabled();
+
+    let woke = unsafe {
+        (facs.add(FACS_WAKING_VECTOR) as *mut u32).write_volatile(vector as u32);
+        if (facs.add(4) as *const u32).read_volatile() as usize >= FACS_X_WAKING_VECTOR + 8 {
+            (facs.add(FACS_X_WAKING_VECTOR) as *mut u64).write_volatile(0);
+        }
+
+        // Stale wake events would wake the machine at once.
+        let (pm1a_event, pm1b_event) = fadt.pm1_event_blocks();
+        let enable_offset = fadt.pm1_event_length() as u16 / 2;
+        for event in core::iter::once(pm1a_event).chain(pm1b_event).filter(|&port| port != 0) {
+            u16::write(event, WAK_STS | RTC_STS);
+            u16::write(event + enable_offset, u16::read(event + enable_offset) | RTC_EN);
+        }
+
+        let typ = S3_SLEEP_TYPE.load(Ordering::Relaxed) as u16;
+        let control = (u16::read(pm1a_control) & !(SLP_TYP | SLP_EN)) | typ << 10 | SLP_EN;
+        acpi_sleep_enter(&mut context, pm1a_control, pm1b_control.unwrap_or(0), control) != 0
+    };
+
+    if woke {
+        unsafe {
+            if apic_enabled {
+                let _ = LOCAL_APIC.init(SPURIOUS_VECTOR);
+            }
+            if let (Some(pics), Some(mask)) = (PROGRAMMABLE_INTERRUPT_CONTROLLER.lock().as_mut(), pic_mask) {
+                pics.initialize();
+                pics.write_mask(mask);
+            }
+            if let (Some(ioapics), Some(redirections)) = (IO_APIC.lock().as_mut(), redirections) {
+                ioapics.restore(&redirections);
+            }
+        }
+    }
+
+    unsafe { DRIVER_MANAGER.resume_all() };
+    for cpu in parked {
+        if !smp::unpark(cpu) {
+            crate::warn!("Processor {} did not come online after the sleep.", cpu);
+        }
+    }
+    if interrupts {
+        unsafe { interrupt::enable() };
+    }
+
+    if woke { Ok(()) } else { Err(SleepError::NotEntered) }
+}
+
+/// Maps the FACS writable and returns a pointer to it.
+unsafe fn map_facs(facs: usize) -> Result<*mut u8, SleepError> {
+    let mmu = unsafe { &mut *MEMORY_MANAGEMENT_UNIT };
+    // The waking vector never crosses a page, as the FACS is aligned to 64 bytes.
+    mmu.map_firmware(facs, facs + 64).map_err(|_| SleepError::NoFacs)?;
+    mmu.update_flags(Page::containing_address(facs), EntryFlags::PRESENT | EntryFlags::WRITABLE)
+        .map_err(|_| SleepError::NoFacs)?;
+
+    let facs = facs as *mut u8;
+    match unsafe { (facs as *const [u8; 4]).read() } {
+        [b'F', b'A', b'C', b'S'] => Ok(facs),
+        _ => Err(SleepError::NoFacs),
+    }
+}
+
+/// Returns the top of the stack used right after the wake up, allocating it on the first call.
+fn wake_stack() -> Result<usize, SleepError> {
+    match WAKE_STACK.load(Ordering::Acquire) {
+        0 => {
+            let mmu = unsafe { &mut *MEMORY_MANAGEMENT_UNIT };
+            let stack = mmu.allocate_stack(WAKE_STACK_PAGES).map_err(SleepError::OutOfMemory)?;
+            WAKE_STACK.store(stack.top, Ordering::Release);
+            Ok(stack.top)
+        },
+        top => Ok(top),
+    }
+}
+
+/// Entry point of the BSP in long mode after the wake up, with the saved context.
+extern "C" fn wakeup_entry(context: usize) -> ! {
+    let context = context as *const SleepContext;
+
+    unsafe {
+        Cr0::write((*context).cr0);
+        Cr4::write((*context).cr4);
+        EFER::write_raw((*context).efer);
+
+        // The TSS was loaded before the sleep, so it's descriptor is still marked busy.
+        GLOBAL_DESCRIPTOR_TABLE.clear_tss_busy(TSS_INDEX as usize);
+        GLOBAL_DESCRIPTOR_TABLE.load_table();
+        CodeSegment::write(SegmentSelector::new(1, false, PrivilegeLevel::KernelLevel));
+        StackSegment::write(SegmentSelector::new(2, false, PrivilegeLevel::KernelLevel));
+        TSS::write(SegmentSelector::new(TSS_INDEX, false, PrivilegeLevel::KernelLevel));
+        INTERRUPT_DESCRIPTOR_TABLE.load_table();
+
+        acpi_sleep_resume(context)
+    }
+}
+
+extern "C" {
+    /// Saves the callee saved registers into the context and writes the control value into the
+    /// PM1 control registers, the second one is skipped if zero. Returns zero if the machine did
+    /// not enter the sleep state in time, and 1 once [`acpi_sleep_resume`] is called.
+    fn acpi_sleep_enter(context: *mut SleepContext, pm1a: u16, pm1b: u16, value: u16) -> u64;
+    /// Restores the registers of the context and returns from [`acpi_sleep_enter`].
+    fn acpi_sleep_resume(context: *const SleepContext) -> !;
+}
+
+// Caches are written back before the sleep, as their content is lost. The write of SLP_EN puts
+// the machine to sleep, but it takes some time until the power is actually off.
+global_asm!(
+    ".pushsection .text.acpi_sleep, \"ax\"",
+    ".global acpi_sleep_enter",
+    ".global acpi_sleep_resume",
+    "acpi_sleep_enter:",
+    "    mov [rdi], rbx",
+    "    mov [rdi + 8], rbp",
+    "    mov [rdi + 16], r12",
+    "    mov [rdi + 24], r13",
+    "    mov [rdi + 32], r14",
+    "    mov [rdi + 40], r15",
+    "    mov [rdi + 48], rsp",
+    "    mov eax, ecx",
+    "    mov r8d, edx",
+    "    mov edx, esi",
+    "    wbinvd",
+    "    out dx, ax",
+    "    test r8w, r8w",
+    "    jz 2f",
+    "    mov edx, r8d",
+    "    out dx, ax",
+    "2:",
+    "    mov ecx, 0x1000000",
+    "3:",
+    "    pause",
+    "    dec ecx",
+    "    jnz 3b",
+    "    xor eax, eax",
+    "    ret",
+    "acpi_sleep_resume:",
+    "    mov rbx, [rdi]",
+    "    mov rbp, [rdi + 8]",
+    "    mov r12, [rdi + 16]",
+    "    mov r13, [rdi + 24]",
+    "    mov r14, [rdi + 32]",
+    "    mov r15, [rdi + 40]",
+    "    mov rsp, [rdi + 48]",
+    "    mov eax, 1",
+    "    ret",
+    ".popsection",
+);
+
+#[test_case]
+fn sleep_type_is_masked() {
+    set_s3_sleep_type(0x0d);
+    assert_eq!(S3_SLEEP_TYPE.load(Ordering::Relaxed), 0b101);
+    set_s3_sleep_type(DEFAULT_S3_SLEEP_TYPE);
+    assert_eq!(core::mem::offset_of!(SleepContext, cr0), 56);
+}
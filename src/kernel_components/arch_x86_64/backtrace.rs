@@ -0,0 +1,93 @@
+//! Stack walking through the chain of saved frame pointers.
+//!
+//! The target specification forces frame pointers, so every function starts by pushing RBP and
+//! moving RSP into it. The saved RBP of a frame points to the saved RBP of it's caller and the
+//! return address lies right above it.
+//!
+//! The walker is used by the fault handlers, so it must never fault itself: each frame is checked
+//! to be aligned and mapped before it is read, and the walk stops once a frame does not lie above
+//! the previous one, as stacks grow down.
+//!
+//! [`dump`] prints the walked frames together with the symbols from [`kallsyms`].
+//!
+//! [`kallsyms`]: crate::kernel_components::kallsyms
+
+use core::arch::asm;
+
+use crate::kernel_components::kallsyms::Symbolized;
+use crate::kernel_components::memory::MEMORY_MANAGEMENT_UNIT;
+use crate::{emergency_println, VirtualAddress};
+
+/// Maximal amount of frames walked, which bounds the walk on a corrupted chain.
+pub const MAX_FRAMES: usize = 32;
+
+/// Iterator over the return addresses of the frames, starting with the caller of the frame
+/// pointed to by RBP.
+#[derive(Debug, Clone)]
+pub struct StackWalker {
+    rbp: VirtualAddress,
+    depth: usize,
+}
+
+impl StackWalker {
+    /// Starts the walk at the provided frame pointer.
+    pub const fn new(rbp: VirtualAddress) -> Self {
+        Self { rbp, depth: 0 }
+    }
+
+    /// Starts the walk at the frame of the caller.
+    #[inline(always)]
+    pub fn current() -> Self {
+        let rbp: usize;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+        Self::new(rbp)
+    }
+
+    /// Checks if both words of the frame can be read without a page fault.
+    fn is_readable(frame: VirtualAddress) -> bool {
+        // Translation only reads the page tables and takes no lock the interrupted code may hold.
+        let mmu = unsafe { &MEMORY_MANAGEMENT_UNIT };
+        mmu.translate(frame).is_some() && mmu.translate(frame + 8).is_some()
+    }
+}
+
+impl Iterator for StackWalker {
+    type Item = VirtualAddress;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.rbp;
+        if self.depth >= MAX_FRAMES || frame == 0 || frame % 8 != 0 || !Self::is_readable(frame) {
+            return None
+        }
+
+        let (caller, ret) = unsafe { (*(frame as *const usize), *((frame + 8) as *const usize)) };
+        if ret == 0 {
+            return None
+        }
+        self.rbp = if caller > frame { caller } else { 0 };
+        self.depth += 1;
+        Some(ret)
+    }
+}
+
+/// Prints the symbolized backtrace with the emergency writer.
+///
+/// The faulting instruction, if known, is printed as the first frame. Return addresses point
+/// right after the call, so they are looked up one byte earlier, which keeps a call at the very
+/// end of a function attributed to it.
+pub fn dump(ip: Option<VirtualAddress>, walker: StackWalker) {
+    emergency_println!("Backtrace:");
+    if let Some(ip) = ip {
+        emergency_println!("  #0  {}", Symbolized(ip));
+    }
+    for (i, ret) in walker.enumerate() {
+        emergency_println!("  #{:<2} {}", i + ip.is_some() as usize, Symbolized(ret - 1));
+    }
+}
+
+#[test_case]
+fn walk_stops_on_invalid_frames() {
+    assert_eq!(StackWalker::new(0).next(), None);
+    assert_eq!(StackWalker::new(0x1003).next(), None);
+    assert!(StackWalker::current().count() <= MAX_FRAMES);
+}
@@ -0,0 +1,457 @@
+/// Support for the local Advanced Programmable Interrupt Controller (LAPIC).
+///
+/// Every CPU core has it's own local APIC, which receives interrupts from the IO APIC, other
+/// cores and it's own local sources (timer, thermal sensor, performance counters, LINT pins) and
+/// delivers them to the core. Unlike the 8259 PIC it is programmed through memory mapped registers,
+/// which are located at the same physical address on every core, while each core only sees it's
+/// own registers there.
+///
+/// The base address is obtained from the IA32_APIC_BASE MSR, which also enables the controller.
+/// The registers are identity mapped as uncached memory by [`LocalApic::init`]. Each register is
+/// accessed with a single 32-bit read or write, so the [`LOCAL_APIC`] instance needs no locking
+/// and is usable from interrupt handlers directly.
+///
+/// # LVT
+///
+/// Local interrupt sources are configured by the local vector table. Each entry defines the vector,
+/// the delivery mode and whether the source is masked. See [`LvtEntry`].
+
+use core::fmt::Display;
+use core::error::Error;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::kernel_components::arch_x86_64::hypervisor;
+use crate::kernel_components::memory::{EntryFlags, Page, MEMORY_MANAGEMENT_UNIT};
+use crate::kernel_components::memory::frames::Frame;
+use crate::kernel_components::memory::memory_module::MemError;
+use crate::kernel_components::registers::ms::{ApicBase, ApicBaseFlags};
+use crate::{single, PhysicalAddress};
+
+/// Vector used for spurious interrupts of the local APIC by default.
+///
+/// The lowest four bits must be set on older processors, so the last vector is used.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// CPUID leaf 1 EDX bit, which reports the presence of a local APIC.
+const CPUID_APIC: u32 = 1 << 9;
+
+/// Enables the local APIC within the spurious interrupt vector register.
+const SVR_ENABLE: u32 = 1 << 8;
+
+/// Delivery status bit of the interrupt command register, set until the IPI is accepted.
+const ICR_PENDING: u32 = 1 << 12;
+/// Level bit of the interrupt command register, which must be set for all but INIT deassert.
+const ICR_ASSERT: u32 = 1 << 14;
+/// Destination shorthand of the interrupt command register, which targets the sending core.
+const ICR_SELF: u32 = 0b01 << 18;
+
+/// Local APIC instance of the current CPU.
+///
+/// The registers of every core are mapped at the same address, so a single instance serves all
+/// cores. It does nothing until [`LocalApic::init`] is called.
+single! {
+    pub LOCAL_APIC: LocalApic = LocalApic::new();
+}
+
+/// Amount of spurious interrupts received from the local APIC.
+static SPURIOUS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Must be called by the handler of the spurious vector. No end of interrupt is sent for them.
+pub fn spurious_interrupt() {
+    SPURIOUS_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the amount of spurious interrupts received so far.
+pub fn spurious_count() -> usize {
+    SPURIOUS_COUNT.load(Ordering::Relaxed)
+}
+
+/// Offsets of the local APIC registers from the base address.
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    Id = 0x20,
+    Version = 0x30,
+    TaskPriority = 0x80,
+    EndOfInterrupt = 0xb0,
+    SpuriousVector = 0xf0,
+    InService = 0x100,
+    ErrorStatus = 0x280,
+    InterruptCommandLow = 0x300,
+    InterruptCommandHigh = 0x310,
+    TimerInitialCount = 0x380,
+    TimerCurrentCount = 0x390,
+    TimerDivide = 0x3e0,
+}
+
+/// Entries of the local vector table.
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lvt {
+    /// Local APIC timer.
+    Timer = 0x320,
+    /// Thermal sensor.
+    Thermal = 0x330,
+    /// Performance monitoring counters.
+    PerformanceCounter = 0x340,
+    /// LINT0 pin, usually wired to the 8259 PIC.
+    Lint0 = 0x350,
+    /// LINT1 pin, usually wired to the NMI.
+    Lint1 = 0x360,
+    /// Internal errors of the local APIC.
+    Error = 0x370,
+}
+
+/// Delivery modes of local interrupt sources and interprocessor interrupts.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// Delivers the interrupt on the vector of the entry.
+    Fixed = 0b000,
+    /// Delivers a system management interrupt.
+    Smi = 0b010,
+    /// Delivers a non maskable interrupt. The vector is ignored.
+    Nmi = 0b100,
+    /// Delivers an INIT request.
+    Init = 0b101,
+    /// Starts an application processor at the page provided as the vector. Only valid for
+    /// interprocessor interrupts.
+    StartUp = 0b110,
+    /// Acts as if the interrupt came from an external 8259 PIC.
+    ExtInt = 0b111,
+}
+
+/// Operating modes of the local APIC timer.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    /// Counts down from the initial count once.
+    OneShot = 0b00 << 17,
+    /// Reloads the initial count every time it reaches zero.
+    Periodic = 0b01 << 17,
+    /// Fires once the TSC reaches the value of the IA32_TSC_DEADLINE MSR.
+    TscDeadline = 0b10 << 17,
+}
+
+/// Single entry of the local vector table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LvtEntry(u32);
+
+impl LvtEntry {
+    /// Masked entry without a vector.
+    pub const MASKED: Self = Self(1 << 16);
+
+    /// Creates an unmasked, edge triggered, active high entry with fixed delivery to the vector.
+    pub const fn new(vector: u8) -> Self {
+        Self(vector as u32)
+    }
+
+    /// Changes the delivery mode.
+    pub const fn delivery(self, mode: DeliveryMode) -> Self {
+        Self(self.0 & !(0b111 << 8) | (mode as u32) << 8)
+    }
+
+    /// Changes the mode of the timer entry.
+    pub const fn timer_mode(self, mode: TimerMode) -> Self {
+        Self(self.0 & !(0b11 << 17) | mode as u32)
+    }
+
+    /// Makes the source active low.
+    pub const fn active_low(self) -> Self {
+        Self(self.0 | 1 << 13)
+    }
+
+    /// Makes the source level triggered.
+    pub const fn level_triggered(self) -> Self {
+        Self(self.0 | 1 << 15)
+    }
+
+    /// Masks or unmasks the source.
+    pub const fn masked(self, masked: bool) -> Self {
+        match masked {
+            true => Self(self.0 | 1 << 16),
+            false => Self(self.0 & !(1 << 16)),
+        }
+    }
+
+    /// Returns the vector of the entry.
+    pub const fn vector(&self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Returns true if the source is masked.
+    pub const fn is_masked(&self) -> bool {
+        self.0 & 1 << 16 != 0
+    }
+
+    /// Returns true if the interrupt was sent, but not yet accepted by the core.
+    pub const fn is_pending(&self) -> bool {
+        self.0 & 1 << 12 != 0
+    }
+
+    /// Returns the raw value of the entry.
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for LvtEntry {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+/// Custom error type for the local APIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    /// The CPU has no local APIC.
+    Unsupported,
+    /// The registers could not be mapped.
+    MapFailed(MemError),
+}
+
+impl Display for ApicError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "The CPU has no local APIC."),
+            Self::MapFailed(err) => write!(f, "Unable to map the local APIC registers: {}", err),
+        }
+    }
+}
+
+impl Error for ApicError {}
+
+/// Local APIC of the current CPU.
+#[derive(Debug)]
+pub struct LocalApic {
+    /// Address of the mapped registers, zero until initialized.
+    base: AtomicUsize,
+}
+
+impl LocalApic {
+    /// Creates a new instance of the local APIC. The registers are not touched until initialized.
+    pub const fn new() -> Self {
+        Self { base: AtomicUsize::new(0) }
+    }
+
+    /// Checks if the CPU has a local APIC.
+    pub fn is_supported() -> bool {
+        unsafe { core::arch::x86_64::__cpuid(0x1) }.edx & CPUID_APIC != 0
+    }
+
+    /// Maps the registers, enables the local APIC and configures the spurious vector.
+    ///
+    /// Timer, thermal, performance counter and error entries are masked. LINT pins are left as
+    /// configured by the firmware, so the 8259 PIC keeps working through LINT0 in the virtual wire
+    /// mode until it is retired.
+    ///
+    /// # Unsafe
+    ///
+    /// A handler must be installed on the spurious vector, which calls [`spurious_interrupt`] and
+    /// sends no end of interrupt.
+    pub unsafe fn init(&self, spurious_vector: u8) -> Result<(), ApicError> {
+        if !Self::is_supported() {
+            return Err(ApicError::Unsupported)
+        }
+
+        let (flags, base) = ApicBase::read();
+        let mmu = unsafe { &mut *MEMORY_MANAGEMENT_UNIT };
+        if mmu.translate(base).is_none() {
+            mmu.map_to(
+                Page::containing_address(base),
+                Frame::info_address(base),
+                EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::WRITE_THROUGH,
+            ).map_err(ApicError::MapFailed)?;
+        }
+
+        let flags = ApicBaseFlags::from((flags | ApicBaseFlags::GLOBAL_ENABLE).bits() & !ApicBaseFlags::X2APIC_ENABLE.bits());
+        unsafe { ApicBase::write(flags, base) };
+        self.base.store(base, Ordering::Release);
+
+        for lvt in [Lvt::Timer, Lvt::Thermal, Lvt::PerformanceCounter, Lvt::Error] {
+            self.mask(lvt);
+        }
+        self.error_status();
+        self.set_task_priority(0);
+        self.set_spurious_vector(spurious_vector);
+        // Under KVM most of the EOI writes, which trap to the host, can be skipped.
+        hypervisor::enable_pv_eoi();
+        Ok(())
+    }
+
+    /// Returns true if the local APIC was initialized.
+    pub fn is_enabled(&self) -> bool {
+        self.base.load(Ordering::Acquire) != 0
+    }
+
+    /// Returns the physical address of the registers.
+    pub fn base_address() -> PhysicalAddress {
+        ApicBase::read().1
+    }
+
+    /// Returns the local APIC id of the current CPU.
+    pub fn id(&self) -> u8 {
+        (self.read(Register::Id as usize) >> 24) as u8
+    }
+
+    /// Returns the version of the local APIC.
+    pub fn version(&self) -> u8 {
+        self.read(Register::Version as usize) as u8
+    }
+
+    /// Returns the amount of entries within the local vector table.
+    pub fn lvt_entries(&self) -> u8 {
+        (self.read(Register::Version as usize) >> 16) as u8 + 1
+    }
+
+    /// Signals the end of the interrupt being handled.
+    ///
+    /// Must be the last thing done by the interrupt handler. Does nothing if the local APIC is not
+    /// initialized, or if the host allowed to skip it with the paravirtual EOI.
+    #[inline]
+    pub fn end_of_interrupt(&self) {
+        if hypervisor::pv_eoi_ack() {
+            return
+        }
+        self.write(Register::EndOfInterrupt as usize, 0);
+    }
+
+    /// Configures the vector of spurious interrupts and enables the local APIC.
+    pub fn set_spurious_vector(&self, vector: u8) {
+        let svr = self.read(Register::SpuriousVector as usize) & !0xff;
+        self.write(Register::SpuriousVector as usize, svr | SVR_ENABLE | vector as u32);
+    }
+
+    /// Sets the task priority. Interrupts with vectors of lower or same priority class are blocked.
+    pub fn set_task_priority(&self, priority: u8) {
+        self.write(Register::TaskPriority as usize, priority as u32);
+    }
+
+    /// Reads the entry of the local vector table.
+    pub fn read_lvt(&self, lvt: Lvt) -> LvtEntry {
+        LvtEntry(self.read(lvt as usize))
+    }
+
+    /// Writes the entry of the local vector table.
+    pub fn write_lvt(&self, lvt: Lvt, entry: LvtEntry) {
+        self.write(lvt as usize, entry.bits());
+    }
+
+    /// Masks the local interrupt source.
+    pub fn mask(&self, lvt: Lvt) {
+        self.write_lvt(lvt, self.read_lvt(lvt).masked(true));
+    }
+
+    /// Unmasks the local interrupt source.
+    pub fn unmask(&self, lvt: Lvt) {
+        self.write_lvt(lvt, self.read_lvt(lvt).masked(false));
+    }
+
+    /// Sets the divider of the bus clock used by the timer. Must be a power of two up to 128.
+    pub fn set_timer_divide(&self, divide: u8) {
+        // Divide by 1 is encoded as 0b1011, others as log2(divide) - 1 with bit 2 skipped.
+        let bits = match divide.trailing_zeros() {
+            0 => 0b1011,
+            n => (n - 1) & 0b11 | ((n - 1) & 0b100) << 1,
+        };
+        self.write(Register::TimerDivide as usize, bits);
+    }
+
+    /// Writes the initial count of the timer, which starts it. Zero stops the timer.
+    pub fn set_timer_initial_count(&self, count: u32) {
+        self.write(Register::TimerInitialCount as usize, count);
+    }
+
+    /// Returns the current count of the timer.
+    pub fn timer_current_count(&self) -> u32 {
+        self.read(Register::TimerCurrentCount as usize)
+    }
+
+    /// Sends an interprocessor interrupt to the local APIC with the provided id.
+    ///
+    /// Waits until the previous IPI is accepted before the new one is sent.
+    pub fn send_ipi(&self, destination: u8, mode: DeliveryMode, vector: u8) {
+        self.wait_ipi();
+        self.write(Register::InterruptCommandHigh as usize, (destination as u32) << 24);
+        // Writing the low half sends the interrupt.
+        self.write(Register::InterruptCommandLow as usize, ICR_ASSERT | (mode as u32) << 8 | vector as u32);
+    }
+
+    /// Sends a fixed interrupt with the provided vector to the current core.
+    ///
+    /// The destination register is ignored, so an IPI sent by the interrupted code is unaffected.
+    pub fn send_self_ipi(&self, vector: u8) {
+        self.wait_ipi();
+        self.write(Register::InterruptCommandLow as usize, ICR_SELF | ICR_ASSERT | vector as u32);
+    }
+
+    /// Spins until the last IPI sent is accepted by the target.
+    pub fn wait_ipi(&self) {
+        while self.read(Register::InterruptCommandLow as usize) & ICR_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Checks if the interrupt with provided vector is being handled.
+    pub fn in_service(&self, vector: u8) -> bool {
+        let reg = Register::InService as usize + (vector as usize / 32) * 0x10;
+        self.read(reg) & 1 << (vector % 32) != 0
+    }
+
+    /// Returns and clears the errors detected by the local APIC.
+    pub fn error_status(&self) -> u32 {
+        // The register is updated by a write.
+        self.write(Register::ErrorStatus as usize, 0);
+        self.read(Register::ErrorStatus as usize)
+    }
+
+    /// Software disables the local APIC. All local sources are masked until it is enabled again
+    /// with [`LocalApic::set_spurious_vector`].
+    pub fn disable(&self) {
+        let svr = self.read(Register::SpuriousVector as usize);
+        self.write(Register::SpuriousVector as usize, svr & !SVR_ENABLE);
+    }
+
+    #[inline]
+    fn read(&self, offset: usize) -> u32 {
+        match self.base.load(Ordering::Acquire) {
+            0 => 0,
+            base => unsafe { ptr::read_volatile((base + offset) as *const u32) },
+        }
+    }
+
+    #[inline]
+    fn write(&self, offset: usize, value: u32) {
+        if let base @ 1.. = self.base.load(Ordering::Acquire) {
+            unsafe { ptr::write_volatile((base + offset) as *mut u32, value) }
+        }
+    }
+}
+
+#[test_case]
+fn lvt_entries_are_encoded() {
+    let entry = LvtEntry::new(0x30)
+        .delivery(DeliveryMode::Fixed)
+        .level_triggered()
+        .active_low()
+        .masked(true);
+    assert_eq!(entry.bits(), 0x30 | 1 << 13 | 1 << 15 | 1 << 16);
+    assert_eq!(entry.vector(), 0x30);
+    assert!(entry.is_masked());
+    assert!(!entry.masked(false).is_masked());
+
+    let nmi = LvtEntry::new(0).delivery(DeliveryMode::Nmi);
+    assert_eq!(nmi.bits(), 0b100 << 8);
+    assert_eq!(nmi.delivery(DeliveryMode::ExtInt).bits(), 0b111 << 8);
+    assert!(LvtEntry::MASKED.is_masked());
+
+    let timer = LvtEntry::new(0x20).timer_mode(TimerMode::TscDeadline);
+    assert_eq!(timer.timer_mode(TimerMode::Periodic).bits(), 0x20 | 1 << 17);
+
+    // Nothing is touched before the initialization.
+    let apic = LocalApic::new();
+    assert!(!apic.is_enabled());
+    assert_eq!(apic.id(), 0);
+    apic.end_of_interrupt();
+}
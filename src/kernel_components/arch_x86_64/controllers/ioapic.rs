@@ -0,0 +1,522 @@
+/// Support for the IO Advanced Programmable Interrupt Controller.
+///
+/// IO APICs receive the interrupts of external devices and forward them to the local APICs of the
+/// processors. Each input pin has it's own redirection table entry, which defines the IDT vector,
+/// the destination processor, the trigger mode and the polarity, so any line can be routed to any
+/// vector. Inputs are numbered system wide by global system interrupts (GSI), every IO APIC
+/// handles a contiguous range of them starting at it's base.
+///
+/// ISA IRQs are identity mapped to GSIs unless the MADT provides an interrupt source override.
+/// The PIT for example is usually wired to GSI 2. [`IoApics`] applies those overrides, so the ISA
+/// lines are routed by their well known numbers.
+///
+/// Once [`init_from_madt`] succeeds, the IO APIC replaces the 8259 PIC as the active interrupt
+/// controller returned by [`with_controller`].
+///
+/// [`with_controller`]: crate::kernel_components::drivers::interrupts::with_controller
+
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::error::Error;
+use core::ptr;
+
+use super::apic::{DeliveryMode, LOCAL_APIC, SPURIOUS_VECTOR, ApicError};
+use super::pic::PROGRAMMABLE_INTERRUPT_CONTROLLER;
+use crate::kernel_components::arch_x86_64::acpi::madt::{MADT, MadtEntry, MpsIntiFlags};
+use crate::kernel_components::arch_x86_64::interrupts::InterruptVector;
+use crate::kernel_components::drivers::interrupts::{InterruptController, IntCtrlError};
+use crate::kernel_components::memory::{EntryFlags, Page, MEMORY_MANAGEMENT_UNIT};
+use crate::kernel_components::memory::frames::Frame;
+use crate::kernel_components::sync::Mutex;
+use crate::{critical_section, PhysicalAddress};
+
+/// Active IO APICs of the system. None until [`init_from_madt`] is called.
+pub static IO_APIC: Mutex<Option<IoApics>> = Mutex::new(None);
+
+/// Register selector.
+const IOREGSEL: usize = 0x00;
+/// Data window of the selected register.
+const IOWIN: usize = 0x10;
+
+/// IO APIC identification register.
+const IOAPICID: u32 = 0x00;
+/// IO APIC version register. Bits 16..24 hold the index of the last redirection entry.
+const IOAPICVER: u32 = 0x01;
+/// First redirection table register. Each entry takes two registers.
+const IOREDTBL: u32 = 0x10;
+
+/// Polarity of an interrupt input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// Trigger mode of an interrupt input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// Single redirection table entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectionEntry(u64);
+
+impl RedirectionEntry {
+    /// Masked entry without a vector.
+    pub const MASKED: Self = Self(1 << 16);
+
+    /// Creates an unmasked, edge triggered, active high entry with fixed delivery of the vector to
+    /// the local APIC with the provided id.
+    pub const fn new(vector: u8, destination: u8) -> Self {
+        Self(vector as u64 | (destination as u64) << 56)
+    }
+
+    /// Changes the delivery mode.
+    pub const fn delivery(self, mode: DeliveryMode) -> Self {
+        Self(self.0 & !(0b111 << 8) | (mode as u64) << 8)
+    }
+
+    /// Changes the polarity.
+    pub const fn polarity(self, polarity: Polarity) -> Self {
+        match polarity {
+            Polarity::ActiveHigh => Self(self.0 & !(1 << 13)),
+            Polarity::ActiveLow => Self(self.0 | 1 << 13),
+        }
+    }
+
+    /// Changes the trigger mode.
+    pub const fn trigger(self, trigger: TriggerMode) -> Self {
+        match trigger {
+            TriggerMode::Edge => Self(self.0 & !(1 << 15)),
+            TriggerMode::Level => Self(self.0 | 1 << 15),
+        }
+    }
+
+    /// Masks or unmasks the input.
+    pub const fn masked(self, masked: bool) -> Self {
+        match masked {
+            true => Self(self.0 | 1 << 16),
+            false => Self(self.0 & !(1 << 16)),
+        }
+    }
+
+    /// Changes the local APIC id of the destination processor.
+    pub const fn destination(self, apic_id: u8) -> Self {
+        Self(self.0 & !(0xff << 56) | (apic_id as u64) << 56)
+    }
+
+    /// Returns the vector of the entry.
+    pub const fn vector(&self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Returns the local APIC id of the destination processor.
+    pub const fn destination_id(&self) -> u8 {
+        (self.0 >> 56) as u8
+    }
+
+    /// Returns true if the input is masked.
+    pub const fn is_masked(&self) -> bool {
+        self.0 & 1 << 16 != 0
+    }
+
+    /// Returns the polarity of the input.
+    pub const fn get_polarity(&self) -> Polarity {
+        match self.0 & 1 << 13 {
+            0 => Polarity::ActiveHigh,
+            _ => Polarity::ActiveLow,
+        }
+    }
+
+    /// Returns the trigger mode of the input.
+    pub const fn get_trigger(&self) -> TriggerMode {
+        match self.0 & 1 << 15 {
+            0 => TriggerMode::Edge,
+            _ => TriggerMode::Level,
+        }
+    }
+
+    /// Returns the raw value of the entry.
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for RedirectionEntry {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+/// Custom error type for the IO APIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoApicError {
+    /// ACPI does not provide a MADT.
+    NoMadt,
+    /// The MADT describes no IO APIC.
+    NoIoApic,
+    /// The global system interrupt is not handled by any IO APIC.
+    InvalidGsi(u32),
+    /// The vector is reserved for exceptions or is out of the IDT.
+    InvalidVector(InterruptVector),
+    /// The local APIC could not be initialized.
+    LocalApic(ApicError),
+}
+
+impl Display for IoApicError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoMadt => write!(f, "ACPI does not provide a MADT."),
+            Self::NoIoApic => write!(f, "The MADT describes no IO APIC."),
+            Self::InvalidGsi(gsi) => write!(f, "No IO APIC handles the GSI {}.", gsi),
+            Self::InvalidVector(vector) => write!(f, "The vector {:?} cannot be used for IRQs.", vector),
+            Self::LocalApic(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for IoApicError {}
+
+/// Single IO APIC chip.
+#[derive(Debug)]
+pub struct IoApic {
+    /// Address of the mapped registers.
+    base: usize,
+    /// First global system interrupt handled by the chip.
+    gsi_base: u32,
+    /// Amount of redirection table entries.
+    entries: u32,
+}
+
+impl IoApic {
+    /// Maps the registers of the IO APIC at the physical address.
+    ///
+    /// # Unsafe
+    ///
+    /// The address must be the base of an IO APIC, as described by the MADT.
+    pub unsafe fn new(base: PhysicalAddress, gsi_base: u32) -> Self {
+        let mmu = unsafe { &mut *MEMORY_MANAGEMENT_UNIT };
+        if mmu.translate(base).is_none() {
+            let _ = mmu.map_to(
+                Page::containing_address(base),
+                Frame::info_address(base),
+                EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::WRITE_THROUGH,
+            );
+        }
+
+        let mut ioapic = Self { base, gsi_base, entries: 0 };
+        ioapic.entries = (ioapic.read(IOAPICVER) >> 16 & 0xff) + 1;
+        ioapic
+    }
+
+    /// Returns the IO APIC id.
+    pub fn id(&self) -> u8 {
+        (self.read(IOAPICID) >> 24 & 0xf) as u8
+    }
+
+    /// Returns the version of the IO APIC.
+    pub fn version(&self) -> u8 {
+        self.read(IOAPICVER) as u8
+    }
+
+    /// Returns the range of global system interrupts handled by the chip.
+    pub fn gsi_range(&self) -> core::ops::Range<u32> {
+        self.gsi_base..self.gsi_base + self.entries
+    }
+
+    /// Reads the redirection table entry of the input pin.
+    pub fn read_entry(&self, pin: u32) -> RedirectionEntry {
+        let low = self.read(IOREDTBL + pin * 2) as u64;
+        let high = self.read(IOREDTBL + pin * 2 + 1) as u64;
+        RedirectionEntry(high << 32 | low)
+    }
+
+    /// Writes the redirection table entry of the input pin.
+    ///
+    /// The entry is masked while the halves are written, so a half written entry never fires.
+    pub fn write_entry(&mut self, pin: u32, entry: RedirectionEntry) {
+        self.write(IOREDTBL + pin * 2, RedirectionEntry::MASKED.bits() as u32);
+        self.write(IOREDTBL + pin * 2 + 1, (entry.bits() >> 32) as u32);
+        self.write(IOREDTBL + pin * 2, entry.bits() as u32);
+    }
+
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            ptr::write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            ptr::read_volatile((self.base + IOWIN) as *const u32)
+        }
+    }
+
+    fn write(&mut self, reg: u32, value: u32) {
+        unsafe {
+            ptr::write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            ptr::write_volatile((self.base + IOWIN) as *mut u32, value);
+        }
+    }
+}
+
+/// ISA IRQ wiring described by an interrupt source override.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsaOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+impl IsaOverride {
+    /// Decodes the override flags. ISA defaults are active high and edge triggered.
+    pub fn new(irq: u8, gsi: u32, flags: u16) -> Self {
+        let polarity = match flags & 0b11 == MpsIntiFlags::ACTIVE_LOW.bits() {
+            true => Polarity::ActiveLow,
+            false => Polarity::ActiveHigh,
+        };
+        let trigger = match flags & 0b11 << 2 == MpsIntiFlags::LEVEL.bits() {
+            true => TriggerMode::Level,
+            false => TriggerMode::Edge,
+        };
+        Self { irq, gsi, polarity, trigger }
+    }
+}
+
+/// All IO APICs of the system with the ISA IRQ overrides.
+#[derive(Debug)]
+pub struct IoApics {
+    chips: Vec<IoApic>,
+    overrides: Vec<IsaOverride>,
+}
+
+impl IoApics {
+    /// Creates the IO APICs from the chips and the overrides.
+    pub fn new(chips: Vec<IoApic>, overrides: Vec<IsaOverride>) -> Self {
+        Self { chips, overrides }
+    }
+
+    /// Maps every IO APIC described by the MADT. All inputs are left masked.
+    pub fn from_madt(madt: &MADT) -> Result<Self, IoApicError> {
+        let mut chips = Vec::new();
+        let mut overrides = Vec::new();
+        for entry in madt.entries() {
+            match entry {
+                MadtEntry::IoApic { address, gsi_base, .. } => {
+                    chips.push(unsafe { IoApic::new(address as PhysicalAddress, gsi_base) })
+                },
+                MadtEntry::InterruptSourceOverride { bus: 0, source, gsi, flags } => {
+                    overrides.push(IsaOverride::new(source, gsi, flags))
+                },
+                _ => (),
+            }
+        }
+        if chips.is_empty() {
+            return Err(IoApicError::NoIoApic)
+        }
+
+        let mut ioapics = Self::new(chips, overrides);
+        for chip in ioapics.chips.iter_mut() {
+            for pin in 0..chip.entries {
+                chip.write_entry(pin, RedirectionEntry::MASKED);
+            }
+        }
+        Ok(ioapics)
+    }
+
+    /// Returns the wiring of the ISA IRQ after the overrides.
+    pub fn isa_irq(&self, irq: u8) -> IsaOverride {
+        self.overrides
+            .iter()
+            .find(|o| o.irq == irq)
+            .copied()
+            .unwrap_or(IsaOverride { irq, gsi: irq as u32, polarity: Polarity::ActiveHigh, trigger: TriggerMode::Edge })
+    }
+
+    /// Routes the global system interrupt to the vector of the destination processor.
+    ///
+    /// The vector must be one of the IRQ mappings of [`InterruptVector`]. The input stays masked if
+    /// it was masked before.
+    pub fn route_gsi(
+        &mut self,
+        gsi: u32,
+        vector: InterruptVector,
+        polarity: Polarity,
+        trigger: TriggerMode,
+        destination: u8,
+    ) -> Result<(), IoApicError> {
+        let vector = irq_vector(vector)?;
+        let (chip, pin) = self.locate(gsi).ok_or(IoApicError::InvalidGsi(gsi))?;
+        let masked = chip.read_entry(pin).is_masked();
+        let entry = RedirectionEntry::new(vector, destination)
+            .polarity(polarity)
+            .trigger(trigger)
+            .masked(masked);
+        chip.write_entry(pin, entry);
+        Ok(())
+    }
+
+    /// Routes the ISA IRQ to the vector of the destination processor, respecting the overrides of
+    /// the MADT.
+    pub fn route_isa(&mut self, irq: u8, vector: InterruptVector, destination: u8) -> Result<(), IoApicError> {
+        let wiring = self.isa_irq(irq);
+        self.route_gsi(wiring.gsi, vector, wiring.polarity, wiring.trigger, destination)
+    }
+
+    /// Returns the redirection table entry of the global system interrupt.
+    pub fn entry(&mut self, gsi: u32) -> Option<RedirectionEntry> {
+        self.locate(gsi).map(|(chip, pin)| chip.read_entry(pin))
+    }
+
+    /// Reads the redirection table entries of every chip, in the order of the chips.
+    ///
+    /// The IO APICs are reset by a sleep state, so the entries are saved before and written back
+    /// with [`IoApics::restore`] afterwards.
+    pub fn save(&self) -> Vec<RedirectionEntry> {
+        self.chips
+            .iter()
+            .flat_map(|chip| (0..chip.entries).map(move |pin| chip.read_entry(pin)))
+            .collect()
+    }
+
+    /// Writes back the redirection table entries read by [`IoApics::save`].
+    pub fn restore(&mut self, entries: &[RedirectionEntry]) {
+        let mut entries = entries.iter();
+        for chip in self.chips.iter_mut() {
+            for (pin, &entry) in (0..chip.entries).zip(&mut entries) {
+                chip.write_entry(pin, entry);
+            }
+        }
+    }
+
+    /// Returns the chip and it's pin for the global system interrupt.
+    fn locate(&mut self, gsi: u32) -> Option<(&mut IoApic, u32)> {
+        self.chips
+            .iter_mut()
+            .find(|chip| chip.gsi_range().contains(&gsi))
+            .map(|chip| {
+                let pin = gsi - chip.gsi_base;
+                (chip, pin)
+            })
+    }
+
+    /// Translates the controller IRQ into the GSI. ISA IRQs go through the overrides.
+    fn gsi_of(&self, irq: u8) -> u32 {
+        match irq {
+            0..=15 => self.isa_irq(irq).gsi,
+            irq => irq as u32,
+        }
+    }
+
+    fn update(&mut self, irq: u8, f: impl FnOnce(RedirectionEntry) -> RedirectionEntry) -> Result<(), IntCtrlError> {
+        let gsi = self.gsi_of(irq);
+        let (chip, pin) = self.locate(gsi).ok_or(IntCtrlError::InvalidIrq(irq))?;
+        let entry = f(chip.read_entry(pin));
+        chip.write_entry(pin, entry);
+        Ok(())
+    }
+}
+
+/// Converts the IRQ mapping into the vector number.
+fn irq_vector(vector: InterruptVector) -> Result<u8, IoApicError> {
+    match vector {
+        InterruptVector::APICMappings(num @ 0x20..0xff)
+            | InterruptVector::PICMappings(num @ 0x20..0xff)
+            | InterruptVector::Custom(num @ 0x20..0xff) => Ok(num as u8),
+        vector => Err(IoApicError::InvalidVector(vector)),
+    }
+}
+
+/// IRQ numbers of the IO APIC controller are ISA IRQs below 16, which are translated through the
+/// interrupt source overrides, and global system interrupts above.
+impl InterruptController for IoApics {
+    fn mask(&mut self, irq: u8) -> Result<(), IntCtrlError> {
+        self.update(irq, |entry| entry.masked(true))
+    }
+
+    fn unmask(&mut self, irq: u8) -> Result<(), IntCtrlError> {
+        self.update(irq, |entry| entry.masked(false))
+    }
+
+    fn is_masked(&mut self, irq: u8) -> Result<bool, IntCtrlError> {
+        let gsi = self.gsi_of(irq);
+        self.entry(gsi).map(|entry| entry.is_masked()).ok_or(IntCtrlError::InvalidIrq(irq))
+    }
+
+    fn end_of_interrupt(&mut self, _vector: u8) {
+        LOCAL_APIC.end_of_interrupt();
+    }
+
+    fn set_affinity(&mut self, irq: u8, cpu: u8) -> Result<(), IntCtrlError> {
+        self.update(irq, |entry| entry.destination(cpu))
+    }
+
+    fn map_gsi(&mut self, gsi: u32) -> Result<u8, IntCtrlError> {
+        let gsi = match u8::try_from(gsi) {
+            Ok(irq) => self.gsi_of(irq),
+            Err(_) => gsi,
+        };
+        match self.entry(gsi) {
+            Some(entry) if entry.vector() >= 0x20 => Ok(entry.vector()),
+            _ => Err(IntCtrlError::InvalidGsi(gsi)),
+        }
+    }
+
+    fn is_spurious(&mut self, vector: u8) -> bool {
+        vector == SPURIOUS_VECTOR
+    }
+}
+
+/// Replaces the 8259 PIC with the IO APICs described by the MADT.
+///
+/// The local APIC is initialized and every ISA IRQ except the cascade line is routed to the vector
+/// at the offset plus the IRQ number on the current processor, with the masks of the PIC carried
+/// over. The PIC is masked afterwards, so handlers placed for the PIC keep working.
+///
+/// # Unsafe
+///
+/// Must be called with interrupts disabled and a handler for [`SPURIOUS_VECTOR`] installed.
+pub unsafe fn init_from_madt(offset: u8) -> Result<(), IoApicError> {
+    let madt = MADT::find().ok_or(IoApicError::NoMadt)?;
+    unsafe { LOCAL_APIC.init(SPURIOUS_VECTOR) }.map_err(IoApicError::LocalApic)?;
+    let destination = LOCAL_APIC.id();
+
+    let mut ioapics = IoApics::from_madt(madt)?;
+    critical_section!(|| {
+        let mut pic = PROGRAMMABLE_INTERRUPT_CONTROLLER.lock();
+        for irq in (0..16u8).filter(|&irq| irq != 2) {
+            let vector = InterruptVector::APICMappings(offset as usize + irq as usize);
+            ioapics.route_isa(irq, vector, destination)?;
+            let masked = pic.as_mut().map_or(Ok(true), |pic| pic.is_masked(irq)).unwrap_or(true);
+            if !masked {
+                let _ = ioapics.unmask(irq);
+            }
+        }
+
+        if let Some(pic) = pic.as_mut() {
+            pic.disable();
+        }
+        IO_APIC.lock().replace(ioapics);
+        Ok(())
+    })
+}
+
+#[test_case]
+fn redirection_entries_and_overrides() {
+    let entry = RedirectionEntry::new(0x21, 3)
+        .polarity(Polarity::ActiveLow)
+        .trigger(TriggerMode::Level)
+        .masked(true);
+    assert_eq!(entry.bits(), 0x21 | 1 << 13 | 1 << 15 | 1 << 16 | 3 << 56);
+    assert_eq!((entry.vector(), entry.destination_id()), (0x21, 3));
+    assert_eq!((entry.get_polarity(), entry.get_trigger()), (Polarity::ActiveLow, TriggerMode::Level));
+    assert!(!entry.masked(false).is_masked());
+
+    let ioapics = IoApics::new(Vec::new(), alloc::vec![IsaOverride::new(0, 2, 0), IsaOverride::new(9, 9, 0x0f)]);
+    assert_eq!(ioapics.isa_irq(0).gsi, 2);
+    assert_eq!(ioapics.isa_irq(9).trigger, TriggerMode::Level);
+    assert_eq!(ioapics.isa_irq(9).polarity, Polarity::ActiveLow);
+    assert_eq!(ioapics.isa_irq(1), IsaOverride { irq: 1, gsi: 1, polarity: Polarity::ActiveHigh, trigger: TriggerMode::Edge });
+
+    assert_eq!(irq_vector(InterruptVector::APICMappings(0x30)), Ok(0x30));
+    assert_eq!(irq_vector(InterruptVector::APICMappings(0x10)), Err(IoApicError::InvalidVector(InterruptVector::APICMappings(0x10))));
+    assert!(irq_vector(InterruptVector::PAGE_FAULT).is_err());
+}
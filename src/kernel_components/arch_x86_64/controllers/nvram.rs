@@ -0,0 +1,215 @@
+/// Module that stores a few bytes of OS configuration in the free range of the CMOS memory.
+///
+/// The CMOS memory is powered by the RTC battery, therefore the values survive reboots and
+/// power loss. The stored bytes are protected with a magic byte and a 16-bit checksum kept in the
+/// last two bytes of the range, so corrupted or foreign data is detected instead of silently used.
+
+use crate::bitflags;
+use super::rtc::{RTC, CMOSAddr};
+
+/// First CMOS address used by the OS.
+///
+/// The range stays clear of the bytes QEMU and SeaBIOS use: 0x38 and 0x3d hold the boot order and
+/// the floppy signature check flag, 0x39 the ATA translation policy, 0x5b..=0x5d the memory above
+/// 4 GiB and 0x5f the amount of processors. Nothing in 0x40..0x5b is touched by the firmware.
+const NVRAM_START: u8 = 0x40;
+/// Address of the magic byte.
+const NVRAM_MAGIC_ADDR: u8 = NVRAM_START;
+/// Address of the low byte of the checksum. The high byte follows.
+const NVRAM_CHECKSUM_ADDR: u8 = 0x46;
+/// Magic value that marks the range as initialized by the OS.
+const NVRAM_MAGIC: u8 = 0x6e;
+
+/// Amount of bytes available for configuration.
+pub const NVRAM_SIZE: usize = (NVRAM_CHECKSUM_ADDR - NVRAM_START - 1) as usize;
+
+/// Offset of the default TTY byte.
+const DEFAULT_TTY: usize = 0;
+/// Offset of the boot flags byte.
+const BOOT_FLAGS: usize = 1;
+
+/// Error type for NVRAM operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NvramError {
+    /// The range was never written by the OS.
+    Uninitialized,
+    /// The stored checksum does not match the stored data.
+    Corrupted { stored: u16, computed: u16 },
+    /// The offset is outside of the configuration range.
+    OutOfRange(usize),
+}
+
+bitflags! {
+    /// Boot flags stored in the NVRAM.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct BootFlags: u8 {
+        /// Print debug messages during boot.
+        const VERBOSE           = 1 << 0,
+        /// Do not load optional drivers.
+        const SAFE_MODE         = 1 << 1,
+        /// Mirror the console to the serial port.
+        const SERIAL_CONSOLE    = 1 << 2,
+        /// Skip boot self tests.
+        const SKIP_SELFTEST     = 1 << 3,
+    };
+}
+
+/// OS configuration stored in the CMOS memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NvramConfig {
+    /// Index of the TTY used as the console after boot.
+    pub default_tty: u8,
+    /// Boot flags.
+    pub boot_flags: BootFlags,
+}
+
+impl Default for NvramConfig {
+    fn default() -> Self {
+        Self {
+            default_tty: 0,
+            boot_flags: BootFlags::empty(),
+        }
+    }
+}
+
+/// Checksum protected configuration storage within the CMOS memory.
+pub struct Nvram {
+    rtc: RTC,
+}
+
+impl Nvram {
+    /// Creates a new instance of NVRAM storage.
+    pub const fn new() -> Self {
+        Self { rtc: RTC::with_nmi(true) }
+    }
+
+    /// Verifies the magic byte and the checksum of the stored data.
+    pub fn verify(&self) -> Result<(), NvramError> {
+        if self.rtc.read(NVRAM_MAGIC_ADDR.into()) != NVRAM_MAGIC {
+            return Err(NvramError::Uninitialized);
+        }
+
+        let stored = self.stored_checksum();
+        let computed = self.computed_checksum();
+        if stored != computed {
+            return Err(NvramError::Corrupted { stored, computed });
+        }
+        Ok(())
+    }
+
+    /// Reads one configuration byte.
+    pub fn read_byte(&self, offset: usize) -> Result<u8, NvramError> {
+        self.verify()?;
+        Ok(self.rtc.read(Self::addr(offset)?))
+    }
+
+    /// Writes one configuration byte and updates the checksum.
+    ///
+    /// Initializes the range with zeroes first, if it was never written before or is corrupted.
+    pub fn write_byte(&mut self, offset: usize, byte: u8) -> Result<(), NvramError> {
+        let addr = Self::addr(offset)?;
+        if self.verify().is_err() {
+            self.format();
+        }
+
+        unsafe { self.rtc.write(addr, byte) };
+        self.seal();
+        Ok(())
+    }
+
+    /// Loads the whole configuration.
+    pub fn load(&self) -> Result<NvramConfig, NvramError> {
+        Ok(NvramConfig {
+            default_tty: self.read_byte(DEFAULT_TTY)?,
+            boot_flags: BootFlags::from(self.read_byte(BOOT_FLAGS)?),
+        })
+    }
+
+    /// Stores the whole configuration.
+    pub fn store(&mut self, config: &NvramConfig) {
+        let _ = self.write_byte(DEFAULT_TTY, config.default_tty);
+        let _ = self.write_byte(BOOT_FLAGS, config.boot_flags.bits());
+    }
+
+    /// Clears the configuration range and writes a valid header.
+    pub fn format(&mut self) {
+        unsafe {
+            for offset in 0..NVRAM_SIZE {
+                self.rtc.write(Self::addr(offset).unwrap(), 0);
+            }
+            self.rtc.write(NVRAM_MAGIC_ADDR.into(), NVRAM_MAGIC);
+        }
+        self.seal();
+    }
+
+    fn addr(offset: usize) -> Result<CMOSAddr, NvramError> {
+        if offset < NVRAM_SIZE {
+            Ok(CMOSAddr::from(NVRAM_START + 1 + offset as u8))
+        } else {
+            Err(NvramError::OutOfRange(offset))
+        }
+    }
+
+    fn stored_checksum(&self) -> u16 {
+        u16::from_le_bytes([
+            self.rtc.read(NVRAM_CHECKSUM_ADDR.into()),
+            self.rtc.read((NVRAM_CHECKSUM_ADDR + 1).into()),
+        ])
+    }
+
+    /// Fletcher-16 over the magic and data bytes, so swapped bytes are detected as well.
+    fn computed_checksum(&self) -> u16 {
+        let (sum1, sum2) = (NVRAM_START..NVRAM_CHECKSUM_ADDR)
+            .map(|addr| self.rtc.read(addr.into()) as u16)
+            .fold((0u16, 0u16), |(s1, s2), byte| {
+                let s1 = (s1 + byte) % 255;
+                (s1, (s2 + s1) % 255)
+            });
+        sum2 << 8 | sum1
+    }
+
+    fn seal(&mut self) {
+        let [low, high] = self.computed_checksum().to_le_bytes();
+        unsafe {
+            self.rtc.write(NVRAM_CHECKSUM_ADDR.into(), low);
+            self.rtc.write((NVRAM_CHECKSUM_ADDR + 1).into(), high);
+        }
+    }
+}
+
+/// Verifies the NVRAM configuration during boot.
+///
+/// Corruption is reported and the range is reformatted, so the defaults are used from now on.
+/// Returns the stored configuration or the default one.
+pub fn check_at_boot() -> NvramConfig {
+    let mut nvram = Nvram::new();
+
+    match nvram.load() {
+        Ok(config) => config,
+        Err(NvramError::Uninitialized) => {
+            nvram.format();
+            NvramConfig::default()
+        },
+        Err(err) => {
+            crate::warn!("CMOS configuration is corrupted ({:?}), restoring defaults.", err);
+            nvram.format();
+            NvramConfig::default()
+        },
+    }
+}
+
+#[test_case]
+fn nvram_detects_corruption() {
+    let mut nvram = Nvram::new();
+    let config = NvramConfig { default_tty: 2, boot_flags: BootFlags::VERBOSE | BootFlags::SAFE_MODE };
+
+    nvram.store(&config);
+    assert_eq!(nvram.load().map(|c| (c.default_tty, c.boot_flags.bits())), Ok((2, config.boot_flags.bits())));
+
+    // Corrupting a data byte behind the back of the checksum.
+    unsafe { nvram.rtc.write(Nvram::addr(3).unwrap(), 0x5a) };
+    assert!(matches!(nvram.verify(), Err(NvramError::Corrupted { .. })));
+
+    assert_eq!(check_at_boot().default_tty, 0);
+    assert_eq!(nvram.verify(), Ok(()));
+}
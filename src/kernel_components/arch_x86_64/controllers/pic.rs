@@ -8,6 +8,8 @@
 /// which can be adjusted based on it's configuration. Individual bits of IRQ register within the 
 /// PIC can be masked out by the software.
 
+use core::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+
 use crate::bitflags;
 use crate::kernel_components::arch_x86_64::ports::{GenericPort, PortAccessType};
 use crate::kernel_components::sync::Mutex;
@@ -17,6 +19,36 @@ use super::pic_command_words::*;
 /// Static vessel for PICs.
 pub static PROGRAMMABLE_INTERRUPT_CONTROLLER: Mutex<Option<ChainedPics>> = Mutex::new(None);
 
+/// IRQ lines, which shall be reported as spurious on the next check. See [´inject_spurious´].
+static SPURIOUS_INJECTION: AtomicU16 = AtomicU16::new(0);
+/// Amount of spurious interrupts detected by [´ChainedPics::is_spurious´].
+static SPURIOUS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Simulates a spurious interrupt on the provided IRQ line.
+///
+/// The next [´ChainedPics::is_spurious´] check of this line ignores the real ISR value and behaves
+/// as if the PIC had no interrupt in service, exactly like with a real spurious IRQ. This allows
+/// to cover spurious interrupt handling without having to provoke the race on real hardware.
+///
+/// # Panics
+///
+/// Spurious interrupts only occur on the lowest priority lines, therefore only IRQ7 and IRQ15 are
+/// accepted.
+pub fn inject_spurious(irq: u8) {
+    assert!(irq == 7 || irq == 15, "Spurious interrupts can only happen on IRQ7 or IRQ15.");
+    SPURIOUS_INJECTION.fetch_or(1 << irq, Ordering::AcqRel);
+}
+
+/// Returns IRQ lines with injected spurious interrupts, which were not yet consumed.
+pub fn injected_spurious() -> IrqMask {
+    IrqMask::from(SPURIOUS_INJECTION.load(Ordering::Acquire))
+}
+
+/// Returns the amount of spurious interrupts detected so far.
+pub fn spurious_count() -> usize {
+    SPURIOUS_COUNT.load(Ordering::Relaxed)
+}
+
 /// Defines the PIC IRQ mappings (hardwired lines) for the PIC controller.
 ///
 /// The PIC can be configured either as a master or a slave device. This will change the upcoming
@@ -699,16 +731,28 @@ impl ChainedPics {
     pub unsafe fn is_spurious(&mut self, vec_id: u8) -> bool {
         assert!(vec_id >= 32, "Cannot be one of the CPU exceptions."); 
 
-        if self.slave.handles_interrupt(vec_id) {
-            if self.slave.is_spurious(vec_id) { 
+        let spurious = if self.slave.handles_interrupt(vec_id) {
+            let injected = Self::take_injected(8 + vec_id - self.slave.offset);
+            if injected || self.slave.is_spurious(vec_id) { 
                 self.master.end_of_interrupt(); 
                 true 
             } else { false } 
         } else if self.master.handles_interrupt(vec_id) { 
-            self.master.is_spurious(vec_id) 
+            Self::take_injected(vec_id - self.master.offset) || self.master.is_spurious(vec_id) 
         } else {
             panic!("Provided interrupt is out of scope for both PICs.")
+        };
+
+        if spurious {
+            SPURIOUS_COUNT.fetch_add(1, Ordering::Relaxed);
         }
+        spurious
+    }
+
+    /// Consumes the simulated spurious interrupt of the IRQ line, if one was injected.
+    fn take_injected(irq: u8) -> bool {
+        let bit = 1 << irq;
+        SPURIOUS_INJECTION.fetch_and(!bit, Ordering::AcqRel) & bit != 0
     }
 
     /// Notify a proper PIC chip that the interrupt was succesfully handled and shall be cleared
@@ -205,9 +205,9 @@ bitflags! {
         /// Expect low byte only
         const LOW_BYTE_ONLY                     = 0b01 << 4,
         /// Expect high byte only
-        const HIGH_ONLY                         = 0b01 << 4,
+        const HIGH_ONLY                         = 0b10 << 4,
         /// Expect full word
-        const FULL_WORD                         = 0b01 << 4,
+        const FULL_WORD                         = 0b11 << 4,
 
         /* Operating Mode */
 
@@ -5,6 +5,15 @@ use alloc::vec::Vec;
 use crate::kernel_components::arch_x86_64::ports::{GenericPort, PortAccessType};
 use crate::bitflags;
 
+/// Acknowledge byte sent by PS/2 devices after each command byte.
+pub const DEVICE_ACK: u8 = 0xfa;
+/// Sent by PS/2 devices when the last byte must be sent again.
+pub const DEVICE_RESEND: u8 = 0xfe;
+/// Times a byte is written again on the request of the device.
+const RESEND_ATTEMPTS: usize = 3;
+/// Maximal amount of status polls while waiting for the device response.
+const RESPONSE_POLLS: usize = 100_000;
+
 /// A struct representing a 8042 PS/2 controller.
 /// 
 /// The PS/2 controller is often called a "Keyboard controller", which is usually used to
@@ -55,6 +64,82 @@ impl PS2 {
         self.data_port.read()
     }
 
+    /// Writes the byte to the first PS/2 device (keyboard).
+    ///
+    /// Waits until the input buffer of the controller is empty before writing.
+    ///
+    /// # Unsafe
+    ///
+    /// Device commands may change the behavior of the device in an unexpected way.
+    #[inline]
+    pub unsafe fn write_data(&mut self, byte: u8) {
+        while SRFlags::INPUT_BUFFER_STATUS.is_in(self.status_register.read()) {}
+        self.data_port.write(byte)
+    }
+
+    /// Writes the byte to the first PS/2 device (keyboard) and waits for the acknowledge, writing
+    /// it again while the device asks to resend it.
+    ///
+    /// A byte left in the output buffer is dropped first, so it is not taken for the response.
+    /// Returns false if the device rejected the byte or did not respond in time.
+    ///
+    /// # Unsafe
+    ///
+    /// Device commands may change the behavior of the device in an unexpected way. Interrupts must
+    /// be disabled, otherwise the response is consumed by the interrupt handler of the device.
+    pub unsafe fn write_data_acknowledged(&mut self, byte: u8) -> bool {
+        while SRFlags::OUTPUT_BUFFER_STATUS.is_in(self.status_register.read()) {
+            self.data_port.read();
+        }
+
+        for _ in 0..=RESEND_ATTEMPTS {
+            self.write_data(byte);
+            match self.read_data_timeout(RESPONSE_POLLS) {
+                Some(DEVICE_ACK) => return true,
+                Some(DEVICE_RESEND) => continue,
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// Writes the byte to the second PS/2 device (mouse).
+    ///
+    /// Waits until the input buffer of the controller is empty before writing.
+    ///
+    /// # Unsafe
+    ///
+    /// Device commands may change the behavior of the device in an unexpected way.
+    #[inline]
+    pub unsafe fn write_second_data(&mut self, byte: u8) {
+        self.write_raw_command(0xd4);
+        self.write_data(byte)
+    }
+
+    /// Writes a single byte command to the command port of the controller.
+    ///
+    /// # Unsafe
+    ///
+    /// Any command byte is written as is, without checking if it is a valid controller command.
+    #[inline]
+    pub unsafe fn write_raw_command(&mut self, command: u8) {
+        while SRFlags::INPUT_BUFFER_STATUS.is_in(self.status_register.read()) {}
+        self.command_register.write(command)
+    }
+
+    /// Waits until the output buffer is full and reads the value from the data port.
+    ///
+    /// Returns None if no data appeared within the provided amount of status polls.
+    pub fn read_data_timeout(&self, polls: usize) -> Option<u8> {
+        for _ in 0..polls {
+            if SRFlags::OUTPUT_BUFFER_STATUS.is_in(self.status_register.read()) {
+                return Some(self.data_port.read())
+            }
+            core::hint::spin_loop();
+        }
+        None
+    }
+
     /// Reads the value from the status register in the PS/2 controller.
     #[inline]
     pub fn read_status(&self) -> u8 {
@@ -107,6 +192,15 @@ impl PS2 {
             None
         }
     }
+
+    /// Resets the CPU by pulsing the reset line of the controller.
+    ///
+    /// # Unsafe
+    ///
+    /// The machine is reset immediately without flushing any state.
+    pub unsafe fn reset_cpu(&mut self) {
+        self.write_command(PSControllerCommand::pulse(0x0e));
+    }
 }
 
 /// This struct represents the command that must be given to the PS/2 controller.
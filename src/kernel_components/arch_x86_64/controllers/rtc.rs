@@ -9,6 +9,9 @@ use crate::{
     }
 };
 
+/// Bit of the index port that disables NMIs while set.
+const NMI_DISABLE: u8 = 1 << 7;
+
 /// Real Time Clock
 ///
 /// Structure that allows to manipulate on RTC chip and it's internal power static memory. RTC
@@ -47,8 +50,9 @@ use crate::{
 ///
 /// # NMI
 ///
-/// All configuration functions will turn off NMI interrupts and do not restore it, until manually
-/// enabled via 'enable_nmi' method.
+/// NMIs are disabled while a value is written to the chip. Afterwards they are restored according
+/// to the state chosen with 'enable_nmi' or 'disable_nmi' methods, so programming the chip never
+/// leaves NMIs disabled behind the back of it's owner.
 pub struct RTC {
     /// Port 0x70, which is used to select an index within the CMOS memory to read/write from
     /// and/or enabling or disabling NMIs. The CMOS memory is 64 bytes long, therefore values
@@ -64,10 +68,17 @@ pub struct RTC {
 impl RTC {
     /// Creates a new instance of RTC.
     pub const fn new() -> Self {
+        Self::with_nmi(false)
+    }
+
+    /// Creates a new instance of RTC with the provided NMI state.
+    ///
+    /// The state is not applied to the hardware until the first access to the chip.
+    pub const fn with_nmi(nmi: bool) -> Self {
         Self {
             index: GenericPort::new(0x70, PortAccessType::WRITEONLY),
             data: GenericPort::new(0x71, PortAccessType::READWRITE),
-            nmi: false,
+            nmi,
         }
     }
 
@@ -109,6 +120,11 @@ impl RTC {
         self.read(0.into());
     }
 
+    /// Returns true if NMIs are kept enabled by this instance.
+    pub fn is_nmi_enabled(&self) -> bool {
+        self.nmi
+    }
+
     /// Reads a value written inside the CMOS memory under a specific address provided.
     ///
     /// This function is always safe, because it ensures that all interrupts are off. The OS should
@@ -116,7 +132,7 @@ impl RTC {
     /// requested byte from the data port. This operation must be atomic.
     pub fn read(&self, addr: CMOSAddr) -> u8 {
         critical_section!(|| {
-            self.index.write(addr.bits() | (self.nmi as u8) << 7);
+            self.index.write(addr.bits() | if self.nmi { 0 } else { NMI_DISABLE });
             DEBUG_BOARD.write(0); // Small delay.
             self.data.read()
         })
@@ -128,13 +144,17 @@ impl RTC {
     ///
     /// As mentioned above, only writes to the RTC configuration (status registers A/B) are safe.
     /// Writing values to other memory fields are most likely to create a mess in the system. When
-    /// writing data, NMI will always be disabled.
+    /// writing data, NMI will always be disabled and restored afterwards.
     pub unsafe fn write(&mut self, addr: CMOSAddr, byte: u8) {
         critical_section!(|| {
-            self.index.write(addr.bits());
+            self.index.write(addr.bits() | NMI_DISABLE);
             DEBUG_BOARD.write(0); // Small delay.
             self.data.write(byte);
-        })
+        });
+        if self.nmi {
+            // Selecting a harmless register restores the NMI state.
+            self.read(CMOSAddr::RTC_STATUS_D);
+        }
     }
 
     /// Writes some byte to the CMOS memory under a specific address preserving all bytes specified
@@ -0,0 +1,307 @@
+/// CPU frequency scaling.
+///
+/// The performance of the processor is controlled either by the hardware controlled performance
+/// states (Intel HWP), which are enabled through MSRs when the processor supports them, or by the
+/// ACPI P-states described by the _PSS and _PCT objects, which are provided with
+/// [`CpuFreq::set_pstates`]. HWP takes precedence, because enabling it hands the P-state control
+/// over to the hardware.
+///
+/// The requested performance is expressed as a level in percent of the available range, which is
+/// chosen by the [`Governor`]. The ondemand governor samples the time spent in the idle loop every
+/// [`SAMPLE_INTERVAL_MS`] and runs at the full performance once the load exceeds the up
+/// threshold, scaling down proportionally to the load otherwise. The governor and the level of the
+/// manual mode are overridden with the "cpufreq.governor" and "cpufreq.manual_percent" tunables.
+
+use alloc::vec::Vec;
+use core::arch::x86_64::{__cpuid, _rdtsc};
+
+use crate::kernel_components::arch_x86_64::ports::Port;
+use crate::kernel_components::registers::ms::{Msr, PmEnable, HwpCapabilities, HwpRequest, PerfCtl};
+use crate::kernel_components::sync::Mutex;
+use crate::kernel_components::task_virtualization::{Thread, IDLE_GOVERNOR};
+use crate::critical_section;
+
+/// Interval between two samples of the governor thread in milliseconds.
+pub const SAMPLE_INTERVAL_MS: u32 = 100;
+/// Load in percent, above which the ondemand governor requests the full performance.
+pub const DEFAULT_UP_THRESHOLD: u8 = 80;
+
+/// CPUID leaf 6 EAX bit, which reports the HWP support.
+const CPUID_HWP: u32 = 1 << 7;
+
+/// Frequency scaling state of the bootstrap processor.
+pub static CPUFREQ: Mutex<CpuFreq> = Mutex::new(CpuFreq::new());
+
+/// Policy choosing the performance level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Governor {
+    /// Follows the load of the processor.
+    Ondemand,
+    /// Always runs at the full performance.
+    Performance,
+    /// Always runs at the lowest performance.
+    Powersave,
+    /// Runs at the level set manually.
+    Manual,
+}
+
+impl Governor {
+    /// Parses the name of the governor as used by the sysctl registry.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ondemand" => Some(Self::Ondemand),
+            "performance" => Some(Self::Performance),
+            "powersave" => Some(Self::Powersave),
+            "manual" => Some(Self::Manual),
+            _ => None,
+        }
+    }
+}
+
+/// Single performance state described by the ACPI _PSS object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PState {
+    /// Core frequency in MHz.
+    pub frequency: u32,
+    /// Typical power consumption in milliwatts.
+    pub power: u32,
+    /// Worst case transition latency in microseconds.
+    pub latency: u32,
+    /// Value written to the control register to request the state.
+    pub control: u64,
+    /// Value of the status register once the state is reached.
+    pub status: u64,
+}
+
+/// Control register described by the ACPI _PCT object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfRegister {
+    /// Functional fixed hardware, which is the IA32_PERF_CTL MSR on x86.
+    FixedHardware,
+    /// System IO port with the access width in bits.
+    SystemIo { port: u16, width: u8 },
+}
+
+/// The way the performance is controlled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Backend {
+    /// No control is available.
+    None,
+    /// Hardware controlled performance states with the range of performance levels.
+    Hwp { lowest: u8, highest: u8 },
+    /// ACPI P-states sorted from the fastest one.
+    PStates { states: Vec<PState>, control: PerfRegister },
+}
+
+/// Frequency scaling state.
+#[derive(Debug)]
+pub struct CpuFreq {
+    backend: Backend,
+    governor: Governor,
+    /// Level used by the manual governor.
+    manual: u8,
+    up_threshold: u8,
+    /// Highest level allowed, lowered by the thermal policy.
+    max_level: u8,
+    /// Currently requested level.
+    level: Option<u8>,
+    /// TSC value and total idle time at the last sample.
+    last_sample: (u64, u64),
+}
+
+impl CpuFreq {
+    /// Creates the state without any control. Nothing is changed until [`CpuFreq::detect`] or
+    /// [`CpuFreq::set_pstates`] is called.
+    pub const fn new() -> Self {
+        Self {
+            backend: Backend::None,
+            governor: Governor::Ondemand,
+            manual: 100,
+            up_threshold: DEFAULT_UP_THRESHOLD,
+            max_level: 100,
+            level: None,
+            last_sample: (0, 0),
+        }
+    }
+
+    /// Enables HWP, if the processor supports it. Returns true if it was enabled.
+    pub fn detect(&mut self) -> bool {
+        if unsafe { __cpuid(0) }.eax < 6 || unsafe { __cpuid(6) }.eax & CPUID_HWP == 0 {
+            return false
+        }
+
+        let capabilities = unsafe {
+            PmEnable::write_raw(1);
+            HwpCapabilities::read_raw()
+        };
+        self.backend = Backend::Hwp { highest: capabilities as u8, lowest: (capabilities >> 24) as u8 };
+        self.level = None;
+        true
+    }
+
+    /// Uses the ACPI P-states for the frequency control.
+    ///
+    /// Ignored while HWP is enabled. Returns true if the states are used.
+    pub fn set_pstates(&mut self, control: PerfRegister, mut states: Vec<PState>) -> bool {
+        if matches!(self.backend, Backend::Hwp { .. }) || states.is_empty() {
+            return false
+        }
+
+        states.sort_unstable_by(|a, b| b.frequency.cmp(&a.frequency));
+        self.backend = Backend::PStates { states, control };
+        self.level = None;
+        true
+    }
+
+    /// Returns true if the frequency can be controlled.
+    pub fn is_available(&self) -> bool {
+        self.backend != Backend::None
+    }
+
+    /// Changes the governor. The new level is applied on the next sample.
+    pub fn set_governor(&mut self, governor: Governor) {
+        self.governor = governor;
+    }
+
+    /// Returns the current governor.
+    pub fn governor(&self) -> Governor {
+        self.governor
+    }
+
+    /// Sets the level in percent used by the manual governor.
+    pub fn set_manual_level(&mut self, level: u8) {
+        self.manual = level.min(100);
+    }
+
+    /// Sets the load in percent, above which the ondemand governor requests the full performance.
+    pub fn set_up_threshold(&mut self, threshold: u8) {
+        self.up_threshold = threshold.clamp(1, 100);
+    }
+
+    /// Caps the level in percent requested by any governor. The current level is lowered at once
+    /// if it is above the cap.
+    pub fn set_max_level(&mut self, level: u8) {
+        self.max_level = level.min(100);
+        if let Some(current) = self.level.filter(|&current| current > self.max_level) {
+            self.apply(current);
+        }
+    }
+
+    /// Returns the highest level allowed in percent.
+    pub fn max_level(&self) -> u8 {
+        self.max_level
+    }
+
+    /// Returns the currently requested level in percent.
+    pub fn level(&self) -> Option<u8> {
+        self.level
+    }
+
+    /// Returns the frequency of the current P-state in MHz. HWP does not report frequencies.
+    pub fn frequency(&self) -> Option<u32> {
+        match &self.backend {
+            Backend::PStates { states, .. } => self.level.map(|level| states[pstate_index(level, states.len())].frequency),
+            _ => None,
+        }
+    }
+
+    /// Measures the load since the previous sample and applies the level chosen by the governor.
+    ///
+    /// The load is the share of time not spent in the idle loop. Returns the load in percent.
+    pub fn sample(&mut self, idle_time: u64, tsc_mhz: u64) -> u8 {
+        let now = unsafe { _rdtsc() };
+        let (last_tsc, last_idle) = core::mem::replace(&mut self.last_sample, (now, idle_time));
+
+        let passed = now.wrapping_sub(last_tsc) / tsc_mhz.max(1);
+        let idle = idle_time.saturating_sub(last_idle);
+        let load = match passed {
+            0 => 100,
+            passed => 100 - (idle.min(passed) * 100 / passed) as u8,
+        };
+
+        let level = match self.governor {
+            Governor::Ondemand => ondemand(load, self.up_threshold),
+            Governor::Performance => 100,
+            Governor::Powersave => 0,
+            Governor::Manual => self.manual,
+        }.min(self.max_level);
+        if self.level != Some(level) {
+            self.apply(level);
+        }
+        load
+    }
+
+    /// Requests the performance level in percent, limited by the cap of [`CpuFreq::set_max_level`].
+    pub fn apply(&mut self, level: u8) {
+        let level = level.min(self.max_level);
+        match &self.backend {
+            Backend::None => return,
+            Backend::Hwp { lowest, highest } => {
+                let desired = hwp_level(level, *lowest, *highest);
+                // The hardware may pick any level in the range, the desired one is only a hint.
+                let request = *lowest as u64 | (*highest as u64) << 8 | (desired as u64) << 16;
+                unsafe { HwpRequest::write_raw(request) };
+            },
+            Backend::PStates { states, control } => {
+                let value = states[pstate_index(level, states.len())].control;
+                unsafe {
+                    match *control {
+                        PerfRegister::FixedHardware => PerfCtl::write_raw(value),
+                        PerfRegister::SystemIo { port, width: 8 } => u8::write(port, value as u8),
+                        PerfRegister::SystemIo { port, width: 16 } => u16::write(port, value as u16),
+                        PerfRegister::SystemIo { port, .. } => u32::write(port, value as u32),
+                    }
+                }
+            },
+        }
+        self.level = Some(level);
+    }
+}
+
+/// Returns the level in percent chosen by the ondemand governor for the load in percent.
+fn ondemand(load: u8, up_threshold: u8) -> u8 {
+    if load >= up_threshold { 100 } else { load }
+}
+
+/// Returns the index of the P-state for the level, P-states are sorted from the fastest one.
+fn pstate_index(level: u8, count: usize) -> usize {
+    ((100 - level.min(100)) as usize * (count - 1) + 50) / 100
+}
+
+/// Returns the HWP performance level for the level in percent.
+fn hwp_level(level: u8, lowest: u8, highest: u8) -> u8 {
+    lowest + ((highest.saturating_sub(lowest)) as u32 * level as u32 / 100) as u8
+}
+
+/// Governor thread, which samples the load periodically.
+pub fn governor_thread(_: &mut Thread) {
+    loop {
+        let (idle_time, tsc_mhz) = critical_section!(|| {
+            let idle = IDLE_GOVERNOR.lock();
+            (idle.idle_time(), idle.tsc_mhz())
+        });
+        critical_section!(|| CPUFREQ.lock().sample(idle_time, tsc_mhz));
+        Thread::sleep(SAMPLE_INTERVAL_MS);
+    }
+}
+
+#[test_case]
+fn levels_map_to_performance_states() {
+    assert_eq!(ondemand(90, 80), 100);
+    assert_eq!(ondemand(30, 80), 30);
+
+    assert_eq!(pstate_index(100, 4), 0);
+    assert_eq!(pstate_index(0, 4), 3);
+    assert_eq!(pstate_index(50, 4), 2);
+    assert_eq!(pstate_index(0, 1), 0);
+
+    assert_eq!(hwp_level(0, 8, 40), 8);
+    assert_eq!(hwp_level(100, 8, 40), 40);
+    assert_eq!(hwp_level(50, 8, 40), 24);
+
+    let mut cpufreq = CpuFreq::new();
+    assert!(!cpufreq.set_pstates(PerfRegister::FixedHardware, Vec::new()));
+    let state = |frequency| PState { frequency, power: 0, latency: 10, control: frequency as u64 / 100, status: 0 };
+    assert!(cpufreq.set_pstates(PerfRegister::SystemIo { port: 0xb2, width: 8 }, alloc::vec![state(800), state(2400), state(1600)]));
+    assert_eq!(Governor::from_name("powersave"), Some(Governor::Powersave));
+}
@@ -42,6 +42,50 @@ impl DTPointer {
     pub const fn null() -> Self {
         Self { addr: 0, size: 0 }
     }
+
+    /// Returns a pointer to the table of the provided amount of entries.
+    ///
+    /// The size written into the pointer is the limit of the table, which is the offset of it's
+    /// last byte, so one less than the length in bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the table is empty or longer than 64 KiB.
+    #[inline]
+    pub fn new(addr: u64, entries: usize, entry_size: usize) -> Self {
+        let len = entries * entry_size;
+        assert!((1..=1 << 16).contains(&len), "Descriptor tables must be 1 B to 64 KiB long.");
+        Self { addr, size: (len - 1) as u16 }
+    }
+
+    /// Returns the amount of entries of the provided size covered by the limit.
+    ///
+    /// Returns None if the limit does not end at the last byte of an entry, which is the usual
+    /// off-by-one error of limits written as the length of the table.
+    #[inline]
+    pub fn entries(&self, entry_size: usize) -> Option<usize> {
+        let len = self.size as usize + 1;
+        (len % entry_size == 0).then_some(len / entry_size)
+    }
+}
+
+/// Checks that the table pointer loaded into the processor is the expected one.
+///
+/// Reads the pointer back with "sgdt" or "sidt" right after loading a table. Both the base and
+/// the limit are compared, as a wrong limit only shows up later as a general protection fault
+/// on some far descriptor.
+///
+/// # Panics
+///
+/// Panics on any mismatch and for LDTs, which are loaded with a selector instead.
+#[track_caller]
+pub fn assert_loaded(table: DescriptorTableType, expected: DTPointer) {
+    let loaded = match table {
+        DescriptorTableType::Gdt => sgdt(),
+        DescriptorTableType::Idt => sidt(),
+        DescriptorTableType::Ldt => panic!("The LDT is not loaded with a table pointer."),
+    };
+    assert_eq!(loaded, expected, "The {:?} pointer read back from the processor does not match the table.", table);
 }
 
 /// Load a GDT via lgdt instruction.
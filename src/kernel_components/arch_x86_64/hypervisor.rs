@@ -0,0 +1,141 @@
+/// Module for detecting the hypervisor and using it's paravirtual features.
+
+use core::arch::x86_64 as arch;
+use core::sync::atomic::{AtomicU64, AtomicBool, Ordering};
+
+use crate::kernel_components::arch_x86_64::controllers::apic::LOCAL_APIC;
+use crate::kernel_components::memory::MEMORY_MANAGEMENT_UNIT;
+use crate::kernel_components::registers::ms::{Msr, KvmPvEoi};
+
+/// CPUID leaf which returns the hypervisor vendor signature.
+const HYPERVISOR_LEAF: u32 = 0x4000_0000;
+/// CPUID leaf which returns KVM paravirtual features.
+const KVM_FEATURES_LEAF: u32 = 0x4000_0001;
+
+/// Known hypervisors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hypervisor {
+    /// Linux KVM. QEMU with KVM acceleration also reports this one.
+    Kvm,
+    /// QEMU without hardware acceleration.
+    QemuTcg,
+    /// VMware Workstation, ESXi or Fusion.
+    VMware,
+    /// Microsoft Hyper-V.
+    HyperV,
+    /// Xen HVM guest.
+    Xen,
+    /// Oracle VirtualBox.
+    VirtualBox,
+    /// Some other hypervisor. The vendor signature is provided.
+    Unknown([u8; 12]),
+}
+
+impl Hypervisor {
+    /// Detects the hypervisor via CPUID.
+    ///
+    /// Returns None when running on bare metal. Hypervisors set the bit 31 of ECX in leaf 1 and
+    /// provide a 12 byte vendor signature in leaf 0x40000000.
+    pub fn detect() -> Option<Self> {
+        let leaf1 = unsafe { arch::__cpuid(0x1) };
+        if leaf1.ecx & (1 << 31) == 0 {
+            return None
+        }
+
+        let leaf = unsafe { arch::__cpuid(HYPERVISOR_LEAF) };
+        let mut sig = [0u8; 12];
+        sig[0..4].copy_from_slice(&leaf.ebx.to_le_bytes());
+        sig[4..8].copy_from_slice(&leaf.ecx.to_le_bytes());
+        sig[8..12].copy_from_slice(&leaf.edx.to_le_bytes());
+
+        Some(match &sig {
+            b"KVMKVMKVM\0\0\0" => Self::Kvm,
+            b"TCGTCGTCGTCG" => Self::QemuTcg,
+            b"VMwareVMware" => Self::VMware,
+            b"Microsoft Hv" => Self::HyperV,
+            b"XenVMMXenVMM" => Self::Xen,
+            b"VBoxVBoxVBox" => Self::VirtualBox,
+            _ => Self::Unknown(sig),
+        })
+    }
+
+    /// Returns true if the kernel runs under the KVM hypervisor.
+    pub fn is_kvm() -> bool {
+        Self::detect() == Some(Self::Kvm)
+    }
+}
+
+/// Paravirtual features advertised by the KVM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KvmFeatures(pub u32);
+
+impl KvmFeatures {
+    /// New kvmclock MSRs are available.
+    pub const CLOCKSOURCE2: u32     = 1 << 3;
+    /// Paravirtual end of interrupt is available.
+    pub const PV_EOI: u32           = 1 << 6;
+    /// The kvmclock is guaranteed to be stable across all vCPUs.
+    pub const CLOCKSOURCE_STABLE: u32 = 1 << 24;
+
+    /// Reads the features of the KVM. Returns None if not running under KVM.
+    pub fn read() -> Option<Self> {
+        if Hypervisor::is_kvm() {
+            Some(Self(unsafe { arch::__cpuid(KVM_FEATURES_LEAF) }.eax))
+        } else {
+            None
+        }
+    }
+
+    /// Checks if the provided feature bit is present.
+    pub fn has(&self, feature: u32) -> bool {
+        self.0 & feature != 0
+    }
+}
+
+/// PV EOI flags shared with the host, indexed by the local APIC id, as every CPU has it's own.
+/// Must be 4 byte aligned.
+static PV_EOI_FLAGS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+/// Marks that PV EOI was enabled on some CPU.
+static PV_EOI_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables paravirtual EOI on the current CPU if supported.
+///
+/// Called by [`LocalApic::init`] on every CPU. With PV EOI the host sets a flag when the interrupt can be acknowledged without the costly
+/// trap on the local APIC EOI register. Returns true if the feature was enabled.
+///
+/// # Note
+///
+/// PV EOI only affects the local APIC. Legacy PIC interrupts must still be acknowledged the
+/// regular way.
+///
+/// [`LocalApic::init`]: crate::kernel_components::arch_x86_64::controllers::apic::LocalApic::init
+pub fn enable_pv_eoi() -> bool {
+    let supported = KvmFeatures::read()
+        .map(|f| f.has(KvmFeatures::PV_EOI))
+        .unwrap_or(false);
+
+    if !supported {
+        return false
+    }
+
+    let flag = &PV_EOI_FLAGS[LOCAL_APIC.id() as usize];
+    match unsafe { MEMORY_MANAGEMENT_UNIT.translate(flag as *const _ as usize) } {
+        Some(phys) => {
+            unsafe { KvmPvEoi::write_raw(phys as u64 | 1) };
+            PV_EOI_ENABLED.store(true, Ordering::Release);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Tries to acknowledge the interrupt via PV EOI flag.
+///
+/// Returns true if the host allowed to skip the APIC EOI write. Otherwise the regular EOI must be
+/// performed. Used by [`LocalApic::end_of_interrupt`].
+///
+/// [`LocalApic::end_of_interrupt`]: crate::kernel_components::arch_x86_64::controllers::apic::LocalApic::end_of_interrupt
+pub fn pv_eoi_ack() -> bool {
+    PV_EOI_ENABLED.load(Ordering::Acquire)
+        && PV_EOI_FLAGS[LOCAL_APIC.id() as usize].fetch_and(!1, Ordering::AcqRel) & 1 != 0
+}
@@ -1,36 +1,422 @@
 /// Defines predefined CPU exception handler functions.
 
 /// A collection of predefined functions that can be used within the gates.
-use crate::{println, print, debug, Color, critical_section};
+use core::arch::global_asm;
+use core::fmt::Display;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use crate::{println, print, debug, log, emergency_println, critical_section, VirtualAddress};
 use super::handler_functions::*;
+use super::{GateDescriptor, InterruptVector, IDT, INTERRUPT_DESCRIPTOR_TABLE};
+use super::interrupt::{GeneralRegisters, CriticalGuard};
+
+/// Vector of the debug exception, which is not named by [`InterruptVector`].
+const DEBUG_VECTOR: usize = 0x1;
+/// Vector of the virtualization exception.
+const VIRTUALIZATION_VECTOR: usize = 0x14;
+/// Vector of the control protection exception.
+const CONTROL_PROTECTION_VECTOR: usize = 0x15;
+
+/// Maximal length of an instruction.
+const MAX_INSTRUCTION_LEN: usize = 15;
+
+/// Prints the exception with the error code and the interrupted context.
+///
+/// The error code is printed decoded when the exception provides a decoder for it. Exceptions
+/// without an error code may provide a diagnosis read from the processor state instead.
+fn report(name: &str, frame: &ExceptionFrame, decoded: Option<&dyn Display>) {
+    critical_section!(|| {
+        log!(Error; "EXCEPTION: {}", name);
+        match (frame.error_code, decoded) {
+            (Some(_), Some(decoded)) => println!("Error code: {}", decoded),
+            (Some(error_code), None) => println!("Error code: {:#x}", error_code.0),
+            (None, Some(decoded)) => println!("Diagnosis: {}", decoded),
+            (None, None) => (),
+        }
+        debug!("{:#?}", frame.frame);
+    });
+}
+
+/// Bytes of the instruction at the address, printed only if the whole instruction is mapped.
+struct InstructionBytes(VirtualAddress);
+
+impl Display for InstructionBytes {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use crate::kernel_components::memory::MEMORY_MANAGEMENT_UNIT;
+
+        let last = self.0.wrapping_add(MAX_INSTRUCTION_LEN - 1);
+        let mmu = unsafe { &MEMORY_MANAGEMENT_UNIT };
+        if mmu.translate(self.0).is_none() || mmu.translate(last).is_none() {
+            return write!(f, "instruction at {:#x} is not mapped", self.0)
+        }
+
+        let bytes = unsafe { core::slice::from_raw_parts(self.0 as *const u8, MAX_INSTRUCTION_LEN) };
+        write!(f, "bytes at {:#x}:", self.0)?;
+        bytes.iter().try_for_each(|byte| write!(f, " {:02x}", byte))
+    }
+}
+
+/// Kills the process of the scheduled thread if it faulted on an address within it's own mmap
+/// window while running on it's own stack.
+///
+/// The handler then returns into a trampoline in which the thread waits until the scheduler
+/// drops it, while the memory of the process is unmapped later by the worker thread. Faults on
+/// kernel addresses, within critical sections or while the process list is locked, are left to
+/// the caller. Returns the pid of the killed process.
+fn kill_current(stack_frame: &mut InterruptStackFrame, addr: VirtualAddress) -> Option<usize> {
+    use crate::kernel_components::task_virtualization::{thread, Scheduler, ROUND_ROBIN, PROCESS_MANAGEMENT_UNIT};
+
+    // The interrupted code may hold spinlocks or be in the middle of updating shared state.
+    if CriticalGuard::depth() != 0 {
+        return None
+    }
+    unsafe {
+        let task = *ROUND_ROBIN.current()?;
+        if PROCESS_MANAGEMENT_UNIT.process_list.is_locked() {
+            return None
+        }
+        let owned = PROCESS_MANAGEMENT_UNIT.process_list.lock()
+            .get(task.pid)
+            .filter(|process| process.address_space.owns(addr))
+            .and_then(|process| process.find_thread(task.tid))
+            .is_some_and(|thread| thread.stack.contain(stack_frame.stack_ptr));
+        if !owned {
+            return None
+        }
+        PROCESS_MANAGEMENT_UNIT.kill_faulted(task.pid).ok()?;
+
+        // The trampoline waits for interrupts, aligned like any freshly called function.
+        let stack_ptr = (stack_frame.stack_ptr & !0xf) - 8;
+        stack_frame.redirect(thread::killed_thread_exit as *const () as usize, stack_ptr);
+        stack_frame.enable_interrupts();
+        Some(task.pid)
+    }
+}
 
 #[no_mangle]
 unsafe extern "x86-interrupt" fn division_by_zero_handler(stack_frame: InterruptStackFrame) -> ! {
-    println!(Color::RED; "EXCEPTION: Division by zero.");
+    log!(Error; "EXCEPTION: Division by zero.");
     debug!("{:#?}", stack_frame);
     loop {}
 }
 
 #[no_mangle]
 unsafe extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!(Color::RED; "EXCEPTION: Breakpoint");
+    log!(Error; "EXCEPTION: Breakpoint");
     debug!("{:#?}", stack_frame);
 }
 
 #[no_mangle]
-unsafe extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame) {
-    println!(Color::RED; "EXCEPTION: Double Fault");
-    debug!("{:#?}", stack_frame);
+unsafe extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    emergency_println!("NMI at {:#x}", stack_frame.instruction_pointer);
+    loop {}
+}
+
+/// Default delay of the reboot after a double fault in seconds.
+pub const DEFAULT_DOUBLE_FAULT_REBOOT_SECS: u32 = 10;
+
+/// Current [`DoubleFaultPolicy`], changed with the "kernel.double_fault" sysctl.
+pub static DOUBLE_FAULT_POLICY: AtomicU8 = AtomicU8::new(DoubleFaultPolicy::Halt as u8);
+/// Seconds between the dump and the reboot with [`DoubleFaultPolicy::Reboot`].
+pub static DOUBLE_FAULT_REBOOT_SECS: AtomicU32 = AtomicU32::new(DEFAULT_DOUBLE_FAULT_REBOOT_SECS);
+
+/// What the double fault handler does once the state is dumped.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DoubleFaultPolicy {
+    /// Halts the processor until it is power cycled.
+    Halt,
+    /// Resets the machine after [`DOUBLE_FAULT_REBOOT_SECS`].
+    Reboot,
+    /// Parks the processor in [`double_fault_debug_trap`], where a debugger attached to the
+    /// machine (the QEMU GDB stub with `make run`) finds the faulted state untouched.
+    Debug,
+}
+
+impl DoubleFaultPolicy {
+    /// Parses the policy from it's sysctl name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "halt" => Some(Self::Halt),
+            "reboot" => Some(Self::Reboot),
+            "debug" => Some(Self::Debug),
+            _ => None,
+        }
+    }
+
+    /// Returns the policy currently in use.
+    pub fn current() -> Self {
+        match DOUBLE_FAULT_POLICY.load(Ordering::Relaxed) {
+            1 => Self::Reboot,
+            2 => Self::Debug,
+            _ => Self::Halt,
+        }
+    }
+
+    /// Makes the policy used by the next double fault.
+    pub fn set(self) {
+        DOUBLE_FAULT_POLICY.store(self as u8, Ordering::Relaxed);
+    }
+}
+
+/// Context of the double fault as saved by [`double_fault_entry`].
+#[repr(C)]
+struct DoubleFaultContext {
+    registers: GeneralRegisters,
+    /// Always zero for the double fault.
+    error_code: ErrorCode,
+    frame: InterruptStackFrame,
+}
+
+extern "C" {
+    /// Entry point of the double fault, which saves the general purpose registers below the
+    /// frame pushed by the processor and calls [`double_fault_report`] with them.
+    fn double_fault_entry();
+}
+
+// The gate switches to the stack of the IST, which the processor aligns to 16 bytes before the
+// frame and the error code are pushed. The fifteen registers misalign it again, so the stack is
+// aligned before the call.
+global_asm!(
+    ".pushsection .text.double_fault, \"ax\"",
+    ".global double_fault_entry",
+    "double_fault_entry:",
+    "    push r15",
+    "    push r14",
+    "    push r13",
+    "    push r12",
+    "    push r11",
+    "    push r10",
+    "    push r9",
+    "    push r8",
+    "    push rbp",
+    "    push rdi",
+    "    push rsi",
+    "    push rdx",
+    "    push rcx",
+    "    push rbx",
+    "    push rax",
+    "    mov rdi, rsp",
+    "    and rsp, -16",
+    "    cld",
+    "    call {report}",
+    "    ud2",
+    ".popsection",
+    report = sym double_fault_report,
+);
+
+/// Dumps the interrupted context of the double fault and halts.
+///
+/// Runs on it's own stack, so the dump works even when the fault was caused by an overflow of the
+/// interrupted stack. Only the emergency writer is used, as any lock may be held by the
+/// interrupted code.
+extern "C" fn double_fault_report(context: &DoubleFaultContext) -> ! {
+    use crate::kernel_components::arch_x86_64::backtrace::{self, StackWalker};
+    use crate::kernel_components::registers::control::{Cr2, Cr3};
+
+    let frame = &context.frame;
+    emergency_println!("EXCEPTION: Double fault (#DF), error code {:#x}", context.error_code.0);
+    emergency_println!("{:#x?}", frame);
+    emergency_println!("{}", context.registers);
+    emergency_println!("CR2 {:#018x} CR3 {:#018x}", Cr2::read(), Cr3::read().0.start_address());
+
+    // Frames of user programs are not trusted, nor are they symbolized by anything.
+    if !frame.is_user_mode() {
+        backtrace::dump(Some(frame.instruction_pointer), StackWalker::new(context.registers.rbp as usize));
+    }
+
+    match DoubleFaultPolicy::current() {
+        DoubleFaultPolicy::Halt => (),
+        DoubleFaultPolicy::Reboot => double_fault_reboot(DOUBLE_FAULT_REBOOT_SECS.load(Ordering::Relaxed)),
+        DoubleFaultPolicy::Debug => double_fault_debug_trap(context),
+    }
+    loop {
+        super::interrupt::hlt();
+    }
+}
+
+/// Waits for the provided amount of seconds and resets the machine.
+///
+/// Interrupts stay disabled, so the delay is measured with the TSC calibrated by the clock. The
+/// machine is reset at once if the TSC was never calibrated.
+fn double_fault_reboot(secs: u32) -> ! {
+    use core::arch::x86_64::_rdtsc;
+    use crate::kernel_components::arch_x86_64::controllers::PS2;
+    use crate::kernel_components::arch_x86_64::descriptor_table::{lidt, DTPointer};
+    use crate::kernel_components::memory::vdso;
+
+    let tsc_per_ms = vdso::data().snapshot().tsc_per_ms;
+    match tsc_per_ms {
+        0 => emergency_println!("Rebooting now, the TSC is not calibrated."),
+        _ => emergency_println!("Rebooting in {} seconds.", secs),
+    }
+    let deadline = unsafe { _rdtsc() }.saturating_add(tsc_per_ms * 1000 * secs as u64);
+    while unsafe { _rdtsc() } < deadline {
+        core::hint::spin_loop();
+    }
+
+    unsafe {
+        PS2::new().reset_cpu();
+        // Without a PS/2 controller, an empty IDT turns the next exception into a triple fault.
+        for _ in 0..1_000_000 {
+            core::hint::spin_loop();
+        }
+        lidt(&DTPointer::null());
+        core::arch::asm!("int3", options(noreturn));
+    }
+}
+
+/// Spins with the faulted context at hand until a debugger takes over the machine.
+///
+/// The kernel has no debugger of it's own, so this only keeps the state intact for an external
+/// one. Setting a breakpoint on this symbol catches every double fault.
+#[no_mangle]
+#[inline(never)]
+extern "C" fn double_fault_debug_trap(context: &DoubleFaultContext) -> ! {
+    emergency_println!("Waiting for a debugger in double_fault_debug_trap, context at {:p}.", context);
+    loop {
+        // Keeps the context alive in a register for the debugger.
+        core::hint::black_box(context);
+        core::hint::spin_loop();
+    }
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    report("Debug (#DB)", &stack_frame.into(), None);
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
+    report("Overflow (#OF)", &stack_frame.into(), None);
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn bound_range_handler(stack_frame: InterruptStackFrame) -> ! {
+    report("Bound range exceeded (#BR)", &stack_frame.into(), None);
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) -> ! {
+    report("Invalid opcode (#UD)", &stack_frame.into(), Some(&InstructionBytes(stack_frame.instruction_pointer)));
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) -> ! {
+    report("Device not available (#NM)", &stack_frame.into(), None);
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn coprocessor_segment_overrun_handler(stack_frame: InterruptStackFrame) -> ! {
+    report("Coprocessor segment overrun", &stack_frame.into(), None);
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame, error_code: ErrorCode) -> ! {
+    report("Invalid TSS (#TS)", &ExceptionFrame::with_error_code(stack_frame, error_code), Some(&error_code.selector_error_code()));
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn segment_not_present_handler(stack_frame: InterruptStackFrame, error_code: ErrorCode) -> ! {
+    report("Segment not present (#NP)", &ExceptionFrame::with_error_code(stack_frame, error_code), Some(&error_code.selector_error_code()));
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn stack_segment_fault_handler(stack_frame: InterruptStackFrame, error_code: ErrorCode) -> ! {
+    report("Stack segment fault (#SS)", &ExceptionFrame::with_error_code(stack_frame, error_code), Some(&error_code.selector_error_code()));
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: InterruptStackFrame, error_code: ErrorCode) -> ! {
+    use crate::kernel_components::arch_x86_64::descriptor_table::sgdt;
+    use crate::kernel_components::registers::segment_regs::SegmentRegisters;
+
+    let code = error_code.selector_error_code();
+    report("General protection fault (#GP)", &ExceptionFrame::with_error_code(stack_frame, error_code), Some(&code));
+
+    let cause = GpfCause::decode(code, &INTERRUPT_DESCRIPTOR_TABLE, sgdt());
+    critical_section!(|| {
+        println!("Diagnosis: {}", cause);
+        println!(
+            "Segments: CS {:#06x} SS {:#06x} {}",
+            stack_frame.code_segment.0, stack_frame.stack_segment, SegmentRegisters::read(),
+        );
+    });
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn x87_fpu_error_handler(stack_frame: InterruptStackFrame) -> ! {
+    report("x87 floating point error (#MF)", &stack_frame.into(), Some(&FloatExceptions::x87()));
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn alignment_check_handler(stack_frame: InterruptStackFrame, error_code: ErrorCode) -> ! {
+    report("Alignment check (#AC)", &ExceptionFrame::with_error_code(stack_frame, error_code), None);
+    critical_section!(|| println!("Diagnosis: unaligned memory access by user code with RFLAGS.AC set"));
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    emergency_println!("EXCEPTION: Machine check (#MC) at {:#x}", stack_frame.instruction_pointer);
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) -> ! {
+    report("SIMD floating point exception (#XM)", &stack_frame.into(), Some(&FloatExceptions::simd()));
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn virtualization_handler(stack_frame: InterruptStackFrame) -> ! {
+    report("Virtualization exception (#VE)", &stack_frame.into(), None);
+    loop {}
+}
+
+#[no_mangle]
+unsafe extern "x86-interrupt" fn control_protection_handler(stack_frame: InterruptStackFrame, error_code: ErrorCode) -> ! {
+    report("Control protection exception (#CP)", &ExceptionFrame::with_error_code(stack_frame, error_code), None);
     loop {}
 }
 
 #[no_mangle]
 unsafe extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: ErrorCode,
 ) {
+    use crate::kernel_components::{registers::control::Cr2, memory::{vma, MEMORY_MANAGEMENT_UNIT}};
+
+    // Pages of the lazy kernel regions, like the heap, are mapped on the first access.
+    let present = PageFaultErrorCode::PRESENT_BIT.is_in(error_code.0);
+    if !present && MEMORY_MANAGEMENT_UNIT.handle_lazy_fault(Cr2::read()) {
+        return
+    }
+
+    // Pages of process mappings are populated on demand and copied on write when shared.
+    let write = PageFaultErrorCode::WRITE_BIT.is_in(error_code.0);
+    if vma::handle_page_fault(Cr2::read(), write, present).is_ok() {
+        return
+    }
+
+    // A thread touching memory it's process does not own only kills that process.
+    let addr = Cr2::read();
+    if let Some(pid) = kill_current(&mut stack_frame, addr) {
+        log!(Error; "Process {} killed by a page fault at {:#x}.", pid, addr);
+        return
+    }
+
     critical_section!(|| {
-        println!(Color::RED; "EXCEPTION: Page Fault");
+        log!(Error; "EXCEPTION: Page Fault");
         debug!("{:#?}", stack_frame);
 
         print!("Error code flags: ");
@@ -47,6 +433,11 @@ unsafe extern "x86-interrupt" fn page_fault_handler(
 /// 
 /// This function provides the error info and a current stack table information.
 pub const DIVISION_BY_ZERO: DivergingHandlerFunction = division_by_zero_handler;
+/// Non-maskable interrupt handler. ('NMI')
+///
+/// NMIs are used by the hardware to report unrecoverable errors. Reports the interrupted
+/// instruction with the emergency writer and halts.
+pub const NMI: HandlerFunction = nmi_handler;
 /// Sets a breakpoint. ('#BP')
 /// 
 /// Will provide a current stack table information.
@@ -65,12 +456,146 @@ pub const BREAKPOINT: HandlerFunction = breakpoint_handler;
 /// - '#SS' -> '#TS', '#NP', '#SS', 'GP';
 /// - '#GP' -> '#TS', '#NP', '#SS', 'GP';
 /// - '#PF' -> '#TS', '#NP', '#SS', 'GP', '#PF';
-pub const DOUBLE_FAULT: HandlerFunction = double_fault_handler;
+///
+/// The handler runs on the stack of the [`DOUBLE_FAULT_IST_INDEX`] entry and dumps the frame, the
+/// general purpose registers, CR2, CR3 and the stack trace of the faulting context.
+// An assembly stub entered with the frame and the error code on the stack is exactly what the
+// type describes, it just never returns.
+pub const DOUBLE_FAULT: DivergingHandlerFunctionWithErrCode = unsafe {
+    core::mem::transmute(double_fault_entry as unsafe extern "C" fn())
+};
+/// Entry of the Interrupt Stack Table in the TSS used by the double fault handler.
+///
+/// Double faults are often caused by an overflow of the kernel stack, which the handler must not
+/// use. The stack of the entry must be set with [`MMU::set_interrupt_stack`] before the TSS is
+/// loaded.
+///
+/// [`MMU::set_interrupt_stack`]: crate::kernel_components::memory::MMU::set_interrupt_stack
+pub const DOUBLE_FAULT_IST_INDEX: usize = 0;
 
 /// A page fault function handler.
 /// 
 /// There are many ways for the page fault to occur, therefore the error code
 /// must be used accordingly as it does provide additional info about the reason
-/// of the page fault invocation.
+/// of the page fault invocation. Faults on not yet populated pages of process
+/// mappings and writes into their shared pages are resolved silently.
 pub const PAGE_FAULT: HandlerFunctionWithErrCode = page_fault_handler;
 
+
+/// Debug exception handler. ('#DB')
+///
+/// Raised by hardware breakpoints and single stepping. Reports the context and resumes.
+pub const DEBUG: HandlerFunction = debug_handler;
+/// Overflow handler. ('#OF')
+///
+/// Raised by the INTO instruction. Reports the context and resumes after the instruction.
+pub const OVERFLOW: HandlerFunction = overflow_handler;
+/// Bound range exceeded handler. ('#BR')
+pub const BOUND_RANGE: DivergingHandlerFunction = bound_range_handler;
+/// Invalid opcode handler. ('#UD')
+///
+/// Usually means a jump into data or an instruction not supported by the processor. The bytes at
+/// the instruction pointer are printed, if they are mapped.
+pub const INVALID_OPCODE: DivergingHandlerFunction = invalid_opcode_handler;
+/// Device not available handler. ('#NM')
+///
+/// Raised by FPU or SIMD instructions while they are disabled in CR0.
+pub const DEVICE_NOT_AVAILABLE: DivergingHandlerFunction = device_not_available_handler;
+/// Coprocessor segment overrun handler.
+///
+/// Never raised by processors after the i386, installed only so the vector has a gate.
+pub const COPROCESSOR_SEGMENT_OVERRUN: DivergingHandlerFunction = coprocessor_segment_overrun_handler;
+/// Invalid TSS handler. ('#TS')
+///
+/// The error code is decoded as the selector of the invalid TSS or segment.
+pub const INVALID_TSS: DivergingHandlerFunctionWithErrCode = invalid_tss_handler;
+/// Segment not present handler. ('#NP')
+///
+/// The error code is decoded as the selector of the segment or gate which is not present.
+pub const SEGMENT_NOT_PRESENT: DivergingHandlerFunctionWithErrCode = segment_not_present_handler;
+/// Stack segment fault handler. ('#SS')
+///
+/// Raised by non canonical stack addresses or invalid stack segments, which selector is decoded
+/// from the error code.
+pub const STACK_SEGMENT_FAULT: DivergingHandlerFunctionWithErrCode = stack_segment_fault_handler;
+/// General protection fault handler. ('#GP')
+///
+/// The error code is decoded as the selector which caused the fault, null selectors mean the
+/// fault is unrelated to segments. The likely cause (see [`GpfCause`]) and the segment registers
+/// of the interrupted code are printed as well.
+pub const GENERAL_PROTECTION_FAULT: DivergingHandlerFunctionWithErrCode = general_protection_fault_handler;
+/// x87 floating point error handler. ('#MF')
+///
+/// The pending exceptions are decoded from the x87 status word.
+pub const X87_FPU_ERROR: DivergingHandlerFunction = x87_fpu_error_handler;
+/// Alignment check handler. ('#AC')
+///
+/// Only raised for unaligned user accesses with the alignment check enabled.
+pub const ALIGNMENT_CHECK: DivergingHandlerFunctionWithErrCode = alignment_check_handler;
+/// Machine check handler. ('#MC')
+///
+/// Reports the interrupted instruction with the emergency writer and halts, as the machine
+/// state can no longer be trusted.
+pub const MACHINE_CHECK: DivergingHandlerFunction = machine_check_handler;
+/// SIMD floating point exception handler. ('#XM')
+///
+/// The pending exceptions are decoded from the MXCSR register.
+pub const SIMD_FLOATING_POINT: DivergingHandlerFunction = simd_floating_point_handler;
+/// Virtualization exception handler. ('#VE')
+pub const VIRTUALIZATION: DivergingHandlerFunction = virtualization_handler;
+/// Control protection exception handler. ('#CP')
+///
+/// Raised by shadow stack and indirect branch tracking violations.
+pub const CONTROL_PROTECTION: DivergingHandlerFunctionWithErrCode = control_protection_handler;
+
+/// Installs the predefined handlers of every architectural exception into the table.
+///
+/// NMIs and machine checks use interrupt gates, so no maskable interrupt nests into them, all
+/// other exceptions use trap gates.
+pub fn idt_install_default_exceptions(idt: &mut IDT) {
+    idt.push(InterruptVector::DIVIDE_BY_ZERO, GateDescriptor::new_trap(DIVISION_BY_ZERO));
+    idt.push(InterruptVector::Custom(DEBUG_VECTOR), GateDescriptor::new_trap(DEBUG));
+    idt.push(InterruptVector::NMI_INTERRUPT, GateDescriptor::new_interrupt(NMI));
+    idt.push(InterruptVector::BREAKPOINT, GateDescriptor::new_trap(BREAKPOINT));
+    idt.push(InterruptVector::OVERFLOW, GateDescriptor::new_trap(OVERFLOW));
+    idt.push(InterruptVector::BOUNDS_OF_RANGE_EXCEPTIONS, GateDescriptor::new_trap(BOUND_RANGE));
+    idt.push(InterruptVector::INVALID_OPCODE, GateDescriptor::new_trap(INVALID_OPCODE));
+    idt.push(InterruptVector::DEVICE_NOT_ABAILABLE, GateDescriptor::new_trap(DEVICE_NOT_AVAILABLE));
+    idt.push(
+        InterruptVector::DOUBLE_FAULT,
+        GateDescriptor::new_trap(DOUBLE_FAULT).with_stack_index(DOUBLE_FAULT_IST_INDEX as u8 + 1),
+    );
+    idt.push(InterruptVector::COPROCESSOR_SEGMENT_OVERRUN, GateDescriptor::new_trap(COPROCESSOR_SEGMENT_OVERRUN));
+    idt.push(InterruptVector::INVALID_TSS, GateDescriptor::new_trap(INVALID_TSS));
+    idt.push(InterruptVector::SEGMENT_NOT_PRESENT, GateDescriptor::new_trap(SEGMENT_NOT_PRESENT));
+    idt.push(InterruptVector::STACK_SEGMENT_FAULT, GateDescriptor::new_trap(STACK_SEGMENT_FAULT));
+    idt.push(InterruptVector::GENERAL_PROTECTION_FAULT, GateDescriptor::new_trap(GENERAL_PROTECTION_FAULT));
+    idt.push(InterruptVector::PAGE_FAULT, GateDescriptor::new_trap(PAGE_FAULT));
+    idt.push(InterruptVector::X87_FPU_ERROR, GateDescriptor::new_trap(X87_FPU_ERROR));
+    idt.push(InterruptVector::ALIGNMENT_CHECK, GateDescriptor::new_trap(ALIGNMENT_CHECK));
+    idt.push(InterruptVector::MACHINE_CHECK, GateDescriptor::new_interrupt(MACHINE_CHECK));
+    idt.push(InterruptVector::SIMD_FLOATING_POINT_EXCEPTION, GateDescriptor::new_trap(SIMD_FLOATING_POINT));
+    idt.push(InterruptVector::Custom(VIRTUALIZATION_VECTOR), GateDescriptor::new_trap(VIRTUALIZATION));
+    idt.push(InterruptVector::Custom(CONTROL_PROTECTION_VECTOR), GateDescriptor::new_trap(CONTROL_PROTECTION));
+}
+
+#[test_case]
+fn default_exceptions_cover_architectural_vectors() {
+    let mut idt = IDT::new_empty();
+    idt_install_default_exceptions(&mut idt);
+
+    let vectors = (0x0..=0x15).filter(|&vector| vector != 0xf);
+    for vector in vectors {
+        assert!(idt[vector].type_attributes.0 & 0x80 != 0, "Exception {:#x} has no gate.", vector);
+    }
+    assert_eq!(idt[0xf].handler_addr(), 0);
+    assert_eq!(idt[0xd].handler_addr(), GENERAL_PROTECTION_FAULT as usize);
+    assert_eq!(idt[0x8].stack_index(), DOUBLE_FAULT_IST_INDEX as u8 + 1);
+    assert_eq!(idt[0xd].stack_index(), 0);
+
+    assert_eq!(DoubleFaultPolicy::from_name("reboot"), Some(DoubleFaultPolicy::Reboot));
+    assert_eq!(DoubleFaultPolicy::from_name("panic"), None);
+    DoubleFaultPolicy::Debug.set();
+    assert_eq!(DoubleFaultPolicy::current(), DoubleFaultPolicy::Debug);
+    DoubleFaultPolicy::Halt.set();
+}
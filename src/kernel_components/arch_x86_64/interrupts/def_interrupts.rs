@@ -7,16 +7,16 @@ use alloc::boxed::Box;
 use core::any::Any;
 use core::arch::asm;
 
-use crate::kernel_components::arch_x86_64::interrupts::{interrupt, INTERRUPT_DESCRIPTOR_TABLE};
+use crate::kernel_components::arch_x86_64::interrupts::{interrupt, latency, INTERRUPT_DESCRIPTOR_TABLE};
 use crate::kernel_components::drivers::DRIVER_MANAGER;
+use crate::kernel_components::drivers::interrupts::with_controller;
+use crate::kernel_components::drivers::timers::ApicTimer;
 use crate::kernel_components::keyboard_interface::OS_CHAR_BUFFER;
 use crate::kernel_components::memory::EntryFlags;
 use crate::kernel_components::task_virtualization::{Thread, Scheduler, PROCESS_MANAGEMENT_UNIT, PRIORITY_SCHEDULER, ROUND_ROBIN, ThreadState};
-use crate::{critical_section, debug, handler_function_prologue, print, println, warn, Color};
-use crate::kernel_components::arch_x86_64::controllers::{
-    PROGRAMMABLE_INTERRUPT_CONTROLLER,
-    PS2,
-};
+use crate::{critical_section, debug, handler_function_prologue, print, println, isr_println, Color};
+use crate::kernel_components::arch_x86_64::controllers::{PS2, apic::LOCAL_APIC};
+use crate::kernel_components::arch_x86_64::segmentation::task_state_segment::switch_io_bitmap;
 use super::handler_functions::*;
 
 /// Software timer interrupt handler
@@ -35,6 +35,19 @@ unsafe extern "x86-interrupt" fn timer_interrupt_handler(mut stack_frame: Interr
     };
     use core::sync::atomic::Ordering;
 
+    let entry = latency::enter();
+
+    // Page tables are walked with interrupts disabled, so the interrupted code walks none now.
+    crate::kernel_components::memory::deferred::quiescent();
+
+    // A voluntary yield raises this vector via INTn with interrupts disabled. No IRQ is in service
+    // in such case, while the next thread must still run with interrupts enabled.
+    let voluntary = crate::kernel_components::task_virtualization::thread::YIELD_REQUESTED
+        .swap(false, Ordering::SeqCst);
+    if voluntary {
+        stack_frame.enable_interrupts();
+    }
+
     // This thread input must be changed when the function call must be done.
     //
     // The input is a mutable reference, therefore it will be putted within the 'rdi' register.
@@ -99,9 +112,10 @@ unsafe extern "x86-interrupt" fn timer_interrupt_handler(mut stack_frame: Interr
                                 // thread can use a mutable reference to itself and perform the instructions.
                                 thread_input = thread as *const _ as usize;
 
-                                stack_frame.instruction_pointer = // Entering the task switch function.
-                                    crate::kernel_components::task_virtualization::thread::task_switch_call as usize;
-                                stack_frame.stack_ptr = thread.stack_ptr.load(Ordering::Acquire);
+                                stack_frame.redirect( // Entering the task switch function.
+                                    crate::kernel_components::task_virtualization::thread::task_switch_call as usize,
+                                    thread.stack_ptr.load(Ordering::Acquire),
+                                );
                                 
                                 /* debug!("PUSH TO THREAD NR: {} with {:?}, {:x}, {:x}", 
                                     task.tid, thread.thread_state, stack_frame.instruction_pointer, stack_frame.stack_ptr); */
@@ -115,6 +129,11 @@ unsafe extern "x86-interrupt" fn timer_interrupt_handler(mut stack_frame: Interr
                                 ROUND_ROBIN.delete(*task);
                                 break;
                             },
+                            ThreadState::PANICKED => {
+                                // Threads killed by an exception never run again.
+                                ROUND_ROBIN.delete(*task);
+                                continue
+                            },
                             ThreadState::HALT(isr) => {
                                 // If isr is set within the IDT - it is time to unhalt.
                                 if INTERRUPT_DESCRIPTOR_TABLE
@@ -127,8 +146,10 @@ unsafe extern "x86-interrupt" fn timer_interrupt_handler(mut stack_frame: Interr
                         }
 
                         // Changing the current stack pointer to the thread's ones.
-                        stack_frame.stack_ptr = thread.stack_ptr.load(Ordering::Acquire);
-                        stack_frame.instruction_pointer = thread.instruction_ptr.load(Ordering::Acquire);
+                        stack_frame.redirect(
+                            thread.instruction_ptr.load(Ordering::Acquire),
+                            thread.stack_ptr.load(Ordering::Acquire),
+                        );
                         /* debug!("PUSH TO THREAD NR: {} with {:?}, {:x}, {:x}", 
                             task.tid, thread.thread_state, stack_frame.instruction_pointer, stack_frame.stack_ptr); */
                         break;
@@ -140,15 +161,35 @@ unsafe extern "x86-interrupt" fn timer_interrupt_handler(mut stack_frame: Interr
                     // If there are no underlying process, we must delete the hangling task
                     ROUND_ROBIN.delete(*task);
                 }
-            }    
+            } else {
+                // Nothing is left to switch to, the interrupted context continues.
+                break
+            }
+        }
+
+        // The next thread may only access the I/O ports granted to it's process.
+        if let Some(process) = ROUND_ROBIN.current().copied().and_then(|task| pmu.get(task.pid)) {
+            switch_io_bitmap(process.pid, process.io_bitmap());
         }
     });
 
-    PROGRAMMABLE_INTERRUPT_CONTROLLER.lock()
-        .as_mut()
-        .map(|pic| 
-            pic.master.end_of_interrupt()
-        );
+    let vector = with_controller(|ctrl| {
+        let vector = ctrl.map_gsi(0).ok()?;
+        if !voluntary {
+            // Ticks of the APIC timer are local, the controller of IRQ0 knows nothing about them.
+            if ApicTimer::is_running() {
+                ApicTimer::handle_interrupt();
+                LOCAL_APIC.end_of_interrupt();
+            } else {
+                ctrl.end_of_interrupt(vector);
+            }
+        }
+        Some(vector)
+    });
+    // The custom epilogue below never returns, so the measurement ends here.
+    if let Ok(Some(vector)) = vector {
+        latency::exit(vector, entry);
+    }
 
     // Before the iretq instruction is done, we must change the rdi, so it can be used as
     // a pointer parameter for a thread function. Because the calling convention automatically
@@ -183,6 +224,7 @@ unsafe extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: Interru
     use crate::kernel_components::arch_x86_64::interrupts;
     use crate::kernel_components::drivers::{DriverType, keyboards::KeyboardDriver};
 
+    let entry = latency::enter();
     critical_section!(|| {
         handler_function_prologue!(33);
 
@@ -192,16 +234,120 @@ unsafe extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: Interru
                 OS_CHAR_BUFFER.lock().append(key)
             }
         } else {
-            warn!("Keyboard input detected, yet ignored due to no available keyboard driver found.");
+            isr_println!(Warning; "WARNING! Keyboard input detected, yet ignored due to no available keyboard driver found.");
             // This allows PIC to send more keyboard interrupts.
             let _ = PS2::new().read_data();
         }
     });
-    PROGRAMMABLE_INTERRUPT_CONTROLLER.lock()
-        .as_mut()
-        .map(|pic| 
-            pic.master.end_of_interrupt()
-        );
+    let _ = with_controller(|ctrl| {
+        if let Ok(vector) = ctrl.map_gsi(1) {
+            ctrl.end_of_interrupt(vector);
+            latency::exit(vector, entry);
+        }
+    });
+}
+
+/// Audio controller interrupt handler
+///
+/// Lets the loaded audio driver refill the consumed DMA buffers. The IRQ line is obtained from
+/// the driver itself, because PCI devices may be routed to any interrupt controller line.
+#[no_mangle]
+unsafe extern "x86-interrupt" fn audio_interrupt_handler(stack_frame: InterruptStackFrame) {
+    use crate::kernel_components::drivers::{DriverType, sound::AudioDriver};
+
+    let entry = latency::enter();
+    let irq = critical_section!(|| {
+        DRIVER_MANAGER.driver::<Box<dyn AudioDriver>>(DriverType::Audio)
+            .map(|audio| {
+                audio.handle_interrupt();
+                audio.irq()
+            })
+    });
+
+    if let Some(irq) = irq {
+        let _ = with_controller(|ctrl| {
+            if let Ok(vector) = ctrl.map_gsi(irq as u32) {
+                ctrl.end_of_interrupt(vector);
+                latency::exit(vector, entry);
+            }
+        });
+    }
+}
+
+/// RTC interrupt handler
+///
+/// Drains the status register C of the RTC chip and dispatches registered periodic and
+/// update-ended callbacks.
+#[no_mangle]
+unsafe extern "x86-interrupt" fn rtc_interrupt_handler(stack_frame: InterruptStackFrame) {
+    use crate::kernel_components::drivers::timers::RealTimeClock;
+    use crate::kernel_components::drivers::timers::rtc_clock::RTC_IRQ;
+
+    let entry = latency::enter();
+    critical_section!(|| RealTimeClock::handle_interrupt());
+
+    let _ = with_controller(|ctrl| {
+        if let Ok(vector) = ctrl.map_gsi(RTC_IRQ as u32) {
+            ctrl.end_of_interrupt(vector);
+            latency::exit(vector, entry);
+        }
+    });
+}
+
+/// Mouse interrupt handler
+///
+/// Lets the loaded mouse driver decode the received byte and moves the console pointer once a
+/// whole packet was received.
+#[no_mangle]
+unsafe extern "x86-interrupt" fn mouse_interrupt_handler(stack_frame: InterruptStackFrame) {
+    use crate::kernel_components::drivers::DriverType;
+    use crate::kernel_components::drivers::mouse::{pointer, MouseDriver};
+
+    let entry = latency::enter();
+    let irq = critical_section!(|| {
+        DRIVER_MANAGER.driver::<Box<dyn MouseDriver>>(DriverType::Mouse)
+            .map(|mouse| {
+                if let Some(event) = mouse.read() {
+                    pointer::handle_event(event);
+                }
+                mouse.irq()
+            })
+    });
+
+    if let Some(irq) = irq {
+        let _ = with_controller(|ctrl| {
+            if let Ok(vector) = ctrl.map_gsi(irq as u32) {
+                ctrl.end_of_interrupt(vector);
+                latency::exit(vector, entry);
+            }
+        });
+    }
+}
+
+/// Local APIC spurious interrupt handler
+///
+/// Spurious interrupts are only counted. No end of interrupt must be sent for them.
+#[no_mangle]
+unsafe extern "x86-interrupt" fn apic_spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::kernel_components::arch_x86_64::controllers::apic::spurious_interrupt();
+}
+
+/// Local APIC thermal interrupt handler
+///
+/// The thermal policy is evaluated later by the worker thread.
+#[no_mangle]
+unsafe extern "x86-interrupt" fn thermal_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::kernel_components::arch_x86_64::thermal::handle_interrupt();
+    LOCAL_APIC.end_of_interrupt();
+}
+
+/// TLB shootdown interrupt handler
+///
+/// Applies the invalidations queued for this processor by the others.
+#[no_mangle]
+unsafe extern "x86-interrupt" fn tlb_shootdown_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::kernel_components::arch_x86_64::shootdown::handle_interrupt();
+    LOCAL_APIC.end_of_interrupt();
 }
 
 /// A timer interrupt handler.
@@ -224,3 +370,37 @@ pub const TIMER_INTERRUPT: HandlerFunction = timer_interrupt_handler;
 /// scancode from the data por of the PS/2 controller.
 pub const KEYBOARD_INTERRUPT: HandlerFunction = keyboard_interrupt_handler;
 
+/// An audio interrupt handler.
+///
+/// This handler must be placed on the vector that corresponds to the IRQ line of the audio
+/// controller, which is configured by the firmware within the PCI configuration space.
+pub const AUDIO_INTERRUPT: HandlerFunction = audio_interrupt_handler;
+
+/// A RTC interrupt handler.
+///
+/// This handler must be placed on the vector of the IRQ8 line. It is required for the periodic
+/// and update-ended interrupts of the [´RealTimeClock´] driver.
+pub const RTC_INTERRUPT: HandlerFunction = rtc_interrupt_handler;
+
+/// A mouse interrupt handler.
+///
+/// This handler must be placed on the vector of the IRQ12 line. It drives the console pointer
+/// and the text selection.
+pub const MOUSE_INTERRUPT: HandlerFunction = mouse_interrupt_handler;
+
+/// A local APIC spurious interrupt handler.
+///
+/// This handler must be placed on the spurious vector provided to [´LocalApic::init´].
+pub const APIC_SPURIOUS_INTERRUPT: HandlerFunction = apic_spurious_interrupt_handler;
+
+/// A local APIC thermal interrupt handler.
+///
+/// This handler must be placed on [´THERMAL_VECTOR´], which is programmed into the thermal entry
+/// of the local vector table by [´ThermalPolicy::detect´].
+pub const THERMAL_INTERRUPT: HandlerFunction = thermal_interrupt_handler;
+
+/// A TLB shootdown interrupt handler.
+///
+/// This handler must be placed on [´SHOOTDOWN_VECTOR´], on which the other processors request
+/// invalidations of their TLB.
+pub const TLB_SHOOTDOWN_INTERRUPT: HandlerFunction = tlb_shootdown_interrupt_handler;
@@ -2,12 +2,15 @@
 
 use crate::kernel_components::arch_x86_64::segmentation::{SegmentDescriptor, SegmentSelector};
 use crate::kernel_components::arch_x86_64::descriptor_table::DescriptorTableType;
+use crate::kernel_components::arch_x86_64::DTPointer;
 use crate::{VirtualAddress, bitflags};
 use core::ops::{Deref, DerefMut};
 use core::arch::asm;
 use core::mem;
 
-use super::INTERRUPT_DESCRIPTOR_TABLE;
+use super::{INTERRUPT_DESCRIPTOR_TABLE, IDT};
+
+pub use super::interrupt::{InterruptStackFrame, ExceptionFrame};
 
 
 /// Must be inserted in the start of any handler function that shall be flagged. Can be used in 
@@ -79,26 +82,6 @@ pub type DivergingHandlerFunctionWithErrCode = unsafe extern "x86-interrupt" fn(
 ) -> !;
 implement_handler_type!(DivergingHandlerFunctionWithErrCode);
 
-/// Represents the interrupt stack frame pushed by the CPU on interrupt or exception entry.
-/// 
-/// This type must be used by the "x86-interrupt" calling convention.
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-pub struct InterruptStackFrame {
-    /// An instruction pointer to the current instruction address that must be executed.  
-    pub instruction_pointer: usize,
-    /// The selector of a code segment.
-    pub code_segment: SegmentSelector,
-    /// A values of RFLAGS register, on the moment of calling the handler function.
-    pub cpu_flags: u64,
-    /// Stack pointer value at the moment of the interrupt.
-    pub stack_ptr: usize,
-    /// The segment descriptor of the stack segment.
-    /// 
-    /// Only the first half of the descriptor is needed. In Long Mode usually not used.
-    pub stack_segment: u64,
-}
-
 /// A representation for any error code that can be used in any handler function that has
 /// an error code.
 /// 
@@ -237,3 +220,160 @@ impl SelectorErrorCode {
         self.bits() == 0
     }
 }
+
+impl core::fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.is_null() {
+            return write!(f, "null (not related to a segment)")
+        }
+        write!(f, "{:?} entry {:#x}", self.table_type(), self.get_index())?;
+        if self.is_external() {
+            write!(f, ", external event")?;
+        }
+        Ok(())
+    }
+}
+
+/// Likely cause of a general protection fault, decoded from it's error code.
+///
+/// The error code only tells which descriptor the processor was using, the cause is guessed by
+/// checking that descriptor against the loaded tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpfCause {
+    /// The error code is null, so the fault is not related to a segment.
+    NotSegmentRelated,
+    /// An interrupt was delivered on a vector without a present gate.
+    MissingGate { vector: u8, external: bool },
+    /// The gate of the vector is present, but cannot be used from the interrupted context.
+    UnusableGate { vector: u8, external: bool },
+    /// The selector points beyond the limit of the table.
+    BeyondLimit { table: DescriptorTableType, index: u16 },
+    /// The descriptor is not valid for the operation.
+    InvalidDescriptor { table: DescriptorTableType, index: u16 },
+}
+
+impl GpfCause {
+    /// Decodes the error code against the provided IDT and the loaded GDT pointer.
+    pub fn decode(code: SelectorErrorCode, idt: &IDT, gdt: DTPointer) -> Self {
+        if code.is_null() {
+            return GpfCause::NotSegmentRelated
+        }
+        let index = code.get_index() as u16;
+        let external = code.is_external();
+
+        match code.table_type() {
+            DescriptorTableType::Idt => match u8::try_from(index) {
+                Ok(vector) if !idt.is_present(vector) => GpfCause::MissingGate { vector, external },
+                Ok(vector) => GpfCause::UnusableGate { vector, external },
+                Err(_) => GpfCause::BeyondLimit { table: DescriptorTableType::Idt, index },
+            },
+            DescriptorTableType::Gdt if (index as usize + 1) * 8 > gdt.size as usize + 1 => {
+                GpfCause::BeyondLimit { table: DescriptorTableType::Gdt, index }
+            },
+            table => GpfCause::InvalidDescriptor { table, index },
+        }
+    }
+}
+
+impl core::fmt::Display for GpfCause {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use GpfCause::*;
+        let source = |external: bool| if external { "hardware" } else { "software" };
+        match self {
+            NotSegmentRelated => write!(
+                f, "not segment related: a privileged instruction, a non-canonical address, a reserved bit \
+                write or a misaligned SSE access"
+            ),
+            MissingGate { vector, external } => write!(
+                f, "{} interrupt on vector {:#x}, which has no handler installed", source(*external), vector
+            ),
+            UnusableGate { vector, external } => write!(
+                f, "{} interrupt on vector {:#x}, which gate is not accessible from the interrupted privilege \
+                level or has an invalid code selector", source(*external), vector
+            ),
+            BeyondLimit { table, index } => write!(f, "{:?} entry {:#x} is beyond the table limit", table, index),
+            InvalidDescriptor { table, index } => write!(f, "{:?} entry {:#x} is not valid for the operation", table, index),
+        }
+    }
+}
+
+/// Pending floating point exceptions, decoded from the x87 status word or the MXCSR register.
+///
+/// Both keep the exception flags in the same low bits. Only the x87 unit reports stack faults,
+/// the bit 6 of MXCSR controls the handling of denormals instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloatExceptions(pub u16);
+
+impl FloatExceptions {
+    /// Names of the exception flags from the lowest bit.
+    const NAMES: [&'static str; 7] = [
+        "invalid operation", "denormal operand", "division by zero", "overflow", "underflow",
+        "inexact result", "stack fault",
+    ];
+
+    /// Reads the exception flags of the x87 unit.
+    pub fn x87() -> Self {
+        let status: u16;
+        unsafe { asm!("fnstsw ax", out("ax") status, options(nomem, nostack, preserves_flags)) };
+        Self::from_x87_status(status)
+    }
+
+    /// Reads the exception flags of the SIMD unit.
+    pub fn simd() -> Self {
+        use crate::kernel_components::registers::mxscr::MxCsr;
+
+        Self::from_mxcsr(MxCsr::read().bits())
+    }
+
+    /// Decodes the exception flags of the x87 status word.
+    pub const fn from_x87_status(status: u16) -> Self {
+        Self(status & 0x7f)
+    }
+
+    /// Decodes the exception flags of the MXCSR register.
+    pub const fn from_mxcsr(mxcsr: u32) -> Self {
+        Self((mxcsr & 0x3f) as u16)
+    }
+}
+
+impl core::fmt::Display for FloatExceptions {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut pending = Self::NAMES.iter()
+            .enumerate()
+            .filter(|(bit, _)| self.0 & 1 << bit != 0)
+            .map(|(_, name)| name);
+        match pending.next() {
+            Some(first) => write!(f, "{}", first)?,
+            None => return write!(f, "no pending exceptions"),
+        }
+        pending.try_for_each(|name| write!(f, ", {}", name))
+    }
+}
+
+#[test_case]
+fn gpf_error_codes_are_decoded() {
+    use alloc::boxed::Box;
+
+    let idt = Box::new(IDT::new_empty());
+    let gdt = DTPointer::new(0x1000, 7, 8);
+    let decode = |code: u64| GpfCause::decode(SelectorErrorCode::new(code), &idt, gdt);
+
+    assert_eq!(decode(0), GpfCause::NotSegmentRelated);
+    // External IRQ 0 remapped to vector 0x20 without a handler: IDT bit and the external bit.
+    assert_eq!(decode(0x20 << 3 | 0b011), GpfCause::MissingGate { vector: 0x20, external: true });
+    assert_eq!(decode(0xd << 3 | 0b010), GpfCause::MissingGate { vector: 0xd, external: false });
+    assert_eq!(decode(0x100 << 3 | 0b010), GpfCause::BeyondLimit { table: DescriptorTableType::Idt, index: 0x100 });
+    assert_eq!(decode(7 << 3), GpfCause::BeyondLimit { table: DescriptorTableType::Gdt, index: 7 });
+    assert_eq!(decode(6 << 3), GpfCause::InvalidDescriptor { table: DescriptorTableType::Gdt, index: 6 });
+    assert_eq!(decode(2 << 3 | 0b100), GpfCause::InvalidDescriptor { table: DescriptorTableType::Ldt, index: 2 });
+}
+
+#[test_case]
+fn float_exceptions_are_decoded() {
+    use alloc::string::ToString;
+
+    assert_eq!(FloatExceptions::from_x87_status(0x3845).to_string(), "invalid operation, division by zero, stack fault");
+    // Masks, rounding control and denormals-are-zero are not exceptions.
+    assert_eq!(FloatExceptions::from_mxcsr(0x1fc0).to_string(), "no pending exceptions");
+    assert_eq!(FloatExceptions::from_mxcsr(0x1f90).to_string(), "underflow");
+}
@@ -3,9 +3,18 @@
 /// Exception catching are done with interrupt description table and handler functions.
 
 use core::arch::asm;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use proc_macros::Iternum;
 
 use crate::kernel_components::registers::flags::{XFLAGS, XFLAGSFlags};
+use crate::kernel_components::arch_x86_64::segmentation::SegmentSelector;
+use crate::kernel_components::arch_x86_64::controllers::{ioapic::IO_APIC, PROGRAMMABLE_INTERRUPT_CONTROLLER};
+use crate::kernel_components::arch_x86_64::smp::{self, MAX_CPUS};
+use crate::kernel_components::drivers::interrupts::{with_controller, InterruptController, IntCtrlError};
+
+use super::handler_functions::ErrorCode;
 
 /// INT vector table enum
 ///
@@ -83,15 +92,320 @@ pub enum InterruptVector {
     // Those mappings are OS specific, even though those values are used most of the time.
     
     /// Mappings that are specific for PIC controller.
+    ///
+    /// Use [`InterruptVector::pic`] to get the vector of an IRQ line from the PIC offsets.
     PICMappings(usize),
     
-    /// Mappings that are specific for APIC controller. (TODO! add the APIC controller support.)
+    /// Mappings that are specific for APIC controller.
+    ///
+    /// Use [`InterruptVector::apic`] to get the vector an IRQ line is routed to.
     APICMappings(usize),
 
     /// Custom mappings for OS specific software interrupts.
     Custom(usize),
 }
 
+/// Short names of the processor exceptions, indexed by the vector.
+const EXCEPTION_NAMES: [&str; 0x20] = [
+    "#DE", "#DB", "NMI", "#BP", "#OF", "#BR", "#UD", "#NM", "#DF", "coprocessor-overrun", "#TS", "#NP",
+    "#SS", "#GP", "#PF", "reserved", "#MF", "#AC", "#MC", "#XM", "#VE", "#CP", "reserved", "reserved",
+    "reserved", "reserved", "reserved", "reserved", "#HV", "#VC", "#SX", "reserved",
+];
+
+/// Amount of IRQ lines searched when a vector is mapped back to it's line.
+const IRQ_LINES: u8 = 24;
+
+impl InterruptVector {
+    /// First vector which is not a processor exception.
+    pub const FIRST_INTERRUPT: usize = 0x20;
+
+    /// Returns the vector of the IRQ line, as remapped by the offsets of the legacy PIC.
+    pub fn pic(irq: u8) -> Result<Self, IntCtrlError> {
+        unsafe { with_int_disabled(|| {
+            PROGRAMMABLE_INTERRUPT_CONTROLLER.lock()
+                .as_mut()
+                .ok_or(IntCtrlError::NotInitialized)
+                .and_then(|pic| pic.map_gsi(irq as u32))
+        }) }.map(|vector| Self::PICMappings(vector as usize))
+    }
+
+    /// Returns the vector of the IRQ line, as routed by the IO APIC.
+    pub fn apic(irq: u8) -> Result<Self, IntCtrlError> {
+        unsafe { with_int_disabled(|| {
+            IO_APIC.lock()
+                .as_mut()
+                .ok_or(IntCtrlError::NotInitialized)
+                .and_then(|ioapic| ioapic.map_gsi(irq as u32))
+        }) }.map(|vector| Self::APICMappings(vector as usize))
+    }
+
+    /// Returns the vector of the IRQ line on the active interrupt controller.
+    pub fn irq(irq: u8) -> Result<Self, IntCtrlError> {
+        match Self::apic(irq) {
+            Err(IntCtrlError::NotInitialized) => Self::pic(irq),
+            vector => vector,
+        }
+    }
+
+    /// Returns the raw vector number.
+    ///
+    /// The number is not validated, mappings may hold values above 255.
+    pub const fn number(&self) -> usize {
+        match *self {
+            Self::PICMappings(num) | Self::APICMappings(num) | Self::Custom(num) => num,
+            // The enum has a primitive representation, so the discriminant is it's first field.
+            _ => unsafe { *(self as *const Self as *const usize) },
+        }
+    }
+
+    /// Returns the IRQ line which the active interrupt controller delivers on this vector.
+    pub fn irq_line(&self) -> Option<u8> {
+        let vector = u8::try_from(*self).ok().filter(|&vector| vector as usize >= Self::FIRST_INTERRUPT)?;
+        with_controller(|ctrl| (0..IRQ_LINES).find(|&irq| ctrl.map_gsi(irq as u32) == Ok(vector)))
+            .ok()
+            .flatten()
+    }
+
+    /// Returns a short name of the vector for the reports.
+    ///
+    /// Exceptions are named by their mnemonic, IRQ lines by the ISA device usually wired to
+    /// them. Other vectors are named after their owner in the IDT, if any.
+    pub fn name(&self) -> &'static str {
+        use crate::kernel_components::arch_x86_64::{
+            controllers::apic::SPURIOUS_VECTOR, shootdown::SHOOTDOWN_VECTOR, thermal::THERMAL_VECTOR,
+        };
+        use super::INTERRUPT_DESCRIPTOR_TABLE;
+
+        let Ok(vector) = u8::try_from(*self) else { return "invalid" };
+        match vector {
+            0x0..=0x1f => EXCEPTION_NAMES[vector as usize],
+            SPURIOUS_VECTOR => "apic-spurious",
+            THERMAL_VECTOR => "thermal",
+            SHOOTDOWN_VECTOR => "tlb-shootdown",
+            _ => self.irq_line()
+                .map(|irq| IsaIrq::from_line(irq).map_or("irq", |irq| irq.name()))
+                .or_else(|| unsafe { INTERRUPT_DESCRIPTOR_TABLE.vector_owner(vector) })
+                .unwrap_or("unknown"),
+        }
+    }
+}
+
+impl From<u8> for InterruptVector {
+    /// Exceptions are converted to their named variants, every other vector to a custom one.
+    fn from(vector: u8) -> Self {
+        Self::iter()
+            .into_iter()
+            .find(|named| named.number() == vector as usize)
+            .unwrap_or(Self::Custom(vector as usize))
+    }
+}
+
+impl TryFrom<InterruptVector> for u8 {
+    type Error = InterruptVector;
+
+    /// Fails for mappings beyond the 256 vectors of the IDT.
+    fn try_from(vector: InterruptVector) -> Result<Self, Self::Error> {
+        u8::try_from(vector.number()).map_err(|_| vector)
+    }
+}
+
+/// ISA IRQ lines of a PC, named after the devices usually wired to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum IsaIrq {
+    Timer = 0,
+    Keyboard = 1,
+    /// Line of the master PIC to which the slave is connected.
+    Cascade = 2,
+    Com2 = 3,
+    Com1 = 4,
+    Lpt2 = 5,
+    Floppy = 6,
+    /// Also the spurious line of the master PIC.
+    Lpt1 = 7,
+    Rtc = 8,
+    Acpi = 9,
+    /// Free line, usually taken by PCI devices.
+    Free10 = 10,
+    /// Free line, usually taken by PCI devices.
+    Free11 = 11,
+    Mouse = 12,
+    Fpu = 13,
+    PrimaryAta = 14,
+    /// Also the spurious line of the slave PIC.
+    SecondaryAta = 15,
+}
+
+impl IsaIrq {
+    /// All of the lines, in the order of their numbers.
+    const LINES: [Self; 16] = [
+        Self::Timer, Self::Keyboard, Self::Cascade, Self::Com2, Self::Com1, Self::Lpt2, Self::Floppy,
+        Self::Lpt1, Self::Rtc, Self::Acpi, Self::Free10, Self::Free11, Self::Mouse, Self::Fpu,
+        Self::PrimaryAta, Self::SecondaryAta,
+    ];
+
+    /// Returns the ISA line with the number, if it is one.
+    pub const fn from_line(irq: u8) -> Option<Self> {
+        if irq < 16 { Some(Self::LINES[irq as usize]) } else { None }
+    }
+
+    /// Returns the number of the line.
+    pub const fn line(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Returns the name of the device usually wired to the line.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Timer => "timer",
+            Self::Keyboard => "keyboard",
+            Self::Cascade => "cascade",
+            Self::Com2 => "com2",
+            Self::Com1 => "com1",
+            Self::Lpt2 => "lpt2",
+            Self::Floppy => "floppy",
+            Self::Lpt1 => "lpt1",
+            Self::Rtc => "rtc",
+            Self::Acpi => "acpi",
+            Self::Free10 => "irq10",
+            Self::Free11 => "irq11",
+            Self::Mouse => "mouse",
+            Self::Fpu => "fpu",
+            Self::PrimaryAta => "ata0",
+            Self::SecondaryAta => "ata1",
+        }
+    }
+}
+
+/// Represents the interrupt stack frame pushed by the CPU on interrupt or exception entry.
+/// 
+/// This type must be used by the "x86-interrupt" calling convention. Every handler, the IRQ
+/// dispatch stubs and the preemption path of the scheduler see the interrupted context through
+/// it, so changes to the frame are applied by the "iretq" instruction.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct InterruptStackFrame {
+    /// An instruction pointer to the current instruction address that must be executed.  
+    pub instruction_pointer: usize,
+    /// The selector of a code segment.
+    pub code_segment: SegmentSelector,
+    /// A values of RFLAGS register, on the moment of calling the handler function.
+    pub cpu_flags: u64,
+    /// Stack pointer value at the moment of the interrupt.
+    pub stack_ptr: usize,
+    /// The segment descriptor of the stack segment.
+    /// 
+    /// Only the first half of the descriptor is needed. In Long Mode usually not used.
+    pub stack_segment: u64,
+}
+
+impl InterruptStackFrame {
+    /// Checks if the interrupted code ran in the user mode (ring 3).
+    #[inline]
+    pub const fn is_user_mode(&self) -> bool {
+        self.code_segment.0 & 0b11 == 3
+    }
+
+    /// Checks if the interrupts were enabled in the interrupted context.
+    #[inline]
+    pub fn interrupts_enabled(&self) -> bool {
+        XFLAGSFlags::INTERRUPT_FLAG.is_in(self.cpu_flags)
+    }
+
+    /// Makes the interrupted context continue with the interrupts enabled.
+    #[inline]
+    pub fn enable_interrupts(&mut self) {
+        self.cpu_flags |= XFLAGSFlags::INTERRUPT_FLAG.bits();
+    }
+
+    /// Makes the "iretq" instruction continue at another instruction with another stack.
+    #[inline]
+    pub fn redirect(&mut self, instruction_pointer: usize, stack_ptr: usize) {
+        self.instruction_pointer = instruction_pointer;
+        self.stack_ptr = stack_ptr;
+    }
+}
+
+/// Interrupt stack frame of an exception together with the error code, if the exception
+/// pushes one.
+///
+/// The processor pushes the error code right below the frame, so the "x86-interrupt" handlers
+/// receive it as a separate argument. This type joins both, so exceptions with and without an
+/// error code are reported the same way.
+#[derive(Debug, Clone, Copy)]
+pub struct ExceptionFrame {
+    pub frame: InterruptStackFrame,
+    pub error_code: Option<ErrorCode>,
+}
+
+impl ExceptionFrame {
+    /// Creates the frame of an exception without the error code.
+    #[inline]
+    pub const fn new(frame: InterruptStackFrame) -> Self {
+        Self { frame, error_code: None }
+    }
+
+    /// Creates the frame of an exception which pushed the error code.
+    #[inline]
+    pub const fn with_error_code(frame: InterruptStackFrame, error_code: ErrorCode) -> Self {
+        Self { frame, error_code: Some(error_code) }
+    }
+}
+
+impl From<InterruptStackFrame> for ExceptionFrame {
+    fn from(frame: InterruptStackFrame) -> Self {
+        Self::new(frame)
+    }
+}
+
+impl Deref for ExceptionFrame {
+    type Target = InterruptStackFrame;
+
+    fn deref(&self) -> &Self::Target {
+        &self.frame
+    }
+}
+
+impl DerefMut for ExceptionFrame {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.frame
+    }
+}
+
+/// General purpose registers of an interrupted context.
+///
+/// The "x86-interrupt" calling convention does not expose them, so only handlers entered through
+/// an assembly stub, which pushes them in the order of the fields in reverse, can see them.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct GeneralRegisters {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+}
+
+impl core::fmt::Display for GeneralRegisters {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "RAX {:#018x} RBX {:#018x} RCX {:#018x}", self.rax, self.rbx, self.rcx)?;
+        writeln!(f, "RDX {:#018x} RSI {:#018x} RDI {:#018x}", self.rdx, self.rsi, self.rdi)?;
+        writeln!(f, "RBP {:#018x} R8  {:#018x} R9  {:#018x}", self.rbp, self.r8, self.r9)?;
+        writeln!(f, "R10 {:#018x} R11 {:#018x} R12 {:#018x}", self.r10, self.r11, self.r12)?;
+        write!(f, "R13 {:#018x} R14 {:#018x} R15 {:#018x}", self.r13, self.r14, self.r15)
+    }
+}
+
 /// Enables interrupts.
 #[inline(always)]
 pub unsafe fn enable() {
@@ -116,6 +430,9 @@ pub fn is_interrupts_enabled() -> bool {
 /// caused via interrupts. This basically disables the software interrupts to occur, which is
 /// timer interrupts and i/o s. It prevents the interrupt to cause undefined behavior of something
 /// that should not be interrupted.
+///
+/// Critical sections can be nested. Interrupts are only restored when the outermost section
+/// ends, see [`CriticalGuard`].
 /// 
 /// # Unsafe
 /// 
@@ -123,19 +440,125 @@ pub fn is_interrupts_enabled() -> bool {
 /// the OS logic. Overusing this will cause a latency in interrupts.
 #[inline(always)]
 pub unsafe fn with_int_disabled<F, T>(fun: F) -> T where F: FnOnce() -> T {
-    let enabled = XFLAGSFlags::INTERRUPT_FLAG.is_in(XFLAGS::read().bits());
+    let _guard = CriticalGuard::enter();
+    fun()
+}
 
-    if enabled {
-        disable();
+/// Default amount of TSC cycles after which a critical section is reported in debug builds.
+pub const DEFAULT_CRITICAL_WARN_CYCLES: u64 = 10_000_000;
+
+/// Critical section warning threshold in TSC cycles. Zero disables the warning.
+///
+/// Only used in debug builds.
+pub static CRITICAL_WARN_CYCLES: AtomicU64 = AtomicU64::new(DEFAULT_CRITICAL_WARN_CYCLES);
+
+/// Critical section state of a processor.
+///
+/// Each processor nests it's own sections, so the interrupt flag saved by one processor is never
+/// restored by another.
+struct CriticalState {
+    /// Amount of currently entered critical sections.
+    depth: AtomicUsize,
+    /// Interrupts must be enabled when the outermost section ends.
+    restore: AtomicBool,
+    /// TSC value when the outermost section was entered.
+    entered_at: AtomicU64,
+}
+
+impl CriticalState {
+    const fn new() -> Self {
+        Self { depth: AtomicUsize::new(0), restore: AtomicBool::new(false), entered_at: AtomicU64::new(0) }
     }
+}
 
-    let output = fun();
+/// Critical section state of each processor, indexed by [`smp::cpu_id`].
+static CRITICAL_STATE: [CriticalState; MAX_CPUS] = [const { CriticalState::new() }; MAX_CPUS];
+/// The longest observed outermost section in TSC cycles, on any processor.
+static CRITICAL_MAX_CYCLES: AtomicU64 = AtomicU64::new(0);
+/// Prevents recursion when the report itself takes too long.
+static CRITICAL_REPORTING: AtomicBool = AtomicBool::new(false);
 
-    if enabled {
-        enable();
+/// RAII guard of a critical section.
+///
+/// Interrupts are disabled while at least one guard is alive. Only the outermost guard remembers
+/// whether interrupts were enabled before and restores them on drop, so nested sections never
+/// enable interrupts too early.
+///
+/// Unlike the closure based [`with_int_disabled`], the guard allows to hold references to
+/// the protected data for the whole scope instead of copying the values out of the closure.
+pub struct CriticalGuard {
+    /// Processor which entered the section. Interrupts are disabled, so it can not migrate.
+    cpu: usize,
+    // Must be dropped on the same CPU.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl CriticalGuard {
+    /// Enters a new critical section.
+    ///
+    /// # Unsafe
+    ///
+    /// Same as for [`with_int_disabled`]: interrupts stay disabled until the guard is dropped.
+    #[inline(always)]
+    pub unsafe fn enter() -> Self {
+        let enabled = is_interrupts_enabled();
+
+        if enabled {
+            disable();
+        }
+
+        let cpu = smp::cpu_id();
+        let state = &CRITICAL_STATE[cpu];
+        if state.depth.fetch_add(1, Ordering::Relaxed) == 0 {
+            state.restore.store(enabled, Ordering::Relaxed);
+            #[cfg(debug_assertions)]
+            state.entered_at.store(core::arch::x86_64::_rdtsc(), Ordering::Relaxed);
+        }
+
+        Self { cpu, _not_send: PhantomData }
     }
 
-    output
+    /// Returns the current nesting depth of critical sections on the running processor.
+    pub fn depth() -> usize {
+        CRITICAL_STATE[smp::cpu_id()].depth.load(Ordering::Relaxed)
+    }
+
+    /// Returns the longest observed outermost critical section in TSC cycles.
+    ///
+    /// Always zero in release builds.
+    pub fn max_cycles() -> u64 {
+        CRITICAL_MAX_CYCLES.load(Ordering::Relaxed)
+    }
+
+    /// Records the duration of the outermost section and reports it if it was too long.
+    #[cfg(debug_assertions)]
+    fn account(entered_at: u64) {
+        let cycles = unsafe { core::arch::x86_64::_rdtsc() }.wrapping_sub(entered_at);
+        CRITICAL_MAX_CYCLES.fetch_max(cycles, Ordering::Relaxed);
+
+        let threshold = CRITICAL_WARN_CYCLES.load(Ordering::Relaxed);
+        if threshold != 0 && cycles > threshold && !CRITICAL_REPORTING.swap(true, Ordering::Acquire) {
+            crate::warn!("Interrupts were disabled for {} cycles (threshold {}).", cycles, threshold);
+            CRITICAL_REPORTING.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for CriticalGuard {
+    #[inline(always)]
+    fn drop(&mut self) {
+        let state = &CRITICAL_STATE[self.cpu];
+        if state.depth.fetch_sub(1, Ordering::Relaxed) == 1 {
+            #[cfg(debug_assertions)]
+            Self::account(state.entered_at.load(Ordering::Relaxed));
+
+            if state.restore.load(Ordering::Relaxed) {
+                unsafe { enable() };
+                // Software interrupts raised within the section could not be delivered so far.
+                super::softint::deliver_pending();
+            }
+        }
+    }
 }
 
 /// Does something with enabled interrupts.
@@ -206,10 +629,66 @@ pub unsafe fn divide_by_zero(input: u32) {
     }
 }
 
+/// Raises the interrupt with the vector known at compile time.
+///
+/// Exceptions and reserved vectors are rejected at compile time. Use [`int_imm_unsafe`] to raise
+/// them anyway.
+#[inline(always)]
+pub fn int_imm<const N: u8>() {
+    let () = ImmVector::<N>::NOT_EXCEPTION;
+    unsafe { int_imm_unsafe::<N>() }
+}
+
+/// Vector of [`int_imm`], checked when the function is instantiated.
+struct ImmVector<const N: u8>;
+
+impl<const N: u8> ImmVector<N> {
+    const NOT_EXCEPTION: () = assert!(N as usize >= InterruptVector::FIRST_INTERRUPT, "exceptions must not be raised with INTn");
+}
+
+/// Raises the interrupt or exception with the vector known at compile time.
+///
+/// # Unsafe
+///
+/// Exceptions should not be caused by the software via INTn, as their handlers may expect an
+/// error code which is never pushed.
+#[inline(always)]
+pub unsafe fn int_imm_unsafe<const N: u8>() {
+    asm!("int {}", const N, options(nomem, preserves_flags));
+}
+
+/// Expands into a row of sixteen trampolines, starting at the provided vector.
+macro_rules! int_row {
+    ($base:expr) => {
+        [
+            int_imm_unsafe::<{ $base }>, int_imm_unsafe::<{ $base + 1 }>,
+            int_imm_unsafe::<{ $base + 2 }>, int_imm_unsafe::<{ $base + 3 }>,
+            int_imm_unsafe::<{ $base + 4 }>, int_imm_unsafe::<{ $base + 5 }>,
+            int_imm_unsafe::<{ $base + 6 }>, int_imm_unsafe::<{ $base + 7 }>,
+            int_imm_unsafe::<{ $base + 8 }>, int_imm_unsafe::<{ $base + 9 }>,
+            int_imm_unsafe::<{ $base + 10 }>, int_imm_unsafe::<{ $base + 11 }>,
+            int_imm_unsafe::<{ $base + 12 }>, int_imm_unsafe::<{ $base + 13 }>,
+            int_imm_unsafe::<{ $base + 14 }>, int_imm_unsafe::<{ $base + 15 }>,
+        ]
+    };
+}
+
+/// Trampolines raising each vector, indexed by the high and the low nibble of the vector.
+///
+/// INTn only takes an immediate, so a vector known at runtime is raised by calling the
+/// trampoline generated for it.
+static INT_TRAMPOLINES: [[unsafe fn(); 16]; 16] = [
+    int_row!(0x00), int_row!(0x10), int_row!(0x20), int_row!(0x30),
+    int_row!(0x40), int_row!(0x50), int_row!(0x60), int_row!(0x70),
+    int_row!(0x80), int_row!(0x90), int_row!(0xa0), int_row!(0xb0),
+    int_row!(0xc0), int_row!(0xd0), int_row!(0xe0), int_row!(0xf0),
+];
+
 /// Causes an interrupt based on the provided interrupt vector.
 /// 
 /// This function can be usable for testing out handler functions, or causing
-/// a software interrupt for personal attends.
+/// a software interrupt for personal attends. Use [`int_imm`] when the vector is
+/// known at compile time.
 /// 
 /// # Warn
 /// 
@@ -238,267 +717,7 @@ pub fn cause_interrupt(vector_num: u8) {
 /// then use the safe version of this function instead.
 #[inline(always)]
 pub unsafe fn cause_interrupt_unsafe(vector_num: u8) {
-    // There is no better way to write it in rust, except to create a macro, but
-    // it requires a #![asm_const] to be enabled. Too much dumb things, better just
-    // spam with match values.
-    match vector_num {
-        0 => asm!("int 0", options(nomem, preserves_flags)),
-        1 => asm!("int 1", options(nomem, preserves_flags)),
-        2 => asm!("int 2", options(nomem, preserves_flags)),
-        3 => asm!("int 3", options(nomem, preserves_flags)),
-        4 => asm!("int 4", options(nomem, preserves_flags)),
-        5 => asm!("int 5", options(nomem, preserves_flags)),
-        6 => asm!("int 6", options(nomem, preserves_flags)),
-        7 => asm!("int 7", options(nomem, preserves_flags)),
-        8 => asm!("int 8", options(nomem, preserves_flags)),
-        9 => asm!("int 9", options(nomem, preserves_flags)),
-        10 => asm!("int 10", options(nomem, preserves_flags)),
-        11 => asm!("int 11", options(nomem, preserves_flags)),
-        12 => asm!("int 12", options(nomem, preserves_flags)),
-        13 => asm!("int 13", options(nomem, preserves_flags)),
-        14 => asm!("int 14", options(nomem, preserves_flags)),
-        15 => asm!("int 15", options(nomem, preserves_flags)),
-        16 => asm!("int 16", options(nomem, preserves_flags)),
-        17 => asm!("int 17", options(nomem, preserves_flags)),
-        18 => asm!("int 18", options(nomem, preserves_flags)),
-        19 => asm!("int 19", options(nomem, preserves_flags)),
-        20 => asm!("int 20", options(nomem, preserves_flags)),
-        21 => asm!("int 21", options(nomem, preserves_flags)),
-        22 => asm!("int 22", options(nomem, preserves_flags)),
-        23 => asm!("int 23", options(nomem, preserves_flags)),
-        24 => asm!("int 24", options(nomem, preserves_flags)),
-        25 => asm!("int 25", options(nomem, preserves_flags)),
-        26 => asm!("int 26", options(nomem, preserves_flags)),
-        27 => asm!("int 27", options(nomem, preserves_flags)),
-        28 => asm!("int 28", options(nomem, preserves_flags)),
-        29 => asm!("int 29", options(nomem, preserves_flags)),
-        30 => asm!("int 30", options(nomem, preserves_flags)),
-        31 => asm!("int 31", options(nomem, preserves_flags)),
-        32 => asm!("int 32", options(nomem, preserves_flags)),
-        33 => asm!("int 33", options(nomem, preserves_flags)),
-        34 => asm!("int 34", options(nomem, preserves_flags)),
-        35 => asm!("int 35", options(nomem, preserves_flags)),
-        36 => asm!("int 36", options(nomem, preserves_flags)),
-        37 => asm!("int 37", options(nomem, preserves_flags)),
-        38 => asm!("int 38", options(nomem, preserves_flags)),
-        39 => asm!("int 39", options(nomem, preserves_flags)),
-        40 => asm!("int 40", options(nomem, preserves_flags)),
-        41 => asm!("int 41", options(nomem, preserves_flags)),
-        42 => asm!("int 42", options(nomem, preserves_flags)),
-        43 => asm!("int 43", options(nomem, preserves_flags)),
-        44 => asm!("int 44", options(nomem, preserves_flags)),
-        45 => asm!("int 45", options(nomem, preserves_flags)),
-        46 => asm!("int 46", options(nomem, preserves_flags)),
-        47 => asm!("int 47", options(nomem, preserves_flags)),
-        48 => asm!("int 48", options(nomem, preserves_flags)),
-        49 => asm!("int 49", options(nomem, preserves_flags)),
-        50 => asm!("int 50", options(nomem, preserves_flags)),
-        51 => asm!("int 51", options(nomem, preserves_flags)),
-        52 => asm!("int 52", options(nomem, preserves_flags)),
-        53 => asm!("int 53", options(nomem, preserves_flags)),
-        54 => asm!("int 54", options(nomem, preserves_flags)),
-        55 => asm!("int 55", options(nomem, preserves_flags)),
-        56 => asm!("int 56", options(nomem, preserves_flags)),
-        57 => asm!("int 57", options(nomem, preserves_flags)),
-        58 => asm!("int 58", options(nomem, preserves_flags)),
-        59 => asm!("int 59", options(nomem, preserves_flags)),
-        60 => asm!("int 60", options(nomem, preserves_flags)),
-        61 => asm!("int 61", options(nomem, preserves_flags)),
-        62 => asm!("int 62", options(nomem, preserves_flags)),
-        63 => asm!("int 63", options(nomem, preserves_flags)),
-        64 => asm!("int 64", options(nomem, preserves_flags)),
-        65 => asm!("int 65", options(nomem, preserves_flags)),
-        66 => asm!("int 66", options(nomem, preserves_flags)),
-        67 => asm!("int 67", options(nomem, preserves_flags)),
-        68 => asm!("int 68", options(nomem, preserves_flags)),
-        69 => asm!("int 69", options(nomem, preserves_flags)),
-        70 => asm!("int 70", options(nomem, preserves_flags)),
-        71 => asm!("int 71", options(nomem, preserves_flags)),
-        72 => asm!("int 72", options(nomem, preserves_flags)),
-        73 => asm!("int 73", options(nomem, preserves_flags)),
-        74 => asm!("int 74", options(nomem, preserves_flags)),
-        75 => asm!("int 75", options(nomem, preserves_flags)),
-        76 => asm!("int 76", options(nomem, preserves_flags)),
-        77 => asm!("int 77", options(nomem, preserves_flags)),
-        78 => asm!("int 78", options(nomem, preserves_flags)),
-        79 => asm!("int 79", options(nomem, preserves_flags)),
-        80 => asm!("int 80", options(nomem, preserves_flags)),
-        81 => asm!("int 81", options(nomem, preserves_flags)),
-        82 => asm!("int 82", options(nomem, preserves_flags)),
-        83 => asm!("int 83", options(nomem, preserves_flags)),
-        84 => asm!("int 84", options(nomem, preserves_flags)),
-        85 => asm!("int 85", options(nomem, preserves_flags)),
-        86 => asm!("int 86", options(nomem, preserves_flags)),
-        87 => asm!("int 87", options(nomem, preserves_flags)),
-        88 => asm!("int 88", options(nomem, preserves_flags)),
-        89 => asm!("int 89", options(nomem, preserves_flags)),
-        90 => asm!("int 90", options(nomem, preserves_flags)),
-        91 => asm!("int 91", options(nomem, preserves_flags)),
-        92 => asm!("int 92", options(nomem, preserves_flags)),
-        93 => asm!("int 93", options(nomem, preserves_flags)),
-        94 => asm!("int 94", options(nomem, preserves_flags)),
-        95 => asm!("int 95", options(nomem, preserves_flags)),
-        96 => asm!("int 96", options(nomem, preserves_flags)),
-        97 => asm!("int 97", options(nomem, preserves_flags)),
-        98 => asm!("int 98", options(nomem, preserves_flags)),
-        99 => asm!("int 99", options(nomem, preserves_flags)),
-        100 => asm!("int 100", options(nomem, preserves_flags)),
-        101 => asm!("int 101", options(nomem, preserves_flags)),
-        102 => asm!("int 102", options(nomem, preserves_flags)),
-        103 => asm!("int 103", options(nomem, preserves_flags)),
-        104 => asm!("int 104", options(nomem, preserves_flags)),
-        105 => asm!("int 105", options(nomem, preserves_flags)),
-        106 => asm!("int 106", options(nomem, preserves_flags)),
-        107 => asm!("int 107", options(nomem, preserves_flags)),
-        108 => asm!("int 108", options(nomem, preserves_flags)),
-        109 => asm!("int 109", options(nomem, preserves_flags)),
-        110 => asm!("int 110", options(nomem, preserves_flags)),
-        111 => asm!("int 111", options(nomem, preserves_flags)),
-        112 => asm!("int 112", options(nomem, preserves_flags)),
-        113 => asm!("int 113", options(nomem, preserves_flags)),
-        114 => asm!("int 114", options(nomem, preserves_flags)),
-        115 => asm!("int 115", options(nomem, preserves_flags)),
-        116 => asm!("int 116", options(nomem, preserves_flags)),
-        117 => asm!("int 117", options(nomem, preserves_flags)),
-        118 => asm!("int 118", options(nomem, preserves_flags)),
-        119 => asm!("int 119", options(nomem, preserves_flags)),
-        120 => asm!("int 120", options(nomem, preserves_flags)),
-        121 => asm!("int 121", options(nomem, preserves_flags)),
-        122 => asm!("int 122", options(nomem, preserves_flags)),
-        123 => asm!("int 123", options(nomem, preserves_flags)),
-        124 => asm!("int 124", options(nomem, preserves_flags)),
-        125 => asm!("int 125", options(nomem, preserves_flags)),
-        126 => asm!("int 126", options(nomem, preserves_flags)),
-        127 => asm!("int 127", options(nomem, preserves_flags)),
-        128 => asm!("int 128", options(nomem, preserves_flags)),
-        129 => asm!("int 129", options(nomem, preserves_flags)),
-        130 => asm!("int 130", options(nomem, preserves_flags)),
-        131 => asm!("int 131", options(nomem, preserves_flags)),
-        132 => asm!("int 132", options(nomem, preserves_flags)),
-        133 => asm!("int 133", options(nomem, preserves_flags)),
-        134 => asm!("int 134", options(nomem, preserves_flags)),
-        135 => asm!("int 135", options(nomem, preserves_flags)),
-        136 => asm!("int 136", options(nomem, preserves_flags)),
-        137 => asm!("int 137", options(nomem, preserves_flags)),
-        138 => asm!("int 138", options(nomem, preserves_flags)),
-        139 => asm!("int 139", options(nomem, preserves_flags)),
-        140 => asm!("int 140", options(nomem, preserves_flags)),
-        141 => asm!("int 141", options(nomem, preserves_flags)),
-        142 => asm!("int 142", options(nomem, preserves_flags)),
-        143 => asm!("int 143", options(nomem, preserves_flags)),
-        144 => asm!("int 144", options(nomem, preserves_flags)),
-        145 => asm!("int 145", options(nomem, preserves_flags)),
-        146 => asm!("int 146", options(nomem, preserves_flags)),
-        147 => asm!("int 147", options(nomem, preserves_flags)),
-        148 => asm!("int 148", options(nomem, preserves_flags)),
-        149 => asm!("int 149", options(nomem, preserves_flags)),
-        150 => asm!("int 150", options(nomem, preserves_flags)),
-        151 => asm!("int 151", options(nomem, preserves_flags)),
-        152 => asm!("int 152", options(nomem, preserves_flags)),
-        153 => asm!("int 153", options(nomem, preserves_flags)),
-        154 => asm!("int 154", options(nomem, preserves_flags)),
-        155 => asm!("int 155", options(nomem, preserves_flags)),
-        156 => asm!("int 156", options(nomem, preserves_flags)),
-        157 => asm!("int 157", options(nomem, preserves_flags)),
-        158 => asm!("int 158", options(nomem, preserves_flags)),
-        159 => asm!("int 159", options(nomem, preserves_flags)),
-        160 => asm!("int 160", options(nomem, preserves_flags)),
-        161 => asm!("int 161", options(nomem, preserves_flags)),
-        162 => asm!("int 162", options(nomem, preserves_flags)),
-        163 => asm!("int 163", options(nomem, preserves_flags)),
-        164 => asm!("int 164", options(nomem, preserves_flags)),
-        165 => asm!("int 165", options(nomem, preserves_flags)),
-        166 => asm!("int 166", options(nomem, preserves_flags)),
-        167 => asm!("int 167", options(nomem, preserves_flags)),
-        168 => asm!("int 168", options(nomem, preserves_flags)),
-        169 => asm!("int 169", options(nomem, preserves_flags)),
-        170 => asm!("int 170", options(nomem, preserves_flags)),
-        171 => asm!("int 171", options(nomem, preserves_flags)),
-        172 => asm!("int 172", options(nomem, preserves_flags)),
-        173 => asm!("int 173", options(nomem, preserves_flags)),
-        174 => asm!("int 174", options(nomem, preserves_flags)),
-        175 => asm!("int 175", options(nomem, preserves_flags)),
-        176 => asm!("int 176", options(nomem, preserves_flags)),
-        177 => asm!("int 177", options(nomem, preserves_flags)),
-        178 => asm!("int 178", options(nomem, preserves_flags)),
-        179 => asm!("int 179", options(nomem, preserves_flags)),
-        180 => asm!("int 180", options(nomem, preserves_flags)),
-        181 => asm!("int 181", options(nomem, preserves_flags)),
-        182 => asm!("int 182", options(nomem, preserves_flags)),
-        183 => asm!("int 183", options(nomem, preserves_flags)),
-        184 => asm!("int 184", options(nomem, preserves_flags)),
-        185 => asm!("int 185", options(nomem, preserves_flags)),
-        186 => asm!("int 186", options(nomem, preserves_flags)),
-        187 => asm!("int 187", options(nomem, preserves_flags)),
-        188 => asm!("int 188", options(nomem, preserves_flags)),
-        189 => asm!("int 189", options(nomem, preserves_flags)),
-        190 => asm!("int 190", options(nomem, preserves_flags)),
-        191 => asm!("int 191", options(nomem, preserves_flags)),
-        192 => asm!("int 192", options(nomem, preserves_flags)),
-        193 => asm!("int 193", options(nomem, preserves_flags)),
-        194 => asm!("int 194", options(nomem, preserves_flags)),
-        195 => asm!("int 195", options(nomem, preserves_flags)),
-        196 => asm!("int 196", options(nomem, preserves_flags)),
-        197 => asm!("int 197", options(nomem, preserves_flags)),
-        198 => asm!("int 198", options(nomem, preserves_flags)),
-        199 => asm!("int 199", options(nomem, preserves_flags)),
-        200 => asm!("int 200", options(nomem, preserves_flags)),
-        201 => asm!("int 201", options(nomem, preserves_flags)),
-        202 => asm!("int 202", options(nomem, preserves_flags)),
-        203 => asm!("int 203", options(nomem, preserves_flags)),
-        204 => asm!("int 204", options(nomem, preserves_flags)),
-        205 => asm!("int 205", options(nomem, preserves_flags)),
-        206 => asm!("int 206", options(nomem, preserves_flags)),
-        207 => asm!("int 207", options(nomem, preserves_flags)),
-        208 => asm!("int 208", options(nomem, preserves_flags)),
-        209 => asm!("int 209", options(nomem, preserves_flags)),
-        210 => asm!("int 210", options(nomem, preserves_flags)),
-        211 => asm!("int 211", options(nomem, preserves_flags)),
-        212 => asm!("int 212", options(nomem, preserves_flags)),
-        213 => asm!("int 213", options(nomem, preserves_flags)),
-        214 => asm!("int 214", options(nomem, preserves_flags)),
-        215 => asm!("int 215", options(nomem, preserves_flags)),
-        216 => asm!("int 216", options(nomem, preserves_flags)),
-        217 => asm!("int 217", options(nomem, preserves_flags)),
-        218 => asm!("int 218", options(nomem, preserves_flags)),
-        219 => asm!("int 219", options(nomem, preserves_flags)),
-        220 => asm!("int 220", options(nomem, preserves_flags)),
-        221 => asm!("int 221", options(nomem, preserves_flags)),
-        222 => asm!("int 222", options(nomem, preserves_flags)),
-        223 => asm!("int 223", options(nomem, preserves_flags)),
-        224 => asm!("int 224", options(nomem, preserves_flags)),
-        225 => asm!("int 225", options(nomem, preserves_flags)),
-        226 => asm!("int 226", options(nomem, preserves_flags)),
-        227 => asm!("int 227", options(nomem, preserves_flags)),
-        228 => asm!("int 228", options(nomem, preserves_flags)),
-        229 => asm!("int 229", options(nomem, preserves_flags)),
-        230 => asm!("int 230", options(nomem, preserves_flags)),
-        231 => asm!("int 231", options(nomem, preserves_flags)),
-        232 => asm!("int 232", options(nomem, preserves_flags)),
-        233 => asm!("int 233", options(nomem, preserves_flags)),
-        234 => asm!("int 234", options(nomem, preserves_flags)),
-        235 => asm!("int 235", options(nomem, preserves_flags)),
-        236 => asm!("int 236", options(nomem, preserves_flags)),
-        237 => asm!("int 237", options(nomem, preserves_flags)),
-        238 => asm!("int 238", options(nomem, preserves_flags)),
-        239 => asm!("int 239", options(nomem, preserves_flags)),
-        240 => asm!("int 240", options(nomem, preserves_flags)),
-        241 => asm!("int 241", options(nomem, preserves_flags)),
-        242 => asm!("int 242", options(nomem, preserves_flags)),
-        243 => asm!("int 243", options(nomem, preserves_flags)),
-        244 => asm!("int 244", options(nomem, preserves_flags)),
-        245 => asm!("int 245", options(nomem, preserves_flags)),
-        246 => asm!("int 246", options(nomem, preserves_flags)),
-        247 => asm!("int 247", options(nomem, preserves_flags)),
-        248 => asm!("int 248", options(nomem, preserves_flags)),
-        249 => asm!("int 249", options(nomem, preserves_flags)),
-        250 => asm!("int 250", options(nomem, preserves_flags)),
-        251 => asm!("int 251", options(nomem, preserves_flags)),
-        252 => asm!("int 252", options(nomem, preserves_flags)),
-        253 => asm!("int 253", options(nomem, preserves_flags)),
-        254 => asm!("int 254", options(nomem, preserves_flags)),
-        255 => asm!("int 255", options(nomem, preserves_flags)),
-    }
+    INT_TRAMPOLINES[vector_num as usize >> 4][vector_num as usize & 0xf]()
 }
 
 /// A macro that provides an easy way to implement critical sections.
@@ -506,11 +725,76 @@ pub unsafe fn cause_interrupt_unsafe(vector_num: u8) {
 /// This macro is just a wrapper around the with_int_disabled method, so it is not necessary to
 /// always import it from this module. This macro can return values the same way as the method
 /// does.
+///
+/// Without arguments it returns a [`CriticalGuard`], which keeps the section entered until
+/// dropped. This allows to return references instead of copying large values out of the closure.
 #[macro_export]
 macro_rules! critical_section {
+    () => {
+        unsafe {
+            $crate::kernel_components::arch_x86_64::interrupts::interrupt::CriticalGuard::enter()
+        }
+    };
     ($fn:expr) => {
         unsafe {
             $crate::kernel_components::arch_x86_64::interrupts::with_int_disabled(|| $fn())
         }
     };
 }
+
+#[test_case]
+fn critical_sections_nest() {
+    let depth = CriticalGuard::depth();
+    let outer = critical_section!();
+
+    assert_eq!(CriticalGuard::depth(), depth + 1);
+    critical_section!(|| assert_eq!(CriticalGuard::depth(), depth + 2));
+    assert!(!is_interrupts_enabled());
+
+    drop(outer);
+    assert_eq!(CriticalGuard::depth(), depth);
+}
+
+#[test_case]
+fn interrupt_stack_frame_layout() {
+    use core::mem::{offset_of, size_of};
+
+    // The order of the fields must match the one pushed by the processor.
+    assert_eq!(size_of::<InterruptStackFrame>(), 40);
+    assert_eq!(offset_of!(InterruptStackFrame, code_segment), 8);
+    assert_eq!(offset_of!(InterruptStackFrame, cpu_flags), 16);
+    assert_eq!(offset_of!(InterruptStackFrame, stack_segment), 32);
+
+    let mut frame = ExceptionFrame::from(InterruptStackFrame {
+        instruction_pointer: 0x1000,
+        code_segment: SegmentSelector(0x1b),
+        cpu_flags: 0x2,
+        stack_ptr: 0x2000,
+        stack_segment: 0x23,
+    });
+    assert!(frame.is_user_mode() && frame.error_code.is_none());
+    assert!(!frame.interrupts_enabled());
+
+    frame.enable_interrupts();
+    frame.redirect(0x3000, 0x4000);
+    assert!(frame.interrupts_enabled());
+    assert_eq!((frame.instruction_pointer, frame.stack_ptr, frame.cpu_flags), (0x3000, 0x4000, 0x202));
+    assert_eq!(ExceptionFrame::with_error_code(frame.frame, ErrorCode(0x10)).error_code, Some(ErrorCode(0x10)));
+}
+
+#[test_case]
+fn vectors_convert_to_raw_numbers() {
+    assert_eq!(InterruptVector::DOUBLE_FAULT.number(), 0x8);
+    assert_eq!(InterruptVector::SIMD_FLOATING_POINT_EXCEPTION.number(), 0x13);
+    assert_eq!(InterruptVector::from(0xe), InterruptVector::PAGE_FAULT);
+    assert_eq!(InterruptVector::from(0x1), InterruptVector::Custom(0x1));
+    assert_eq!(InterruptVector::from(0x80), InterruptVector::Custom(0x80));
+
+    assert_eq!(u8::try_from(InterruptVector::APICMappings(0x30)), Ok(0x30));
+    assert_eq!(u8::try_from(InterruptVector::PICMappings(0x100)), Err(InterruptVector::PICMappings(0x100)));
+
+    assert_eq!(InterruptVector::GENERAL_PROTECTION_FAULT.name(), "#GP");
+    assert_eq!(InterruptVector::Custom(0xf9).name(), "tlb-shootdown");
+    assert_eq!(IsaIrq::from_line(8).map(|irq| irq.name()), Some("rtc"));
+    assert_eq!(IsaIrq::from_line(16), None);
+}
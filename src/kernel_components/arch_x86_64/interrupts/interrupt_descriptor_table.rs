@@ -8,13 +8,18 @@ use crate::kernel_components::arch_x86_64::{
     segmentation::SegmentSelector,
     PrivilegeLevel,
     DTPointer,
-    descriptor_table::{lidt, sidt},
+    descriptor_table::{lidt, sidt, assert_loaded, DescriptorTableType},
+    controllers::apic::SPURIOUS_VECTOR,
+    shootdown::SHOOTDOWN_VECTOR,
+    thermal::THERMAL_VECTOR,
 };
 
-use super::{InterruptVector, HandlerFn};
+use super::{InterruptVector, HandlerFn, unhandled};
 
+use core::error::Error;
+use core::fmt::Display;
 use core::marker::PhantomData;
-use core::ops::Index;
+use core::ops::{Index, RangeInclusive};
 use core::mem;
 
 /// A static instance of a global IDT.
@@ -25,6 +30,36 @@ single! {
     pub mut INTERRUPT_DESCRIPTOR_TABLE: IDT = IDT::new_empty();
 }
 
+/// Vectors handed out by [`IDT::allocate_vector`]. Vectors below are the exceptions and the
+/// ISA IRQs of the PIC or the IO APIC.
+pub const DYNAMIC_VECTORS: RangeInclusive<u8> = 0x30..=0xff;
+
+/// Errors of the vector allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorError {
+    /// Every vector of the dynamic range is taken.
+    Exhausted,
+    /// The vector is outside of [`DYNAMIC_VECTORS`].
+    OutOfRange(u8),
+    /// The vector is already owned.
+    InUse(u8, &'static str),
+    /// The vector is not allocated.
+    NotAllocated(u8),
+}
+
+impl Display for VectorError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Exhausted => write!(f, "No free interrupt vectors left."),
+            Self::OutOfRange(vector) => write!(f, "Vector {:#x} is not dynamically allocated.", vector),
+            Self::InUse(vector, owner) => write!(f, "Vector {:#x} is owned by {}.", vector, owner),
+            Self::NotAllocated(vector) => write!(f, "Vector {:#x} is not allocated.", vector),
+        }
+    }
+}
+
+impl Error for VectorError {}
+
 /// Interrupt description table.
 /// 
 /// Special table that specifies a handler function for each CPU exception. It is a binary 
@@ -36,11 +71,18 @@ single! {
 /// (0x0..0x1F inclusive) entries are reserved by the CPU, so called processor-generated exceptions.
 /// Not only that, but some entries that go afterwards are also reserved, which leads to less space
 /// for interrupts, but it is completely more than enough for a regular OS to handle.
+///
+/// Subsystems which need a vector of their own (MSI, IPIs, local APIC interrupts) should obtain
+/// it with [`IDT::allocate_vector`] instead of hardcoding a number. The owner of each allocated
+/// vector is tracked, so collisions are reported instead of silently replacing a gate.
 #[derive(Debug)]
 #[repr(C, align(16))]
 pub struct IDT {
     table: [GateDescriptor; 256],
     ints: [bool; 256],
+    owners: [Option<&'static str>; 256],
+    /// Freed vectors get the unhandled vector stub instead of an empty gate.
+    unhandled: bool,
 }
 
 impl IDT {
@@ -51,11 +93,20 @@ impl IDT {
     /// Each entry will be marked as empty entry. All of them will be invalid, so one must manually
     /// config all standard gates. This option provides the most of possibilities and overall flexibility.
     /// Because of that, the length is zero after the initialization.
+    ///
+    /// The fixed vectors of the local APIC are reserved from the start.
     #[inline]
     pub fn new_empty() -> Self {
+        let mut owners = [None; 256];
+        owners[SPURIOUS_VECTOR as usize] = Some("apic-spurious");
+        owners[THERMAL_VECTOR as usize] = Some("thermal");
+        owners[SHOOTDOWN_VECTOR as usize] = Some("tlb-shootdown");
+
         Self { 
             table: [GateDescriptor::EMPTY; 256], 
             ints: [false; 256],
+            owners,
+            unhandled: false,
         }
     }
     
@@ -64,24 +115,88 @@ impl IDT {
     /// By pushing, it just means rewriting the empty entries as a new ones.
     #[inline]
     pub fn push(&mut self, index: InterruptVector, gate: GateDescriptor) {
-        // Just converting a C-like enum to regular usize.
-        let index = match index {
-            InterruptVector::Custom(num) | InterruptVector::APICMappings(num) | InterruptVector::PICMappings(num) => num,
-            _ => InterruptVector::get_index(index)
-        };
-
+        let index = index.number();
         assert!(index < 256, "Index is out of bounds.");
 
         self.table[index] = gate;
     }
 
+    /// Allocates the lowest free vector of [`DYNAMIC_VECTORS`] for the owner.
+    ///
+    /// Vectors with a gate pushed manually are never handed out.
+    pub fn allocate_vector(&mut self, owner: &'static str) -> Result<u8, VectorError> {
+        let vector = DYNAMIC_VECTORS.clone()
+            .find(|&vector| self.is_free(vector))
+            .ok_or(VectorError::Exhausted)?;
+        self.owners[vector as usize] = Some(owner);
+        Ok(vector)
+    }
+
+    /// Claims the exact vector for the owner, for devices which can only use a fixed one.
+    pub fn reserve_vector(&mut self, vector: u8, owner: &'static str) -> Result<(), VectorError> {
+        if !DYNAMIC_VECTORS.contains(&vector) {
+            return Err(VectorError::OutOfRange(vector))
+        }
+        if !self.is_free(vector) {
+            return Err(VectorError::InUse(vector, self.owners[vector as usize].unwrap_or("unknown")))
+        }
+        self.owners[vector as usize] = Some(owner);
+        Ok(())
+    }
+
+    /// Frees the allocated vector and removes it's gate.
+    pub fn free_vector(&mut self, vector: u8) -> Result<(), VectorError> {
+        if !DYNAMIC_VECTORS.contains(&vector) {
+            return Err(VectorError::OutOfRange(vector))
+        }
+        self.owners[vector as usize].take().ok_or(VectorError::NotAllocated(vector))?;
+        self.table[vector as usize] = match self.unhandled {
+            true => unhandled::gate(vector),
+            false => GateDescriptor::EMPTY,
+        };
+        Ok(())
+    }
+
+    /// Returns the owner of the vector, if it was allocated or reserved.
+    #[inline]
+    pub fn vector_owner(&self, vector: u8) -> Option<&'static str> {
+        self.owners[vector as usize]
+    }
+
+    /// Checks if a present gate is installed on the vector.
+    #[inline]
+    pub fn is_present(&self, vector: u8) -> bool {
+        self.table[vector as usize].type_attributes.0 & TypeAttributesFlags::PRESENT_BIT.bits() != 0
+    }
+
+    /// Checks if the vector has a present gate of a handler other than the unhandled vector stub.
+    #[inline]
+    pub fn is_handled(&self, vector: u8) -> bool {
+        self.is_present(vector) && !unhandled::is_stub(self.table[vector as usize].handler_addr())
+    }
+
+    /// Installs the unhandled vector stub into every slot without a present gate.
+    ///
+    /// Stray interrupts and exceptions without a handler are reported this way instead of faulting
+    /// on a not present gate. The stubs are not counted as handlers by the vector allocation, and
+    /// freed vectors get their stub back.
+    pub fn fill_unhandled(&mut self) {
+        for vector in 0..=u8::MAX {
+            if !self.is_present(vector) {
+                self.table[vector as usize] = unhandled::gate(vector);
+            }
+        }
+        self.unhandled = true;
+    }
+
+    fn is_free(&self, vector: u8) -> bool {
+        self.owners[vector as usize].is_none() && !self.is_handled(vector)
+    }
+
     /// Returns the current table as a 'DTPointer'.
     #[inline]
     pub fn as_dt_ptr(&self) -> DTPointer {
-        DTPointer {
-            addr: self as *const _ as u64,
-            size: (mem::size_of::<Self>() - 1) as u16,
-        }
+        DTPointer::new(self.table.as_ptr() as u64, self.table.len(), mem::size_of::<GateDescriptor>())
     }
 
     /// Returns the current table from the 'DTPointer'.
@@ -95,7 +210,9 @@ impl IDT {
     /// Static lifetime guarantees the safety loading.
     #[inline]
     pub fn load_table(&'static self) {
-        unsafe { lidt(&self.as_dt_ptr()) }
+        let ptr = self.as_dt_ptr();
+        unsafe { lidt(&ptr) };
+        assert_loaded(DescriptorTableType::Idt, ptr);
     }
 
     /// Reads the current table value from the CPU.
@@ -265,6 +382,23 @@ impl GateDescriptor {
         )
     }
 
+    /// Makes the gate switch to a stack of the Interrupt Stack Table on entry.
+    ///
+    /// The index is in range 1..=7 and selects the entry `index - 1` of the TSS, while zero keeps
+    /// the legacy stack switching.
+    #[inline]
+    pub fn with_stack_index(mut self, index: u8) -> Self {
+        assert!(index < 8, "The IST is only 7 entries long.");
+        self.interupt_stack_table = index;
+        self
+    }
+
+    /// Returns the index of the Interrupt Stack Table entry used by the gate, or zero.
+    #[inline]
+    pub fn stack_index(&self) -> u8 {
+        self.interupt_stack_table & 0b111
+    }
+
     /// Returns the virtual address of this IDT entry's handler function.
     #[inline]
     pub fn handler_addr(&self) -> VirtualAddress {
@@ -409,3 +543,21 @@ impl TypeAttributes {
         self
     }
 }
+
+#[test_case]
+fn vectors_are_allocated_once() {
+    let mut idt = IDT::new_empty();
+    assert_eq!(idt.vector_owner(SHOOTDOWN_VECTOR), Some("tlb-shootdown"));
+
+    let first = idt.allocate_vector("msi").unwrap();
+    assert_eq!(first, *DYNAMIC_VECTORS.start());
+    assert_eq!(idt.reserve_vector(first, "ipi"), Err(VectorError::InUse(first, "msi")));
+    assert_eq!(idt.reserve_vector(0x21, "ipi"), Err(VectorError::OutOfRange(0x21)));
+
+    idt.table[first as usize + 1].type_attributes.set_present();
+    assert_eq!(idt.allocate_vector("ipi"), Ok(first + 2));
+
+    assert_eq!(idt.free_vector(first), Ok(()));
+    assert_eq!(idt.free_vector(first), Err(VectorError::NotAllocated(first)));
+    assert_eq!(idt.allocate_vector("timer"), Ok(first));
+}
@@ -0,0 +1,263 @@
+/// Runtime registration of IRQ handlers.
+///
+/// Instead of a hardcoded handler pushed into the IDT at boot, drivers register closures for
+/// an IRQ line with [`IrqManager::register`]. The first handler of a line installs a small stub
+/// on the vector the active interrupt controller delivers the line to and unmasks it, the last
+/// one unregistered masks it again.
+///
+/// Lines may be shared, every handler of the line is called on each interrupt and reports
+/// whether it's device raised it. Interrupts nobody claimed are counted as unhandled. The end of
+/// interrupt is sent by the manager to the controller which is active at the moment, so handlers
+/// never talk to the PIC or the APIC themselves.
+///
+/// Handlers run with interrupts disabled and the line locked, so they must not register or
+/// unregister handlers of their own line.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::error::Error;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::kernel_components::arch_x86_64::interrupts::{
+    handler_functions::HandlerFunction, interrupt::InterruptStackFrame,
+    latency, softint, GateDescriptor, InterruptVector, INTERRUPT_DESCRIPTOR_TABLE,
+};
+use crate::kernel_components::drivers::interrupts::{with_controller, IntCtrlError};
+use crate::kernel_components::sync::IrqSpinlock;
+
+/// Amount of IRQ lines handled by the manager, which covers the ISA lines and the pins of the
+/// first IO APIC.
+pub const MAX_IRQS: usize = 24;
+
+/// Global IRQ manager.
+pub static IRQ_MANAGER: IrqManager = IrqManager::new();
+
+/// Defines an entry point, which dispatches the line to the registered handlers, for each line.
+macro_rules! irq_stubs {
+    ($($irq:literal),*) => {
+        [$({
+            unsafe extern "x86-interrupt" fn stub(_stack_frame: InterruptStackFrame) {
+                IRQ_MANAGER.dispatch($irq);
+            }
+            stub as HandlerFunction
+        }),*]
+    };
+}
+
+/// Entry points installed into the IDT, one for each line.
+const STUBS: [HandlerFunction; MAX_IRQS] = irq_stubs!(
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23
+);
+
+/// Result of a single handler of a possibly shared line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    /// The device of the handler raised the interrupt.
+    Handled,
+    /// The interrupt was raised by another device on the line.
+    Unhandled,
+}
+
+/// Error type of the IRQ manager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The line is not handled by the manager.
+    InvalidIrq(u8),
+    /// The interrupt controller is unable to deliver the line.
+    Controller(IntCtrlError),
+    /// The handler is not registered.
+    NotRegistered,
+}
+
+impl Display for IrqError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidIrq(irq) => write!(f, "IRQ {} is out of range 0..{}.", irq, MAX_IRQS),
+            Self::Controller(err) => write!(f, "Interrupt controller error: {:?}", err),
+            Self::NotRegistered => write!(f, "The handler is not registered."),
+        }
+    }
+}
+
+impl Error for IrqError {}
+
+/// Handle of a registered handler, used to unregister it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqHandle {
+    irq: u8,
+    id: usize,
+}
+
+impl IrqHandle {
+    /// Returns the line of the handler.
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+}
+
+/// Registered handler.
+struct IrqAction {
+    id: usize,
+    name: &'static str,
+    handler: Box<dyn FnMut() -> IrqReturn + Send>,
+}
+
+/// State of a single line.
+struct IrqLine {
+    actions: Vec<IrqAction>,
+    /// Vector on which the stub of the line is installed.
+    vector: Option<u8>,
+    count: u64,
+    unhandled: u64,
+}
+
+impl IrqLine {
+    const fn new() -> Self {
+        Self { actions: Vec::new(), vector: None, count: 0, unhandled: 0 }
+    }
+
+    /// Calls every handler of the line. Returns true if any of them claimed the interrupt.
+    fn handle(&mut self) -> bool {
+        self.count += 1;
+        let mut handled = false;
+        for action in self.actions.iter_mut() {
+            handled |= (action.handler)() == IrqReturn::Handled;
+        }
+        if !handled {
+            self.unhandled += 1;
+        }
+        handled
+    }
+}
+
+/// Registry of the handlers of each line.
+pub struct IrqManager {
+    lines: [IrqSpinlock<IrqLine>; MAX_IRQS],
+    next_id: AtomicUsize,
+}
+
+impl IrqManager {
+    /// Creates a manager without any handlers.
+    pub const fn new() -> Self {
+        Self {
+            lines: [const { IrqSpinlock::new(IrqLine::new()) }; MAX_IRQS],
+            next_id: AtomicUsize::new(1),
+        }
+    }
+
+    /// Registers the handler of the line.
+    ///
+    /// The line is routed and unmasked when the first handler is registered, so the interrupt
+    /// controller must be initialized at this point.
+    pub fn register<F>(&self, irq: u8, name: &'static str, handler: F) -> Result<IrqHandle, IrqError> where
+        F: FnMut() -> IrqReturn + Send + 'static,
+    {
+        let mut line = self.line(irq)?.lock();
+        if line.vector.is_none() {
+            let vector = InterruptVector::irq(irq).map_err(IrqError::Controller)?;
+            unsafe {
+                INTERRUPT_DESCRIPTOR_TABLE.push(vector, GateDescriptor::new_interrupt(STUBS[irq as usize]));
+            }
+            // Both controllers only deliver on vectors of the IDT.
+            line.vector = u8::try_from(vector).ok();
+        }
+
+        // The handler is only kept once the line is unmasked, so a failed unmask is retried by the
+        // next registration. The interrupt can not be dispatched before the line is unlocked.
+        if line.actions.is_empty() {
+            with_controller(|ctrl| ctrl.unmask(irq))
+                .and_then(|result| result)
+                .map_err(IrqError::Controller)?;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        line.actions.push(IrqAction { id, name, handler: Box::new(handler) });
+        Ok(IrqHandle { irq, id })
+    }
+
+    /// Unregisters the handler. The line is masked once no handlers are left.
+    pub fn unregister(&self, handle: IrqHandle) -> Result<(), IrqError> {
+        let mut line = self.line(handle.irq)?.lock();
+        let position = line.actions.iter()
+            .position(|action| action.id == handle.id)
+            .ok_or(IrqError::NotRegistered)?;
+        line.actions.remove(position);
+
+        if line.actions.is_empty() {
+            let _ = with_controller(|ctrl| ctrl.mask(handle.irq));
+        }
+        Ok(())
+    }
+
+    /// Returns the names of the handlers registered on the line.
+    pub fn handlers(&self, irq: u8) -> Vec<&'static str> {
+        self.line(irq)
+            .map(|line| line.lock().actions.iter().map(|action| action.name).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the amount of interrupts received and the amount of those not claimed by any
+    /// handler on the line.
+    pub fn stats(&self, irq: u8) -> (u64, u64) {
+        self.line(irq)
+            .map(|line| {
+                let line = line.lock();
+                (line.count, line.unhandled)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Calls the handlers of the line and signals the end of interrupt.
+    ///
+    /// Called by the stubs installed into the IDT.
+    pub fn dispatch(&self, irq: u8) {
+        let entry = latency::enter();
+        let Ok(line) = self.line(irq) else { return };
+        let mut line = line.lock();
+        let Some(vector) = line.vector else { return };
+
+        // No end of interrupt must be sent for spurious interrupts.
+        if with_controller(|ctrl| ctrl.is_spurious(vector)) == Ok(true) {
+            return
+        }
+        // Wakes the threads halted until this interrupt.
+        unsafe { INTERRUPT_DESCRIPTOR_TABLE.with_int(vector, |bit| *bit = true) };
+
+        line.handle();
+        drop(line);
+
+        let _ = with_controller(|ctrl| ctrl.end_of_interrupt(vector));
+        latency::exit(vector, entry);
+        softint::deliver_pending();
+    }
+
+    fn line(&self, irq: u8) -> Result<&IrqSpinlock<IrqLine>, IrqError> {
+        self.lines.get(irq as usize).ok_or(IrqError::InvalidIrq(irq))
+    }
+}
+
+#[test_case]
+fn shared_line_calls_every_handler() {
+    use alloc::sync::Arc;
+    use core::sync::atomic::AtomicU32;
+
+    let calls = Arc::new(AtomicU32::new(0));
+    let mut line = IrqLine::new();
+    for claim in [IrqReturn::Unhandled, IrqReturn::Handled] {
+        let calls = calls.clone();
+        line.actions.push(IrqAction {
+            id: 0,
+            name: "test",
+            handler: Box::new(move || { calls.fetch_add(1, Ordering::Relaxed); claim }),
+        });
+    }
+
+    assert!(line.handle());
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+    line.actions.pop();
+    assert!(!line.handle());
+    assert_eq!((line.count, line.unhandled), (2, 1));
+
+    assert_eq!(IRQ_MANAGER.register(MAX_IRQS as u8, "test", || IrqReturn::Handled).err(), Some(IrqError::InvalidIrq(MAX_IRQS as u8)));
+    assert_eq!(IRQ_MANAGER.unregister(IrqHandle { irq: 3, id: 0 }), Err(IrqError::NotRegistered));
+}
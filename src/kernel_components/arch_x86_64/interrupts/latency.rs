@@ -0,0 +1,123 @@
+/// Interrupt handler latency instrumentation.
+///
+/// When the `irq_latency` feature is enabled, every predefined interrupt handler takes a TSC
+/// timestamp on entry and on exit. The amount of cycles spent inside the handler is accumulated
+/// per vector, so long critical sections within the handlers become visible as growing maximum
+/// values in the [`print_irq_latency!`] report.
+///
+/// Without the feature [`enter`] and [`exit`] compile to nothing.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::println;
+
+use super::InterruptVector;
+
+/// Latency statistics of a single vector.
+pub struct VectorLatency {
+    count: AtomicU64,
+    total: AtomicU64,
+    max: AtomicU64,
+}
+
+impl VectorLatency {
+    const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    /// Amount of measured handler invocations.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Mean amount of TSC cycles spent inside the handler.
+    pub fn mean(&self) -> u64 {
+        self.total.load(Ordering::Relaxed) / self.count().max(1)
+    }
+
+    /// Maximal amount of TSC cycles spent inside the handler.
+    pub fn max(&self) -> u64 {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, cycles: u64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(cycles, Ordering::Relaxed);
+        self.max.fetch_max(cycles, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.total.store(0, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Statistics for each of the 256 vectors.
+static LATENCY: [VectorLatency; 256] = [const { VectorLatency::new() }; 256];
+
+/// Must be called at the very beginning of the interrupt handler.
+///
+/// Returns the entry timestamp which must be passed to [`exit`].
+#[inline(always)]
+pub fn enter() -> u64 {
+    #[cfg(feature = "irq_latency")] {
+        unsafe { core::arch::x86_64::_rdtsc() }
+    }
+    #[cfg(not(feature = "irq_latency"))] {
+        0
+    }
+}
+
+/// Must be called right before returning from the interrupt handler.
+#[inline(always)]
+pub fn exit(vector: u8, entry: u64) {
+    #[cfg(feature = "irq_latency")] {
+        let now = unsafe { core::arch::x86_64::_rdtsc() };
+        LATENCY[vector as usize].record(now.wrapping_sub(entry));
+    }
+}
+
+/// Returns the statistics of the provided vector.
+pub fn latency(vector: u8) -> &'static VectorLatency {
+    &LATENCY[vector as usize]
+}
+
+/// Clears statistics of all vectors.
+pub fn reset() {
+    LATENCY.iter().for_each(VectorLatency::reset);
+}
+
+#[doc(hidden)]
+pub fn _print_irq_latency() {
+    if cfg!(not(feature = "irq_latency")) {
+        return println!("IRQ latency instrumentation is disabled (enable the 'irq_latency' feature).");
+    }
+
+    println!("{:>6} {:<14} {:>10} {:>12} {:>12}", "VECTOR", "NAME", "COUNT", "MEAN (cyc)", "MAX (cyc)");
+    for (vector, stats) in LATENCY.iter().enumerate().filter(|(_, s)| s.count() != 0) {
+        let name = InterruptVector::from(vector as u8).name();
+        println!("{:>6} {:<14} {:>10} {:>12} {:>12}", vector, name, stats.count(), stats.mean(), stats.max());
+    }
+}
+
+/// Prints the per vector interrupt handler latency report.
+#[macro_export]
+macro_rules! print_irq_latency {
+    () => ($crate::kernel_components::arch_x86_64::interrupts::latency::_print_irq_latency());
+}
+
+#[test_case]
+fn latency_statistics() {
+    let stats = VectorLatency::new();
+    stats.record(10);
+    stats.record(30);
+
+    assert_eq!(stats.count(), 2);
+    assert_eq!(stats.mean(), 20);
+    assert_eq!(stats.max(), 30);
+}
@@ -0,0 +1,140 @@
+//! Software interrupts raised to defer work into interrupt context.
+//!
+//! [`raise_softint`] makes the vector pending on the running processor. With the local APIC
+//! enabled, a self IPI is sent: the vector is latched in the request register and delivered once
+//! interrupts are enabled and it's priority class (the high nibble of the vector) is above the
+//! processor priority, exactly like a device interrupt. Raising it from a handler of a higher
+//! priority therefore runs the work right after that handler returns.
+//!
+//! Without the local APIC, the vector is raised with INTn if interrupts are enabled. Otherwise it
+//! is remembered and raised once the outermost critical section ends, or once the IRQ being
+//! handled received it's end of interrupt. Pending vectors are raised from the highest one, but
+//! a running handler is never preempted by a higher vector in this mode.
+//!
+//! Handlers of software interrupts must finish with [`end_of_softint`] instead of the end of
+//! interrupt of the IRQ controller.
+
+use core::fmt::Display;
+use core::error::Error;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::kernel_components::arch_x86_64::controllers::apic::{DeliveryMode, LOCAL_APIC};
+use crate::kernel_components::arch_x86_64::smp;
+
+use super::interrupt::{self, InterruptVector};
+
+/// Vectors waiting for interrupts to be enabled, one bit for each. Only used without the local
+/// APIC, so only the bootstrap processor raises them.
+static PENDING: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+/// Set while any bit of [`PENDING`] is, so the fast path only reads a single value.
+static ANY_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Custom error type for software interrupts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftintError {
+    /// Exceptions and reserved vectors can not be raised.
+    ReservedVector(u8),
+    /// The processor is not online.
+    InvalidCpu(usize),
+    /// Other processors can not be interrupted without the local APIC.
+    Unsupported,
+}
+
+impl Display for SoftintError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::ReservedVector(vector) => write!(f, "Vector {:#x} is reserved for exceptions.", vector),
+            Self::InvalidCpu(cpu) => write!(f, "Processor {} is not online.", cpu),
+            Self::Unsupported => write!(f, "Interprocessor interrupts require the local APIC."),
+        }
+    }
+}
+
+impl Error for SoftintError {}
+
+fn check_vector(vector: u8) -> Result<(), SoftintError> {
+    match (vector as usize) < InterruptVector::FIRST_INTERRUPT {
+        true => Err(SoftintError::ReservedVector(vector)),
+        false => Ok(()),
+    }
+}
+
+/// Raises the software interrupt on the running processor.
+///
+/// The handler runs in interrupt context as soon as the priority of the vector allows it, which
+/// might be before this function returns.
+pub fn raise_softint(vector: u8) -> Result<(), SoftintError> {
+    check_vector(vector)?;
+
+    if LOCAL_APIC.is_enabled() {
+        LOCAL_APIC.send_self_ipi(vector);
+        return Ok(())
+    }
+
+    PENDING[vector as usize / 64].fetch_or(1 << (vector % 64), Ordering::AcqRel);
+    ANY_PENDING.store(true, Ordering::Release);
+    if interrupt::is_interrupts_enabled() {
+        deliver_pending();
+    }
+    Ok(())
+}
+
+/// Raises the software interrupt on another processor, which is used for notifications.
+pub fn raise_softint_on(cpu: usize, vector: u8) -> Result<(), SoftintError> {
+    check_vector(vector)?;
+    if cpu >= smp::cpu_count().max(1) {
+        return Err(SoftintError::InvalidCpu(cpu))
+    }
+    if cpu == smp::cpu_id() {
+        return raise_softint(vector)
+    }
+    if !LOCAL_APIC.is_enabled() {
+        return Err(SoftintError::Unsupported)
+    }
+
+    LOCAL_APIC.send_ipi(smp::apic_id(cpu), DeliveryMode::Fixed, vector);
+    Ok(())
+}
+
+/// Must be called by the handlers of software interrupts once they are done.
+pub fn end_of_softint() {
+    // Vectors raised with INTn are never seen by any controller.
+    if LOCAL_APIC.is_enabled() {
+        LOCAL_APIC.end_of_interrupt();
+    }
+}
+
+/// Checks if the vector is waiting to be raised.
+pub fn is_pending(vector: u8) -> bool {
+    PENDING[vector as usize / 64].load(Ordering::Acquire) & 1 << (vector % 64) != 0
+}
+
+/// Raises the pending vectors, starting with the highest one.
+///
+/// Called whenever interrupts may become deliverable again. Does nothing if no vector is pending.
+#[inline]
+pub fn deliver_pending() {
+    if !ANY_PENDING.swap(false, Ordering::AcqRel) {
+        return
+    }
+
+    for (word, pending) in PENDING.iter().enumerate().rev() {
+        let mut bits = pending.swap(0, Ordering::AcqRel);
+        while bits != 0 {
+            let bit = 63 - bits.leading_zeros() as usize;
+            bits &= !(1 << bit);
+            unsafe { interrupt::cause_interrupt_unsafe((word * 64 + bit) as u8) };
+        }
+    }
+}
+
+#[test_case]
+fn softint_vectors_are_checked() {
+    assert_eq!(raise_softint(0xe), Err(SoftintError::ReservedVector(0xe)));
+    assert_eq!(raise_softint_on(0, 0x1f), Err(SoftintError::ReservedVector(0x1f)));
+    assert_eq!(raise_softint_on(smp::MAX_CPUS, 0x40), Err(SoftintError::InvalidCpu(smp::MAX_CPUS)));
+
+    // Nothing is raised when no vector is pending.
+    assert!(!is_pending(0x40));
+    deliver_pending();
+}
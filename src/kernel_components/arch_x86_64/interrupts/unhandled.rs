@@ -0,0 +1,165 @@
+//! Default gate of the vectors without a handler.
+//!
+//! [`IDT::fill_unhandled`] installs a stub into every slot of the table without a gate. Without
+//! it, a stray interrupt or an exception nobody handles raises a general protection fault on the
+//! not present gate, which quickly turns into a double fault.
+//!
+//! Each stub pushes it's vector and jumps to the common part, which saves the scratch registers
+//! and calls [`unhandled_vector`]. Exceptions are fatal, while interrupts are counted, reported
+//! once per vector and ignored. No end of interrupt is sent, as it is unknown which controller,
+//! if any, delivered the interrupt.
+//!
+//! [`IDT::fill_unhandled`]: super::IDT::fill_unhandled
+
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{critical_section, debug, log, println, warn, VirtualAddress};
+
+use super::handler_functions::{ErrorCode, ExceptionFrame, HandlerFunction, InterruptStackFrame};
+use super::GateDescriptor;
+
+/// Distance between the stubs of two neighbouring vectors.
+const STUB_SIZE: usize = 16;
+/// Vectors below are the exceptions of the processor.
+const FIRST_INTERRUPT: u8 = 0x20;
+
+/// Amount of unhandled interrupts received on each vector.
+static UNHANDLED: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+extern "C" {
+    /// First of the stubs, which are [`STUB_SIZE`] bytes apart.
+    static unhandled_vector_stubs: u8;
+}
+
+// A stub is a push of it's vector as an imm32, encoded by hand, and a jump to the common part.
+// The common part saves the registers clobbered by a call, aligns the stack and calls the
+// handler with the address of the pushed vector. If the handler returns, the vector is dropped
+// and the interrupted code continues.
+global_asm!(
+    ".pushsection .text.unhandled_vectors, \"ax\"",
+    ".global unhandled_vector_stubs",
+    ".balign 16",
+    "unhandled_vector_stubs:",
+    ".set unhandled_vector, 0",
+    ".rept 256",
+    "    .balign 16",
+    "    .byte 0x68",
+    "    .long unhandled_vector",
+    "    jmp unhandled_vector_common",
+    "    .set unhandled_vector, unhandled_vector + 1",
+    ".endr",
+    "unhandled_vector_common:",
+    "    push rax",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push rbx",
+    "    lea rdi, [rsp + 80]",
+    "    mov rbx, rsp",
+    "    and rsp, -16",
+    "    cld",
+    "    call {handler}",
+    "    mov rsp, rbx",
+    "    pop rbx",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rax",
+    "    add rsp, 8",
+    "    iretq",
+    ".popsection",
+    handler = sym unhandled_vector,
+);
+
+/// Returns the address of the stub of the vector.
+fn stub(vector: u8) -> VirtualAddress {
+    unsafe { &unhandled_vector_stubs as *const u8 as usize + vector as usize * STUB_SIZE }
+}
+
+/// Returns the gate which reports the vector as unhandled.
+pub fn gate(vector: u8) -> GateDescriptor {
+    // The stub is entered with the frame on the stack, exactly as the handler type expects.
+    let handler = unsafe { core::mem::transmute::<VirtualAddress, HandlerFunction>(stub(vector)) };
+    GateDescriptor::new_interrupt(handler)
+}
+
+/// Checks if the address is the entry of one of the stubs.
+pub fn is_stub(addr: VirtualAddress) -> bool {
+    let first = stub(0);
+    (first..=stub(u8::MAX)).contains(&addr) && (addr - first) % STUB_SIZE == 0
+}
+
+/// Returns the amount of unhandled interrupts received on the vector.
+pub fn unhandled_count(vector: u8) -> u64 {
+    UNHANDLED[vector as usize].load(Ordering::Relaxed)
+}
+
+/// Checks if the processor pushes an error code for the exception.
+const fn pushes_error_code(vector: u8) -> bool {
+    matches!(vector, 0x8 | 0xa..=0xe | 0x11 | 0x15 | 0x1d | 0x1e)
+}
+
+/// Reports the vector. Never returns for exceptions.
+///
+/// The stack points to the vector pushed by the stub, which is followed by the error code, if
+/// any, and the frame pushed by the processor.
+extern "C" fn unhandled_vector(stack: *const u64) {
+    let vector = unsafe { *stack } as u8;
+    let frame = unsafe {
+        match pushes_error_code(vector) {
+            true => ExceptionFrame::with_error_code(*(stack.add(2) as *const InterruptStackFrame), ErrorCode(*stack.add(1))),
+            false => ExceptionFrame::new(*(stack.add(1) as *const InterruptStackFrame)),
+        }
+    };
+
+    if vector < FIRST_INTERRUPT {
+        critical_section!(|| {
+            log!(Error; "EXCEPTION: Unhandled exception on vector {:#x}", vector);
+            if let Some(error_code) = frame.error_code {
+                println!("Error code: {:#x}", error_code.0);
+            }
+            debug!("{:#?}", frame.frame);
+        });
+        loop {}
+    }
+
+    if UNHANDLED[vector as usize].fetch_add(1, Ordering::Relaxed) == 0 {
+        warn!(
+            "Unhandled interrupt on vector {:#x} at {:#x}, further ones are only counted.",
+            vector, frame.instruction_pointer
+        );
+    }
+}
+
+#[test_case]
+fn every_slot_gets_a_gate() {
+    use alloc::boxed::Box;
+    use super::{IDT, InterruptVector, interrupt_descriptor_table::DYNAMIC_VECTORS};
+
+    let mut idt = Box::new(IDT::new_empty());
+    idt.push(InterruptVector::Custom(0x40), GateDescriptor::new_interrupt(super::def_exceptions::NMI));
+    idt.fill_unhandled();
+
+    assert!((0..=u8::MAX).all(|vector| idt.is_present(vector)));
+    assert!(is_stub(idt[0x3].handler_addr()) && !is_stub(idt[0x40].handler_addr()));
+    assert_eq!(idt[0xff].handler_addr(), stub(0) + 0xff * STUB_SIZE);
+
+    // Stubs do not count as handlers, so the dynamic vectors are still handed out.
+    let vector = idt.allocate_vector("msi").unwrap();
+    assert_eq!(vector, *DYNAMIC_VECTORS.start());
+    assert_eq!(idt.allocate_vector("ipi"), Ok(vector + 1));
+    idt.push(InterruptVector::Custom(vector as usize), GateDescriptor::new_interrupt(super::def_exceptions::NMI));
+    assert_eq!(idt.free_vector(vector), Ok(()));
+    assert!(is_stub(idt[vector as usize].handler_addr()));
+}
@@ -0,0 +1,187 @@
+/// Module for PCI configuration space access via legacy I/O mechanism #1.
+
+use alloc::vec::Vec;
+
+use crate::kernel_components::arch_x86_64::ports::{GenericPort, PortAccessType};
+use crate::critical_section;
+
+/// Port used to select the configuration space address.
+const CONFIG_ADDRESS: GenericPort<u32> = GenericPort::new(0xcf8, PortAccessType::WRITEONLY);
+/// Port used to read or write the selected configuration space dword.
+const CONFIG_DATA: GenericPort<u32> = GenericPort::new(0xcfc, PortAccessType::READWRITE);
+
+/// Location of a single PCI function on the bus.
+///
+/// Configuration space of each function is accessed through the 0xCF8/0xCFC port pair, which
+/// is available on every PC compatible chipset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciDevice {
+    /// Creates a new reference to PCI function at provided location.
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self { bus, device, function }
+    }
+
+    /// Reads the dword from the configuration space at provided offset.
+    ///
+    /// The offset is always aligned down to the dword boundary.
+    pub fn read(&self, offset: u8) -> u32 {
+        critical_section!(|| {
+            CONFIG_ADDRESS.write(self.address(offset));
+            CONFIG_DATA.read()
+        })
+    }
+
+    /// Writes the dword to the configuration space at provided offset.
+    ///
+    /// # Unsafe
+    ///
+    /// Writing configuration space may reconfigure the device in an unexpected way.
+    pub unsafe fn write(&self, offset: u8, value: u32) {
+        critical_section!(|| {
+            CONFIG_ADDRESS.write(self.address(offset));
+            CONFIG_DATA.write(value);
+        })
+    }
+
+    /// Returns the vendor id of the function. 0xffff means that no function is present.
+    pub fn vendor_id(&self) -> u16 {
+        self.read(0x00) as u16
+    }
+
+    /// Returns the device id of the function.
+    pub fn device_id(&self) -> u16 {
+        (self.read(0x00) >> 16) as u16
+    }
+
+    /// Returns the class code of the function.
+    pub fn class(&self) -> u8 {
+        (self.read(0x08) >> 24) as u8
+    }
+
+    /// Returns the subclass code of the function.
+    pub fn subclass(&self) -> u8 {
+        (self.read(0x08) >> 16) as u8
+    }
+
+    /// Returns the programming interface byte of the function.
+    pub fn prog_if(&self) -> u8 {
+        (self.read(0x08) >> 8) as u8
+    }
+
+    /// Returns the header type of the function without the multifunction bit.
+    pub fn header_type(&self) -> u8 {
+        ((self.read(0x0c) >> 16) as u8) & 0x7f
+    }
+
+    /// Returns true if the device contains more than one function.
+    pub fn is_multifunction(&self) -> bool {
+        (self.read(0x0c) >> 16) & 0x80 != 0
+    }
+
+    /// Returns the IRQ line configured by the firmware for this function.
+    pub fn interrupt_line(&self) -> u8 {
+        self.read(0x3c) as u8
+    }
+
+    /// Returns the raw value of the base address register with provided index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of range of general device header.
+    pub fn bar(&self, index: u8) -> u32 {
+        assert!(index < 6, "PCI device only has 6 BARs.");
+        self.read(0x10 + index * 4)
+    }
+
+    /// Returns the I/O port base from the BAR, if the BAR describes an I/O space.
+    pub fn io_bar(&self, index: u8) -> Option<u16> {
+        let bar = self.bar(index);
+        if bar & 1 == 1 {
+            Some((bar & !0b11) as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Enables I/O space decoding and bus mastering for the function.
+    ///
+    /// Bus mastering is required for all devices that perform DMA.
+    pub fn enable_bus_master(&self) {
+        let command = self.read(0x04);
+        unsafe { self.write(0x04, command | 0b101) }
+    }
+
+    /// Calculates the value for CONFIG_ADDRESS port.
+    fn address(&self, offset: u8) -> u32 {
+        1 << 31
+            | (self.bus as u32) << 16
+            | (self.device as u32 & 0x1f) << 11
+            | (self.function as u32 & 0x7) << 8
+            | (offset as u32 & 0xfc)
+    }
+}
+
+/// Brute-force scan of all PCI buses.
+///
+/// Returns every function that responded with a valid vendor id.
+pub fn scan() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            let dev = PciDevice::new(bus, device, 0);
+            if dev.vendor_id() == 0xffff {
+                continue
+            }
+            devices.push(dev);
+
+            if dev.is_multifunction() {
+                for function in 1..8u8 {
+                    let func = PciDevice::new(bus, device, function);
+                    if func.vendor_id() != 0xffff {
+                        devices.push(func);
+                    }
+                }
+            }
+        }
+    }
+
+    devices
+}
+
+/// Returns a short description of the class and subclass codes.
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, _) => "storage controller",
+        (0x02, _) => "network controller",
+        (0x03, _) => "display controller",
+        (0x04, 0x01) => "audio device",
+        (0x04, _) => "multimedia controller",
+        (0x05, _) => "memory controller",
+        (0x06, 0x00) => "host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "bridge",
+        (0x07, _) => "communication controller",
+        (0x08, _) => "system peripheral",
+        (0x0c, 0x03) => "USB controller",
+        (0x0c, 0x05) => "SMBus controller",
+        (0x0c, _) => "serial bus controller",
+        (0xff, _) => "vendor specific",
+        _ => "unclassified device",
+    }
+}
+
+/// Finds the first function with provided class and subclass codes.
+pub fn find_by_class(class: u8, subclass: u8) -> Option<PciDevice> {
+    scan().into_iter().find(|dev| dev.class() == class && dev.subclass() == subclass)
+}
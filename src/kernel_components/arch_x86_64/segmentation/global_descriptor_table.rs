@@ -13,11 +13,14 @@ pub use crate::kernel_components::registers::segment_regs::SegmentSelector;
 use crate::kernel_components::arch_x86_64::{
     PrivilegeLevel,
     DTPointer,
-    descriptor_table::{lgdt, sgdt},
+    descriptor_table::{lgdt, sgdt, assert_loaded, DescriptorTableType},
 };
+use crate::kernel_components::registers::segment_regs::{Segment, CodeSegment, StackSegment};
 
-use crate::{bitflags, single, VirtualAddress};
+use crate::{bitflags, critical_section, single, VirtualAddress};
 use super::task_state_segment::{TSS_SIZE, TSS};
+use core::error::Error;
+use core::fmt::Display;
 use core::ops::{Deref, Index};
 use core::arch::asm;
 use core::mem;
@@ -31,6 +34,36 @@ single!{
     pub mut GLOBAL_DESCRIPTOR_TABLE: GDT = GDT::new();
 }
 
+/// Segment registers checked against a new table before it replaces the loaded one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentRegister {
+    /// Must reference a present 64-bit code segment.
+    CS,
+    /// Must be null or reference a present writable data segment.
+    SS,
+    /// Must be null or reference a TSS descriptor.
+    TR,
+}
+
+/// Errors of the checked GDT replacement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GdtError {
+    /// The selector currently loaded into the register would not be valid in the new table.
+    InvalidSelector(SegmentRegister, SegmentSelector),
+}
+
+impl Display for GdtError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidSelector(register, selector) => write!(
+                f, "The loaded {:?} selector {:#x} does not reference a valid descriptor of the new table.", register, selector.0
+            ),
+        }
+    }
+}
+
+impl Error for GdtError {}
+
 /// Global Descriptor Table (GDT) implementation.
 /// 
 /// The GDT is pointed to by the value in the GDTR register. This is loaded using the 
@@ -87,10 +120,7 @@ impl GDT {
     /// Returns the current table as a 'DTPointer'.
     #[inline]
     pub fn as_dt_ptr(&self) -> DTPointer {
-        DTPointer {
-            size: (self.len * mem::size_of::<u64>() - 1) as u16,
-            addr: self.table.as_ptr() as u64,
-        }
+        DTPointer::new(self.table.as_ptr() as u64, self.len, mem::size_of::<u64>())
     }
 
     /// Returns the current table from the 'DTPointer'.
@@ -109,7 +139,9 @@ impl GDT {
     /// reloaded with those values, usually with kernel mode ones.
     #[inline]
     pub fn load_table(&'static self) {
-        unsafe { lgdt(&self.as_dt_ptr()) };
+        let ptr = self.as_dt_ptr();
+        unsafe { lgdt(&ptr) };
+        assert_loaded(DescriptorTableType::Gdt, ptr);
     }
 
     /// Reads the current table value from the CPU.
@@ -147,10 +179,85 @@ impl GDT {
     }
 
     /// Rewrites the current GDT to some pre-made value.
+    ///
+    /// Nothing is checked, if the table is loaded already, the next segment load or interrupt
+    /// may fault. Prefer [`GDT::replace`] for loaded tables.
     pub fn reinit(&mut self, gdt: GDT) {
         *self = gdt
     }
 
+    /// Replaces the table with the new one and loads it.
+    ///
+    /// The selectors currently loaded into CS, SS and TR are checked against the new table
+    /// first, the table is left untouched if any of them would become invalid. Otherwise the
+    /// table is loaded and CS and SS are reloaded with interrupts disabled, so the hidden parts
+    /// of the registers are refreshed from the new descriptors.
+    pub fn replace(&'static mut self, gdt: GDT) -> Result<(), GdtError> {
+        let cs = CodeSegment::read();
+        let ss = StackSegment::read();
+        gdt.check_selector(SegmentRegister::CS, cs)?;
+        gdt.check_selector(SegmentRegister::SS, ss)?;
+        gdt.check_selector(SegmentRegister::TR, TSS::read())?;
+
+        critical_section!(|| unsafe {
+            *self = gdt;
+            lgdt(&self.as_dt_ptr());
+            CodeSegment::write(cs);
+            StackSegment::write(ss);
+        });
+        assert_loaded(DescriptorTableType::Gdt, self.as_dt_ptr());
+        Ok(())
+    }
+
+    /// Checks if the selector may be loaded into the register with this table.
+    pub fn check_selector(&self, register: SegmentRegister, selector: SegmentSelector) -> Result<(), GdtError> {
+        let index = selector.get_index() as usize;
+        let invalid = Err(GdtError::InvalidSelector(register, selector));
+
+        if index == 0 {
+            return if register == SegmentRegister::CS { invalid } else { Ok(()) }
+        }
+        // LDT selectors are never used.
+        if selector.0 & 1 << 2 != 0 || index >= self.len {
+            return invalid
+        }
+
+        let entry = self.table[index];
+        let present = entry & 1 << 47 != 0;
+        let system = entry & 1 << 44 == 0;
+        let executable = entry & 1 << 43 != 0;
+        let valid = present && match register {
+            SegmentRegister::CS => !system && executable && entry & 1 << 53 != 0,
+            SegmentRegister::SS => !system && !executable && entry & 1 << 41 != 0,
+            // Available or busy 64-bit TSS.
+            SegmentRegister::TR => system && matches!(entry >> 40 & 0xf, 0x9 | 0xb),
+        };
+        if valid { Ok(()) } else { invalid }
+    }
+
+    /// Iterates over the used entries with their indices, starting with the null entry.
+    ///
+    /// Descriptors with a base address take two entries, the second one is the upper half of
+    /// the address.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.table[..self.len].iter().copied().enumerate()
+    }
+
+    /// Returns the amount of used entries, including the null entry.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Marks the TSS descriptor at the provided index as available again.
+    ///
+    /// Loading the task register sets the busy bit of the descriptor, and loading a busy TSS
+    /// faults. The bit must be cleared before the same TSS is loaded again, like after a wake up
+    /// from a sleep state.
+    pub fn clear_tss_busy(&mut self, index: usize) {
+        self.table[index] &= !(1 << 41);
+    }
+
     /// Returns the address of the GDT structure.
     pub fn addr(&'static self) -> usize {
         self as *const GDT as usize
@@ -557,3 +664,27 @@ fn initiating_flat_setup() {
 
     GLOBAL_DESC_TABLE.load_table();
 }
+
+#[test_case]
+fn loaded_selectors_are_checked() {
+    use crate::kernel_components::arch_x86_64::segmentation::TSS;
+
+    static TASK_STATE_SEGMENT: TSS = TSS::new();
+    let gdt = GDT::flat_setup(&TASK_STATE_SEGMENT);
+    let selector = |index| SegmentSelector::new(index, false, PrivilegeLevel::KernelLevel);
+
+    assert_eq!(gdt.entries().count(), gdt.len());
+    assert_eq!(gdt.entries().next(), Some((0, 0)));
+
+    assert_eq!(gdt.check_selector(SegmentRegister::CS, selector(1)), Ok(()));
+    assert_eq!(gdt.check_selector(SegmentRegister::SS, selector(0)), Ok(()));
+    assert_eq!(gdt.check_selector(SegmentRegister::SS, selector(2)), Ok(()));
+    assert_eq!(gdt.check_selector(SegmentRegister::TR, selector(5)), Ok(()));
+
+    for (register, index) in [(SegmentRegister::CS, 0), (SegmentRegister::CS, 2), (SegmentRegister::SS, 1), (SegmentRegister::TR, 1), (SegmentRegister::SS, 7)] {
+        assert_eq!(gdt.check_selector(register, selector(index)), Err(GdtError::InvalidSelector(register, selector(index))));
+    }
+
+    // The running code segment is valid in the table.
+    assert_eq!(gdt.check_selector(SegmentRegister::CS, CodeSegment::read()), Ok(()));
+}
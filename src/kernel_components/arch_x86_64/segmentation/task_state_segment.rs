@@ -7,10 +7,28 @@
 
 use crate::VirtualAddress;
 use crate::kernel_components::arch_x86_64::segmentation::SegmentSelector;
+use crate::kernel_components::arch_x86_64::descriptor_table::sgdt;
 use core::arch::asm;
+use core::fmt::Debug;
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use core::mem;
 
 pub const TSS_SIZE: u32 = mem::size_of::<TSS>() as u32;
+/// Amount of I/O ports, each one has a single bit in the I/O permission bitmap.
+pub const IO_PORTS: usize = 1 << 16;
+/// Size of the I/O permission bitmap in bytes.
+pub const IO_BITMAP_SIZE: usize = IO_PORTS / 8;
+/// Offset of the I/O permission bitmap from the base of the TSS.
+pub const IO_BITMAP_OFFSET: u16 = mem::offset_of!(TSS, io_bitmap) as u16;
+
+/// Key of the bitmap copied into the loaded TSS, which is the pid of it's process or
+/// [`NO_IO_BITMAP`] if every port is denied.
+static LOADED_IO_BITMAP: AtomicUsize = AtomicUsize::new(NO_IO_BITMAP);
+/// Key of the bitmap which denies every port.
+const NO_IO_BITMAP: usize = usize::MAX;
+/// Key which never matches, so the next switch copies the bitmap.
+const STALE_IO_BITMAP: usize = usize::MAX - 1;
 
 /// A struct representing the Task State Segment.
 /// 
@@ -39,7 +57,9 @@ pub const TSS_SIZE: u32 = mem::size_of::<TSS>() as u32;
 /// - I/O map base address field. Contains a 16-bit offset from the base of the TSS to the
 /// I/O Permission Bit Map.
 /// - Reserved fields that must take some place in memory. 
-#[derive(Debug, Clone, Copy)]
+/// - The I/O permission bitmap itself, which tells the ports code running with a privilege level
+/// above IOPL may access. The limit of the TSS descriptor covers it.
+#[derive(Clone, Copy)]
 #[repr(C, packed(4))]
 pub struct TSS {
     _reserved_1:                                        u32,
@@ -49,10 +69,14 @@ pub struct TSS {
     _reserved_3:                                        u64,
     _reserved_4:                                        u16,
     pub io_map_base_address_field:                      u16,
+    /// The processor may read one byte past the bitmap, which must have every bit set.
+    io_bitmap:                      [u8; IO_BITMAP_SIZE + 1],
 }
 
 impl TSS {
     /// Returns a new TSS with zeroed interrupt and privilege tables.
+    ///
+    /// Every I/O port is denied to the code running above IOPL.
     #[inline]
     pub const fn new() -> Self {
         Self {
@@ -62,10 +86,55 @@ impl TSS {
             interrupt_stack_pointers_table:                   [0; 7],
             _reserved_3:                                           0,
             _reserved_4:                                           0,
-            io_map_base_address_field: TSS_SIZE as u16,
+            io_map_base_address_field:              IO_BITMAP_OFFSET,
+            io_bitmap:                 [0xff; IO_BITMAP_SIZE + 1],
+        }
+    }
+
+    /// Copies the bitmap into the TSS, or denies every port with None.
+    pub fn set_io_bitmap(&mut self, bitmap: Option<&IoBitmap>) {
+        match bitmap {
+            Some(bitmap) => self.io_bitmap[..IO_BITMAP_SIZE].copy_from_slice(&bitmap.0),
+            None => self.io_bitmap[..IO_BITMAP_SIZE].fill(0xff),
         }
     }
 
+    /// Checks if code running above IOPL may access the port with this TSS.
+    #[inline]
+    pub fn io_allowed(&self, port: u16) -> bool {
+        self.io_bitmap[port as usize / 8] & 1 << (port % 8) == 0
+    }
+
+    /// Returns the task state segment loaded into TR on this processor.
+    ///
+    /// The base of the TSS is taken from it's descriptor in the loaded GDT. Returns None if no
+    /// TSS is loaded or it's limit is too short to cover the I/O permission bitmap.
+    ///
+    /// # Safety
+    ///
+    /// The TSS must have been loaded with a descriptor made by
+    /// [`SegmentDescriptor::tss_segment_descriptor`] and no other reference to it may be used
+    /// at the same time.
+    ///
+    /// [`SegmentDescriptor::tss_segment_descriptor`]: super::SegmentDescriptor::tss_segment_descriptor
+    pub unsafe fn current() -> Option<&'static mut TSS> {
+        let index = TSS::read().get_index() as usize;
+        let gdt = sgdt();
+        if index == 0 || (index + 2) * 8 > gdt.size as usize + 1 {
+            return None
+        }
+
+        let descriptor = gdt.addr as *const u64;
+        let (low, high) = unsafe { (*descriptor.add(index), *descriptor.add(index + 1)) };
+        let limit = (low & 0xffff) | (low >> 32 & 0xf_0000);
+        let base = (low >> 16 & 0xff_ffff) | (low >> 32 & 0xff00_0000) | high << 32;
+
+        if limit < TSS_SIZE as u64 - 1 {
+            return None
+        }
+        unsafe { (base as *mut TSS).as_mut() }
+    }
+
     /// Loads the task state segment selectors value into the TSS register (TR).
     /// 
     /// This operation marks the TSS segment in GDT as busy. This prevents other
@@ -88,9 +157,103 @@ impl TSS {
         let segment: u16;
 
         unsafe {
-            asm!("rtr {0:x}", out(reg) segment, options(nomem, nostack, preserves_flags));
+            asm!("str {0:x}", out(reg) segment, options(nomem, nostack, preserves_flags));
         }
 
         SegmentSelector(segment)
     }
-}
\ No newline at end of file
+}
+
+impl Debug for TSS {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (pst, ist, iomap) = (
+            self.privilege_stack_pointers_table,
+            self.interrupt_stack_pointers_table,
+            self.io_map_base_address_field,
+        );
+        f.debug_struct("TSS")
+            .field("privilege_stack_pointers_table", &pst)
+            .field("interrupt_stack_pointers_table", &ist)
+            .field("io_map_base_address_field", &iomap)
+            .field("allowed_ports", &(0..=u16::MAX).filter(|&port| self.io_allowed(port)).count())
+            .finish()
+    }
+}
+
+/// I/O ports which code of a process may access directly.
+///
+/// A set bit denies the port, like in the bitmap of the TSS. The bitmap of the scheduled process
+/// is copied into the TSS with [`switch_io_bitmap`].
+#[derive(Clone)]
+pub struct IoBitmap([u8; IO_BITMAP_SIZE]);
+
+impl IoBitmap {
+    /// Creates a bitmap which denies every port.
+    pub const fn new() -> Self {
+        Self([0xff; IO_BITMAP_SIZE])
+    }
+
+    /// Allows the range of ports.
+    pub fn allow(&mut self, ports: RangeInclusive<u16>) {
+        ports.for_each(|port| self.0[port as usize / 8] &= !(1 << (port % 8)));
+    }
+
+    /// Denies the range of ports.
+    pub fn deny(&mut self, ports: RangeInclusive<u16>) {
+        ports.for_each(|port| self.0[port as usize / 8] |= 1 << (port % 8));
+    }
+
+    /// Checks if the port is allowed.
+    #[inline]
+    pub fn is_allowed(&self, port: u16) -> bool {
+        self.0[port as usize / 8] & 1 << (port % 8) == 0
+    }
+}
+
+impl Debug for IoBitmap {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("IoBitmap")
+            .field("allowed_ports", &(0..=u16::MAX).filter(|&port| self.is_allowed(port)).count())
+            .finish()
+    }
+}
+
+/// Loads the I/O permission bitmap of the process into the TSS of this processor.
+///
+/// Called on task switches. The bitmap is only copied when it belongs to another process than
+/// the loaded one, processes without any bitmap share the one which denies every port.
+pub fn switch_io_bitmap(pid: usize, bitmap: Option<&IoBitmap>) {
+    let key = bitmap.map_or(NO_IO_BITMAP, |_| pid);
+    if LOADED_IO_BITMAP.load(Ordering::Relaxed) == key {
+        return
+    }
+    if let Some(tss) = unsafe { TSS::current() } {
+        tss.set_io_bitmap(bitmap);
+        LOADED_IO_BITMAP.store(key, Ordering::Relaxed);
+    }
+}
+
+/// Forces the next [`switch_io_bitmap`] to copy the bitmap, after the one of the process changed.
+pub fn invalidate_io_bitmap() {
+    LOADED_IO_BITMAP.store(STALE_IO_BITMAP, Ordering::Relaxed);
+}
+
+#[test_case]
+fn io_bitmap_covers_every_port() {
+    let mut bitmap = IoBitmap::new();
+    bitmap.allow(0x3f8..=0x3ff);
+    bitmap.allow(u16::MAX..=u16::MAX);
+    bitmap.deny(0x3fa..=0x3fa);
+    assert!(bitmap.is_allowed(0x3f8) && bitmap.is_allowed(0x3ff) && bitmap.is_allowed(u16::MAX));
+    assert!(!bitmap.is_allowed(0x3f7) && !bitmap.is_allowed(0x3fa) && !bitmap.is_allowed(0x400));
+
+    let mut tss = TSS::new();
+    assert_eq!({ tss.io_map_base_address_field }, 104);
+    assert!((0..=u16::MAX).all(|port| !tss.io_allowed(port)));
+    tss.set_io_bitmap(Some(&bitmap));
+    assert!(tss.io_allowed(0x3f9) && !tss.io_allowed(0x3fa));
+    // The byte after the bitmap keeps every bit set.
+    assert_eq!(tss.io_bitmap[IO_BITMAP_SIZE], 0xff);
+    tss.set_io_bitmap(None);
+    assert!(!tss.io_allowed(0x3f9));
+}
@@ -0,0 +1,250 @@
+/// TLB shootdown through interprocessor interrupts.
+///
+/// `invlpg` and CR3 reloads only invalidate the TLB of the running processor, while all
+/// processors share the kernel page tables. Once a mapping is removed or it's flags change,
+/// [`flush`] invalidates the page locally, queues the invalidation for every other online
+/// processor and sends them an IPI on [`SHOOTDOWN_VECTOR`]. The caller waits until each target
+/// drained it's queue, so the frame may be reused as soon as the call returns.
+///
+/// Every processor has it's own queue of up to [`QUEUE_LEN`] pages, a queue which overflows turns
+/// into a full flush. While waiting for the targets, the processor keeps draining it's own queue,
+/// so two processors shooting each other down with interrupts disabled do not deadlock. A target
+/// spinning on a lock held by the initiator with interrupts disabled still does.
+///
+/// Invalidations made within a [`Batch`] are sent in a single round once the batch is dropped,
+/// which is used when whole areas are unmapped.
+///
+/// Before the APs are started or with parked APs, only the online processors are interrupted.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::kernel_components::arch_x86_64::TLB;
+use crate::kernel_components::arch_x86_64::controllers::apic::{DeliveryMode, LOCAL_APIC};
+use crate::kernel_components::arch_x86_64::smp::{self, MAX_CPUS};
+use crate::kernel_components::memory::deferred;
+use crate::kernel_components::sync::IrqSpinlock;
+use crate::VirtualAddress;
+
+/// Vector of the shootdown IPI.
+pub const SHOOTDOWN_VECTOR: u8 = 0xf9;
+/// Amount of pages queued for a processor before a full flush is requested instead.
+pub const QUEUE_LEN: usize = 16;
+
+/// Pending invalidations of each processor.
+static QUEUES: [IrqSpinlock<Queue>; MAX_CPUS] = [const { IrqSpinlock::new(Queue::new()) }; MAX_CPUS];
+/// Invalidations collected by the open batch of each processor.
+static BATCHES: [IrqSpinlock<Option<Queue>>; MAX_CPUS] = [const { IrqSpinlock::new(None) }; MAX_CPUS];
+/// Bit mask of processors with a non empty queue.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+/// Amount of outer batches open on all processors.
+static OPEN_BATCHES: AtomicUsize = AtomicUsize::new(0);
+
+/// Invalidation requested from the other processors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invalidation {
+    /// Single page containing the address.
+    Page(VirtualAddress),
+    /// Every non global translation.
+    All,
+}
+
+/// Queue of pending invalidations.
+#[derive(Debug, Clone, Copy)]
+struct Queue {
+    pages: [VirtualAddress; QUEUE_LEN],
+    len: usize,
+    all: bool,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Self { pages: [0; QUEUE_LEN], len: 0, all: false }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0 && !self.all
+    }
+
+    fn push(&mut self, invalidation: Invalidation) {
+        match invalidation {
+            Invalidation::Page(addr) if !self.all && self.len < QUEUE_LEN => {
+                self.pages[self.len] = addr;
+                self.len += 1;
+            },
+            _ => self.all = true,
+        }
+    }
+
+    fn append(&mut self, other: &Queue) {
+        match other.all {
+            true => self.all = true,
+            false => other.pages[..other.len].iter().for_each(|&addr| self.push(Invalidation::Page(addr))),
+        }
+    }
+
+    /// Invalidates the queued translations on the running processor.
+    fn apply(&self) {
+        match self.all {
+            true => TLB::flush_all(),
+            false => self.pages[..self.len].iter().for_each(|&addr| TLB::flush(addr)),
+        }
+    }
+}
+
+/// Collects the invalidations of the running processor until it is dropped, then shoots them
+/// down in a single round.
+///
+/// The local TLB is still flushed at once. Nested batches are merged into the outer one. Other
+/// processors keep using the old translations until then, so the frames unmapped within a batch
+/// are freed with [`MMU::deallocate_frame_deferred`].
+///
+/// [`MMU::deallocate_frame_deferred`]: crate::kernel_components::memory::MMU::deallocate_frame_deferred
+pub struct Batch {
+    cpu: usize,
+    outer: bool,
+}
+
+impl Batch {
+    /// Opens a batch on the running processor.
+    pub fn begin() -> Self {
+        let cpu = smp::cpu_id();
+        let mut batch = BATCHES[cpu].lock();
+        let outer = batch.is_none();
+        if outer {
+            OPEN_BATCHES.fetch_add(1, Ordering::AcqRel);
+            *batch = Some(Queue::new());
+        }
+        Self { cpu, outer }
+    }
+
+    /// Checks if any processor has a batch open, which invalidations are not sent yet.
+    pub fn any_open() -> bool {
+        OPEN_BATCHES.load(Ordering::Acquire) != 0
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        if !self.outer {
+            return
+        }
+        if let Some(queue) = BATCHES[self.cpu].lock().take().filter(|queue| !queue.is_empty()) {
+            send(self.cpu, &queue);
+        }
+        OPEN_BATCHES.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Invalidates the page containing the address on every online processor.
+pub fn flush(addr: VirtualAddress) {
+    shootdown(Invalidation::Page(addr))
+}
+
+/// Invalidates every non global translation on every online processor.
+pub fn flush_all() {
+    shootdown(Invalidation::All)
+}
+
+/// Invalidates every non global translation on every online processor without waiting for the
+/// open batch of the running processor.
+///
+/// Translations left behind by the open batches of any processor are gone once it returns.
+pub fn flush_all_now() {
+    TLB::flush_all();
+    if smp::cpu_count() == 1 {
+        return
+    }
+    let mut queue = Queue::new();
+    queue.push(Invalidation::All);
+    send(smp::cpu_id(), &queue);
+}
+
+/// Applies the invalidations queued for the running processor.
+///
+/// Must be called by loops waiting for the other processors with interrupts disabled.
+pub fn poll() {
+    drain(smp::cpu_id())
+}
+
+/// Invalidates the translation locally and on the other online processors.
+pub fn shootdown(invalidation: Invalidation) {
+    match invalidation {
+        Invalidation::Page(addr) => TLB::flush(addr),
+        Invalidation::All => TLB::flush_all(),
+    }
+    if smp::cpu_count() == 1 {
+        return
+    }
+
+    let cpu = smp::cpu_id();
+    if let Some(batch) = BATCHES[cpu].lock().as_mut() {
+        return batch.push(invalidation)
+    }
+    let mut queue = Queue::new();
+    queue.push(invalidation);
+    send(cpu, &queue);
+}
+
+/// Must be called from the handler of [`SHOOTDOWN_VECTOR`].
+pub fn handle_interrupt() {
+    drain(smp::cpu_id());
+    deferred::quiescent();
+}
+
+/// Queues the invalidations for every other online processor and waits until they are applied.
+fn send(cpu: usize, queue: &Queue) {
+    let targets = (0..smp::cpu_count())
+        .filter(|&target| target != cpu && !smp::is_parked(target))
+        .fold(0u64, |mask, target| mask | 1 << target);
+    if targets == 0 {
+        return
+    }
+
+    for target in (0..MAX_CPUS).filter(|target| targets & 1 << target != 0) {
+        // The bit is set under the lock, so the target never clears it before the queue is applied.
+        let mut pending = QUEUES[target].lock();
+        pending.append(queue);
+        PENDING.fetch_or(1 << target, Ordering::AcqRel);
+        drop(pending);
+        LOCAL_APIC.send_ipi(smp::apic_id(target), DeliveryMode::Fixed, SHOOTDOWN_VECTOR);
+    }
+
+    while PENDING.load(Ordering::Acquire) & targets != 0 {
+        drain(cpu);
+        core::hint::spin_loop();
+    }
+}
+
+/// Applies the queued invalidations of the processor.
+fn drain(cpu: usize) {
+    if PENDING.load(Ordering::Acquire) & 1 << cpu == 0 {
+        return
+    }
+    let mut pending = QUEUES[cpu].lock();
+    pending.apply();
+    *pending = Queue::new();
+    PENDING.fetch_and(!(1 << cpu), Ordering::AcqRel);
+}
+
+#[test_case]
+fn queue_overflows_into_full_flush() {
+    let mut queue = Queue::new();
+    assert!(queue.is_empty());
+    for page in 0..QUEUE_LEN {
+        queue.push(Invalidation::Page(page * 0x1000));
+    }
+    assert!(!queue.all);
+    assert_eq!(queue.len, QUEUE_LEN);
+
+    let mut merged = Queue::new();
+    merged.push(Invalidation::Page(0xdead_0000));
+    merged.append(&queue);
+    assert!(merged.all);
+
+    // A single processor only flushes it's own TLB, the open batch stays empty.
+    let batch = Batch::begin();
+    flush(0xdead_0000);
+    assert!(BATCHES[smp::cpu_id()].lock().is_some_and(|queue| queue.is_empty()));
+    drop(batch);
+    assert!(BATCHES[smp::cpu_id()].lock().is_none());
+}
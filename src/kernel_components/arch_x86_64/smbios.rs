@@ -0,0 +1,324 @@
+/// Module for reading the System Management BIOS tables.
+///
+/// SMBIOS describes the machine as the firmware sees it: vendor and model of the system and the
+/// board, BIOS version and installed memory modules. The entry point is either copied by the
+/// bootloader into the multiboot structure or found in the BIOS area below 1 MiB. The structure
+/// table itself is a list of formatted structures, each followed by a set of strings.
+
+use alloc::string::String;
+
+use crate::kernel_components::memory::tags::{TagTrait, TagType, TagTypeId, Tag};
+use crate::kernel_components::memory::MEMORY_MANAGEMENT_UNIT;
+use crate::PhysicalAddress;
+
+/// Area of the BIOS ROM, which is searched for the entry point on legacy systems.
+const BIOS_AREA_START: PhysicalAddress = 0xf0000;
+const BIOS_AREA_END: PhysicalAddress = 0x100000;
+
+/// Type of the structure, which marks the end of the table.
+const END_OF_TABLE: u8 = 127;
+
+/// Structure types used by the kernel.
+pub const BIOS_INFORMATION: u8 = 0;
+pub const SYSTEM_INFORMATION: u8 = 1;
+pub const BASEBOARD_INFORMATION: u8 = 2;
+pub const PROCESSOR_INFORMATION: u8 = 4;
+pub const MEMORY_DEVICE: u8 = 17;
+
+/// Location of the structure table obtained from the entry point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryPoint {
+    /// SMBIOS version in (major, minor) format.
+    pub version: (u8, u8),
+    /// Physical address of the structure table.
+    pub table: PhysicalAddress,
+    /// Length of the table. For SMBIOS 3 it is only the maximal length.
+    pub length: usize,
+}
+
+impl EntryPoint {
+    /// Parses the 32-bit ("_SM_") or 64-bit ("_SM3_") entry point and verifies it's checksum.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let read = |at: usize, len: usize| -> Option<u64> {
+            let field = bytes.get(at..at + len)?;
+            Some(field.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64))
+        };
+        let checksum = |len: usize| -> Option<bool> {
+            Some(bytes.get(..len)?.iter().fold(0u8, |s, b| s.wrapping_add(*b)) == 0)
+        };
+
+        if bytes.starts_with(b"_SM3_") {
+            let len = *bytes.get(6)? as usize;
+            if len < 24 || !checksum(len)? {
+                return None
+            }
+            Some(Self {
+                version: (bytes[7], bytes[8]),
+                table: read(16, 8)? as PhysicalAddress,
+                length: read(12, 4)? as usize,
+            })
+        } else if bytes.starts_with(b"_SM_") {
+            let len = *bytes.get(5)? as usize;
+            if len < 31 || !checksum(len)? || bytes.get(16..21)? != b"_DMI_" {
+                return None
+            }
+            Some(Self {
+                version: (bytes[6], bytes[7]),
+                table: read(24, 4)? as PhysicalAddress,
+                length: read(22, 2)? as usize,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Finds the entry point provided by the bootloader or searches the BIOS area.
+    pub fn find() -> Option<Self> {
+        let mmu = unsafe { &mut *MEMORY_MANAGEMENT_UNIT };
+        if let Some(entry) = mmu.boot_info().get_tag::<SmbiosTag>().and_then(|tag| Self::parse(tag.entry_point())) {
+            return Some(entry)
+        }
+
+        mmu.map_firmware(BIOS_AREA_START, BIOS_AREA_END).ok()?;
+        let area = unsafe {
+            core::slice::from_raw_parts(BIOS_AREA_START as *const u8, BIOS_AREA_END - BIOS_AREA_START)
+        };
+        // Entry point is always 16 byte aligned.
+        (0..area.len()).step_by(16).find_map(|at| Self::parse(&area[at..]))
+    }
+
+    /// Maps the structure table and returns it as bytes.
+    pub fn table(&self) -> Option<&'static [u8]> {
+        let end = self.table.checked_add(self.length)?;
+        unsafe { MEMORY_MANAGEMENT_UNIT.map_firmware(self.table, end) }.ok()?;
+        Some(unsafe { core::slice::from_raw_parts(self.table as *const u8, self.length) })
+    }
+}
+
+/// Single SMBIOS structure.
+#[derive(Debug, Clone, Copy)]
+pub struct Structure<'a> {
+    /// Type of the structure.
+    pub kind: u8,
+    /// Handle used by other structures to refer to this one.
+    pub handle: u16,
+    /// Formatted area including the header.
+    pub data: &'a [u8],
+    /// Unformatted area of null terminated strings.
+    strings: &'a [u8],
+}
+
+impl<'a> Structure<'a> {
+    /// Reads a byte from the formatted area.
+    pub fn byte(&self, offset: usize) -> Option<u8> {
+        self.data.get(offset).copied()
+    }
+
+    /// Reads a word from the formatted area.
+    pub fn word(&self, offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes(self.data.get(offset..offset + 2)?.try_into().ok()?))
+    }
+
+    /// Reads a dword from the formatted area.
+    pub fn dword(&self, offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(self.data.get(offset..offset + 4)?.try_into().ok()?))
+    }
+
+    /// Returns the string referenced by the byte at the offset. Strings are numbered from one,
+    /// zero means that no string is provided.
+    pub fn string(&self, offset: usize) -> Option<&'a str> {
+        let index = self.byte(offset)? as usize;
+        if index == 0 {
+            return None
+        }
+
+        let string = self.strings.split(|&b| b == 0).nth(index - 1)?;
+        core::str::from_utf8(string).ok().map(str::trim).filter(|s| !s.is_empty())
+    }
+}
+
+/// Iterator over the structures of the table. Stops at the end of table or at the first
+/// truncated structure.
+#[derive(Debug, Clone)]
+pub struct StructureIter<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StructureIter<'a> {
+    /// Creates the iterator over the table bytes.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+}
+
+impl<'a> Iterator for StructureIter<'a> {
+    type Item = Structure<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = *self.bytes.get(1)? as usize;
+        if len < 4 || len > self.bytes.len() {
+            self.bytes = &[];
+            return None
+        }
+
+        let (data, rest) = self.bytes.split_at(len);
+        // Strings end with a double null. A structure without strings still has two nulls.
+        let Some(end) = rest.windows(2).position(|w| w == [0, 0]) else {
+            self.bytes = &[];
+            return None
+        };
+        self.bytes = &rest[end + 2..];
+
+        let structure = Structure {
+            kind: data[0],
+            handle: u16::from_le_bytes([data[2], data[3]]),
+            data,
+            strings: &rest[..end],
+        };
+        if structure.kind == END_OF_TABLE {
+            self.bytes = &[];
+        }
+        Some(structure)
+    }
+}
+
+/// Summary of the system described by SMBIOS.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SmbiosInfo {
+    /// SMBIOS version in (major, minor) format.
+    pub version: (u8, u8),
+    pub bios_vendor: Option<String>,
+    pub bios_version: Option<String>,
+    pub system_vendor: Option<String>,
+    pub system_product: Option<String>,
+    pub board_vendor: Option<String>,
+    pub board_product: Option<String>,
+    pub processor: Option<String>,
+    /// Amount of populated memory slots.
+    pub memory_devices: usize,
+    /// Size of the installed memory in MiB.
+    pub memory_mib: usize,
+}
+
+impl SmbiosInfo {
+    /// Reads the SMBIOS tables of the machine. Returns None if there are no tables.
+    pub fn read() -> Option<Self> {
+        let entry = EntryPoint::find()?;
+        Some(Self::from_table(entry.version, entry.table()?))
+    }
+
+    /// Collects the summary from the structure table.
+    pub fn from_table(version: (u8, u8), table: &[u8]) -> Self {
+        let mut info = Self { version, ..Self::default() };
+        let owned = |s: Option<&str>| s.map(String::from);
+
+        for structure in StructureIter::new(table) {
+            match structure.kind {
+                BIOS_INFORMATION => {
+                    info.bios_vendor = owned(structure.string(0x04));
+                    info.bios_version = owned(structure.string(0x05));
+                },
+                SYSTEM_INFORMATION => {
+                    info.system_vendor = owned(structure.string(0x04));
+                    info.system_product = owned(structure.string(0x05));
+                },
+                BASEBOARD_INFORMATION => {
+                    info.board_vendor = owned(structure.string(0x04));
+                    info.board_product = owned(structure.string(0x05));
+                },
+                PROCESSOR_INFORMATION if info.processor.is_none() => {
+                    info.processor = owned(structure.string(0x10));
+                },
+                MEMORY_DEVICE => if let Some(size) = memory_device_size(&structure) {
+                    info.memory_devices += 1;
+                    info.memory_mib += size;
+                },
+                _ => (),
+            }
+        }
+        info
+    }
+}
+
+/// Returns the size of the memory device in MiB, or None if the slot is empty or unknown.
+fn memory_device_size(structure: &Structure) -> Option<usize> {
+    match structure.word(0x0c)? {
+        0 | 0xffff => None,
+        // Size does not fit, the extended size field holds it.
+        0x7fff => structure.dword(0x1c).map(|size| (size & 0x7fff_ffff) as usize),
+        // Bit 15 set means the size is in KiB.
+        size if size & 0x8000 != 0 => Some((size & 0x7fff) as usize / 1024),
+        size => Some(size as usize),
+    }
+}
+
+/// Tag which contains a copy of the SMBIOS entry point.
+///
+/// This is the tag from multiboot2 structure. Bootloaders provide it on UEFI systems, where the
+/// entry point is not located in the BIOS area.
+#[derive(Clone)]
+#[repr(C)]
+pub struct SmbiosTag {
+    tag_type: TagTypeId,
+    size: u32,
+    pub major: u8,
+    pub minor: u8,
+    _reserved: [u8; 6],
+}
+
+impl SmbiosTag {
+    /// Returns the copied entry point.
+    pub fn entry_point(&self) -> &[u8] {
+        &self.bytes()[core::mem::size_of::<Self>()..]
+    }
+}
+
+impl TagTrait for SmbiosTag {
+    const ID: TagType = TagType::Smbios;
+    fn dst_size(_: &Tag) -> Option<()> {
+        Some(())
+    }
+}
+
+#[test_case]
+fn smbios_structures_are_parsed() {
+    use alloc::vec::Vec;
+
+    let mut table = Vec::new();
+    // BIOS information with two strings.
+    table.extend_from_slice(&[0, 0x18, 0, 0, 1, 2]);
+    table.resize(0x18, 0);
+    table.extend_from_slice(b"SeaBIOS\0rel-1.16\0\0");
+    // System information without strings.
+    table.extend_from_slice(&[1, 0x08, 1, 0, 0, 0, 0, 0, 0, 0]);
+    // Two memory devices, 512 MiB and 2048 KiB, and an empty slot.
+    for size in [512u16, 0x8000 | 2048, 0] {
+        let start = table.len();
+        table.extend_from_slice(&[17, 0x22, 2, 0]);
+        table.resize(start + 0x22, 0);
+        table[start + 0x0c..start + 0x0e].copy_from_slice(&size.to_le_bytes());
+        table.extend_from_slice(&[0, 0]);
+    }
+    table.extend_from_slice(&[END_OF_TABLE, 4, 0xff, 0xff, 0, 0]);
+    // Nothing is read after the end of table.
+    table.extend_from_slice(&[0, 0x18, 0, 0]);
+
+    let info = SmbiosInfo::from_table((3, 0), &table);
+    assert_eq!(info.bios_vendor.as_deref(), Some("SeaBIOS"));
+    assert_eq!(info.bios_version.as_deref(), Some("rel-1.16"));
+    assert_eq!(info.system_vendor, None);
+    assert_eq!((info.memory_devices, info.memory_mib), (2, 514));
+    assert_eq!(StructureIter::new(&table).count(), 6);
+    assert_eq!(StructureIter::new(&table[..20]).count(), 0);
+
+    let mut entry = [0u8; 24];
+    entry[..5].copy_from_slice(b"_SM3_");
+    entry[6] = 24;
+    entry[7] = 3;
+    entry[12..16].copy_from_slice(&0x1000u32.to_le_bytes());
+    entry[16..24].copy_from_slice(&0xf_5000u64.to_le_bytes());
+    entry[5] = 0u8.wrapping_sub(entry.iter().fold(0u8, |s, b| s.wrapping_add(*b)));
+    assert_eq!(EntryPoint::parse(&entry), Some(EntryPoint { version: (3, 0), table: 0xf_5000, length: 0x1000 }));
+    entry[20] ^= 1;
+    assert_eq!(EntryPoint::parse(&entry), None);
+}
@@ -0,0 +1,452 @@
+/// Bringup of the application processors.
+///
+/// Only the bootstrap processor (BSP) runs the kernel after the boot, all other processors (APs)
+/// wait for an INIT-SIPI-SIPI sequence sent through the local APIC. The startup IPI starts an AP
+/// in real mode at a page below 1 MiB, so a small trampoline is copied there, which switches the
+/// processor to long mode with the page tables of the BSP and jumps into [`ap_entry`].
+///
+/// The trampoline page must be taken from the early boot memory with [`reserve_trampoline`]
+/// before the memory is initialized, because the frame allocator hands out the low memory later.
+/// Early allocations stay identity mapped, which the trampoline relies on when paging is enabled.
+///
+/// Every AP gets it's own stack, GDT and TSS with an interrupt stack, shares the IDT of the BSP
+/// and initializes it's local APIC before it parks in a HLT loop. Processors are started one by
+/// one, so the trampoline is reused.
+///
+/// An AP can be parked with [`park`], which sends it an INIT IPI. The processor then waits for
+/// the next startup IPI in it's lowest power state, [`unpark`] starts it again with the same
+/// stacks and tables.
+///
+/// The same trampoline brings the BSP back into long mode after a wake up from a sleep state,
+/// see [`prepare_wakeup`].
+///
+/// # Processor Ids
+///
+/// Processors are numbered in the order they came online, the BSP is always 0. [`cpu_id`] maps
+/// the local APIC id of the running processor to that index. Only the xAPIC ids of the MADT are
+/// used, processors described only by x2APIC entries are not started.
+
+use alloc::boxed::Box;
+use core::arch::{asm, global_asm};
+use core::arch::x86_64::_rdtsc;
+use core::fmt::Display;
+use core::error::Error;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
+
+use crate::kernel_components::arch_x86_64::PrivilegeLevel;
+use crate::kernel_components::arch_x86_64::acpi::madt::{MADT, MadtEntry};
+use crate::kernel_components::arch_x86_64::controllers::apic::{DeliveryMode, LOCAL_APIC, SPURIOUS_VECTOR};
+use crate::kernel_components::arch_x86_64::interrupts::{interrupt, INTERRUPT_DESCRIPTOR_TABLE};
+use crate::kernel_components::arch_x86_64::segmentation::{GDT, TSS};
+use crate::kernel_components::memory::{bootmem, MEMORY_MANAGEMENT_UNIT};
+use crate::kernel_components::memory::frames::PAGE_SIZE;
+use crate::kernel_components::memory::memory_module::MemError;
+use crate::kernel_components::registers::control::Cr3;
+use crate::kernel_components::registers::ms::Pat;
+use crate::kernel_components::registers::segment_regs::{CodeSegment, Segment, SegmentSelector, StackSegment};
+use crate::kernel_components::task_virtualization::IDLE_GOVERNOR;
+use crate::critical_section;
+
+/// Maximal amount of processors handled by the kernel.
+pub const MAX_CPUS: usize = 64;
+
+/// Size of the stack of each AP in pages.
+const AP_STACK_PAGES: usize = 4;
+/// Time the AP gets to come online after the startup IPIs in microseconds.
+const AP_TIMEOUT_US: u64 = 100_000;
+/// Offset of the data block within the trampoline page, which is filled before each startup.
+const DATA_OFFSET: usize = 0xf00;
+/// Flat segments used by the trampoline: 32-bit code, data and 64-bit code.
+const TRAMPOLINE_GDT: [u64; 4] = [0, 0x00cf_9a00_0000_ffff, 0x00cf_9200_0000_ffff, 0x00af_9a00_0000_ffff];
+
+/// Physical address of the trampoline page, zero if none is reserved.
+static TRAMPOLINE: AtomicUsize = AtomicUsize::new(0);
+/// Amount of processors online, including the BSP.
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+/// Local APIC ids of the online processors, indexed by the processor index.
+static APIC_IDS: [AtomicU8; MAX_CPUS] = [const { AtomicU8::new(0) }; MAX_CPUS];
+/// Stack tops of the APs, indexed by the processor index.
+static AP_STACKS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+/// GDT addresses of the APs, indexed by the processor index.
+static AP_GDTS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+/// Bit mask of parked processors.
+static PARKED: AtomicU64 = AtomicU64::new(0);
+/// Set by the AP being started, once it is ready.
+static AP_READY: AtomicBool = AtomicBool::new(false);
+
+/// Error type for the processor bringup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmpError {
+    /// No MADT describes the processors.
+    NoMadt,
+    /// The local APIC of the BSP is not initialized.
+    NoLocalApic,
+    /// No trampoline page was reserved below 1 MiB.
+    NoTrampoline,
+    /// The page tables of the BSP are not reachable from the 32-bit trampoline.
+    PageTablesAbove4G,
+    /// Unable to allocate the stacks of an AP.
+    OutOfMemory(MemError),
+}
+
+impl Display for SmpError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoMadt => write!(f, "No MADT describes the processors of the system."),
+            Self::NoLocalApic => write!(f, "The local APIC must be initialized before the APs are started."),
+            Self::NoTrampoline => write!(f, "No page below 1 MiB was reserved for the AP trampoline."),
+            Self::PageTablesAbove4G => write!(f, "The page tables are located above 4 GiB."),
+            Self::OutOfMemory(err) => write!(f, "Unable to allocate the stacks of an AP: {}", err),
+        }
+    }
+}
+
+impl Error for SmpError {}
+
+/// Reserves the trampoline page from the early boot memory.
+///
+/// Must be called right after [`bootmem::init`], while the low memory is still free. The first
+/// page is skipped, as it holds the real mode interrupt vector table. Returns false if no page
+/// below 1 MiB is available.
+pub fn reserve_trampoline() -> bool {
+    let page = match bootmem::alloc(PAGE_SIZE, PAGE_SIZE) {
+        Some(0) => bootmem::alloc(PAGE_SIZE, PAGE_SIZE),
+        page => page,
+    };
+    match page {
+        Some(page) if page < 0x10_0000 => {
+            TRAMPOLINE.store(page, Ordering::Release);
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Returns the amount of processors brought up, including the BSP. Parked processors are counted
+/// as well, see [`is_parked`].
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::Acquire)
+}
+
+/// Returns the index of the running processor, 0 for the BSP.
+pub fn cpu_id() -> usize {
+    if !LOCAL_APIC.is_enabled() {
+        return 0
+    }
+    let apic_id = LOCAL_APIC.id();
+    APIC_IDS[..cpu_count()].iter().position(|id| id.load(Ordering::Relaxed) == apic_id).unwrap_or(0)
+}
+
+/// Returns the local APIC id of the processor.
+pub fn apic_id(cpu: usize) -> u8 {
+    APIC_IDS[cpu].load(Ordering::Relaxed)
+}
+
+/// Starts every enabled processor described by the MADT. Returns the amount of processors online
+/// afterwards.
+///
+/// An AP which does not come online in time is reported and stops the bringup, because it might
+/// still enter the trampoline later.
+///
+/// # Unsafe
+///
+/// The IDT must be loaded and the local APIC of the BSP initialized. Must be called once.
+pub unsafe fn start_aps() -> Result<usize, SmpError> {
+    let madt = MADT::find().ok_or(SmpError::NoMadt)?;
+    if !LOCAL_APIC.is_enabled() {
+        return Err(SmpError::NoLocalApic)
+    }
+    let trampoline = match TRAMPOLINE.load(Ordering::Acquire) {
+        0 => return Err(SmpError::NoTrampoline),
+        page => page,
+    };
+    let (p4, _) = Cr3::read();
+    let cr3 = u32::try_from(p4.start_address()).map_err(|_| SmpError::PageTablesAbove4G)?;
+
+    let bsp = LOCAL_APIC.id();
+    APIC_IDS[0].store(bsp, Ordering::Relaxed);
+    unsafe { install_trampoline(trampoline, cr3) };
+    let tsc_mhz = critical_section!(|| IDLE_GOVERNOR.lock().tsc_mhz());
+
+    let aps = madt.entries().filter_map(|entry| match entry {
+        MadtEntry::LocalApic { apic_id, flags, .. } if flags & 1 != 0 && apic_id != bsp => Some(apic_id),
+        _ => None,
+    });
+    for apic_id in aps {
+        let cpu = cpu_count();
+        if cpu == MAX_CPUS {
+            crate::warn!("Only {} processors are supported.", MAX_CPUS);
+            break
+        }
+
+        unsafe { prepare_ap(cpu)? };
+        APIC_IDS[cpu].store(apic_id, Ordering::Relaxed);
+        if !unsafe { boot_ap(cpu, tsc_mhz) } {
+            crate::warn!("Processor with APIC id {} did not come online.", apic_id);
+            break
+        }
+        CPU_COUNT.fetch_add(1, Ordering::AcqRel);
+    }
+    Ok(cpu_count())
+}
+
+/// Parks the AP by sending it an INIT IPI. Returns false for the BSP, unknown or already parked
+/// processors.
+///
+/// Nothing must be scheduled on the processor, it stops immediately.
+pub fn park(cpu: usize) -> bool {
+    if cpu == 0 || cpu >= cpu_count() || is_parked(cpu) {
+        return false
+    }
+    LOCAL_APIC.send_ipi(APIC_IDS[cpu].load(Ordering::Relaxed), DeliveryMode::Init, 0);
+    PARKED.fetch_or(1 << cpu, Ordering::AcqRel);
+    true
+}
+
+/// Starts the parked AP again. Returns false if the processor is not parked or did not come
+/// online.
+pub fn unpark(cpu: usize) -> bool {
+    if !is_parked(cpu) {
+        return false
+    }
+    let tsc_mhz = critical_section!(|| IDLE_GOVERNOR.lock().tsc_mhz());
+    if !unsafe { boot_ap(cpu, tsc_mhz) } {
+        return false
+    }
+    PARKED.fetch_and(!(1 << cpu), Ordering::AcqRel);
+    true
+}
+
+/// Returns true if the processor is parked.
+pub fn is_parked(cpu: usize) -> bool {
+    cpu < MAX_CPUS && PARKED.load(Ordering::Acquire) & 1 << cpu != 0
+}
+
+/// Prepares the trampoline to bring the BSP back into long mode after a wake up from a sleep
+/// state, and returns it's physical address, which is used as the waking vector.
+///
+/// The trampoline calls the entry point on the provided stack with the argument in RDI and
+/// interrupts disabled. Only the paging and the GDT of the trampoline are set up at that point.
+///
+/// # Unsafe
+///
+/// No AP may be starting while the trampoline is rewritten.
+pub(crate) unsafe fn prepare_wakeup(entry: extern "C" fn(usize) -> !, stack: usize, arg: usize) -> Result<usize, SmpError> {
+    let trampoline = match TRAMPOLINE.load(Ordering::Acquire) {
+        0 => return Err(SmpError::NoTrampoline),
+        page => page,
+    };
+    let (p4, _) = Cr3::read();
+    let cr3 = u32::try_from(p4.start_address()).map_err(|_| SmpError::PageTablesAbove4G)?;
+
+    unsafe {
+        install_trampoline(trampoline, cr3);
+        let data = (trampoline + DATA_OFFSET) as *mut u8;
+        (data.add(0x30) as *mut u64).write(stack as u64);
+        (data.add(0x38) as *mut u64).write(entry as *const () as u64);
+        (data.add(0x40) as *mut u64).write(arg as u64);
+    }
+    Ok(trampoline)
+}
+
+/// Sends the INIT-SIPI-SIPI sequence to the prepared AP and waits until it comes online.
+unsafe fn boot_ap(cpu: usize, tsc_mhz: u64) -> bool {
+    let trampoline = TRAMPOLINE.load(Ordering::Acquire);
+    let apic_id = APIC_IDS[cpu].load(Ordering::Relaxed);
+    unsafe {
+        let data = (trampoline + DATA_OFFSET) as *mut u8;
+        (data.add(0x30) as *mut u64).write(AP_STACKS[cpu].load(Ordering::Relaxed) as u64);
+        (data.add(0x38) as *mut u64).write(ap_entry as *const () as u64);
+        (data.add(0x40) as *mut u64).write(cpu as u64);
+    }
+    AP_READY.store(false, Ordering::Release);
+
+    // INIT, then two startup IPIs, as the first one may be missed.
+    LOCAL_APIC.send_ipi(apic_id, DeliveryMode::Init, 0);
+    delay_us(10_000, tsc_mhz);
+    for _ in 0..2 {
+        LOCAL_APIC.send_ipi(apic_id, DeliveryMode::StartUp, (trampoline / PAGE_SIZE) as u8);
+        delay_us(200, tsc_mhz);
+        if AP_READY.load(Ordering::Acquire) {
+            return true
+        }
+    }
+
+    let start = unsafe { _rdtsc() };
+    while !AP_READY.load(Ordering::Acquire) {
+        if unsafe { _rdtsc() }.wrapping_sub(start) / tsc_mhz.max(1) > AP_TIMEOUT_US {
+            return false
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
+
+/// Copies the trampoline code into the page and fills the parts, which are the same for every AP.
+unsafe fn install_trampoline(page: usize, cr3: u32) {
+    let code = trampoline_code();
+    assert!(code.len() <= DATA_OFFSET, "The AP trampoline overlaps it's data block.");
+
+    unsafe {
+        let dst = page as *mut u8;
+        core::ptr::copy_nonoverlapping(code.as_ptr(), dst, code.len());
+
+        // Targets of the far jumps are patched, as the code does not know where it runs.
+        let patch = |label: *const u8, target: *const u8| {
+            let at = label as usize - code.as_ptr() as usize;
+            let target = page + (target as usize - code.as_ptr() as usize);
+            (dst.add(at) as *mut u32).write_unaligned(target as u32);
+        };
+        patch(&raw const smp_trampoline_jump32, &raw const smp_trampoline_32);
+        patch(&raw const smp_trampoline_jump64, &raw const smp_trampoline_64);
+
+        let data = dst.add(DATA_OFFSET);
+        (data as *mut [u64; 4]).write(TRAMPOLINE_GDT);
+        (data.add(0x20) as *mut u16).write((TRAMPOLINE_GDT.len() * 8 - 1) as u16);
+        (data.add(0x22) as *mut u32).write_unaligned((page + DATA_OFFSET) as u32);
+        (data.add(0x28) as *mut u32).write(cr3);
+    }
+}
+
+/// Allocates the stacks, GDT and TSS of the AP.
+unsafe fn prepare_ap(cpu: usize) -> Result<(), SmpError> {
+    let mmu = unsafe { &mut *MEMORY_MANAGEMENT_UNIT };
+    let stack = mmu.allocate_stack(AP_STACK_PAGES).map_err(SmpError::OutOfMemory)?;
+
+    let mut tss = TSS::new();
+    mmu.set_interrupt_stack(&mut tss, 0, 1).map_err(SmpError::OutOfMemory)?;
+    let tss: &'static TSS = Box::leak(Box::new(tss));
+    let gdt: &'static GDT = Box::leak(Box::new(GDT::flat_setup(tss)));
+
+    AP_STACKS[cpu].store(stack.top, Ordering::Relaxed);
+    AP_GDTS[cpu].store(gdt as *const GDT as usize, Ordering::Release);
+    Ok(())
+}
+
+/// Entry point of the APs in long mode, with the index of the processor.
+extern "C" fn ap_entry(cpu: usize) -> ! {
+    let gdt = unsafe { &*(AP_GDTS[cpu].load(Ordering::Acquire) as *const GDT) };
+
+    unsafe {
+        gdt.load_table();
+        CodeSegment::write(SegmentSelector::new(1, false, PrivilegeLevel::KernelLevel));
+        StackSegment::write(SegmentSelector::new(2, false, PrivilegeLevel::KernelLevel));
+        TSS::write(SegmentSelector::new(5, false, PrivilegeLevel::KernelLevel));
+        INTERRUPT_DESCRIPTOR_TABLE.load_table();
+        // Every processor must agree on the memory types of the shared mappings.
+        Pat::enable_write_combining();
+
+        if LOCAL_APIC.init(SPURIOUS_VECTOR).is_err() || LOCAL_APIC.id() != APIC_IDS[cpu].load(Ordering::Relaxed) {
+            // Nobody waits for a processor which cannot receive interrupts.
+            loop {
+                asm!("cli", "hlt", options(nomem, nostack));
+            }
+        }
+    }
+    AP_READY.store(true, Ordering::Release);
+
+    loop {
+        interrupt::wait_for_interrupt();
+    }
+}
+
+/// Spins for the provided amount of microseconds.
+fn delay_us(us: u64, tsc_mhz: u64) {
+    let start = unsafe { _rdtsc() };
+    while unsafe { _rdtsc() }.wrapping_sub(start) < us * tsc_mhz {
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns the position independent trampoline code.
+fn trampoline_code() -> &'static [u8] {
+    unsafe {
+        let start = &smp_trampoline_start as *const u8;
+        let end = &smp_trampoline_end as *const u8;
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+extern "C" {
+    static smp_trampoline_start: u8;
+    static smp_trampoline_jump32: u8;
+    static smp_trampoline_32: u8;
+    static smp_trampoline_jump64: u8;
+    static smp_trampoline_64: u8;
+    static smp_trampoline_end: u8;
+}
+
+// The trampoline is entered in real mode at the start of the page with CS set to the page. The
+// data block at DATA_OFFSET holds a flat GDT and it's pointer at 0x20, CR3 at 0x28, the stack top
+// at 0x30, the entry point at 0x38 and the processor index at 0x40.
+//
+// It loads the GDT and enters protected mode, enables PAE, loads the page tables of the BSP, sets
+// EFER.LME and EFER.NXE, and enables paging and write protection, which activates long mode. The
+// far jump targets are patched on installation. In long mode the stack is loaded and the entry
+// point is called with the processor index in RDI.
+global_asm!(
+    ".pushsection .text.smp, \"ax\"",
+    ".global smp_trampoline_start",
+    ".global smp_trampoline_jump32",
+    ".global smp_trampoline_32",
+    ".global smp_trampoline_jump64",
+    ".global smp_trampoline_64",
+    ".global smp_trampoline_end",
+    ".code16",
+    "smp_trampoline_start:",
+    "    cli",
+    "    cld",
+    "    mov ax, cs",
+    "    mov ds, ax",
+    "    xor ebx, ebx",
+    "    mov bx, ax",
+    "    shl ebx, 4",
+    "    lgdt [0xf20]",
+    "    mov eax, cr0",
+    "    or eax, 1",
+    "    mov cr0, eax",
+    "    .byte 0x66, 0xea",
+    "smp_trampoline_jump32:",
+    "    .long 0",
+    "    .word 0x08",
+    ".code32",
+    "smp_trampoline_32:",
+    "    mov ax, 0x10",
+    "    mov ds, ax",
+    "    mov es, ax",
+    "    mov ss, ax",
+    "    mov eax, cr4",
+    "    or eax, 1 << 5",
+    "    mov cr4, eax",
+    "    mov eax, [ebx + 0xf28]",
+    "    mov cr3, eax",
+    "    mov ecx, 0xc0000080",
+    "    rdmsr",
+    "    or eax, (1 << 8) | (1 << 11)",
+    "    wrmsr",
+    "    mov eax, cr0",
+    "    or eax, 0x80010000",
+    "    mov cr0, eax",
+    "    .byte 0xea",
+    "smp_trampoline_jump64:",
+    "    .long 0",
+    "    .word 0x18",
+    ".code64",
+    "smp_trampoline_64:",
+    "    mov rsp, [rbx + 0xf30]",
+    "    mov rdi, [rbx + 0xf40]",
+    "    mov rax, [rbx + 0xf38]",
+    "    xor ebp, ebp",
+    "    push 0",
+    "    jmp rax",
+    "smp_trampoline_end:",
+    ".popsection",
+);
+
+#[test_case]
+fn trampoline_fits_before_its_data() {
+    assert!(trampoline_code().len() <= DATA_OFFSET);
+    assert_eq!(cpu_count(), 1);
+    assert_eq!(cpu_id(), 0);
+    assert!(!park(0) && !unpark(0));
+}
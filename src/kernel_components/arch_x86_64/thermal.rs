@@ -0,0 +1,363 @@
+/// Thermal throttling policy.
+///
+/// Temperatures come from the digital thermal sensor of the processor and from the ACPI thermal
+/// zones. The sensor is polled by the monitor thread every [`POLL_INTERVAL_MS`] and also raises the
+/// thermal interrupt of the local APIC once the passive or hot trip point is crossed, so a fast
+/// rise is handled without waiting for the next poll. The kernel has no AML interpreter to
+/// evaluate _TMP and the trip points of the zones, therefore those are registered with
+/// [`ThermalPolicy::add_zone`] and the readings are reported with [`ThermalPolicy::report`].
+///
+/// Every zone is in one of the [`ThermalState`]s chosen by it's trip points, and the most severe
+/// state of all zones applies to the system:
+///
+/// - Passive: the performance level of [`CPUFREQ`] is capped at [`PASSIVE_LEVEL`].
+/// - Hot: the lowest performance level is forced and every AP is parked.
+/// - Critical: the machine is shut down at once.
+///
+/// A zone leaves a state only once it cooled [`HYSTERESIS`] below the trip point, so a reading
+/// around a trip point does not make the policy flap. Every transition of a zone is logged and
+/// kept in the event history.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::kernel_components::arch_x86_64::cpufreq::CPUFREQ;
+use crate::kernel_components::arch_x86_64::controllers::apic::{Lvt, LvtEntry, LOCAL_APIC};
+use crate::kernel_components::arch_x86_64::smp;
+use crate::kernel_components::drivers::DRIVER_MANAGER;
+use crate::kernel_components::registers::ms::{
+    Msr, ThermInterrupt, ThermStatus, TemperatureTarget, PackageThermStatus, PackageThermInterrupt,
+};
+use crate::kernel_components::sync::Mutex;
+use crate::kernel_components::task_virtualization::{workqueue, Thread};
+use crate::critical_section;
+
+/// Interval between two readings of the thermal sensor in milliseconds.
+pub const POLL_INTERVAL_MS: u32 = 1000;
+/// Distance below a trip point in tenths of a degree Celsius, which a zone must cool down to
+/// leave the state.
+pub const HYSTERESIS: i32 = 50;
+/// Performance level in percent, at which the frequency is capped in the passive state.
+pub const PASSIVE_LEVEL: u8 = 50;
+/// Vector of the thermal interrupt of the local APIC.
+pub const THERMAL_VECTOR: u8 = 0xf8;
+
+/// Amount of transitions kept in the event history.
+const EVENT_HISTORY: usize = 32;
+/// Maximal junction temperature assumed when the processor does not report it.
+const DEFAULT_TJ_MAX: i32 = 100;
+/// Distances of the passive and hot trip points of the processor below TjMax in degrees Celsius.
+const CPU_TRIPS: (i32, i32) = (15, 5);
+/// CPUID leaf 6 EAX bit, which reports the digital thermal sensor.
+const CPUID_DTS: u32 = 1 << 0;
+/// CPUID leaf 6 EAX bit, which reports the package thermal management.
+const CPUID_PTM: u32 = 1 << 6;
+/// Readout valid bit of the thermal status registers.
+const STATUS_VALID: u64 = 1 << 31;
+
+/// Processors parked by the thermal policy, one bit per processor. Processors parked for another
+/// reason are left alone when the system cools down.
+static THERMAL_PARKED: AtomicU64 = AtomicU64::new(0);
+
+/// Thermal policy of the system.
+pub static THERMAL: Mutex<ThermalPolicy> = Mutex::new(ThermalPolicy::new());
+
+/// State of a thermal zone, from the least severe one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThermalState {
+    /// Below every trip point.
+    Normal,
+    /// At or above the passive trip point (_PSV).
+    Passive,
+    /// At or above the hot trip point (_HOT).
+    Hot,
+    /// At or above the critical trip point (_CRT).
+    Critical,
+}
+
+/// Trip points of a zone in tenths of a degree Celsius.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TripPoints {
+    pub passive: i32,
+    pub hot: i32,
+    pub critical: i32,
+}
+
+impl TripPoints {
+    /// Returns the state the temperature belongs to.
+    pub fn state_at(&self, temperature: i32) -> ThermalState {
+        match temperature {
+            t if t >= self.critical => ThermalState::Critical,
+            t if t >= self.hot => ThermalState::Hot,
+            t if t >= self.passive => ThermalState::Passive,
+            _ => ThermalState::Normal,
+        }
+    }
+
+    /// Returns the state following the current one after the reading. A more severe state is
+    /// entered at once, a less severe one only with the hysteresis applied.
+    pub fn next_state(&self, current: ThermalState, temperature: i32) -> ThermalState {
+        match self.state_at(temperature) {
+            state if state >= current => state,
+            _ => self.state_at(temperature + HYSTERESIS).min(current),
+        }
+    }
+}
+
+/// Single thermal zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThermalZone {
+    /// Name of the zone, like the ACPI path of the thermal zone object.
+    pub name: &'static str,
+    pub trips: TripPoints,
+    /// Last reading in tenths of a degree Celsius.
+    pub temperature: Option<i32>,
+    pub state: ThermalState,
+}
+
+/// Transition of a zone between two states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThermalEvent {
+    pub zone: &'static str,
+    /// Reading which caused the transition in tenths of a degree Celsius.
+    pub temperature: i32,
+    pub from: ThermalState,
+    pub to: ThermalState,
+}
+
+/// Digital thermal sensor of the processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sensor {
+    /// Maximal junction temperature in degrees Celsius.
+    tj_max: i32,
+    /// Reads the package sensor instead of the one of the core.
+    package: bool,
+    /// Zone fed by the sensor.
+    zone: usize,
+}
+
+/// Thermal zones and the state of the system.
+#[derive(Debug)]
+pub struct ThermalPolicy {
+    zones: Vec<ThermalZone>,
+    events: VecDeque<ThermalEvent>,
+    sensor: Option<Sensor>,
+    state: ThermalState,
+}
+
+impl ThermalPolicy {
+    /// Creates the policy without any zones.
+    pub const fn new() -> Self {
+        Self { zones: Vec::new(), events: VecDeque::new(), sensor: None, state: ThermalState::Normal }
+    }
+
+    /// Detects the digital thermal sensor and adds it as the "cpu" zone with the trip points
+    /// derived from TjMax. The thermal interrupt is enabled when the local APIC is initialized.
+    ///
+    /// Returns true if the sensor is available.
+    pub fn detect(&mut self) -> bool {
+        if self.sensor.is_some() {
+            return true
+        }
+        if unsafe { __cpuid(0) }.eax < 6 || unsafe { __cpuid(6) }.eax & CPUID_DTS == 0 {
+            return false
+        }
+
+        let package = unsafe { __cpuid(6) }.eax & CPUID_PTM != 0;
+        // The temperature target is model specific, only Intel processors report it.
+        let tj_max = match unsafe { TemperatureTarget::read_raw() } >> 16 & 0xff {
+            0 => DEFAULT_TJ_MAX,
+            tj_max => tj_max as i32,
+        };
+        let trips = TripPoints {
+            passive: (tj_max - CPU_TRIPS.0) * 10,
+            hot: (tj_max - CPU_TRIPS.1) * 10,
+            critical: tj_max * 10,
+        };
+        let zone = self.add_zone("cpu", trips);
+        self.sensor = Some(Sensor { tj_max, package, zone });
+
+        if LOCAL_APIC.is_enabled() {
+            // High and low temperature, critical temperature and both thresholds at the passive
+            // and hot trip points, which are programmed as distances below TjMax.
+            let value = 0b10011 | (CPU_TRIPS.0 as u64) << 8 | 1 << 15 | (CPU_TRIPS.1 as u64) << 16 | 1 << 23;
+            unsafe {
+                match package {
+                    true => PackageThermInterrupt::write_raw(value),
+                    false => ThermInterrupt::write_raw(value),
+                }
+            }
+            LOCAL_APIC.write_lvt(Lvt::Thermal, LvtEntry::new(THERMAL_VECTOR));
+        }
+        true
+    }
+
+    /// Adds the zone with the provided trip points. Returns the index of the zone.
+    pub fn add_zone(&mut self, name: &'static str, trips: TripPoints) -> usize {
+        self.zones.push(ThermalZone { name, trips, temperature: None, state: ThermalState::Normal });
+        self.zones.len() - 1
+    }
+
+    /// Reports the reading of the ACPI thermal zone in tenths of Kelvin, as returned by _TMP.
+    ///
+    /// Returns the new state of the system, if it changed.
+    pub fn report(&mut self, zone: usize, deci_kelvin: u32) -> Option<ThermalState> {
+        self.update(zone, deci_kelvin as i32 - 2732)
+    }
+
+    /// Updates the zone with the reading in tenths of a degree Celsius.
+    ///
+    /// Returns the new state of the system, if it changed. Unknown zones are ignored.
+    pub fn update(&mut self, zone: usize, temperature: i32) -> Option<ThermalState> {
+        let zone = self.zones.get_mut(zone)?;
+        zone.temperature = Some(temperature);
+
+        let next = zone.trips.next_state(zone.state, temperature);
+        if next != zone.state {
+            let event = ThermalEvent { zone: zone.name, temperature, from: zone.state, to: next };
+            zone.state = next;
+            crate::warn!(
+                "Thermal zone \"{}\" at {}.{} C: {:?} -> {:?}",
+                event.zone, temperature / 10, (temperature % 10).abs(), event.from, event.to,
+            );
+            if self.events.len() == EVENT_HISTORY {
+                self.events.pop_front();
+            }
+            self.events.push_back(event);
+        }
+
+        let state = self.zones.iter().map(|zone| zone.state).max().unwrap_or(ThermalState::Normal);
+        (core::mem::replace(&mut self.state, state) != state).then_some(state)
+    }
+
+    /// Reads the digital thermal sensor and updates it's zone.
+    ///
+    /// Returns the new state of the system, if it changed.
+    pub fn poll_sensor(&mut self) -> Option<ThermalState> {
+        let sensor = self.sensor?;
+        let status = unsafe {
+            match sensor.package {
+                true => PackageThermStatus::read_raw(),
+                false => ThermStatus::read_raw(),
+            }
+        };
+        // The package register has no valid bit.
+        if !sensor.package && status & STATUS_VALID == 0 {
+            return None
+        }
+        self.update(sensor.zone, (sensor.tj_max - (status >> 16 & 0x7f) as i32) * 10)
+    }
+
+    /// Returns the state of the system.
+    pub fn state(&self) -> ThermalState {
+        self.state
+    }
+
+    /// Returns the known zones.
+    pub fn zones(&self) -> &[ThermalZone] {
+        &self.zones
+    }
+
+    /// Returns the recent transitions, starting with the oldest one.
+    pub fn events(&self) -> impl Iterator<Item = &ThermalEvent> {
+        self.events.iter()
+    }
+}
+
+/// Applies the response of the policy to the state of the system.
+pub fn apply(state: ThermalState) {
+    let cap = match state {
+        ThermalState::Normal => 100,
+        ThermalState::Passive => PASSIVE_LEVEL,
+        ThermalState::Hot => 0,
+        ThermalState::Critical => emergency_shutdown(),
+    };
+    critical_section!(|| CPUFREQ.lock().set_max_level(cap));
+
+    for cpu in 1..smp::cpu_count() {
+        let parked_here = THERMAL_PARKED.load(Ordering::Acquire) & 1 << cpu != 0;
+        match state {
+            ThermalState::Hot => if smp::park(cpu) {
+                THERMAL_PARKED.fetch_or(1 << cpu, Ordering::AcqRel);
+            },
+            // A processor which was unparked by someone else is not ours anymore.
+            _ if parked_here && (smp::unpark(cpu) || !smp::is_parked(cpu)) => {
+                THERMAL_PARKED.fetch_and(!(1 << cpu), Ordering::AcqRel);
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Reads the thermal sensor and applies the policy if the state of the system changed.
+pub fn evaluate() {
+    if let Some(state) = critical_section!(|| THERMAL.lock().poll_sensor()) {
+        apply(state);
+    }
+}
+
+/// Shuts the machine down after the critical temperature was reached.
+///
+/// The APs are parked and the drivers are shut down. The power is only turned off under QEMU,
+/// as the kernel cannot evaluate the \_S5 object yet, otherwise the processor is halted and the
+/// hardware protection turns the power off if the temperature keeps rising.
+pub fn emergency_shutdown() -> ! {
+    crate::emergency_println!("Thermal: critical temperature reached, shutting down.");
+    for cpu in 1..smp::cpu_count() {
+        smp::park(cpu);
+    }
+    unsafe { DRIVER_MANAGER.shutdown_all() };
+
+    #[cfg(feature = "virt_qemu")]
+    let _ = crate::kernel_components::arch_x86_64::acpi::acpi::acpi_service::shutdown(None);
+
+    loop {
+        unsafe { asm!("cli", "hlt", options(nomem, nostack)) };
+    }
+}
+
+/// Must be called from the handler of [`THERMAL_VECTOR`]. The evaluation is deferred to the
+/// worker thread.
+pub fn handle_interrupt() {
+    // The log bits are sticky and cleared by writing zeroes, the other writable bits are zero.
+    unsafe {
+        ThermStatus::write_raw(0);
+        if __cpuid(6).eax & CPUID_PTM != 0 {
+            PackageThermStatus::write_raw(0);
+        }
+    }
+    workqueue::schedule(evaluate);
+}
+
+/// Monitor thread, which polls the thermal sensor periodically.
+pub fn monitor_thread(_: &mut Thread) {
+    loop {
+        evaluate();
+        Thread::sleep(POLL_INTERVAL_MS);
+    }
+}
+
+#[test_case]
+fn trip_points_apply_hysteresis() {
+    let trips = TripPoints { passive: 700, hot: 900, critical: 1000 };
+    assert_eq!(trips.state_at(650), ThermalState::Normal);
+    assert_eq!(trips.state_at(900), ThermalState::Hot);
+    assert_eq!(trips.next_state(ThermalState::Normal, 950), ThermalState::Hot);
+    assert_eq!(trips.next_state(ThermalState::Hot, 880), ThermalState::Hot);
+    assert_eq!(trips.next_state(ThermalState::Hot, 840), ThermalState::Passive);
+    assert_eq!(trips.next_state(ThermalState::Passive, 660), ThermalState::Passive);
+    assert_eq!(trips.next_state(ThermalState::Passive, 640), ThermalState::Normal);
+
+    let mut policy = ThermalPolicy::new();
+    let cpu = policy.add_zone("cpu", trips);
+    let zone = policy.add_zone("\\_TZ.THM0", TripPoints { passive: 600, hot: 800, critical: 950 });
+    assert_eq!(policy.update(cpu, 720), Some(ThermalState::Passive));
+    assert_eq!(policy.report(zone, 2732 + 810), Some(ThermalState::Hot));
+    assert_eq!(policy.update(cpu, 500), None);
+    assert_eq!(policy.report(zone, 2732 + 500), Some(ThermalState::Normal));
+    assert_eq!(policy.events().count(), 4);
+    assert_eq!(policy.zones()[zone].temperature, Some(500));
+}
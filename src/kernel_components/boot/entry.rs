@@ -0,0 +1,103 @@
+//! Hand over from a boot front-end to the kernel entry point.
+//!
+//! Whatever the front-end is, the kernel expects the state the multiboot path leaves it in: the
+//! low memory identity mapped, the first GiB mapped at [`KERNEL_OFFSET`] as well, the P4 table
+//! mapped recursively by the entry [`RECURSIVE_INDEX`], interrupts disabled and a stack within
+//! the kernel image. Front-ends build the page tables with [`build_page_tables`] and enter the
+//! kernel with [`jump`].
+
+use core::arch::asm;
+use core::ptr;
+
+use crate::kernel_components::memory::{EntryFlags, frames::PAGE_SIZE};
+use crate::kernel_components::memory::layout::{KERNEL_OFFSET, RECURSIVE_INDEX};
+
+/// Size of the stack the kernel runs on, same as the one of the multiboot path.
+pub const STACK_SIZE: usize = 4096 * 4;
+/// Amount of memory identity mapped by a single P2 table.
+pub const P2_COVERAGE: u64 = 1 << 30;
+/// Only the first P4 entry is used for the identity mapping.
+pub const IDENTITY_LIMIT: u64 = 512 * P2_COVERAGE;
+
+#[repr(C, align(16))]
+struct BootStack([u8; STACK_SIZE]);
+
+/// Stack for the kernel, which is a part of the image like the one of the multiboot path, so it
+/// stays mapped after the kernel is remapped.
+static mut BOOT_STACK: BootStack = BootStack([0; STACK_SIZE]);
+
+/// Returns the amount of pages the boot page tables take.
+pub const fn table_pages(p2_count: usize) -> usize {
+    3 + p2_count
+}
+
+/// Fills page tables, that identity map the first p2_count GiB with huge pages. The first GiB is
+/// mapped at [`KERNEL_OFFSET`] by the same P2 table.
+///
+/// The tables are consecutive pages starting with P4, the P3 of the low memory and the P3 of the
+/// kernel, followed by p2_count P2 tables. The entries point to the tables by the provided
+/// physical address, which differs from the pointer when the tables are a part of the image.
+///
+/// # Safety
+///
+/// The memory must be writable and hold [`table_pages`] pages.
+pub unsafe fn build_page_tables(tables: *mut u64, phys: u64, p2_count: usize) {
+    debug_assert!((1..=512).contains(&p2_count));
+
+    let table = |i: usize| tables.add(i * 512);
+    let flags = (EntryFlags::PRESENT | EntryFlags::WRITABLE).bits();
+    let entry = |i: usize| (phys + (i * PAGE_SIZE) as u64) | flags;
+    let (p4, p3, p3_kernel) = (table(0), table(1), table(2));
+
+    ptr::write_bytes(tables, 0, 3 * 512);
+    *p4 = entry(1);
+    *p4.add(RECURSIVE_INDEX) = entry(0);
+    *p4.add(KERNEL_OFFSET >> 39 & 0o777) = entry(2);
+    *p3_kernel.add(KERNEL_OFFSET >> 30 & 0o777) = entry(3);
+    for i in 0..p2_count {
+        let p2 = table(3 + i);
+        *p3.add(i) = entry(3 + i);
+        for j in 0..512 {
+            let addr = i as u64 * P2_COVERAGE + j as u64 * (2 << 20);
+            *p2.add(j) = addr | flags | EntryFlags::HUGE_PAGE.bits();
+        }
+    }
+}
+
+/// Switches to the page tables and the boot stack and calls the kernel entry with the address of
+/// the boot information.
+///
+/// # Safety
+///
+/// The page tables must map the running code, the boot stack and the boot information at their
+/// current addresses.
+pub unsafe fn jump(p4: u64, info: usize, entry: extern "C" fn(usize)) -> ! {
+    let stack_top = ptr::addr_of_mut!(BOOT_STACK) as u64 + STACK_SIZE as u64;
+    // The argument is passed in the first argument register of both System V and Microsoft
+    // calling conventions, since "C" means the latter on UEFI targets. The space below the
+    // return address is the shadow space of the Microsoft convention.
+    asm!(
+        "cli",
+        "mov cr3, {p4}",
+        "mov rsp, {stack}",
+        "xor ebp, ebp",
+        "sub rsp, 32",
+        "call {entry}",
+        "2:",
+        "hlt",
+        "jmp 2b",
+        p4 = in(reg) p4,
+        stack = in(reg) stack_top,
+        entry = in(reg) entry,
+        in("rdi") info,
+        in("rcx") info,
+        options(noreturn),
+    )
+}
+
+/// Halts forever. Used when the boot information cannot be built and there is no way to report it.
+pub fn halt() -> ! {
+    loop {
+        unsafe { asm!("cli", "hlt") }
+    }
+}
@@ -0,0 +1,318 @@
+//! Boot information builder for boot paths other than multiboot2.
+//!
+//! The memory management and every other consumer of boot information only understand the
+//! multiboot2 structure, which is parsed by [`InfoPointer`]. Instead of teaching each of them a
+//! new format, other boot front-ends translate what their firmware or bootloader provides into
+//! the same structure with [`BootInfoBuilder`] and pass it's address to the kernel entry point.
+//!
+//! The builder does not allocate, because it runs before any allocator exists. It writes into a
+//! caller provided buffer, which must stay untouched until the memory is initialized.
+//!
+//! [`InfoPointer`]: crate::kernel_components::memory::InfoPointer
+
+use core::fmt::Display;
+use core::error::Error;
+
+use crate::kernel_components::memory::{
+    memory_map::MemoryArea,
+    sections::ElfSectionInner64,
+    tags::TagType,
+};
+use crate::kernel_components::arch_x86_64::smbios::EntryPoint;
+use crate::AsBytes;
+
+/// Size of the fixed part of the framebuffer tag, without the color information.
+const FRAMEBUFFER_COMMON_SIZE: usize = 32;
+/// Framebuffer type of direct RGB framebuffers.
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+/// Custom error type for building boot information.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootInfoError {
+    /// The buffer is not aligned to 8 bytes.
+    Unaligned,
+    /// The buffer is too small for all tags.
+    BufferFull,
+}
+
+impl Display for BootInfoError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Unaligned => write!(f, "The boot information buffer is not aligned to 8 bytes."),
+            Self::BufferFull => write!(f, "The boot information buffer is too small."),
+        }
+    }
+}
+
+impl Error for BootInfoError {}
+
+/// Description of a linear RGB framebuffer.
+///
+/// Each color channel is described by the position of it's lowest bit within a pixel and the
+/// amount of bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framebuffer {
+    /// Physical address of the framebuffer.
+    pub addr: u64,
+    /// Amount of bytes in one line.
+    pub pitch: u32,
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+    /// Bits per pixel.
+    pub bpp: u8,
+    /// Position and size of the red channel.
+    pub red: (u8, u8),
+    /// Position and size of the green channel.
+    pub green: (u8, u8),
+    /// Position and size of the blue channel.
+    pub blue: (u8, u8),
+}
+
+impl Framebuffer {
+    /// Returns the position and size of a channel described by a bit mask.
+    pub const fn channel(mask: u32) -> (u8, u8) {
+        if mask == 0 {
+            return (0, 0)
+        }
+        (mask.trailing_zeros() as u8, mask.count_ones() as u8)
+    }
+}
+
+/// Writes a multiboot2 boot information structure into a buffer.
+///
+/// Tags are written in the order of the calls and [`BootInfoBuilder::finish`] appends the end
+/// tag and the total size.
+pub struct BootInfoBuilder<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> BootInfoBuilder<'a> {
+    /// Creates a new builder over the buffer, which must be aligned to 8 bytes.
+    pub fn new(buf: &'a mut [u8]) -> Result<Self, BootInfoError> {
+        if buf.as_ptr().align_offset(8) != 0 {
+            return Err(BootInfoError::Unaligned)
+        }
+        if buf.len() < 8 {
+            return Err(BootInfoError::BufferFull)
+        }
+        // Total size and reserved field are written by finish.
+        buf[..8].fill(0);
+        Ok(Self { buf, len: 8 })
+    }
+
+    /// Adds the kernel command line.
+    pub fn command_line(&mut self, cmdline: &str) -> Result<&mut Self, BootInfoError> {
+        let start = self.begin(TagType::Cmd)?;
+        self.write(cmdline.as_bytes())?;
+        self.write(&[0])?;
+        self.end(start)
+    }
+
+    /// Adds the memory map.
+    pub fn memory_map<I>(&mut self, areas: I) -> Result<&mut Self, BootInfoError> where
+        I: IntoIterator<Item = MemoryArea>,
+    {
+        let start = self.begin(TagType::Mmap)?;
+        self.write(&(size_of::<MemoryArea>() as u32).to_le_bytes())?;
+        self.write(&0u32.to_le_bytes())?;
+        for area in areas {
+            self.write(&area.as_bytes())?;
+        }
+        self.end(start)
+    }
+
+    /// Adds the section headers of the kernel image. The shndx is the index of the section with
+    /// section names.
+    pub fn elf_sections<I>(&mut self, sections: I, shndx: u32) -> Result<&mut Self, BootInfoError> where
+        I: IntoIterator<Item = ElfSectionInner64>,
+    {
+        let start = self.begin(TagType::ElfSections)?;
+        // The amount of sections is patched after they are written.
+        self.write(&0u32.to_le_bytes())?;
+        self.write(&(size_of::<ElfSectionInner64>() as u32).to_le_bytes())?;
+        self.write(&shndx.to_le_bytes())?;
+
+        let mut count = 0u32;
+        for section in sections {
+            self.write(&section.as_bytes())?;
+            count += 1;
+        }
+        self.buf[start + 8..start + 12].copy_from_slice(&count.to_le_bytes());
+        self.end(start)
+    }
+
+    /// Adds a boot module loaded at the physical range from start to end (exclusive).
+    pub fn module(&mut self, start: u32, end: u32, cmdline: &str) -> Result<&mut Self, BootInfoError> {
+        let tag = self.begin(TagType::Module)?;
+        self.write(&start.to_le_bytes())?;
+        self.write(&end.to_le_bytes())?;
+        self.write(cmdline.as_bytes())?;
+        self.write(&[0])?;
+        self.end(tag)
+    }
+
+    /// Adds a direct RGB framebuffer.
+    pub fn framebuffer(&mut self, fb: &Framebuffer) -> Result<&mut Self, BootInfoError> {
+        let start = self.begin(TagType::FrameBuf)?;
+        self.write(&fb.addr.to_le_bytes())?;
+        self.write(&fb.pitch.to_le_bytes())?;
+        self.write(&fb.width.to_le_bytes())?;
+        self.write(&fb.height.to_le_bytes())?;
+        self.write(&[fb.bpp, FRAMEBUFFER_TYPE_RGB, 0, 0])?;
+        debug_assert_eq!(self.len - start, FRAMEBUFFER_COMMON_SIZE);
+        self.write(&[fb.red.0, fb.red.1, fb.green.0, fb.green.1, fb.blue.0, fb.blue.1])?;
+        self.end(start)
+    }
+
+    /// Adds a copy of the ACPI 1.0 RSDP.
+    pub fn acpi_old(&mut self, rsdp: &[u8]) -> Result<&mut Self, BootInfoError> {
+        self.tag(TagType::AcpiOld, rsdp)
+    }
+
+    /// Adds a copy of the ACPI 2.0 XSDP.
+    pub fn acpi_new(&mut self, xsdp: &[u8]) -> Result<&mut Self, BootInfoError> {
+        self.tag(TagType::AcpiNew, xsdp)
+    }
+
+    /// Adds a copy of the SMBIOS entry point.
+    pub fn smbios(&mut self, major: u8, minor: u8, entry: &[u8]) -> Result<&mut Self, BootInfoError> {
+        let start = self.begin(TagType::Smbios)?;
+        self.write(&[major, minor, 0, 0, 0, 0, 0, 0])?;
+        self.write(entry)?;
+        self.end(start)
+    }
+
+    /// Adds a tag with an arbitrary payload.
+    pub fn tag(&mut self, typ: TagType, payload: &[u8]) -> Result<&mut Self, BootInfoError> {
+        let start = self.begin(typ)?;
+        self.write(payload)?;
+        self.end(start)
+    }
+
+    /// Appends the end tag and returns the finished structure.
+    pub fn finish(mut self) -> Result<&'a [u8], BootInfoError> {
+        let start = self.begin(TagType::End)?;
+        self.end(start)?;
+
+        let total = self.len as u32;
+        self.buf[..4].copy_from_slice(&total.to_le_bytes());
+        Ok(&self.buf[..self.len])
+    }
+
+    /// Writes the tag header with a placeholder size and returns the start of the tag.
+    fn begin(&mut self, typ: TagType) -> Result<usize, BootInfoError> {
+        let start = self.len;
+        self.write(&typ.get().to_le_bytes())?;
+        self.write(&0u32.to_le_bytes())?;
+        Ok(start)
+    }
+
+    /// Patches the size of the tag and pads it to 8 bytes.
+    fn end(&mut self, start: usize) -> Result<&mut Self, BootInfoError> {
+        let size = (self.len - start) as u32;
+        self.buf[start + 4..start + 8].copy_from_slice(&size.to_le_bytes());
+
+        let padded = self.len.next_multiple_of(8);
+        self.buf.get_mut(self.len..padded).ok_or(BootInfoError::BufferFull)?.fill(0);
+        self.len = padded;
+        Ok(self)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), BootInfoError> {
+        let end = self.len + bytes.len();
+        self.buf.get_mut(self.len..end).ok_or(BootInfoError::BufferFull)?.copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Returns the version and bytes of a valid SMBIOS entry point, which can be copied into the
+/// SMBIOS tag.
+///
+/// # Safety
+///
+/// The pointer must point to readable memory of at least 7 bytes, that is not modified later.
+pub unsafe fn smbios_entry_point(ptr: *const u8) -> Option<((u8, u8), &'static [u8])> {
+    let header = core::slice::from_raw_parts(ptr, 7);
+    let len = if header.starts_with(b"_SM3_") {
+        header[6]
+    } else if header.starts_with(b"_SM_") {
+        header[5]
+    } else {
+        return None
+    };
+    let bytes = core::slice::from_raw_parts(ptr, len as usize);
+    EntryPoint::parse(bytes).map(|entry| (entry.version, bytes))
+}
+
+#[test_case]
+fn boot_info_builder_output_is_parsed() {
+    use crate::kernel_components::memory::{
+        memory_map::MemoryAreaType,
+        sections::{ElfSectionFlags, ElfSectionType},
+        InfoPointer, BootInfoHeader,
+    };
+
+    #[repr(C, align(8))]
+    struct Buffer([u8; 512]);
+    static mut BUFFER: Buffer = Buffer([0; 512]);
+
+    let areas = [
+        MemoryArea::new(0, 0x9f000, MemoryAreaType::Available),
+        MemoryArea::new(0x100000, 0x3ff00000, MemoryAreaType::Available),
+        MemoryArea::new(0x7fe0000, 0x20000, MemoryAreaType::AcpiAvailable),
+    ];
+    let names = b"\0.kernel\0.shstrtab\0";
+    let sections = [
+        ElfSectionInner64::new(0, ElfSectionType::Unused, ElfSectionFlags::empty(), 0, 0),
+        ElfSectionInner64::new(
+            1, ElfSectionType::ProgramSection,
+            ElfSectionFlags::ALLOCATED | ElfSectionFlags::WRITABLE,
+            0x200000, 0x80000,
+        ),
+        ElfSectionInner64::new(
+            9, ElfSectionType::StringTable, ElfSectionFlags::empty(),
+            names.as_ptr() as u64, names.len() as u64,
+        ),
+    ];
+
+    let fb = Framebuffer {
+        addr: 0xfd000000, pitch: 4096, width: 1024, height: 768, bpp: 32,
+        red: Framebuffer::channel(0xff0000),
+        green: Framebuffer::channel(0xff00),
+        blue: Framebuffer::channel(0xff),
+    };
+
+    let mut builder = BootInfoBuilder::new(unsafe { &mut BUFFER.0 }).unwrap();
+    builder
+        .command_line("console=fb").unwrap()
+        .memory_map(areas).unwrap()
+        .elf_sections(sections, 2).unwrap()
+        .framebuffer(&fb).unwrap();
+    let mbi = builder.finish().unwrap();
+    assert_eq!(mbi.len() % 8, 0);
+
+    let info = unsafe { InfoPointer::load(mbi.as_ptr() as *const BootInfoHeader) }.unwrap();
+    assert_eq!(info.command_line(), Some("console=fb"));
+    assert_eq!(info.memory_map_tag().unwrap().memory_map_iter().count(), 3);
+    let kernel = info.elf_sections_tag().unwrap().find(|s| s.is_allocated()).unwrap();
+    assert_eq!(kernel.name(), Ok(".kernel"));
+    assert_eq!((kernel.start_address(), kernel.size()), (0x200000, 0x80000));
+    assert_eq!(info.framebuffer(), Some(fb));
+
+    // A framebuffer tag too short for it's fixed fields is skipped.
+    let mut truncated = Buffer([0; 512]);
+    let mut builder = BootInfoBuilder::new(&mut truncated.0).unwrap();
+    builder.tag(TagType::FrameBuf, &[0; 8]).unwrap();
+    let mbi = builder.finish().unwrap();
+    let info = unsafe { InfoPointer::load(mbi.as_ptr() as *const BootInfoHeader) }.unwrap();
+    assert_eq!(info.framebuffer(), None);
+
+    let mut small = Buffer([0; 512]);
+    let mut builder = BootInfoBuilder::new(&mut small.0[..24]).unwrap();
+    assert_eq!(builder.command_line("a command line that does not fit").err(), Some(BootInfoError::BufferFull));
+    assert_eq!(BootInfoBuilder::new(&mut small.0[1..]).err(), Some(BootInfoError::Unaligned));
+}
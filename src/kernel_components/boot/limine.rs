@@ -0,0 +1,540 @@
+//! Limine boot protocol front-end.
+//!
+//! Limine loads the kernel ELF at it's link address in virtual memory, while the physical
+//! location is chosen by the bootloader. Requests are static structures within the image, which
+//! the bootloader finds by their identifiers and answers with responses before it calls the
+//! entry point in long mode.
+//!
+//! [`boot`] translates the responses into the multiboot2 boot information: the memory map,
+//! modules, framebuffer, ACPI and SMBIOS entry points, command line and section headers of the
+//! kernel. The higher half direct map (HHDM) offset and the processors parked by Limine have no
+//! multiboot2 counterpart and are kept in [`LIMINE_INFO`].
+//!
+//! The rest of the kernel expects to be loaded [`KERNEL_OFFSET`] below it's link address, so if
+//! it is not, the loadable segments are copied there before switching to the boot page tables.
+//! The destination must be usable memory below 4 GiB.
+//!
+//! [`KERNEL_OFFSET`]: crate::kernel_components::memory::layout::KERNEL_OFFSET
+
+use core::cell::UnsafeCell;
+use core::convert::Infallible;
+use core::ffi::{c_char, CStr};
+use core::ptr;
+
+use super::info::{BootInfoBuilder, Framebuffer, smbios_entry_point};
+use super::entry::{build_page_tables, table_pages, jump, halt, P2_COVERAGE};
+use crate::kernel_components::memory::{
+    memory_map::{MemoryArea, MemoryAreaType},
+    sections::{ElfSectionInner64, ElfSectionInner},
+    layout::{kernel_phys, RECURSIVE_INDEX},
+};
+use crate::kernel_components::registers::control::Cr3;
+use crate::{single, PhysicalAddress};
+
+/// Maximal amount of processors reported from the SMP response.
+pub const MAX_CPUS: usize = 64;
+/// Amount of low memory identity mapped by the boot page tables, in GiB.
+const LOW_P2_COUNT: usize = 4;
+/// Size of the buffer for the boot information.
+const INFO_SIZE: usize = 16 * 1024;
+/// Size of the buffer for the section names of the kernel.
+const SECTION_NAMES_SIZE: usize = 4096;
+/// First half of every request identifier.
+const COMMON_MAGIC: [u64; 2] = [0xc7b1dd30df4c8b88, 0x0a82e883a194f07b];
+
+// Memory map entry types.
+const USABLE: u64 = 0;
+const ACPI_RECLAIMABLE: u64 = 2;
+const ACPI_NVS: u64 = 3;
+const BAD_MEMORY: u64 = 4;
+
+#[repr(C, align(4096))]
+struct PageTables([u64; 512 * table_pages(LOW_P2_COUNT)]);
+
+#[repr(C, align(8))]
+struct InfoBuffer([u8; INFO_SIZE]);
+
+// Everything the kernel reads after the jump lives within the image, so it is moved with it.
+static mut TABLES: PageTables = PageTables([0; 512 * table_pages(LOW_P2_COUNT)]);
+static mut INFO: InfoBuffer = InfoBuffer([0; INFO_SIZE]);
+static mut SECTION_NAMES: [u8; SECTION_NAMES_SIZE] = [0; SECTION_NAMES_SIZE];
+
+single! {
+    pub mut LIMINE_INFO: Option<LimineInfo> = None;
+}
+
+/// Information provided by Limine, which does not fit into the multiboot2 boot information.
+#[derive(Debug, Clone, Copy)]
+pub struct LimineInfo {
+    /// Offset of the higher half direct map of the physical memory. The map stays valid until
+    /// the memory is initialized.
+    pub hhdm_offset: usize,
+    /// Processors started and parked by the bootloader.
+    pub smp: Option<SmpInfo>,
+}
+
+/// One processor parked by the bootloader.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cpu {
+    /// ACPI processor UID.
+    pub processor_id: u32,
+    /// Local APIC id of the processor.
+    pub lapic_id: u32,
+    /// Physical address of the field, that starts the processor when an entry address is
+    /// atomically written into it.
+    pub goto_address: PhysicalAddress,
+}
+
+/// Processors reported by the SMP response.
+#[derive(Debug, Clone, Copy)]
+pub struct SmpInfo {
+    /// Local APIC id of the bootstrap processor.
+    pub bsp_lapic_id: u32,
+    /// Checks if the processors were switched to the x2APIC mode.
+    pub x2apic: bool,
+    count: usize,
+    cpus: [Cpu; MAX_CPUS],
+}
+
+impl SmpInfo {
+    /// Returns every reported processor, including the bootstrap one.
+    pub fn cpus(&self) -> &[Cpu] {
+        &self.cpus[..self.count]
+    }
+}
+
+/// Request to the bootloader, which is answered before the entry point is called.
+#[repr(C)]
+pub struct Request<R, E = ()> {
+    id: [u64; 4],
+    revision: u64,
+    response: UnsafeCell<*const R>,
+    extra: E,
+}
+
+unsafe impl<R, E: Sync> Sync for Request<R, E> {}
+
+impl<R, E> Request<R, E> {
+    const fn with(id: [u64; 2], extra: E) -> Self {
+        Self {
+            id: [COMMON_MAGIC[0], COMMON_MAGIC[1], id[0], id[1]],
+            revision: 0,
+            response: UnsafeCell::new(ptr::null()),
+            extra,
+        }
+    }
+
+    /// Returns the response if the bootloader answered the request.
+    fn response(&self) -> Option<&R> {
+        unsafe { ptr::read_volatile(self.response.get()).as_ref() }
+    }
+}
+
+/// Request that overrides the entry point of the ELF file.
+pub type EntryPointRequest = Request<EntryPointResponse, extern "C" fn() -> !>;
+
+impl EntryPointRequest {
+    /// Creates the request with the function, which must call [`boot`].
+    pub const fn new(entry: extern "C" fn() -> !) -> Self {
+        Self::with([0x13d86c035a1cd3e1, 0x2b0caa89d8f3026a], entry)
+    }
+}
+
+/// Base revision of the protocol. The bootloader zeroes the revision if it is supported.
+#[repr(C)]
+struct BaseRevision(UnsafeCell<[u64; 3]>);
+
+unsafe impl Sync for BaseRevision {}
+
+impl BaseRevision {
+    fn is_supported(&self) -> bool {
+        unsafe { ptr::read_volatile(self.0.get())[2] == 0 }
+    }
+}
+
+#[repr(C)]
+pub struct EntryPointResponse {
+    revision: u64,
+}
+
+#[repr(C)]
+struct HhdmResponse {
+    revision: u64,
+    offset: u64,
+}
+
+#[repr(C)]
+struct MemmapResponse {
+    revision: u64,
+    entry_count: u64,
+    entries: *const *const MemmapEntry,
+}
+
+#[repr(C)]
+struct MemmapEntry {
+    base: u64,
+    length: u64,
+    typ: u64,
+}
+
+#[repr(C)]
+struct FramebufferResponse {
+    revision: u64,
+    framebuffer_count: u64,
+    framebuffers: *const *const LimineFramebuffer,
+}
+
+#[repr(C)]
+struct LimineFramebuffer {
+    address: u64,
+    width: u64,
+    height: u64,
+    pitch: u64,
+    bpp: u16,
+    memory_model: u8,
+    red_mask_size: u8,
+    red_mask_shift: u8,
+    green_mask_size: u8,
+    green_mask_shift: u8,
+    blue_mask_size: u8,
+    blue_mask_shift: u8,
+}
+
+#[repr(C)]
+struct File {
+    revision: u64,
+    address: *const u8,
+    size: u64,
+    path: *const c_char,
+    cmdline: *const c_char,
+}
+
+#[repr(C)]
+struct ModuleResponse {
+    revision: u64,
+    module_count: u64,
+    modules: *const *const File,
+}
+
+#[repr(C)]
+struct KernelFileResponse {
+    revision: u64,
+    kernel_file: *const File,
+}
+
+#[repr(C)]
+struct KernelAddressResponse {
+    revision: u64,
+    physical_base: u64,
+    virtual_base: u64,
+}
+
+#[repr(C)]
+struct RsdpResponse {
+    revision: u64,
+    address: u64,
+}
+
+#[repr(C)]
+struct SmbiosResponse {
+    revision: u64,
+    entry_32: u64,
+    entry_64: u64,
+}
+
+#[repr(C)]
+struct SmpResponse {
+    revision: u64,
+    flags: u32,
+    bsp_lapic_id: u32,
+    cpu_count: u64,
+    cpus: *const *const SmpCpu,
+}
+
+#[repr(C)]
+struct SmpCpu {
+    processor_id: u32,
+    lapic_id: u32,
+    _reserved: u64,
+    goto_address: u64,
+    extra_argument: u64,
+}
+
+#[used]
+static BASE_REVISION: BaseRevision = BaseRevision(UnsafeCell::new([0xf9562b2d5c95a6c8, 0x6a7b384944536bdc, 2]));
+#[used]
+static HHDM: Request<HhdmResponse> = Request::with([0x48dcf1cb8ad2b852, 0x63984e959a98244b], ());
+#[used]
+static MEMMAP: Request<MemmapResponse> = Request::with([0x67cf3d9d378a806f, 0xe304acdfc50c3c62], ());
+#[used]
+static FRAMEBUFFER: Request<FramebufferResponse> = Request::with([0x9d5827dcd881dd75, 0xa3148604f6fab11b], ());
+#[used]
+static MODULES: Request<ModuleResponse> = Request::with([0x3e7e279702be32af, 0xca1c4f3bd1280cee], ());
+#[used]
+static KERNEL_FILE: Request<KernelFileResponse> = Request::with([0xad97e90e83f1ed67, 0x31eb5d1c5ff23b69], ());
+#[used]
+static KERNEL_ADDRESS: Request<KernelAddressResponse> = Request::with([0x71ba76863cc55f63, 0xb2644a48c516a487], ());
+#[used]
+static RSDP: Request<RsdpResponse> = Request::with([0xc5e77b6b397e7b43, 0x27637845accdcf3c], ());
+#[used]
+static SMBIOS: Request<SmbiosResponse> = Request::with([0x9e9046f11e095391, 0xaa4a520fefbde5ee], ());
+/// Flags of the request are zero, the processors are left in the xAPIC mode.
+#[used]
+static SMP: Request<SmpResponse, u64> = Request::with([0x95a67b819a1b857e, 0xa0b61b723b6a73e0], 0);
+
+/// Converts the Limine memory map entry into the multiboot2 one.
+///
+/// Bootloader reclaimable memory holds the code of parked processors and the kernel and modules
+/// memory holds the modules, so both are reserved.
+fn memory_area(entry: &MemmapEntry) -> MemoryArea {
+    let typ = match entry.typ {
+        USABLE => MemoryAreaType::Available,
+        ACPI_RECLAIMABLE => MemoryAreaType::AcpiAvailable,
+        ACPI_NVS => MemoryAreaType::ReservedHibernate,
+        BAD_MEMORY => MemoryAreaType::Defective,
+        _ => MemoryAreaType::Reserved,
+    };
+    MemoryArea::new(entry.base, entry.length, typ)
+}
+
+/// The parts of the kernel ELF file needed for the boot information.
+struct Elf<'a> {
+    file: &'a [u8],
+}
+
+impl<'a> Elf<'a> {
+    fn new(file: &'a [u8]) -> Option<Self> {
+        // 64-bit little endian only.
+        if file.get(0..6)? != b"\x7fELF\x02\x01" {
+            return None
+        }
+        Some(Self { file })
+    }
+
+    fn field(&self, at: usize, len: usize) -> Option<usize> {
+        let field = self.file.get(at..at + len)?;
+        Some(field.iter().rev().fold(0, |acc, &b| acc << 8 | b as usize))
+    }
+
+    /// Iterates over the virtual ranges of the loadable segments as start and size.
+    fn segments(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        let (phoff, phentsize, phnum) = (self.field(32, 8), self.field(54, 2), self.field(56, 2));
+        (0..phnum.unwrap_or(0)).filter_map(move |i| {
+            let ph = phoff? + i * phentsize?;
+            // PT_LOAD
+            if self.field(ph, 4)? != 1 {
+                return None
+            }
+            Some((self.field(ph + 16, 8)? as u64, self.field(ph + 40, 8)? as u64))
+        }).filter(|&(_, size)| size != 0)
+    }
+
+    /// Iterates over the section headers.
+    fn sections(&self) -> impl Iterator<Item = ElfSectionInner64> + '_ {
+        let (shoff, shentsize, shnum) = (self.field(40, 8), self.field(58, 2), self.field(60, 2));
+        (0..shnum.unwrap_or(0)).map_while(move |i| {
+            let sh = shoff? + i * shentsize?;
+            let bytes = self.file.get(sh..sh + size_of::<ElfSectionInner64>())?;
+            Some(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const ElfSectionInner64) })
+        })
+    }
+
+    /// Index of the section header string table.
+    fn shstrndx(&self) -> Option<usize> {
+        self.field(62, 2)
+    }
+}
+
+/// Entry of the Limine boot path.
+///
+/// Builds the boot information and calls the kernel entry with it's address. Halts if a
+/// required response is missing or the kernel cannot be moved to it's link address, since
+/// nothing can be printed without the boot information.
+///
+/// # Safety
+///
+/// Must be called once from the entry point requested with [`EntryPointRequest`].
+pub unsafe fn boot(entry: extern "C" fn(usize)) -> ! {
+    match try_boot(entry) {
+        Some(never) => match never {},
+        None => halt(),
+    }
+}
+
+unsafe fn try_boot(entry: extern "C" fn(usize)) -> Option<Infallible> {
+    if !BASE_REVISION.is_supported() {
+        return None
+    }
+
+    let hhdm = HHDM.response()?.offset;
+    // Addresses are either physical or within the direct map, depending on the base revision.
+    let phys = |addr: u64| if addr >= hhdm { addr - hhdm } else { addr };
+    let virt = |addr: u64| (phys(addr) + hhdm) as *const u8;
+
+    let memmap = MEMMAP.response()?;
+    let entries = core::slice::from_raw_parts(memmap.entries, memmap.entry_count as usize);
+    let kernel_file = &*KERNEL_FILE.response()?.kernel_file;
+    let kernel_address = KERNEL_ADDRESS.response()?;
+    let elf = Elf::new(core::slice::from_raw_parts(kernel_file.address, kernel_file.size as usize))?;
+
+    let relocate = kernel_phys(kernel_address.virtual_base as usize) as u64 != kernel_address.physical_base;
+    let low_limit = LOW_P2_COUNT as u64 * P2_COVERAGE;
+    for (start, size) in elf.segments() {
+        let start = kernel_phys(start as usize) as u64;
+        let fits = entries.iter().map(|&e| &*e).any(|e| {
+            e.typ == USABLE && e.base <= start && start + size <= e.base + e.length
+        });
+        if start + size > low_limit || (relocate && !fits) {
+            return None
+        }
+    }
+
+    // The bootloader's higher half, which holds the direct map, is kept up to the recursive entry.
+    let tables = ptr::addr_of_mut!(TABLES.0) as *mut u64;
+    build_page_tables(tables, kernel_phys(tables as usize) as u64, LOW_P2_COUNT);
+    let (p4_frame, _) = Cr3::read();
+    let limine_p4 = virt(p4_frame.start_address() as u64) as *const u64;
+    for i in 256..RECURSIVE_INDEX {
+        *tables.add(i) = *limine_p4.add(i);
+    }
+
+    // Section names are copied into the image, the ELF file is not identity mapped.
+    let shstrndx = elf.shstrndx()?;
+    let names = &mut *ptr::addr_of_mut!(SECTION_NAMES);
+    let strtab = elf.sections().nth(shstrndx)?;
+    let strtab = elf.file.get(strtab.offset() as usize..(strtab.offset() + strtab.size()) as usize)?;
+    let len = strtab.len().min(SECTION_NAMES_SIZE);
+    names[..len].copy_from_slice(&strtab[..len]);
+    let sections = elf.sections().enumerate().map(|(i, mut section)| {
+        if i == shstrndx {
+            section.set_addr(names.as_ptr() as u64);
+        }
+        section
+    });
+
+    let cmdline = if kernel_file.cmdline.is_null() {
+        ""
+    } else {
+        CStr::from_ptr(kernel_file.cmdline).to_str().unwrap_or("")
+    };
+
+    let info = &mut (*ptr::addr_of_mut!(INFO)).0;
+    let mut builder = BootInfoBuilder::new(info).ok()?;
+    builder
+        .command_line(cmdline).ok()?
+        .memory_map(entries.iter().map(|&e| memory_area(&*e))).ok()?
+        .elf_sections(sections, shstrndx as u32).ok()?;
+
+    if let Some(modules) = MODULES.response() {
+        for &module in core::slice::from_raw_parts(modules.modules, modules.module_count as usize) {
+            let module = &*module;
+            let start = phys(module.address as u64);
+            let (Ok(start), Ok(end)) = (u32::try_from(start), u32::try_from(start + module.size)) else {
+                continue
+            };
+            let cmdline = if module.cmdline.is_null() {
+                ""
+            } else {
+                CStr::from_ptr(module.cmdline).to_str().unwrap_or("")
+            };
+            builder.module(start, end, cmdline).ok()?;
+        }
+    }
+
+    let framebuffers = FRAMEBUFFER.response().map_or(&[][..], |r| {
+        core::slice::from_raw_parts(r.framebuffers, r.framebuffer_count as usize)
+    });
+    // Memory model 1 is RGB, the only one defined.
+    if let Some(fb) = framebuffers.iter().map(|&fb| &*fb).find(|fb| fb.memory_model == 1) {
+        builder.framebuffer(&Framebuffer {
+            addr: phys(fb.address),
+            pitch: fb.pitch as u32,
+            width: fb.width as u32,
+            height: fb.height as u32,
+            bpp: fb.bpp as u8,
+            red: (fb.red_mask_shift, fb.red_mask_size),
+            green: (fb.green_mask_shift, fb.green_mask_size),
+            blue: (fb.blue_mask_shift, fb.blue_mask_size),
+        }).ok()?;
+    }
+
+    if let Some(rsdp) = RSDP.response().filter(|r| r.address != 0) {
+        let ptr = virt(rsdp.address);
+        // The revision field tells if it is the extended root pointer.
+        if *ptr.add(15) >= 2 {
+            builder.acpi_new(core::slice::from_raw_parts(ptr, 36)).ok()?;
+        } else {
+            builder.acpi_old(core::slice::from_raw_parts(ptr, 20)).ok()?;
+        }
+    }
+
+    let smbios = SMBIOS.response()
+        .and_then(|r| [r.entry_64, r.entry_32].into_iter().find(|&addr| addr != 0))
+        .and_then(|addr| smbios_entry_point(virt(addr)));
+    if let Some((version, entry)) = smbios {
+        builder.smbios(version.0, version.1, entry).ok()?;
+    }
+
+    // The kernel reads the boot information from the identity mapped low memory.
+    let info = kernel_phys(builder.finish().ok()?.as_ptr() as usize);
+
+    *LIMINE_INFO = Some(LimineInfo {
+        hhdm_offset: hhdm as usize,
+        smp: SMP.response().map(|r| {
+            let mut smp = SmpInfo {
+                bsp_lapic_id: r.bsp_lapic_id,
+                x2apic: r.flags & 1 != 0,
+                count: 0,
+                cpus: [Cpu::default(); MAX_CPUS],
+            };
+            for &cpu in core::slice::from_raw_parts(r.cpus, r.cpu_count as usize).iter().take(MAX_CPUS) {
+                smp.cpus[smp.count] = Cpu {
+                    processor_id: (*cpu).processor_id,
+                    lapic_id: (*cpu).lapic_id,
+                    goto_address: phys(ptr::addr_of!((*cpu).goto_address) as u64) as PhysicalAddress,
+                };
+                smp.count += 1;
+            }
+            smp
+        }),
+    });
+
+    // Nothing within the image may be written from here on, or the copy would be outdated.
+    if relocate {
+        for (start, size) in elf.segments() {
+            let dst = virt(kernel_phys(start as usize) as u64) as *mut u8;
+            ptr::copy_nonoverlapping(start as *const u8, dst, size as usize);
+        }
+    }
+    jump(kernel_phys(tables as usize) as u64, info, entry)
+}
+
+#[test_case]
+fn limine_responses_are_translated() {
+    let entry = |base, length, typ| MemmapEntry { base, length, typ };
+    let area = memory_area(&entry(0x100000, 0x7ee0000, USABLE));
+    assert_eq!((area.start_address(), area.size(), area.area_type()), (0x100000, 0x7ee0000, MemoryAreaType::Available));
+    assert_eq!(memory_area(&entry(0x7fe0000, 0x20000, ACPI_RECLAIMABLE)).area_type(), MemoryAreaType::AcpiAvailable);
+    // Bootloader reclaimable, kernel and modules and framebuffer memory.
+    for typ in [5, 6, 7] {
+        assert_eq!(memory_area(&entry(0, 0x1000, typ)).area_type(), MemoryAreaType::Reserved);
+    }
+
+    // ELF header with two program headers right after it, one of them loadable.
+    let mut file = [0u8; 64 + 2 * 56];
+    file[..6].copy_from_slice(b"\x7fELF\x02\x01");
+    file[32..40].copy_from_slice(&64u64.to_le_bytes());
+    file[54..56].copy_from_slice(&56u16.to_le_bytes());
+    file[56..58].copy_from_slice(&2u16.to_le_bytes());
+    file[64..68].copy_from_slice(&1u32.to_le_bytes());
+    file[64 + 16..64 + 24].copy_from_slice(&0x100000u64.to_le_bytes());
+    file[64 + 40..64 + 48].copy_from_slice(&0x5000u64.to_le_bytes());
+    file[120..124].copy_from_slice(&4u32.to_le_bytes());
+
+    let elf = Elf::new(&file).unwrap();
+    let mut segments = elf.segments();
+    assert_eq!(segments.next(), Some((0x100000, 0x5000)));
+    assert_eq!(segments.next(), None);
+    assert_eq!(elf.sections().count(), 0);
+    assert!(Elf::new(&file[1..]).is_none());
+}
@@ -0,0 +1,247 @@
+//! Progress of the kernel initialization.
+//!
+//! `_start` and the memory initialization report each [`Stage`] they enter with [`progress`].
+//! The stage is drawn as a bar on the bottom row of the VGA text buffer and written as a line with
+//! the time since the first stage to the COM1 UART. When the boot hangs, the screen shows the
+//! last stage entered even on machines without a serial port.
+//!
+//! Nothing here locks or allocates and both outputs are identity mapped before and after the
+//! kernel is remapped, so progress can be reported from within the remapping itself. The bar is
+//! drawn over whatever the logger wrote on that row and scrolls away with the following output.
+//!
+//! The timestamps are kept, and once `_start` is done with [`finish`], the time spent in each
+//! stage is printed as a [`BootProfile`].
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::arch::x86_64::_rdtsc;
+
+use crate::kernel_components::emergency;
+use crate::kernel_components::memory::vdso;
+use crate::kernel_components::vga_buffer::{Color, BUFFER_ADDR, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+/// Width of the bar in cells.
+const BAR_WIDTH: usize = 20;
+/// Attribute of the status row, black on light gray.
+const ATTRIBUTE: u16 = ((Color::LIGHTGRAY as u16) << 4 | Color::BLACK as u16) << 8;
+
+/// Stages of the kernel initialization in the order they are entered.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Early boot allocations and the heap allocator.
+    EarlyMemory,
+    /// Mapping the kernel sections into the new page table.
+    RemapKernel,
+    /// Identity mapping the ACPI tables.
+    MapAcpi,
+    /// Switching to the new page table and preparing the heap.
+    SwitchTable,
+    /// Loading the GDT, TSS and IDT.
+    Descriptors,
+    /// Remapping the PIC or the IO APIC.
+    InterruptController,
+    /// Loading the drivers.
+    Drivers,
+    /// Registering the tunables and applying the command line.
+    Configuration,
+    /// Starting the application processors.
+    Processors,
+    /// Collecting the hardware inventory.
+    Hardware,
+    /// Queueing the first process.
+    FirstProcess,
+}
+
+impl Stage {
+    /// Every stage in the order they are entered.
+    pub const ALL: [Stage; 11] = [
+        Stage::EarlyMemory, Stage::RemapKernel, Stage::MapAcpi, Stage::SwitchTable, Stage::Descriptors,
+        Stage::InterruptController, Stage::Drivers, Stage::Configuration, Stage::Processors,
+        Stage::Hardware, Stage::FirstProcess,
+    ];
+
+    /// Name shown on the screen and in the log.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Stage::EarlyMemory => "early memory",
+            Stage::RemapKernel => "kernel remap",
+            Stage::MapAcpi => "ACPI mapping",
+            Stage::SwitchTable => "table switch",
+            Stage::Descriptors => "GDT and IDT",
+            Stage::InterruptController => "interrupt controller",
+            Stage::Drivers => "drivers",
+            Stage::Configuration => "configuration",
+            Stage::Processors => "processors",
+            Stage::Hardware => "hardware inventory",
+            Stage::FirstProcess => "first process",
+        }
+    }
+}
+
+/// TSC value at which each stage was entered, zero if it was not yet.
+static TIMESTAMPS: [AtomicU64; Stage::ALL.len()] = [const { AtomicU64::new(0) }; Stage::ALL.len()];
+
+/// TSC value at which the initialization finished, zero until then.
+static FINISHED: AtomicU64 = AtomicU64::new(0);
+
+/// Reports that the initialization entered the stage.
+pub fn progress(stage: Stage) {
+    let now = unsafe { _rdtsc() };
+    TIMESTAMPS[stage as usize].store(now, Ordering::Release);
+    let start = timestamp(Stage::ALL[0]).unwrap_or(now);
+
+    let mut line = LineWriter::new();
+    let _ = write!(line, "boot: [{}/{}] {}", stage as usize + 1, Stage::ALL.len(), stage.name());
+    let _ = match vdso::data().snapshot().tsc_per_ms {
+        0 => write!(line, " at +{} cycles", now.wrapping_sub(start)),
+        tsc_per_ms => write!(line, " at +{} ms", now.wrapping_sub(start) / tsc_per_ms),
+    };
+    emergency::serial_write(line.as_bytes());
+    emergency::serial_write(b"\r\n");
+
+    draw_bar(stage);
+}
+
+/// Returns the TSC value at which the stage was entered.
+pub fn timestamp(stage: Stage) -> Option<u64> {
+    match TIMESTAMPS[stage as usize].load(Ordering::Acquire) {
+        0 => None,
+        tsc => Some(tsc),
+    }
+}
+
+/// Returns the stage entered last.
+pub fn current() -> Option<Stage> {
+    Stage::ALL.into_iter().rev().find(|&stage| timestamp(stage).is_some())
+}
+
+/// Marks the end of the last stage and prints the time spent in each one.
+pub fn finish() {
+    FINISHED.store(unsafe { _rdtsc() }, Ordering::Release);
+    crate::println!("{}", BootProfile::collect());
+}
+
+/// Time spent in each stage of the initialization.
+#[derive(Debug, Clone, Copy)]
+pub struct BootProfile {
+    /// Cycles spent in each stage, None if it was not entered.
+    cycles: [Option<u64>; Stage::ALL.len()],
+    tsc_per_ms: u64,
+}
+
+impl BootProfile {
+    /// Collects the durations from the timestamps recorded so far. A stage lasts until the next
+    /// one entered, while the last one lasts until [`finish`], or until now.
+    pub fn collect() -> Self {
+        let end = match FINISHED.load(Ordering::Acquire) {
+            0 => unsafe { _rdtsc() },
+            tsc => tsc,
+        };
+        Self::from_timestamps(Stage::ALL.map(timestamp), end, vdso::data().snapshot().tsc_per_ms)
+    }
+
+    fn from_timestamps(timestamps: [Option<u64>; Stage::ALL.len()], end: u64, tsc_per_ms: u64) -> Self {
+        let mut cycles = [None; Stage::ALL.len()];
+        for (i, start) in timestamps.iter().enumerate() {
+            let Some(start) = start else { continue };
+            let next = timestamps[i + 1..].iter().find_map(|&tsc| tsc).unwrap_or(end);
+            cycles[i] = Some(next.saturating_sub(*start));
+        }
+        Self { cycles, tsc_per_ms }
+    }
+
+    /// Returns the cycles spent in the stage.
+    pub fn cycles(&self, stage: Stage) -> Option<u64> {
+        self.cycles[stage as usize]
+    }
+
+    /// Returns the cycles spent from the first stage until the end.
+    pub fn total(&self) -> u64 {
+        self.cycles.iter().flatten().sum()
+    }
+
+    fn write_duration(&self, f: &mut fmt::Formatter<'_>, cycles: u64) -> fmt::Result {
+        match self.tsc_per_ms {
+            0 => write!(f, "{:>12} cycles", cycles),
+            tsc_per_ms => {
+                let us = cycles as u128 * 1000 / tsc_per_ms as u128;
+                write!(f, "{:>8}.{:03} ms", us / 1000, us % 1000)
+            },
+        }
+    }
+}
+
+impl fmt::Display for BootProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total().max(1);
+        writeln!(f, "Boot time breakdown:")?;
+        for stage in Stage::ALL {
+            let Some(cycles) = self.cycles(stage) else { continue };
+            write!(f, "  {:<22}", stage.name())?;
+            self.write_duration(f, cycles)?;
+            writeln!(f, " {:>3}%", cycles * 100 / total)?;
+        }
+        write!(f, "  {:<22}", "total")?;
+        self.write_duration(f, self.total())
+    }
+}
+
+/// Draws the bar and the name of the stage over the bottom row of the screen.
+fn draw_bar(stage: Stage) {
+    let done = (stage as usize + 1) * BAR_WIDTH / Stage::ALL.len();
+    let mut row = LineWriter::new();
+    let _ = row.write_char('[');
+    (0..BAR_WIDTH).for_each(|cell| { let _ = row.write_char(if cell < done { '#' } else { '.' }); });
+    let _ = write!(row, "] {}", stage.name());
+
+    let cells = unsafe { (BUFFER_ADDR as *mut u16).add((BUFFER_HEIGHT - 1) * BUFFER_WIDTH) };
+    for col in 0..BUFFER_WIDTH {
+        let byte = row.as_bytes().get(col).copied().unwrap_or(b' ');
+        unsafe { cells.add(col).write_volatile(ATTRIBUTE | byte as u16) };
+    }
+}
+
+/// Formats a single line into a fixed buffer, silently cutting it at the width of the screen.
+struct LineWriter {
+    buf: [u8; BUFFER_WIDTH],
+    len: usize,
+}
+
+impl LineWriter {
+    const fn new() -> Self {
+        Self { buf: [0; BUFFER_WIDTH], len: 0 }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Write for LineWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(BUFFER_WIDTH - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn stages_are_ordered() {
+    assert!(Stage::ALL.iter().enumerate().all(|(i, &stage)| stage as usize == i));
+
+    let mut timestamps = [None; Stage::ALL.len()];
+    timestamps[Stage::EarlyMemory as usize] = Some(1000);
+    timestamps[Stage::Descriptors as usize] = Some(3000);
+    timestamps[Stage::Drivers as usize] = Some(6000);
+    let profile = BootProfile::from_timestamps(timestamps, 10_000, 1000);
+    assert_eq!(profile.cycles(Stage::EarlyMemory), Some(2000));
+    assert_eq!(profile.cycles(Stage::RemapKernel), None);
+    assert_eq!(profile.cycles(Stage::Drivers), Some(4000));
+    assert_eq!(profile.total(), 9000);
+
+    let mut line = LineWriter::new();
+    (0..BUFFER_WIDTH + 10).for_each(|_| { let _ = line.write_char('#'); });
+    assert_eq!(line.as_bytes().len(), BUFFER_WIDTH);
+}
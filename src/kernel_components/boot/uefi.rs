@@ -0,0 +1,538 @@
+//! UEFI boot path.
+//!
+//! When the kernel is built as an EFI application (the `uefi` feature and the
+//! `x86_64-unknown-uefi` target), the firmware starts it in long mode with it's own page tables
+//! instead of GRUB. [`boot`] collects everything the kernel needs while boot services are still
+//! available: the memory map, the GOP framebuffer, the ACPI and SMBIOS entry points and the
+//! location of the loaded image. It then exits boot services, writes the same multiboot2 boot
+//! information the multiboot path provides and jumps to the regular kernel entry point, so
+//! [`MMU::init`] and everything after it do not know which path was taken.
+//!
+//! The page tables are built the same way `boot.asm` builds them: the low memory is identity
+//! mapped with 2 MiB pages and the P4 table is mapped recursively. Only the protocols required
+//! for that are defined here.
+//!
+//! The firmware relocates the image to wherever it loads it, so unlike the other paths the kernel
+//! keeps running identity mapped in the lower half.
+//!
+//! [`MMU::init`]: crate::kernel_components::memory::MMU::init
+
+use core::ffi::c_void;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::info::{BootInfoBuilder, BootInfoError, Framebuffer, smbios_entry_point};
+use super::entry::{build_page_tables, table_pages, jump, halt, P2_COVERAGE, IDENTITY_LIMIT};
+use crate::kernel_components::memory::{
+    memory_map::{MemoryArea, MemoryAreaType},
+    sections::{ElfSectionInner64, ElfSectionType, ElfSectionFlags},
+    layout,
+    frames::PAGE_SIZE,
+};
+use crate::kernel_components::arch_x86_64::acpi::rsdp::{RSDP, XSDP};
+
+/// Handle to a firmware object.
+pub type Handle = *mut c_void;
+
+/// Maximal length of the command line taken from the load options.
+const CMDLINE_MAX: usize = 1024;
+/// Additional memory map descriptors reserved for the allocations made after the map is sized.
+const MAP_SLACK: usize = 16;
+/// Room for every tag of the boot information except the memory map and the command line.
+const INFO_RESERVE: usize = 4096;
+
+/// Names of the synthesized kernel sections. Lives within the image, so it stays mapped.
+static SECTION_NAMES: [u8; 18] = *b"\0.image\0.shstrtab\0";
+/// Start and exclusive end of the image, as reported by the loaded image protocol.
+static IMAGE_BOUNDS: [AtomicU64; 2] = [const { AtomicU64::new(0) }; 2];
+
+/// Status code returned by firmware services.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Status(pub usize);
+
+impl Status {
+    const ERROR: usize = 1 << (usize::BITS - 1);
+
+    pub const SUCCESS: Self = Self(0);
+    pub const LOAD_ERROR: Self = Self(Self::ERROR | 1);
+    pub const INVALID_PARAMETER: Self = Self(Self::ERROR | 2);
+    pub const UNSUPPORTED: Self = Self(Self::ERROR | 3);
+    pub const BUFFER_TOO_SMALL: Self = Self(Self::ERROR | 5);
+    pub const OUT_OF_RESOURCES: Self = Self(Self::ERROR | 9);
+    pub const NOT_FOUND: Self = Self(Self::ERROR | 14);
+
+    /// Checks if the status is an error.
+    pub const fn is_error(&self) -> bool {
+        self.0 & Self::ERROR != 0
+    }
+
+    fn result(self) -> Result<(), Status> {
+        if self.is_error() { Err(self) } else { Ok(()) }
+    }
+}
+
+/// Globally unique identifier of protocols and configuration tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Guid(u32, u16, u16, [u8; 8]);
+
+const LOADED_IMAGE_PROTOCOL: Guid = Guid(0x5b1b31a1, 0x9562, 0x11d2, [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]);
+const GRAPHICS_OUTPUT_PROTOCOL: Guid = Guid(0x9042a9de, 0x23dc, 0x4a38, [0x96, 0xfb, 0x7a, 0xde, 0xd0, 0x80, 0x51, 0x6a]);
+const ACPI_TABLE: Guid = Guid(0xeb9d2d30, 0x2d88, 0x11d3, [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]);
+const ACPI_20_TABLE: Guid = Guid(0x8868e871, 0xe4f1, 0x11d3, [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81]);
+const SMBIOS_TABLE: Guid = Guid(0xeb9d2d31, 0x2d88, 0x11d3, [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]);
+const SMBIOS3_TABLE: Guid = Guid(0xf2fd1544, 0x9794, 0x4a2c, [0x99, 0x2e, 0xe5, 0xbb, 0xcf, 0x20, 0xe3, 0x94]);
+
+#[repr(C)]
+struct TableHeader {
+    signature: u64,
+    revision: u32,
+    header_size: u32,
+    crc32: u32,
+    _reserved: u32,
+}
+
+/// EFI system table passed to the image entry point.
+#[repr(C)]
+pub struct SystemTable {
+    hdr: TableHeader,
+    firmware_vendor: *const u16,
+    firmware_revision: u32,
+    console_in_handle: Handle,
+    con_in: *mut c_void,
+    console_out_handle: Handle,
+    con_out: *mut c_void,
+    standard_error_handle: Handle,
+    std_err: *mut c_void,
+    runtime_services: *mut c_void,
+    boot_services: *const BootServices,
+    number_of_table_entries: usize,
+    configuration_table: *const ConfigurationTable,
+}
+
+#[repr(C)]
+struct ConfigurationTable {
+    vendor_guid: Guid,
+    vendor_table: *const c_void,
+}
+
+/// Boot services table. Services which are not used are kept as opaque pointers.
+#[repr(C)]
+struct BootServices {
+    hdr: TableHeader,
+    _tpl: [usize; 2],
+    allocate_pages: unsafe extern "efiapi" fn(u32, u32, usize, *mut u64) -> Status,
+    _free_pages: usize,
+    get_memory_map: unsafe extern "efiapi" fn(*mut usize, *mut u8, *mut usize, *mut usize, *mut u32) -> Status,
+    _pool: [usize; 2],
+    _events: [usize; 6],
+    _protocol_interfaces: [usize; 3],
+    handle_protocol: unsafe extern "efiapi" fn(Handle, *const Guid, *mut *mut c_void) -> Status,
+    _reserved: usize,
+    _handles: [usize; 4],
+    _images: [usize; 4],
+    exit_boot_services: unsafe extern "efiapi" fn(Handle, usize) -> Status,
+    _misc: [usize; 3],
+    _controllers: [usize; 2],
+    _open_protocol: [usize; 3],
+    _library: [usize; 2],
+    locate_protocol: unsafe extern "efiapi" fn(*const Guid, *mut c_void, *mut *mut c_void) -> Status,
+}
+
+/// Allocation type that allocates pages below the provided address.
+const ALLOCATE_MAX_ADDRESS: u32 = 1;
+/// Memory type of data allocated by the image.
+const LOADER_DATA: u32 = 2;
+
+#[repr(C)]
+struct LoadedImage {
+    revision: u32,
+    parent_handle: Handle,
+    system_table: *const SystemTable,
+    device_handle: Handle,
+    file_path: *const c_void,
+    _reserved: *const c_void,
+    load_options_size: u32,
+    load_options: *const u16,
+    image_base: *const c_void,
+    image_size: u64,
+    image_code_type: u32,
+    image_data_type: u32,
+    unload: usize,
+}
+
+#[repr(C)]
+struct GraphicsOutput {
+    _query_mode: usize,
+    _set_mode: usize,
+    _blt: usize,
+    mode: *const GraphicsMode,
+}
+
+#[repr(C)]
+struct GraphicsMode {
+    max_mode: u32,
+    mode: u32,
+    info: *const GraphicsModeInfo,
+    size_of_info: usize,
+    frame_buffer_base: u64,
+    frame_buffer_size: usize,
+}
+
+#[repr(C)]
+struct GraphicsModeInfo {
+    version: u32,
+    horizontal_resolution: u32,
+    vertical_resolution: u32,
+    pixel_format: u32,
+    /// Red, green, blue and reserved masks of the bit mask pixel format.
+    pixel_information: [u32; 4],
+    pixels_per_scan_line: u32,
+}
+
+/// One entry of the UEFI memory map.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct MemoryDescriptor {
+    pub typ: u32,
+    pub physical_start: u64,
+    pub virtual_start: u64,
+    pub number_of_pages: u64,
+    pub attribute: u64,
+}
+
+impl MemoryDescriptor {
+    /// Converts the UEFI memory type into the multiboot2 one.
+    ///
+    /// Boot services memory is free after boot services are exited. Loader memory holds the
+    /// kernel image, the boot information and the boot page tables, so it is reserved.
+    pub fn area_type(&self) -> MemoryAreaType {
+        match self.typ {
+            3 | 4 | 7 => MemoryAreaType::Available,
+            8 => MemoryAreaType::Defective,
+            9 => MemoryAreaType::AcpiAvailable,
+            10 => MemoryAreaType::ReservedHibernate,
+            _ => MemoryAreaType::Reserved,
+        }
+    }
+
+    /// Returns the end of the described memory.
+    pub fn end(&self) -> u64 {
+        self.physical_start + self.number_of_pages * PAGE_SIZE as u64
+    }
+}
+
+/// Memory map obtained from the firmware.
+struct MemoryMap {
+    buf: *mut u8,
+    capacity: usize,
+    size: usize,
+    desc_size: usize,
+    key: usize,
+}
+
+impl MemoryMap {
+    /// Iterates over the descriptors of the map.
+    fn descriptors(&self) -> impl Iterator<Item = MemoryDescriptor> + '_ {
+        (0..self.size / self.desc_size).map(|i| unsafe {
+            ptr::read_unaligned(self.buf.add(i * self.desc_size) as *const MemoryDescriptor)
+        })
+    }
+}
+
+/// Converts UEFI memory descriptors into multiboot2 memory areas.
+///
+/// Firmware maps are fragmented, so adjacent descriptors of the same converted type are merged.
+pub fn memory_areas<I>(descriptors: I) -> impl Iterator<Item = MemoryArea> where
+    I: IntoIterator<Item = MemoryDescriptor>,
+{
+    let mut descriptors = descriptors.into_iter().filter(|d| d.number_of_pages != 0);
+    let mut pending: Option<(u64, u64, MemoryAreaType)> = None;
+
+    core::iter::from_fn(move || loop {
+        let Some(desc) = descriptors.next() else {
+            return pending.take().map(|(start, end, typ)| MemoryArea::new(start, end - start, typ))
+        };
+        let typ = desc.area_type();
+
+        match pending {
+            Some((start, end, pending_typ)) if end == desc.physical_start && pending_typ == typ => {
+                pending = Some((start, desc.end(), typ));
+            },
+            _ => {
+                let done = pending.replace((desc.physical_start, desc.end(), typ));
+                if let Some((start, end, typ)) = done {
+                    return Some(MemoryArea::new(start, end - start, typ))
+                }
+            },
+        }
+    })
+}
+
+/// Returns the start and the exclusive end of the loaded image, or zeros before [`boot`] ran.
+pub fn image_bounds() -> (u64, u64) {
+    (IMAGE_BOUNDS[0].load(Ordering::Relaxed), IMAGE_BOUNDS[1].load(Ordering::Relaxed))
+}
+
+/// Entry of the UEFI boot path.
+///
+/// Exits boot services and calls the kernel entry with the address of the boot information on a
+/// new stack. Returns only if something fails before boot services are exited, in which case the
+/// status is meant to be returned to the firmware.
+///
+/// # Safety
+///
+/// Must be called once from the image entry point with the arguments provided by the firmware.
+pub unsafe fn boot(image: Handle, system_table: *const SystemTable, entry: extern "C" fn(usize)) -> Status {
+    match try_boot(image, &*system_table, entry) {
+        Ok(never) => match never {},
+        Err(status) => status,
+    }
+}
+
+unsafe fn try_boot(
+    image: Handle,
+    st: &SystemTable,
+    entry: extern "C" fn(usize),
+) -> Result<core::convert::Infallible, Status> {
+    let bs = &*st.boot_services;
+
+    let mut loaded_image: *mut c_void = ptr::null_mut();
+    (bs.handle_protocol)(image, &LOADED_IMAGE_PROTOCOL, &mut loaded_image).result()?;
+    let loaded_image = &*(loaded_image as *const LoadedImage);
+    let image_start = loaded_image.image_base as u64;
+    let image_end = image_start + loaded_image.image_size;
+    if image_end > IDENTITY_LIMIT || image_start % PAGE_SIZE as u64 != 0 {
+        return Err(Status::LOAD_ERROR)
+    }
+    IMAGE_BOUNDS[0].store(image_start, Ordering::Relaxed);
+    IMAGE_BOUNDS[1].store(image_end, Ordering::Relaxed);
+
+    let mut cmdline = [0u8; CMDLINE_MAX];
+    let mut found = Firmware {
+        image: (image_start, image_end),
+        cmdline: command_line(loaded_image, &mut cmdline),
+        framebuffer: framebuffer(bs),
+        rsdp: None,
+        xsdp: None,
+        smbios: None,
+    };
+    for table in core::slice::from_raw_parts(st.configuration_table, st.number_of_table_entries) {
+        let ptr = Some(table.vendor_table as *const u8);
+        match table.vendor_guid {
+            ACPI_TABLE => found.rsdp = ptr,
+            ACPI_20_TABLE => found.xsdp = ptr,
+            SMBIOS3_TABLE => found.smbios = ptr,
+            SMBIOS_TABLE if found.smbios.is_none() => found.smbios = ptr,
+            _ => (),
+        }
+    }
+
+    // Sizing the memory map. Every following allocation may split a descriptor.
+    let mut map = MemoryMap { buf: ptr::null_mut(), capacity: 0, size: 0, desc_size: 0, key: 0 };
+    let mut version = 0;
+    let status = (bs.get_memory_map)(&mut map.size, map.buf, &mut map.key, &mut map.desc_size, &mut version);
+    if status != Status::BUFFER_TOO_SMALL || map.desc_size < size_of::<MemoryDescriptor>() {
+        return Err(status)
+    }
+    map.capacity = map.size + MAP_SLACK * map.desc_size;
+    map.buf = allocate(bs, map.capacity)?;
+    get_memory_map(bs, &mut map)?;
+
+    let top = map.descriptors()
+        .map(|d| d.end())
+        .chain(found.framebuffer.map(|(fb, size)| fb.addr + size as u64))
+        .fold(layout::IDENTITY_END as u64, u64::max)
+        .next_multiple_of(P2_COVERAGE)
+        .min(IDENTITY_LIMIT);
+    let p2_count = (top / P2_COVERAGE) as usize;
+    let tables = allocate(bs, table_pages(p2_count) * PAGE_SIZE)? as *mut u64;
+
+    let entries = map.capacity / map.desc_size;
+    let info_size = 8 + entries * size_of::<MemoryArea>() + found.cmdline.len() + INFO_RESERVE;
+    let info = core::slice::from_raw_parts_mut(allocate(bs, info_size)?, info_size.next_multiple_of(PAGE_SIZE));
+
+    build_page_tables(tables, tables as u64, p2_count);
+
+    // The key changes if the firmware modified the map in between, so one retry is allowed.
+    get_memory_map(bs, &mut map)?;
+    if (bs.exit_boot_services)(image, map.key).is_error() {
+        get_memory_map(bs, &mut map)?;
+        (bs.exit_boot_services)(image, map.key).result()?;
+    }
+
+    // Nothing can be reported from here on, as the firmware console is gone.
+    let Ok(info) = build_info(info, &found, &map) else {
+        halt()
+    };
+    jump(tables as u64, info.as_ptr() as usize, entry)
+}
+
+/// Everything obtained from the firmware, that goes into the boot information.
+struct Firmware<'a> {
+    image: (u64, u64),
+    cmdline: &'a str,
+    framebuffer: Option<(Framebuffer, usize)>,
+    rsdp: Option<*const u8>,
+    xsdp: Option<*const u8>,
+    smbios: Option<*const u8>,
+}
+
+/// Writes the boot information after boot services are exited.
+///
+/// The image is described as a single section covering all of it.
+unsafe fn build_info<'a>(
+    buf: &'a mut [u8],
+    found: &Firmware,
+    map: &MemoryMap,
+) -> Result<&'a [u8], BootInfoError> {
+    let (image_start, image_end) = found.image;
+    let sections = [
+        ElfSectionInner64::new(0, ElfSectionType::Unused, ElfSectionFlags::empty(), 0, 0),
+        ElfSectionInner64::new(
+            1, ElfSectionType::ProgramSection,
+            ElfSectionFlags::ALLOCATED | ElfSectionFlags::WRITABLE | ElfSectionFlags::EXECUTABLE,
+            image_start, image_end - image_start,
+        ),
+        ElfSectionInner64::new(
+            8, ElfSectionType::StringTable, ElfSectionFlags::empty(),
+            SECTION_NAMES.as_ptr() as u64, SECTION_NAMES.len() as u64,
+        ),
+    ];
+
+    let mut builder = BootInfoBuilder::new(buf)?;
+    builder
+        .command_line(found.cmdline)?
+        .memory_map(memory_areas(map.descriptors()))?
+        .elf_sections(sections, 2)?;
+    if let Some((fb, _)) = found.framebuffer {
+        builder.framebuffer(&fb)?;
+    }
+    if let Some(rsdp) = found.rsdp {
+        builder.acpi_old(core::slice::from_raw_parts(rsdp, size_of::<RSDP>()))?;
+    }
+    if let Some(xsdp) = found.xsdp {
+        builder.acpi_new(core::slice::from_raw_parts(xsdp, size_of::<XSDP>()))?;
+    }
+    if let Some((version, entry)) = found.smbios.and_then(|ptr| smbios_entry_point(ptr)) {
+        builder.smbios(version.0, version.1, entry)?;
+    }
+    builder.finish()
+}
+
+/// Obtains the current memory map into the buffer of the map.
+unsafe fn get_memory_map(bs: &BootServices, map: &mut MemoryMap) -> Result<(), Status> {
+    let mut version = 0;
+    map.size = map.capacity;
+    (bs.get_memory_map)(&mut map.size, map.buf, &mut map.key, &mut map.desc_size, &mut version).result()
+}
+
+/// Allocates zeroed pages below the end of the boot identity region, which the early boot
+/// allocations expect to be mapped.
+unsafe fn allocate(bs: &BootServices, size: usize) -> Result<*mut u8, Status> {
+    let pages = size.div_ceil(PAGE_SIZE);
+    let mut addr = layout::IDENTITY_END as u64 - 1;
+    (bs.allocate_pages)(ALLOCATE_MAX_ADDRESS, LOADER_DATA, pages, &mut addr).result()?;
+    ptr::write_bytes(addr as *mut u8, 0, pages * PAGE_SIZE);
+    Ok(addr as *mut u8)
+}
+
+/// Converts the UCS-2 load options into the ASCII command line.
+///
+/// Options which are not printable text (boot manager entries may pass binary data) are ignored.
+/// When the image is started from the shell, the first word is the image path, which is skipped.
+fn command_line<'a>(image: &LoadedImage, buf: &'a mut [u8; CMDLINE_MAX]) -> &'a str {
+    if image.load_options.is_null() {
+        return ""
+    }
+    let options = unsafe {
+        core::slice::from_raw_parts(image.load_options, image.load_options_size as usize / 2)
+    };
+    let options = options.split(|&c| c == 0).next().unwrap_or(&[]);
+    if options.len() > CMDLINE_MAX || options.iter().any(|&c| !(0x20..0x7f).contains(&c)) {
+        return ""
+    }
+
+    for (b, &c) in buf.iter_mut().zip(options) {
+        *b = c as u8;
+    }
+    let cmdline = core::str::from_utf8(&buf[..options.len()]).unwrap_or("").trim();
+    let is_path = |word: &str| word.len() >= 4 && word[word.len() - 4..].eq_ignore_ascii_case(".efi");
+    match cmdline.split_once(' ') {
+        Some((first, rest)) if is_path(first) => rest.trim_start(),
+        None if is_path(cmdline) => "",
+        _ => cmdline,
+    }
+}
+
+/// Returns the framebuffer of the current GOP mode and it's size.
+unsafe fn framebuffer(bs: &BootServices) -> Option<(Framebuffer, usize)> {
+    let mut gop: *mut c_void = ptr::null_mut();
+    (bs.locate_protocol)(&GRAPHICS_OUTPUT_PROTOCOL, ptr::null_mut(), &mut gop).result().ok()?;
+    let mode = (*(gop as *const GraphicsOutput)).mode.as_ref()?;
+    let info = mode.info.as_ref()?;
+
+    let (red, green, blue) = match info.pixel_format {
+        // 8 bits per channel in RGB or BGR byte order.
+        0 => ((0, 8), (8, 8), (16, 8)),
+        1 => ((16, 8), (8, 8), (0, 8)),
+        2 => {
+            let [r, g, b, _] = info.pixel_information;
+            (Framebuffer::channel(r), Framebuffer::channel(g), Framebuffer::channel(b))
+        },
+        // Only block transfers are supported, there is no linear framebuffer.
+        _ => return None,
+    };
+    let bpp = match info.pixel_format {
+        2 => (32 - info.pixel_information.iter().fold(0, |a, m| a | m).leading_zeros()).next_multiple_of(8) as u8,
+        _ => 32,
+    };
+
+    Some((Framebuffer {
+        addr: mode.frame_buffer_base,
+        pitch: info.pixels_per_scan_line * bpp as u32 / 8,
+        width: info.horizontal_resolution,
+        height: info.vertical_resolution,
+        bpp,
+        red,
+        green,
+        blue,
+    }, mode.frame_buffer_size))
+}
+
+#[test_case]
+fn uefi_memory_map_is_converted() {
+    use alloc::vec::Vec;
+
+    let desc = |typ, start: u64, pages| MemoryDescriptor {
+        typ, physical_start: start, virtual_start: 0, number_of_pages: pages, attribute: 0,
+    };
+    let descriptors = [
+        // Conventional memory followed by boot services code and data is merged.
+        desc(7, 0, 0x9f),
+        desc(3, 0x9f000, 0x1),
+        desc(0, 0xa0000, 0x60),
+        desc(7, 0x100000, 0x100),
+        desc(4, 0x200000, 0x100),
+        desc(0, 0x300000, 0),
+        // Loader data holds the boot information and stays reserved.
+        desc(2, 0x300000, 0x10),
+        desc(9, 0x7fe0000, 0x20),
+        desc(10, 0x7ff0000, 0x10),
+    ];
+
+    let areas: Vec<_> = memory_areas(descriptors).collect();
+    let expected = [
+        (0, 0xa0000, MemoryAreaType::Available),
+        (0xa0000, 0x60000, MemoryAreaType::Reserved),
+        (0x100000, 0x200000, MemoryAreaType::Available),
+        (0x300000, 0x10000, MemoryAreaType::Reserved),
+        (0x7fe0000, 0x20000, MemoryAreaType::AcpiAvailable),
+        (0x7ff0000, 0x10000, MemoryAreaType::ReservedHibernate),
+    ];
+    assert_eq!(areas.len(), expected.len());
+    for (area, &(start, size, typ)) in areas.iter().zip(&expected) {
+        assert_eq!((area.start_address(), area.size(), area.area_type()), (start, size, typ));
+    }
+}
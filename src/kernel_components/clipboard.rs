@@ -0,0 +1,160 @@
+/// Kernel clipboard.
+///
+/// Holds the last copied text, for example the mouse selection on the console, so it can be pasted
+/// into any terminal later. The clipboard is a single kernel wide buffer, therefore every terminal
+/// sees the same content. It is bounded by [`CLIPBOARD_SIZE`], longer text is truncated.
+///
+/// The content is copied and pasted with the mouse selection and keyboard shortcuts registered by
+/// [`register_shortcuts`]:
+///
+/// - Ctrl+Shift+C copies the current mouse selection;
+/// - Ctrl+Shift+V pastes the clipboard into the terminal input.
+///
+/// User programs access the clipboard as a character device with [`read`] and [`write`]. Those
+/// follow the file semantics, so they can back the "clipboard" node once the kernel gets a device
+/// file system.
+
+use alloc::string::String;
+use core::fmt::Display;
+use core::error::Error;
+
+use crate::kernel_components::drivers::keyboards::{keyboard::register_shortcut, Key, ShortcutModifiers};
+use crate::kernel_components::keyboard_interface::OS_CHAR_BUFFER;
+use crate::kernel_components::sync::Mutex;
+use crate::kernel_components::vga_buffer::LOGGER;
+use crate::critical_section;
+
+/// Maximal amount of bytes held by the clipboard.
+pub const CLIPBOARD_SIZE: usize = 4096;
+/// Name of the clipboard character device.
+pub const DEVICE_NAME: &str = "clipboard";
+
+/// Content of the clipboard.
+static CLIPBOARD: Mutex<String> = Mutex::new(String::new());
+
+/// Custom error type for the clipboard device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardError {
+    /// Written bytes are not a valid UTF-8 text.
+    InvalidText,
+}
+
+impl Display for ClipboardError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidText => write!(f, "Only UTF-8 text can be written into the clipboard."),
+        }
+    }
+}
+
+impl Error for ClipboardError {}
+
+/// Replaces the content of the clipboard.
+///
+/// Text longer than [`CLIPBOARD_SIZE`] is truncated at the last whole character. Returns the
+/// amount of stored bytes.
+pub fn copy(text: &str) -> usize {
+    let len = floor_char_boundary(text, CLIPBOARD_SIZE);
+    critical_section!(|| {
+        let mut clipboard = CLIPBOARD.lock();
+        clipboard.clear();
+        clipboard.push_str(&text[..len]);
+    });
+    len
+}
+
+/// Returns a copy of the clipboard content.
+pub fn paste() -> String {
+    critical_section!(|| CLIPBOARD.lock().clone())
+}
+
+/// Returns the amount of bytes in the clipboard.
+pub fn len() -> usize {
+    critical_section!(|| CLIPBOARD.lock().len())
+}
+
+/// Clears the clipboard.
+pub fn clear() {
+    critical_section!(|| CLIPBOARD.lock().clear());
+}
+
+/// Copies the text highlighted by the mouse into the clipboard.
+pub fn copy_selection() {
+    let text = critical_section!(|| LOGGER.lock().selected_text());
+    if !text.is_empty() {
+        copy(&text);
+    }
+}
+
+/// Pastes the clipboard content into the terminal input, as if it was typed.
+pub fn paste_into_terminal() {
+    let text = paste();
+    critical_section!(|| {
+        let mut input = unsafe { OS_CHAR_BUFFER.lock() };
+        text.chars().for_each(|c| unsafe { input.append(c) });
+    });
+}
+
+/// Registers the copy and paste keyboard shortcuts for every keyboard driver.
+pub fn register_shortcuts() {
+    register_shortcut(ShortcutModifiers::CTRL | ShortcutModifiers::SHIFT, Key::C, copy_selection);
+    register_shortcut(ShortcutModifiers::CTRL | ShortcutModifiers::SHIFT, Key::V, paste_into_terminal);
+}
+
+/// Reads the clipboard content from the byte offset into the buffer.
+///
+/// Returns the amount of read bytes, zero once the offset reaches the end of the content.
+pub fn read(offset: usize, buf: &mut [u8]) -> usize {
+    critical_section!(|| {
+        let clipboard = CLIPBOARD.lock();
+        let bytes = clipboard.as_bytes().get(offset..).unwrap_or_default();
+        let len = bytes.len().min(buf.len());
+        buf[..len].copy_from_slice(&bytes[..len]);
+        len
+    })
+}
+
+/// Replaces the clipboard content with the written bytes.
+///
+/// Returns the amount of stored bytes, which is less than the length of the buffer if it does not
+/// fit into the clipboard.
+pub fn write(buf: &[u8]) -> Result<usize, ClipboardError> {
+    // A character cut by the size limit is not an error.
+    let text = match core::str::from_utf8(buf) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() || e.valid_up_to() >= CLIPBOARD_SIZE => unsafe {
+            core::str::from_utf8_unchecked(&buf[..e.valid_up_to()])
+        },
+        Err(_) => return Err(ClipboardError::InvalidText),
+    };
+    Ok(copy(text))
+}
+
+/// Returns the largest character boundary of the text, which is not greater than the index.
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    if index >= text.len() {
+        return text.len()
+    }
+    (0..=index).rev().find(|&i| text.is_char_boundary(i)).unwrap_or(0)
+}
+
+#[test_case]
+fn clipboard_is_bounded() {
+    let previous = paste();
+
+    assert_eq!(copy("notOS"), 5);
+    let mut buf = [0; 4];
+    assert_eq!(read(0, &mut buf), 4);
+    assert_eq!(&buf, b"notO");
+    assert_eq!(read(4, &mut buf), 1);
+    assert_eq!(read(5, &mut buf), 0);
+    assert_eq!(read(9, &mut buf), 0);
+
+    let long = "é".repeat(CLIPBOARD_SIZE);
+    assert_eq!(copy(&long), CLIPBOARD_SIZE);
+    assert_eq!(len(), CLIPBOARD_SIZE);
+    assert_eq!(write(&long.as_bytes()[..CLIPBOARD_SIZE + 1]), Ok(CLIPBOARD_SIZE));
+    assert_eq!(write(b"\xffnot text"), Err(ClipboardError::InvalidText));
+
+    copy(&previous);
+}
@@ -0,0 +1,129 @@
+/// Snapshots of the console output for bug reports.
+///
+/// A snapshot is the text currently shown by the VGA text console, or the raw content of a linear
+/// framebuffer encoded as a binary PPM image. Snapshots are streamed to the COM1 serial port
+/// between the [`BEGIN_MARKER`] and [`END_MARKER`] lines, so they can be cut out of the captured
+/// serial log on the host.
+///
+/// The text snapshot reads the VGA memory directly and takes no locks, therefore it is also taken
+/// by the panic handler before the emergency output overwrites the screen. The kernel has no file
+/// system to store snapshots in yet, so the shell can only send them to the serial port or copy
+/// the text into the [`clipboard`].
+///
+/// [`clipboard`]: crate::kernel_components::clipboard
+
+use alloc::string::String;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::kernel_components::emergency::serial_write;
+use crate::kernel_components::gfx::LinearFramebuffer;
+use crate::kernel_components::vga_buffer::{BUFFER_ADDR, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+/// Line written before the snapshot data.
+pub const BEGIN_MARKER: &str = "-----BEGIN CONSOLE SNAPSHOT-----\r\n";
+/// Line written after the snapshot data.
+pub const END_MARKER: &str = "\r\n-----END CONSOLE SNAPSHOT-----\r\n";
+
+/// Set once the panic snapshot was taken, so a nested panic does not repeat it.
+static PANIC_SNAPSHOT: AtomicBool = AtomicBool::new(false);
+
+/// Writes the text of the VGA console.
+///
+/// Trailing spaces of each row are dropped and characters which are not printable ASCII are
+/// replaced with '?'.
+pub fn write_text(out: &mut impl fmt::Write) -> fmt::Result {
+    let buffer = BUFFER_ADDR as *const u16;
+    for row in 0..BUFFER_HEIGHT {
+        let mut line = [b' '; BUFFER_WIDTH];
+        for (col, byte) in line.iter_mut().enumerate() {
+            let c = unsafe { buffer.add(row * BUFFER_WIDTH + col).read_volatile() } as u8;
+            *byte = if c.is_ascii_graphic() || c == b' ' { c } else { b'?' };
+        }
+        // Only ASCII was kept, so the line is always valid.
+        let line = core::str::from_utf8(&line).unwrap_or_default();
+        out.write_str(line.trim_end())?;
+        out.write_char('\n')?;
+    }
+    Ok(())
+}
+
+/// Returns the text of the VGA console.
+pub fn text() -> String {
+    let mut text = String::with_capacity((BUFFER_WIDTH + 1) * BUFFER_HEIGHT);
+    let _ = write_text(&mut text);
+    text
+}
+
+/// Encodes the content of the framebuffer as a binary PPM (P6) image.
+///
+/// The image is produced row by row into the sink, so no buffer of the whole picture is needed.
+pub fn write_ppm(fb: &LinearFramebuffer, out: &mut impl FnMut(&[u8])) {
+    let mut header = String::new();
+    let _ = fmt::Write::write_fmt(&mut header, format_args!("P6\n{} {}\n255\n", fb.width(), fb.height()));
+    out(header.as_bytes());
+
+    let mut row = [0u8; 3 * 64];
+    for y in 0..fb.height() {
+        for x in (0..fb.width()).step_by(64) {
+            let count = (fb.width() - x).min(64);
+            for i in 0..count {
+                let (r, g, b) = fb.rgb(fb.get_pixel(x + i, y));
+                row[i * 3..i * 3 + 3].copy_from_slice(&[r, g, b]);
+            }
+            out(&row[..count * 3]);
+        }
+    }
+}
+
+/// Sends the text of the VGA console to the serial port.
+pub fn snapshot() {
+    struct Serial;
+    impl fmt::Write for Serial {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for line in s.split_inclusive('\n') {
+                match line.strip_suffix('\n') {
+                    Some(line) => { serial_write(line.as_bytes()); serial_write(b"\r\n") },
+                    None => serial_write(line.as_bytes()),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    serial_write(BEGIN_MARKER.as_bytes());
+    let _ = write_text(&mut Serial);
+    serial_write(END_MARKER.as_bytes());
+}
+
+/// Sends the content of the framebuffer to the serial port as a binary PPM image.
+pub fn snapshot_framebuffer(fb: &LinearFramebuffer) {
+    serial_write(BEGIN_MARKER.as_bytes());
+    write_ppm(fb, &mut |bytes| serial_write(bytes));
+    serial_write(END_MARKER.as_bytes());
+}
+
+/// Takes the text snapshot for the panic report. Only the first call does anything.
+pub fn snapshot_on_panic() {
+    if !PANIC_SNAPSHOT.swap(true, Ordering::AcqRel) {
+        snapshot();
+    }
+}
+
+#[test_case]
+fn framebuffer_is_encoded_as_ppm() {
+    use alloc::vec::Vec;
+    use crate::kernel_components::boot::info::Framebuffer;
+
+    let mut memory = [0u32, 0xff0000, 0x00ff00, 0x0000ff];
+    let info = Framebuffer {
+        addr: 0, pitch: 8, width: 2, height: 2, bpp: 32,
+        red: (16, 8), green: (8, 8), blue: (0, 8),
+    };
+    let fb = unsafe { LinearFramebuffer::new(memory.as_mut_ptr() as *mut u8, info) };
+
+    let mut image = Vec::new();
+    write_ppm(&fb, &mut |bytes| image.extend_from_slice(bytes));
+    assert_eq!(&image[..11], b"P6\n2 2\n255\n");
+    assert_eq!(&image[11..], &[0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255]);
+}
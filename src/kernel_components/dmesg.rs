@@ -0,0 +1,266 @@
+//! Ring buffer of the kernel log lines.
+//!
+//! Every log record which passes the filters is split into lines, and each line is stored with
+//! the TSC value at which it was logged, it's level and target. Once the ring is full, the oldest
+//! line is overwritten. The boot messages, the drivers loaded and whatever else scrolled off the
+//! screen are printed again with the "dmesg" shell command.
+//!
+//! Writers never wait. Each line claims the next sequence number, which picks the slot, and
+//! publishes the slot once it is filled. Readers copy a slot and keep the copy only if the slot
+//! still holds the same line afterwards, so they never block the writers either. A line is
+//! dropped only if it's slot is still being written by a writer a whole ring behind.
+
+use core::fmt::{self, Display, Write};
+use core::sync::atomic::{fence, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use core::arch::x86_64::_rdtsc;
+
+use crate::kernel_components::boot::progress::{self, Stage};
+use crate::kernel_components::logging::{LogSink, Record};
+use crate::kernel_components::memory::vdso;
+use crate::kernel_components::vga_buffer::LogLevel;
+
+/// Amount of lines kept.
+pub const DMESG_LINES: usize = 256;
+/// Longest line stored, the target included. Longer lines are cut.
+pub const LINE_LEN: usize = 120;
+
+/// Words of the text of a single line.
+const WORDS: usize = LINE_LEN / 8;
+/// Lowest bit of the slot state, set while the slot is written.
+const BUSY: u64 = 1;
+
+/// Slot of the ring. The state is the sequence number of the line stored plus one, shifted left
+/// by one, or zero if the slot was never written.
+struct Slot {
+    state: AtomicU64,
+    tsc: AtomicU64,
+    level: AtomicU8,
+    len: AtomicU8,
+    text: [AtomicU64; WORDS],
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            state: AtomicU64::new(0),
+            tsc: AtomicU64::new(0),
+            level: AtomicU8::new(0),
+            len: AtomicU8::new(0),
+            text: [const { AtomicU64::new(0) }; WORDS],
+        }
+    }
+}
+
+/// Lock free ring buffer of timestamped log lines.
+pub struct DmesgRing {
+    slots: [Slot; DMESG_LINES],
+    /// Sequence number of the next line.
+    head: AtomicU64,
+    /// Lines before this sequence number are not read anymore.
+    cleared: AtomicU64,
+    /// Lines dropped because their slot was busy.
+    dropped: AtomicUsize,
+}
+
+/// The kernel message ring, registered as the "dmesg" log sink.
+pub static DMESG: DmesgRing = DmesgRing::new();
+
+impl DmesgRing {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { Slot::new() }; DMESG_LINES],
+            head: AtomicU64::new(0),
+            cleared: AtomicU64::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Stores a single line, which must not be longer than [`LINE_LEN`].
+    fn push(&self, tsc: u64, level: LogLevel, line: &[u8]) {
+        let seq = self.head.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[seq as usize % DMESG_LINES];
+
+        let state = slot.state.load(Ordering::Relaxed);
+        let claimed = (seq + 1) << 1 | BUSY;
+        if state & BUSY != 0
+            || slot.state.compare_exchange(state, claimed, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return
+        }
+        fence(Ordering::Release);
+
+        slot.tsc.store(tsc, Ordering::Relaxed);
+        slot.level.store(level as u8, Ordering::Relaxed);
+        slot.len.store(line.len() as u8, Ordering::Relaxed);
+        for (word, chunk) in slot.text.iter().zip(line.chunks(8)) {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            word.store(u64::from_le_bytes(bytes), Ordering::Relaxed);
+        }
+
+        slot.state.store(claimed & !BUSY, Ordering::Release);
+    }
+
+    /// Copies the line with the sequence number, if it is still stored.
+    fn get(&self, seq: u64) -> Option<Message> {
+        let slot = &self.slots[seq as usize % DMESG_LINES];
+        let state = slot.state.load(Ordering::Acquire);
+        if state != (seq + 1) << 1 {
+            return None
+        }
+
+        let mut text = [0; LINE_LEN];
+        for (chunk, word) in text.chunks_mut(8).zip(slot.text.iter()) {
+            chunk.copy_from_slice(&word.load(Ordering::Relaxed).to_le_bytes());
+        }
+        let message = Message {
+            seq,
+            tsc: slot.tsc.load(Ordering::Relaxed),
+            level: *LogLevel::ALL.get(slot.level.load(Ordering::Relaxed) as usize)?,
+            len: (slot.len.load(Ordering::Relaxed) as usize).min(LINE_LEN),
+            text,
+        };
+
+        fence(Ordering::Acquire);
+        (slot.state.load(Ordering::Relaxed) == state).then_some(message)
+    }
+
+    /// Passes the stored lines, oldest first, to the function. Lines overwritten while reading
+    /// are skipped.
+    pub fn for_each(&self, mut f: impl FnMut(&Message)) {
+        let head = self.head.load(Ordering::Acquire);
+        let start = head.saturating_sub(DMESG_LINES as u64).max(self.cleared.load(Ordering::Relaxed));
+        (start..head).filter_map(|seq| self.get(seq)).for_each(|message| f(&message));
+    }
+
+    /// Hides every line stored so far from the readers.
+    pub fn clear(&self) {
+        self.cleared.fetch_max(self.head.load(Ordering::Acquire), Ordering::Relaxed);
+    }
+
+    /// Returns the amount of lines logged since boot.
+    pub fn logged(&self) -> u64 {
+        self.head.load(Ordering::Relaxed)
+    }
+
+    /// Returns the amount of lines dropped because their slot was busy.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl LogSink for DmesgRing {
+    fn name(&self) -> &'static str {
+        "dmesg"
+    }
+
+    fn write(&self, record: &Record) {
+        let mut lines = LineSplitter {
+            ring: self,
+            tsc: unsafe { _rdtsc() },
+            level: record.level,
+            buf: [0; LINE_LEN],
+            len: 0,
+            prefix: 0,
+        };
+        let _ = write!(lines, "{}: ", record.target);
+        lines.prefix = lines.len;
+        let _ = write!(lines, "{}", record.args);
+        lines.flush();
+    }
+}
+
+/// Cuts the formatted record into lines, each one prefixed with the target.
+struct LineSplitter<'a> {
+    ring: &'a DmesgRing,
+    tsc: u64,
+    level: LogLevel,
+    buf: [u8; LINE_LEN],
+    len: usize,
+    /// Length of the target prefix, which is kept for every following line.
+    prefix: usize,
+}
+
+impl LineSplitter<'_> {
+    /// Stores the pending line, unless nothing was written after the prefix.
+    fn flush(&mut self) {
+        if self.len > self.prefix {
+            self.ring.push(self.tsc, self.level, &self.buf[..self.len]);
+        }
+        self.len = self.prefix;
+    }
+}
+
+impl Write for LineSplitter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '\n' => self.flush(),
+                c if self.len + c.len_utf8() <= LINE_LEN => {
+                    self.len += c.encode_utf8(&mut self.buf[self.len..]).len();
+                },
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copy of a stored line.
+#[derive(Debug, Clone, Copy)]
+pub struct Message {
+    pub seq: u64,
+    /// TSC value at which the line was logged.
+    pub tsc: u64,
+    pub level: LogLevel,
+    len: usize,
+    text: [u8; LINE_LEN],
+}
+
+impl Message {
+    /// Returns the target and the text of the line.
+    pub fn text(&self) -> &str {
+        core::str::from_utf8(&self.text[..self.len]).unwrap_or("")
+    }
+}
+
+impl Display for Message {
+    /// Prints the line with the time since boot, in seconds once the TSC is calibrated.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_boot = self.tsc.saturating_sub(progress::timestamp(Stage::EarlyMemory).unwrap_or(0));
+        match vdso::data().snapshot().tsc_per_ms {
+            0 => write!(f, "[{:>14}]", since_boot)?,
+            tsc_per_ms => {
+                let us = since_boot as u128 * 1000 / tsc_per_ms as u128;
+                write!(f, "[{:>7}.{:06}]", us / 1_000_000, us % 1_000_000)?
+            },
+        }
+        write!(f, " {:<7} {}", self.level.name(), self.text())
+    }
+}
+
+#[test_case]
+fn lines_are_split_and_overwritten() {
+    static RING: DmesgRing = DmesgRing::new();
+
+    RING.write(&Record { level: LogLevel::Warning, target: "drivers", args: format_args!("first\nsecond\n") });
+    let mut lines = 0;
+    RING.for_each(|message| {
+        assert_eq!(message.level, LogLevel::Warning);
+        assert_eq!(message.text(), ["drivers: first", "drivers: second"][lines]);
+        lines += 1;
+    });
+    assert_eq!(lines, 2);
+
+    let long = [b'x'; LINE_LEN];
+    for _ in 0..DMESG_LINES {
+        RING.push(0, LogLevel::Info, &long);
+    }
+    assert!(RING.get(0).is_none());
+    assert_eq!(RING.get(DMESG_LINES as u64 + 1).map(|message| message.text().len()), Some(LINE_LEN));
+
+    RING.clear();
+    RING.for_each(|_| panic!("cleared line was read"));
+    assert_eq!(RING.logged(), DMESG_LINES as u64 + 2);
+    assert_eq!(RING.dropped(), 0);
+}
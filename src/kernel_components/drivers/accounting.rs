@@ -0,0 +1,169 @@
+//! Memory accounting of drivers.
+//!
+//! Heap allocations and blocks of frames from [`MMU::alloc_pages`] are charged to the driver
+//! whose context is entered on the running processor. The [`DriverManager`] enters the context of
+//! a driver while probing and loading it, while it's hooks run and while it's IRQ handlers
+//! registered with [`DriverManager::register_irq`] run. Contexts nest, so an interrupt in the
+//! middle of a probe is charged to the driver of the interrupt and the probe continues in it's own
+//! context afterwards.
+//!
+//! The allocator does not remember the owner of each block, so memory is uncharged from the
+//! context in which it is freed. Memory a driver hands over to the rest of the kernel therefore
+//! stays charged to it. Drivers are dropped within their context when unloaded, so anything still
+//! charged after that is most likely a leak, which is reported.
+//!
+//! The context belongs to the processor, not to the thread. A probe which sleeps lets the other
+//! threads allocate on the driver's account meanwhile.
+//!
+//! [`MMU::alloc_pages`]: crate::kernel_components::memory::MMU::alloc_pages
+//! [`DriverManager`]: super::DriverManager
+//! [`DriverManager::register_irq`]: super::DriverManager::register_irq
+
+use core::sync::atomic::{AtomicIsize, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use crate::kernel_components::arch_x86_64::smp::{self, MAX_CPUS};
+
+use super::DriverType;
+
+/// Context value of a processor not running driver code.
+const NO_DRIVER: u8 = u8::MAX;
+
+/// Driver whose context is entered on each processor.
+static CONTEXT: [AtomicU8; MAX_CPUS] = [const { AtomicU8::new(NO_DRIVER) }; MAX_CPUS];
+/// Amount of contexts entered on all processors, so allocations outside of drivers skip the
+/// lookup of the processor.
+static ENTERED: AtomicUsize = AtomicUsize::new(0);
+/// Charged memory of each driver type.
+static ACCOUNTS: [Account; DriverType::COUNT] = [const { Account::new() }; DriverType::COUNT];
+
+/// Counters of a single driver.
+struct Account {
+    heap_bytes: AtomicIsize,
+    peak_bytes: AtomicIsize,
+    allocations: AtomicU64,
+    frees: AtomicU64,
+    frames: AtomicIsize,
+}
+
+impl Account {
+    const fn new() -> Self {
+        Self {
+            heap_bytes: AtomicIsize::new(0),
+            peak_bytes: AtomicIsize::new(0),
+            allocations: AtomicU64::new(0),
+            frees: AtomicU64::new(0),
+            frames: AtomicIsize::new(0),
+        }
+    }
+}
+
+/// Memory charged to a driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemUsage {
+    /// Heap bytes allocated minus the ones freed in the context of the driver.
+    pub heap_bytes: isize,
+    /// Highest amount of heap bytes charged at once.
+    pub peak_bytes: isize,
+    /// Amount of heap allocations.
+    pub allocations: u64,
+    /// Amount of heap frees.
+    pub frees: u64,
+    /// Frames of blocks allocated minus the ones freed.
+    pub frames: isize,
+}
+
+impl MemUsage {
+    /// Checks if the driver still has any memory charged.
+    pub fn is_leaking(&self) -> bool {
+        self.heap_bytes > 0 || self.frames > 0
+    }
+}
+
+/// Runs the function in the allocation context of the driver.
+pub fn with_context<R>(dtype: DriverType, f: impl FnOnce() -> R) -> R {
+    let cpu = smp::cpu_id();
+    ENTERED.fetch_add(1, Ordering::AcqRel);
+    let previous = CONTEXT[cpu].swap(dtype as u8, Ordering::AcqRel);
+    let output = f();
+    CONTEXT[cpu].store(previous, Ordering::Release);
+    ENTERED.fetch_sub(1, Ordering::AcqRel);
+    output
+}
+
+/// Returns the account of the context entered on the running processor.
+fn current() -> Option<&'static Account> {
+    if ENTERED.load(Ordering::Acquire) == 0 {
+        return None
+    }
+    ACCOUNTS.get(CONTEXT[smp::cpu_id()].load(Ordering::Acquire) as usize)
+}
+
+/// Charges a heap allocation to the current driver, if any.
+pub fn charge_heap(size: usize) {
+    if let Some(account) = current() {
+        let bytes = account.heap_bytes.fetch_add(size as isize, Ordering::Relaxed) + size as isize;
+        account.peak_bytes.fetch_max(bytes, Ordering::Relaxed);
+        account.allocations.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Uncharges a freed heap allocation from the current driver, if any.
+pub fn uncharge_heap(size: usize) {
+    if let Some(account) = current() {
+        account.heap_bytes.fetch_sub(size as isize, Ordering::Relaxed);
+        account.frees.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Charges the amount of allocated frames, negative if freed, to the current driver, if any.
+pub fn charge_frames(frames: isize) {
+    if let Some(account) = current() {
+        account.frames.fetch_add(frames, Ordering::Relaxed);
+    }
+}
+
+/// Returns the memory charged to the driver.
+pub fn usage(dtype: DriverType) -> MemUsage {
+    let account = &ACCOUNTS[dtype as usize];
+    MemUsage {
+        heap_bytes: account.heap_bytes.load(Ordering::Relaxed),
+        peak_bytes: account.peak_bytes.load(Ordering::Relaxed),
+        allocations: account.allocations.load(Ordering::Relaxed),
+        frees: account.frees.load(Ordering::Relaxed),
+        frames: account.frames.load(Ordering::Relaxed),
+    }
+}
+
+/// Clears the counters of the driver, before a new driver of the type is loaded.
+pub fn reset(dtype: DriverType) {
+    let account = &ACCOUNTS[dtype as usize];
+    account.heap_bytes.store(0, Ordering::Relaxed);
+    account.peak_bytes.store(0, Ordering::Relaxed);
+    account.allocations.store(0, Ordering::Relaxed);
+    account.frees.store(0, Ordering::Relaxed);
+    account.frames.store(0, Ordering::Relaxed);
+}
+
+#[test_case]
+fn allocations_are_charged_to_the_entered_context() {
+    use alloc::boxed::Box;
+
+    let (serial, net) = (DriverType::Serial, DriverType::Net);
+    reset(serial);
+    reset(net);
+
+    let boxed = with_context(serial, || Box::new([0u8; 64]));
+    assert!(current().is_none());
+    assert_eq!((usage(serial).heap_bytes, usage(serial).allocations), (64, 1));
+    assert!(usage(serial).is_leaking());
+
+    with_context(serial, || {
+        with_context(net, || drop(boxed));
+        assert!(core::ptr::eq(current().unwrap(), &ACCOUNTS[serial as usize]));
+    });
+    assert_eq!((usage(serial).heap_bytes, usage(serial).peak_bytes), (64, 64));
+    assert_eq!((usage(net).heap_bytes, usage(net).frees), (-64, 1));
+
+    reset(serial);
+    reset(net);
+}
@@ -0,0 +1,151 @@
+/// A module that defines a global interface to the interrupt controller.
+///
+/// Drivers must not care whether the legacy PIC or the APIC routes their interrupts. Every
+/// controller implements [`InterruptController`] and the currently active one is obtained with
+/// [`with_controller`], so masking an IRQ line, sending an EOI or finding the vector of a device
+/// looks the same on every configuration. The IO APIC is active once it is initialized, the PIC
+/// otherwise.
+
+use crate::kernel_components::arch_x86_64::controllers::{
+    pic::{ChainedPics, IrqMask},
+    ioapic::IO_APIC,
+    PROGRAMMABLE_INTERRUPT_CONTROLLER,
+};
+use crate::critical_section;
+
+/// Error type for interrupt controller operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IntCtrlError {
+    /// The IRQ line does not exist on the controller.
+    InvalidIrq(u8),
+    /// The global system interrupt is not routed by the controller.
+    InvalidGsi(u32),
+    /// The controller is not able to perform the requested operation.
+    Unsupported,
+    /// No interrupt controller is initialized yet.
+    NotInitialized,
+}
+
+/// An interrupt controller trait.
+///
+/// IRQ numbers are the input lines of the controller. Global system interrupts (GSI) are the
+/// system wide interrupt numbers used by ACPI, which are identical to the ISA IRQ numbers on
+/// the legacy PIC.
+pub trait InterruptController {
+    /// Masks the IRQ line, so the device can no longer interrupt the CPU.
+    fn mask(&mut self, irq: u8) -> Result<(), IntCtrlError>;
+
+    /// Unmasks the IRQ line.
+    fn unmask(&mut self, irq: u8) -> Result<(), IntCtrlError>;
+
+    /// Returns true if the IRQ line is masked.
+    fn is_masked(&mut self, irq: u8) -> Result<bool, IntCtrlError>;
+
+    /// Signals the end of the interrupt with the provided vector.
+    ///
+    /// Must be the last thing done by the interrupt handler.
+    fn end_of_interrupt(&mut self, vector: u8);
+
+    /// Routes the IRQ line to the provided CPU.
+    fn set_affinity(&mut self, irq: u8, cpu: u8) -> Result<(), IntCtrlError>;
+
+    /// Returns the IDT vector to which the global system interrupt is delivered.
+    fn map_gsi(&mut self, gsi: u32) -> Result<u8, IntCtrlError>;
+
+    /// Checks if the interrupt with provided vector is spurious.
+    ///
+    /// No end of interrupt shall be sent for spurious interrupts. Controllers without spurious
+    /// interrupts may use the default implementation.
+    fn is_spurious(&mut self, vector: u8) -> bool {
+        let _ = vector;
+        false
+    }
+}
+
+/// Performs something on the currently active interrupt controller.
+///
+/// The closure is executed within a critical section, so it is safe to use from the interrupt
+/// handlers as well.
+pub fn with_controller<F, R>(f: F) -> Result<R, IntCtrlError> where
+    F: FnOnce(&mut dyn InterruptController) -> R
+{
+    critical_section!(|| {
+        if let Some(ioapic) = IO_APIC.lock().as_mut() {
+            return Ok(f(ioapic))
+        }
+        PROGRAMMABLE_INTERRUPT_CONTROLLER.lock()
+            .as_mut()
+            .map(|pic| f(pic))
+            .ok_or(IntCtrlError::NotInitialized)
+    })
+}
+
+impl ChainedPics {
+    fn irq_bit(irq: u8) -> Result<IrqMask, IntCtrlError> {
+        if irq < 16 {
+            Ok(IrqMask::from(1u16 << irq))
+        } else {
+            Err(IntCtrlError::InvalidIrq(irq))
+        }
+    }
+}
+
+impl InterruptController for ChainedPics {
+    fn mask(&mut self, irq: u8) -> Result<(), IntCtrlError> {
+        let mask = self.get_mask() | Self::irq_bit(irq)?;
+        unsafe { self.write_mask(mask) };
+        Ok(())
+    }
+
+    fn unmask(&mut self, irq: u8) -> Result<(), IntCtrlError> {
+        let mask = self.get_mask() & !Self::irq_bit(irq)?;
+        unsafe { self.write_mask(mask) };
+        // Slave lines are only delivered through the cascade line of the master.
+        if irq >= 8 {
+            self.enable_slave();
+        }
+        Ok(())
+    }
+
+    fn is_masked(&mut self, irq: u8) -> Result<bool, IntCtrlError> {
+        Ok(Self::irq_bit(irq)?.is_in(self.get_mask().bits()))
+    }
+
+    fn end_of_interrupt(&mut self, vector: u8) {
+        unsafe { self.notify_end_of_interrupt(vector) }
+    }
+
+    fn set_affinity(&mut self, irq: u8, cpu: u8) -> Result<(), IntCtrlError> {
+        Self::irq_bit(irq)?;
+        // The PIC is only wired to the bootstrap processor.
+        if cpu == 0 { Ok(()) } else { Err(IntCtrlError::Unsupported) }
+    }
+
+    fn map_gsi(&mut self, gsi: u32) -> Result<u8, IntCtrlError> {
+        match gsi {
+            0..=7 => Ok(self.master.offset + gsi as u8),
+            8..=15 => Ok(self.slave.offset + gsi as u8 - 8),
+            _ => Err(IntCtrlError::InvalidGsi(gsi)),
+        }
+    }
+
+    fn is_spurious(&mut self, vector: u8) -> bool {
+        // Only the lowest priority lines of each chip can be spurious.
+        if vector == self.master.offset + 7 || vector == self.slave.offset + 7 {
+            unsafe { ChainedPics::is_spurious(self, vector) }
+        } else {
+            false
+        }
+    }
+}
+
+#[test_case]
+fn pic_maps_isa_irqs() {
+    let mut pics = ChainedPics::new(32, 40);
+
+    assert_eq!(pics.map_gsi(0), Ok(32));
+    assert_eq!(pics.map_gsi(15), Ok(47));
+    assert_eq!(pics.map_gsi(16), Err(IntCtrlError::InvalidGsi(16)));
+    assert_eq!(pics.set_affinity(1, 1), Err(IntCtrlError::Unsupported));
+    assert_eq!(pics.mask(16), Err(IntCtrlError::InvalidIrq(16)));
+}
@@ -7,14 +7,18 @@ use super::{
 };
 use crate::kernel_components::drivers::Driver;
 use crate::kernel_components::sync::Mutex;
-use alloc::boxed::Box;
-use crate::single;
+use crate::kernel_components::task_virtualization::workqueue::{self, Work};
+use alloc::{boxed::Box, vec::Vec};
+use crate::{bitflags, critical_section, single};
+
+/// Registered keyboard shortcuts.
+static SHORTCUTS: Mutex<Vec<Shortcut>> = Mutex::new(Vec::new());
 
 /// Driver sub-trait that must be implemented by all keyboard drivers.
 ///
 /// Via this driver all system utilities, which expect user keyboard input can read obtain their
 /// data.
-pub trait KeyboardDriver {
+pub trait KeyboardDriver: Driver {
     /// Read the character from the keyboard input.
     ///
     /// This function MUST always return the last pressed key from the keyboard. Driver should not
@@ -30,9 +34,80 @@ pub trait KeyboardDriver {
     /// space programs, but only for system utilities. If no user input found, this should always 
     /// return None.
     fn key(&mut self) -> Option<Key>;
+
+    /// Registers a keyboard shortcut.
+    ///
+    /// The callback is scheduled to the workqueue when the key is pressed while exactly the
+    /// provided modifiers are held, so it never runs within the interrupt handler. The key
+    /// press which triggered the shortcut is consumed and not reported by read or key methods.
+    /// Registering the same combination again replaces the previous callback.
+    fn register_shortcut(&mut self, modifiers: ShortcutModifiers, key: Key, callback: Work) {
+        register_shortcut(modifiers, key, callback)
+    }
+}
+
+impl_driver!(Box<dyn KeyboardDriver>, |s| s.as_ref().info());
+
+bitflags! {
+    /// Modifier combination of a keyboard shortcut.
+    ///
+    /// Left and right modifier keys are not distinguished.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ShortcutModifiers: u8 {
+        const CTRL  = 1 << 0,
+        const ALT   = 1 << 1,
+        const SHIFT = 1 << 2,
+    };
+}
+
+/// A single registered shortcut.
+#[derive(Debug, Clone, Copy)]
+struct Shortcut {
+    modifiers: u8,
+    key: Key,
+    callback: Work,
+}
+
+/// Registers a keyboard shortcut for every keyboard driver.
+///
+/// See [´KeyboardDriver::register_shortcut´].
+pub fn register_shortcut(modifiers: ShortcutModifiers, key: Key, callback: Work) {
+    let modifiers = modifiers.bits();
+    critical_section!(|| {
+        let mut shortcuts = SHORTCUTS.lock();
+        shortcuts.retain(|s| !(s.modifiers == modifiers && s.key == key));
+        shortcuts.push(Shortcut { modifiers, key, callback });
+    });
+}
+
+/// Removes a registered keyboard shortcut.
+pub fn unregister_shortcut(modifiers: ShortcutModifiers, key: Key) {
+    let modifiers = modifiers.bits();
+    critical_section!(|| SHORTCUTS.lock().retain(|s| !(s.modifiers == modifiers && s.key == key)));
 }
 
-impl_driver!(Box<dyn KeyboardDriver>);
+/// Evaluates the keycode against SysRq combinations and registered shortcuts.
+///
+/// Must be called by keyboard drivers for every decoded keycode, after the modifiers were
+/// updated. Returns true if the keycode triggered a shortcut and must be consumed.
+pub fn dispatch_shortcut(modifiers: &Modifiers, keycode: KeyCode) -> bool {
+    if super::sysrq::handle_key(modifiers, keycode) {
+        return true;
+    }
+    if !keycode.is_pressed() {
+        return false;
+    }
+
+    let current = modifiers.shortcut_modifiers().bits();
+    let callback = critical_section!(|| {
+        SHORTCUTS.lock()
+            .iter()
+            .find(|s| s.modifiers == current && s.key == keycode.key)
+            .map(|s| s.callback)
+    });
+
+    callback.map(workqueue::schedule).is_some()
+}
 
 /// The keys that can be pressed by any keyboard.
 /// 
@@ -417,6 +492,15 @@ impl Modifiers {
     pub const fn is_caps(&self) -> bool {
         self.is_shifted() ^ self.capslock
     }
+
+    /// Returns the held modifiers as a shortcut combination.
+    pub fn shortcut_modifiers(&self) -> ShortcutModifiers {
+        let mut modifiers = ShortcutModifiers::empty();
+        if self.is_ctrl() { modifiers |= ShortcutModifiers::CTRL }
+        if self.is_alt() { modifiers |= ShortcutModifiers::ALT }
+        if self.is_shifted() { modifiers |= ShortcutModifiers::SHIFT }
+        modifiers
+    }
 }
 
 impl Default for Modifiers {
@@ -457,3 +541,25 @@ impl KeyCode {
         self.is_pressed
     }
 }
+
+#[test_case]
+fn shortcut_is_deferred() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    static TRIGGERED: AtomicBool = AtomicBool::new(false);
+
+    let mut modifiers = Modifiers::default();
+    modifiers.lctrl = true;
+    modifiers.ralt = true;
+    register_shortcut(ShortcutModifiers::CTRL | ShortcutModifiers::ALT, Key::Custom(0xfe), || {
+        TRIGGERED.store(true, Ordering::SeqCst)
+    });
+
+    assert!(!dispatch_shortcut(&modifiers, KeyCode::new(Key::Custom(0xfe), false)));
+    assert!(!dispatch_shortcut(&Modifiers::default(), KeyCode::new(Key::Custom(0xfe), true)));
+    assert!(dispatch_shortcut(&modifiers, KeyCode::new(Key::Custom(0xfe), true)));
+    assert!(!TRIGGERED.load(Ordering::SeqCst));
+
+    workqueue::run_pending();
+    assert!(TRIGGERED.load(Ordering::SeqCst));
+    unregister_shortcut(ShortcutModifiers::CTRL | ShortcutModifiers::ALT, Key::Custom(0xfe));
+}
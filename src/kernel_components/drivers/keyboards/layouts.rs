@@ -28,7 +28,7 @@ impl KeyboardLayout for US104KEY {
             return None
         }
 
-        match keycode.key {
+        let c = match keycode.key {
             Oem8 => {
                 if modifiers.is_shifted() {
                     Some('~')
@@ -121,8 +121,8 @@ impl KeyboardLayout for US104KEY {
                     Some('=')
                 }
             }
-            Backspace => Some(0x0.into()),
-            Tab => Some(0x0.into()),
+            Backspace => Some(0x8.into()),
+            Tab => Some(0x9.into()),
             Q => {
                 if modifiers.is_caps() {
                     Some('Q')
@@ -442,6 +442,12 @@ impl KeyboardLayout for US104KEY {
             }
             NumpadEnter => Some(10.into()),
             _ => None,
+        };
+
+        // Ctrl with a letter gives the control character, i.e Ctrl+C gives ETX (0x03).
+        match c {
+            Some(c) if modifiers.is_ctrl() && c.is_ascii_alphabetic() => Some((c as u8 & 0x1f) as char),
+            c => c,
         }
     }
 }
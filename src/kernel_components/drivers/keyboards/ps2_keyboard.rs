@@ -1,7 +1,7 @@
 /// A driver module for PS/2 Keyboard.
 
-use crate::{kernel_components::{arch_x86_64::controllers::PS2, drivers::Driver, sync::Mutex}, single};
-use super::{keyboard::KeyboardDriver, layouts::US104KEY, Key, KeyCode, KeyboardLayout, Modifiers, ScanCode, ScancodeError, ScancodeSet1, ScancodeSetTrait};
+use crate::{kernel_components::{arch_x86_64::controllers::PS2, drivers::{Driver, DriverInfo, DriverCaps, BoundDevice}, sync::Mutex}, single};
+use super::{keyboard::{KeyboardDriver, dispatch_shortcut}, layouts::US104KEY, Key, KeyCode, KeyboardLayout, Modifiers, ScanCode, ScancodeError, ScancodeSet1, ScancodeSetTrait};
 use core::fmt::Debug;
 
 /// A driver for a PS/2 keyboard.
@@ -78,17 +78,35 @@ impl<S: ScancodeSetTrait + Debug + Clone + Copy, L: KeyboardLayout> PS2Keyboard<
     }
 }
 
+impl<S: ScancodeSetTrait + Debug + Clone + Copy + 'static, L: KeyboardLayout + 'static> Driver for PS2Keyboard<S, L> {
+    fn as_driver(&mut self) -> &mut dyn core::any::Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "PS2Keyboard"
+    }
+
+    fn info(&self) -> DriverInfo {
+        DriverInfo::new(self.name())
+            .version(0, 1, 0)
+            .caps(DriverCaps::READ | DriverCaps::IRQ)
+            .bound_to(BoundDevice::IoPort(0x60))
+    }
+}
+
 impl Default for PS2Keyboard<ScancodeSet1, US104KEY> {
     fn default() -> Self {
         Self::new(ScancodeSet1, US104KEY)
     }
 }
 
-impl<S: ScancodeSetTrait + Debug + Clone + Copy, L: KeyboardLayout> KeyboardDriver for PS2Keyboard<S, L> {
+impl<S: ScancodeSetTrait + Debug + Clone + Copy + 'static, L: KeyboardLayout + 'static> KeyboardDriver for PS2Keyboard<S, L> {
     fn read(&mut self) -> Option<char> {
         let scancode = self.controller.read_data();
 
         if let Ok(Some(keycode)) = self.scan_key(scancode) {
+            if dispatch_shortcut(&self.modifiers, keycode) { return None }
             if let Some(key) = self.scan_char(keycode) { return Some(key) }
         }
         None
@@ -97,7 +115,10 @@ impl<S: ScancodeSetTrait + Debug + Clone + Copy, L: KeyboardLayout> KeyboardDriv
     fn key(&mut self) -> Option<Key> { 
         let scancode = self.controller.read_data();
 
-        if let Ok(Some(key_code)) = self.scan_key(scancode) { return Some(key_code.key) }
+        if let Ok(Some(key_code)) = self.scan_key(scancode) {
+            if dispatch_shortcut(&self.modifiers, key_code) { return None }
+            return Some(key_code.key)
+        }
         None
     }
 }
@@ -0,0 +1,127 @@
+/// Magic SysRq key combinations.
+///
+/// Holding Alt + PrintScreen (the SysRq key) and pressing one of the action keys performs an
+/// emergency debug action, which works even if the shell or the focused program does not react
+/// to input anymore. Every action is routed through the debug API of the respective subsystem.
+///
+/// Actions are deferred to the workqueue, except the emergency reboot, which is performed right
+/// away within the interrupt handler, so it works even when the worker thread never gets
+/// scheduled again.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{Key, KeyCode, Modifiers};
+use crate::kernel_components::arch_x86_64::controllers::PS2;
+use crate::kernel_components::memory::MEMORY_MANAGEMENT_UNIT;
+use crate::kernel_components::task_virtualization::{workqueue::{self, Work}, PROCESS_MANAGEMENT_UNIT, ROUND_ROBIN};
+use crate::{critical_section, emergency_println, println};
+
+/// Set while the SysRq key is held down.
+static SYSRQ_HELD: AtomicBool = AtomicBool::new(false);
+
+/// A single SysRq action.
+pub struct SysRqAction {
+    /// Key which triggers the action.
+    pub key: Key,
+    /// Short description printed by the help action.
+    pub help: &'static str,
+    /// The action itself.
+    pub action: Work,
+    /// The action is performed in the interrupt handler instead of the workqueue.
+    pub immediate: bool,
+}
+
+/// Every supported SysRq action.
+pub static SYSRQ_ACTIONS: [SysRqAction; 6] = [
+    SysRqAction { key: Key::H, help: "help", action: help, immediate: false },
+    SysRqAction { key: Key::T, help: "dump task list", action: dump_tasks, immediate: false },
+    SysRqAction { key: Key::M, help: "dump memory stats", action: dump_memory, immediate: false },
+    SysRqAction { key: Key::N, help: "rebalance scheduler", action: rebalance, immediate: false },
+    SysRqAction { key: Key::S, help: "sync file mappings", action: sync, immediate: false },
+    SysRqAction { key: Key::B, help: "emergency reboot", action: reboot, immediate: true },
+];
+
+/// Evaluates the keycode as a part of a SysRq combination.
+///
+/// Must be called by keyboard drivers for every decoded keycode, after the modifiers were
+/// updated. Returns true if the keycode was consumed by the SysRq handler.
+pub fn handle_key(modifiers: &Modifiers, keycode: KeyCode) -> bool {
+    match keycode.key {
+        Key::SystemRequest => {
+            SYSRQ_HELD.store(keycode.is_pressed(), Ordering::Release);
+            true
+        },
+        Key::PrintScreen if modifiers.is_alt() || !keycode.is_pressed() => {
+            SYSRQ_HELD.store(keycode.is_pressed(), Ordering::Release);
+            true
+        },
+        key if SYSRQ_HELD.load(Ordering::Acquire) => {
+            if keycode.is_pressed() {
+                trigger(key);
+            }
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Performs the SysRq action bound to the key.
+///
+/// Returns false if no action is bound to the key.
+pub fn trigger(key: Key) -> bool {
+    match SYSRQ_ACTIONS.iter().find(|a| a.key == key) {
+        Some(action) if action.immediate => {
+            (action.action)();
+            true
+        },
+        Some(action) => workqueue::schedule(action.action),
+        None => {
+            workqueue::schedule(help);
+            false
+        },
+    }
+}
+
+fn help() {
+    println!("SysRq: Alt + PrintScreen + <key>");
+    for action in SYSRQ_ACTIONS.iter() {
+        println!("  {:?}: {}", action.key, action.help);
+    }
+}
+
+fn dump_tasks() {
+    unsafe { PROCESS_MANAGEMENT_UNIT.dump_tasks() }
+}
+
+fn dump_memory() {
+    unsafe { MEMORY_MANAGEMENT_UNIT.dump_stats() }
+}
+
+fn rebalance() {
+    critical_section!(|| ROUND_ROBIN.rebalance());
+    println!("SysRq: scheduler run queue rebuilt");
+}
+
+fn sync() {
+    let written = unsafe { PROCESS_MANAGEMENT_UNIT.sync_all() };
+    println!("SysRq: {} dirty pages of file mappings written back", written);
+}
+
+fn reboot() {
+    emergency_println!("SysRq: emergency reboot");
+    unsafe { PS2::new().reset_cpu() };
+}
+
+#[test_case]
+fn sysrq_consumes_combination() {
+    let mut modifiers = Modifiers::default();
+    modifiers.lalt = true;
+
+    assert!(!handle_key(&modifiers, KeyCode::new(Key::T, true)));
+    assert!(handle_key(&modifiers, KeyCode::new(Key::PrintScreen, true)));
+    assert!(handle_key(&modifiers, KeyCode::new(Key::Custom(0xfd), true)));
+    assert!(handle_key(&modifiers, KeyCode::new(Key::PrintScreen, false)));
+    assert!(!handle_key(&modifiers, KeyCode::new(Key::T, true)));
+
+    workqueue::run_pending();
+}
@@ -2,11 +2,14 @@ use core::{any::Any, ops::DerefMut};
 
 /// A module for all build-in libraries.
 
-use alloc::{boxed::Box, collections::BTreeMap, string::String};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
 use keyboards::keyboard::KeyboardDriver;
-use timers::ClockDriver;
+use timers::{ClockDriver, RealTimeClock, RtcCallback, RtcEvent, RtcTimer};
 
-use crate::{debug, single};
+use crate::kernel_components::arch_x86_64::interrupts::irq_manager::{IrqError, IrqHandle, IrqReturn, IRQ_MANAGER};
+use crate::kernel_components::arch_x86_64::pci::{self, PciDevice};
+use crate::kernel_components::task_virtualization::{capability, Capability};
+use crate::{bitflags, debug, warn, single};
 
 pub type DriverResult<T> = Result<T, DriverError>;
 
@@ -17,6 +20,30 @@ pub type DriverResult<T> = Result<T, DriverError>;
 pub trait Driver: Any {
     fn as_driver(&mut self) -> &mut dyn Any;  // Method to enable downcasting
     fn name(&self) -> &str;
+
+    /// Returns the metadata of the driver.
+    ///
+    /// By default only the name is provided. Drivers should override this to expose their
+    /// version, capabilities and the device they are bound to.
+    fn info(&self) -> DriverInfo {
+        DriverInfo::new(self.name())
+    }
+
+    /// Quiesces the device before the kernel hands over the machine.
+    ///
+    /// After this call the device must not raise interrupts or perform DMA. Does nothing by
+    /// default.
+    fn shutdown(&mut self) {}
+
+    /// Saves the state of the device before the machine enters a sleep state.
+    ///
+    /// Like after [`Driver::shutdown`], the device must stay quiet until it is resumed. Does
+    /// nothing by default.
+    fn suspend(&mut self) {}
+
+    /// Restores the state saved by [`Driver::suspend`] after the machine woke up. Does nothing by
+    /// default.
+    fn resume(&mut self) {}
 }
 
 /// A default driver manager.
@@ -32,9 +59,27 @@ single! {
 /// manager instance to obtain a reference to real drivers. This structure does not ensure any
 /// mutual exclusion or synchronization of any kind, therefore it is must be implemented within the
 /// drivers logic.
+///
+/// # Resources
+///
+/// IRQ handlers registered with [`DriverManager::register_irq`], timers registered with
+/// [`DriverManager::register_timer`] and PCI functions claimed with [`DriverManager::claim_pci`]
+/// are owned by the driver. The PCI function the driver reports in [`Driver::info`] is claimed
+/// while loading. When the driver is unloaded, it is shut down first, then it's handlers and
+/// timers are removed, so none of them fires into the freed driver, and finally it's PCI functions
+/// are released, so another driver may probe them, see [`DriverManager::unclaimed_pci`].
 #[derive(Default)]
 pub struct DriverManager {
     drivers: BTreeMap<DriverType, Box<dyn Driver>>,
+    resources: BTreeMap<DriverType, Resources>,
+}
+
+/// Resources owned by a driver, which are released when it is unloaded.
+#[derive(Debug, Default)]
+struct Resources {
+    irqs: Vec<IrqHandle>,
+    timers: Vec<RtcTimer>,
+    devices: Vec<PciDevice>,
 }
 
 impl DriverManager {
@@ -49,11 +94,48 @@ impl DriverManager {
     ///
     /// # Returns
     ///
-    /// An error if such driver already exist. A string with driver's name if it was loaded
-    /// successfully.
+    /// An error if such driver already exist or the current process may not load drivers. A
+    /// string with driver's name if it was loaded successfully.
     pub fn load<T>(&mut self, driver: T, dtype: DriverType) -> DriverResult<String> where T: Driver {
+        self.prepare(dtype)?;
+        self.insert(driver, dtype)
+    }
+
+    /// Creates the driver with the probe function and loads it.
+    ///
+    /// Unlike with [`DriverManager::load`], the memory allocated while probing the device is
+    /// charged to the driver, see [`accounting`].
+    ///
+    /// # Returns
+    ///
+    /// An error if such driver already exist, the current process may not load drivers or the
+    /// probe failed. A string with driver's name if it was loaded successfully.
+    pub fn probe<T, F>(&mut self, dtype: DriverType, probe: F) -> DriverResult<String> where
+        T: Driver,
+        F: FnOnce() -> DriverResult<T>,
+    {
+        self.prepare(dtype)?;
+        let driver = accounting::with_context(dtype, probe)?;
+        self.insert(driver, dtype)
+    }
+
+    /// Checks if a driver of the type may be loaded and clears it's memory accounting.
+    fn prepare(&self, dtype: DriverType) -> DriverResult<()> {
+        capability::require(Capability::DRIVER_LOAD).map_err(|_| DriverError::PermissionDenied)?;
+        if self.drivers.contains_key(&dtype) {
+            return Err(DriverError::AlreadyLoaded)
+        }
+        accounting::reset(dtype);
+        Ok(())
+    }
+
+    fn insert<T>(&mut self, driver: T, dtype: DriverType) -> DriverResult<String> where T: Driver {
         let str = String::from(driver.name());
-        if let Err(_) = self.drivers.try_insert(dtype, Box::new(driver)) {
+        if let Some(BoundDevice::Pci(device)) = driver.info().device {
+            self.claim_pci(dtype, device)?;
+        }
+        let driver: Box<dyn Driver> = accounting::with_context(dtype, || Box::new(driver));
+        if self.drivers.try_insert(dtype, driver).is_err() {
             Err(DriverError::AlreadyLoaded)
         } else {
             debug!("Mod \"{}\" is loaded", str.as_str());
@@ -61,20 +143,161 @@ impl DriverManager {
         }
     }
 
+    /// Registers the IRQ handler of the driver, which runs in the allocation context of the driver.
+    ///
+    /// The handler is unregistered when the driver is unloaded.
+    pub fn register_irq<F>(&mut self, dtype: DriverType, irq: u8, name: &'static str, mut handler: F) -> Result<IrqHandle, IrqError> where
+        F: FnMut() -> IrqReturn + Send + 'static,
+    {
+        let handle = IRQ_MANAGER.register(irq, name, move || accounting::with_context(dtype, &mut handler))?;
+        self.resources.entry(dtype).or_default().irqs.push(handle);
+        Ok(handle)
+    }
+
+    /// Unregisters the IRQ handler of the driver before it is unloaded.
+    pub fn unregister_irq(&mut self, dtype: DriverType, handle: IrqHandle) -> Result<(), IrqError> {
+        if let Some(resources) = self.resources.get_mut(&dtype) {
+            resources.irqs.retain(|irq| *irq != handle);
+        }
+        IRQ_MANAGER.unregister(handle)
+    }
+
+    /// Registers the RTC callback of the driver, which is canceled when the driver is unloaded.
+    pub fn register_timer(&mut self, dtype: DriverType, event: RtcEvent, callback: RtcCallback) -> RtcTimer {
+        let timer = RealTimeClock::register(event, callback);
+        self.resources.entry(dtype).or_default().timers.push(timer);
+        timer
+    }
+
+    /// Cancels the RTC callback of the driver before it is unloaded. Returns false if it was
+    /// canceled before.
+    pub fn cancel_timer(&mut self, dtype: DriverType, timer: RtcTimer) -> bool {
+        if let Some(resources) = self.resources.get_mut(&dtype) {
+            resources.timers.retain(|owned| *owned != timer);
+        }
+        RealTimeClock::cancel(timer)
+    }
+
+    /// Claims the PCI function for the driver until it is unloaded.
+    ///
+    /// # Returns
+    ///
+    /// An error if the function is claimed by another driver already.
+    pub fn claim_pci(&mut self, dtype: DriverType, device: PciDevice) -> DriverResult<()> {
+        match self.pci_owner(device) {
+            Some(owner) if owner == dtype => Ok(()),
+            Some(_) => Err(DriverError::DeviceBusy),
+            None => {
+                self.resources.entry(dtype).or_default().devices.push(device);
+                Ok(())
+            },
+        }
+    }
+
+    /// Returns the driver which claimed the PCI function, if any.
+    pub fn pci_owner(&self, device: PciDevice) -> Option<DriverType> {
+        self.resources.iter()
+            .find(|(_, resources)| resources.devices.contains(&device))
+            .map(|(dtype, _)| *dtype)
+    }
+
+    /// Scans the PCI buses for functions no driver has claimed, which are left to probe.
+    pub fn unclaimed_pci(&self) -> Vec<PciDevice> {
+        pci::scan().into_iter()
+            .filter(|device| self.pci_owner(*device).is_none())
+            .collect()
+    }
+
+    /// Returns the metadata of every loaded driver together with it's type.
+    pub fn list(&self) -> Vec<(DriverType, DriverInfo)> {
+        self.drivers.iter()
+            .map(|(dtype, driver)| (*dtype, driver.info()))
+            .collect()
+    }
+
+    /// Calls the shutdown hook of every loaded driver.
+    ///
+    /// Used before handing the machine over to another kernel image or firmware.
+    pub fn shutdown_all(&mut self) {
+        for (dtype, driver) in self.drivers.iter_mut() {
+            debug!("Shutting down \"{}\"", driver.name());
+            accounting::with_context(*dtype, || driver.shutdown());
+        }
+    }
+
+    /// Calls the suspend hook of every loaded driver.
+    pub fn suspend_all(&mut self) {
+        for (dtype, driver) in self.drivers.iter_mut() {
+            debug!("Suspending \"{}\"", driver.name());
+            accounting::with_context(*dtype, || driver.suspend());
+        }
+    }
+
+    /// Calls the resume hook of every loaded driver in the reverse order of suspending.
+    pub fn resume_all(&mut self) {
+        for (dtype, driver) in self.drivers.iter_mut().rev() {
+            debug!("Resuming \"{}\"", driver.name());
+            accounting::with_context(*dtype, || driver.resume());
+        }
+    }
+
     /// Unloads the requested driver.
     ///
     /// # Returns 
     ///
-    /// An error if such driver does not exist already. An Ok(()) if was deleted successfully
+    /// An error if such driver does not exist already or the current process may not unload
+    /// drivers. An Ok(()) if was deleted successfully
+    ///
+    /// The driver is shut down and it's resources are released, see [`DriverManager`]. It is
+    /// dropped in it's allocation context afterwards. Memory which is still charged to it then is
+    /// reported as leaked.
+    ///
+    /// Must not be called from an IRQ handler of the driver, whose line is locked meanwhile.
     pub fn unload(&mut self, name: String) -> DriverResult<()> {
-        if let Some((dtype, _)) = self.drivers.iter().find(|(k, v)| v.name() == name) {
-            self.drivers.remove(&dtype.clone());
+        capability::require(Capability::DRIVER_LOAD).map_err(|_| DriverError::PermissionDenied)?;
+        if let Some((&dtype, _)) = self.drivers.iter().find(|(_, v)| v.name() == name) {
+            let mut driver = self.drivers.remove(&dtype);
+            if let Some(driver) = driver.as_mut() {
+                accounting::with_context(dtype, || driver.shutdown());
+            }
+            self.release(dtype);
+            accounting::with_context(dtype, || drop(driver));
             debug!("Mod \"{}\" is unloaded", name.as_str());
+
+            let usage = accounting::usage(dtype);
+            if usage.is_leaking() {
+                warn!(
+                    "Mod \"{}\" leaked {} bytes in {} allocations and {} frames",
+                    name.as_str(), usage.heap_bytes, usage.allocations.saturating_sub(usage.frees), usage.frames,
+                );
+            }
             Ok(())
         } else {
             Err(DriverError::NotLoaded)
         }
     }
+
+    /// Unregisters the IRQ handlers and cancels the timers of the driver, then releases it's PCI
+    /// functions for probing.
+    fn release(&mut self, dtype: DriverType) {
+        let Some(resources) = self.resources.remove(&dtype) else { return };
+        for handle in resources.irqs {
+            if let Err(err) = IRQ_MANAGER.unregister(handle) {
+                warn!("Unable to unregister the handler of IRQ {}: {}", handle.irq(), err);
+            }
+        }
+        for timer in resources.timers {
+            RealTimeClock::cancel(timer);
+        }
+        for device in resources.devices {
+            debug!("Released PCI {:02x}:{:02x}.{} for probing", device.bus, device.device, device.function);
+        }
+    }
+
+    /// Returns the memory charged to the driver of the type, see [`accounting`].
+    pub fn memory_usage(&self, dtype: DriverType) -> MemUsage {
+        accounting::usage(dtype)
+    }
 }
 
 macro_rules! impl_driver {
@@ -89,12 +312,171 @@ macro_rules! impl_driver {
             }
         }
     };
+    ($t:ty, $info:expr) => {
+        impl Driver for $t {
+            fn as_driver(&mut self) -> &mut dyn core::any::Any {
+                self
+            }
+
+            fn name(&self) -> &str {
+                stringify!($t)
+            }
+
+            fn info(&self) -> crate::kernel_components::drivers::DriverInfo {
+                let info: fn(&Self) -> crate::kernel_components::drivers::DriverInfo = $info;
+                info(self)
+            }
+        }
+    };
+    ($t:ty, $info:expr, $shutdown:expr) => {
+        impl Driver for $t {
+            fn as_driver(&mut self) -> &mut dyn core::any::Any {
+                self
+            }
+
+            fn name(&self) -> &str {
+                stringify!($t)
+            }
+
+            fn info(&self) -> crate::kernel_components::drivers::DriverInfo {
+                let info: fn(&Self) -> crate::kernel_components::drivers::DriverInfo = $info;
+                info(self)
+            }
+
+            fn shutdown(&mut self) {
+                let shutdown: fn(&mut Self) = $shutdown;
+                shutdown(self)
+            }
+        }
+    };
+    ($t:ty, $info:expr, $shutdown:expr, $suspend:expr, $resume:expr) => {
+        impl Driver for $t {
+            fn as_driver(&mut self) -> &mut dyn core::any::Any {
+                self
+            }
+
+            fn name(&self) -> &str {
+                stringify!($t)
+            }
+
+            fn info(&self) -> crate::kernel_components::drivers::DriverInfo {
+                let info: fn(&Self) -> crate::kernel_components::drivers::DriverInfo = $info;
+                info(self)
+            }
+
+            fn shutdown(&mut self) {
+                let shutdown: fn(&mut Self) = $shutdown;
+                shutdown(self)
+            }
+
+            fn suspend(&mut self) {
+                let suspend: fn(&mut Self) = $suspend;
+                suspend(self)
+            }
+
+            fn resume(&mut self) {
+                let resume: fn(&mut Self) = $resume;
+                resume(self)
+            }
+        }
+    };
 }
 
 /// Defines different driver types for query.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
 pub enum DriverType {
-    Keyboard, Mouse, Clock
+    Keyboard, Mouse, Clock, Interrupt,
+    Storage, Net, Video, Audio, Serial, Bus, Power,
+}
+
+impl DriverType {
+    /// Amount of driver types.
+    pub const COUNT: usize = DriverType::Power as usize + 1;
+}
+
+/// Metadata of the driver.
+///
+/// Provides everything that is needed to present a meaningful listing of loaded drivers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriverInfo {
+    /// Name of the driver.
+    pub name: String,
+    /// Version of the driver in (major, minor, patch) format.
+    pub version: (u16, u16, u16),
+    /// Author of the driver.
+    pub author: &'static str,
+    /// Features supported by the driver.
+    pub caps: DriverCaps,
+    /// The device which is driven by the driver, if known.
+    pub device: Option<BoundDevice>,
+}
+
+impl DriverInfo {
+    /// Creates a new driver info with only name provided.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            version: (0, 1, 0),
+            author: "unknown",
+            caps: DriverCaps::EMPTY,
+            device: None,
+        }
+    }
+
+    /// Sets the version of the driver.
+    pub fn version(mut self, major: u16, minor: u16, patch: u16) -> Self {
+        self.version = (major, minor, patch);
+        self
+    }
+
+    /// Sets the author of the driver.
+    pub fn author(mut self, author: &'static str) -> Self {
+        self.author = author;
+        self
+    }
+
+    /// Sets the capability flags of the driver.
+    pub fn caps(mut self, caps: DriverCaps) -> Self {
+        self.caps = caps;
+        self
+    }
+
+    /// Sets the device that the driver is bound to.
+    pub fn bound_to(mut self, device: BoundDevice) -> Self {
+        self.device = Some(device);
+        self
+    }
+}
+
+/// A device to which the driver is bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundDevice {
+    /// A function on the PCI bus.
+    Pci(PciDevice),
+    /// A legacy device accessed via I/O port base.
+    IoPort(u16),
+    /// A platform device that is always present, i.e PIT or CMOS.
+    Platform(&'static str),
+}
+
+bitflags! {
+    /// Capability flags of the driver.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DriverCaps: u32 {
+        /// The driver can provide data to the OS.
+        const READ              = 1 << 0,
+        /// The driver can accept data from the OS.
+        const WRITE             = 1 << 1,
+        /// The driver is interrupt driven.
+        const IRQ               = 1 << 2,
+        /// The driver performs DMA transfers.
+        const DMA               = 1 << 3,
+        /// The device can be attached or detached at runtime.
+        const HOTPLUG           = 1 << 4,
+        /// The driver supports power state transitions.
+        const POWER_MANAGEMENT  = 1 << 5,
+    }
 }
 
 /// Error type for driver error handling.
@@ -104,8 +486,21 @@ pub enum DriverError {
     AlreadyLoaded,
     /// Trying to remove an already unloaded driver.
     NotLoaded,
+    /// The current process lacks the [`Capability::DRIVER_LOAD`] capability.
+    ///
+    /// [`Capability::DRIVER_LOAD`]: crate::kernel_components::task_virtualization::Capability::DRIVER_LOAD
+    PermissionDenied,
+    /// The device is claimed by another driver.
+    DeviceBusy,
+    /// The probe found no device or could not initialize it.
+    ProbeFailed,
 }
 
+/// Memory charged to the loaded drivers.
+pub mod accounting;
+
+pub use accounting::MemUsage;
+
 /// Keyboard drivers.
 pub mod keyboards {
     /// Scancode parser for PS/2 keyboard.
@@ -117,17 +512,97 @@ pub mod keyboards {
 
     /// PS/2 keyboard driver.
     pub mod ps2_keyboard;
+    /// Magic SysRq debug key combinations.
+    pub mod sysrq;
 
-    pub use keyboard::{Key, KeyCode, Modifiers, KeyboardDriver};
+    pub use keyboard::{Key, KeyCode, Modifiers, KeyboardDriver, ShortcutModifiers};
     pub use scancodes::{ScanCode, ScancodeError, ScancodeSetTrait, ScancodeSet1, ScancodeSet2};
     pub use layouts::KeyboardLayout;
 
     pub use ps2_keyboard::PS2Keyboard;
 }
 
+/// Interrupt controllers.
+pub mod interrupts {
+    /// Global interrupt controller interface.
+    pub mod int_ctrl;
+
+    pub use int_ctrl::{InterruptController, IntCtrlError, with_controller};
+}
+
 /// Mouse drivers.
 pub mod mouse {
+    /// Global mouse interface.
+    pub mod mouse;
+    /// PS/2 mouse driver.
+    pub mod ps2_mouse;
+    /// Console pointer and text selection.
+    pub mod pointer;
+
+    pub use mouse::{MouseDriver, MouseEvent, MouseButtons};
+    pub use ps2_mouse::{PS2Mouse, PS2MouseError};
+}
+
+/// Sound output drivers.
+pub mod sound {
+    /// Global audio interface.
+    pub mod audio;
+    /// PC speaker driver based on PIT channel 2.
+    pub mod pcspeaker;
+    /// AC'97 codec driver with PCM playback.
+    pub mod ac97;
+
+    pub use audio::{AudioDriver, AudioError};
+    pub use pcspeaker::PCSpeaker;
+    pub use ac97::AC97;
+}
+
+/// Virtio paravirtual device drivers.
+pub mod virtio {
+    /// Legacy PCI transport shared by all virtio devices.
+    pub mod transport;
+    /// Split virtqueue implementation.
+    pub mod virtqueue;
+    /// Virtio console driver.
+    pub mod console;
+    /// 9P2000.L client for host directory sharing.
+    pub mod ninep;
+    /// Virtio block device driver.
+    pub mod block;
+
+    pub use transport::{LegacyTransport, VirtioError, DmaRegion};
+    pub use virtqueue::{Virtqueue, Buffer};
+    pub use console::VirtioConsole;
+    pub use block::VirtioBlock;
+    pub use ninep::{NinePClient, NinePError};
+}
 
+/// Block storage devices.
+pub mod storage {
+    /// Global block device interface with asynchronous requests.
+    pub mod block;
+    /// Block device backed by the kernel heap.
+    pub mod ramdisk;
+    /// Loopback block device backed by a file.
+    pub mod loop_device;
+
+    pub use block::{
+        BlockDevice, BlockError, BlockOp, BlockRequest, IoFuture, IoCompletion, IoResult,
+        io_pair, SECTOR_SIZE,
+    };
+    pub use ramdisk::RamDisk;
+    pub use loop_device::{LoopDevice, BackingFile, NinePFile};
+}
+
+/// Serial ports.
+pub mod serial {
+    /// Polled driver for 16550 compatible UARTs.
+    pub mod uart;
+    /// XMODEM file receiver.
+    pub mod xmodem;
+
+    pub use uart::{Uart16550, UartError, COM1, COM2};
+    pub use xmodem::{SerialLink, XmodemError};
 }
 
 /// Timers, counters and clocks.
@@ -136,7 +611,34 @@ pub mod timers {
     pub mod clock;
     /// Clock driver based on AT RTC chip.
     pub mod rtc_clock;
+    /// Clock driver based on KVM paravirtual clock.
+    pub mod kvm_clock;
+    /// Clock driver and scheduler tick source based on the local APIC timer.
+    pub mod apic_timer;
 
     pub use clock::ClockDriver;
-    pub use rtc_clock::RealTimeClock;
+    pub use rtc_clock::{RealTimeClock, RtcCallback, RtcEvent, RtcTimer};
+    pub use kvm_clock::KvmClock;
+    pub use apic_timer::{ApicTimer, ApicTimerError};
+}
+
+#[test_case]
+fn unloading_releases_the_resources_of_the_driver() {
+    struct Dummy;
+    impl_driver!(Dummy, |s| DriverInfo::new(s.name()).bound_to(BoundDevice::Pci(PciDevice::new(0, 31, 7))));
+
+    let mut manager = DriverManager::default();
+    let (device, other) = (PciDevice::new(0, 31, 7), PciDevice::new(0, 31, 6));
+    manager.load(Dummy, DriverType::Power).unwrap();
+    manager.claim_pci(DriverType::Power, other).unwrap();
+    let timer = manager.register_timer(DriverType::Power, RtcEvent::Update, |_| ());
+
+    assert_eq!(manager.pci_owner(device), Some(DriverType::Power));
+    assert_eq!(manager.claim_pci(DriverType::Bus, other), Err(DriverError::DeviceBusy));
+    assert_eq!(manager.load(Dummy, DriverType::Bus), Err(DriverError::DeviceBusy));
+
+    manager.unload(String::from("Dummy")).unwrap();
+    assert!(manager.pci_owner(device).is_none() && manager.pci_owner(other).is_none());
+    assert!(!RealTimeClock::cancel(timer));
+    assert!(manager.resources.is_empty());
 }
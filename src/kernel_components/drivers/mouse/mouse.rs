@@ -0,0 +1,50 @@
+/// A module that defines a global interface to pointing devices.
+
+use alloc::boxed::Box;
+
+use crate::bitflags;
+use crate::kernel_components::drivers::Driver;
+
+bitflags! {
+    /// Mouse buttons held during the event.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MouseButtons: u8 {
+        const LEFT      = 1 << 0,
+        const RIGHT     = 1 << 1,
+        const MIDDLE    = 1 << 2,
+    };
+}
+
+/// A single movement or button state change of the mouse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Relative horizontal movement. Positive values move right.
+    pub dx: i16,
+    /// Relative vertical movement. Positive values move up.
+    pub dy: i16,
+    /// Buttons held after the event.
+    pub buttons: u8,
+}
+
+impl MouseEvent {
+    /// Checks if the button is held.
+    pub fn is_held(&self, button: MouseButtons) -> bool {
+        button.is_in(self.buttons)
+    }
+}
+
+/// A mouse driver trait.
+///
+/// All pointing device drivers must implement this trait for global use throughout the OS.
+pub trait MouseDriver: Driver {
+    /// Must be called from the interrupt handler of the device.
+    ///
+    /// Consumes the data received from the device and returns an event once a whole packet
+    /// was received.
+    fn read(&mut self) -> Option<MouseEvent>;
+
+    /// Returns the legacy IRQ line used by the device.
+    fn irq(&self) -> u8;
+}
+
+impl_driver!(Box<dyn MouseDriver>, |s| s.as_ref().info());
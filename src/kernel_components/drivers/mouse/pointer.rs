@@ -0,0 +1,132 @@
+/// Console mouse pointer and text selection.
+///
+/// Mouse events move a pointer drawn over the VGA text console. Dragging with the left button
+/// held selects the text between the press and the current position, and releasing the button
+/// copies the selection into the kernel clipboard. A click without movement clears the
+/// selection.
+///
+/// The events arrive within the interrupt handler, therefore only the overlays are redrawn
+/// immediately, while the copying is deferred to the workqueue.
+
+use super::{MouseButtons, MouseEvent};
+use crate::kernel_components::clipboard;
+use crate::kernel_components::sync::Mutex;
+use crate::kernel_components::task_virtualization::workqueue;
+use crate::kernel_components::vga_buffer::{LOGGER, BUFFER_WIDTH, BUFFER_HEIGHT};
+
+/// Amount of mouse counts required to move the pointer by one cell horizontally.
+const COUNTS_PER_COLUMN: i32 = 8;
+/// Amount of mouse counts required to move the pointer by one cell vertically.
+const COUNTS_PER_ROW: i32 = 16;
+
+/// State of the console pointer.
+static POINTER: Mutex<Pointer> = Mutex::new(Pointer::new());
+
+/// Position of the pointer in mouse counts and the selection anchor.
+#[derive(Debug)]
+pub struct Pointer {
+    x: i32,
+    y: i32,
+    buttons: u8,
+    /// Cell where the left button was pressed.
+    anchor: Option<usize>,
+    /// The pointer was moved while the left button was held.
+    dragged: bool,
+}
+
+impl Pointer {
+    /// Creates a pointer in the center of the screen.
+    pub const fn new() -> Self {
+        Self {
+            x: (BUFFER_WIDTH / 2) as i32 * COUNTS_PER_COLUMN,
+            y: (BUFFER_HEIGHT / 2) as i32 * COUNTS_PER_ROW,
+            buttons: 0,
+            anchor: None,
+            dragged: false,
+        }
+    }
+
+    /// Returns the cell under the pointer.
+    pub fn cell(&self) -> usize {
+        let col = (self.x / COUNTS_PER_COLUMN) as usize;
+        let row = (self.y / COUNTS_PER_ROW) as usize;
+        row * BUFFER_WIDTH + col
+    }
+
+    /// Applies the event and returns what shall happen with the selection.
+    fn update(&mut self, event: MouseEvent) -> Selection {
+        let old_cell = self.cell();
+        self.x = (self.x + event.dx as i32).clamp(0, BUFFER_WIDTH as i32 * COUNTS_PER_COLUMN - 1);
+        // The vertical axis of the mouse grows upwards.
+        self.y = (self.y - event.dy as i32).clamp(0, BUFFER_HEIGHT as i32 * COUNTS_PER_ROW - 1);
+        let cell = self.cell();
+
+        let was_held = MouseButtons::LEFT.is_in(self.buttons);
+        let held = event.is_held(MouseButtons::LEFT);
+        self.buttons = event.buttons;
+
+        match (was_held, held) {
+            (false, true) => {
+                self.anchor = Some(cell);
+                self.dragged = false;
+                Selection::Clear
+            },
+            (true, true) => {
+                self.dragged |= cell != old_cell;
+                match self.anchor {
+                    Some(anchor) if self.dragged => Selection::Extend(anchor, cell),
+                    _ => Selection::Keep,
+                }
+            },
+            (true, false) if self.dragged => Selection::Copy,
+            _ => Selection::Keep,
+        }
+    }
+}
+
+/// Selection change caused by the event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Selection {
+    Keep,
+    Clear,
+    Extend(usize, usize),
+    Copy,
+}
+
+/// Handles a single mouse event.
+///
+/// Must be called within a critical section, usually from the mouse interrupt handler.
+pub fn handle_event(event: MouseEvent) {
+    let (cell, selection) = {
+        let mut pointer = POINTER.lock();
+        let selection = pointer.update(event);
+        (pointer.cell(), selection)
+    };
+
+    let mut logger = LOGGER.lock();
+    logger.set_pointer(Some(cell));
+    match selection {
+        Selection::Clear => logger.set_selection(None),
+        Selection::Extend(anchor, cell) => logger.set_selection(Some((anchor, cell))),
+        Selection::Copy => { workqueue::schedule(clipboard::copy_selection); },
+        Selection::Keep => (),
+    }
+}
+
+#[test_case]
+fn drag_selects_and_copies() {
+    let mut pointer = Pointer::new();
+    let start = pointer.cell();
+    let left = MouseButtons::LEFT.bits();
+
+    assert_eq!(pointer.update(MouseEvent { dx: 0, dy: 0, buttons: left }), Selection::Clear);
+    assert_eq!(
+        pointer.update(MouseEvent { dx: 3 * COUNTS_PER_COLUMN as i16, dy: 0, buttons: left }),
+        Selection::Extend(start, start + 3)
+    );
+    assert_eq!(pointer.update(MouseEvent { dx: 0, dy: 0, buttons: 0 }), Selection::Copy);
+
+    // A click without movement does not copy anything.
+    assert_eq!(pointer.update(MouseEvent { dx: 0, dy: 0, buttons: left }), Selection::Clear);
+    assert_eq!(pointer.update(MouseEvent { dx: 0, dy: 0, buttons: 0 }), Selection::Keep);
+}
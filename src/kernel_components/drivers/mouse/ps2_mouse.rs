@@ -0,0 +1,139 @@
+/// A driver module for PS/2 Mouse.
+
+use super::{MouseDriver, MouseEvent};
+use crate::kernel_components::arch_x86_64::controllers::{PS2, PSControllerConfiguration};
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+
+/// Acknowledge byte sent by PS/2 devices after each command.
+const ACK: u8 = 0xfa;
+/// Maximal amount of status polls while waiting for the device response.
+const RESPONSE_POLLS: usize = 100_000;
+
+/// Error type for the PS/2 mouse driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PS2MouseError {
+    /// The device did not respond in time.
+    Timeout,
+    /// The device responded with something other than an acknowledge.
+    NotAcknowledged(u8),
+}
+
+/// A driver for a standard 3 byte packet PS/2 mouse, connected to the second PS/2 port.
+#[derive(Debug)]
+pub struct PS2Mouse {
+    controller: PS2,
+    packet: [u8; 3],
+    index: usize,
+}
+
+impl PS2Mouse {
+    /// IRQ line of the second PS/2 port.
+    pub const IRQ: u8 = 12;
+
+    /// Creates a new instance of 'PS2Mouse'.
+    ///
+    /// This does not initialize the device. Use [´PS2Mouse::init´] for that.
+    pub const fn new() -> Self {
+        Self {
+            controller: PS2::new(),
+            packet: [0; 3],
+            index: 0,
+        }
+    }
+
+    /// Enables the second PS/2 port with interrupts and switches the mouse to streaming mode.
+    pub fn init(&mut self) -> Result<(), PS2MouseError> {
+        unsafe {
+            // Enabling the auxiliary device.
+            self.controller.write_raw_command(0xa8);
+
+            // Enabling IRQ12 in the controller configuration byte.
+            self.controller.write_raw_command(0x20);
+            let config = self.controller.read_data_timeout(RESPONSE_POLLS).ok_or(PS2MouseError::Timeout)?;
+            self.controller.write_raw_command(0x60);
+            // A set clock bit means that the clock of the port is disabled.
+            self.controller.write_data(
+                (config | PSControllerConfiguration::SECOND_PORT_INTERRUPT_ENABLE.bits())
+                    & !PSControllerConfiguration::SECOND_PS_2_CLOCK_PORT_ENABLE.bits()
+            );
+
+            // Set defaults, then enable data reporting.
+            self.command(0xf6)?;
+            self.command(0xf4)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a command to the mouse and waits for the acknowledge.
+    unsafe fn command(&mut self, byte: u8) -> Result<(), PS2MouseError> {
+        self.controller.write_second_data(byte);
+        match self.controller.read_data_timeout(RESPONSE_POLLS) {
+            Some(ACK) => Ok(()),
+            Some(other) => Err(PS2MouseError::NotAcknowledged(other)),
+            None => Err(PS2MouseError::Timeout),
+        }
+    }
+
+    /// Feeds a single byte of the packet.
+    ///
+    /// Returns the decoded event once all three bytes were received. Bytes which can not be the
+    /// first byte of a packet are dropped to resynchronize with the device.
+    pub fn decode(&mut self, byte: u8) -> Option<MouseEvent> {
+        // Bit 3 of the first byte is always set.
+        if self.index == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+
+        self.packet[self.index] = byte;
+        self.index += 1;
+        if self.index < self.packet.len() {
+            return None;
+        }
+        self.index = 0;
+
+        let [flags, x, y] = self.packet;
+        // Movement is dropped on overflow.
+        if flags & 0xc0 != 0 {
+            return Some(MouseEvent { dx: 0, dy: 0, buttons: flags & 0x07 });
+        }
+
+        Some(MouseEvent {
+            dx: x as i16 - ((flags as i16) << 4 & 0x100),
+            dy: y as i16 - ((flags as i16) << 3 & 0x100),
+            buttons: flags & 0x07,
+        })
+    }
+}
+
+impl MouseDriver for PS2Mouse {
+    fn read(&mut self) -> Option<MouseEvent> {
+        let byte = self.controller.read_data();
+        self.decode(byte)
+    }
+
+    fn irq(&self) -> u8 {
+        Self::IRQ
+    }
+}
+
+impl_driver!(PS2Mouse, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::READ | DriverCaps::IRQ)
+    .bound_to(BoundDevice::IoPort(0x60))
+);
+
+#[test_case]
+fn packets_are_decoded() {
+    let mut mouse = PS2Mouse::new();
+
+    // Garbage before the first byte of the packet.
+    assert_eq!(mouse.decode(0x00), None);
+    assert_eq!(mouse.decode(0x09), None);
+    assert_eq!(mouse.decode(0x05), None);
+    assert_eq!(mouse.decode(0x03), Some(MouseEvent { dx: 5, dy: 3, buttons: 1 }));
+
+    // Negative movement on both axes.
+    assert_eq!(mouse.decode(0x38), None);
+    assert_eq!(mouse.decode(0xfe), None);
+    assert_eq!(mouse.decode(0xff), Some(MouseEvent { dx: -2, dy: -1, buttons: 0 }));
+}
@@ -0,0 +1,158 @@
+/// A polled driver for the 16550 compatible UARTs of the PC.
+///
+/// The driver never enables the interrupts of the chip, bytes are read by polling the line status.
+/// COM1 is shared with the serial log sink and the emergency output, which both write to it
+/// without the driver.
+
+use core::fmt::Display;
+use core::error::Error;
+use core::arch::x86_64::_rdtsc;
+
+use crate::kernel_components::arch_x86_64::ports::{GenericPort, PortAccessType};
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+use crate::kernel_components::memory::vdso;
+
+/// Base port of the first serial port.
+pub const COM1: u16 = 0x3f8;
+/// Base port of the second serial port.
+pub const COM2: u16 = 0x2f8;
+
+/// Frequency of the baud rate generator divided by 16.
+const MAX_BAUD: u32 = 115_200;
+/// Line status bit of a received byte waiting in the receiver.
+const DATA_READY: u8 = 1 << 0;
+/// Line status bit of an empty transmitter holding register.
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+/// Modem control bits of the loopback mode with the outputs raised.
+const LOOPBACK: u8 = 0x1e;
+/// Modem control bits of the normal operation: DTR, RTS and OUT2.
+const NORMAL: u8 = 0x0b;
+/// Polls of the line status per millisecond, assumed while the TSC is not calibrated. A port read
+/// takes about a microsecond.
+const POLLS_PER_MS: u64 = 1000;
+/// Maximal amount of polls of the line status before a byte is dropped.
+const TRANSMIT_POLLS: usize = 100_000;
+
+/// Error type for the UART driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UartError {
+    /// The baud rate is zero or does not divide 115200.
+    InvalidBaudRate(u32),
+    /// No chip at the port returned the byte sent in the loopback mode.
+    NotPresent(u16),
+}
+
+impl Display for UartError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidBaudRate(baud) => write!(f, "Unsupported baud rate {}.", baud),
+            Self::NotPresent(port) => write!(f, "No UART found at port {:#x}.", port),
+        }
+    }
+}
+
+impl Error for UartError {}
+
+/// A 16550 compatible UART at the base port.
+pub struct Uart16550 {
+    base: u16,
+    data: GenericPort<u8>,
+    line_status: GenericPort<u8>,
+}
+
+impl Uart16550 {
+    /// Creates a new instance of the UART at the base port.
+    ///
+    /// This does not program the chip. Use [´Uart16550::init´] for that.
+    pub const fn new(base: u16) -> Self {
+        Self {
+            base,
+            data: GenericPort::new(base, PortAccessType::READWRITE),
+            line_status: GenericPort::new(base + 5, PortAccessType::READONLY),
+        }
+    }
+
+    /// Programs the UART for the baud rate, 8N1, with it's interrupts disabled, and checks that
+    /// the chip exists in the loopback mode.
+    pub fn init(&mut self, baud: u32) -> Result<(), UartError> {
+        if baud == 0 || MAX_BAUD % baud != 0 {
+            return Err(UartError::InvalidBaudRate(baud))
+        }
+        let divisor = (MAX_BAUD / baud) as u16;
+
+        self.register(1, 0x00);
+        // Divisor latch access, then the divisor in two bytes.
+        self.register(3, 0x80);
+        self.register(0, divisor as u8);
+        self.register(1, (divisor >> 8) as u8);
+        self.register(3, 0x03);
+        // FIFOs enabled and cleared, with a 14 byte threshold.
+        self.register(2, 0xc7);
+
+        self.register(4, LOOPBACK);
+        self.data.write(0xae);
+        let echoed = self.data.read();
+        self.register(4, NORMAL);
+        match echoed {
+            0xae => Ok(()),
+            _ => Err(UartError::NotPresent(self.base)),
+        }
+    }
+
+    /// Returns the base port of the UART.
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    /// Sends the byte, dropping it if the transmitter stays busy.
+    pub fn send(&mut self, byte: u8) {
+        for _ in 0..TRANSMIT_POLLS {
+            if self.line_status.read() & TRANSMIT_EMPTY != 0 {
+                return self.data.write(byte)
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Returns the received byte, if any.
+    pub fn try_recv(&mut self) -> Option<u8> {
+        match self.line_status.read() & DATA_READY {
+            0 => None,
+            _ => Some(self.data.read()),
+        }
+    }
+
+    /// Waits for the received byte at most the amount of milliseconds.
+    ///
+    /// The time is measured with the TSC if the clock calibrated it, otherwise the polls are
+    /// counted.
+    pub fn recv_timeout(&mut self, ms: u32) -> Option<u8> {
+        let tsc_per_ms = vdso::data().snapshot().tsc_per_ms;
+        let (start, mut polls) = (unsafe { _rdtsc() }, 0);
+        loop {
+            if let Some(byte) = self.try_recv() {
+                return Some(byte)
+            }
+            polls += 1;
+            let elapsed = match tsc_per_ms {
+                0 => polls / POLLS_PER_MS,
+                tsc_per_ms => unsafe { _rdtsc() }.wrapping_sub(start) / tsc_per_ms,
+            };
+            if elapsed >= ms as u64 {
+                return None
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Writes to the register at the offset from the base port.
+    fn register(&self, offset: u16, value: u8) {
+        GenericPort::<u8>::new(self.base + offset, PortAccessType::WRITEONLY).write(value);
+    }
+}
+
+impl_driver!(Uart16550, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::READ | DriverCaps::WRITE)
+    .bound_to(BoundDevice::IoPort(s.base))
+);
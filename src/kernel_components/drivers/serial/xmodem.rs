@@ -0,0 +1,271 @@
+/// XMODEM receiver for pushing files to the machine over a serial line.
+///
+/// The receiver asks for the 16 bit CRC variant first and falls back to the original arithmetic
+/// checksum if the sender does not start. Both 128 byte and 1 KiB blocks are accepted, so XMODEM-1K
+/// senders work as well, i.e. `sx -k file < /dev/ttyS0 > /dev/ttyS0` from lrzsz or the XMODEM
+/// upload of minicom and picocom.
+///
+/// The protocol does not transfer the size of the file. The last block is padded with SUB (0x1a)
+/// bytes, which are stripped from the end of the file, so a file ending with such bytes is
+/// shortened. Corrupted or lost blocks are repeated by the sender on NAK, the transfer is canceled
+/// after too many errors in a row.
+
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::error::Error;
+
+use super::Uart16550;
+
+/// Start of a 128 byte block.
+const SOH: u8 = 0x01;
+/// Start of a 1 KiB block.
+const STX: u8 = 0x02;
+/// End of the transfer.
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+/// Cancels the transfer when received twice in a row.
+const CAN: u8 = 0x18;
+/// Padding of the last block.
+const SUB: u8 = 0x1a;
+/// Requests the transfer with a CRC instead of a NAK.
+const CRC_REQUEST: u8 = b'C';
+
+/// Requests sent before the sender starts, which gives a minute to start it.
+const START_ATTEMPTS: usize = 20;
+/// Requests of the CRC variant before falling back to the checksum.
+const CRC_ATTEMPTS: usize = 10;
+/// Milliseconds to wait for the start of the transfer after each request.
+const START_TIMEOUT_MS: u32 = 3000;
+/// Milliseconds to wait for the next block.
+const BLOCK_TIMEOUT_MS: u32 = 10_000;
+/// Milliseconds to wait for each byte within a block. Also the silence which ends a purge.
+const BYTE_TIMEOUT_MS: u32 = 1000;
+/// Errors in a row after which the transfer is canceled.
+const MAX_ERRORS: usize = 10;
+
+/// Byte oriented link to the sender.
+pub trait SerialLink {
+    /// Sends the byte.
+    fn send(&mut self, byte: u8);
+    /// Waits for the received byte at most the amount of milliseconds.
+    fn recv_timeout(&mut self, ms: u32) -> Option<u8>;
+}
+
+impl SerialLink for Uart16550 {
+    fn send(&mut self, byte: u8) {
+        Uart16550::send(self, byte)
+    }
+
+    fn recv_timeout(&mut self, ms: u32) -> Option<u8> {
+        Uart16550::recv_timeout(self, ms)
+    }
+}
+
+/// Error type of the XMODEM receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XmodemError {
+    /// The sender did not start or stopped sending.
+    Timeout,
+    /// The sender canceled the transfer.
+    Canceled,
+    /// Too many blocks in a row were corrupted.
+    TooManyErrors,
+    /// The sender skipped a block.
+    OutOfSync,
+    /// The file is larger than the provided limit.
+    TooLarge,
+}
+
+impl Display for XmodemError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "The sender timed out."),
+            Self::Canceled => write!(f, "The transfer was canceled by the sender."),
+            Self::TooManyErrors => write!(f, "Too many corrupted blocks."),
+            Self::OutOfSync => write!(f, "The sender skipped a block."),
+            Self::TooLarge => write!(f, "The file exceeds the size limit."),
+        }
+    }
+}
+
+impl Error for XmodemError {}
+
+/// Receives a single file of at most the limit in bytes.
+pub fn receive(link: &mut impl SerialLink, limit: usize) -> Result<Vec<u8>, XmodemError> {
+    let (mut crc, mut next) = (true, None);
+    for attempt in 0..START_ATTEMPTS {
+        crc = attempt < CRC_ATTEMPTS;
+        link.send(if crc { CRC_REQUEST } else { NAK });
+        next = link.recv_timeout(START_TIMEOUT_MS);
+        if next.is_some() {
+            break
+        }
+    }
+    if next.is_none() {
+        return Err(XmodemError::Timeout)
+    }
+
+    let (mut data, mut last_block, mut expected, mut errors) = (Vec::new(), 0, 1u8, 0);
+    loop {
+        if errors > MAX_ERRORS {
+            cancel(link);
+            return Err(XmodemError::TooManyErrors)
+        }
+        let Some(header) = next.take().or_else(|| link.recv_timeout(BLOCK_TIMEOUT_MS)) else {
+            if errors == MAX_ERRORS {
+                cancel(link);
+                return Err(XmodemError::Timeout)
+            }
+            errors += 1;
+            link.send(NAK);
+            continue
+        };
+
+        match header {
+            SOH | STX => {
+                let size = if header == SOH { 128 } else { 1024 };
+                match read_block(link, size, crc) {
+                    Some((num, block)) if num == expected => {
+                        if data.len() + block.len() > limit {
+                            cancel(link);
+                            return Err(XmodemError::TooLarge)
+                        }
+                        data.extend_from_slice(&block);
+                        (last_block, expected, errors) = (block.len(), expected.wrapping_add(1), 0);
+                        link.send(ACK);
+                    },
+                    // The acknowledge of the previous block was lost.
+                    Some((num, _)) if num == expected.wrapping_sub(1) => link.send(ACK),
+                    Some(_) => {
+                        cancel(link);
+                        return Err(XmodemError::OutOfSync)
+                    },
+                    None => {
+                        errors += 1;
+                        purge(link);
+                        link.send(NAK);
+                    },
+                }
+            },
+            EOT => {
+                link.send(ACK);
+                let padded = data.len() - last_block;
+                while data.len() > padded && data.last() == Some(&SUB) {
+                    data.pop();
+                }
+                return Ok(data)
+            },
+            CAN if link.recv_timeout(BYTE_TIMEOUT_MS) == Some(CAN) => return Err(XmodemError::Canceled),
+            _ => {
+                errors += 1;
+                purge(link);
+                link.send(NAK);
+            },
+        }
+    }
+}
+
+/// Reads the rest of a block after it's header. Returns the block number with the data, or None
+/// if the block is incomplete or corrupted.
+fn read_block(link: &mut impl SerialLink, size: usize, crc: bool) -> Option<(u8, Vec<u8>)> {
+    let num = link.recv_timeout(BYTE_TIMEOUT_MS)?;
+    let complement = link.recv_timeout(BYTE_TIMEOUT_MS)?;
+    let mut block = Vec::with_capacity(size);
+    for _ in 0..size {
+        block.push(link.recv_timeout(BYTE_TIMEOUT_MS)?);
+    }
+
+    let valid = if crc {
+        let high = link.recv_timeout(BYTE_TIMEOUT_MS)?;
+        let low = link.recv_timeout(BYTE_TIMEOUT_MS)?;
+        u16::from_be_bytes([high, low]) == crc16(&block)
+    } else {
+        link.recv_timeout(BYTE_TIMEOUT_MS)? == block.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+    };
+    (valid && num == !complement).then_some((num, block))
+}
+
+/// Drops the received bytes until the line is silent, so the sender can repeat the block.
+fn purge(link: &mut impl SerialLink) {
+    while link.recv_timeout(BYTE_TIMEOUT_MS).is_some() {}
+}
+
+/// Tells the sender to stop.
+fn cancel(link: &mut impl SerialLink) {
+    (0..3).for_each(|_| link.send(CAN));
+}
+
+/// CRC-16 of the XMODEM protocol, with the 0x1021 polynomial and no initial value.
+const fn crc16(data: &[u8]) -> u16 {
+    let (mut crc, mut i) = (0u16, 0);
+    while i < data.len() {
+        crc ^= (data[i] as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+            bit += 1;
+        }
+        i += 1;
+    }
+    crc
+}
+
+#[test_case]
+fn xmodem_receives_blocks_and_repeats_corrupted_ones() {
+    use alloc::collections::VecDeque;
+
+    /// Replays the bytes of the sender, where None is a moment of silence.
+    struct Script {
+        input: VecDeque<Option<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl SerialLink for Script {
+        fn send(&mut self, byte: u8) {
+            self.sent.push(byte);
+        }
+
+        fn recv_timeout(&mut self, _: u32) -> Option<u8> {
+            self.input.pop_front().flatten()
+        }
+    }
+
+    fn block(num: u8, data: &[u8], crc: u16) -> impl Iterator<Item = Option<u8>> {
+        let mut bytes = alloc::vec![SOH, num, !num];
+        bytes.extend_from_slice(data);
+        bytes.resize(3 + 128, SUB);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+        bytes.into_iter().map(Some)
+    }
+
+    assert_eq!(crc16(b"123456789"), 0x31c3);
+
+    let first = [b'a'; 128];
+    let mut last = [SUB; 128];
+    last[..5].copy_from_slice(b"hello");
+    let mut input = VecDeque::new();
+    input.extend(block(1, &first, crc16(&first)));
+    input.extend(block(1, &first, crc16(&first)));
+    input.extend(block(2, &last, !crc16(&last)));
+    input.push_back(None);
+    input.extend(block(2, &last, crc16(&last)));
+    input.push_back(Some(EOT));
+
+    let mut script = Script { input, sent: Vec::new() };
+    let data = receive(&mut script, 1024).unwrap();
+    assert_eq!((&data[..128], &data[128..]), (&first[..], &b"hello"[..]));
+    assert_eq!(script.sent, [CRC_REQUEST, ACK, ACK, NAK, ACK, ACK]);
+
+    let mut script = Script { input: [Some(CAN), Some(CAN)].into_iter().collect(), sent: Vec::new() };
+    assert_eq!(receive(&mut script, 1024), Err(XmodemError::Canceled));
+
+    let mut script = Script { input: block(1, &first, crc16(&first)).collect(), sent: Vec::new() };
+    assert_eq!(receive(&mut script, 100), Err(XmodemError::TooLarge));
+    assert_eq!(script.sent, [CRC_REQUEST, CAN, CAN, CAN]);
+
+    let mut script = Script { input: VecDeque::new(), sent: Vec::new() };
+    assert_eq!(receive(&mut script, 1024), Err(XmodemError::Timeout));
+    assert_eq!(script.sent.iter().filter(|&&byte| byte == CRC_REQUEST).count(), CRC_ATTEMPTS);
+    assert_eq!(script.sent.len(), START_ATTEMPTS);
+}
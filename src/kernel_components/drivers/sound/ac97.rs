@@ -0,0 +1,323 @@
+/// AC'97 audio codec driver with interrupt-driven PCM playback.
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+use alloc::collections::VecDeque;
+use core::alloc::Layout;
+
+use super::{AudioDriver, AudioError};
+use crate::kernel_components::arch_x86_64::pci::{self, PciDevice};
+use crate::kernel_components::arch_x86_64::ports::{GenericPort, PortAccessType};
+use crate::kernel_components::memory::MEMORY_MANAGEMENT_UNIT;
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+use crate::critical_section;
+
+/// Amount of entries in the buffer descriptor list. Fixed by the specification.
+const BDL_ENTRIES: usize = 32;
+/// Amount of DMA buffers that are cycled through the descriptor list.
+const DMA_BUFFERS: usize = 4;
+/// Size of a single DMA buffer. One page, so that it is always physically contiguous.
+const DMA_BUFFER_SIZE: usize = 4096;
+/// Amount of 16-bit samples within a single DMA buffer.
+const SAMPLES_PER_BUFFER: usize = DMA_BUFFER_SIZE / 2;
+/// The only rate that every AC'97 codec supports.
+const DEFAULT_RATE: u32 = 48000;
+
+/* Native Audio Mixer registers (BAR0). */
+const NAM_RESET: u16                = 0x00;
+const NAM_MASTER_VOLUME: u16        = 0x02;
+const NAM_PCM_OUT_VOLUME: u16       = 0x18;
+const NAM_EXT_AUDIO_ID: u16         = 0x28;
+const NAM_EXT_AUDIO_CTRL: u16       = 0x2a;
+const NAM_PCM_FRONT_RATE: u16       = 0x2c;
+
+/* Native Audio Bus Master registers (BAR1). PCM out box. */
+const NABM_PO_BDBAR: u16            = 0x10;
+const NABM_PO_CIV: u16              = 0x14;
+const NABM_PO_LVI: u16              = 0x15;
+const NABM_PO_SR: u16               = 0x16;
+const NABM_PO_CR: u16               = 0x1b;
+const NABM_GLOBAL_CONTROL: u16      = 0x2c;
+
+/* Bits of the PCM out control register. */
+const CR_RUN: u8                    = 1 << 0;
+const CR_RESET: u8                  = 1 << 1;
+const CR_LVBIE: u8                  = 1 << 2;
+const CR_IOCE: u8                   = 1 << 4;
+
+/* Bits of the PCM out status register. Bits 2..=4 are cleared by writing 1. */
+const SR_LVBCI: u16                 = 1 << 2;
+const SR_BCIS: u16                  = 1 << 3;
+const SR_FIFOE: u16                 = 1 << 4;
+
+/// Single entry of the buffer descriptor list.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct BufferDescriptor {
+    /// Physical address of the buffer.
+    address: u32,
+    /// Amount of samples within the buffer.
+    samples: u16,
+    /// Bit 15 raises an interrupt on completion. Bit 14 plays zeroes after the last buffer.
+    flags: u16,
+}
+
+impl BufferDescriptor {
+    const IOC: u16 = 1 << 15;
+    const BUP: u16 = 1 << 14;
+}
+
+/// AC'97 audio controller driver.
+///
+/// The controller is discovered on the PCI bus as a multimedia audio device (class 0x04,
+/// subclass 0x01). Playback is performed with the PCM out DMA engine, which walks a ring of 32
+/// buffer descriptors. Only a few page sized DMA buffers are cycled through the ring, and each
+/// completed buffer is refilled from the sample queue within the interrupt handler.
+///
+/// Intel HDA controllers (subclass 0x03) use a completely different command interface and are
+/// not supported by this driver.
+pub struct AC97 {
+    device: PciDevice,
+    /// Native Audio Mixer I/O base.
+    nam: u16,
+    /// Native Audio Bus Master I/O base.
+    nabm: u16,
+    /// Buffer descriptor list shared with the controller.
+    bdl: *mut BufferDescriptor,
+    /// DMA buffers cycled through the descriptor list.
+    buffers: [*mut i16; DMA_BUFFERS],
+    /// Samples that are still waiting to be written into the DMA buffers.
+    queue: VecDeque<i16>,
+    /// True if the codec supports variable rate audio.
+    variable_rate: bool,
+    /// Current master volume in range 0..=100.
+    volume: u8,
+    is_playing: bool,
+}
+
+impl AC97 {
+    /// Finds the first AC'97 controller on the PCI bus and initializes it.
+    pub fn new() -> Result<Self, AudioError> {
+        let device = pci::find_by_class(0x04, 0x01).ok_or(AudioError::DeviceNotFound)?;
+        let nam = device.io_bar(0).ok_or(AudioError::DeviceNotFound)?;
+        let nabm = device.io_bar(1).ok_or(AudioError::DeviceNotFound)?;
+
+        device.enable_bus_master();
+
+        let bdl = unsafe {
+            alloc_zeroed(Self::bdl_layout()) as *mut BufferDescriptor
+        };
+        let mut buffers = [core::ptr::null_mut(); DMA_BUFFERS];
+        for buf in buffers.iter_mut() {
+            *buf = unsafe { alloc_zeroed(Self::buffer_layout()) as *mut i16 };
+        }
+
+        let mut ac97 = Self {
+            device, nam, nabm, bdl, buffers,
+            queue: VecDeque::new(),
+            variable_rate: false,
+            volume: 100,
+            is_playing: false,
+        };
+
+        // Cold reset of the AC-link and the codec registers.
+        ac97.nabm_port::<u32>(NABM_GLOBAL_CONTROL).write(0b10);
+        ac97.nam_port::<u16>(NAM_RESET).write(0);
+
+        // Enabling variable rate audio if the codec supports it.
+        if ac97.nam_port::<u16>(NAM_EXT_AUDIO_ID).read() & 1 == 1 {
+            let ctrl = ac97.nam_port::<u16>(NAM_EXT_AUDIO_CTRL);
+            ctrl.write(ctrl.read() | 1);
+            ac97.variable_rate = true;
+        }
+
+        ac97.nam_port::<u16>(NAM_PCM_OUT_VOLUME).write(0x0808);
+        ac97.set_volume(100);
+        ac97.reset_dma()?;
+
+        Ok(ac97)
+    }
+
+    /// Returns the PCI function of the controller.
+    pub fn device(&self) -> PciDevice {
+        self.device
+    }
+
+    /// Resets the PCM out DMA engine and points it to the buffer descriptor list.
+    fn reset_dma(&mut self) -> Result<(), AudioError> {
+        let cr = self.nabm_port::<u8>(NABM_PO_CR);
+        cr.write(CR_RESET);
+        while cr.read() & CR_RESET != 0 {}
+
+        let bdl_phys = Self::physical(self.bdl as usize)?;
+        self.nabm_port::<u32>(NABM_PO_BDBAR).write(bdl_phys as u32);
+
+        for i in 0..BDL_ENTRIES {
+            let buffer = self.buffers[i % DMA_BUFFERS] as usize;
+            unsafe {
+                self.bdl.add(i).write(BufferDescriptor {
+                    address: Self::physical(buffer)? as u32,
+                    samples: 0,
+                    flags: BufferDescriptor::IOC | BufferDescriptor::BUP,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copies queued samples into the DMA buffer of provided descriptor entry.
+    ///
+    /// Returns false if there was nothing to copy.
+    fn fill_entry(&mut self, entry: usize) -> bool {
+        if self.queue.is_empty() {
+            return false
+        }
+
+        let buffer = self.buffers[entry % DMA_BUFFERS];
+        let count = self.queue.len().min(SAMPLES_PER_BUFFER);
+
+        for i in 0..count {
+            unsafe { buffer.add(i).write(self.queue.pop_front().unwrap()) };
+        }
+
+        unsafe { (*self.bdl.add(entry)).samples = count as u16 };
+        true
+    }
+
+    /// Physical address of the virtual address for DMA purposes.
+    ///
+    /// The controller only accepts 32-bit physical addresses.
+    fn physical(addr: usize) -> Result<usize, AudioError> {
+        unsafe { MEMORY_MANAGEMENT_UNIT.translate(addr) }
+            .filter(|phys| *phys <= u32::MAX as usize)
+            .ok_or(AudioError::DmaUnavailable)
+    }
+
+    fn nam_port<T: crate::kernel_components::arch_x86_64::ports::Port<T>>(&self, reg: u16) -> GenericPort<T> {
+        GenericPort::new(self.nam + reg, PortAccessType::READWRITE)
+    }
+
+    fn nabm_port<T: crate::kernel_components::arch_x86_64::ports::Port<T>>(&self, reg: u16) -> GenericPort<T> {
+        GenericPort::new(self.nabm + reg, PortAccessType::READWRITE)
+    }
+
+    fn bdl_layout() -> Layout {
+        Layout::from_size_align(BDL_ENTRIES * core::mem::size_of::<BufferDescriptor>(), 256).unwrap()
+    }
+
+    fn buffer_layout() -> Layout {
+        Layout::from_size_align(DMA_BUFFER_SIZE, DMA_BUFFER_SIZE).unwrap()
+    }
+}
+
+impl AudioDriver for AC97 {
+    fn play_pcm(&mut self, samples: &[i16], rate: u32) -> Result<(), AudioError> {
+        if rate != DEFAULT_RATE {
+            if !self.variable_rate || rate > u16::MAX as u32 {
+                return Err(AudioError::UnsupportedRate(rate))
+            }
+        }
+
+        critical_section!(|| {
+            self.queue.extend(samples.iter().copied());
+
+            if self.is_playing {
+                return Ok(())
+            }
+
+            if self.variable_rate {
+                self.nam_port::<u16>(NAM_PCM_FRONT_RATE).write(rate as u16);
+            }
+
+            self.reset_dma()?;
+
+            // Filling all DMA buffers ahead before starting the engine.
+            let mut last = 0;
+            for entry in 0..DMA_BUFFERS {
+                if !self.fill_entry(entry) {
+                    break
+                }
+                last = entry;
+            }
+
+            self.nabm_port::<u8>(NABM_PO_LVI).write(last as u8);
+            self.nabm_port::<u8>(NABM_PO_CR).write(CR_RUN | CR_IOCE | CR_LVBIE);
+            self.is_playing = true;
+
+            Ok(())
+        })
+    }
+
+    fn stop(&mut self) {
+        critical_section!(|| {
+            self.nabm_port::<u8>(NABM_PO_CR).write(0);
+            self.queue.clear();
+            self.is_playing = false;
+        });
+    }
+
+    fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(100);
+
+        // The register holds the attenuation in 1.5 dB steps, where bit 15 mutes the output.
+        let reg = if self.volume == 0 {
+            1 << 15
+        } else {
+            let attenuation = (63 - (self.volume as u16 * 63) / 100) & 0x3f;
+            attenuation << 8 | attenuation
+        };
+
+        self.nam_port::<u16>(NAM_MASTER_VOLUME).write(reg);
+    }
+
+    fn volume(&mut self) -> u8 {
+        self.volume
+    }
+
+    fn irq(&self) -> u8 {
+        self.device.interrupt_line()
+    }
+
+    fn handle_interrupt(&mut self) {
+        let sr = self.nabm_port::<u16>(NABM_PO_SR);
+        let status = sr.read();
+
+        if status & SR_BCIS != 0 {
+            // The next entry after the last valid one reuses the buffer that was just consumed.
+            let lvi = self.nabm_port::<u8>(NABM_PO_LVI).read() as usize;
+            let next = (lvi + 1) % BDL_ENTRIES;
+
+            if self.fill_entry(next) {
+                self.nabm_port::<u8>(NABM_PO_LVI).write(next as u8);
+            }
+        }
+
+        if status & SR_LVBCI != 0 && self.queue.is_empty() {
+            let civ = self.nabm_port::<u8>(NABM_PO_CIV).read();
+            if civ == self.nabm_port::<u8>(NABM_PO_LVI).read() {
+                self.is_playing = false;
+            }
+        }
+
+        sr.write(status & (SR_LVBCI | SR_BCIS | SR_FIFOE));
+    }
+}
+
+impl Drop for AC97 {
+    fn drop(&mut self) {
+        self.stop();
+        unsafe {
+            dealloc(self.bdl as *mut u8, Self::bdl_layout());
+            for buf in self.buffers {
+                dealloc(buf as *mut u8, Self::buffer_layout());
+            }
+        }
+    }
+}
+
+impl_driver!(AC97, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::WRITE | DriverCaps::IRQ | DriverCaps::DMA)
+    .bound_to(BoundDevice::Pci(s.device)),
+    |s| s.stop()
+);
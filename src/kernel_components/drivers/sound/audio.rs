@@ -0,0 +1,46 @@
+use alloc::boxed::Box;
+
+/// A module that defines a global interface to OS audio output.
+
+use crate::kernel_components::drivers::{Driver, DriverInfo};
+
+/// Error type for audio drivers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AudioError {
+    /// No supported audio controller was found on the PCI bus.
+    DeviceNotFound,
+    /// The controller cannot play samples with the requested rate.
+    UnsupportedRate(u32),
+    /// Unable to obtain a physical address for the DMA buffers.
+    DmaUnavailable,
+}
+
+/// An audio driver trait.
+///
+/// All PCM playback drivers must implement this trait for global use throughout the OS.
+pub trait AudioDriver: Driver {
+    /// Queues the provided samples for playback and starts the playback if it is not running.
+    ///
+    /// Samples are expected to be signed 16-bit little endian values in interleaved stereo
+    /// format. The rate is the sample rate in Hz.
+    fn play_pcm(&mut self, samples: &[i16], rate: u32) -> Result<(), AudioError>;
+
+    /// Stops the playback and drops all queued samples.
+    fn stop(&mut self);
+
+    /// Sets the master volume in range 0..=100. Zero mutes the output.
+    fn set_volume(&mut self, volume: u8);
+
+    /// Returns the current master volume in range 0..=100.
+    fn volume(&mut self) -> u8;
+
+    /// Returns the legacy IRQ line used by the controller.
+    fn irq(&self) -> u8;
+
+    /// Must be called from the interrupt handler of the controller.
+    ///
+    /// Acknowledges the interrupt and refills the consumed DMA buffers with queued samples.
+    fn handle_interrupt(&mut self);
+}
+
+impl_driver!(Box<dyn AudioDriver>, |s| s.as_ref().info(), |s| s.stop());
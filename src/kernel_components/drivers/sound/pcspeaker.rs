@@ -0,0 +1,93 @@
+/// PC speaker driver. The most primitive sound output available on x86 machines.
+
+use crate::kernel_components::arch_x86_64::controllers::{PIT, PITCommand};
+use crate::kernel_components::arch_x86_64::ports::{GenericPort, PortAccessType};
+use crate::kernel_components::task_virtualization::Thread;
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+use crate::critical_section;
+
+/// Base frequency of the PIT oscillator in Hz.
+const PIT_FREQUENCY: u32 = 1_193_182;
+
+/// PC speaker driver.
+///
+/// The speaker is connected to the output of PIT channel 2. The channel is configured as a
+/// square wave generator with the divisor based on the requested frequency, while the speaker
+/// itself is gated via the keyboard controller port B (0x61).
+///
+/// # Port 0x61
+///
+/// - Bit 0: Timer 2 gate. Enables the counting on PIT channel 2.
+/// - Bit 1: Speaker data. Connects the channel 2 output to the speaker.
+pub struct PCSpeaker {
+    pit: PIT,
+    port_b: GenericPort<u8>,
+}
+
+impl PCSpeaker {
+    /// Bits of port B that must be set for the speaker to produce sound.
+    const SPEAKER_ENABLE: u8 = 0b11;
+
+    /// Creates a new instance of the PC speaker driver.
+    pub fn new() -> Self {
+        Self {
+            pit: PIT::new(),
+            port_b: GenericPort::new(0x61, PortAccessType::READWRITE),
+        }
+    }
+
+    /// Starts playing the tone with the provided frequency in Hz.
+    ///
+    /// The tone will be played until the stop function is called. Frequencies lower than 19 Hz
+    /// cannot be generated by the PIT, therefore they are clamped.
+    pub fn play(&mut self, freq: u32) {
+        let divisor = (PIT_FREQUENCY / freq.max(19)) as u16;
+
+        critical_section!(|| {
+            unsafe {
+                self.pit.command(
+                    PITCommand::CHANNEL2 | PITCommand::FULL_WORD | PITCommand::SQUARE_WAVE_GENERATOR
+                );
+            }
+            self.pit.channel2.write(divisor);
+
+            let state = self.port_b.read();
+            if state & Self::SPEAKER_ENABLE != Self::SPEAKER_ENABLE {
+                self.port_b.write(state | Self::SPEAKER_ENABLE);
+            }
+        });
+    }
+
+    /// Silences the speaker.
+    pub fn stop(&mut self) {
+        let state = self.port_b.read();
+        self.port_b.write(state & !Self::SPEAKER_ENABLE);
+    }
+
+    /// Returns true if the speaker is currently connected to the PIT channel 2.
+    pub fn is_playing(&self) -> bool {
+        self.port_b.read() & Self::SPEAKER_ENABLE == Self::SPEAKER_ENABLE
+    }
+
+    /// Plays the tone with the provided frequency for a certain amount of milliseconds.
+    ///
+    /// There is no timer wheel to schedule the stop, therefore the calling thread sleeps for the
+    /// requested duration and silences the speaker afterwards. The precision of the duration
+    /// depends on the loaded clock driver.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if no clock driver is loaded.
+    pub fn beep(&mut self, freq: u32, duration: u32) {
+        self.play(freq);
+        Thread::sleep(duration);
+        self.stop();
+    }
+}
+
+impl_driver!(PCSpeaker, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::WRITE)
+    .bound_to(BoundDevice::IoPort(0x61)),
+    |s| s.stop()
+);
@@ -0,0 +1,301 @@
+/// A module that defines a global interface to block storage devices.
+///
+/// Every transfer is described with a [`BlockRequest`] and submitted to the device, which returns an
+/// [`IoFuture`] right away. Interrupt driven devices complete the request later from their interrupt
+/// handler through the paired [`IoCompletion`], so the caller may keep many requests in flight and
+/// only wait for the ones whose data it needs right now.
+///
+/// Request buffers are owned by the request. The buffer of a read is returned filled on completion,
+/// while the buffer of a write is given back so it can be reused.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use crate::kernel_components::drivers::{Driver, DriverInfo};
+use crate::kernel_components::sync::Mutex;
+use crate::kernel_components::task_virtualization::Thread;
+use crate::critical_section;
+
+/// Size of a single sector in bytes.
+pub const SECTOR_SIZE: usize = 512;
+
+/// Output of a finished request. Holds the buffer of the request.
+pub type IoResult = Result<Vec<u8>, BlockError>;
+
+/// Error type for block devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlockError {
+    /// The request touches sectors past the end of the device.
+    OutOfRange { lba: u64, count: u32 },
+    /// The size of the write buffer is not a multiple of the sector size.
+    Unaligned(usize),
+    /// The request is bigger than the device can handle at once.
+    TooLarge(u32),
+    /// Writing to a read-only device.
+    ReadOnly,
+    /// The operation is not supported by the device.
+    Unsupported,
+    /// The device reported an error.
+    DeviceFailure,
+    /// The device was reset or detached before the request was done.
+    Aborted,
+}
+
+/// Operation performed by the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOp {
+    /// Reads sectors from the device.
+    Read,
+    /// Writes sectors to the device.
+    Write,
+    /// Flushes the write cache of the device.
+    Flush,
+}
+
+/// A single transfer between the memory and the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRequest {
+    /// Requested operation.
+    pub op: BlockOp,
+    /// First sector of the transfer.
+    pub lba: u64,
+    /// Amount of sectors.
+    pub count: u32,
+    /// Data to write or the buffer for the read.
+    pub buffer: Vec<u8>,
+}
+
+impl BlockRequest {
+    /// Creates a request that reads the provided amount of sectors.
+    pub fn read(lba: u64, count: u32) -> Self {
+        Self { op: BlockOp::Read, lba, count, buffer: vec![0; count as usize * SECTOR_SIZE] }
+    }
+
+    /// Creates a request that writes the buffer starting from the provided sector.
+    pub fn write(lba: u64, buffer: Vec<u8>) -> Self {
+        Self { op: BlockOp::Write, lba, count: (buffer.len() / SECTOR_SIZE) as u32, buffer }
+    }
+
+    /// Creates a request that flushes the write cache of the device.
+    pub fn flush() -> Self {
+        Self { op: BlockOp::Flush, lba: 0, count: 0, buffer: Vec::new() }
+    }
+
+    /// Size of the transfer in bytes.
+    pub fn len(&self) -> usize {
+        self.count as usize * SECTOR_SIZE
+    }
+
+    /// Validates the request against the device with provided capacity in sectors.
+    pub fn check(&self, capacity: u64, read_only: bool) -> Result<(), BlockError> {
+        match self.op {
+            BlockOp::Flush => return Ok(()),
+            BlockOp::Write if read_only => return Err(BlockError::ReadOnly),
+            BlockOp::Write if self.buffer.len() % SECTOR_SIZE != 0 => {
+                return Err(BlockError::Unaligned(self.buffer.len()))
+            },
+            _ => (),
+        }
+
+        match self.lba.checked_add(self.count as u64) {
+            Some(end) if end <= capacity => Ok(()),
+            _ => Err(BlockError::OutOfRange { lba: self.lba, count: self.count }),
+        }
+    }
+}
+
+/// State shared between the future and the completion.
+struct IoState {
+    done: AtomicBool,
+    result: Mutex<Option<IoResult>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// Creates a connected pair of the future handed to the submitter and the completion kept by the
+/// driver.
+pub fn io_pair() -> (IoFuture, IoCompletion) {
+    let state = Arc::new(IoState {
+        done: AtomicBool::new(false),
+        result: Mutex::new(None),
+        waker: Mutex::new(None),
+    });
+    (IoFuture { state: state.clone() }, IoCompletion { state })
+}
+
+/// Completion handle of a submitted request.
+///
+/// Can be polled, awaited or waited for in a blocking way.
+pub struct IoFuture {
+    state: Arc<IoState>,
+}
+
+impl IoFuture {
+    /// Creates a future that is already complete.
+    pub fn ready(result: IoResult) -> Self {
+        let (future, completion) = io_pair();
+        completion.complete(result);
+        future
+    }
+
+    /// Returns true if the request is done.
+    pub fn is_complete(&self) -> bool {
+        self.state.done.load(Ordering::Acquire)
+    }
+
+    /// Takes the result if the request is done.
+    ///
+    /// The result can only be taken once, the following calls return None.
+    pub fn try_take(&mut self) -> Option<IoResult> {
+        match self.is_complete() {
+            true => critical_section!(|| self.state.result.lock().take()),
+            false => None,
+        }
+    }
+
+    /// Blocks the current thread until the request is done.
+    ///
+    /// The thread yields while waiting, therefore the interrupt of the device must be able to
+    /// arrive. Use [`BlockDevice::wait`] to also poll the device.
+    pub fn wait(mut self) -> IoResult {
+        loop {
+            if let Some(result) = self.try_take() {
+                return result
+            }
+            Thread::yield_now();
+        }
+    }
+}
+
+impl Future for IoFuture {
+    type Output = IoResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult> {
+        if let Some(result) = self.try_take() {
+            return Poll::Ready(result)
+        }
+
+        critical_section!(|| *self.state.waker.lock() = Some(cx.waker().clone()));
+        // The request might have been completed while the waker was registered.
+        match self.try_take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Driver side of the submitted request.
+///
+/// Dropping the completion without completing it aborts the request, so no submitter ever waits
+/// for a request that the driver has forgotten about.
+pub struct IoCompletion {
+    state: Arc<IoState>,
+}
+
+impl IoCompletion {
+    /// Finishes the request and wakes up the submitter.
+    ///
+    /// Can be called from the interrupt handler of the device.
+    pub fn complete(self, result: IoResult) {
+        self.finish(result)
+    }
+
+    fn finish(&self, result: IoResult) {
+        critical_section!(|| {
+            *self.state.result.lock() = Some(result);
+            self.state.done.store(true, Ordering::Release);
+
+            if let Some(waker) = self.state.waker.lock().take() {
+                waker.wake();
+            }
+        });
+    }
+}
+
+impl Drop for IoCompletion {
+    fn drop(&mut self) {
+        if !self.state.done.load(Ordering::Acquire) {
+            self.finish(Err(BlockError::Aborted));
+        }
+    }
+}
+
+/// A block device trait.
+///
+/// All storage drivers must implement this trait for global use throughout the OS.
+pub trait BlockDevice: Driver {
+    /// Returns the amount of sectors of the device.
+    fn sector_count(&self) -> u64;
+
+    /// Returns true if the device cannot be written.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Queues the request and returns its completion handle right away.
+    ///
+    /// Invalid requests return a future that is already completed with an error.
+    fn submit(&mut self, request: BlockRequest) -> IoFuture;
+
+    /// Processes finished requests of the device.
+    ///
+    /// Must be called from the interrupt handler of the device. Polling drivers may also do the
+    /// actual transfer here. Does nothing by default.
+    fn poll(&mut self) {}
+
+    /// Returns the legacy IRQ line used by the device, if it is interrupt driven.
+    fn irq(&self) -> Option<u8> {
+        None
+    }
+
+    /// Waits for the request while polling the device.
+    ///
+    /// Works even when the interrupts of the device are not delivered.
+    fn wait(&mut self, mut future: IoFuture) -> IoResult {
+        loop {
+            if let Some(result) = future.try_take() {
+                return result
+            }
+            self.poll();
+            Thread::yield_now();
+        }
+    }
+
+    /// Reads the sectors synchronously.
+    fn read_blocking(&mut self, lba: u64, count: u32) -> IoResult {
+        let future = self.submit(BlockRequest::read(lba, count));
+        self.wait(future)
+    }
+
+    /// Writes the buffer synchronously. Returns the buffer back.
+    fn write_blocking(&mut self, lba: u64, buffer: Vec<u8>) -> IoResult {
+        let future = self.submit(BlockRequest::write(lba, buffer));
+        self.wait(future)
+    }
+}
+
+impl_driver!(Box<dyn BlockDevice>, |s| s.as_ref().info());
+
+#[test_case]
+fn completion_reaches_future() {
+    let (mut future, completion) = io_pair();
+    assert!(future.try_take().is_none());
+
+    completion.complete(Ok(vec![1, 2, 3]));
+    assert_eq!(future.try_take(), Some(Ok(vec![1, 2, 3])));
+
+    // Forgotten requests are aborted.
+    let (future, completion) = io_pair();
+    drop(completion);
+    assert_eq!(future.wait(), Err(BlockError::Aborted));
+
+    assert_eq!(
+        BlockRequest::read(7, 2).check(8, false),
+        Err(BlockError::OutOfRange { lba: 7, count: 2 })
+    );
+}
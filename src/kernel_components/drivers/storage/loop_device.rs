@@ -0,0 +1,199 @@
+/// Loopback block device.
+///
+/// Presents a regular file as a block device, so a filesystem image stored within another
+/// filesystem can be mounted and tested like a real disk.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use super::block::{BlockDevice, BlockError, BlockOp, BlockRequest, IoFuture, SECTOR_SIZE};
+use crate::kernel_components::drivers::virtio::{NinePClient, NinePError};
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+
+/// A file that can back the loop device.
+pub trait BackingFile {
+    /// Returns the size of the file in bytes.
+    fn size(&mut self) -> Result<u64, BlockError>;
+
+    /// Fills the whole buffer with the data of the file at provided offset.
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Writes the data into the file at provided offset. Files are read-only by default.
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), BlockError> {
+        let _ = (offset, data);
+        Err(BlockError::ReadOnly)
+    }
+
+    /// Returns true if the file cannot be written.
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+/// Image kept in memory, for example a file unpacked from the initramfs.
+impl BackingFile for Vec<u8> {
+    fn size(&mut self) -> Result<u64, BlockError> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        let start = offset as usize;
+        buffer.copy_from_slice(self.get(start..start + buffer.len()).ok_or(BlockError::DeviceFailure)?);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), BlockError> {
+        let start = offset as usize;
+        self.get_mut(start..start + data.len()).ok_or(BlockError::DeviceFailure)?.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+/// A file on the host directory shared over 9P.
+///
+/// The 9P client only supports reading, therefore such loop devices are always read-only.
+pub struct NinePFile {
+    client: NinePClient,
+    fid: u32,
+}
+
+impl NinePFile {
+    /// Opens the file under the provided path within the shared directory.
+    pub fn open(mut client: NinePClient, path: &str) -> Result<Self, NinePError> {
+        let fid = client.walk(path)?;
+        if let Err(err) = client.open(fid, 0) {
+            let _ = client.clunk(fid);
+            return Err(err)
+        }
+        Ok(Self { client, fid })
+    }
+
+    /// Closes the file and returns the client back.
+    pub fn close(mut self) -> NinePClient {
+        let _ = self.client.clunk(self.fid);
+        self.client
+    }
+}
+
+impl BackingFile for NinePFile {
+    fn size(&mut self) -> Result<u64, BlockError> {
+        self.client.size(self.fid).map_err(|_| BlockError::DeviceFailure)
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        let mut done = 0;
+        while done < buffer.len() {
+            let chunk = self.client.read(self.fid, offset + done as u64, (buffer.len() - done) as u32)
+                .map_err(|_| BlockError::DeviceFailure)?;
+            if chunk.is_empty() {
+                // Unexpected end of the file.
+                return Err(BlockError::DeviceFailure)
+            }
+            buffer[done..done + chunk.len()].copy_from_slice(&chunk);
+            done += chunk.len();
+        }
+        Ok(())
+    }
+}
+
+/// Loop device.
+///
+/// Every request is translated into reads and writes of the backing file and completed right
+/// within the submit call. A trailing part of the file which does not fill the whole sector is
+/// not accessible.
+pub struct LoopDevice {
+    file: Box<dyn BackingFile>,
+    /// Offset of the first sector within the file.
+    offset: u64,
+    sectors: u64,
+    read_only: bool,
+}
+
+impl LoopDevice {
+    /// Attaches the whole file.
+    pub fn new(file: Box<dyn BackingFile>) -> Result<Self, BlockError> {
+        Self::with_offset(file, 0)
+    }
+
+    /// Attaches the file starting from the provided offset in bytes, i.e to skip a partition
+    /// table of the image.
+    pub fn with_offset(mut file: Box<dyn BackingFile>, offset: u64) -> Result<Self, BlockError> {
+        if offset % SECTOR_SIZE as u64 != 0 {
+            return Err(BlockError::Unaligned(offset as usize))
+        }
+        let size = file.size()?;
+        let read_only = file.is_read_only();
+
+        Ok(Self {
+            file,
+            offset,
+            sectors: size.saturating_sub(offset) / SECTOR_SIZE as u64,
+            read_only,
+        })
+    }
+
+    /// Marks the device as read-only even if the file is writable.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Detaches the backing file.
+    pub fn detach(self) -> Box<dyn BackingFile> {
+        self.file
+    }
+}
+
+impl BlockDevice for LoopDevice {
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn submit(&mut self, mut request: BlockRequest) -> IoFuture {
+        if let Err(err) = request.check(self.sectors, self.read_only) {
+            return IoFuture::ready(Err(err))
+        }
+
+        let offset = self.offset + request.lba * SECTOR_SIZE as u64;
+        let result = match request.op {
+            BlockOp::Read => {
+                request.buffer.resize(request.len(), 0);
+                self.file.read_at(offset, &mut request.buffer)
+            },
+            BlockOp::Write => self.file.write_at(offset, &request.buffer),
+            BlockOp::Flush => Ok(()),
+        };
+        IoFuture::ready(result.map(|_| request.buffer))
+    }
+}
+
+impl_driver!(LoopDevice, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(if s.read_only { DriverCaps::READ } else { DriverCaps::READ | DriverCaps::WRITE })
+    .bound_to(BoundDevice::Platform("loop"))
+);
+
+#[test_case]
+fn loop_device_over_memory_image() {
+    use alloc::vec;
+
+    let mut image = vec![0u8; 3 * SECTOR_SIZE + 100];
+    image[SECTOR_SIZE] = 0x55;
+
+    let mut device = LoopDevice::with_offset(Box::new(image), SECTOR_SIZE as u64).unwrap();
+    // The partial sector at the end is not visible.
+    assert_eq!(device.sector_count(), 2);
+    assert_eq!(device.read_blocking(0, 1).unwrap()[0], 0x55);
+
+    device.write_blocking(1, vec![0xaa; SECTOR_SIZE]).unwrap();
+    assert_eq!(device.read_blocking(1, 1).unwrap()[0], 0xaa);
+    assert!(device.read_only().write_blocking(0, vec![0; SECTOR_SIZE]).is_err());
+}
@@ -0,0 +1,77 @@
+/// Block device backed by the kernel heap.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::block::{BlockDevice, BlockOp, BlockRequest, IoFuture, SECTOR_SIZE};
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+
+/// RAM disk.
+///
+/// Every request is completed right within the submit call, therefore the returned futures are
+/// always ready. Useful for testing the code built on top of block devices.
+pub struct RamDisk {
+    data: Vec<u8>,
+    read_only: bool,
+}
+
+impl RamDisk {
+    /// Creates a zeroed disk with provided amount of sectors.
+    pub fn new(sectors: usize) -> Self {
+        Self { data: vec![0; sectors * SECTOR_SIZE], read_only: false }
+    }
+
+    /// Creates a disk from the provided image. The image is padded to the whole sector.
+    pub fn from_image(mut image: Vec<u8>, read_only: bool) -> Self {
+        image.resize(image.len().next_multiple_of(SECTOR_SIZE), 0);
+        Self { data: image, read_only }
+    }
+
+    /// Returns the whole content of the disk.
+    pub fn image(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn sector_count(&self) -> u64 {
+        (self.data.len() / SECTOR_SIZE) as u64
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn submit(&mut self, mut request: BlockRequest) -> IoFuture {
+        if let Err(err) = request.check(self.sector_count(), self.read_only) {
+            return IoFuture::ready(Err(err))
+        }
+
+        let range = request.lba as usize * SECTOR_SIZE..request.lba as usize * SECTOR_SIZE + request.len();
+        match request.op {
+            BlockOp::Read => {
+                request.buffer.resize(request.len(), 0);
+                request.buffer.copy_from_slice(&self.data[range]);
+            },
+            BlockOp::Write => self.data[range].copy_from_slice(&request.buffer),
+            BlockOp::Flush => (),
+        }
+        IoFuture::ready(Ok(request.buffer))
+    }
+}
+
+impl_driver!(RamDisk, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::READ | DriverCaps::WRITE)
+    .bound_to(BoundDevice::Platform("ram"))
+);
+
+#[test_case]
+fn ramdisk_round_trip() {
+    let mut disk = RamDisk::new(4);
+    let data = vec![0xab; 2 * SECTOR_SIZE];
+
+    assert_eq!(disk.write_blocking(1, data.clone()), Ok(data.clone()));
+    assert_eq!(disk.read_blocking(1, 2), Ok(data));
+    assert!(disk.read_blocking(3, 2).is_err());
+}
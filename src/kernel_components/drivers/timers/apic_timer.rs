@@ -0,0 +1,304 @@
+/// A clock driver and the scheduler tick source based on the local APIC timer.
+
+use core::arch::x86_64 as arch;
+use core::fmt::Display;
+use core::error::Error;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::{ClockDriver, RealTimeClock};
+use crate::kernel_components::arch_x86_64::controllers::{PIT, PITCommand};
+use crate::kernel_components::arch_x86_64::controllers::apic::{LvtEntry, Lvt, TimerMode, LOCAL_APIC};
+use crate::kernel_components::arch_x86_64::ports::{GenericPort, PortAccessType};
+use crate::kernel_components::registers::ms::{Msr, TscDeadline};
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+use crate::kernel_components::memory::vdso::{self, TimeSnapshot};
+use crate::critical_section;
+
+/// Default frequency of the scheduler ticks in Hz.
+pub const TICK_HZ: u32 = 100;
+
+/// Base frequency of the PIT oscillator in Hz.
+const PIT_FREQUENCY: u32 = 1_193_182;
+/// Length of the calibration in milliseconds.
+const CALIBRATION_MS: u32 = 10;
+/// Divider of the bus clock used by the timer.
+const TIMER_DIVIDE: u8 = 16;
+/// CPUID leaf 1 ECX bit, which reports the TSC-deadline mode of the timer.
+const CPUID_TSC_DEADLINE: u32 = 1 << 24;
+
+/// Amount of periodic interrupts handled so far.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Set while the timer is armed and raises it's vector.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Error type for the APIC timer driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicTimerError {
+    /// The local APIC is not initialized.
+    NoLocalApic,
+    /// The timer did not count during the calibration.
+    CalibrationFailed,
+    /// The requested frequency cannot be generated.
+    InvalidRate(u32),
+    /// The processor does not support the TSC-deadline mode.
+    NoTscDeadline,
+}
+
+impl Display for ApicTimerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoLocalApic => write!(f, "The local APIC must be initialized before it's timer is used."),
+            Self::CalibrationFailed => write!(f, "The APIC timer did not count during the calibration."),
+            Self::InvalidRate(hz) => write!(f, "Unable to generate {} Hz with the APIC timer.", hz),
+            Self::NoTscDeadline => write!(f, "The CPU does not support the TSC-deadline timer mode."),
+        }
+    }
+}
+
+impl Error for ApicTimerError {}
+
+/// A clock driver implementation that uses the local APIC timer.
+///
+/// The timer counts down with the bus clock, which frequency is unknown, therefore it is
+/// calibrated against PIT channel 2 on creation. The TSC is calibrated along the way and used to
+/// tell the time, while the wall clock is read once from the RTC, like the kvmclock driver does.
+///
+/// # Tick Source
+///
+/// After [´ApicTimer::start_periodic´] the timer raises the provided vector periodically. When
+/// the vector is the one of the timer interrupt handler, the handler preempts the running thread
+/// on every tick, so IRQ0 of the PIT must be masked. The timer can also be armed once with
+/// [´ApicTimer::one_shot´] or [´ApicTimer::deadline´].
+pub struct ApicTimer {
+    rtc: RealTimeClock,
+    vector: u8,
+    /// Timer counts per millisecond with the used divider.
+    counts_per_ms: u32,
+    /// TSC cycles per millisecond.
+    tsc_per_ms: u64,
+    /// Time of the day in milliseconds at the moment of calibration.
+    base_ms: u32,
+    /// TSC value at the moment of calibration.
+    base_tsc: u64,
+    /// Frequency of the periodic mode, restarted on resume.
+    hz: Option<u32>,
+}
+
+impl ApicTimer {
+    /// Milliseconds in one day.
+    const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+    /// Creates a new driver, which raises the provided vector, and calibrates the timer.
+    ///
+    /// Takes about 10 ms. The timer is stopped afterwards.
+    pub fn new(vector: u8) -> Result<Self, ApicTimerError> {
+        if !LOCAL_APIC.is_enabled() {
+            return Err(ApicTimerError::NoLocalApic)
+        }
+
+        let (counts, cycles) = critical_section!(|| unsafe { Self::calibrate() });
+        if counts == 0 {
+            return Err(ApicTimerError::CalibrationFailed)
+        }
+
+        let mut rtc = RealTimeClock::new();
+        let mut timer = Self {
+            base_ms: rtc.now(),
+            base_tsc: unsafe { arch::_rdtsc() },
+            rtc,
+            vector,
+            counts_per_ms: counts / CALIBRATION_MS,
+            tsc_per_ms: cycles / CALIBRATION_MS as u64,
+            hz: None,
+        };
+        timer.publish();
+        Ok(timer)
+    }
+
+    /// Shares the TSC calibration and the wall clock snapshot with the processes.
+    fn publish(&mut self) {
+        vdso::publish(TimeSnapshot {
+            tsc_per_ms: self.tsc_per_ms,
+            base_tsc: self.base_tsc,
+            wall_ms: self.base_ms,
+            year: self.rtc.year(),
+            month: self.rtc.month(),
+            day: self.rtc.day(),
+        });
+    }
+
+    /// Returns the amount of timer counts and TSC cycles within the calibration period, measured
+    /// with PIT channel 2 in the one-shot mode.
+    unsafe fn calibrate() -> (u32, u64) {
+        let mut pit = PIT::new();
+        let port_b = GenericPort::<u8>::new(0x61, PortAccessType::READWRITE);
+
+        // Gate of channel 2 on, speaker off.
+        port_b.write(port_b.read() & !0b10 | 0b01);
+        pit.command(PITCommand::CHANNEL2 | PITCommand::FULL_WORD | PITCommand::INT_ON_TERMINAL_COUNT);
+        pit.channel2.write((PIT_FREQUENCY / 1000 * CALIBRATION_MS) as u16);
+
+        LOCAL_APIC.set_timer_divide(TIMER_DIVIDE);
+        LOCAL_APIC.write_lvt(Lvt::Timer, LvtEntry::MASKED.timer_mode(TimerMode::OneShot));
+        LOCAL_APIC.set_timer_initial_count(u32::MAX);
+        let start = arch::_rdtsc();
+
+        // Output of the channel goes high at the terminal count.
+        while port_b.read() & 0x20 == 0 {
+            core::hint::spin_loop();
+        }
+
+        let counts = u32::MAX - LOCAL_APIC.timer_current_count();
+        let cycles = arch::_rdtsc().wrapping_sub(start);
+        LOCAL_APIC.set_timer_initial_count(0);
+        (counts, cycles)
+    }
+
+    /// Starts raising the vector with the provided frequency in Hz.
+    pub fn start_periodic(&mut self, hz: u32) -> Result<(), ApicTimerError> {
+        let count = match hz {
+            0 => None,
+            hz => (self.counts_per_ms as u64 * 1000 / hz as u64).try_into().ok().filter(|&count: &u32| count != 0),
+        }.ok_or(ApicTimerError::InvalidRate(hz))?;
+
+        LOCAL_APIC.set_timer_divide(TIMER_DIVIDE);
+        LOCAL_APIC.write_lvt(Lvt::Timer, LvtEntry::new(self.vector).timer_mode(TimerMode::Periodic));
+        LOCAL_APIC.set_timer_initial_count(count);
+        RUNNING.store(true, Ordering::Release);
+        self.hz = Some(hz);
+        Ok(())
+    }
+
+    /// Raises the vector once after the provided amount of microseconds.
+    pub fn one_shot(&mut self, us: u32) {
+        let count = (self.counts_per_ms as u64 * us as u64 / 1000).clamp(1, u32::MAX as u64) as u32;
+
+        LOCAL_APIC.set_timer_divide(TIMER_DIVIDE);
+        LOCAL_APIC.write_lvt(Lvt::Timer, LvtEntry::new(self.vector).timer_mode(TimerMode::OneShot));
+        LOCAL_APIC.set_timer_initial_count(count);
+        RUNNING.store(true, Ordering::Release);
+    }
+
+    /// Raises the vector once the TSC reaches the deadline.
+    pub fn deadline(&mut self, tsc: u64) -> Result<(), ApicTimerError> {
+        if !Self::has_tsc_deadline() {
+            return Err(ApicTimerError::NoTscDeadline)
+        }
+
+        LOCAL_APIC.write_lvt(Lvt::Timer, LvtEntry::new(self.vector).timer_mode(TimerMode::TscDeadline));
+        // The write of the LVT entry must be ordered before the MSR write.
+        unsafe {
+            core::arch::asm!("mfence", options(nostack));
+            TscDeadline::write_raw(tsc);
+        }
+        RUNNING.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Stops the timer.
+    pub fn stop(&mut self) {
+        self.hz = None;
+        RUNNING.store(false, Ordering::Release);
+        LOCAL_APIC.write_lvt(Lvt::Timer, LvtEntry::MASKED);
+        LOCAL_APIC.set_timer_initial_count(0);
+    }
+
+    /// Returns the amount of timer counts per millisecond.
+    pub fn counts_per_ms(&self) -> u32 {
+        self.counts_per_ms
+    }
+
+    /// Returns the amount of TSC cycles per millisecond.
+    pub fn tsc_per_ms(&self) -> u64 {
+        self.tsc_per_ms
+    }
+
+    /// Checks if the processor supports the TSC-deadline mode.
+    pub fn has_tsc_deadline() -> bool {
+        unsafe { arch::__cpuid(0x1) }.ecx & CPUID_TSC_DEADLINE != 0
+    }
+
+    /// Returns true while the timer is armed, until it is stopped.
+    ///
+    /// The timer interrupt handler must acknowledge the interrupt to the local APIC in such case,
+    /// regardless of the controller handling the IRQ lines.
+    pub fn is_running() -> bool {
+        RUNNING.load(Ordering::Acquire)
+    }
+
+    /// Returns the amount of timer interrupts handled so far.
+    pub fn periodic_ticks() -> u64 {
+        TICKS.load(Ordering::Relaxed)
+    }
+
+    /// Must be called from the handler of the timer vector.
+    pub fn handle_interrupt() {
+        TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl ClockDriver for ApicTimer {
+    fn now(&mut self) -> u32 {
+        let passed = unsafe { arch::_rdtsc() }.wrapping_sub(self.base_tsc) / self.tsc_per_ms.max(1);
+        ((self.base_ms as u64 + passed) % Self::DAY_MS) as u32
+    }
+
+    fn year(&mut self) -> u16 {
+        self.rtc.year()
+    }
+
+    fn month(&mut self) -> u8 {
+        self.rtc.month()
+    }
+
+    fn day(&mut self) -> u8 {
+        self.rtc.day()
+    }
+
+    fn hours(&mut self) -> u8 {
+        (self.now() / (60 * 60 * 1000)) as u8
+    }
+
+    fn minutes(&mut self) -> u8 {
+        (self.now() / (60 * 1000) % 60) as u8
+    }
+
+    fn seconds(&mut self) -> u8 {
+        (self.now() / 1000 % 60) as u8
+    }
+
+    fn millis(&mut self) -> u8 {
+        // Milliseconds do not fit into u8, therefore hundredths of a second are provided.
+        (self.now() % 1000 / 10) as u8
+    }
+
+    fn ticks(&mut self) -> Option<u64> {
+        Self::is_running().then(Self::periodic_ticks)
+    }
+
+    fn suspend(&mut self) {
+        // Only the rate is kept, the local APIC loses it's state in the sleep state.
+        let hz = self.hz;
+        self.stop();
+        self.hz = hz;
+    }
+
+    fn resume(&mut self) {
+        // The TSC does not count in the sleep state.
+        self.base_ms = self.rtc.now();
+        self.base_tsc = unsafe { arch::_rdtsc() };
+        self.publish();
+        if let Some(hz) = self.hz {
+            let _ = self.start_periodic(hz);
+        }
+    }
+}
+
+impl_driver!(ApicTimer, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::READ | DriverCaps::IRQ)
+    .bound_to(BoundDevice::Platform("Local APIC timer")),
+    |s| s.stop(),
+    |s| ClockDriver::suspend(s),
+    |s| ClockDriver::resume(s)
+);
@@ -2,12 +2,12 @@ use alloc::boxed::Box;
 
 /// A module that defines a global interface to OS clock.
 
-use crate::kernel_components::drivers::Driver;
+use crate::kernel_components::drivers::{Driver, DriverInfo};
 
 /// A clock driver trait.
 ///
 /// All clock drivers must implement this trait for global in throughout the OS.
-pub trait ClockDriver {
+pub trait ClockDriver: Driver {
     /// Returns current date and time at that specific point.
     ///
     /// This function must be implemented manually by the driver. The value returned should be
@@ -52,6 +52,22 @@ pub trait ClockDriver {
     fn millis(&mut self) -> u8 {
         self.now() as u8
     }
+
+    /// Returns the amount of ticks generated so far, if the clock is used as a periodic tick
+    /// source. None by default.
+    fn ticks(&mut self) -> Option<u64> {
+        None
+    }
+
+    /// Stops the periodic ticks before the machine enters a sleep state. Does nothing by default.
+    fn suspend(&mut self) {}
+
+    /// Restarts the ticks and reads the wall clock again after the machine woke up, as the time
+    /// spent asleep is not counted. Does nothing by default.
+    fn resume(&mut self) {}
 }
 
-impl_driver!(Box<dyn ClockDriver>);
+impl_driver!(Box<dyn ClockDriver>, |s| s.as_ref().info(), |_| {},
+    |s| ClockDriver::suspend(s.as_mut()),
+    |s| ClockDriver::resume(s.as_mut())
+);
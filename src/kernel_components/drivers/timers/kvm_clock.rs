@@ -0,0 +1,164 @@
+/// A clock driver based on the KVM paravirtual clock.
+
+use alloc::boxed::Box;
+use core::arch::x86_64 as arch;
+use core::ptr;
+
+use super::{ClockDriver, RealTimeClock};
+use crate::kernel_components::arch_x86_64::hypervisor::KvmFeatures;
+use crate::kernel_components::memory::MEMORY_MANAGEMENT_UNIT;
+use crate::kernel_components::registers::ms::{Msr, KvmSystemTime};
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+
+/// Time information structure shared with the host.
+///
+/// The host updates it whenever the TSC to nanoseconds relation changes. The version field is
+/// odd while the update is in progress.
+#[derive(Debug, Default)]
+#[repr(C, align(32))]
+struct PvClockTimeInfo {
+    version: u32,
+    _pad0: u32,
+    tsc_timestamp: u64,
+    system_time: u64,
+    tsc_to_system_mul: u32,
+    tsc_shift: i8,
+    flags: u8,
+    _pad1: [u8; 2],
+}
+
+/// A clock driver implementation that uses kvmclock as a main clock source.
+///
+/// The kvmclock provides the nanoseconds passed since the boot, which is calculated from the TSC
+/// without any traps to the hypervisor. The wall clock is read once from the RTC and the time of
+/// the day is calculated from the kvmclock afterwards.
+pub struct KvmClock {
+    info: Box<PvClockTimeInfo>,
+    rtc: RealTimeClock,
+    /// Time of the day in milliseconds at the moment of initialization.
+    base_ms: u32,
+    /// Kvmclock value in nanoseconds at the moment of initialization.
+    base_ns: u64,
+}
+
+impl KvmClock {
+    /// Milliseconds in one day.
+    const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+    /// Creates a new instance of kvmclock driver.
+    ///
+    /// Returns None if not running under KVM or the kvmclock is not supported.
+    pub fn new() -> Option<Self> {
+        if !KvmFeatures::read()?.has(KvmFeatures::CLOCKSOURCE2) {
+            return None
+        }
+
+        let info = Box::new(PvClockTimeInfo::default());
+        let phys = unsafe { MEMORY_MANAGEMENT_UNIT.translate(&*info as *const _ as usize) }?;
+
+        unsafe { KvmSystemTime::write_raw(phys as u64 | 1) };
+
+        let mut rtc = RealTimeClock::new();
+        let base_ms = rtc.now();
+        let mut clock = Self { info, rtc, base_ms, base_ns: 0 };
+        clock.base_ns = clock.nanos();
+
+        Some(clock)
+    }
+
+    /// Returns the amount of nanoseconds passed since the boot.
+    pub fn nanos(&self) -> u64 {
+        let info = &*self.info as *const PvClockTimeInfo;
+
+        loop {
+            unsafe {
+                let version = ptr::read_volatile(&(*info).version);
+                if version & 1 == 1 {
+                    continue
+                }
+
+                let tsc_timestamp = ptr::read_volatile(&(*info).tsc_timestamp);
+                let system_time = ptr::read_volatile(&(*info).system_time);
+                let mul = ptr::read_volatile(&(*info).tsc_to_system_mul);
+                let shift = ptr::read_volatile(&(*info).tsc_shift);
+
+                let mut delta = arch::_rdtsc().wrapping_sub(tsc_timestamp);
+                if shift < 0 {
+                    delta >>= -shift as u32;
+                } else {
+                    delta <<= shift as u32;
+                }
+                let ns = system_time + ((delta as u128 * mul as u128) >> 32) as u64;
+
+                if ptr::read_volatile(&(*info).version) == version {
+                    return ns
+                }
+            }
+        }
+    }
+}
+
+impl ClockDriver for KvmClock {
+    fn now(&mut self) -> u32 {
+        let passed = (self.nanos() - self.base_ns) / 1_000_000;
+        ((self.base_ms as u64 + passed) % Self::DAY_MS) as u32
+    }
+
+    fn dt(&mut self, t: u32) -> Option<u32> {
+        self.now().checked_sub(t)
+    }
+
+    fn year(&mut self) -> u16 {
+        self.rtc.year()
+    }
+
+    fn month(&mut self) -> u8 {
+        self.rtc.month()
+    }
+
+    fn day(&mut self) -> u8 {
+        self.rtc.day()
+    }
+
+    fn hours(&mut self) -> u8 {
+        (self.now() / (60 * 60 * 1000)) as u8
+    }
+
+    fn minutes(&mut self) -> u8 {
+        (self.now() / (60 * 1000) % 60) as u8
+    }
+
+    fn seconds(&mut self) -> u8 {
+        (self.now() / 1000 % 60) as u8
+    }
+
+    fn millis(&mut self) -> u8 {
+        // Milliseconds do not fit into u8, therefore hundredths of a second are provided.
+        (self.now() % 1000 / 10) as u8
+    }
+
+    fn resume(&mut self) {
+        // The registration of the structure does not survive the sleep state.
+        if let Some(phys) = unsafe { MEMORY_MANAGEMENT_UNIT.translate(&*self.info as *const _ as usize) } {
+            unsafe { KvmSystemTime::write_raw(phys as u64 | 1) };
+        }
+        self.base_ms = self.rtc.now();
+        self.base_ns = self.nanos();
+    }
+}
+
+impl Drop for KvmClock {
+    fn drop(&mut self) {
+        // The host must stop writing into the structure before it is freed.
+        unsafe { KvmSystemTime::write_raw(0) };
+    }
+}
+
+impl_driver!(KvmClock, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::READ)
+    .bound_to(BoundDevice::Platform("kvmclock")),
+    |_| {},
+    |s| ClockDriver::suspend(s),
+    |s| ClockDriver::resume(s)
+);
@@ -1,13 +1,79 @@
 /// A clock driver based on the RTC chip.
 
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, AtomicU64, AtomicUsize, Ordering};
+
 use super::ClockDriver;
 use crate::kernel_components::arch_x86_64::controllers::{RTC, CMOSAddr};
-use crate::kernel_components::drivers::Driver;
+use crate::kernel_components::arch_x86_64::controllers::rtc::{bcd2bin, RTCStatusA, RTCStatusB, RTCStatusC};
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+use crate::kernel_components::drivers::interrupts::{with_controller, IntCtrlError};
+use crate::kernel_components::sync::Mutex;
+use crate::critical_section;
+
+/// IRQ line of the RTC chip.
+pub const RTC_IRQ: u8 = 8;
+
+/// A callback invoked from the IRQ8 handler.
+///
+/// Receives the amount of periodic ticks counted so far. Callbacks run with interrupts disabled
+/// and must be short. Registering new callbacks from within a callback will deadlock.
+pub type RtcCallback = fn(u64);
+
+/// Callbacks invoked on each periodic interrupt, with their ids.
+static PERIODIC_CALLBACKS: Mutex<Vec<(usize, RtcCallback)>> = Mutex::new(Vec::new());
+/// Callbacks invoked once per second, after the clock update has ended, with their ids.
+static UPDATE_CALLBACKS: Mutex<Vec<(usize, RtcCallback)>> = Mutex::new(Vec::new());
+/// Id of the next registered callback.
+static NEXT_CALLBACK_ID: AtomicUsize = AtomicUsize::new(0);
+/// Amount of periodic interrupts handled so far.
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+/// Current periodic interrupt rate in Hz. Zero when periodic interrupts are disabled.
+static PERIODIC_RATE: AtomicU16 = AtomicU16::new(0);
+
+/// Error type for the RTC driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RtcError {
+    /// The periodic rate must be a power of two in range 2..=8192 Hz.
+    InvalidRate(u16),
+    /// Unable to unmask the IRQ8 line.
+    Irq(IntCtrlError),
+}
+
+/// Interrupt cause on which a callback is invoked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcEvent {
+    /// Each periodic interrupt.
+    Periodic,
+    /// Once per second, after the clock update has ended.
+    Update,
+}
+
+/// Handle of a registered callback, used to cancel it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtcTimer {
+    event: RtcEvent,
+    id: usize,
+}
+
+impl RtcTimer {
+    /// Returns the interrupt cause on which the callback is invoked.
+    pub fn event(&self) -> RtcEvent {
+        self.event
+    }
+}
 
 /// A clock driver implementation that uses RTC as a main clock source.
 ///
 /// All values are read from the chip itself via simple read commands. The clock is unable to
 /// provide milliseconds via regular reads, therefore they are always set to 0.
+///
+/// # Tick Source
+///
+/// The chip can also be used as a periodic tick source. After [´RealTimeClock::start_periodic´]
+/// the IRQ8 handler drains the status register C and calls every callback registered with
+/// [´RealTimeClock::on_periodic´] or [´RealTimeClock::on_update´]. The chip is always programmed
+/// with NMIs kept enabled.
 pub struct RealTimeClock {
     rtc: RTC
 }
@@ -16,9 +82,162 @@ impl RealTimeClock {
     /// Creates a new instance of real time clock driver.
     pub fn new () -> Self {
         Self {
-            rtc: RTC::new(),
+            rtc: RTC::with_nmi(true),
+        }
+    }
+
+    /// Configures periodic interrupts with the requested rate in Hz and unmasks the IRQ8 line.
+    ///
+    /// The rate must be a power of two in range 2..=8192. The IRQ8 handler must be installed
+    /// before calling this function.
+    pub fn start_periodic(&mut self, hz: u16) -> Result<(), RtcError> {
+        let rate = Self::rate_bits(hz).ok_or(RtcError::InvalidRate(hz))?;
+
+        critical_section!(|| unsafe {
+            let prescaler = self.rtc.status_a().bits() & 0x70;
+            self.rtc.write(CMOSAddr::RTC_STATUS_A, prescaler | rate);
+            self.rtc.write_preserved(
+                CMOSAddr::RTC_STATUS_B, RTCStatusB::PERIODIC_INTERRUPT.bits(), !RTCStatusB::PERIODIC_INTERRUPT.bits()
+            );
+            PERIODIC_RATE.store(hz, Ordering::Release);
+            // Stale flags would prevent new interrupts from being raised.
+            self.rtc.status_c();
+        });
+
+        Self::unmask()
+    }
+
+    /// Disables periodic interrupts.
+    pub fn stop_periodic(&mut self) {
+        critical_section!(|| unsafe {
+            self.rtc.write_preserved(CMOSAddr::RTC_STATUS_B, 0, !RTCStatusB::PERIODIC_INTERRUPT.bits());
+            PERIODIC_RATE.store(0, Ordering::Release);
+        });
+    }
+
+    /// Enables or disables the update-ended interrupt, which is raised once per second.
+    pub fn set_update_interrupt(&mut self, enable: bool) -> Result<(), RtcError> {
+        let bit = RTCStatusB::UPDATE_ENDED_INTERRUPT.bits();
+        critical_section!(|| unsafe {
+            self.rtc.write_preserved(CMOSAddr::RTC_STATUS_B, if enable { bit } else { 0 }, !bit);
+            self.rtc.status_c();
+        });
+
+        if enable { Self::unmask() } else { Ok(()) }
+    }
+
+    /// Arms the alarm to go off after the provided amount of seconds, within the same day.
+    ///
+    /// The alarm raises IRQ8 if the line is unmasked, and wakes the machine from a sleep state
+    /// if the RTC wake event is enabled. It stays armed until [´RealTimeClock::clear_alarm´].
+    pub fn set_alarm(&mut self, seconds: u32) {
+        critical_section!(|| unsafe {
+            let binary = RTCStatusB::DATA_MODE.is_in(self.rtc.status_b().bits());
+            let decode = |value: u8| (if binary { value } else { bcd2bin(value) }) as u32;
+            let encode = |value: u32| if binary { value as u8 } else { (value / 10 << 4 | value % 10) as u8 };
+
+            let now = decode(self.rtc.read(CMOSAddr::RTC_HOURS)) * 60 * 60
+                + decode(self.rtc.read(CMOSAddr::RTC_MINUTES)) * 60
+                + decode(self.rtc.read(CMOSAddr::RTC_SECONDS));
+            let alarm = (now + seconds) % (24 * 60 * 60);
+
+            self.rtc.write(CMOSAddr::RTC_HOUR_ALARM, encode(alarm / (60 * 60)));
+            self.rtc.write(CMOSAddr::RTC_MINUTE_ALARM, encode(alarm / 60 % 60));
+            self.rtc.write(CMOSAddr::RTC_SECOND_ALARM, encode(alarm % 60));
+            let bit = RTCStatusB::ALARM_INTERRUPT.bits();
+            self.rtc.write_preserved(CMOSAddr::RTC_STATUS_B, bit, !bit);
+            self.rtc.status_c();
+        });
+    }
+
+    /// Disarms the alarm.
+    pub fn clear_alarm(&mut self) {
+        let bit = RTCStatusB::ALARM_INTERRUPT.bits();
+        critical_section!(|| unsafe {
+            self.rtc.write_preserved(CMOSAddr::RTC_STATUS_B, 0, !bit);
+            self.rtc.status_c();
+        });
+    }
+
+    /// Registers a callback invoked on each periodic interrupt.
+    pub fn on_periodic(callback: RtcCallback) -> RtcTimer {
+        Self::register(RtcEvent::Periodic, callback)
+    }
+
+    /// Registers a callback invoked once per second after the clock update.
+    pub fn on_update(callback: RtcCallback) -> RtcTimer {
+        Self::register(RtcEvent::Update, callback)
+    }
+
+    /// Registers a callback invoked on the interrupt cause.
+    pub fn register(event: RtcEvent, callback: RtcCallback) -> RtcTimer {
+        let id = NEXT_CALLBACK_ID.fetch_add(1, Ordering::Relaxed);
+        critical_section!(|| Self::callbacks(event).lock().push((id, callback)));
+        RtcTimer { event, id }
+    }
+
+    /// Cancels the callback, so it's never invoked again. Returns false if it was canceled before.
+    pub fn cancel(timer: RtcTimer) -> bool {
+        critical_section!(|| {
+            let mut callbacks = Self::callbacks(timer.event).lock();
+            let index = callbacks.iter().position(|(id, _)| *id == timer.id);
+            index.map(|index| callbacks.remove(index)).is_some()
+        })
+    }
+
+    fn callbacks(event: RtcEvent) -> &'static Mutex<Vec<(usize, RtcCallback)>> {
+        match event {
+            RtcEvent::Periodic => &PERIODIC_CALLBACKS,
+            RtcEvent::Update => &UPDATE_CALLBACKS,
+        }
+    }
+
+    /// Returns the current periodic rate in Hz, or None if periodic interrupts are disabled.
+    pub fn periodic_rate() -> Option<u16> {
+        match PERIODIC_RATE.load(Ordering::Acquire) {
+            0 => None,
+            hz => Some(hz),
         }
     }
+
+    /// Returns the amount of periodic interrupts handled so far.
+    pub fn periodic_ticks() -> u64 {
+        PERIODIC_TICKS.load(Ordering::Relaxed)
+    }
+
+    /// Must be called from the IRQ8 handler.
+    ///
+    /// Drains the status register C, so the chip can raise the next interrupt, and dispatches
+    /// registered callbacks based on the interrupt cause.
+    pub fn handle_interrupt() {
+        let status = RTC::with_nmi(true).status_c();
+
+        if RTCStatusC::PERIODIC_INTERRUPT.is_in(status.bits()) {
+            let ticks = PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+            PERIODIC_CALLBACKS.lock().iter().for_each(|(_, callback)| callback(ticks));
+        }
+
+        if RTCStatusC::UPDATE_ENDED_INTERRUPT.is_in(status.bits()) {
+            let ticks = Self::periodic_ticks();
+            UPDATE_CALLBACKS.lock().iter().for_each(|(_, callback)| callback(ticks));
+        }
+    }
+
+    /// Converts the frequency to the rate selection bits of status register A.
+    ///
+    /// The resulting frequency is 32768 >> (rate - 1).
+    fn rate_bits(hz: u16) -> Option<u8> {
+        if !hz.is_power_of_two() || !(2..=8192).contains(&hz) {
+            return None;
+        }
+        Some(16 - hz.trailing_zeros() as u8)
+    }
+
+    fn unmask() -> Result<(), RtcError> {
+        with_controller(|ctrl| ctrl.unmask(RTC_IRQ))
+            .and_then(|res| res)
+            .map_err(RtcError::Irq)
+    }
 }
 
 impl ClockDriver for RealTimeClock {
@@ -56,6 +275,23 @@ impl ClockDriver for RealTimeClock {
     fn millis(&mut self) -> u8 {
         0
     }
+
+    fn ticks(&mut self) -> Option<u64> {
+        Self::periodic_rate().map(|_| Self::periodic_ticks())
+    }
 }
 
-impl_driver!(RealTimeClock);
+impl_driver!(RealTimeClock, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::READ | DriverCaps::IRQ)
+    .bound_to(BoundDevice::Platform("CMOS RTC"))
+);
+
+#[test_case]
+fn periodic_rate_bits() {
+    assert_eq!(RealTimeClock::rate_bits(1024), Some(RTCStatusA::INTSEC1024.bits()));
+    assert_eq!(RealTimeClock::rate_bits(2), Some(RTCStatusA::INTSEC2.bits()));
+    assert_eq!(RealTimeClock::rate_bits(8192), Some(RTCStatusA::INTSEC8192.bits()));
+    assert_eq!(RealTimeClock::rate_bits(1000), None);
+    assert_eq!(RealTimeClock::rate_bits(1), None);
+}
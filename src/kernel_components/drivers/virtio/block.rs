@@ -0,0 +1,312 @@
+/// Virtio block device driver.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::transport::{DeviceStatus, DmaRegion, LegacyTransport, VirtioError};
+use super::virtqueue::{Buffer, Virtqueue};
+use crate::kernel_components::drivers::storage::{
+    BlockDevice, BlockError, BlockOp, BlockRequest, IoCompletion, IoFuture, io_pair, SECTOR_SIZE,
+};
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice, DriverType, DRIVER_MANAGER};
+use crate::kernel_components::arch_x86_64::interrupts::IrqReturn;
+use crate::critical_section;
+
+/// Virtio device type of the block device.
+const BLOCK_DEVICE_TYPE: u16 = 2;
+/// The only request queue of the device.
+const REQUEST_QUEUE: u16 = 0;
+/// The device is read-only.
+const VIRTIO_BLK_F_RO: u32 = 1 << 5;
+/// The device supports cache flushes.
+const VIRTIO_BLK_F_FLUSH: u32 = 1 << 9;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_S_OK: u8 = 0;
+
+/// Maximal amount of requests in flight.
+const SLOTS: usize = 8;
+/// Maximal size of a single request in bytes.
+const SLOT_DATA_SIZE: usize = 4096;
+/// Size of the request header.
+const HEADER_SIZE: usize = 16;
+
+/// Header placed in front of every request.
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// A request that was handed to the device.
+struct InFlight {
+    head: u16,
+    request: BlockRequest,
+    completion: IoCompletion,
+}
+
+/// Virtio block driver.
+///
+/// Requests are placed into a software queue first and handed to the device as long as it has free
+/// slots. Every slot owns a small part of the DMA regions for the header, data and status of the
+/// request. Finished requests are completed from the interrupt handler, which also moves the next
+/// queued requests to the freed slots.
+pub struct VirtioBlock {
+    transport: LegacyTransport,
+    queue: Virtqueue,
+    /// Headers and statuses of all slots.
+    control: DmaRegion,
+    /// Data buffers of all slots.
+    data: DmaRegion,
+    capacity: u64,
+    features: u32,
+    pending: VecDeque<(BlockRequest, IoCompletion)>,
+    in_flight: [Option<InFlight>; SLOTS],
+}
+
+impl VirtioBlock {
+    /// Finds the virtio block device and initializes it.
+    pub fn new() -> Result<Self, VirtioError> {
+        let transport = LegacyTransport::probe(BLOCK_DEVICE_TYPE)?;
+        let features = transport.negotiate(VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH);
+
+        let init = || -> Result<Self, VirtioError> {
+            // The capacity is a 64-bit amount of 512 byte sectors at the start of the config space.
+            let capacity = (0..4).fold(0u64, |acc, i| {
+                acc | (transport.config_read_u16(i * 2) as u64) << (i * 16)
+            });
+
+            Ok(Self {
+                queue: Virtqueue::new(&transport, REQUEST_QUEUE)?,
+                control: DmaRegion::new(SLOTS * (HEADER_SIZE + 1))?,
+                data: DmaRegion::new(SLOTS * SLOT_DATA_SIZE)?,
+                capacity,
+                features,
+                pending: VecDeque::new(),
+                in_flight: Default::default(),
+                transport,
+            })
+        };
+
+        match init() {
+            Ok(block) => {
+                transport.add_status(DeviceStatus::DriverOk);
+                Ok(block)
+            },
+            Err(err) => {
+                transport.add_status(DeviceStatus::Failed);
+                Err(err)
+            }
+        }
+    }
+
+    /// Amount of requests handed to the device and not completed yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Amount of requests waiting for a free slot.
+    pub fn queued(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn header_offset(slot: usize) -> usize {
+        slot * HEADER_SIZE
+    }
+
+    fn status_offset(slot: usize) -> usize {
+        SLOTS * HEADER_SIZE + slot
+    }
+
+    /// Moves queued requests to free slots and notifies the device.
+    fn dispatch(&mut self) {
+        let mut notify = false;
+
+        while let Some(slot) = self.in_flight.iter().position(Option::is_none) {
+            // Every request needs up to three descriptors.
+            if self.queue.num_free() < 3 {
+                break
+            }
+            let Some((request, completion)) = self.pending.pop_front() else { break };
+
+            match self.start(slot, &request) {
+                Ok(head) => {
+                    self.in_flight[slot] = Some(InFlight { head, request, completion });
+                    notify = true;
+                },
+                Err(_) => completion.complete(Err(BlockError::DeviceFailure)),
+            }
+        }
+
+        if notify {
+            self.transport.notify(REQUEST_QUEUE);
+        }
+    }
+
+    /// Fills the slot with the request and places it into the queue.
+    fn start(&mut self, slot: usize, request: &BlockRequest) -> Result<u16, VirtioError> {
+        let kind = match request.op {
+            BlockOp::Read => VIRTIO_BLK_T_IN,
+            BlockOp::Write => VIRTIO_BLK_T_OUT,
+            BlockOp::Flush => VIRTIO_BLK_T_FLUSH,
+        };
+        let data = slot * SLOT_DATA_SIZE;
+
+        unsafe {
+            (self.control.virt.add(Self::header_offset(slot)) as *mut RequestHeader)
+                .write_volatile(RequestHeader { kind, reserved: 0, sector: request.lba });
+            self.control.virt.add(Self::status_offset(slot)).write_volatile(0xff);
+
+            if request.op == BlockOp::Write {
+                core::ptr::copy_nonoverlapping(request.buffer.as_ptr(), self.data.virt.add(data), request.len());
+            }
+        }
+
+        let header = Buffer {
+            phys: self.control.phys + Self::header_offset(slot),
+            len: HEADER_SIZE as u32,
+            writable: false,
+        };
+        let status = Buffer {
+            phys: self.control.phys + Self::status_offset(slot),
+            len: 1,
+            writable: true,
+        };
+
+        match request.op {
+            BlockOp::Flush => self.queue.push(&[header, status]),
+            op => self.queue.push(&[
+                header,
+                Buffer {
+                    phys: self.data.phys + data,
+                    len: request.len() as u32,
+                    writable: op == BlockOp::Read,
+                },
+                status,
+            ]),
+        }
+    }
+
+    /// Completes all requests finished by the device.
+    fn reap(&mut self) {
+        while let Some((head, _)) = self.queue.pop_used() {
+            let Some(slot) = self.in_flight.iter()
+                .position(|f| f.as_ref().is_some_and(|f| f.head == head)) else { continue };
+            let InFlight { mut request, completion, .. } = self.in_flight[slot].take().unwrap();
+
+            let status = unsafe { self.control.virt.add(Self::status_offset(slot)).read_volatile() };
+            if status != VIRTIO_BLK_S_OK {
+                completion.complete(Err(BlockError::DeviceFailure));
+                continue
+            }
+
+            if request.op == BlockOp::Read {
+                request.buffer.resize(request.len(), 0);
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        self.data.virt.add(slot * SLOT_DATA_SIZE), request.buffer.as_mut_ptr(), request.len()
+                    );
+                }
+            }
+            completion.complete(Ok(request.buffer));
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlock {
+    fn sector_count(&self) -> u64 {
+        self.capacity
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.features & VIRTIO_BLK_F_RO != 0
+    }
+
+    fn submit(&mut self, request: BlockRequest) -> IoFuture {
+        if let Err(err) = request.check(self.capacity, self.is_read_only()) {
+            return IoFuture::ready(Err(err))
+        }
+        if request.len() > SLOT_DATA_SIZE {
+            return IoFuture::ready(Err(BlockError::TooLarge(request.count)))
+        }
+        if request.op == BlockOp::Flush && self.features & VIRTIO_BLK_F_FLUSH == 0 {
+            // Without the feature the device has no write cache, so there is nothing to flush.
+            return IoFuture::ready(Ok(Vec::new()))
+        }
+
+        let (future, completion) = io_pair();
+        critical_section!(|| {
+            self.pending.push_back((request, completion));
+            self.dispatch();
+        });
+        future
+    }
+
+    fn poll(&mut self) {
+        critical_section!(|| {
+            // Reading ISR acknowledges the interrupt.
+            let _ = self.transport.isr();
+            self.reap();
+            self.dispatch();
+        });
+    }
+
+    fn irq(&self) -> Option<u8> {
+        Some(self.transport.device().interrupt_line())
+    }
+}
+
+impl_driver!(VirtioBlock, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::READ | DriverCaps::WRITE | DriverCaps::IRQ | DriverCaps::DMA)
+    .bound_to(BoundDevice::Pci(s.transport.device())),
+    // Writing zero to the status register resets the device and stops all DMA. Dropped
+    // completions abort all unfinished requests.
+    |s| {
+        s.transport.set_status(0);
+        s.pending.clear();
+        s.in_flight.iter_mut().for_each(|slot| drop(slot.take()));
+    }
+);
+
+/// Handler of the interrupt line of the block device loaded as [`DriverType::Storage`].
+///
+/// Lets the driver complete the finished requests and start the queued ones. Registered with
+/// [`DriverManager::register_irq`] on the line returned by [`BlockDevice::irq`].
+///
+/// [`DriverManager::register_irq`]: crate::kernel_components::drivers::DriverManager::register_irq
+pub fn interrupt_handler() -> IrqReturn {
+    match unsafe { DRIVER_MANAGER.driver::<Box<dyn BlockDevice>>(DriverType::Storage) } {
+        Some(block) => {
+            block.poll();
+            IrqReturn::Handled
+        },
+        None => IrqReturn::Unhandled,
+    }
+}
+
+#[test_case]
+fn interrupt_handler_completes_requests() {
+    // Only runs when QEMU provides a virtio disk, like the test target of the Makefile does.
+    let Ok(block) = VirtioBlock::new() else { return };
+    let block: Box<dyn BlockDevice> = Box::new(block);
+    let name = unsafe { DRIVER_MANAGER.load(block, DriverType::Storage) }.unwrap();
+
+    let mut future = unsafe { DRIVER_MANAGER.driver::<Box<dyn BlockDevice>>(DriverType::Storage) }
+        .unwrap()
+        .submit(BlockRequest::read(0, 1));
+    let result = loop {
+        if let Some(result) = future.try_take() {
+            break result
+        }
+        assert_eq!(interrupt_handler(), IrqReturn::Handled);
+    };
+    assert_eq!(result.map(|data| data.len()), Ok(SECTOR_SIZE));
+
+    unsafe { DRIVER_MANAGER.unload(name) }.unwrap();
+    assert_eq!(interrupt_handler(), IrqReturn::Unhandled);
+}
@@ -0,0 +1,168 @@
+/// Virtio console front-end. Provides a text channel between the guest and the host.
+
+use alloc::collections::VecDeque;
+use core::fmt::Write;
+
+use super::transport::{DeviceStatus, DmaRegion, LegacyTransport, VirtioError};
+use super::virtqueue::{Buffer, Virtqueue};
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+use crate::critical_section;
+
+/// Virtio device type of the console.
+const CONSOLE_DEVICE_TYPE: u16 = 3;
+/// Queue used for receiving data from the host.
+const RECEIVE_QUEUE: u16 = 0;
+/// Queue used for transmitting data to the host.
+const TRANSMIT_QUEUE: u16 = 1;
+/// Amount of receive buffers posted to the device.
+const RX_BUFFERS: usize = 16;
+/// Size of a single receive buffer.
+const RX_BUFFER_SIZE: usize = 64;
+/// Size of the transmit bounce buffer.
+const TX_BUFFER_SIZE: usize = 4096;
+
+/// Virtio console driver.
+///
+/// Only the first port is used, so the multiport feature is never negotiated. The transmit path
+/// copies data into a bounce buffer and waits until the device consumes it. The receive path
+/// keeps several small buffers posted to the device and moves received bytes into the inner
+/// queue when polled.
+pub struct VirtioConsole {
+    transport: LegacyTransport,
+    rx: Virtqueue,
+    tx: Virtqueue,
+    rx_region: DmaRegion,
+    tx_region: DmaRegion,
+    input: VecDeque<u8>,
+}
+
+impl VirtioConsole {
+    /// Finds the virtio console device and initializes it.
+    pub fn new() -> Result<Self, VirtioError> {
+        let transport = LegacyTransport::probe(CONSOLE_DEVICE_TYPE)?;
+        transport.negotiate(0);
+
+        let init = || -> Result<Self, VirtioError> {
+            let mut console = Self {
+                rx: Virtqueue::new(&transport, RECEIVE_QUEUE)?,
+                tx: Virtqueue::new(&transport, TRANSMIT_QUEUE)?,
+                rx_region: DmaRegion::new(RX_BUFFERS * RX_BUFFER_SIZE)?,
+                tx_region: DmaRegion::new(TX_BUFFER_SIZE)?,
+                input: VecDeque::new(),
+                transport,
+            };
+
+            for i in 0..RX_BUFFERS {
+                console.post_rx(i)?;
+            }
+            console.transport.notify(RECEIVE_QUEUE);
+
+            Ok(console)
+        };
+
+        match init() {
+            Ok(console) => {
+                transport.add_status(DeviceStatus::DriverOk);
+                Ok(console)
+            },
+            Err(err) => {
+                transport.add_status(DeviceStatus::Failed);
+                Err(err)
+            }
+        }
+    }
+
+    /// Sends the bytes to the host.
+    ///
+    /// Blocks until the device consumes all provided data.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), VirtioError> {
+        for chunk in bytes.chunks(TX_BUFFER_SIZE) {
+            critical_section!(|| {
+                unsafe {
+                    core::ptr::copy_nonoverlapping(chunk.as_ptr(), self.tx_region.virt, chunk.len());
+                }
+
+                self.tx.push(&[Buffer {
+                    phys: self.tx_region.phys,
+                    len: chunk.len() as u32,
+                    writable: false,
+                }])?;
+                self.transport.notify(TRANSMIT_QUEUE);
+
+                while self.tx.pop_used().is_none() {
+                    core::hint::spin_loop();
+                }
+
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reads a single byte received from the host, if any.
+    pub fn read(&mut self) -> Option<u8> {
+        if self.input.is_empty() {
+            self.poll();
+        }
+        self.input.pop_front()
+    }
+
+    /// Moves all data received from the host into the input queue and reposts the buffers.
+    ///
+    /// Can be called from the interrupt handler of the device.
+    pub fn poll(&mut self) {
+        critical_section!(|| {
+            // Reading ISR acknowledges the interrupt.
+            let _ = self.transport.isr();
+
+            let mut reposted = false;
+            while let Some((id, len)) = self.rx.pop_used() {
+                let slot = self.rx_slot(id);
+                let data = unsafe {
+                    core::slice::from_raw_parts(
+                        self.rx_region.virt.add(slot * RX_BUFFER_SIZE),
+                        (len as usize).min(RX_BUFFER_SIZE)
+                    )
+                };
+                self.input.extend(data.iter().copied());
+
+                if self.post_rx(slot).is_ok() {
+                    reposted = true;
+                }
+            }
+
+            if reposted {
+                self.transport.notify(RECEIVE_QUEUE);
+            }
+        });
+    }
+
+    /// Posts the receive buffer with provided slot index to the device.
+    fn post_rx(&mut self, slot: usize) -> Result<(), VirtioError> {
+        self.rx.push(&[Buffer {
+            phys: self.rx_region.phys + slot * RX_BUFFER_SIZE,
+            len: RX_BUFFER_SIZE as u32,
+            writable: true,
+        }])?;
+        Ok(())
+    }
+
+    /// Each receive buffer is a single descriptor, so the descriptor id matches the slot index.
+    fn rx_slot(&self, id: u16) -> usize {
+        id as usize % RX_BUFFERS
+    }
+}
+
+impl Write for VirtioConsole {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+impl_driver!(VirtioConsole, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::READ | DriverCaps::WRITE | DriverCaps::DMA)
+    .bound_to(BoundDevice::Pci(s.transport.device())),
+    // Writing zero to the status register resets the device and stops all DMA.
+    |s| s.transport.set_status(0)
+);
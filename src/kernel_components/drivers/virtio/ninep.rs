@@ -0,0 +1,375 @@
+/// 9P2000.L client over virtio transport for sharing host directories.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::transport::{DeviceStatus, DmaRegion, LegacyTransport, VirtioError};
+use super::virtqueue::{Buffer, Virtqueue};
+use crate::kernel_components::drivers::{Driver, DriverInfo, DriverCaps, BoundDevice};
+use crate::critical_section;
+
+/// Virtio device type of the 9P transport.
+const NINEP_DEVICE_TYPE: u16 = 9;
+/// Feature bit which marks that the mount tag is available in the config space.
+const MOUNT_TAG_FEATURE: u32 = 1;
+/// Maximal message size proposed to the server, which may lower it in the version handshake.
+const MSIZE: u32 = 8192;
+/// Size of the Rread and Rreaddir headers before the data: size[4] type[1] tag[2] count[4].
+const IO_HEADER: u32 = 11;
+/// Protocol version string.
+const VERSION: &str = "9P2000.L";
+/// Fid used for the root of the attached tree.
+const ROOT_FID: u32 = 0;
+/// Tag that marks messages without tag (used only by version).
+const NOTAG: u16 = 0xffff;
+
+/* Message types of 9P2000.L protocol. */
+const RLERROR: u8   = 7;
+const TLOPEN: u8    = 12;
+const TGETATTR: u8  = 24;
+const TREADDIR: u8  = 40;
+const TVERSION: u8  = 100;
+const TATTACH: u8   = 104;
+const TWALK: u8     = 110;
+const TREAD: u8     = 116;
+const TCLUNK: u8    = 120;
+
+/// Error type for 9P client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NinePError {
+    /// Transport level error.
+    Virtio(VirtioError),
+    /// The server replied with Rlerror and provided errno code.
+    Remote(i32),
+    /// The reply could not be parsed or has unexpected type.
+    Protocol,
+}
+
+impl From<VirtioError> for NinePError {
+    fn from(value: VirtioError) -> Self {
+        NinePError::Virtio(value)
+    }
+}
+
+/// Unique identification of the file on the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Qid {
+    pub qtype: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+impl Qid {
+    /// Returns true if the qid describes a directory.
+    pub fn is_dir(&self) -> bool {
+        self.qtype & 0x80 != 0
+    }
+}
+
+/// Single entry returned by readdir.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub qid: Qid,
+    pub offset: u64,
+    pub name: String,
+}
+
+/// 9P2000.L client bound to the virtio-9p device.
+///
+/// The client provides a fid based interface that mirrors the protocol. There is no VFS in the
+/// kernel yet, therefore the shared tree is not mounted anywhere and is accessed directly via
+/// walk/open/read calls.
+pub struct NinePClient {
+    transport: LegacyTransport,
+    queue: Virtqueue,
+    request: DmaRegion,
+    response: DmaRegion,
+    mount_tag: String,
+    /// Message size negotiated with the server.
+    msize: u32,
+    next_fid: u32,
+    next_tag: u16,
+}
+
+impl NinePClient {
+    /// Finds the virtio-9p device, negotiates the protocol version and attaches to the root of
+    /// the exported tree.
+    pub fn new() -> Result<Self, NinePError> {
+        let transport = LegacyTransport::probe(NINEP_DEVICE_TYPE)?;
+        let features = transport.negotiate(MOUNT_TAG_FEATURE);
+
+        let init = || -> Result<Self, NinePError> {
+            let mut mount_tag = String::new();
+            if features & MOUNT_TAG_FEATURE != 0 {
+                let len = transport.config_read_u16(0);
+                for i in 0..len {
+                    mount_tag.push(transport.config_read_u8(2 + i) as char);
+                }
+            }
+
+            let mut client = Self {
+                queue: Virtqueue::new(&transport, 0)?,
+                request: DmaRegion::new(MSIZE as usize)?,
+                response: DmaRegion::new(MSIZE as usize)?,
+                transport,
+                mount_tag,
+                msize: MSIZE,
+                next_fid: ROOT_FID + 1,
+                next_tag: 0,
+            };
+            transport.add_status(DeviceStatus::DriverOk);
+
+            client.version()?;
+            client.attach()?;
+            Ok(client)
+        };
+
+        init().map_err(|err| {
+            transport.add_status(DeviceStatus::Failed);
+            err
+        })
+    }
+
+    /// Returns the mount tag provided by the host.
+    pub fn mount_tag(&self) -> &str {
+        &self.mount_tag
+    }
+
+    /// Walks from the root to the provided path and returns a new fid for it.
+    ///
+    /// Path components are separated with '/'.
+    pub fn walk(&mut self, path: &str) -> Result<u32, NinePError> {
+        let names: Vec<&str> = path.split('/').filter(|n| !n.is_empty()).collect();
+        let fid = self.alloc_fid();
+
+        let mut msg = Message::new(TWALK, self.tag());
+        msg.u32(ROOT_FID).u32(fid).u16(names.len() as u16);
+        for name in names.iter() {
+            msg.str(name);
+        }
+
+        let mut reply = self.transact(msg)?;
+        if reply.u16()? as usize != names.len() {
+            return Err(NinePError::Remote(2))
+        }
+        Ok(fid)
+    }
+
+    /// Opens the walked fid with provided Linux open flags. Returns qid of the file.
+    pub fn open(&mut self, fid: u32, flags: u32) -> Result<Qid, NinePError> {
+        let mut msg = Message::new(TLOPEN, self.tag());
+        msg.u32(fid).u32(flags);
+        self.transact(msg)?.qid()
+    }
+
+    /// Reads up to count bytes from the opened fid at provided offset. The count is capped to fit
+    /// into the negotiated message size.
+    pub fn read(&mut self, fid: u32, offset: u64, count: u32) -> Result<Vec<u8>, NinePError> {
+        let mut msg = Message::new(TREAD, self.tag());
+        msg.u32(fid).u64(offset).u32(count.min(self.msize - IO_HEADER));
+
+        let mut reply = self.transact(msg)?;
+        let len = reply.u32()? as usize;
+        Ok(reply.bytes(len)?.to_vec())
+    }
+
+    /// Reads the whole file under provided path.
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, NinePError> {
+        let fid = self.walk(path)?;
+        let result = self.open(fid, 0).and_then(|_| {
+            let mut data = Vec::new();
+            loop {
+                let chunk = self.read(fid, data.len() as u64, self.msize - IO_HEADER)?;
+                if chunk.is_empty() {
+                    break Ok(data)
+                }
+                data.extend_from_slice(&chunk);
+            }
+        });
+        let _ = self.clunk(fid);
+        result
+    }
+
+    /// Reads the entries of the opened directory fid starting from provided offset.
+    pub fn readdir(&mut self, fid: u32, offset: u64) -> Result<Vec<DirEntry>, NinePError> {
+        let mut msg = Message::new(TREADDIR, self.tag());
+        msg.u32(fid).u64(offset).u32(self.msize - IO_HEADER);
+
+        let mut reply = self.transact(msg)?;
+        let len = reply.u32()? as usize;
+        let end = reply.pos + len;
+
+        let mut entries = Vec::new();
+        while reply.pos < end {
+            let qid = reply.qid()?;
+            let offset = reply.u64()?;
+            let _dtype = reply.u8()?;
+            let name = reply.str()?;
+            entries.push(DirEntry { qid, offset, name });
+        }
+        Ok(entries)
+    }
+
+    /// Returns the size of the file under the fid.
+    pub fn size(&mut self, fid: u32) -> Result<u64, NinePError> {
+        const GETATTR_SIZE: u64 = 0x200;
+
+        let mut msg = Message::new(TGETATTR, self.tag());
+        msg.u32(fid).u64(GETATTR_SIZE);
+
+        let mut reply = self.transact(msg)?;
+        // valid[8] qid[13] mode[4] uid[4] gid[4] nlink[8] rdev[8] size[8]
+        reply.bytes(8 + 13 + 4 + 4 + 4 + 8 + 8)?;
+        reply.u64()
+    }
+
+    /// Releases the fid on the server.
+    pub fn clunk(&mut self, fid: u32) -> Result<(), NinePError> {
+        let mut msg = Message::new(TCLUNK, self.tag());
+        msg.u32(fid);
+        self.transact(msg).map(|_| ())
+    }
+
+    /// Negotiates the protocol version and the message size, which limits every following
+    /// message in both directions.
+    fn version(&mut self) -> Result<(), NinePError> {
+        let mut msg = Message::new(TVERSION, NOTAG);
+        msg.u32(MSIZE).str(VERSION);
+
+        let mut reply = self.transact(msg)?;
+        let msize = reply.u32()?;
+        if reply.str()? != VERSION || msize <= IO_HEADER {
+            return Err(NinePError::Protocol)
+        }
+        // The server must not raise the size, but the buffers would not hold a larger one anyway.
+        self.msize = msize.min(MSIZE);
+        Ok(())
+    }
+
+    fn attach(&mut self) -> Result<(), NinePError> {
+        let mut msg = Message::new(TATTACH, self.tag());
+        msg.u32(ROOT_FID).u32(!0).str("root").str("").u32(0);
+        self.transact(msg).map(|_| ())
+    }
+
+    /// Sends the message and waits for the reply.
+    ///
+    /// Returns the reader positioned after the reply header.
+    fn transact(&mut self, msg: Message) -> Result<Reply, NinePError> {
+        let bytes = msg.finish();
+        let expected = bytes[4] + 1;
+        if bytes.len() > self.msize as usize {
+            return Err(NinePError::Protocol)
+        }
+
+        critical_section!(|| {
+            unsafe {
+                core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.request.virt, bytes.len());
+            }
+
+            self.queue.push(&[
+                Buffer { phys: self.request.phys, len: bytes.len() as u32, writable: false },
+                Buffer { phys: self.response.phys, len: self.msize, writable: true },
+            ])?;
+            self.transport.notify(self.queue.index());
+
+            while self.queue.pop_used().is_none() {
+                core::hint::spin_loop();
+            }
+            Ok::<(), NinePError>(())
+        })?;
+
+        let size = u32::from_le_bytes(unsafe { *(self.response.virt as *const [u8; 4]) }) as usize;
+        if size < 7 || size > self.msize as usize {
+            return Err(NinePError::Protocol)
+        }
+
+        let data = unsafe { core::slice::from_raw_parts(self.response.virt, size) }.to_vec();
+        let mut reply = Reply { data, pos: 7 };
+
+        match reply.data[4] {
+            RLERROR => Err(NinePError::Remote(reply.u32()? as i32)),
+            t if t == expected => Ok(reply),
+            _ => Err(NinePError::Protocol),
+        }
+    }
+
+    fn alloc_fid(&mut self) -> u32 {
+        let fid = self.next_fid;
+        self.next_fid += 1;
+        fid
+    }
+
+    fn tag(&mut self) -> u16 {
+        self.next_tag = (self.next_tag + 1) % NOTAG;
+        self.next_tag
+    }
+}
+
+/// Builder of outgoing 9P messages.
+struct Message(Vec<u8>);
+
+impl Message {
+    fn new(mtype: u8, tag: u16) -> Self {
+        let mut msg = Self(Vec::new());
+        msg.u32(0);
+        msg.0.push(mtype);
+        msg.u16(tag);
+        msg
+    }
+
+    fn u16(&mut self, v: u16) -> &mut Self { self.0.extend_from_slice(&v.to_le_bytes()); self }
+    fn u32(&mut self, v: u32) -> &mut Self { self.0.extend_from_slice(&v.to_le_bytes()); self }
+    fn u64(&mut self, v: u64) -> &mut Self { self.0.extend_from_slice(&v.to_le_bytes()); self }
+
+    fn str(&mut self, s: &str) -> &mut Self {
+        self.u16(s.len() as u16);
+        self.0.extend_from_slice(s.as_bytes());
+        self
+    }
+
+    /// Writes the size field and returns raw bytes.
+    fn finish(mut self) -> Vec<u8> {
+        let size = (self.0.len() as u32).to_le_bytes();
+        self.0[0..4].copy_from_slice(&size);
+        self.0
+    }
+}
+
+/// Reader of incoming 9P messages.
+struct Reply {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl Reply {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], NinePError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len())
+            .ok_or(NinePError::Protocol)?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, NinePError> { Ok(self.bytes(1)?[0]) }
+    fn u16(&mut self) -> Result<u16, NinePError> { Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap())) }
+    fn u32(&mut self) -> Result<u32, NinePError> { Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap())) }
+    fn u64(&mut self) -> Result<u64, NinePError> { Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap())) }
+
+    fn str(&mut self) -> Result<String, NinePError> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn qid(&mut self) -> Result<Qid, NinePError> {
+        Ok(Qid { qtype: self.u8()?, version: self.u32()?, path: self.u64()? })
+    }
+}
+
+impl_driver!(NinePClient, |s| DriverInfo::new(s.name())
+    .version(0, 1, 0)
+    .caps(DriverCaps::READ | DriverCaps::DMA)
+    .bound_to(BoundDevice::Pci(s.transport.device())),
+    // Writing zero to the status register resets the device and stops all DMA.
+    |s| s.transport.set_status(0)
+);
@@ -0,0 +1,212 @@
+/// Legacy virtio PCI transport shared by all virtio drivers.
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+use core::alloc::Layout;
+
+use crate::kernel_components::arch_x86_64::pci::{self, PciDevice};
+use crate::kernel_components::arch_x86_64::ports::{GenericPort, Port, PortAccessType};
+use crate::kernel_components::memory::{frames::PAGE_SIZE, MEMORY_MANAGEMENT_UNIT};
+use crate::PhysicalAddress;
+
+/// PCI vendor id of all virtio devices.
+pub const VIRTIO_VENDOR: u16 = 0x1af4;
+
+/* Legacy virtio register layout within I/O BAR0. */
+const DEVICE_FEATURES: u16          = 0x00;
+const GUEST_FEATURES: u16           = 0x04;
+const QUEUE_ADDRESS: u16            = 0x08;
+const QUEUE_SIZE: u16               = 0x0c;
+const QUEUE_SELECT: u16             = 0x0e;
+const QUEUE_NOTIFY: u16             = 0x10;
+const DEVICE_STATUS: u16            = 0x12;
+const ISR_STATUS: u16               = 0x13;
+/// Device specific configuration space when MSI-X is disabled.
+const DEVICE_CONFIG: u16            = 0x14;
+
+/// Error type for virtio drivers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VirtioError {
+    /// No device with requested subsystem id was found.
+    DeviceNotFound,
+    /// The device does not expose legacy I/O BAR.
+    NoIoBar,
+    /// The device does not provide requested queue.
+    QueueUnavailable(u16),
+    /// Unable to allocate physically contiguous memory for the device.
+    DmaUnavailable,
+    /// The queue has no free descriptors left.
+    QueueFull,
+    /// The device reported a failure or sent a malformed response.
+    DeviceFailure,
+}
+
+/// Device status bits written during initialization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DeviceStatus {
+    Acknowledge = 1,
+    Driver = 2,
+    DriverOk = 4,
+    FeaturesOk = 8,
+    Failed = 128,
+}
+
+/// Legacy (virtio 0.9.5) PCI transport.
+///
+/// Transitional virtio devices expose all common registers through I/O BAR0, which is much
+/// simpler to drive than the capability based modern interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LegacyTransport {
+    device: PciDevice,
+    io: u16,
+}
+
+impl LegacyTransport {
+    /// Finds the first transitional virtio device with provided subsystem id and resets it.
+    ///
+    /// Subsystem ids match virtio device types: 1 - net, 2 - block, 3 - console, 9 - 9p.
+    pub fn probe(device_type: u16) -> Result<Self, VirtioError> {
+        let device = pci::scan().into_iter()
+            .find(|dev| {
+                dev.vendor_id() == VIRTIO_VENDOR &&
+                (0x1000..0x1040).contains(&dev.device_id()) &&
+                (dev.read(0x2c) >> 16) as u16 == device_type
+            })
+            .ok_or(VirtioError::DeviceNotFound)?;
+        let io = device.io_bar(0).ok_or(VirtioError::NoIoBar)?;
+
+        device.enable_bus_master();
+
+        let transport = Self { device, io };
+        transport.set_status(0);
+        transport.add_status(DeviceStatus::Acknowledge);
+        transport.add_status(DeviceStatus::Driver);
+
+        Ok(transport)
+    }
+
+    /// Returns the PCI function of the device.
+    pub fn device(&self) -> PciDevice {
+        self.device
+    }
+
+    /// Reads the features offered by the device.
+    pub fn device_features(&self) -> u32 {
+        self.port::<u32>(DEVICE_FEATURES).read()
+    }
+
+    /// Acknowledges the subset of offered features which the driver understands.
+    pub fn negotiate(&self, supported: u32) -> u32 {
+        let features = self.device_features() & supported;
+        self.port::<u32>(GUEST_FEATURES).write(features);
+        features
+    }
+
+    /// Returns the size of selected queue. Zero means the queue does not exist.
+    pub fn queue_size(&self, queue: u16) -> u16 {
+        self.port::<u16>(QUEUE_SELECT).write(queue);
+        self.port::<u16>(QUEUE_SIZE).read()
+    }
+
+    /// Provides the physical address of the queue to the device.
+    ///
+    /// The legacy interface expects the page frame number of the queue.
+    pub fn set_queue_address(&self, queue: u16, phys: PhysicalAddress) {
+        self.port::<u16>(QUEUE_SELECT).write(queue);
+        self.port::<u32>(QUEUE_ADDRESS).write((phys / PAGE_SIZE) as u32);
+    }
+
+    /// Notifies the device that new buffers are available in the queue.
+    pub fn notify(&self, queue: u16) {
+        self.port::<u16>(QUEUE_NOTIFY).write(queue);
+    }
+
+    /// Reads and acknowledges the interrupt status.
+    pub fn isr(&self) -> u8 {
+        self.port::<u8>(ISR_STATUS).read()
+    }
+
+    /// Writes the device status.
+    pub fn set_status(&self, status: u8) {
+        self.port::<u8>(DEVICE_STATUS).write(status);
+    }
+
+    /// Adds the bit to the device status.
+    pub fn add_status(&self, status: DeviceStatus) {
+        let port = self.port::<u8>(DEVICE_STATUS);
+        port.write(port.read() | status as u8);
+    }
+
+    /// Reads the byte from the device specific configuration space.
+    pub fn config_read_u8(&self, offset: u16) -> u8 {
+        self.port::<u8>(DEVICE_CONFIG + offset).read()
+    }
+
+    /// Reads the word from the device specific configuration space.
+    pub fn config_read_u16(&self, offset: u16) -> u16 {
+        self.port::<u16>(DEVICE_CONFIG + offset).read()
+    }
+
+    fn port<T: Port<T>>(&self, reg: u16) -> GenericPort<T> {
+        GenericPort::new(self.io + reg, PortAccessType::READWRITE)
+    }
+}
+
+/// Physically contiguous memory region shared with the device.
+#[derive(Debug)]
+pub struct DmaRegion {
+    pub virt: *mut u8,
+    pub phys: PhysicalAddress,
+    layout: Layout,
+}
+
+impl DmaRegion {
+    /// Allocates page aligned zeroed region on the heap.
+    ///
+    /// The heap is not guaranteed to be physically contiguous, therefore every page is checked
+    /// and an error is returned if the region is scattered.
+    pub fn new(size: usize) -> Result<Self, VirtioError> {
+        let layout = Layout::from_size_align(size.max(1), PAGE_SIZE)
+            .map_err(|_| VirtioError::DmaUnavailable)?;
+        let virt = unsafe { alloc_zeroed(layout) };
+
+        if virt.is_null() {
+            return Err(VirtioError::DmaUnavailable)
+        }
+
+        let contiguous = || -> Result<PhysicalAddress, VirtioError> {
+            let phys = Self::translate(virt as usize)?;
+            for offset in (PAGE_SIZE..size).step_by(PAGE_SIZE) {
+                if Self::translate(virt as usize + offset)? != phys + offset {
+                    return Err(VirtioError::DmaUnavailable)
+                }
+            }
+            Ok(phys)
+        };
+
+        match contiguous() {
+            Ok(phys) => Ok(Self { virt, phys, layout }),
+            Err(err) => {
+                unsafe { dealloc(virt, layout) };
+                Err(err)
+            }
+        }
+    }
+
+    /// Size of the region in bytes.
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+
+    fn translate(addr: usize) -> Result<PhysicalAddress, VirtioError> {
+        unsafe { MEMORY_MANAGEMENT_UNIT.translate(addr) }.ok_or(VirtioError::DmaUnavailable)
+    }
+}
+
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        if !self.virt.is_null() {
+            unsafe { dealloc(self.virt, self.layout) }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+/// Split virtqueue implementation in the legacy memory layout.
+
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use super::transport::{DmaRegion, LegacyTransport, VirtioError};
+use crate::kernel_components::memory::frames::PAGE_SIZE;
+use crate::PhysicalAddress;
+
+/// Descriptor continues via the next field.
+const DESC_F_NEXT: u16 = 1;
+/// Buffer is write-only for the device.
+const DESC_F_WRITE: u16 = 2;
+
+/// Single descriptor of the descriptor table.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// Single element of the used ring.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer provided to the device.
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    /// Physical address of the buffer.
+    pub phys: PhysicalAddress,
+    /// Length of the buffer in bytes.
+    pub len: u32,
+    /// True if the device writes into the buffer.
+    pub writable: bool,
+}
+
+/// Split virtqueue.
+///
+/// The queue consists of three parts that are placed within one physically contiguous region:
+/// descriptor table, available ring (driver to device) and used ring (device to driver). The
+/// used ring starts on the next page boundary after the available ring.
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    region: DmaRegion,
+    used_offset: usize,
+    free_head: u16,
+    num_free: u16,
+    last_used: u16,
+}
+
+impl Virtqueue {
+    /// Allocates the queue with provided index and hands it to the device.
+    pub fn new(transport: &LegacyTransport, index: u16) -> Result<Self, VirtioError> {
+        let size = transport.queue_size(index);
+        if size == 0 {
+            return Err(VirtioError::QueueUnavailable(index))
+        }
+
+        let avail_size = size_of::<u16>() * (3 + size as usize);
+        let used_offset = Self::align(size_of::<Descriptor>() * size as usize + avail_size);
+        let used_size = size_of::<u16>() * 3 + size_of::<UsedElem>() * size as usize;
+        let region = DmaRegion::new(used_offset + Self::align(used_size))?;
+
+        let mut queue = Self {
+            index, size, region, used_offset,
+            free_head: 0,
+            num_free: size,
+            last_used: 0,
+        };
+
+        // Chaining all descriptors into the free list.
+        for i in 0..size {
+            unsafe { (*queue.desc(i)).next = i + 1 };
+        }
+
+        transport.set_queue_address(index, queue.region.phys);
+        Ok(queue)
+    }
+
+    /// Index of the queue within the device.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Amount of free descriptors.
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    /// Places the chain of buffers into the available ring.
+    ///
+    /// Returns the id of the head descriptor, which will be returned by pop_used when the device
+    /// is done with the chain. The device must be notified afterwards.
+    pub fn push(&mut self, buffers: &[Buffer]) -> Result<u16, VirtioError> {
+        if buffers.is_empty() || buffers.len() > self.num_free as usize {
+            return Err(VirtioError::QueueFull)
+        }
+
+        let head = self.free_head;
+
+        for (i, buf) in buffers.iter().enumerate() {
+            let id = self.free_head;
+            let desc = self.desc(id);
+            unsafe {
+                self.free_head = (*desc).next;
+                (*desc).addr = buf.phys as u64;
+                (*desc).len = buf.len;
+                (*desc).flags = if buf.writable { DESC_F_WRITE } else { 0 };
+                if i + 1 < buffers.len() {
+                    (*desc).flags |= DESC_F_NEXT;
+                }
+            }
+        }
+        self.num_free -= buffers.len() as u16;
+
+        unsafe {
+            let idx = ptr::read_volatile(self.avail_idx());
+            ptr::write_volatile(self.avail_ring(idx % self.size), head);
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(self.avail_idx(), idx.wrapping_add(1));
+        }
+
+        Ok(head)
+    }
+
+    /// Returns the next chain used by the device with the amount of bytes written into it.
+    ///
+    /// All descriptors of the chain are returned to the free list.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        fence(Ordering::SeqCst);
+        let used_idx = unsafe { ptr::read_volatile(self.used_idx()) };
+        if used_idx == self.last_used {
+            return None
+        }
+
+        let elem = unsafe { ptr::read_volatile(self.used_ring(self.last_used % self.size)) };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        // Returning the chain to the free list.
+        let head = elem.id as u16;
+        let mut id = head;
+        loop {
+            self.num_free += 1;
+            let desc = self.desc(id);
+            unsafe {
+                if (*desc).flags & DESC_F_NEXT == 0 {
+                    (*desc).next = self.free_head;
+                    break
+                }
+                id = (*desc).next;
+            }
+        }
+        self.free_head = head;
+
+        Some((head, elem.len))
+    }
+
+    /// Returns true if the device has used some buffers that were not popped yet.
+    pub fn has_used(&self) -> bool {
+        fence(Ordering::SeqCst);
+        unsafe { ptr::read_volatile(self.used_idx()) != self.last_used }
+    }
+
+    fn desc(&self, id: u16) -> *mut Descriptor {
+        unsafe { (self.region.virt as *mut Descriptor).add(id as usize) }
+    }
+
+    fn avail_idx(&self) -> *mut u16 {
+        unsafe { (self.region.virt.add(size_of::<Descriptor>() * self.size as usize) as *mut u16).add(1) }
+    }
+
+    fn avail_ring(&self, slot: u16) -> *mut u16 {
+        unsafe { self.avail_idx().add(1 + slot as usize) }
+    }
+
+    fn used_idx(&self) -> *mut u16 {
+        unsafe { (self.region.virt.add(self.used_offset) as *mut u16).add(1) }
+    }
+
+    fn used_ring(&self, slot: u16) -> *mut UsedElem {
+        unsafe { (self.used_idx().add(1) as *mut UsedElem).add(slot as usize) }
+    }
+
+    const fn align(size: usize) -> usize {
+        (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+    }
+}
@@ -0,0 +1,147 @@
+//! Emergency output for fatal paths.
+//!
+//! Panic, double fault and NMI handlers must not rely on the regular printing machinery: the
+//! logger lock might be held by the interrupted code and the allocator or the logger state might
+//! be corrupted. The emergency writer holds no locks, never allocates and writes every byte
+//! straight to the COM1 UART and to the VGA text buffer at 0xb8000.
+//!
+//! Output is written to the UART first, so the message still reaches the serial console even if
+//! the VGA mapping itself is what caused the fault.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::kernel_components::arch_x86_64::ports::Port;
+
+/// Base port of the COM1 UART.
+const COM1: u16 = 0x3f8;
+/// Physical (and identity mapped) address of the VGA text buffer.
+const VGA_BUFFER: usize = 0xb8000;
+const VGA_WIDTH: usize = 80;
+const VGA_HEIGHT: usize = 25;
+/// White on red.
+const VGA_ATTRIBUTE: u16 = 0x4f << 8;
+/// Maximal amount of polls of the UART line status before the byte is dropped.
+const UART_TIMEOUT: usize = 100_000;
+
+/// Marks that the UART was programmed by the emergency writer.
+static UART_READY: AtomicBool = AtomicBool::new(false);
+/// Next VGA row to be used by the emergency writer.
+static NEXT_ROW: AtomicUsize = AtomicUsize::new(0);
+
+/// Lock free, allocation free writer for fatal paths.
+pub struct EmergencyWriter {
+    row: usize,
+    col: usize,
+}
+
+impl EmergencyWriter {
+    /// Creates a new writer, starting at the next free emergency row of the screen.
+    pub fn new() -> Self {
+        Self::ensure_uart();
+
+        let row = NEXT_ROW.load(Ordering::Acquire) % VGA_HEIGHT;
+        let mut writer = Self { row, col: 0 };
+        writer.clear_row();
+        writer
+    }
+
+    fn ensure_uart() {
+        if !UART_READY.swap(true, Ordering::AcqRel) {
+            unsafe { Self::init_uart() };
+        }
+    }
+
+    /// Programs COM1 for 115200 baud, 8N1, with interrupts disabled.
+    unsafe fn init_uart() {
+        u8::write(COM1 + 1, 0x00);
+        u8::write(COM1 + 3, 0x80);
+        u8::write(COM1, 0x01);
+        u8::write(COM1 + 1, 0x00);
+        u8::write(COM1 + 3, 0x03);
+        u8::write(COM1 + 2, 0xc7);
+        u8::write(COM1 + 4, 0x03);
+    }
+
+    fn uart_put(byte: u8) {
+        unsafe {
+            for _ in 0..UART_TIMEOUT {
+                if u8::read(COM1 + 5) & 0x20 != 0 {
+                    return u8::write(COM1, byte)
+                }
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    fn vga_put(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            byte => {
+                if self.col >= VGA_WIDTH {
+                    self.new_line();
+                }
+                let byte = if byte.is_ascii_graphic() || byte == b' ' { byte } else { b'?' };
+                unsafe {
+                    let cell = (VGA_BUFFER as *mut u16).add(self.row * VGA_WIDTH + self.col);
+                    cell.write_volatile(VGA_ATTRIBUTE | byte as u16);
+                }
+                self.col += 1;
+            },
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.row = (self.row + 1) % VGA_HEIGHT;
+        self.col = 0;
+        NEXT_ROW.store(self.row, Ordering::Release);
+        self.clear_row();
+    }
+
+    fn clear_row(&mut self) {
+        for col in 0..VGA_WIDTH {
+            unsafe {
+                let cell = (VGA_BUFFER as *mut u16).add(self.row * VGA_WIDTH + col);
+                cell.write_volatile(VGA_ATTRIBUTE | b' ' as u16);
+            }
+        }
+    }
+}
+
+impl Write for EmergencyWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                Self::uart_put(b'\r');
+            }
+            Self::uart_put(byte);
+            self.vga_put(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Writes raw bytes to the COM1 UART only, without any translation of new lines.
+///
+/// Used to stream binary data, like console snapshots, out of the machine from fatal paths.
+pub fn serial_write(bytes: &[u8]) {
+    EmergencyWriter::ensure_uart();
+    bytes.iter().for_each(|&byte| EmergencyWriter::uart_put(byte));
+}
+
+#[doc(hidden)]
+pub fn _emergency_print(args: fmt::Arguments) {
+    let _ = EmergencyWriter::new().write_fmt(args);
+}
+
+/// Prints the message with the emergency writer.
+///
+/// Must only be used on fatal paths (panics, double faults, NMIs). Works like println, but never
+/// takes locks or allocates and does not support coloring.
+#[macro_export]
+macro_rules! emergency_println {
+    () => ($crate::kernel_components::emergency::_emergency_print(format_args!("\n")));
+    ($($arg:tt)*) => ($crate::kernel_components::emergency::_emergency_print(
+        format_args!("{}\n", format_args!($($arg)*))
+    ));
+}
@@ -0,0 +1,369 @@
+//! Module that defines a crate-wide kernel error type.
+//!
+//! Every subsystem defines it's own error enum, which is perfect for handling errors locally.
+//! When the error must cross the subsystem boundary (i.e returned to user space), it must be
+//! converted into KError, which also provides an errno-like numeric code.
+
+use core::error::Error;
+use core::fmt::Display;
+
+use crate::kernel_components::drivers::{DriverError, keyboards::ScancodeError, sound::AudioError, virtio::{VirtioError, NinePError}};
+use crate::kernel_components::memory::memory_module::{MemError, MbiLoadError};
+use crate::kernel_components::arch_x86_64::acpi::{
+    acpi::{SDTValidationError, acpi_service::ACPIError},
+    rsdp::RootPointerError,
+};
+use crate::kernel_components::arch_x86_64::interrupts::{VectorError, SoftintError};
+use crate::kernel_components::arch_x86_64::segmentation::GdtError;
+use crate::kernel_components::task_virtualization::{join_handle::ThreadOutputError, CapabilityError};
+use crate::kernel_components::kexec::KexecError;
+use crate::kernel_components::logging::LogError;
+use crate::kernel_components::drivers::storage::BlockError;
+use crate::kernel_components::drivers::serial::{UartError, XmodemError};
+use crate::kernel_components::fs::{fat::FatError, TmpfsError};
+use crate::kernel_components::memory::{vma::VmaError, swap::SwapError};
+
+/// Result type with unified kernel error.
+pub type KResult<T> = Result<T, KError>;
+
+/// Unified kernel error.
+///
+/// Each variant corresponds to one POSIX errno value, which can be obtained with errno method.
+/// The subsystem specific errors are converted with From implementations, so the '?' operator
+/// can be used to propagate them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KError {
+    /// Operation not permitted.
+    NotPermitted,
+    /// No such file, directory or object.
+    NotFound,
+    /// I/O error.
+    Io,
+    /// No such device or address.
+    NoDevice,
+    /// Resource temporarily unavailable.
+    WouldBlock,
+    /// Out of memory.
+    OutOfMemory,
+    /// Bad address.
+    BadAddress,
+    /// Device or resource busy.
+    Busy,
+    /// Object already exists.
+    AlreadyExists,
+    /// Invalid argument.
+    InvalidArgument,
+    /// Function not implemented.
+    NotImplemented,
+    /// Operation not supported.
+    NotSupported,
+    /// Invalid or corrupted data.
+    InvalidData,
+}
+
+impl KError {
+    /// Returns the errno-style numeric code of the error.
+    ///
+    /// Values match the ones used by Linux, so they can be passed to user space directly. System
+    /// calls return them negated.
+    pub const fn errno(&self) -> i32 {
+        use KError::*;
+        match self {
+            NotPermitted    => 1,
+            NotFound        => 2,
+            Io              => 5,
+            NoDevice        => 6,
+            WouldBlock      => 11,
+            OutOfMemory     => 12,
+            BadAddress      => 14,
+            Busy            => 16,
+            AlreadyExists   => 17,
+            InvalidArgument => 22,
+            NotImplemented  => 38,
+            NotSupported    => 95,
+            InvalidData     => 74,
+        }
+    }
+
+    /// Converts the errno code back into the error. Returns None for unknown codes.
+    pub const fn from_errno(errno: i32) -> Option<Self> {
+        use KError::*;
+        Some(match errno.unsigned_abs() {
+            1  => NotPermitted,
+            2  => NotFound,
+            5  => Io,
+            6  => NoDevice,
+            11 => WouldBlock,
+            12 => OutOfMemory,
+            14 => BadAddress,
+            16 => Busy,
+            17 => AlreadyExists,
+            22 => InvalidArgument,
+            38 => NotImplemented,
+            95 => NotSupported,
+            74 => InvalidData,
+            _  => return None,
+        })
+    }
+}
+
+impl Display for KError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use KError::*;
+        match self {
+            NotPermitted    => write!(f, "Operation not permitted"),
+            NotFound        => write!(f, "No such object"),
+            Io              => write!(f, "I/O error"),
+            NoDevice        => write!(f, "No such device"),
+            WouldBlock      => write!(f, "Resource temporarily unavailable"),
+            OutOfMemory     => write!(f, "Out of memory"),
+            BadAddress      => write!(f, "Bad address"),
+            Busy            => write!(f, "Device or resource busy"),
+            AlreadyExists   => write!(f, "Object already exists"),
+            InvalidArgument => write!(f, "Invalid argument"),
+            NotImplemented  => write!(f, "Function not implemented"),
+            NotSupported    => write!(f, "Operation not supported"),
+            InvalidData     => write!(f, "Invalid data"),
+        }
+    }
+}
+
+impl Error for KError {}
+
+impl From<DriverError> for KError {
+    fn from(value: DriverError) -> Self {
+        match value {
+            DriverError::AlreadyLoaded => KError::AlreadyExists,
+            DriverError::NotLoaded => KError::NoDevice,
+            DriverError::PermissionDenied => KError::NotPermitted,
+            DriverError::DeviceBusy => KError::Busy,
+            DriverError::ProbeFailed => KError::NoDevice,
+        }
+    }
+}
+
+impl From<MemError> for KError {
+    fn from(value: MemError) -> Self {
+        match value {
+            MemError::NoFrameAlloc => KError::OutOfMemory,
+            MemError::NotMapped => KError::BadAddress,
+            MemError::BootInfo(err) => err.into(),
+            MemError::MissingTag(_) => KError::NotSupported,
+            MemError::UnalignedSection(_) => KError::BadAddress,
+            MemError::OutOfFrames => KError::OutOfMemory,
+            MemError::AcpiMapFailed => KError::NotSupported,
+            MemError::StackExhausted => KError::OutOfMemory,
+            MemError::InvalidRegion(_) => KError::InvalidArgument,
+            MemError::FramebufferTooLarge(_) => KError::NotSupported,
+            MemError::LazyRegionsFull => KError::OutOfMemory,
+            MemError::OrderTooLarge(_) => KError::InvalidArgument,
+        }
+    }
+}
+
+impl From<MbiLoadError> for KError {
+    fn from(value: MbiLoadError) -> Self {
+        match value {
+            MbiLoadError::IllegalAddress => KError::BadAddress,
+            MbiLoadError::IllegalTotalSize(_) | MbiLoadError::NoEndTag => KError::InvalidData,
+        }
+    }
+}
+
+impl From<ScancodeError> for KError {
+    fn from(_: ScancodeError) -> Self {
+        KError::InvalidData
+    }
+}
+
+impl From<AudioError> for KError {
+    fn from(value: AudioError) -> Self {
+        match value {
+            AudioError::DeviceNotFound => KError::NoDevice,
+            AudioError::UnsupportedRate(_) => KError::InvalidArgument,
+            AudioError::DmaUnavailable => KError::BadAddress,
+        }
+    }
+}
+
+impl From<VirtioError> for KError {
+    fn from(value: VirtioError) -> Self {
+        match value {
+            VirtioError::DeviceNotFound | VirtioError::NoIoBar => KError::NoDevice,
+            VirtioError::QueueUnavailable(_) => KError::NotSupported,
+            VirtioError::DmaUnavailable => KError::OutOfMemory,
+            VirtioError::QueueFull => KError::WouldBlock,
+            VirtioError::DeviceFailure => KError::Io,
+        }
+    }
+}
+
+impl From<NinePError> for KError {
+    fn from(value: NinePError) -> Self {
+        match value {
+            NinePError::Virtio(err) => err.into(),
+            NinePError::Remote(errno) => KError::from_errno(errno).unwrap_or(KError::Io),
+            NinePError::Protocol => KError::InvalidData,
+        }
+    }
+}
+
+impl From<ACPIError> for KError {
+    fn from(_: ACPIError) -> Self {
+        KError::NotFound
+    }
+}
+
+impl From<RootPointerError> for KError {
+    fn from(value: RootPointerError) -> Self {
+        match value {
+            RootPointerError::NOTAG => KError::NotFound,
+            RootPointerError::CHECKSUM | RootPointerError::SIGNATURE |
+            RootPointerError::LENGTH | RootPointerError::SDT(_) => KError::InvalidData,
+        }
+    }
+}
+
+impl From<SDTValidationError> for KError {
+    fn from(_: SDTValidationError) -> Self {
+        KError::InvalidData
+    }
+}
+
+impl From<ThreadOutputError> for KError {
+    fn from(_: ThreadOutputError) -> Self {
+        KError::Io
+    }
+}
+
+impl From<CapabilityError> for KError {
+    fn from(value: CapabilityError) -> Self {
+        match value {
+            CapabilityError::Missing(_) | CapabilityError::PermissionDenied => KError::NotPermitted,
+            CapabilityError::NoSuchProcess => KError::NotFound,
+        }
+    }
+}
+
+impl From<VectorError> for KError {
+    fn from(value: VectorError) -> Self {
+        match value {
+            VectorError::Exhausted | VectorError::InUse(..) => KError::Busy,
+            VectorError::OutOfRange(_) => KError::InvalidArgument,
+            VectorError::NotAllocated(_) => KError::NotFound,
+        }
+    }
+}
+
+impl From<SoftintError> for KError {
+    fn from(value: SoftintError) -> Self {
+        match value {
+            SoftintError::ReservedVector(_) => KError::InvalidArgument,
+            SoftintError::InvalidCpu(_) => KError::NoDevice,
+            SoftintError::Unsupported => KError::NotSupported,
+        }
+    }
+}
+
+impl From<LogError> for KError {
+    fn from(value: LogError) -> Self {
+        match value {
+            LogError::TooManySinks => KError::OutOfMemory,
+            LogError::DuplicateSink(_) => KError::AlreadyExists,
+            LogError::UnknownSink => KError::NotFound,
+        }
+    }
+}
+
+impl From<GdtError> for KError {
+    fn from(_: GdtError) -> Self {
+        KError::InvalidArgument
+    }
+}
+
+impl From<KexecError> for KError {
+    fn from(value: KexecError) -> Self {
+        match value {
+            KexecError::InvalidImage | KexecError::NoMultibootHeader => KError::InvalidData,
+            KexecError::Unsupported => KError::NotSupported,
+            KexecError::OutOfMemory => KError::OutOfMemory,
+            KexecError::Overlap => KError::Busy,
+            KexecError::NotLoaded => KError::NotFound,
+        }
+    }
+}
+
+impl From<BlockError> for KError {
+    fn from(value: BlockError) -> Self {
+        match value {
+            BlockError::OutOfRange { .. } | BlockError::Unaligned(_) | BlockError::TooLarge(_) => KError::InvalidArgument,
+            BlockError::ReadOnly => KError::NotPermitted,
+            BlockError::Unsupported => KError::NotSupported,
+            BlockError::DeviceFailure | BlockError::Aborted => KError::Io,
+        }
+    }
+}
+
+impl From<FatError> for KError {
+    fn from(value: FatError) -> Self {
+        match value {
+            FatError::Io(err) => err.into(),
+            FatError::NotFat32 | FatError::InvalidBpb => KError::InvalidData,
+        }
+    }
+}
+
+impl From<TmpfsError> for KError {
+    fn from(value: TmpfsError) -> Self {
+        match value {
+            TmpfsError::NotFound => KError::NotFound,
+            TmpfsError::InvalidName => KError::InvalidArgument,
+            TmpfsError::NoSpace => KError::OutOfMemory,
+        }
+    }
+}
+
+impl From<UartError> for KError {
+    fn from(value: UartError) -> Self {
+        match value {
+            UartError::InvalidBaudRate(_) => KError::InvalidArgument,
+            UartError::NotPresent(_) => KError::NoDevice,
+        }
+    }
+}
+
+impl From<XmodemError> for KError {
+    fn from(value: XmodemError) -> Self {
+        match value {
+            XmodemError::TooLarge => KError::OutOfMemory,
+            XmodemError::Timeout | XmodemError::Canceled | XmodemError::TooManyErrors | XmodemError::OutOfSync => KError::Io,
+        }
+    }
+}
+
+impl From<VmaError> for KError {
+    fn from(value: VmaError) -> Self {
+        match value {
+            VmaError::InvalidLength | VmaError::Unaligned(_) | VmaError::NoVirtualSpace => KError::InvalidArgument,
+            VmaError::NotMapped(_) | VmaError::AccessViolation(_) => KError::BadAddress,
+            VmaError::ReadOnlyFile => KError::NotPermitted,
+            VmaError::NoProcess => KError::NotFound,
+            VmaError::OutOfMemory => KError::OutOfMemory,
+            VmaError::Io(err) => err.into(),
+            VmaError::Swap(SwapError::Full) => KError::OutOfMemory,
+            VmaError::Swap(_) => KError::Io,
+        }
+    }
+}
+
+#[test_case]
+fn errno_round_trip() {
+    use KError::*;
+
+    for err in [NotPermitted, NotFound, Io, NoDevice, WouldBlock, OutOfMemory, BadAddress, Busy,
+                AlreadyExists, InvalidArgument, NotImplemented, NotSupported, InvalidData] {
+        assert_eq!(KError::from_errno(err.errno()), Some(err));
+        assert_eq!(KError::from_errno(-err.errno()), Some(err));
+    }
+    assert_eq!(KError::from_errno(i32::MIN), None);
+}
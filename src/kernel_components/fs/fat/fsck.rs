@@ -0,0 +1,300 @@
+/// Consistency checker of FAT32 volumes.
+///
+/// The checker walks the directory tree starting from the root directory and claims every cluster
+/// reachable from the directory entries. Problems are detected on the way:
+///
+/// - chains that link to free, reserved or out of range clusters;
+/// - clusters claimed by two chains (cross-linked), including chains looping on themselves;
+/// - allocated clusters not reachable from any directory entry (lost chains);
+/// - files whose size does not match the length of their chain;
+/// - FAT copies that differ from the first one.
+///
+/// Broken chains are terminated right before the invalid link, lost chains are freed and all FAT
+/// copies are rewritten from the first one. In dry-run mode the repairs are only reported.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::volume::{FatVolume, FatError, FAT_FREE, FAT_BAD, FAT_EOC};
+use crate::kernel_components::drivers::storage::BlockDevice;
+
+/// Mode of the check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsckMode {
+    /// Only detects the problems. Nothing is written to the device.
+    DryRun,
+    /// Writes the repairs to the device.
+    Commit,
+}
+
+/// A problem found on the volume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// The FAT copy differs from the first copy.
+    FatMismatch { copy: u8 },
+    /// The chain links from the cluster to an invalid cluster.
+    InvalidLink { cluster: u32, next: u32 },
+    /// The cluster is claimed by both chains, which are identified by their first clusters.
+    CrossLinked { cluster: u32, first: u32, second: u32 },
+    /// Allocated clusters not reachable from any directory.
+    LostChain { start: u32, length: u32 },
+    /// The size of the file does not match the length of its chain.
+    SizeMismatch { start: u32, size: u32, clusters: u32 },
+}
+
+/// Result of the check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsckReport {
+    /// All found problems in the order of detection.
+    pub problems: Vec<FsckProblem>,
+    /// True if the repairs were written to the device.
+    pub repaired: bool,
+}
+
+impl FsckReport {
+    /// Returns true if no problems were found.
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// State of a single check.
+struct Checker<'v, 'd> {
+    volume: &'v mut FatVolume<'d>,
+    /// First cluster of the chain that owns the cluster. Zero for unclaimed clusters.
+    owner: Vec<u32>,
+    problems: Vec<FsckProblem>,
+    /// The in-memory FAT was changed.
+    dirty: bool,
+}
+
+impl Checker<'_, '_> {
+    /// Walks the chain and claims its clusters.
+    ///
+    /// Returns the clusters of the chain. The chain is terminated before the first invalid link
+    /// or the first cluster claimed by another chain.
+    fn walk(&mut self, start: u32) -> Vec<u32> {
+        let max = self.volume.bpb().max_cluster();
+        let mut clusters = Vec::new();
+
+        if !(2..max).contains(&start) {
+            self.problems.push(FsckProblem::InvalidLink { cluster: 0, next: start });
+            return clusters
+        }
+
+        let mut current = start;
+        loop {
+            let owner = self.owner[current as usize];
+            if owner != 0 {
+                self.problems.push(FsckProblem::CrossLinked { cluster: current, first: owner, second: start });
+                // The entry itself points to a claimed cluster, which can only be fixed manually.
+                if let Some(&last) = clusters.last() {
+                    self.terminate(last);
+                }
+                break
+            }
+
+            self.owner[current as usize] = start;
+            clusters.push(current);
+
+            match self.volume.entry(current) {
+                next if next >= FAT_EOC => break,
+                next if next == FAT_FREE || next == FAT_BAD || next < 2 || next >= max => {
+                    self.problems.push(FsckProblem::InvalidLink { cluster: current, next });
+                    self.terminate(current);
+                    break
+                },
+                next => current = next,
+            }
+        }
+        clusters
+    }
+
+    fn terminate(&mut self, cluster: u32) {
+        self.volume.set_entry(cluster, FAT_EOC | 0x7);
+        self.dirty = true;
+    }
+
+    /// Checks the whole directory tree.
+    fn check_tree(&mut self) -> Result<(), FatError> {
+        let cluster_size = self.volume.bpb().cluster_size() as u32;
+        let mut directories = vec![self.walk(self.volume.bpb().root_cluster)];
+
+        while let Some(chain) = directories.pop() {
+            for cluster in chain {
+                let (entries, more) = self.volume.read_dir_cluster(cluster)?;
+
+                for entry in entries.into_iter().filter(|e| !e.is_dot()) {
+                    if entry.first_cluster == 0 {
+                        if entry.size != 0 {
+                            self.problems.push(FsckProblem::SizeMismatch { start: 0, size: entry.size, clusters: 0 });
+                        }
+                        continue
+                    }
+
+                    let chain = self.walk(entry.first_cluster);
+                    if entry.is_dir() {
+                        directories.push(chain);
+                    } else if chain.len() as u32 != entry.size.div_ceil(cluster_size) {
+                        self.problems.push(FsckProblem::SizeMismatch {
+                            start: entry.first_cluster,
+                            size: entry.size,
+                            clusters: chain.len() as u32,
+                        });
+                    }
+                }
+
+                if !more {
+                    break
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds and frees allocated clusters that were not claimed by any chain.
+    fn free_lost_chains(&mut self) {
+        let max = self.volume.bpb().max_cluster();
+        let is_lost = |c: &Checker, cluster: u32| {
+            let entry = c.volume.entry(cluster);
+            c.owner[cluster as usize] == 0 && entry != FAT_FREE && entry != FAT_BAD
+        };
+
+        let lost: Vec<u32> = (2..max).filter(|&c| is_lost(self, c)).collect();
+        // Clusters that are linked from another lost cluster are not heads of the lost chains.
+        let mut linked = vec![false; max as usize];
+        for &c in lost.iter() {
+            let next = self.volume.entry(c);
+            if next < max {
+                linked[next as usize] = true;
+            }
+        }
+
+        // Heads first, then whatever remains within cycles.
+        let heads = lost.iter().filter(|&&c| !linked[c as usize]).chain(lost.iter());
+        for &start in heads.collect::<Vec<_>>() {
+            if !is_lost(self, start) {
+                continue
+            }
+
+            let mut length = 0;
+            let mut current = start;
+            while current < max && is_lost(self, current) {
+                let next = self.volume.entry(current);
+                self.volume.set_entry(current, FAT_FREE);
+                length += 1;
+                current = next;
+            }
+            self.problems.push(FsckProblem::LostChain { start, length });
+            self.dirty = true;
+        }
+    }
+}
+
+/// Checks the FAT32 volume on the device and optionally repairs it.
+pub fn fsck(device: &mut dyn BlockDevice, mode: FsckMode) -> Result<FsckReport, FatError> {
+    let mut volume = FatVolume::open(device)?;
+    let mut problems = Vec::new();
+
+    let first = volume.read_fat_copy(0)?;
+    for copy in 1..volume.bpb().fat_count {
+        if volume.read_fat_copy(copy)? != first {
+            problems.push(FsckProblem::FatMismatch { copy });
+        }
+    }
+    let mismatch = !problems.is_empty();
+
+    let mut checker = Checker {
+        owner: vec![0; volume.bpb().max_cluster() as usize],
+        volume: &mut volume,
+        problems,
+        dirty: false,
+    };
+    checker.check_tree()?;
+    checker.free_lost_chains();
+
+    let Checker { problems, dirty, .. } = checker;
+    let repaired = mode == FsckMode::Commit && (dirty || mismatch);
+    if repaired {
+        volume.write_fat()?;
+    }
+
+    Ok(FsckReport { problems, repaired })
+}
+
+/// Builds a small FAT32 image with one cluster per sector.
+///
+/// The root directory (cluster 2) holds the file "A" (clusters 3 and 4, 1000 bytes) and the
+/// directory "SUB" (cluster 5) with the file "B" (cluster 6, 10 bytes).
+#[cfg(test)]
+fn test_image() -> crate::kernel_components::drivers::storage::RamDisk {
+    use crate::kernel_components::drivers::storage::{RamDisk, SECTOR_SIZE};
+    const RESERVED: usize = 32;
+    const CLUSTERS: usize = 64;
+
+    let mut image = vec![0u8; (RESERVED + 2 + CLUSTERS) * SECTOR_SIZE];
+    let boot = &mut image[..SECTOR_SIZE];
+    boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    boot[13] = 1;
+    boot[14..16].copy_from_slice(&(RESERVED as u16).to_le_bytes());
+    boot[16] = 2;
+    boot[32..36].copy_from_slice(&((RESERVED + 2 + CLUSTERS) as u32).to_le_bytes());
+    boot[36..40].copy_from_slice(&1u32.to_le_bytes());
+    boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+    boot[510] = 0x55;
+    boot[511] = 0xaa;
+
+    let fat: [u32; 7] = [0x0fff_fff8, 0x0fff_ffff, FAT_EOC, 4, FAT_EOC, FAT_EOC, FAT_EOC];
+    for copy in 0..2 {
+        let base = (RESERVED + copy) * SECTOR_SIZE;
+        for (i, entry) in fat.iter().enumerate() {
+            image[base + i * 4..base + i * 4 + 4].copy_from_slice(&entry.to_le_bytes());
+        }
+    }
+
+    let mut dir_entry = |cluster: usize, index: usize, name: &[u8; 11], attr: u8, first: u32, size: u32| {
+        let off = (RESERVED + 2 + cluster - 2) * SECTOR_SIZE + index * 32;
+        image[off..off + 11].copy_from_slice(name);
+        image[off + 11] = attr;
+        image[off + 26..off + 28].copy_from_slice(&(first as u16).to_le_bytes());
+        image[off + 28..off + 32].copy_from_slice(&size.to_le_bytes());
+    };
+    dir_entry(2, 0, b"A          ", 0, 3, 1000);
+    dir_entry(2, 1, b"SUB        ", 0x10, 5, 0);
+    dir_entry(5, 0, b".          ", 0x10, 5, 0);
+    dir_entry(5, 1, b"..         ", 0x10, 0, 0);
+    dir_entry(5, 2, b"B          ", 0, 6, 10);
+
+    RamDisk::from_image(image, false)
+}
+
+#[test_case]
+fn fsck_repairs_corrupted_image() {
+    let mut disk = test_image();
+    assert!(fsck(&mut disk, FsckMode::DryRun).unwrap().is_clean());
+
+    // Corrupting the first FAT copy: B continues into the chain of A and a lost chain appears.
+    let mut fat = disk.read_blocking(32, 1).unwrap();
+    fat[6 * 4..6 * 4 + 4].copy_from_slice(&4u32.to_le_bytes());
+    fat[10 * 4..10 * 4 + 4].copy_from_slice(&11u32.to_le_bytes());
+    fat[11 * 4..11 * 4 + 4].copy_from_slice(&FAT_EOC.to_le_bytes());
+    disk.write_blocking(32, fat).unwrap();
+
+    let expected = [
+        FsckProblem::FatMismatch { copy: 1 },
+        FsckProblem::CrossLinked { cluster: 4, first: 3, second: 6 },
+        FsckProblem::LostChain { start: 10, length: 2 },
+    ];
+
+    let report = fsck(&mut disk, FsckMode::DryRun).unwrap();
+    assert_eq!(report.problems, expected);
+    assert!(!report.repaired);
+    // Dry run must not write anything.
+    assert_eq!(fsck(&mut disk, FsckMode::DryRun).unwrap().problems, expected);
+
+    let report = fsck(&mut disk, FsckMode::Commit).unwrap();
+    assert_eq!(report.problems, expected);
+    assert!(report.repaired);
+    assert!(fsck(&mut disk, FsckMode::DryRun).unwrap().is_clean());
+    assert_eq!(disk.read_blocking(32, 1).unwrap(), disk.read_blocking(33, 1).unwrap());
+}
@@ -0,0 +1,285 @@
+/// On-disk structures of the FAT32 filesystem.
+///
+/// Provides parsing of the BIOS parameter block, access to the file allocation table and
+/// directory entries. Only volumes with 512 byte sectors are supported.
+
+use alloc::vec::Vec;
+
+use crate::kernel_components::drivers::storage::{BlockDevice, BlockError, SECTOR_SIZE};
+
+/// Free cluster.
+pub const FAT_FREE: u32 = 0;
+/// Cluster marked as bad.
+pub const FAT_BAD: u32 = 0x0fff_fff7;
+/// The smallest value that marks the end of the chain.
+pub const FAT_EOC: u32 = 0x0fff_fff8;
+/// Only the lower 28 bits of the entry are used.
+pub const FAT_MASK: u32 = 0x0fff_ffff;
+/// Size of the directory entry.
+pub const DIR_ENTRY_SIZE: usize = 32;
+
+/// Maximal amount of sectors read with a single request.
+const MAX_SECTORS_PER_REQUEST: u32 = 8;
+
+/// Error type for FAT filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatError {
+    /// The underlying device failed.
+    Io(BlockError),
+    /// The boot sector does not describe a FAT32 volume.
+    NotFat32,
+    /// The BIOS parameter block holds inconsistent values.
+    InvalidBpb,
+}
+
+impl From<BlockError> for FatError {
+    fn from(value: BlockError) -> Self {
+        FatError::Io(value)
+    }
+}
+
+/// BIOS parameter block of FAT32 volume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bpb {
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fat_count: u8,
+    pub total_sectors: u32,
+    /// Size of one FAT copy in sectors.
+    pub fat_size: u32,
+    pub root_cluster: u32,
+}
+
+impl Bpb {
+    /// Parses the boot sector.
+    pub fn parse(sector: &[u8]) -> Result<Self, FatError> {
+        let u16_at = |off: usize| u16::from_le_bytes([sector[off], sector[off + 1]]);
+        let u32_at = |off: usize| u32::from_le_bytes(sector[off..off + 4].try_into().unwrap());
+
+        if sector.len() < SECTOR_SIZE || sector[510] != 0x55 || sector[511] != 0xaa {
+            return Err(FatError::NotFat32)
+        }
+        // FAT12 and FAT16 have fixed root directory and 16-bit FAT size.
+        if u16_at(17) != 0 || u16_at(22) != 0 {
+            return Err(FatError::NotFat32)
+        }
+
+        let bpb = Self {
+            sectors_per_cluster: sector[13],
+            reserved_sectors: u16_at(14),
+            fat_count: sector[16],
+            total_sectors: match u16_at(19) {
+                0 => u32_at(32),
+                total => total as u32,
+            },
+            fat_size: u32_at(36),
+            root_cluster: u32_at(44),
+        };
+
+        // The FATs must not wrap around the 32-bit sector numbers.
+        let data_start = (bpb.fat_count as u32).checked_mul(bpb.fat_size)
+            .and_then(|fats| fats.checked_add(bpb.reserved_sectors as u32));
+        let valid = u16_at(11) as usize == SECTOR_SIZE
+            && bpb.sectors_per_cluster.is_power_of_two()
+            && bpb.reserved_sectors != 0
+            && bpb.fat_count != 0
+            && bpb.fat_size != 0
+            && data_start.is_some_and(|start| start < bpb.total_sectors)
+            && (2..bpb.max_cluster()).contains(&bpb.root_cluster)
+            // Every data cluster must have its entry within the FAT.
+            && bpb.max_cluster() as usize <= bpb.fat_size as usize * SECTOR_SIZE / 4;
+
+        match valid {
+            true => Ok(bpb),
+            false => Err(FatError::InvalidBpb),
+        }
+    }
+
+    /// First sector of the data region.
+    pub fn first_data_sector(&self) -> u32 {
+        self.reserved_sectors as u32 + self.fat_count as u32 * self.fat_size
+    }
+
+    /// Amount of data clusters.
+    pub fn cluster_count(&self) -> u32 {
+        (self.total_sectors - self.first_data_sector()) / self.sectors_per_cluster as u32
+    }
+
+    /// The first cluster number which is past the end of the volume.
+    pub fn max_cluster(&self) -> u32 {
+        self.cluster_count() + 2
+    }
+
+    /// Size of a cluster in bytes.
+    pub fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    /// First sector of the cluster.
+    pub fn cluster_lba(&self, cluster: u32) -> u64 {
+        self.first_data_sector() as u64 + (cluster - 2) as u64 * self.sectors_per_cluster as u64
+    }
+}
+
+/// Short directory entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: [u8; 11],
+    pub attributes: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+}
+
+impl DirEntry {
+    pub const ATTR_VOLUME_ID: u8 = 0x08;
+    pub const ATTR_DIRECTORY: u8 = 0x10;
+    /// Long file name entries set all of the lower four attribute bits.
+    pub const ATTR_LONG_NAME: u8 = 0x0f;
+
+    /// Parses the entry. Returns None for free, deleted, long name and volume label entries.
+    pub fn parse(raw: &[u8]) -> Option<Self> {
+        if raw[0] == 0x00 || raw[0] == 0xe5 || raw[11] & Self::ATTR_LONG_NAME == Self::ATTR_LONG_NAME
+            || raw[11] & Self::ATTR_VOLUME_ID != 0
+        {
+            return None
+        }
+
+        Some(Self {
+            name: raw[..11].try_into().unwrap(),
+            attributes: raw[11],
+            first_cluster: (u16::from_le_bytes([raw[20], raw[21]]) as u32) << 16
+                | u16::from_le_bytes([raw[26], raw[27]]) as u32,
+            size: u32::from_le_bytes(raw[28..32].try_into().unwrap()),
+        })
+    }
+
+    /// Returns true for directories.
+    pub fn is_dir(&self) -> bool {
+        self.attributes & Self::ATTR_DIRECTORY != 0
+    }
+
+    /// Returns true for the '.' and '..' entries.
+    pub fn is_dot(&self) -> bool {
+        self.name[0] == b'.'
+    }
+}
+
+/// Mounted FAT32 volume.
+///
+/// The first copy of the FAT is kept in memory. Changes are only written back with write_fat.
+pub struct FatVolume<'d> {
+    device: &'d mut dyn BlockDevice,
+    bpb: Bpb,
+    fat: Vec<u32>,
+}
+
+impl<'d> FatVolume<'d> {
+    /// Reads the boot sector and the first FAT copy of the volume.
+    pub fn open(device: &'d mut dyn BlockDevice) -> Result<Self, FatError> {
+        let boot = device.read_blocking(0, 1)?;
+        let bpb = Bpb::parse(&boot)?;
+
+        let mut volume = Self { device, bpb, fat: Vec::new() };
+        volume.fat = volume.read_fat_copy(0)?;
+        Ok(volume)
+    }
+
+    /// Returns the BIOS parameter block.
+    pub fn bpb(&self) -> &Bpb {
+        &self.bpb
+    }
+
+    /// Reads the provided FAT copy. Entries past the last cluster are dropped.
+    pub fn read_fat_copy(&mut self, copy: u8) -> Result<Vec<u32>, FatError> {
+        let lba = self.bpb.reserved_sectors as u64 + copy as u64 * self.bpb.fat_size as u64;
+        let raw = self.read_sectors(lba, self.bpb.fat_size)?;
+
+        Ok(raw.chunks_exact(4)
+            .take(self.bpb.max_cluster() as usize)
+            .map(|e| u32::from_le_bytes(e.try_into().unwrap()) & FAT_MASK)
+            .collect())
+    }
+
+    /// Returns the FAT entry of the cluster.
+    pub fn entry(&self, cluster: u32) -> u32 {
+        self.fat[cluster as usize]
+    }
+
+    /// Changes the FAT entry of the cluster in memory.
+    pub fn set_entry(&mut self, cluster: u32, value: u32) {
+        self.fat[cluster as usize] = value & FAT_MASK;
+    }
+
+    /// Writes the in-memory FAT into every FAT copy on the device.
+    ///
+    /// The upper reserved bits of every entry are preserved.
+    pub fn write_fat(&mut self) -> Result<(), FatError> {
+        for copy in 0..self.bpb.fat_count {
+            let lba = self.bpb.reserved_sectors as u64 + copy as u64 * self.bpb.fat_size as u64;
+            let mut raw = self.read_sectors(lba, self.bpb.fat_size)?;
+
+            for (entry, value) in raw.chunks_exact_mut(4).zip(self.fat.iter()) {
+                let old = u32::from_le_bytes((&*entry).try_into().unwrap());
+                entry.copy_from_slice(&(old & !FAT_MASK | value).to_le_bytes());
+            }
+            self.write_sectors(lba, raw)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the whole cluster.
+    pub fn read_cluster(&mut self, cluster: u32) -> Result<Vec<u8>, FatError> {
+        self.read_sectors(self.bpb.cluster_lba(cluster), self.bpb.sectors_per_cluster as u32)
+    }
+
+    /// Reads the directory entries stored within the cluster.
+    ///
+    /// The second value is false if the end of the directory was reached.
+    pub fn read_dir_cluster(&mut self, cluster: u32) -> Result<(Vec<DirEntry>, bool), FatError> {
+        let data = self.read_cluster(cluster)?;
+        let mut entries = Vec::new();
+
+        for raw in data.chunks_exact(DIR_ENTRY_SIZE) {
+            if raw[0] == 0x00 {
+                return Ok((entries, false))
+            }
+            entries.extend(DirEntry::parse(raw));
+        }
+        Ok((entries, true))
+    }
+
+    fn read_sectors(&mut self, lba: u64, count: u32) -> Result<Vec<u8>, FatError> {
+        let mut data = Vec::with_capacity(count as usize * SECTOR_SIZE);
+        for start in (0..count).step_by(MAX_SECTORS_PER_REQUEST as usize) {
+            let n = (count - start).min(MAX_SECTORS_PER_REQUEST);
+            data.extend_from_slice(&self.device.read_blocking(lba + start as u64, n)?);
+        }
+        Ok(data)
+    }
+
+    fn write_sectors(&mut self, lba: u64, data: Vec<u8>) -> Result<(), FatError> {
+        let chunk = MAX_SECTORS_PER_REQUEST as usize * SECTOR_SIZE;
+        for (i, part) in data.chunks(chunk).enumerate() {
+            self.device.write_blocking(lba + (i * chunk / SECTOR_SIZE) as u64, part.to_vec())?;
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn bpb_rejects_overflowing_fat_region() {
+    let mut sector = [0u8; SECTOR_SIZE];
+    sector[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    sector[13] = 1;
+    sector[14..16].copy_from_slice(&32u16.to_le_bytes());
+    sector[16] = 2;
+    sector[32..36].copy_from_slice(&0x1_0000u32.to_le_bytes());
+    sector[36..40].copy_from_slice(&0x200u32.to_le_bytes());
+    sector[44..48].copy_from_slice(&2u32.to_le_bytes());
+    sector[510..512].copy_from_slice(&[0x55, 0xaa]);
+    assert_eq!(Bpb::parse(&sector).map(|bpb| bpb.first_data_sector()), Ok(32 + 2 * 0x200));
+
+    // Two FATs of 2^31 sectors wrap around to the reserved sectors.
+    sector[36..40].copy_from_slice(&0x8000_0000u32.to_le_bytes());
+    assert_eq!(Bpb::parse(&sector), Err(FatError::InvalidBpb));
+}
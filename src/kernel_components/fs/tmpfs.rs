@@ -0,0 +1,124 @@
+/// In-memory filesystem.
+///
+/// Holds files in the kernel heap until they are removed or the machine is reset. The namespace
+/// is flat, names are unique and may not contain '/'. The total size of the files is limited, so
+/// a runaway upload can not exhaust the heap.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::error::Error;
+
+use crate::kernel_components::sync::Mutex;
+
+/// Maximal total size of the files in the global filesystem.
+pub const TMPFS_SIZE_LIMIT: usize = 16 * 1024 * 1024;
+/// Maximal length of a name in bytes.
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Global in-memory filesystem.
+pub static TMPFS: Mutex<TmpFs> = Mutex::new(TmpFs::new(TMPFS_SIZE_LIMIT));
+
+/// Error type for the in-memory filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TmpfsError {
+    /// No file has the name.
+    NotFound,
+    /// The name is empty, too long or contains '/' or NUL.
+    InvalidName,
+    /// The file does not fit into the size limit.
+    NoSpace,
+}
+
+impl Display for TmpfsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "No such file."),
+            Self::InvalidName => write!(f, "Invalid file name."),
+            Self::NoSpace => write!(f, "No space left in tmpfs."),
+        }
+    }
+}
+
+impl Error for TmpfsError {}
+
+/// Flat set of named files kept in memory.
+#[derive(Debug)]
+pub struct TmpFs {
+    files: BTreeMap<String, Vec<u8>>,
+    used: usize,
+    limit: usize,
+}
+
+impl TmpFs {
+    /// Creates an empty filesystem, whose files may take at most the limit in bytes.
+    pub const fn new(limit: usize) -> Self {
+        Self { files: BTreeMap::new(), used: 0, limit }
+    }
+
+    /// Stores the file, replacing the one with the same name.
+    pub fn write(&mut self, name: &str, data: Vec<u8>) -> Result<(), TmpfsError> {
+        if !is_valid_name(name) {
+            return Err(TmpfsError::InvalidName)
+        }
+        let replaced = self.files.get(name).map_or(0, Vec::len);
+        if self.used - replaced + data.len() > self.limit {
+            return Err(TmpfsError::NoSpace)
+        }
+        self.used = self.used - replaced + data.len();
+        self.files.insert(String::from(name), data);
+        Ok(())
+    }
+
+    /// Returns the content of the file.
+    pub fn read(&self, name: &str) -> Result<&[u8], TmpfsError> {
+        self.files.get(name).map(Vec::as_slice).ok_or(TmpfsError::NotFound)
+    }
+
+    /// Removes the file and returns it's content.
+    pub fn remove(&mut self, name: &str) -> Result<Vec<u8>, TmpfsError> {
+        let data = self.files.remove(name).ok_or(TmpfsError::NotFound)?;
+        self.used -= data.len();
+        Ok(data)
+    }
+
+    /// Returns an iterator over the names and sizes of the files, sorted by name.
+    pub fn files(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.files.iter().map(|(name, data)| (name.as_str(), data.len()))
+    }
+
+    /// Returns the total size of the files in bytes.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Returns the amount of bytes left for files.
+    pub fn available(&self) -> usize {
+        self.limit - self.used
+    }
+}
+
+/// Checks if the name may be given to a file.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LEN && !name.contains(['/', '\0'])
+}
+
+#[test_case]
+fn tmpfs_keeps_files_within_the_limit() {
+    use alloc::vec;
+
+    let mut fs = TmpFs::new(8);
+    assert_eq!(fs.write("a/b", vec![0]), Err(TmpfsError::InvalidName));
+    fs.write("init", vec![1; 6]).unwrap();
+    assert_eq!(fs.write("data", vec![2; 3]), Err(TmpfsError::NoSpace));
+
+    // Replacing a file only needs the space of the difference.
+    fs.write("init", vec![3; 8]).unwrap();
+    assert_eq!((fs.read("init"), fs.used(), fs.available()), (Ok(&[3; 8][..]), 8, 0));
+
+    assert_eq!(fs.remove("init").map(|data| data.len()), Ok(8));
+    fs.write("data", vec![2; 3]).unwrap();
+    assert!(fs.files().eq([("data", 3)]));
+    assert_eq!(fs.read("init"), Err(TmpfsError::NotFound));
+}
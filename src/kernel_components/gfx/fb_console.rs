@@ -0,0 +1,250 @@
+//! Text console on a linear framebuffer.
+//!
+//! Rendering glyphs pixel by pixel is expensive at high resolutions, so the console never
+//! re-renders the whole screen while logging:
+//!
+//! - The text is kept in a grid of cells. Writing only updates cells and grows the dirty
+//! rectangle, which is rendered on [`FbConsole::flush`].
+//! - Scrolling shifts the cells and is only counted. The flush moves the already rendered rows
+//! with a single memmove of the framebuffer for all accumulated lines, then renders the dirty
+//! cells, which usually are the new bottom lines. If the whole screen scrolled away, the memmove
+//! is skipped and everything is rendered once.
+//!
+//! Cells keep the style they were written with. Colors are mapped to pixels by the framebuffer,
+//! while bold text, which the VGA text mode only shows brighter, is also drawn with thicker
+//! strokes.
+
+use alloc::vec::Vec;
+use alloc::vec;
+use core::fmt;
+
+use super::framebuffer::{LinearFramebuffer, Rect};
+use crate::kernel_components::vga_buffer::{Attributes, Color, Style};
+
+/// Bitmap font used by the console.
+pub trait Font {
+    /// Width of a glyph in pixels.
+    fn width(&self) -> usize;
+    /// Height of a glyph in pixels.
+    fn height(&self) -> usize;
+    /// Returns the bitmap of the glyph.
+    ///
+    /// The bitmap consists of height rows, each row takes width / 8 bytes rounded up, the most
+    /// significant bit is the leftmost pixel.
+    fn glyph(&self, c: char) -> &[u8];
+}
+
+/// A single character cell of the console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    c: char,
+    style: Style,
+}
+
+/// Text console rendering into a linear framebuffer.
+pub struct FbConsole<F: Font> {
+    fb: LinearFramebuffer,
+    font: F,
+    cells: Vec<Cell>,
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    style: Style,
+    /// Cells changed since the last flush.
+    dirty: Option<Rect>,
+    /// Lines scrolled since the last flush.
+    scrolled: usize,
+}
+
+impl<F: Font> FbConsole<F> {
+    /// Creates a new console over the whole framebuffer. The screen is cleared on the first flush.
+    pub fn new(fb: LinearFramebuffer, font: F) -> Self {
+        let columns = fb.width() / font.width();
+        let rows = fb.height() / font.height();
+        let style = Style::new(Color::WHITE, Color::BLACK);
+
+        Self {
+            fb,
+            font,
+            cells: vec![Cell { c: ' ', style }; columns * rows],
+            columns,
+            rows,
+            column: 0,
+            row: 0,
+            style,
+            dirty: Some(Rect::new(0, 0, columns, rows)),
+            scrolled: 0,
+        }
+    }
+
+    /// Returns the size of the console in cells as columns and rows.
+    pub fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    /// Changes the style of the following output.
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    /// Writes a single character. New lines move the cursor to the next line.
+    pub fn write_char(&mut self, c: char) {
+        if c == '\n' {
+            return self.new_line()
+        }
+        if self.column >= self.columns {
+            self.new_line();
+        }
+
+        self.cells[self.row * self.columns + self.column] = Cell { c, style: self.style };
+        self.mark(Rect::new(self.column, self.row, 1, 1));
+        self.column += 1;
+    }
+
+    /// Moves the cursor back by one cell within the line and clears that cell.
+    pub fn backspace(&mut self) {
+        if self.column == 0 {
+            return
+        }
+        self.column -= 1;
+        self.cells[self.row * self.columns + self.column] = self.blank();
+        self.mark(Rect::new(self.column, self.row, 1, 1));
+    }
+
+    /// Moves the cursor to the beginning of the line.
+    pub fn carriage_return(&mut self) {
+        self.column = 0;
+    }
+
+    /// Clears the screen and moves the cursor to the top left corner.
+    pub fn clear(&mut self) {
+        let blank = self.blank();
+        self.cells.fill(blank);
+        self.column = 0;
+        self.row = 0;
+        self.scrolled = 0;
+        self.redraw();
+    }
+
+    /// Renders the whole screen on the next flush.
+    pub fn redraw(&mut self) {
+        self.dirty = Some(Rect::new(0, 0, self.columns, self.rows));
+    }
+
+    /// Renders the pending changes into the framebuffer.
+    pub fn flush(&mut self) {
+        if self.scrolled >= self.rows {
+            self.redraw();
+        } else if self.scrolled != 0 {
+            let background = self.fb.color(self.style.background);
+            self.fb.scroll_up(self.scrolled * self.font.height(), background);
+        }
+        self.scrolled = 0;
+
+        if let Some(dirty) = self.dirty.take() {
+            for row in dirty.y..dirty.y + dirty.height {
+                for column in dirty.x..dirty.x + dirty.width {
+                    self.render(column, row);
+                }
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return
+        }
+
+        // Cells move up by one row, so does the part of the dirty rectangle which stays visible.
+        let blank = self.blank();
+        self.cells.copy_within(self.columns.., 0);
+        let last = (self.rows - 1) * self.columns;
+        self.cells[last..].fill(blank);
+        self.scrolled += 1;
+
+        self.dirty = self.dirty.and_then(|dirty| match dirty.y {
+            0 if dirty.height <= 1 => None,
+            0 => Some(Rect { height: dirty.height - 1, ..dirty }),
+            y => Some(Rect { y: y - 1, ..dirty }),
+        });
+        self.mark(Rect::new(0, self.rows - 1, self.columns, 1));
+    }
+
+    fn mark(&mut self, rect: Rect) {
+        self.dirty = Some(self.dirty.map_or(rect, |dirty| dirty.union(rect)));
+    }
+
+    fn blank(&self) -> Cell {
+        Cell { c: ' ', style: self.style }
+    }
+
+    /// Renders a single cell.
+    fn render(&mut self, column: usize, row: usize) {
+        let cell = self.cells[row * self.columns + column];
+        let foreground = self.fb.color(cell.style.foreground);
+        let background = self.fb.color(cell.style.background);
+        let (width, height) = (self.font.width(), self.font.height());
+        let (x, y) = (column * width, row * height);
+
+        if cell.c == ' ' {
+            return self.fb.fill_rect(Rect::new(x, y, width, height), background)
+        }
+
+        // Bold text is emboldened by also setting the pixel right of every set pixel.
+        let bold = Attributes::BOLD.is_in(cell.style.attributes.bits());
+        let stride = width.div_ceil(8);
+        let glyph = self.font.glyph(cell.c);
+        for dy in 0..height {
+            let bits = glyph.get(dy * stride..(dy + 1) * stride).unwrap_or(&[]);
+            let bit = |dx: usize| bits.get(dx / 8).is_some_and(|byte| byte & (0x80 >> (dx % 8)) != 0);
+            for dx in 0..width {
+                let set = bit(dx) || (bold && dx > 0 && bit(dx - 1));
+                self.fb.put_pixel(x + dx, y + dy, if set { foreground } else { background });
+            }
+        }
+    }
+}
+
+impl<F: Font> fmt::Write for FbConsole<F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.chars().for_each(|c| self.write_char(c));
+        Ok(())
+    }
+}
+
+#[test_case]
+fn scrolling_moves_rendered_rows() {
+    use crate::kernel_components::boot::info::Framebuffer;
+
+    struct Block;
+    impl Font for Block {
+        fn width(&self) -> usize { 8 }
+        fn height(&self) -> usize { 8 }
+        fn glyph(&self, _: char) -> &[u8] { &[0xff; 8] }
+    }
+
+    let mut memory = [0u32; 16 * 16];
+    let info = Framebuffer {
+        addr: 0, pitch: 64, width: 16, height: 16, bpp: 32,
+        red: (16, 8), green: (8, 8), blue: (0, 8),
+    };
+    let fb = unsafe { LinearFramebuffer::new(memory.as_mut_ptr() as *mut u8, info) };
+    let mut console = FbConsole::new(fb, Block);
+    assert_eq!(console.size(), (2, 2));
+
+    "a \n b".chars().for_each(|c| console.write_char(c));
+    console.flush();
+    assert_eq!(console.dirty, None);
+
+    "\nc".chars().for_each(|c| console.write_char(c));
+    assert_eq!((console.scrolled, console.dirty), (1, Some(Rect::new(0, 1, 2, 1))));
+    console.flush();
+    drop(console);
+
+    let pixel = |x: usize, y: usize| memory[y * 16 + x];
+    assert_eq!((pixel(0, 0), pixel(8, 0)), (0, 0xffffff));
+    assert_eq!((pixel(0, 8), pixel(8, 8)), (0xffffff, 0));
+}
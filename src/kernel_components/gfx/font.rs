@@ -0,0 +1,296 @@
+//! PC Screen Fonts of the framebuffer console.
+//!
+//! Both versions of the PSF format are understood. A PSF1 font has 256 or 512 glyphs, each 8
+//! pixels wide, while a PSF2 font describes the size and the amount of it's glyphs in the header.
+//! Either may end with a unicode table, which lists the code points each glyph shows. Without the
+//! table, glyphs are indexed by the code point directly. Fonts are parsed in const context, so a
+//! broken embedded font fails the build instead of the boot.
+//!
+//! The built-in font covers the first 256 code points (ASCII and Latin-1) with 8x16 glyphs, the
+//! same cell size as the VGA text mode, so the console shows the same bytes the VGA writer prints.
+//! It was rasterized from DejaVu Sans Mono Bold, whose license allows embedding it. The table also
+//! maps typographic quotes and dashes to their ASCII look-alikes. Characters without a glyph are
+//! shown as U+FFFD if the font has it, or as '?' otherwise.
+
+use core::fmt::Display;
+use core::error::Error;
+
+use super::fb_console::Font;
+
+/// Magic bytes of a PSF1 font.
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+/// Magic number of a PSF2 font.
+const PSF2_MAGIC: u32 = 0x864a_b572;
+/// PSF1 font with 512 glyphs instead of 256.
+const PSF1_MODE512: u8 = 1 << 0;
+/// PSF1 font followed by a unicode table.
+const PSF1_MODEHASTAB: u8 = 1 << 1;
+/// PSF1 font with sequences in the unicode table, which implies the table.
+const PSF1_MODESEQ: u8 = 1 << 2;
+/// PSF2 font followed by a unicode table.
+const PSF2_HAS_UNICODE_TABLE: u32 = 1 << 0;
+
+/// Code point of a glyph without a mapping.
+const UNMAPPED: u16 = u16::MAX;
+/// Table entry ending the code points of a glyph.
+const TERMINATOR: u32 = u32::MAX;
+/// Table entry starting a sequence of code points shown by a single glyph.
+const SEQUENCE: u32 = u32::MAX - 1;
+
+/// Font used by the console of the screen logger.
+pub static DEFAULT_FONT: PsfFont = match PsfFont::parse(include_bytes!("font8x16.psf")) {
+    Ok(font) => font,
+    Err(_) => panic!("The built-in font is not a valid PSF font."),
+};
+
+/// Custom error type for parsing PSF fonts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsfError {
+    /// Neither the PSF1 nor the PSF2 magic was found.
+    BadMagic,
+    /// The font ends before all glyphs.
+    Truncated,
+    /// The glyph size does not match the width and height, or the font has no glyphs.
+    InvalidGlyphs,
+}
+
+impl Display for PsfError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "The data is not a PSF font."),
+            Self::Truncated => write!(f, "The font is truncated."),
+            Self::InvalidGlyphs => write!(f, "The font has no valid glyphs."),
+        }
+    }
+}
+
+impl Error for PsfError {}
+
+/// Version of a PSF font, which decides the encoding of the unicode table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PsfVersion {
+    /// Table entries are 16 bit code points.
+    Psf1,
+    /// Table entries are UTF-8 encoded.
+    Psf2,
+}
+
+/// Font in the PC Screen Font format.
+#[derive(Debug, Clone, Copy)]
+pub struct PsfFont {
+    version: PsfVersion,
+    width: usize,
+    height: usize,
+    glyph_size: usize,
+    glyphs: &'static [u8],
+    unicode: Option<&'static [u8]>,
+    /// Glyphs of the first 256 code points, which are looked up without walking the table.
+    latin1: [u16; 256],
+    /// Glyph shown for characters the font does not have.
+    fallback: usize,
+}
+
+impl PsfFont {
+    /// Parses a PSF1 or PSF2 font.
+    pub const fn parse(data: &'static [u8]) -> Result<Self, PsfError> {
+        let (version, width, height, glyph_size, count, header, has_table) =
+            if data.len() >= 4 && data[0] == PSF1_MAGIC[0] && data[1] == PSF1_MAGIC[1] {
+                let mode = data[2];
+                let count = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+                let has_table = mode & (PSF1_MODEHASTAB | PSF1_MODESEQ) != 0;
+                (PsfVersion::Psf1, 8, data[3] as usize, data[3] as usize, count, 4, has_table)
+            } else if data.len() >= 32 && read_u32(data, 0) == PSF2_MAGIC {
+                let has_table = read_u32(data, 12) & PSF2_HAS_UNICODE_TABLE != 0;
+                let (count, glyph_size) = (read_u32(data, 16) as usize, read_u32(data, 20) as usize);
+                let (height, width) = (read_u32(data, 24) as usize, read_u32(data, 28) as usize);
+                (PsfVersion::Psf2, width, height, glyph_size, count, read_u32(data, 8) as usize, has_table)
+            } else {
+                return Err(PsfError::BadMagic)
+            };
+
+        if count == 0 || width == 0 || glyph_size != width.div_ceil(8) * height {
+            return Err(PsfError::InvalidGlyphs)
+        }
+        let Some(size) = count.checked_mul(glyph_size) else { return Err(PsfError::Truncated) };
+        if header > data.len() || size > data.len() - header {
+            return Err(PsfError::Truncated)
+        }
+        let (glyphs, table) = data.split_at(header).1.split_at(size);
+
+        let mut font = Self {
+            version,
+            width,
+            height,
+            glyph_size,
+            glyphs,
+            unicode: if has_table { Some(table) } else { None },
+            latin1: [UNMAPPED; 256],
+            fallback: 0,
+        };
+
+        let mut c = 0;
+        while c < 256 {
+            if font.unicode.is_none() && c < count {
+                font.latin1[c] = c as u16;
+            }
+            c += 1;
+        }
+        if let Some(table) = font.unicode {
+            let (mut pos, mut glyph, mut in_sequence) = (0, 0, false);
+            while pos < table.len() && glyph < count {
+                let (entry, next) = read_entry(version, table, pos);
+                pos = next;
+                match entry {
+                    TERMINATOR => (glyph, in_sequence) = (glyph + 1, false),
+                    SEQUENCE => in_sequence = true,
+                    c if !in_sequence && c < 256 && font.latin1[c as usize] == UNMAPPED => {
+                        font.latin1[c as usize] = glyph as u16;
+                    },
+                    _ => (),
+                }
+            }
+        }
+
+        font.fallback = match font.index(char::REPLACEMENT_CHARACTER) {
+            Some(glyph) => glyph,
+            None => match font.index('?') {
+                Some(glyph) => glyph,
+                None => 0,
+            },
+        };
+        Ok(font)
+    }
+
+    /// Returns the amount of glyphs.
+    pub const fn glyph_count(&self) -> usize {
+        self.glyphs.len() / self.glyph_size
+    }
+
+    /// Checks if the font has a glyph of the character.
+    pub const fn has_glyph(&self, c: char) -> bool {
+        self.index(c).is_some()
+    }
+
+    /// Returns the index of the glyph showing the character.
+    const fn index(&self, c: char) -> Option<usize> {
+        let c = c as u32;
+        if c < 256 {
+            return match self.latin1[c as usize] {
+                UNMAPPED => None,
+                glyph => Some(glyph as usize),
+            }
+        }
+
+        let Some(table) = self.unicode else {
+            return if (c as usize) < self.glyph_count() { Some(c as usize) } else { None }
+        };
+        let (mut pos, mut glyph, mut in_sequence) = (0, 0, false);
+        while pos < table.len() && glyph < self.glyph_count() {
+            let (entry, next) = read_entry(self.version, table, pos);
+            pos = next;
+            match entry {
+                TERMINATOR => (glyph, in_sequence) = (glyph + 1, false),
+                SEQUENCE => in_sequence = true,
+                entry if !in_sequence && entry == c => return Some(glyph),
+                _ => (),
+            }
+        }
+        None
+    }
+}
+
+impl Font for PsfFont {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn glyph(&self, c: char) -> &[u8] {
+        let glyph = self.index(c).unwrap_or(self.fallback);
+        &self.glyphs[glyph * self.glyph_size..(glyph + 1) * self.glyph_size]
+    }
+}
+
+/// Reads a little endian 32 bit value.
+const fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Decodes the unicode table entry at the position, returning it with the position of the next
+/// one. Malformed entries decode to a code point no character has.
+const fn read_entry(version: PsfVersion, table: &[u8], pos: usize) -> (u32, usize) {
+    const INVALID: u32 = 0x11_0000;
+
+    match version {
+        PsfVersion::Psf1 => {
+            if pos + 1 >= table.len() {
+                return (TERMINATOR, table.len())
+            }
+            match u16::from_le_bytes([table[pos], table[pos + 1]]) {
+                0xffff => (TERMINATOR, pos + 2),
+                0xfffe => (SEQUENCE, pos + 2),
+                c => (c as u32, pos + 2),
+            }
+        },
+        PsfVersion::Psf2 => {
+            let (mut c, len) = match table[pos] {
+                0xff => return (TERMINATOR, pos + 1),
+                0xfe => return (SEQUENCE, pos + 1),
+                byte @ 0x00..=0x7f => return (byte as u32, pos + 1),
+                byte @ 0xc0..=0xdf => ((byte & 0x1f) as u32, 2),
+                byte @ 0xe0..=0xef => ((byte & 0x0f) as u32, 3),
+                byte @ 0xf0..=0xf7 => ((byte & 0x07) as u32, 4),
+                _ => return (INVALID, pos + 1),
+            };
+            if pos + len > table.len() {
+                return (INVALID, table.len())
+            }
+            let mut i = 1;
+            while i < len {
+                if table[pos + i] & 0xc0 != 0x80 {
+                    return (INVALID, pos + i)
+                }
+                c = c << 6 | (table[pos + i] & 0x3f) as u32;
+                i += 1;
+            }
+            (c, pos + len)
+        },
+    }
+}
+
+#[test_case]
+fn psf_fonts_map_characters_to_glyphs() {
+    assert_eq!(DEFAULT_FONT.glyph_count(), 256);
+    assert!(DEFAULT_FONT.has_glyph('\u{ff}') && !DEFAULT_FONT.has_glyph('\u{100}'));
+    assert!(DEFAULT_FONT.glyph(' ').iter().all(|&row| row == 0));
+    assert!(DEFAULT_FONT.glyph('A').iter().any(|&row| row != 0));
+    assert_eq!(DEFAULT_FONT.glyph('\u{263a}'), DEFAULT_FONT.glyph('?'));
+    assert_eq!(DEFAULT_FONT.glyph('\u{2019}'), DEFAULT_FONT.glyph('\''));
+
+    // PSF1 font of 256 glyphs, 2 rows each, whose table swaps the first two glyphs and gives
+    // the last one a sequence, which must not map a single character.
+    static PSF1: [u8; 4 + 512 + 14 + 253 * 2] = {
+        let header = [0x36, 0x04, PSF1_MODESEQ, 2, 0x01, 0x02, 0x03, 0x04];
+        let table = [b'B', 0, 0xff, 0xff, b'A', 0, 0xff, 0xff, 0x3a, 0x26, 0xfe, 0xff, b'Z', 0];
+        let mut font = [0xff; 4 + 512 + 14 + 253 * 2];
+        let mut i = 0;
+        while i < table.len() {
+            if i < header.len() {
+                font[i] = header[i];
+            }
+            font[516 + i] = table[i];
+            i += 1;
+        }
+        font
+    };
+    let font = PsfFont::parse(&PSF1).unwrap();
+    assert_eq!((font.width(), font.height(), font.glyph_count()), (8, 2, 256));
+    assert_eq!((font.glyph('A'), font.glyph('B')), (&[0x03, 0x04][..], &[0x01, 0x02][..]));
+    assert!(!font.has_glyph('Z') && font.has_glyph('\u{263a}'));
+
+    assert_eq!(PsfFont::parse(&PSF1[..100]).err(), Some(PsfError::Truncated));
+    assert_eq!(PsfFont::parse(&PSF1[1..]).err(), Some(PsfError::BadMagic));
+}
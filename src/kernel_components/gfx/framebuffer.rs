@@ -0,0 +1,183 @@
+//! Linear framebuffer access.
+//!
+//! A [`LinearFramebuffer`] wraps the memory of a direct RGB framebuffer described by the boot
+//! information [`Framebuffer`]. Drawing is done with whole rectangles and scrolling moves whole
+//! rows of pixels with a single memmove, so the consumers never touch single pixels on hot paths.
+
+use core::ptr;
+
+use crate::kernel_components::boot::info::Framebuffer;
+use crate::kernel_components::vga_buffer::Color;
+
+/// RGB values of the 16 VGA text mode colors.
+pub const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0xaa), (0x00, 0xaa, 0x00), (0x00, 0xaa, 0xaa),
+    (0xaa, 0x00, 0x00), (0xaa, 0x00, 0xaa), (0xaa, 0x55, 0x00), (0xaa, 0xaa, 0xaa),
+    (0x55, 0x55, 0x55), (0x55, 0x55, 0xff), (0x55, 0xff, 0x55), (0x55, 0xff, 0xff),
+    (0xff, 0x55, 0x55), (0xff, 0x55, 0xff), (0xff, 0xff, 0x55), (0xff, 0xff, 0xff),
+];
+
+/// Rectangle in pixels or cells, depending on the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    /// Creates a new rectangle.
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// Returns the smallest rectangle which contains both rectangles.
+    pub fn union(self, other: Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    /// Returns true if the rectangle covers no area.
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+/// Mapped linear framebuffer.
+#[derive(Debug)]
+pub struct LinearFramebuffer {
+    base: *mut u8,
+    info: Framebuffer,
+    /// Bytes per pixel.
+    bytes: usize,
+}
+
+impl LinearFramebuffer {
+    /// Creates a framebuffer over the mapped memory.
+    ///
+    /// # Safety
+    ///
+    /// The base must point to at least pitch * height writable bytes, which are not accessed by
+    /// anything else. Only 16, 24 and 32 bits per pixel are supported.
+    pub unsafe fn new(base: *mut u8, info: Framebuffer) -> Self {
+        debug_assert!(matches!(info.bpp, 16 | 24 | 32));
+        Self { base, info, bytes: info.bpp as usize / 8 }
+    }
+
+    /// Returns the description of the framebuffer.
+    pub fn info(&self) -> &Framebuffer {
+        &self.info
+    }
+
+    /// Returns the width in pixels.
+    pub fn width(&self) -> usize {
+        self.info.width as usize
+    }
+
+    /// Returns the height in pixels.
+    pub fn height(&self) -> usize {
+        self.info.height as usize
+    }
+
+    /// Encodes the RGB value into the pixel format of the framebuffer.
+    pub fn pixel(&self, (r, g, b): (u8, u8, u8)) -> u32 {
+        fn channel(value: u8, (shift, size): (u8, u8)) -> u32 {
+            match size {
+                0 => 0,
+                size => (value as u32 >> 8u8.saturating_sub(size)) << shift,
+            }
+        }
+        channel(r, self.info.red) | channel(g, self.info.green) | channel(b, self.info.blue)
+    }
+
+    /// Decodes the pixel in the format of the framebuffer back into the RGB value.
+    pub fn rgb(&self, pixel: u32) -> (u8, u8, u8) {
+        fn channel(pixel: u32, (shift, size): (u8, u8)) -> u8 {
+            match size {
+                0 => 0,
+                size => ((pixel >> shift & (1 << size.min(8)) - 1) << 8u8.saturating_sub(size)) as u8,
+            }
+        }
+        (channel(pixel, self.info.red), channel(pixel, self.info.green), channel(pixel, self.info.blue))
+    }
+
+    /// Encodes the VGA color into the pixel format of the framebuffer.
+    pub fn color(&self, color: Color) -> u32 {
+        self.pixel(PALETTE[color as usize])
+    }
+
+    /// Writes a single pixel. Pixels outside of the framebuffer are ignored.
+    #[inline]
+    pub fn put_pixel(&mut self, x: usize, y: usize, pixel: u32) {
+        if x < self.width() && y < self.height() {
+            unsafe { self.write_pixel(self.offset(x, y), pixel) }
+        }
+    }
+
+    /// Reads a single pixel. Pixels outside of the framebuffer are read as zero.
+    pub fn get_pixel(&self, x: usize, y: usize) -> u32 {
+        if x >= self.width() || y >= self.height() {
+            return 0
+        }
+        unsafe { self.read_pixel(self.offset(x, y)) }
+    }
+
+    /// Fills the rectangle in pixels. The part outside of the framebuffer is ignored.
+    pub fn fill_rect(&mut self, rect: Rect, pixel: u32) {
+        let right = (rect.x + rect.width).min(self.width());
+        let bottom = (rect.y + rect.height).min(self.height());
+        for y in rect.y..bottom {
+            for x in rect.x..right {
+                unsafe { self.write_pixel(self.offset(x, y), pixel) }
+            }
+        }
+    }
+
+    /// Moves the whole content up by the amount of pixel rows and fills the freed rows at the
+    /// bottom.
+    pub fn scroll_up(&mut self, rows: usize, fill: u32) {
+        let height = self.height();
+        let rows = rows.min(height);
+        let pitch = self.info.pitch as usize;
+
+        // Rows are contiguous, so the whole visible part is moved at once.
+        unsafe {
+            ptr::copy(self.base.add(rows * pitch), self.base, (height - rows) * pitch);
+        }
+        self.fill_rect(Rect::new(0, height - rows, self.width(), rows), fill);
+    }
+
+    fn offset(&self, x: usize, y: usize) -> usize {
+        y * self.info.pitch as usize + x * self.bytes
+    }
+
+    unsafe fn read_pixel(&self, offset: usize) -> u32 {
+        let ptr = self.base.add(offset);
+        match self.bytes {
+            4 => ptr::read_volatile(ptr as *const u32),
+            2 => ptr::read_volatile(ptr as *const u16) as u32,
+            _ => (0..self.bytes).fold(0, |pixel, i| pixel | (ptr::read_volatile(ptr.add(i)) as u32) << (i * 8)),
+        }
+    }
+
+    unsafe fn write_pixel(&mut self, offset: usize, pixel: u32) {
+        let ptr = self.base.add(offset);
+        match self.bytes {
+            4 => ptr::write_volatile(ptr as *mut u32, pixel),
+            2 => ptr::write_volatile(ptr as *mut u16, pixel as u16),
+            _ => pixel.to_le_bytes()[..self.bytes]
+                .iter()
+                .enumerate()
+                .for_each(|(i, &b)| ptr::write_volatile(ptr.add(i), b)),
+        }
+    }
+}
+
+unsafe impl Send for LinearFramebuffer {}
@@ -0,0 +1,308 @@
+//! Hardware inventory report.
+//!
+//! Combines everything the kernel knows about the machine into a single report: the processor as
+//! reported by CPUID, the system description from SMBIOS, ACPI tables and namespace devices, PCI
+//! functions and the drivers bound to them. PCI functions without a driver are marked, so it is
+//! clear which hardware is detected but not used. The report is printed at boot when the
+//! "boot.hwinfo" tunable is set and by the "hwinfo" shell command.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::arch::x86_64 as arch;
+use core::fmt::{self, Display};
+
+use crate::kernel_components::arch_x86_64::acpi::{acpi::ACPISDTHeader, acpi_service, diff::AML_INTERPRETER, RSDT, XSDT};
+use crate::kernel_components::arch_x86_64::hypervisor::Hypervisor;
+use crate::kernel_components::arch_x86_64::pci::{self, PciDevice};
+use crate::kernel_components::arch_x86_64::smbios::SmbiosInfo;
+use crate::kernel_components::drivers::{BoundDevice, DriverInfo, DriverType, DRIVER_MANAGER};
+use crate::kernel_components::memory::MEMORY_MANAGEMENT_UNIT;
+
+/// Class code of PCI bridges. Bridges are handled by the bus enumeration and need no driver.
+const PCI_CLASS_BRIDGE: u8 = 0x06;
+
+/// Feature bits of CPUID leaf 1 (EDX, ECX) and leaf 0x80000001 (EDX) shown in the report.
+const FEATURES_EDX: [(u32, &str); 9] = [
+    (0, "fpu"), (4, "tsc"), (5, "msr"), (6, "pae"), (9, "apic"),
+    (19, "clflush"), (25, "sse"), (26, "sse2"), (28, "htt"),
+];
+const FEATURES_ECX: [(u32, &str); 12] = [
+    (0, "sse3"), (9, "ssse3"), (13, "cx16"), (19, "sse4.1"), (20, "sse4.2"), (21, "x2apic"),
+    (23, "popcnt"), (25, "aes"), (26, "xsave"), (28, "avx"), (30, "rdrand"), (31, "hypervisor"),
+];
+const FEATURES_EXT_EDX: [(u32, &str); 4] = [
+    (20, "nx"), (26, "pdpe1gb"), (27, "rdtscp"), (29, "lm"),
+];
+
+/// Processor identification obtained via CPUID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuInfo {
+    /// Vendor signature, i.e "GenuineIntel".
+    pub vendor: String,
+    /// Brand string, if the processor provides one.
+    pub brand: Option<String>,
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    /// Amount of logical processors in the package.
+    pub logical_cpus: u32,
+    /// Names of the detected features.
+    pub features: Vec<&'static str>,
+}
+
+impl CpuInfo {
+    /// Reads the processor identification of the current CPU.
+    pub fn read() -> Self {
+        let leaf0 = unsafe { arch::__cpuid(0) };
+        let mut vendor = [0u8; 12];
+        vendor[0..4].copy_from_slice(&leaf0.ebx.to_le_bytes());
+        vendor[4..8].copy_from_slice(&leaf0.edx.to_le_bytes());
+        vendor[8..12].copy_from_slice(&leaf0.ecx.to_le_bytes());
+
+        let leaf1 = unsafe { arch::__cpuid(1) };
+        let base_family = (leaf1.eax >> 8) & 0xf;
+        let base_model = (leaf1.eax >> 4) & 0xf;
+        // Extended fields are only meaningful for the families 6 and 15.
+        let family = match base_family {
+            0xf => base_family + ((leaf1.eax >> 20) & 0xff),
+            _ => base_family,
+        };
+        let model = match base_family {
+            0x6 | 0xf => base_model | ((leaf1.eax >> 12) & 0xf0),
+            _ => base_model,
+        };
+
+        let max_ext = unsafe { arch::__cpuid(0x8000_0000) }.eax;
+        let ext_edx = if max_ext >= 0x8000_0001 { unsafe { arch::__cpuid(0x8000_0001) }.edx } else { 0 };
+        let brand = (max_ext >= 0x8000_0004).then(|| {
+            let bytes: Vec<u8> = (0x8000_0002..=0x8000_0004u32)
+                .map(|leaf| unsafe { arch::__cpuid(leaf) })
+                .flat_map(|r| [r.eax, r.ebx, r.ecx, r.edx])
+                .flat_map(u32::to_le_bytes)
+                .take_while(|&b| b != 0)
+                .collect();
+            String::from(String::from_utf8_lossy(&bytes).trim())
+        }).filter(|brand| !brand.is_empty());
+
+        let has = |reg: u32, table: &[(u32, &'static str)]| -> Vec<&'static str> {
+            table.iter().filter(|(bit, _)| reg & (1 << bit) != 0).map(|(_, name)| *name).collect()
+        };
+        let mut features = has(leaf1.edx, &FEATURES_EDX);
+        features.extend(has(leaf1.ecx, &FEATURES_ECX));
+        features.extend(has(ext_edx, &FEATURES_EXT_EDX));
+
+        Self {
+            vendor: String::from(String::from_utf8_lossy(&vendor)),
+            brand,
+            family,
+            model,
+            stepping: leaf1.eax & 0xf,
+            logical_cpus: ((leaf1.ebx >> 16) & 0xff).max(1),
+            features,
+        }
+    }
+}
+
+/// ACPI tables and devices described by the namespace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AcpiInfo {
+    /// Signatures of the tables listed in the XSDT or RSDT.
+    pub tables: Vec<String>,
+    /// Paths of the devices in the namespace. Empty until the DSDT is parsed.
+    pub devices: Vec<String>,
+    /// Some table failed validation and is ignored.
+    pub degraded: bool,
+}
+
+impl AcpiInfo {
+    /// Collects the ACPI information. Tables are only listed while ACPI memory is mapped.
+    pub fn read() -> Self {
+        let signature = |header: *const ACPISDTHeader| {
+            unsafe { header.read_unaligned() }.signature.iter().map(|c| c.0 as char).collect::<String>()
+        };
+
+        let tables = if unsafe { MEMORY_MANAGEMENT_UNIT.acpi_available() } {
+            match XSDT::try_new() {
+                Ok(xsdt) => xsdt.tables().map(signature).collect(),
+                Err(_) => RSDT::try_new().map(|rsdt| rsdt.tables().map(signature).collect()).unwrap_or_default(),
+            }
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            tables,
+            devices: unsafe { AML_INTERPRETER.devices() },
+            degraded: acpi_service::is_degraded(),
+        }
+    }
+}
+
+/// PCI function together with the driver bound to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PciEntry {
+    pub device: PciDevice,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    /// Name of the bound driver.
+    pub driver: Option<String>,
+}
+
+impl PciEntry {
+    /// Returns true if the function needs a driver, but none is bound.
+    pub fn lacks_driver(&self) -> bool {
+        self.driver.is_none() && self.class != PCI_CLASS_BRIDGE
+    }
+}
+
+/// The whole hardware inventory.
+#[derive(Debug, Clone)]
+pub struct HwInfo {
+    pub cpu: CpuInfo,
+    pub hypervisor: Option<Hypervisor>,
+    pub smbios: Option<SmbiosInfo>,
+    pub acpi: AcpiInfo,
+    pub pci: Vec<PciEntry>,
+    pub drivers: Vec<(DriverType, DriverInfo)>,
+}
+
+impl HwInfo {
+    /// Detects the hardware and collects the report.
+    ///
+    /// Scans the whole PCI bus and maps the SMBIOS tables, so it is not meant for hot paths.
+    pub fn collect() -> Self {
+        let drivers = unsafe { DRIVER_MANAGER.list() };
+        let pci = pci::scan().into_iter()
+            .map(|device| PciEntry {
+                device,
+                vendor_id: device.vendor_id(),
+                device_id: device.device_id(),
+                class: device.class(),
+                subclass: device.subclass(),
+                driver: bound_driver(&drivers, device),
+            })
+            .collect();
+
+        Self {
+            cpu: CpuInfo::read(),
+            hypervisor: Hypervisor::detect(),
+            smbios: SmbiosInfo::read(),
+            acpi: AcpiInfo::read(),
+            pci,
+            drivers,
+        }
+    }
+
+    /// Returns the PCI functions which lack a driver.
+    pub fn unbound(&self) -> impl Iterator<Item = &PciEntry> {
+        self.pci.iter().filter(|entry| entry.lacks_driver())
+    }
+}
+
+/// Finds the name of the driver bound to the PCI function.
+fn bound_driver(drivers: &[(DriverType, DriverInfo)], device: PciDevice) -> Option<String> {
+    drivers.iter()
+        .find(|(_, info)| info.device == Some(BoundDevice::Pci(device)))
+        .map(|(_, info)| info.name.clone())
+}
+
+impl Display for HwInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cpu = &self.cpu;
+        writeln!(f, "CPU:        {} {}", cpu.vendor, cpu.brand.as_deref().unwrap_or("(no brand string)"))?;
+        writeln!(
+            f, "            family {:#x} model {:#x} stepping {}, {} logical",
+            cpu.family, cpu.model, cpu.stepping, cpu.logical_cpus,
+        )?;
+        writeln!(f, "            {}", cpu.features.join(" "))?;
+        match self.hypervisor {
+            Some(hv) => writeln!(f, "Hypervisor: {:?}", hv)?,
+            None => writeln!(f, "Hypervisor: none")?,
+        }
+
+        let unknown = |s: &Option<String>| s.clone().unwrap_or_else(|| String::from("unknown"));
+        match &self.smbios {
+            Some(smbios) => {
+                let (major, minor) = smbios.version;
+                writeln!(f, "SMBIOS:     {}.{}", major, minor)?;
+                writeln!(f, "            system {} {}", unknown(&smbios.system_vendor), unknown(&smbios.system_product))?;
+                writeln!(f, "            board  {} {}", unknown(&smbios.board_vendor), unknown(&smbios.board_product))?;
+                writeln!(f, "            bios   {} {}", unknown(&smbios.bios_vendor), unknown(&smbios.bios_version))?;
+                writeln!(f, "            memory {} MiB in {} modules", smbios.memory_mib, smbios.memory_devices)?;
+            },
+            None => writeln!(f, "SMBIOS:     not found")?,
+        }
+
+        let acpi = &self.acpi;
+        writeln!(f, "ACPI:       tables {}{}", acpi.tables.join(" "), if acpi.degraded { " (degraded)" } else { "" })?;
+        writeln!(f, "            {} namespace devices", acpi.devices.len())?;
+        for device in &acpi.devices {
+            writeln!(f, "            {}", device)?;
+        }
+
+        writeln!(f, "PCI:")?;
+        for entry in &self.pci {
+            let dev = entry.device;
+            write!(
+                f, "  {:02x}:{:02x}.{} {:04x}:{:04x} {:<24} ",
+                dev.bus, dev.device, dev.function, entry.vendor_id, entry.device_id,
+                pci::class_name(entry.class, entry.subclass),
+            )?;
+            match &entry.driver {
+                Some(driver) => writeln!(f, "{}", driver)?,
+                None if entry.lacks_driver() => writeln!(f, "no driver")?,
+                None => writeln!(f, "-")?,
+            }
+        }
+
+        writeln!(f, "Drivers:")?;
+        for (dtype, info) in &self.drivers {
+            let (major, minor, patch) = info.version;
+            write!(f, "  {:<10} {} v{}.{}.{}", alloc::format!("{:?}", dtype), info.name, major, minor, patch)?;
+            match info.device {
+                Some(BoundDevice::Pci(dev)) => writeln!(f, " at {:02x}:{:02x}.{}", dev.bus, dev.device, dev.function)?,
+                Some(BoundDevice::IoPort(port)) => writeln!(f, " at port {:#x}", port)?,
+                Some(BoundDevice::Platform(name)) => writeln!(f, " at {}", name)?,
+                None => writeln!(f)?,
+            }
+        }
+
+        write!(f, "{} of {} PCI functions lack a driver", self.unbound().count(), self.pci.len())
+    }
+}
+
+#[test_case]
+fn hwinfo_marks_devices_without_driver() {
+    let cpu = CpuInfo::read();
+    assert!(cpu.features.contains(&"lm") && cpu.features.contains(&"fpu"));
+    assert_eq!(cpu.vendor.len(), 12);
+
+    let entry = |function, class, driver: Option<&str>| PciEntry {
+        device: PciDevice::new(0, 3, function),
+        vendor_id: 0x8086,
+        device_id: 0x100e,
+        class,
+        subclass: 0,
+        driver: driver.map(String::from),
+    };
+    let report = HwInfo {
+        cpu,
+        hypervisor: None,
+        smbios: None,
+        acpi: AcpiInfo::default(),
+        pci: alloc::vec![entry(0, 0x02, None), entry(1, 0x02, Some("E1000")), entry(2, PCI_CLASS_BRIDGE, None)],
+        drivers: Vec::new(),
+    };
+
+    assert_eq!(report.unbound().map(|e| e.device.function).collect::<Vec<_>>(), [0]);
+    let text = alloc::format!("{}", report);
+    assert!(text.contains("00:03.0 8086:100e network controller       no driver"));
+    assert!(text.ends_with("1 of 3 PCI functions lack a driver"));
+
+    let drivers = [(DriverType::Net, DriverInfo::new("E1000").bound_to(BoundDevice::Pci(PciDevice::new(0, 3, 1))))];
+    assert_eq!(bound_driver(&drivers, PciDevice::new(0, 3, 1)).as_deref(), Some("E1000"));
+    assert_eq!(bound_driver(&drivers, PciDevice::new(0, 3, 0)), None);
+}
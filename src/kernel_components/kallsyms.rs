@@ -0,0 +1,170 @@
+//! Full kernel symbol table embedded into the image after linking.
+//!
+//! Unlike [`ksyms`], which only holds the few symbols exported to loadable modules, this table
+//! holds every function of the kernel and is used to symbolize backtraces. The names are only
+//! known once the image is linked, so a zeroed area of [`KALLSYMS_SIZE`] bytes is reserved in the
+//! `.kallsyms` section and `kallsyms.py` overwrites it with the table taken from `nm` of the
+//! linked image. The size of the section does not change, so no address moves.
+//!
+//! Layout of the table, all little endian:
+//!
+//! | Offset          | Content                                                           |
+//! |-----------------|-------------------------------------------------------------------|
+//! | 0               | [`KALLSYMS_MAGIC`]                                                |
+//! | 4               | Amount of entries as u32                                          |
+//! | 8               | Entries sorted by address: u64 address, u32 name offset, u32 length |
+//! | 8 + 16 * count  | Names, without the hash suffix of the mangling                    |
+//!
+//! An image which was not patched holds only zeroes, in which case the lookup falls back to the
+//! exported symbols.
+//!
+//! [`ksyms`]: super::ksyms
+
+use core::fmt::{self, Display};
+
+use super::ksyms;
+
+/// Size of the area reserved for the table.
+pub const KALLSYMS_SIZE: usize = 512 * 1024;
+/// First bytes of a patched table.
+pub const KALLSYMS_MAGIC: [u8; 4] = *b"KSYM";
+/// Size of the header before the entries.
+const HEADER_SIZE: usize = 8;
+/// Size of a single entry.
+const ENTRY_SIZE: usize = 16;
+
+/// Area overwritten by `kallsyms.py`. It is only read through the linker symbols, as the
+/// compiler knows the initial value and would fold every read of the static to zero.
+#[used]
+#[link_section = ".kallsyms"]
+static KALLSYMS: [u8; KALLSYMS_SIZE] = [0; KALLSYMS_SIZE];
+
+extern "C" {
+    static __kallsyms_start: u8;
+    static __kallsyms_end: u8;
+}
+
+/// Returns the raw bytes of the section.
+fn section() -> &'static [u8] {
+    unsafe {
+        let start = &__kallsyms_start as *const u8;
+        let end = &__kallsyms_end as *const u8;
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+/// View over the embedded symbol table.
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolTable<'a> {
+    entries: &'a [u8],
+    names: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Parses the table, returning None if the bytes do not hold a valid one.
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE || bytes[..4] != KALLSYMS_MAGIC {
+            return None
+        }
+        let count = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        let names_start = count.checked_mul(ENTRY_SIZE)?.checked_add(HEADER_SIZE)?;
+        if names_start > bytes.len() {
+            return None
+        }
+        Some(Self { entries: &bytes[HEADER_SIZE..names_start], names: &bytes[names_start..] })
+    }
+
+    /// Amount of symbols in the table.
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    /// Checks if the table holds no symbols.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the address and the name of the entry.
+    fn entry(&self, index: usize) -> (usize, &'a str) {
+        let entry = &self.entries[index * ENTRY_SIZE..][..ENTRY_SIZE];
+        let addr = u64::from_le_bytes(entry[..8].try_into().unwrap()) as usize;
+        let offset = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as usize;
+        let len = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as usize;
+        let name = self.names.get(offset..offset + len)
+            .and_then(|name| core::str::from_utf8(name).ok())
+            .unwrap_or("<invalid>");
+        (addr, name)
+    }
+
+    /// Finds the closest symbol below the address, returning it's name and the offset from it.
+    pub fn resolve(&self, addr: usize) -> Option<(&'a str, usize)> {
+        // Index of the first symbol above the address.
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.entry(mid).0 <= addr {
+                true => low = mid + 1,
+                false => high = mid,
+            }
+        }
+        let (start, name) = self.entry(low.checked_sub(1)?);
+        Some((name, addr - start))
+    }
+}
+
+/// Returns the embedded table, or None if the image was not patched.
+pub fn table() -> Option<SymbolTable<'static>> {
+    SymbolTable::parse(section())
+}
+
+/// Resolves the address to a symbol name and the offset from it.
+///
+/// Only the exported symbols are searched when the image holds no table, in which case the
+/// result is only a rough hint.
+pub fn resolve(addr: usize) -> Option<(&'static str, usize)> {
+    match table() {
+        Some(table) => table.resolve(addr),
+        None => ksyms::symbolize(addr).map(|(sym, offset)| (sym.name, offset)),
+    }
+}
+
+/// Displays the address followed by the symbol it belongs to, if any.
+///
+/// Never allocates nor locks, so it is safe to use from the fatal paths.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbolized(pub usize);
+
+impl Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match resolve(self.0) {
+            Some((name, offset)) => write!(f, "{:#018x} {}+{:#x}", self.0, name, offset),
+            None => write!(f, "{:#018x} <unknown>", self.0),
+        }
+    }
+}
+
+#[test_case]
+fn symbol_table_is_searched_by_address() {
+    let mut bytes = [0u8; HEADER_SIZE + 2 * ENTRY_SIZE + 8];
+    bytes[..4].copy_from_slice(&KALLSYMS_MAGIC);
+    bytes[4..8].copy_from_slice(&2u32.to_le_bytes());
+    for (i, (addr, offset, len)) in [(0x1000u64, 0u32, 4u32), (0x2000, 4, 4)].into_iter().enumerate() {
+        let entry = &mut bytes[HEADER_SIZE + i * ENTRY_SIZE..][..ENTRY_SIZE];
+        entry[..8].copy_from_slice(&addr.to_le_bytes());
+        entry[8..12].copy_from_slice(&offset.to_le_bytes());
+        entry[12..16].copy_from_slice(&len.to_le_bytes());
+    }
+    bytes[HEADER_SIZE + 2 * ENTRY_SIZE..].copy_from_slice(b"initmain");
+
+    let table = SymbolTable::parse(&bytes).unwrap();
+    assert_eq!(table.len(), 2);
+    assert_eq!(table.resolve(0xfff), None);
+    assert_eq!(table.resolve(0x1000), Some(("init", 0)));
+    assert_eq!(table.resolve(0x1fff), Some(("init", 0xfff)));
+    assert_eq!(table.resolve(0x2010), Some(("main", 0x10)));
+
+    // A zeroed, unpatched area and a count beyond the bytes are both rejected.
+    assert!(SymbolTable::parse(&[0; 64]).is_none());
+    bytes[4..8].copy_from_slice(&3u32.to_le_bytes());
+    assert!(SymbolTable::parse(&bytes).is_none());
+}
@@ -0,0 +1,610 @@
+//! Booting a new kernel image from the running one.
+//!
+//! [`load`] parses a multiboot2 compliant ELF image, stages it's loadable segments in freshly
+//! allocated frames and builds a boot information structure for it. [`execute`] quiesces all
+//! devices through driver shutdown hooks, leaves long mode through a small identity mapped
+//! trampoline, moves the staged segments to their load addresses and jumps to the entry point
+//! exactly like a multiboot2 bootloader would.
+
+use core::arch::global_asm;
+use core::convert::Infallible;
+use core::fmt::Display;
+use core::error::Error;
+
+use alloc::vec::Vec;
+
+use crate::kernel_components::memory::{
+    MEMORY_MANAGEMENT_UNIT, Page, EntryFlags,
+    frames::{Frame, PAGE_SIZE},
+    tags::TagType,
+};
+use crate::kernel_components::drivers::DRIVER_MANAGER;
+use crate::kernel_components::arch_x86_64::controllers::PROGRAMMABLE_INTERRUPT_CONTROLLER;
+use crate::kernel_components::arch_x86_64::interrupts::interrupt;
+use crate::kernel_components::sync::Mutex;
+use crate::{debug, PhysicalAddress};
+
+/// Magic value of the multiboot2 header inside the kernel image.
+const MULTIBOOT2_HEADER_MAGIC: u32 = 0xe85250d6;
+/// The multiboot2 header must be within this amount of bytes from the start of the image.
+const MULTIBOOT2_SEARCH_LIMIT: usize = 32768;
+/// Maximal amount of loadable segments that the trampoline can move.
+const MAX_SEGMENTS: usize = 16;
+/// Offset of the parameters block within the control frame. The trampoline code is copied
+/// at the beginning of the same frame.
+const PARAMS_OFFSET: usize = 2048;
+/// Everything handed to the 32-bit trampoline must be below 4 GiB.
+const LOW_MEMORY_LIMIT: usize = 1 << 32;
+
+/// The currently staged image, if any.
+static STAGED_IMAGE: Mutex<Option<StagedImage>> = Mutex::new(None);
+
+/// Custom error type for kexec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KexecError {
+    /// The image is not a valid ELF executable.
+    InvalidImage,
+    /// No valid multiboot2 header was found within the first 32 KiB of the image.
+    NoMultibootHeader,
+    /// The image has too many loadable segments or must be loaded above 4 GiB.
+    Unsupported,
+    /// Unable to allocate enough contiguous frames for staging.
+    OutOfMemory,
+    /// Staging memory overlaps the load addresses of the image.
+    Overlap,
+    /// No image is loaded.
+    NotLoaded,
+}
+
+impl Display for KexecError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use KexecError::*;
+
+        match self {
+            InvalidImage => write!(f, "The image is not a valid ELF executable."),
+            NoMultibootHeader => write!(f, "The image does not contain a multiboot2 header."),
+            Unsupported => write!(f, "The image layout is not supported by kexec."),
+            OutOfMemory => write!(f, "Not enough contiguous memory to stage the image."),
+            Overlap => write!(f, "Staging memory overlaps the load addresses of the image."),
+            NotLoaded => write!(f, "No kexec image is loaded."),
+        }
+    }
+}
+
+impl Error for KexecError {}
+
+/// One loadable segment moved by the trampoline.
+///
+/// Copies `len` bytes from `src` to `dst` and zeroes the following `zero` bytes.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+struct CopySegment {
+    src: u32,
+    dst: u32,
+    len: u32,
+    zero: u32,
+}
+
+/// Parameters block read by the trampoline.
+///
+/// The layout is fixed, because the trampoline accesses the fields by their offsets.
+#[repr(C)]
+struct TrampolineParams {
+    _pad: [u16; 3],
+    /// Limit of the temporary GDT (offset 6).
+    gdt_limit: u16,
+    /// Base of the temporary GDT (offset 8).
+    gdt_base: u64,
+    /// Null, flat 32-bit code and flat 32-bit data descriptors (offset 16).
+    gdt: [u64; 3],
+    /// Entry point of the new kernel (offset 40).
+    entry: u32,
+    /// Physical address of the boot information structure (offset 44).
+    mbi: u32,
+    /// Amount of valid entries in segments (offset 48).
+    count: u32,
+    _reserved: u32,
+    /// Segments to move (offset 56).
+    segments: [CopySegment; MAX_SEGMENTS],
+}
+
+/// An image which is staged in memory and ready to be executed.
+#[derive(Debug)]
+struct StagedImage {
+    /// Physical (and identity mapped) address of the control frame.
+    control: PhysicalAddress,
+    /// Areas holding the segments, the boot information and the control frame.
+    areas: StagingAreas,
+}
+
+/// Contiguous areas allocated while staging an image.
+///
+/// The areas are released when dropped, so an image failing a check after the allocation, or
+/// replaced by a newer one, does not leak it's frames.
+#[derive(Debug, Default)]
+struct StagingAreas {
+    areas: [(PhysicalAddress, usize); 3],
+    len: usize,
+}
+
+impl StagingAreas {
+    /// Allocates the area with [`alloc_contiguous`] and keeps it until drop.
+    fn alloc(&mut self, size: usize) -> Result<PhysicalAddress, KexecError> {
+        let start = alloc_contiguous(size)?;
+        self.areas[self.len] = (start, size);
+        self.len += 1;
+        Ok(start)
+    }
+}
+
+impl Drop for StagingAreas {
+    fn drop(&mut self) {
+        for &(start, size) in self.areas[..self.len].iter() {
+            let count = size.div_ceil(PAGE_SIZE).max(1);
+            let first = Frame::info_address(start);
+            release_contiguous(first, Frame::info_address(start + (count - 1) * PAGE_SIZE), count);
+        }
+    }
+}
+
+/// A loadable segment of the ELF image.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    offset: usize,
+    paddr: usize,
+    filesz: usize,
+    memsz: usize,
+}
+
+/// Parsed parts of the ELF image that matter for loading it.
+struct ElfImage {
+    is_64: bool,
+    entry: usize,
+    segments: Vec<Segment>,
+    shoff: usize,
+    shentsize: usize,
+    shnum: usize,
+    shstrndx: usize,
+}
+
+impl ElfImage {
+    /// Parses the ELF header and program headers of the image.
+    fn parse(image: &[u8]) -> Result<Self, KexecError> {
+        if image.get(0..4) != Some(b"\x7fELF") {
+            return Err(KexecError::InvalidImage)
+        }
+
+        let is_64 = match image.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(KexecError::InvalidImage),
+        };
+        let word = |off64, off32| if is_64 { read_u64(image, off64) } else { read_u32(image, off32) };
+
+        let entry = word(24, 24)?;
+        let phoff = word(32, 28)?;
+        let shoff = word(40, 32)?;
+        let phentsize = read_u16(image, if is_64 { 54 } else { 42 })?;
+        let phnum = read_u16(image, if is_64 { 56 } else { 44 })?;
+        let shentsize = read_u16(image, if is_64 { 58 } else { 46 })?;
+        let shnum = read_u16(image, if is_64 { 60 } else { 48 })?;
+        let shstrndx = read_u16(image, if is_64 { 62 } else { 50 })?;
+
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let ph = i.checked_mul(phentsize)
+                .and_then(|rel| rel.checked_add(phoff))
+                .ok_or(KexecError::InvalidImage)?;
+            let field = |off64, off32| word(ph.saturating_add(off64), ph.saturating_add(off32));
+
+            // PT_LOAD
+            if read_u32(image, ph)? != 1 {
+                continue
+            }
+
+            let segment = Segment {
+                offset: field(8, 4)?,
+                paddr: field(24, 12)?,
+                filesz: field(32, 16)?,
+                memsz: field(40, 20)?,
+            };
+
+            let file_end = segment.offset.checked_add(segment.filesz).ok_or(KexecError::InvalidImage)?;
+            let load_end = segment.paddr.checked_add(segment.memsz).ok_or(KexecError::InvalidImage)?;
+            if segment.filesz > segment.memsz || file_end > image.len() {
+                return Err(KexecError::InvalidImage)
+            }
+            if load_end > LOW_MEMORY_LIMIT {
+                return Err(KexecError::Unsupported)
+            }
+            if segment.memsz != 0 {
+                segments.push(segment);
+            }
+        }
+
+        let sections_end = shnum.checked_mul(shentsize).and_then(|size| size.checked_add(shoff));
+        if segments.is_empty() || shstrndx >= shnum || sections_end.is_none_or(|end| end > image.len()) {
+            return Err(KexecError::InvalidImage)
+        }
+        if segments.len() > MAX_SEGMENTS || entry >= LOW_MEMORY_LIMIT {
+            return Err(KexecError::Unsupported)
+        }
+
+        Ok(Self { is_64, entry, segments, shoff, shentsize, shnum, shstrndx })
+    }
+
+    /// Returns the raw section header table.
+    fn section_headers<'a>(&self, image: &'a [u8]) -> &'a [u8] {
+        &image[self.shoff..self.shoff + self.shnum * self.shentsize]
+    }
+
+    /// Returns the contents of the section header string table.
+    fn string_table<'a>(&self, image: &'a [u8]) -> Result<&'a [u8], KexecError> {
+        let sh = self.shoff + self.shstrndx * self.shentsize;
+        let (offset, size) = if self.is_64 {
+            (read_u64(image, sh + 24)?, read_u64(image, sh + 32)?)
+        } else {
+            (read_u32(image, sh + 16)?, read_u32(image, sh + 20)?)
+        };
+        let end = offset.checked_add(size).ok_or(KexecError::InvalidImage)?;
+        image.get(offset..end).ok_or(KexecError::InvalidImage)
+    }
+
+    /// Offset of the address field of the string table header within the section header table.
+    fn string_table_addr_offset(&self) -> usize {
+        self.shstrndx * self.shentsize + if self.is_64 { 16 } else { 12 }
+    }
+}
+
+/// Stages a new multiboot2 kernel image, reusing the command line of the running kernel.
+pub fn load(image: &[u8]) -> Result<(), KexecError> {
+    let cmdline = unsafe { MEMORY_MANAGEMENT_UNIT.boot_info().command_line() };
+    load_with_cmdline(image, cmdline.unwrap_or(""))
+}
+
+/// Stages a new multiboot2 kernel image with a custom command line.
+///
+/// The loadable segments are copied into reserved frames, because their final load addresses
+/// are most likely occupied by the running kernel. They are moved into place by the trampoline
+/// only after paging is disabled. A previously loaded image is replaced and it's frames are
+/// returned to the allocator, as are the frames of an image which fails to load.
+pub fn load_with_cmdline(image: &[u8], cmdline: &str) -> Result<(), KexecError> {
+    check_multiboot_header(image)?;
+    let elf = ElfImage::parse(image)?;
+    let strtab = elf.string_table(image)?;
+    let mut areas = StagingAreas::default();
+
+    // Staging the segments in one contiguous area.
+    let staged_size: usize = elf.segments.iter().map(|s| s.filesz).sum();
+    let staging = areas.alloc(staged_size)?;
+
+    let mut params_segments = [CopySegment::default(); MAX_SEGMENTS];
+    let mut offset = 0;
+    for (segment, params) in elf.segments.iter().zip(params_segments.iter_mut()) {
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                image[segment.offset..].as_ptr(),
+                (staging + offset) as *mut u8,
+                segment.filesz,
+            );
+        }
+        *params = CopySegment {
+            src: (staging + offset) as u32,
+            dst: segment.paddr as u32,
+            len: segment.filesz as u32,
+            zero: (segment.memsz - segment.filesz) as u32,
+        };
+        offset += segment.filesz;
+    }
+
+    // Building the boot information structure. The section string table is placed right after
+    // it, so the new kernel can resolve section names.
+    let mut mbi = build_boot_info(&elf, image, cmdline);
+    let strtab_offset = mbi.len();
+    let mbi_phys = areas.alloc(strtab_offset + strtab.len())?;
+
+    let addr_at = mbi_sections_offset(&mbi) + elf.string_table_addr_offset();
+    let strtab_addr = (mbi_phys + strtab_offset) as u64;
+    if elf.is_64 {
+        mbi[addr_at..addr_at + 8].copy_from_slice(&strtab_addr.to_le_bytes());
+    } else {
+        mbi[addr_at..addr_at + 4].copy_from_slice(&(strtab_addr as u32).to_le_bytes());
+    }
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(mbi.as_ptr(), mbi_phys as *mut u8, mbi.len());
+        core::ptr::copy_nonoverlapping(strtab.as_ptr(), (mbi_phys + strtab_offset) as *mut u8, strtab.len());
+    }
+
+    // Control frame with the trampoline code and it's parameters.
+    let control = areas.alloc(PAGE_SIZE)?;
+    let trampoline = trampoline_code();
+    assert!(trampoline.len() <= PARAMS_OFFSET, "The kexec trampoline does not fit into the control frame.");
+
+    // Staging areas must survive until the trampoline is done with them.
+    let reserved = [
+        (staging, staged_size),
+        (mbi_phys, strtab_offset + strtab.len()),
+        (control, PAGE_SIZE),
+    ];
+    for segment in elf.segments.iter() {
+        let overlaps = reserved.iter().any(|&(start, size)| {
+            start < segment.paddr + segment.memsz && segment.paddr < start + size
+        });
+        if overlaps {
+            return Err(KexecError::Overlap)
+        }
+    }
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(trampoline.as_ptr(), control as *mut u8, trampoline.len());
+
+        let params = &mut *((control + PARAMS_OFFSET) as *mut TrampolineParams);
+        params.gdt = [0, 0x00cf9a000000ffff, 0x00cf92000000ffff];
+        params.gdt_limit = (core::mem::size_of::<[u64; 3]>() - 1) as u16;
+        params.gdt_base = (control + PARAMS_OFFSET + 16) as u64;
+        params.entry = elf.entry as u32;
+        params.mbi = mbi_phys as u32;
+        params.count = elf.segments.len() as u32;
+        params.segments = params_segments;
+    }
+
+    debug!("kexec: staged {} segments, entry at {:#x}", elf.segments.len(), elf.entry);
+    *STAGED_IMAGE.lock() = Some(StagedImage { control, areas });
+    Ok(())
+}
+
+/// Returns true if an image is staged and ready to be executed.
+pub fn is_loaded() -> bool {
+    STAGED_IMAGE.lock().is_some()
+}
+
+/// Boots the staged image.
+///
+/// Calls the shutdown hook of every loaded driver, masks all interrupts and jumps to the
+/// trampoline. Only returns if no image is loaded.
+pub fn execute() -> Result<Infallible, KexecError> {
+    let control = STAGED_IMAGE.lock().as_ref().map(|image| image.control).ok_or(KexecError::NotLoaded)?;
+
+    unsafe { interrupt::disable() };
+    unsafe { DRIVER_MANAGER.shutdown_all() };
+    PROGRAMMABLE_INTERRUPT_CONTROLLER.lock().as_mut().map(|pic| pic.disable());
+
+    unsafe {
+        let trampoline: extern "C" fn(usize) -> ! = core::mem::transmute(control);
+        trampoline(control + PARAMS_OFFSET)
+    }
+}
+
+/// Looks for a valid multiboot2 header within the search limit of the image.
+fn check_multiboot_header(image: &[u8]) -> Result<(), KexecError> {
+    let limit = image.len().min(MULTIBOOT2_SEARCH_LIMIT);
+
+    (0..limit.saturating_sub(16)).step_by(8)
+        .find(|&off| {
+            let magic = read_u32(image, off).unwrap_or(0) as u32;
+            let arch = read_u32(image, off + 4).unwrap_or(0) as u32;
+            let len = read_u32(image, off + 8).unwrap_or(0) as u32;
+            let checksum = read_u32(image, off + 12).unwrap_or(0) as u32;
+
+            magic == MULTIBOOT2_HEADER_MAGIC && arch == 0 &&
+                magic.wrapping_add(arch).wrapping_add(len).wrapping_add(checksum) == 0
+        })
+        .map(|_| ())
+        .ok_or(KexecError::NoMultibootHeader)
+}
+
+/// Builds the multiboot2 boot information structure for the new kernel.
+///
+/// Every tag provided by the bootloader to the running kernel is passed along, except for the
+/// ones that describe the running kernel image itself, which are replaced with the new ones.
+fn build_boot_info(elf: &ElfImage, image: &[u8], cmdline: &str) -> Vec<u8> {
+    let mut mbi = Vec::new();
+    // Total size and reserved field are patched at the end.
+    mbi.extend_from_slice(&[0; 8]);
+
+    let push_tag = |mbi: &mut Vec<u8>, typ: u32, payload: &[u8]| {
+        mbi.extend_from_slice(&typ.to_le_bytes());
+        mbi.extend_from_slice(&((8 + payload.len()) as u32).to_le_bytes());
+        mbi.extend_from_slice(payload);
+        mbi.resize((mbi.len() + 7) & !7, 0);
+    };
+
+    let mut cmd = Vec::from(cmdline.as_bytes());
+    cmd.push(0);
+    push_tag(&mut mbi, TagType::Cmd.get(), &cmd);
+
+    let mut sections = Vec::new();
+    sections.extend_from_slice(&(elf.shnum as u32).to_le_bytes());
+    sections.extend_from_slice(&(elf.shentsize as u32).to_le_bytes());
+    sections.extend_from_slice(&(elf.shstrndx as u32).to_le_bytes());
+    sections.extend_from_slice(elf.section_headers(image));
+    push_tag(&mut mbi, TagType::ElfSections.get(), &sections);
+
+    for tag in unsafe { MEMORY_MANAGEMENT_UNIT.boot_info().tags() } {
+        match tag.get_type() {
+            TagType::Cmd | TagType::ElfSections | TagType::Module | TagType::LoadBase => continue,
+            _ => {
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(tag as *const _ as *const u8, tag.size as usize)
+                };
+                mbi.extend_from_slice(bytes);
+                mbi.resize((mbi.len() + 7) & !7, 0);
+            }
+        }
+    }
+
+    push_tag(&mut mbi, TagType::End.get(), &[]);
+
+    let total = mbi.len() as u32;
+    mbi[0..4].copy_from_slice(&total.to_le_bytes());
+    mbi
+}
+
+/// Offset of the first section header inside the boot information built by [`build_boot_info`].
+fn mbi_sections_offset(mbi: &[u8]) -> usize {
+    // Header, then the command line tag, then the sections tag header.
+    let cmd_size = u32::from_le_bytes(mbi[12..16].try_into().unwrap()) as usize;
+    8 + ((cmd_size + 7) & !7) + 20
+}
+
+/// Allocates a physically contiguous area of at least the given size and identity maps it.
+///
+/// Returns the physical address of the area. On failure every frame taken so far is returned to
+/// the frame allocator.
+fn alloc_contiguous(size: usize) -> Result<PhysicalAddress, KexecError> {
+    let mmu = unsafe { &mut MEMORY_MANAGEMENT_UNIT };
+    let count = size.div_ceil(PAGE_SIZE).max(1);
+
+    let first = mmu.allocate_frame().ok_or(KexecError::OutOfMemory)?;
+    let mut last = first.clone();
+    for _ in 1..count {
+        let Some(frame) = mmu.allocate_frame() else {
+            release_contiguous(first, last, 0);
+            return Err(KexecError::OutOfMemory)
+        };
+        if frame.start_address() != last.start_address() + PAGE_SIZE {
+            mmu.deallocate_frame(frame);
+            release_contiguous(first, last, 0);
+            return Err(KexecError::OutOfMemory)
+        }
+        last = frame;
+    }
+
+    if last.start_address() + PAGE_SIZE > LOW_MEMORY_LIMIT {
+        release_contiguous(first, last, 0);
+        return Err(KexecError::Unsupported)
+    }
+
+    let start = first.start_address();
+    for (mapped, frame) in Frame::range_inclusive(first.clone(), last.clone()).enumerate() {
+        let page = Page::containing_address(frame.start_address());
+        if mmu.map_to(page, frame, EntryFlags::WRITABLE).is_err() {
+            release_contiguous(first, last, mapped);
+            return Err(KexecError::OutOfMemory)
+        }
+    }
+    Ok(start)
+}
+
+/// Frees the frames of a partially allocated contiguous area, unmapping the first mapped ones.
+fn release_contiguous(first: Frame, last: Frame, mapped: usize) {
+    let mmu = unsafe { &mut MEMORY_MANAGEMENT_UNIT };
+    for (i, frame) in Frame::range_inclusive(first, last).enumerate() {
+        if i < mapped {
+            let _ = mmu.unmap(Page::containing_address(frame.start_address()));
+        }
+        mmu.deallocate_frame(frame);
+    }
+}
+
+fn read_u16(image: &[u8], off: usize) -> Result<usize, KexecError> {
+    off.checked_add(2).and_then(|end| image.get(off..end))
+        .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+        .ok_or(KexecError::InvalidImage)
+}
+
+fn read_u32(image: &[u8], off: usize) -> Result<usize, KexecError> {
+    off.checked_add(4).and_then(|end| image.get(off..end))
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+        .ok_or(KexecError::InvalidImage)
+}
+
+fn read_u64(image: &[u8], off: usize) -> Result<usize, KexecError> {
+    off.checked_add(8).and_then(|end| image.get(off..end))
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()) as usize)
+        .ok_or(KexecError::InvalidImage)
+}
+
+/// Returns the position independent trampoline code.
+fn trampoline_code() -> &'static [u8] {
+    extern "C" {
+        static kexec_trampoline_start: u8;
+        static kexec_trampoline_end: u8;
+    }
+
+    unsafe {
+        let start = &kexec_trampoline_start as *const u8;
+        let end = &kexec_trampoline_end as *const u8;
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+// The trampoline is entered in long mode with the address of the parameters block in RDI. Both
+// the trampoline and the parameters must be identity mapped and below 4 GiB.
+//
+// It switches to a flat 32-bit code segment (compatibility mode), disables paging and clears
+// EFER.LME, which leaves the CPU in plain protected mode. Then the staged segments are moved to
+// their load addresses and control is passed to the new kernel with the multiboot2 magic in EAX
+// and the boot information address in EBX.
+global_asm!(
+    ".pushsection .text.kexec, \"ax\"",
+    ".global kexec_trampoline_start",
+    ".global kexec_trampoline_end",
+    ".code64",
+    "kexec_trampoline_start:",
+    "    cli",
+    "    mov rsi, rdi",
+    "    lgdt [rsi + 6]",
+    "    lea rax, [rip + kexec_trampoline_32]",
+    "    push 0x08",
+    "    push rax",
+    "    retfq",
+    ".code32",
+    "kexec_trampoline_32:",
+    "    mov ax, 0x10",
+    "    mov ds, ax",
+    "    mov es, ax",
+    "    mov ss, ax",
+    "    mov eax, cr0",
+    "    and eax, 0x7fffffff",
+    "    mov cr0, eax",
+    "    mov ecx, 0xc0000080",
+    "    rdmsr",
+    "    and eax, 0xfffffeff",
+    "    wrmsr",
+    "    mov ebp, esi",
+    "    mov ebx, [ebp + 48]",
+    "    lea edx, [ebp + 56]",
+    "    cld",
+    "kexec_trampoline_copy:",
+    "    test ebx, ebx",
+    "    jz kexec_trampoline_jump",
+    "    mov esi, [edx]",
+    "    mov edi, [edx + 4]",
+    "    mov ecx, [edx + 8]",
+    "    rep movsb",
+    "    mov ecx, [edx + 12]",
+    "    xor eax, eax",
+    "    rep stosb",
+    "    add edx, 16",
+    "    dec ebx",
+    "    jmp kexec_trampoline_copy",
+    "kexec_trampoline_jump:",
+    "    mov ebx, [ebp + 44]",
+    "    mov ecx, [ebp + 40]",
+    "    mov eax, 0x36d76289",
+    "    jmp ecx",
+    "kexec_trampoline_end:",
+    ".code64",
+    ".popsection",
+);
+
+#[test_case]
+fn elf_offsets_do_not_overflow() {
+    let mut header = [0u8; 64];
+    header[0..4].copy_from_slice(b"\x7fELF");
+    header[4] = 2;
+    // A single program header far beyond the end of the address space.
+    header[32..40].copy_from_slice(&(usize::MAX - 2).to_le_bytes());
+    header[54..56].copy_from_slice(&56u16.to_le_bytes());
+    header[56..58].copy_from_slice(&1u16.to_le_bytes());
+    assert!(matches!(ElfImage::parse(&header), Err(KexecError::InvalidImage)));
+
+    // No program headers and a section header table which wraps around.
+    header[56..58].copy_from_slice(&0u16.to_le_bytes());
+    header[40..48].copy_from_slice(&(usize::MAX - 2).to_le_bytes());
+    header[58..60].copy_from_slice(&64u16.to_le_bytes());
+    header[60..62].copy_from_slice(&2u16.to_le_bytes());
+    assert!(matches!(ElfImage::parse(&header), Err(KexecError::InvalidImage)));
+}
@@ -0,0 +1,354 @@
+//! Kernel log layer between the call sites and the screen.
+//!
+//! Messages printed with [`printk!`] are filtered here before reaching the log sinks:
+//!
+//! - Every call site owns it's own [`RateLimit`] token. A site may print a burst of messages
+//! within an interval of TSC cycles, the rest is dropped and counted. The first message of the
+//! next interval reports how many were dropped. Since the budget is per site, a flooding debug
+//! path never consumes the budget of other sites. Errors are never rate limited.
+//! - Consecutive identical messages of the same site are collapsed. They are reported as a single
+//! "last message repeated N times" line once a different message arrives or [`flush`] is called.
+//!
+//! Verbose debug output of hot paths (heap allocations, page mappings) is printed with [`trace!`]
+//! and belongs to a [`TraceCategory`]. Categories are disabled by default and toggled at runtime
+//! with the "kernel.trace" tunable, i.e "kernel.trace=heap,paging" on the kernel command line.
+//!
+//! [`printk!`]: crate::printk
+//! [`trace!`]: crate::trace
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::kernel_components::sync::Mutex;
+use crate::kernel_components::logging::{self, Record};
+use crate::kernel_components::vga_buffer::LogLevel;
+use crate::{critical_section, bitflags};
+
+/// Default length of the rate limiting interval. Roughly five seconds on a 1 GHz TSC.
+pub const DEFAULT_INTERVAL_CYCLES: u64 = 5_000_000_000;
+/// Default amount of messages a single call site may print within the interval.
+pub const DEFAULT_BURST: u32 = 10;
+
+/// Length of the rate limiting interval in TSC cycles.
+pub static INTERVAL_CYCLES: AtomicU64 = AtomicU64::new(DEFAULT_INTERVAL_CYCLES);
+/// Amount of messages per call site and interval. Zero disables rate limiting.
+pub static BURST: AtomicU32 = AtomicU32::new(DEFAULT_BURST);
+
+/// Mask of enabled trace categories.
+static TRACE_MASK: AtomicU32 = AtomicU32::new(0);
+
+bitflags! {
+    /// Subsystems with verbose debug output printed by [`trace!`].
+    ///
+    /// [`trace!`]: crate::trace
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TraceCategory: u32 {
+        /// Allocations and deallocations of the heap allocators.
+        const HEAP = 1,
+        /// Mapping of kernel sections, heap pages and the page tables.
+        const PAGING = 1 << 1,
+        /// Mapping of stack pages.
+        const STACKS = 1 << 2,
+        /// Mapping of ACPI tables.
+        const ACPI = 1 << 3,
+    }
+}
+
+/// Names of the trace categories used by the "kernel.trace" tunable.
+pub const TRACE_CATEGORIES: [(&str, TraceCategory); 4] = [
+    ("heap", TraceCategory::HEAP),
+    ("paging", TraceCategory::PAGING),
+    ("stacks", TraceCategory::STACKS),
+    ("acpi", TraceCategory::ACPI),
+];
+
+/// Checks if the trace output of the category is enabled.
+#[inline]
+pub fn trace_enabled(category: TraceCategory) -> bool {
+    TRACE_MASK.load(Ordering::Relaxed) & category.bits() != 0
+}
+
+/// Enables or disables the trace output of the category.
+pub fn set_trace(category: TraceCategory, enabled: bool) {
+    match enabled {
+        true => TRACE_MASK.fetch_or(category.bits(), Ordering::Relaxed),
+        false => TRACE_MASK.fetch_and(!category.bits(), Ordering::Relaxed),
+    };
+}
+
+/// Parses a comma separated list of category names into a mask. "all" and "none" are accepted
+/// as well.
+pub fn parse_trace_mask(list: &str) -> Option<u32> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .try_fold(0, |mask, name| match name {
+            "all" => Some(TRACE_CATEGORIES.iter().fold(mask, |mask, (_, c)| mask | c.bits())),
+            "none" => Some(mask),
+            _ => TRACE_CATEGORIES
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, c)| mask | c.bits()),
+        })
+}
+
+/// Replaces the mask of enabled trace categories.
+pub fn set_trace_mask(mask: u32) {
+    TRACE_MASK.store(mask, Ordering::Relaxed);
+}
+
+/// Last printed message, used to collapse repeated ones.
+static LAST: Mutex<LastMessage> = Mutex::new(LastMessage::new());
+
+/// Rate limiting state of a single call site.
+///
+/// The [`printk!`] macro creates a static token for each invocation, so it is rarely created
+/// manually.
+///
+/// [`printk!`]: crate::printk
+pub struct RateLimit {
+    /// Location of the call site, reported with the amount of dropped messages.
+    site: &'static str,
+    /// Timestamp of the beginning of the current interval.
+    begin: AtomicU64,
+    /// Messages printed within the current interval.
+    printed: AtomicU32,
+    /// Messages dropped within the current interval.
+    missed: AtomicU32,
+}
+
+impl RateLimit {
+    /// Creates a new token for the call site.
+    pub const fn new(site: &'static str) -> Self {
+        Self {
+            site,
+            begin: AtomicU64::new(0),
+            printed: AtomicU32::new(0),
+            missed: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns the location of the call site.
+    pub fn site(&self) -> &'static str {
+        self.site
+    }
+
+    /// Checks if a message may be printed at the provided timestamp.
+    ///
+    /// Returns the amount of messages dropped within the previous interval if allowed, or None
+    /// if the message must be dropped.
+    pub fn allow(&self, now: u64) -> Option<u32> {
+        let burst = BURST.load(Ordering::Relaxed);
+        if burst == 0 {
+            return Some(0)
+        }
+
+        let mut missed = 0;
+        let begin = self.begin.load(Ordering::Relaxed);
+        if begin == 0 || now.wrapping_sub(begin) >= INTERVAL_CYCLES.load(Ordering::Relaxed) {
+            self.begin.store(now, Ordering::Relaxed);
+            self.printed.store(0, Ordering::Relaxed);
+            missed = self.missed.swap(0, Ordering::Relaxed);
+        }
+
+        if self.printed.load(Ordering::Relaxed) < burst {
+            self.printed.fetch_add(1, Ordering::Relaxed);
+            Some(missed)
+        } else {
+            self.missed.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    /// Returns the amount of messages dropped so far within the current interval.
+    pub fn missed(&self) -> u32 {
+        self.missed.load(Ordering::Relaxed)
+    }
+}
+
+/// Identity of the last printed message.
+struct LastMessage {
+    /// Address of the call site token, zero if nothing was printed yet.
+    site: usize,
+    hash: u64,
+    level: LogLevel,
+    target: &'static str,
+    repeated: u32,
+}
+
+impl LastMessage {
+    const fn new() -> Self {
+        Self { site: 0, hash: 0, level: LogLevel::Info, target: "", repeated: 0 }
+    }
+
+    /// Prints the amount of collapsed repeats, if any.
+    fn flush(&mut self) {
+        if self.repeated != 0 {
+            logging::dispatch(&Record {
+                level: self.level,
+                target: self.target,
+                args: format_args!("last message repeated {} times\n", self.repeated),
+            });
+            self.repeated = 0;
+        }
+    }
+}
+
+/// FNV-1a hash of the formatted message, so it is never stored.
+struct MessageHash(u64);
+
+impl Write for MessageHash {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+        Ok(())
+    }
+}
+
+/// Prints the pending "last message repeated" line.
+pub fn flush() {
+    critical_section!(|| LAST.lock().flush());
+}
+
+/// Prints a message of the call site, unless it is dropped by the log filters, the rate limiting
+/// or collapsed as a repeat of the last message.
+///
+/// Returns true if the message was printed.
+#[doc(hidden)]
+pub fn _printk(site: &'static RateLimit, level: LogLevel, module_path: &'static str, args: fmt::Arguments) -> bool {
+    let target = logging::short_target(module_path);
+    if !logging::enabled(level, target) {
+        return false
+    }
+
+    let mut hash = MessageHash(0xcbf29ce484222325);
+    let _ = hash.write_fmt(args);
+    let now = unsafe { core::arch::x86_64::_rdtsc() };
+
+    critical_section!(|| {
+        let mut last = LAST.lock();
+        let addr = site as *const RateLimit as usize;
+        if last.site == addr && last.hash == hash.0 {
+            last.repeated += 1;
+            return false
+        }
+
+        let missed = match level {
+            LogLevel::Error => 0,
+            _ => match site.allow(now) {
+                Some(missed) => missed,
+                None => return false,
+            },
+        };
+
+        last.flush();
+        if missed != 0 {
+            logging::dispatch(&Record {
+                level,
+                target,
+                args: format_args!("printk: {} messages suppressed at {}\n", missed, site.site),
+            });
+        }
+        logging::dispatch(&Record { level, target, args });
+        *last = LastMessage { site: addr, hash: hash.0, level, target, repeated: 0 };
+        true
+    })
+}
+
+/// Prints a kernel message through the kernel log layer and moves the cursor to new line.
+///
+/// Works like [`log!`], but the call site is rate limited and consecutive identical messages
+/// are collapsed. Use it for messages which might be printed in loops or from hot paths.
+///
+/// # Examples
+/// '''
+/// use notOS::printk;
+///
+/// for page in 0..4096 {
+///     // Only a burst of these reaches the screen.
+///     printk!(Trace; "Mapping page {}", page);
+/// }
+/// '''
+///
+/// [`log!`]: crate::log
+#[macro_export]
+macro_rules! printk {
+    ($level:ident; $fmt:expr) => ($crate::printk!($level; $fmt,));
+    ($level:ident; $fmt:expr, $($arg:tt)*) => ({
+        const ENABLED: bool = $crate::kernel_components::logging::static_enabled(
+            $crate::kernel_components::vga_buffer::LogLevel::$level,
+            module_path!(),
+        );
+        static SITE: $crate::kernel_components::klog::RateLimit =
+            $crate::kernel_components::klog::RateLimit::new(concat!(file!(), ":", line!()));
+        ENABLED && $crate::kernel_components::klog::_printk(
+            &SITE,
+            $crate::kernel_components::vga_buffer::LogLevel::$level,
+            module_path!(),
+            format_args!(concat!($fmt, '\n'), $($arg)*),
+        )
+    });
+}
+
+/// Prints a debug message of the trace category, if the category is enabled at runtime.
+///
+/// The message goes through [`printk!`], so it is rate limited as well. Disabled categories cost
+/// a single atomic load.
+///
+/// # Examples
+/// '''
+/// use notOS::trace;
+///
+/// trace!(HEAP; "Allocating {} bytes at {:#x}", 64, 0x4444_4444_0000usize);
+/// '''
+///
+/// [`printk!`]: crate::printk
+#[macro_export]
+macro_rules! trace {
+    ($category:ident; $fmt:expr) => ($crate::trace!($category; $fmt,));
+    ($category:ident; $fmt:expr, $($arg:tt)*) => (
+        if $crate::kernel_components::klog::trace_enabled($crate::kernel_components::klog::TraceCategory::$category) {
+            $crate::printk!(Trace; concat!("[", stringify!($category), "] ", $fmt), $($arg)*);
+        }
+    );
+}
+
+#[test_case]
+fn printk_rate_limits_and_collapses() {
+    let site = RateLimit::new("test");
+    let interval = INTERVAL_CYCLES.load(Ordering::Relaxed);
+    let burst = BURST.load(Ordering::Relaxed);
+
+    for _ in 0..burst {
+        assert_eq!(site.allow(1), Some(0));
+    }
+    assert_eq!(site.allow(2), None);
+    assert_eq!(site.allow(3), None);
+    assert_eq!(site.missed(), 2);
+    assert_eq!(site.allow(1 + interval), Some(2));
+
+    static REPEATED: RateLimit = RateLimit::new("repeated");
+    assert!(_printk(&REPEATED, LogLevel::Trace, module_path!(), format_args!("same\n")));
+    assert!(!_printk(&REPEATED, LogLevel::Trace, module_path!(), format_args!("same\n")));
+    assert_eq!(LAST.lock().repeated, 1);
+    assert!(_printk(&REPEATED, LogLevel::Trace, module_path!(), format_args!("different\n")));
+    assert_eq!(LAST.lock().repeated, 0);
+}
+
+#[test_case]
+fn trace_categories_are_toggled() {
+    assert_eq!(parse_trace_mask("heap, acpi"), Some(TraceCategory::HEAP.bits() | TraceCategory::ACPI.bits()));
+    assert_eq!(parse_trace_mask("none"), Some(0));
+    assert_eq!(parse_trace_mask("all"), Some(0b1111));
+    assert_eq!(parse_trace_mask("heap,unknown"), None);
+
+    let previous = TRACE_MASK.load(Ordering::Relaxed);
+    set_trace_mask(0);
+    set_trace(TraceCategory::PAGING, true);
+    assert!(trace_enabled(TraceCategory::PAGING));
+    assert!(!trace_enabled(TraceCategory::HEAP));
+    set_trace(TraceCategory::PAGING, false);
+    assert!(!trace_enabled(TraceCategory::PAGING));
+    set_trace_mask(previous);
+}
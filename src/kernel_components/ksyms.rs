@@ -0,0 +1,116 @@
+//! Kernel symbol export table.
+//!
+//! Every symbol exported with [`export_symbol!`] is placed into the `.ksymtab` linker section.
+//! The linker script keeps this section and brackets it with `__ksymtab_start` and `__ksymtab_end`,
+//! so the whole table is available at runtime as a plain slice of [`KernelSymbol`] entries. The
+//! loadable module subsystem uses [`lookup`] to resolve module references against the running kernel.
+
+use core::fmt::Debug;
+
+/// One entry of the exported symbol table.
+#[repr(C)]
+pub struct KernelSymbol {
+    /// Name under which the symbol is exported.
+    pub name: &'static str,
+    /// Address of the symbol.
+    pub addr: *const (),
+}
+
+/// Table entries are immutable and only ever read.
+unsafe impl Sync for KernelSymbol {}
+
+impl KernelSymbol {
+    /// Returns the address of the symbol as a plain number.
+    #[inline]
+    pub fn address(&self) -> usize {
+        self.addr as usize
+    }
+}
+
+impl Debug for KernelSymbol {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:#018x} {}", self.address(), self.name)
+    }
+}
+
+extern "C" {
+    static __ksymtab_start: u8;
+    static __ksymtab_end: u8;
+}
+
+/// Exports a kernel function or static, making it resolvable with [`lookup`].
+///
+/// The symbol is exported under the name written in the invocation. An explicit name can be
+/// provided with `export_symbol!(path as "name")`.
+///
+/// # Examples
+///
+/// ```ignore
+/// export_symbol!(crate::kernel_components::vga_buffer::_print);
+/// export_symbol!(Thread::<'static>::sleep as "thread_sleep");
+/// ```
+#[macro_export]
+macro_rules! export_symbol {
+    ($sym:path as $name:expr) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".ksymtab"]
+            static __KSYM: $crate::kernel_components::ksyms::KernelSymbol =
+                $crate::kernel_components::ksyms::KernelSymbol {
+                    name: $name,
+                    addr: $sym as *const (),
+                };
+        };
+    };
+    ($sym:path) => {
+        $crate::export_symbol!($sym as stringify!($sym));
+    };
+}
+
+/// Returns the whole exported symbol table.
+pub fn symbols() -> &'static [KernelSymbol] {
+    unsafe {
+        let start = &__ksymtab_start as *const u8 as *const KernelSymbol;
+        let end = &__ksymtab_end as *const u8 as *const KernelSymbol;
+        let len = (end as usize - start as usize) / core::mem::size_of::<KernelSymbol>();
+        core::slice::from_raw_parts(start, len)
+    }
+}
+
+/// Resolves an exported symbol by name.
+///
+/// Returns the address of the symbol or None, if no symbol with such name was exported.
+pub fn lookup(name: &str) -> Option<usize> {
+    symbols().iter().find(|sym| sym.name == name).map(KernelSymbol::address)
+}
+
+/// Finds the exported symbol that contains the provided address.
+///
+/// Since the table holds no sizes, the closest exported symbol below the address is returned
+/// together with the offset from it.
+pub fn symbolize(addr: usize) -> Option<(&'static KernelSymbol, usize)> {
+    symbols().iter()
+        .filter(|sym| sym.address() <= addr)
+        .max_by_key(|sym| sym.address())
+        .map(|sym| (sym, addr - sym.address()))
+}
+
+use crate::kernel_components::task_virtualization::Thread;
+use crate::kernel_components::arch_x86_64::{interrupts::interrupt, pci};
+
+export_symbol!(crate::kernel_components::vga_buffer::_print as "_print");
+export_symbol!(Thread::<'static>::sleep as "thread_sleep");
+export_symbol!(Thread::<'static>::r#yield as "thread_yield");
+export_symbol!(interrupt::hlt as "hlt");
+export_symbol!(interrupt::is_interrupts_enabled as "is_interrupts_enabled");
+export_symbol!(pci::scan as "pci_scan");
+export_symbol!(lookup as "ksyms_lookup");
+
+#[test_case]
+fn exported_symbols_resolve() {
+    assert_eq!(lookup("ksyms_lookup"), Some(lookup as usize));
+    assert!(lookup("no_such_symbol").is_none());
+    let (sym, off) = symbolize(lookup as usize + 1).unwrap();
+    assert_eq!(sym.name, "ksyms_lookup");
+    assert_eq!(off, 1);
+}
@@ -0,0 +1,497 @@
+//! Kernel log records and their sinks.
+//!
+//! Every message of the [`log!`] family of macros, [`printk!`] and [`trace!`] included, becomes a
+//! [`Record`] with a [`LogLevel`] and a target, which is the module path of the call site without
+//! the crate name. A record is dropped unless it passes two filters:
+//!
+//! - The maximal level of the longest target filter matching the target, set with the
+//! "kernel.log_targets" tunable, i.e. "kernel.log_targets=memory::allocators=warn,acpi=debug".
+//! A filter matches the target itself and every module below it.
+//! - The global maximal level, set with the "kernel.log_level" tunable, if no filter matches.
+//!
+//! Before both of them, the macros check the same kind of filters at compile time, so disabled
+//! call sites are compiled out with their arguments:
+//!
+//! - The "max_level_*" cargo features set the [`STATIC_MAX_LEVEL`], i.e. "max_level_info" leaves
+//! out every debug and trace message.
+//! - The NOTOS_LOG environment variable of the build holds the [`STATIC_TARGET_FILTERS`], with the
+//! syntax of "kernel.log_targets", i.e. `NOTOS_LOG=memory::allocators=off cargo build`. A filter
+//! overrides the maximal level for it's targets in both directions.
+//!
+//! Records which pass are written to every [`LogSink`] whose own level allows them. The screen,
+//! the COM1 UART and the [`DMESG`] ring buffer are registered from the start, further sinks are
+//! added with [`register_sink`]. Sinks are never removed, only disabled.
+//!
+//! Plain [`print!`] and [`println!`] are console output, not log records, and only reach the
+//! screen. The same holds for [`isr_println!`], which must stay a plain copy.
+//!
+//! [`log!`]: crate::log
+//! [`printk!`]: crate::printk
+//! [`trace!`]: crate::trace
+//! [`print!`]: crate::print
+//! [`println!`]: crate::println
+//! [`isr_println!`]: crate::isr_println
+
+use core::cell::UnsafeCell;
+use core::error::Error;
+use core::fmt::{self, Display, Write};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::kernel_components::emergency;
+use crate::kernel_components::dmesg::DMESG;
+use crate::kernel_components::sync::IrqSpinlock;
+use crate::kernel_components::vga_buffer::{LogLevel, _print_level};
+
+/// Maximal amount of sinks, the built-in ones included.
+pub const MAX_SINKS: usize = 8;
+/// Level of the serial sink until changed with "kernel.log_serial". Debug output is left out,
+/// as the UART is polled and slow.
+pub const DEFAULT_SERIAL_LEVEL: LogLevel = LogLevel::Info;
+
+/// Encoded level of disabled sinks and filters.
+const OFF: u8 = 0;
+
+/// Encodes the maximal level, where None disables the output.
+const fn encode(level: Option<LogLevel>) -> u8 {
+    match level {
+        Some(level) => level as u8 + 1,
+        None => OFF,
+    }
+}
+
+fn decode(level: u8) -> Option<LogLevel> {
+    LogLevel::ALL.get((level as usize).checked_sub(1)?).copied()
+}
+
+/// Parses a level name, where "off" disables the output.
+pub fn parse_level(name: &str) -> Option<Option<LogLevel>> {
+    match name {
+        "off" => Some(None),
+        name => LogLevel::from_name(name).map(Some),
+    }
+}
+
+/// Custom error type for the log sinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogError {
+    /// Every slot for sinks is taken.
+    TooManySinks,
+    /// A sink with the same name is registered already.
+    DuplicateSink(&'static str),
+    /// No sink with such name.
+    UnknownSink,
+}
+
+impl Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManySinks => write!(f, "At most {} log sinks can be registered.", MAX_SINKS),
+            Self::DuplicateSink(name) => write!(f, "Log sink \"{}\" is registered already.", name),
+            Self::UnknownSink => write!(f, "No such log sink."),
+        }
+    }
+}
+
+impl Error for LogError {}
+
+/// Single kernel message.
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub level: LogLevel,
+    /// Module path of the call site without the crate name.
+    pub target: &'static str,
+    /// Message, which ends with a new line.
+    pub args: fmt::Arguments<'a>,
+}
+
+/// Destination of the log records.
+///
+/// Sinks may be called from any context, interrupt handlers included, so they must never wait
+/// for a lock. A record which can not be written right away is dropped or staged.
+pub trait LogSink: Sync {
+    /// Name used to configure the sink.
+    fn name(&self) -> &'static str;
+
+    /// Writes the record.
+    fn write(&self, record: &Record);
+}
+
+/// Slot states of the sink table.
+const FREE: u8 = 0;
+const CLAIMED: u8 = 1;
+const READY: u8 = 2;
+
+/// Entry of the sink table. The sink is written once while the slot is claimed and only read
+/// once it is ready, so the table is read without any lock.
+struct Slot {
+    state: AtomicU8,
+    level: AtomicU8,
+    sink: UnsafeCell<Option<&'static dyn LogSink>>,
+}
+
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    const fn free() -> Self {
+        Self { state: AtomicU8::new(FREE), level: AtomicU8::new(OFF), sink: UnsafeCell::new(None) }
+    }
+
+    const fn with(sink: &'static dyn LogSink, level: Option<LogLevel>) -> Self {
+        Self { state: AtomicU8::new(READY), level: AtomicU8::new(encode(level)), sink: UnsafeCell::new(Some(sink)) }
+    }
+
+    fn sink(&self) -> Option<&'static dyn LogSink> {
+        match self.state.load(Ordering::Acquire) {
+            READY => unsafe { *self.sink.get() },
+            _ => None,
+        }
+    }
+}
+
+static SINKS: [Slot; MAX_SINKS] = [
+    Slot::with(&ScreenSink, Some(LogLevel::Trace)),
+    Slot::with(&SerialSink, Some(DEFAULT_SERIAL_LEVEL)),
+    Slot::with(&DMESG, Some(LogLevel::Trace)),
+    Slot::free(), Slot::free(), Slot::free(), Slot::free(), Slot::free(),
+];
+
+/// Global maximal level.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(encode(Some(LogLevel::Trace)));
+/// Filters of the targets, sorted from the longest target.
+static TARGET_FILTERS: IrqSpinlock<Vec<TargetFilter>> = IrqSpinlock::new(Vec::new());
+/// Set while any target filter is, so no lock is taken without them.
+static HAS_TARGET_FILTERS: AtomicBool = AtomicBool::new(false);
+
+/// Adds a sink, which receives the records up to the level.
+pub fn register_sink(sink: &'static dyn LogSink, level: Option<LogLevel>) -> Result<(), LogError> {
+    if find_sink(sink.name()).is_some() {
+        return Err(LogError::DuplicateSink(sink.name()))
+    }
+    let slot = SINKS.iter()
+        .find(|slot| slot.state.compare_exchange(FREE, CLAIMED, Ordering::Acquire, Ordering::Relaxed).is_ok())
+        .ok_or(LogError::TooManySinks)?;
+    unsafe { *slot.sink.get() = Some(sink) };
+    slot.level.store(encode(level), Ordering::Relaxed);
+    slot.state.store(READY, Ordering::Release);
+    Ok(())
+}
+
+fn find_sink(name: &str) -> Option<&'static Slot> {
+    SINKS.iter().find(|slot| slot.sink().is_some_and(|sink| sink.name() == name))
+}
+
+/// Changes the maximal level of the sink, None disables it.
+pub fn set_sink_level(name: &str, level: Option<LogLevel>) -> Result<(), LogError> {
+    let slot = find_sink(name).ok_or(LogError::UnknownSink)?;
+    slot.level.store(encode(level), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns the maximal level of the sink.
+pub fn sink_level(name: &str) -> Result<Option<LogLevel>, LogError> {
+    find_sink(name).map(|slot| decode(slot.level.load(Ordering::Relaxed))).ok_or(LogError::UnknownSink)
+}
+
+/// Changes the global maximal level, None drops every record without a matching target filter.
+pub fn set_max_level(level: Option<LogLevel>) {
+    MAX_LEVEL.store(encode(level), Ordering::Relaxed);
+}
+
+/// Returns the global maximal level.
+pub fn max_level() -> Option<LogLevel> {
+    decode(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Maximal level of the records of a target and the modules below it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetFilter {
+    pub target: String,
+    pub level: Option<LogLevel>,
+}
+
+impl TargetFilter {
+    /// Checks if the filter applies to the target.
+    pub fn matches(&self, target: &str) -> bool {
+        target.strip_prefix(self.target.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    }
+}
+
+/// Parses a comma separated list of "target=level" filters. An empty list is valid.
+pub fn parse_target_filters(list: &str) -> Option<Vec<TargetFilter>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|filter| !filter.is_empty())
+        .map(|filter| {
+            let (target, level) = filter.split_once('=')?;
+            let target = target.trim();
+            match target.is_empty() {
+                true => None,
+                false => Some(TargetFilter { target: String::from(target), level: parse_level(level.trim())? }),
+            }
+        })
+        .collect()
+}
+
+/// Replaces the target filters.
+pub fn set_target_filters(mut filters: Vec<TargetFilter>) {
+    filters.sort_by(|a, b| b.target.len().cmp(&a.target.len()));
+    let mut current = TARGET_FILTERS.lock();
+    HAS_TARGET_FILTERS.store(!filters.is_empty(), Ordering::Release);
+    *current = filters;
+}
+
+/// Checks if a record of the target with the level passes the filters.
+///
+/// The target filters are skipped while they are being replaced.
+pub fn enabled(level: LogLevel, target: &str) -> bool {
+    let mut max = MAX_LEVEL.load(Ordering::Relaxed);
+    if HAS_TARGET_FILTERS.load(Ordering::Acquire) {
+        if let Some(filters) = TARGET_FILTERS.try_lock() {
+            if let Some(filter) = filters.iter().find(|filter| filter.matches(target)) {
+                max = encode(filter.level);
+            }
+        }
+    }
+    encode(Some(level)) <= max && max != OFF
+}
+
+/// Strips the crate name and the components module from the module path.
+pub fn short_target(module_path: &'static str) -> &'static str {
+    let path = module_path.split_once("::").map_or("", |(_, path)| path);
+    path.strip_prefix("kernel_components::").unwrap_or(path)
+}
+
+/// Maximal level compiled in, chosen with the "max_level_*" cargo features. The most restrictive
+/// feature wins, without any every level is compiled in.
+pub const STATIC_MAX_LEVEL: Option<LogLevel> = if cfg!(feature = "max_level_off") {
+    None
+} else if cfg!(feature = "max_level_error") {
+    Some(LogLevel::Error)
+} else if cfg!(feature = "max_level_warn") {
+    Some(LogLevel::Warning)
+} else if cfg!(feature = "max_level_info") {
+    Some(LogLevel::Info)
+} else if cfg!(feature = "max_level_debug") {
+    Some(LogLevel::Debug)
+} else {
+    Some(LogLevel::Trace)
+};
+
+/// Target filters compiled in, taken from the NOTOS_LOG environment variable of the build.
+pub const STATIC_TARGET_FILTERS: &str = match option_env!("NOTOS_LOG") {
+    Some(filters) => filters,
+    None => "",
+};
+
+/// Checks if a record of the module with the level is compiled in. Evaluated at compile time by
+/// the macros, where an invalid NOTOS_LOG fails the build.
+pub const fn static_enabled(level: LogLevel, module_path: &str) -> bool {
+    let target = const_short_target(module_path.as_bytes());
+    let max = match static_target_level(STATIC_TARGET_FILTERS.as_bytes(), target) {
+        Some(max) => max,
+        None => encode(STATIC_MAX_LEVEL),
+    };
+    encode(Some(level)) <= max && max != OFF
+}
+
+/// Returns the encoded level of the longest filter in the list matching the target.
+const fn static_target_level(filters: &[u8], target: &[u8]) -> Option<u8> {
+    let mut found = None;
+    let mut longest = 0;
+    let mut start = 0;
+    while start < filters.len() {
+        let mut end = start;
+        while end < filters.len() && filters[end] != b',' {
+            end += 1;
+        }
+        let filter = trim(bytes(filters, start, end));
+        start = end + 1;
+        if filter.is_empty() {
+            continue
+        }
+
+        let mut eq = 0;
+        while eq < filter.len() && filter[eq] != b'=' {
+            eq += 1;
+        }
+        let name = trim(bytes(filter, 0, eq));
+        if eq == filter.len() || name.is_empty() {
+            panic!("NOTOS_LOG: target filters must look like \"target=level\"")
+        }
+        let level = match const_parse_level(trim(bytes(filter, eq + 1, filter.len()))) {
+            Some(level) => level,
+            None => panic!("NOTOS_LOG: unknown log level"),
+        };
+
+        let matches = target.len() >= name.len()
+            && eq_bytes(bytes(target, 0, name.len()), name)
+            && (target.len() == name.len() || eq_bytes(bytes(target, name.len(), name.len() + 2), b"::"));
+        if matches && (found.is_none() || name.len() > longest) {
+            found = Some(level);
+            longest = name.len();
+        }
+    }
+    found
+}
+
+/// Same as [`short_target`], for constant evaluation.
+const fn const_short_target(path: &[u8]) -> &[u8] {
+    let mut i = 0;
+    while i + 1 < path.len() && !(path[i] == b':' && path[i + 1] == b':') {
+        i += 1;
+    }
+    if i + 1 >= path.len() {
+        return &[]
+    }
+    let path = bytes(path, i + 2, path.len());
+    let prefix = b"kernel_components::";
+    match path.len() >= prefix.len() && eq_bytes(bytes(path, 0, prefix.len()), prefix) {
+        true => bytes(path, prefix.len(), path.len()),
+        false => path,
+    }
+}
+
+/// Same as [`parse_level`], for constant evaluation. Returns the encoded level.
+const fn const_parse_level(name: &[u8]) -> Option<u8> {
+    if eq_bytes(name, b"off") {
+        return Some(OFF)
+    }
+    if eq_bytes(name, b"warning") {
+        return Some(encode(Some(LogLevel::Warning)))
+    }
+    let mut i = 0;
+    while i < LogLevel::ALL.len() {
+        if eq_bytes(name, LogLevel::ALL[i].name().as_bytes()) {
+            return Some(encode(Some(LogLevel::ALL[i])))
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Returns the bytes from start up to the end, clamped to the slice.
+const fn bytes(s: &[u8], start: usize, end: usize) -> &[u8] {
+    let end = if end < s.len() { end } else { s.len() };
+    let start = if start < end { start } else { end };
+    s.split_at(end).0.split_at(start).1
+}
+
+const fn trim(mut s: &[u8]) -> &[u8] {
+    while let [b' ', rest @ ..] = s {
+        s = rest;
+    }
+    while let [rest @ .., b' '] = s {
+        s = rest;
+    }
+    s
+}
+
+const fn eq_bytes(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Writes the record to the sinks, without checking the filters.
+pub fn dispatch(record: &Record) {
+    for slot in SINKS.iter() {
+        let Some(sink) = slot.sink() else { continue };
+        let level = slot.level.load(Ordering::Relaxed);
+        if level != OFF && encode(Some(record.level)) <= level {
+            sink.write(record);
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: LogLevel, module_path: &'static str, args: fmt::Arguments) {
+    let target = short_target(module_path);
+    if enabled(level, target) {
+        dispatch(&Record { level, target, args });
+    }
+}
+
+/// The VGA screen. Messages are printed with the style of their level, without the target.
+pub struct ScreenSink;
+
+impl LogSink for ScreenSink {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn write(&self, record: &Record) {
+        _print_level(record.level, record.args);
+    }
+}
+
+/// The COM1 UART, written through the lock free emergency output. Every record is prefixed
+/// with the level and the target.
+pub struct SerialSink;
+
+impl LogSink for SerialSink {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn write(&self, record: &Record) {
+        struct Uart;
+
+        impl Write for Uart {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                for line in s.split_inclusive('\n') {
+                    match line.strip_suffix('\n') {
+                        Some(line) => {
+                            emergency::serial_write(line.as_bytes());
+                            emergency::serial_write(b"\r\n");
+                        },
+                        None => emergency::serial_write(line.as_bytes()),
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        let _ = write!(Uart, "[{}] {}: {}", record.level.name(), record.target, record.args);
+    }
+}
+
+#[test_case]
+fn records_are_filtered_by_target() {
+    assert_eq!(short_target("notOS::kernel_components::memory::vdso"), "memory::vdso");
+    assert_eq!(parse_level("off"), Some(None));
+    assert_eq!(parse_level("warning"), Some(Some(LogLevel::Warning)));
+    assert!(parse_target_filters("memory=warn,=debug").is_none());
+    assert!(parse_target_filters("memory").is_none());
+
+    let filters = parse_target_filters("memory=warn, memory::allocators=off").unwrap();
+    set_target_filters(filters);
+    assert!(enabled(LogLevel::Error, "memory::vdso"));
+    assert!(!enabled(LogLevel::Info, "memory::vdso"));
+    assert!(!enabled(LogLevel::Error, "memory::allocators::free_list_alloc"));
+    assert!(enabled(LogLevel::Info, "memory_map"));
+    set_target_filters(Vec::new());
+    assert!(enabled(LogLevel::Trace, "memory::vdso"));
+
+    assert_eq!(register_sink(&ScreenSink, None), Err(LogError::DuplicateSink("vga")));
+    assert_eq!(set_sink_level("nonexistent", None), Err(LogError::UnknownSink));
+    assert_eq!(sink_level("dmesg"), Ok(Some(LogLevel::Trace)));
+
+    assert_eq!(const_short_target(b"notOS::kernel_components::memory::vdso"), b"memory::vdso");
+    assert!(const_short_target(b"notOS").is_empty());
+    let filters = b" memory=warn ,memory::allocators = off,acpi=debug,";
+    assert_eq!(static_target_level(filters, b"memory::allocators::free_list_alloc"), Some(OFF));
+    assert_eq!(static_target_level(filters, b"memory::vdso"), Some(encode(Some(LogLevel::Warning))));
+    assert_eq!(static_target_level(filters, b"memory_map"), None);
+    assert_eq!(static_target_level(b"", b"acpi"), None);
+}
@@ -31,8 +31,8 @@ use core::mem;
 use core::ptr::{self, NonNull};
 use core::sync::atomic::{AtomicUsize, Ordering, AtomicBool};
 
-/// Start address of the memory heap. Use any address as long as it is not used.
-pub const BUDDY_ALLOC_HEAP_START: usize = 0o_000_001_000_000_0000;
+/// Start address of the memory heap. See the heap region in `memory::layout`.
+pub const BUDDY_ALLOC_HEAP_START: usize = crate::kernel_components::memory::layout::HEAP_START;
 /// Maximal size of the whole arena. Adjust the size as needed.
 pub const BUDDY_ALLOC_HEAP_ARENA: usize = 128 * 1024;
 /// Constant size of the free-list node.
@@ -309,9 +309,7 @@ unsafe impl Allocator for BuddyAlloc {
 
                                 let return_ptr = (node as *const _ as usize + BUDDY_HEADER_SIZE) & align_mask;
 
-                                #[cfg(debug_assertions)] {
-                                    crate::println!("Allocating {} bytes at {:#x}, with node size of: {} bytes.", layout.size(), return_ptr, size);
-                                }
+                                crate::trace!(HEAP; "Allocating {} bytes at {:#x}, with node size of: {} bytes.", layout.size(), return_ptr, size);
 
                                 return Ok(NonNull::slice_from_raw_parts(
                                     NonNull::new(return_ptr as *mut u8).unwrap(),
@@ -372,9 +370,7 @@ unsafe impl Allocator for BuddyAlloc {
     /// While searches for the requested memory from the top, merges all buddies, which
     /// are freed, including the one which is about to be freed by this function call.
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-        #[cfg(debug_assertions)] {
-            crate::println!("Deallocating {} bytes from {:#x}", layout.size(), ptr.as_ptr() as usize);
-        }
+        crate::trace!(HEAP; "Deallocating {} bytes from {:#x}", layout.size(), ptr.as_ptr() as usize);
 
         let node_ptr = (ptr.as_ptr() as usize).saturating_sub(BUDDY_HEADER_SIZE);
 
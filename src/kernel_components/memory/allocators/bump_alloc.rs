@@ -18,8 +18,8 @@ use core::mem;
 use core::ptr::{NonNull, self};
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-/// Start address of the memory heap. Use any address as long as it is not used.
-pub const BUMP_ALLOC_HEAP_START: usize = 0o_000_001_000_000_0000;
+/// Start address of the memory heap. See the heap region in `memory::layout`.
+pub const BUMP_ALLOC_HEAP_START: usize = crate::kernel_components::memory::layout::HEAP_START;
 /// Maximal size of the whole arena. Adjust the size as needed.
 pub const BUMP_ALLOC_HEAP_ARENA: usize = 128 * 1024;
 
@@ -154,9 +154,7 @@ unsafe impl Allocator for BumpAlloc {
                     }
                 }
 
-                #[cfg(debug_assertions)] {
-                    crate::println!("Allocating {} bytes at {:#x}", layout.size(), current_next_ptr);
-                }
+                crate::trace!(HEAP; "Allocating {} bytes at {:#x}", layout.size(), current_next_ptr);
                 if let Ok(cas_current_next) = self.next_ptr.compare_exchange(
                     current_next_ptr,
                     end_alloc,
@@ -196,9 +194,7 @@ unsafe impl Allocator for BumpAlloc {
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
         // Check is the hole struct will fit in deallocated place.
         if mem::size_of::<BumpHole>() > layout.size() {
-            #[cfg(debug_assertions)] {
-                crate::println!("Ignoring the deallocation, because size is too small: {}", layout.size());
-            }
+            crate::trace!(HEAP; "Ignoring the deallocation, because size is too small: {}", layout.size());
             return
         }
 
@@ -238,9 +234,7 @@ unsafe impl Allocator for BumpAlloc {
                 // first and changed the next_ptr. This thread must retry again. 
                 continue
             }
-            #[cfg(debug_assertions)] {
-                crate::println!("Deallocating {} bytes from {:#x}", layout.size(), start_dealloc);
-            }
+            crate::trace!(HEAP; "Deallocating {} bytes from {:#x}", layout.size(), start_dealloc);
 
             break
         }
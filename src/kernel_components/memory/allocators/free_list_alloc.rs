@@ -52,8 +52,8 @@ use core::mem;
 use core::ptr::{self, NonNull};
 use core::sync::atomic::{AtomicUsize, Ordering, AtomicBool};
 
-/// Start address of the memory heap. Use any address as long as it is not used.
-pub const FREE_LIST_ALLOC_HEAP_START: usize = 0o_000_001_000_000_0000;
+/// Start address of the memory heap. See the heap region in `memory::layout`.
+pub const FREE_LIST_ALLOC_HEAP_START: usize = crate::kernel_components::memory::layout::HEAP_START;
 /// Maximal size of the whole arena. Adjust the size as needed.
 pub const FREE_LIST_ALLOC_HEAP_ARENA: usize = 128 * 1024;
 /// Constant size of the free-list node.
@@ -148,7 +148,7 @@ impl FreeListAlloc {
         }
 
         if !self.initialized.load(Ordering::Relaxed) {
-            crate::println!(crate::Color::YELLOW; "The allocator is not initialized yet.");
+            crate::log!(Warning; "The allocator is not initialized yet.");
         } else {
             crate::println!();
         }
@@ -340,9 +340,7 @@ unsafe impl Allocator for FreeListAlloc {
                 
                 let return_ptr = (node as *const _ as usize + NODE_HEADER_SIZE) & align_mask;
                 
-                //#[cfg(debug_assertions)] {
-                //   crate::println!("Allocating {} bytes at {:#x}", layout.size(), return_ptr);
-                //}
+                crate::trace!(HEAP; "Allocating {} bytes at {:#x}", layout.size(), return_ptr);
                 
                 return Ok(NonNull::slice_from_raw_parts(
                     NonNull::new(return_ptr as *mut u8).unwrap(),
@@ -433,9 +431,7 @@ unsafe impl Allocator for FreeListAlloc {
                 next_node = node.next.load(Ordering::Acquire);
             }
     
-            //#[cfg(debug_assertions)] {
-            //    crate::println!("Deallocating {} bytes from {:#x}", layout.size(), ptr.as_ptr() as usize);
-            //}
+            crate::trace!(HEAP; "Deallocating {} bytes from {:#x}", layout.size(), ptr.as_ptr() as usize);
     
             break
         }
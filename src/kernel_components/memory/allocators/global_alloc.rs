@@ -13,6 +13,7 @@ use core::fmt::Debug;
 use super::*;
 use crate::{single, critical_section};
 use crate::kernel_components::structures::Single;
+use crate::kernel_components::drivers::accounting;
 use core::sync::atomic::{
     AtomicUsize,
     Ordering::SeqCst,
@@ -131,6 +132,71 @@ impl GAllocator {
     }
 }
 
+/// Amount of bytes currently allocated from the global allocator.
+static HEAP_USED: AtomicUsize = AtomicUsize::new(0);
+
+impl GAllocator {
+    /// Returns the amount of bytes currently allocated from the heap.
+    ///
+    /// Redzones and the bookkeeping of the inner allocator are not counted.
+    #[inline]
+    pub fn used(&self) -> usize {
+        HEAP_USED.load(SeqCst)
+    }
+
+    /// Allocates memory with the inner allocator.
+    ///
+    /// When the inner allocator fails, registered memory shrinkers are invoked at the critical
+    /// pressure level and the allocation is retried once. Pages of the process mappings are not
+    /// evicted here, see [`pressure::check`]. Successful allocations are charged to the driver
+    /// running at the moment, if any.
+    ///
+    /// [`pressure::check`]: crate::kernel_components::memory::pressure::check
+    fn inner_allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
+        use crate::kernel_components::memory::pressure::{self, PressureLevel};
+
+        let result = self.raw_allocate(layout).or_else(|err| {
+            match pressure::shrink(PressureLevel::Critical) {
+                0 => Err(err),
+                _ => self.raw_allocate(layout),
+            }
+        });
+        if result.is_ok() {
+            HEAP_USED.fetch_add(layout.size(), SeqCst);
+            accounting::charge_heap(layout.size());
+        }
+        result
+    }
+
+    /// Allocates memory with the inner allocator.
+    ///
+    /// In debug builds every allocation is surrounded with redzones, which are verified on free.
+    fn raw_allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
+        #[cfg(debug_assertions)] {
+            self.allocator.allocate(redzone::outer_layout(layout)).map(|block| unsafe {
+                let ptr = redzone::arm(block.as_mut_ptr(), layout);
+                NonNull::slice_from_raw_parts(NonNull::new_unchecked(ptr), layout.size())
+            })
+        }
+        #[cfg(not(debug_assertions))] {
+            self.allocator.allocate(layout)
+        }
+    }
+
+    /// Deallocates memory with the inner allocator, verifying the redzones in debug builds.
+    unsafe fn inner_deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        HEAP_USED.fetch_sub(layout.size(), SeqCst);
+        accounting::uncharge_heap(layout.size());
+        #[cfg(debug_assertions)] {
+            let base = redzone::disarm(ptr.as_ptr(), layout);
+            self.allocator.deallocate(NonNull::new_unchecked(base), redzone::outer_layout(layout))
+        }
+        #[cfg(not(debug_assertions))] {
+            self.allocator.deallocate(ptr, layout)
+        }
+    }
+}
+
 unsafe impl Sync for GAllocator {}
 
 unsafe impl GlobalAlloc for GAllocator {
@@ -141,7 +207,7 @@ unsafe impl GlobalAlloc for GAllocator {
     /// Returns a pointer to the allocated memory block, or panics, if the pointer is null.
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         critical_section!(|| {
-            match self.allocator.allocate(layout) {
+            match self.inner_allocate(layout) {
                 Ok(address) => address.as_mut_ptr(),
                 Err(alloc_error) => panic!("Allocation error: {alloc_error}. Memory overflow.")
             }
@@ -151,7 +217,7 @@ unsafe impl GlobalAlloc for GAllocator {
     /// This function calls the inner allocator's deallocate function.
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         critical_section!(|| {
-            self.allocator.deallocate(
+            self.inner_deallocate(
                 NonNull::new(ptr).unwrap(),
                 layout,
             )
@@ -163,14 +229,14 @@ unsafe impl Allocator for GAllocator {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
         unsafe {
             critical_section!(|| {
-                self.allocator.allocate(layout)
+                self.inner_allocate(layout)
             })
         }
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
         critical_section!(|| {
-            self.allocator.deallocate(ptr, layout)
+            self.inner_deallocate(ptr, layout)
         });
     }
 }
@@ -20,8 +20,8 @@ use core::alloc::{Allocator, Layout, GlobalAlloc, AllocError};
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-/// Start address of the memory heap. Use any address as long as it is not used.
-pub const LEAK_ALLOC_HEAP_START: usize = 0o_000_001_000_000_0000;
+/// Start address of the memory heap. See the heap region in `memory::layout`.
+pub const LEAK_ALLOC_HEAP_START: usize = crate::kernel_components::memory::layout::HEAP_START;
 /// Maximal size of the whole arena. Adjust the size as needed.
 pub const LEAK_ALLOC_HEAP_ARENA: usize = 128 * 1024;
 
@@ -115,9 +115,7 @@ unsafe impl Allocator for LeakAlloc {
             let end_alloc = start_alloc.saturating_add(layout.size());
 
             if end_alloc <= self.end_ptr_addr() {
-                #[cfg(debug_assertions)] {
-                    crate::println!("Allocating {} bytes at {:#x}", layout.size(), current_next_ptr);
-                }
+                crate::trace!(HEAP; "Allocating {} bytes at {:#x}", layout.size(), current_next_ptr);
                 if let Ok(cas_current_next) = self.next_ptr.compare_exchange(
                     current_next_ptr,
                     end_alloc,
@@ -42,8 +42,8 @@ use core::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
 use core::cell::UnsafeCell;
 use core::marker::PhantomData;
 
-/// Start address of the memory heap. Use any address as long as it is not used.
-pub const NODE_ALLOC_HEAP_START: usize = 0o_000_001_000_000_0000;
+/// Start address of the memory heap. See the heap region in `memory::layout`.
+pub const NODE_ALLOC_HEAP_START: usize = crate::kernel_components::memory::layout::HEAP_START;
 /// Maximal size of the whole arena.
 pub const NODE_ALLOC_HEAP_ARENA: usize = NODE_AMOUNT * NODE_SIZE;
 /// Overall amount of nodes in the allocator. The arena size of allocator will be decided as NODE_AMOUNT * NODE_SIZE.
@@ -184,9 +184,7 @@ unsafe impl Allocator for NodeAlloc {
                             cas_counter += 1;
                         }
 
-                        #[cfg(debug_assertions)] {
-                            crate::println!("Allocating {} bytes at {:#x}", layout.size(), node.addr);
-                        }
+                        crate::trace!(HEAP; "Allocating {} bytes at {:#x}", layout.size(), node.addr);
 
                         // After all nodes are noted as used, return the pointer to the first node.
                         return Ok(NonNull::slice_from_raw_parts(
@@ -218,9 +216,7 @@ unsafe impl Allocator for NodeAlloc {
         let start_id = (ptr.as_ptr() as usize - NODE_ALLOC_HEAP_START) / self.node_size; 
         let end_id = start_id + layout.size() / self.node_size;
         
-        #[cfg(debug_assertions)] {
-            crate::println!("Deallocating {} bytes from {:#x}", layout.size(), ptr.as_ptr() as usize);
-        }
+        crate::trace!(HEAP; "Deallocating {} bytes from {:#x}", layout.size(), ptr.as_ptr() as usize);
 
         // Since we own the allocated memory region, we can simply deallocate the memory
         // nodes with cas operations, and it would fail only when other thread did so first.
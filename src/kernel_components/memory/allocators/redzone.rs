@@ -0,0 +1,222 @@
+/// Redzone checking for heap allocations.
+///
+/// In debug builds the global allocator surrounds every allocation with redzones filled with
+/// a known pattern. The redzones are verified when the allocation is freed and periodically by
+/// a background checker thread, which turns heap overflows into immediate diagnostics instead of
+/// silently corrupted allocator nodes.
+///
+/// The front redzone starts with a header that links all live allocations into an intrusive list,
+/// therefore no additional memory is required for tracking. The layout of one allocation is:
+///
+/// | header | front pattern | user data | tail pattern |
+///
+/// The header is protected with a checksum, so a corrupted header is diagnosed as well.
+
+use core::alloc::Layout;
+use core::mem::{size_of, align_of};
+use core::ptr::null_mut;
+
+use crate::kernel_components::task_virtualization::Thread;
+use crate::critical_section;
+
+/// Pattern written into redzones.
+pub const REDZONE_PATTERN: u8 = 0xfb;
+/// Minimal amount of pattern bytes on each side of the allocation.
+pub const REDZONE_SIZE: usize = 16;
+/// Interval between two full checks of the background checker in milliseconds.
+pub const CHECK_INTERVAL_MS: u32 = 1000;
+
+const HEADER_MAGIC: usize = 0x5a5a_7e6e_d20e_c0de;
+
+/// Head of the list of live allocations.
+static mut LIVE_ALLOCATIONS: *mut RedzoneHeader = null_mut();
+
+/// Header placed at the beginning of the front redzone.
+#[repr(C)]
+struct RedzoneHeader {
+    size: usize,
+    front: usize,
+    prev: *mut RedzoneHeader,
+    next: *mut RedzoneHeader,
+    checksum: usize,
+}
+
+impl RedzoneHeader {
+    fn compute_checksum(&self) -> usize {
+        HEADER_MAGIC ^ self.size ^ self.front.rotate_left(16) ^
+            (self.prev as usize).rotate_left(32) ^ (self.next as usize).rotate_left(48)
+    }
+
+    fn seal(&mut self) {
+        self.checksum = self.compute_checksum();
+    }
+
+    /// Returns the pointer to the user data.
+    fn data(&self) -> *mut u8 {
+        unsafe { (self as *const Self as *mut u8).add(self.front) }
+    }
+}
+
+/// Returns the size of the front redzone for the provided layout.
+fn front_size(layout: Layout) -> usize {
+    let min = size_of::<RedzoneHeader>() + REDZONE_SIZE;
+    min.next_multiple_of(layout.align().max(align_of::<RedzoneHeader>()))
+}
+
+/// Returns the layout that must be requested from the underlying allocator.
+pub fn outer_layout(layout: Layout) -> Layout {
+    Layout::from_size_align(
+        front_size(layout) + layout.size() + REDZONE_SIZE,
+        layout.align().max(align_of::<RedzoneHeader>()),
+    ).expect("Redzone layout overflow.")
+}
+
+/// Fills the redzones of a freshly allocated outer block and links it to the list of live
+/// allocations.
+///
+/// Returns the pointer which must be handed to the user.
+///
+/// # Safety
+///
+/// The base pointer must point to the block allocated with outer_layout(layout). Must be called
+/// within a critical section.
+pub unsafe fn arm(base: *mut u8, layout: Layout) -> *mut u8 {
+    let front = front_size(layout);
+    let header = base as *mut RedzoneHeader;
+
+    header.write(RedzoneHeader {
+        size: layout.size(),
+        front,
+        prev: null_mut(),
+        next: LIVE_ALLOCATIONS,
+        checksum: 0,
+    });
+    (*header).seal();
+
+    if let Some(next) = LIVE_ALLOCATIONS.as_mut() {
+        next.prev = header;
+        next.seal();
+    }
+    LIVE_ALLOCATIONS = header;
+
+    let data = base.add(front);
+    let header_end = base.add(size_of::<RedzoneHeader>());
+    header_end.write_bytes(REDZONE_PATTERN, data as usize - header_end as usize);
+    data.add(layout.size()).write_bytes(REDZONE_PATTERN, REDZONE_SIZE);
+
+    data
+}
+
+/// Verifies the redzones of an allocation and unlinks it from the list of live allocations.
+///
+/// Returns the base pointer that must be returned to the underlying allocator.
+///
+/// # Panics
+///
+/// Panics with a diagnostic message if any of the redzones was overwritten.
+///
+/// # Safety
+///
+/// The pointer must be previously returned from arm() with the same layout. Must be called within
+/// a critical section.
+pub unsafe fn disarm(ptr: *mut u8, layout: Layout) -> *mut u8 {
+    let base = ptr.sub(front_size(layout));
+    let header = &mut *(base as *mut RedzoneHeader);
+
+    verify(header);
+    assert_eq!(
+        header.size, layout.size(),
+        "Heap allocation {:#x} is freed with a wrong layout.", ptr as usize
+    );
+
+    if let Some(prev) = header.prev.as_mut() {
+        prev.next = header.next;
+        prev.seal();
+    } else {
+        LIVE_ALLOCATIONS = header.next;
+    }
+    if let Some(next) = header.next.as_mut() {
+        next.prev = header.prev;
+        next.seal();
+    }
+
+    // Poisoning the header to catch double frees.
+    header.checksum = !header.compute_checksum();
+    base
+}
+
+/// Verifies redzones of every live allocation.
+///
+/// Returns the amount of checked allocations.
+///
+/// # Panics
+///
+/// Panics with a diagnostic message on the first corrupted allocation.
+pub fn check_all() -> usize {
+    unsafe {
+        critical_section!(|| {
+            let mut count = 0;
+            let mut current = LIVE_ALLOCATIONS;
+
+            while let Some(header) = current.as_ref() {
+                verify(header);
+                current = header.next;
+                count += 1;
+            }
+            count
+        })
+    }
+}
+
+/// Background thread that periodically checks all live allocations.
+pub fn checker(_: &mut Thread) {
+    loop {
+        check_all();
+        Thread::sleep(CHECK_INTERVAL_MS);
+    }
+}
+
+/// Checks one allocation and panics with a diagnostic on corruption.
+unsafe fn verify(header: &RedzoneHeader) {
+    let base = header as *const RedzoneHeader as usize;
+
+    if header.checksum != header.compute_checksum() {
+        panic!(
+            "Heap redzone header at {:#x} is corrupted (double free or underflow of a previous allocation).",
+            base
+        );
+    }
+
+    let data = header.data();
+    let front_start = (base + size_of::<RedzoneHeader>()) as *const u8;
+    let front = core::slice::from_raw_parts(front_start, data as usize - front_start as usize);
+    let tail = core::slice::from_raw_parts(data.add(header.size), REDZONE_SIZE);
+
+    if let Some(pos) = front.iter().rposition(|&b| b != REDZONE_PATTERN) {
+        panic!(
+            "Heap underflow: {} byte(s) before allocation {:#x} ({} bytes) were overwritten.",
+            front.len() - pos, data as usize, header.size
+        );
+    }
+
+    if let Some(pos) = tail.iter().position(|&b| b != REDZONE_PATTERN) {
+        panic!(
+            "Heap overflow: byte {} past the end of allocation {:#x} ({} bytes) was overwritten.",
+            pos, data as usize, header.size
+        );
+    }
+}
+
+#[test_case]
+#[cfg(debug_assertions)]
+fn redzones_surround_allocations() {
+    use alloc::vec::Vec;
+
+    let mut v: Vec<u8> = Vec::with_capacity(13);
+    v.extend_from_slice(&[0; 13]);
+
+    let ptr = v.as_ptr();
+    let tail = unsafe { core::slice::from_raw_parts(ptr.add(13), REDZONE_SIZE) };
+    assert!(tail.iter().all(|&b| b == REDZONE_PATTERN));
+    assert!(check_all() >= 1);
+}
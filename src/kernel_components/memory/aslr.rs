@@ -0,0 +1,115 @@
+//! Address space layout randomization of user programs.
+//!
+//! The ELF loader asks [`UserLayout::new`] where to place the program image, the initial stack
+//! and the start of the heap within the mmap window of the process. With randomization enabled,
+//! position independent images are slid by a random amount of pages within the first
+//! [`IMAGE_REGION`] bytes of the window, the heap starts a random gap after the image and the
+//! stack top lies a random gap below the end of the window. Images linked at a fixed base keep
+//! it, only their heap and stack are randomized.
+//!
+//! The offsets are drawn from RDRAND, which is the only source of cryptographically secure
+//! random numbers available to the kernel. If the processor keeps failing to provide one, the
+//! layout silently falls back to the fixed one.
+//!
+//! Randomization can be disabled for debugging with the "mm.randomize_va_space" tunable, also on
+//! the kernel command line. The kernel has no ELF loader yet, so nothing calls this for now.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::kernel_components::arch_x86_64::RdRand;
+use crate::VirtualAddress;
+
+use super::frames::PAGE_SIZE;
+use super::vma::{VmaError, MMAP_WINDOW};
+
+/// Part of the window at it's start in which position independent images are placed.
+pub const IMAGE_REGION: usize = 256 << 20;
+/// Upper bound of the random gap between the end of the image and the start of the heap.
+pub const HEAP_GAP: usize = 32 << 20;
+/// Upper bound of the random gap between the end of the window and the stack top.
+pub const STACK_GAP: usize = 64 << 20;
+/// Attempts to get a random number before RDRAND is considered unavailable.
+const RDRAND_RETRIES: usize = 10;
+
+/// Enables the randomization of user layouts. Tunable with "mm.randomize_va_space".
+pub static ASLR_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Placement of a loaded user program within it's window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserLayout {
+    /// Address at which the first segment of the image is loaded.
+    pub image_base: VirtualAddress,
+    /// First address of the heap, right after the random gap behind the image.
+    pub heap_start: VirtualAddress,
+    /// Initial stack pointer, the stack grows down from here.
+    pub stack_top: VirtualAddress,
+}
+
+impl UserLayout {
+    /// Computes the layout of a program with an image of the provided length.
+    ///
+    /// `fixed_base` is the link address of images which are not position independent. The
+    /// window is the one of the process address space.
+    pub fn new(window: VirtualAddress, image_len: usize, fixed_base: Option<VirtualAddress>) -> Result<Self, VmaError> {
+        Self::with_randomization(window, image_len, fixed_base, ASLR_ENABLED.load(Ordering::Relaxed))
+    }
+
+    fn with_randomization(
+        window: VirtualAddress,
+        image_len: usize,
+        fixed_base: Option<VirtualAddress>,
+        randomize: bool,
+    ) -> Result<Self, VmaError> {
+        if image_len == 0 {
+            return Err(VmaError::InvalidLength)
+        }
+        let image_len = image_len.next_multiple_of(PAGE_SIZE);
+        let random_offset = |limit: usize| match randomize {
+            true => random_pages(limit / PAGE_SIZE) * PAGE_SIZE,
+            false => 0,
+        };
+
+        let image_base = match fixed_base {
+            Some(base) => base,
+            None => {
+                let slide = IMAGE_REGION.checked_sub(image_len).ok_or(VmaError::NoVirtualSpace)?;
+                window + random_offset(slide)
+            },
+        };
+        let heap_start = (image_base + image_len).next_multiple_of(PAGE_SIZE) + random_offset(HEAP_GAP);
+        let stack_top = window + MMAP_WINDOW - random_offset(STACK_GAP);
+
+        Ok(Self { image_base, heap_start, stack_top })
+    }
+}
+
+/// Returns a random amount of pages below the limit, or zero without a random number source.
+fn random_pages(limit: usize) -> usize {
+    if limit == 0 {
+        return 0
+    }
+    RdRand::new()
+        .and_then(|rng| (0..RDRAND_RETRIES).find_map(|_| rng.get_u64()))
+        .map_or(0, |value| value as usize % limit)
+}
+
+#[test_case]
+fn randomized_layout_stays_within_window() {
+    let window = super::vma::MMAP_BASE + 5 * MMAP_WINDOW;
+
+    let fixed = UserLayout::with_randomization(window, 0x1800, None, false).unwrap();
+    assert_eq!(fixed, UserLayout { image_base: window, heap_start: window + 0x2000, stack_top: window + MMAP_WINDOW });
+
+    for _ in 0..16 {
+        let layout = UserLayout::with_randomization(window, 0x1800, None, true).unwrap();
+        assert!([layout.image_base, layout.heap_start, layout.stack_top].iter().all(|addr| addr % PAGE_SIZE == 0));
+        assert!(layout.image_base >= window && layout.image_base + 0x2000 <= window + IMAGE_REGION);
+        assert!(layout.heap_start >= layout.image_base + 0x2000 && layout.heap_start < layout.image_base + 0x2000 + HEAP_GAP);
+        assert!(layout.stack_top > window + MMAP_WINDOW - STACK_GAP && layout.stack_top <= window + MMAP_WINDOW);
+    }
+
+    let linked = UserLayout::with_randomization(window, 0x1000, Some(0x40_0000), true).unwrap();
+    assert_eq!(linked.image_base, 0x40_0000);
+    assert_eq!(UserLayout::new(window, 0, None), Err(VmaError::InvalidLength));
+    assert_eq!(UserLayout::new(window, IMAGE_REGION + 1, None), Err(VmaError::NoVirtualSpace));
+}
@@ -0,0 +1,198 @@
+//! Early boot memory allocator.
+//!
+//! Before the kernel is remapped there is neither a frame allocator nor a heap. [`BootMem`] hands
+//! out physical memory from the areas which the memory map reports as available, skipping the
+//! kernel image and the multiboot structure. Allocations only grow upwards and are never freed.
+//! The bootloader identity maps the first GiB, so returned addresses are usable right away.
+//!
+//! When the memory is initialized, the boot allocator is turned into the frame allocator, which
+//! continues right after the last early allocation, and the used range stays identity mapped.
+
+use crate::{single, PhysicalAddress};
+
+use super::frames::{Frame, AreaFrameAllocator, PAGE_SIZE};
+use super::memory_map::{MemoryAreaIter, MemoryAreaType};
+use super::memory_module::{InfoPointer, BootInfoHeader};
+use super::layout;
+
+/// Boot allocator, which is available between [`init`] and the memory initialization.
+single! {
+    pub mut BOOTMEM: Option<BootMem> = None;
+}
+
+/// Bump allocator over the available memory areas.
+#[derive(Debug, Clone)]
+pub struct BootMem {
+    areas: MemoryAreaIter,
+    kernel: (PhysicalAddress, PhysicalAddress),
+    multiboot: (PhysicalAddress, PhysicalAddress),
+    start: Option<PhysicalAddress>,
+    next: PhysicalAddress,
+}
+
+impl BootMem {
+    /// Creates a new boot allocator. The kernel and multiboot boundaries are inclusive.
+    pub fn new(
+        kernel_start: usize,
+        kernel_end: usize,
+        multiboot_start: usize,
+        multiboot_end: usize,
+        memory_areas: MemoryAreaIter,
+    ) -> Self {
+        Self {
+            areas: memory_areas,
+            kernel: (kernel_start, kernel_end + 1),
+            multiboot: (multiboot_start, multiboot_end + 1),
+            start: None,
+            next: 0,
+        }
+    }
+
+    /// Creates a new boot allocator over the memory map of the multiboot structure.
+    ///
+    /// Returns None if the memory map tag is not provided.
+    pub fn from_boot_info(boot_info: &InfoPointer) -> Option<Self> {
+        let areas = boot_info.memory_map_tag()?.memory_map_iter();
+        Some(Self::new(
+            boot_info.kstart() as usize,
+            boot_info.kend() as usize,
+            boot_info.mstart(),
+            boot_info.mend(),
+            areas,
+        ))
+    }
+
+    /// Allocates size bytes aligned to the power of two alignment.
+    ///
+    /// Returns the physical address, which is identity mapped until the kernel is remapped, or
+    /// None if no available area below the identity mapped boot region can hold the allocation.
+    pub fn alloc(&mut self, size: usize, align: usize) -> Option<PhysicalAddress> {
+        if size == 0 || !align.is_power_of_two() {
+            return None
+        }
+
+        let mut addr = self.next.checked_next_multiple_of(align)?;
+        loop {
+            let end = addr.checked_add(size)?;
+            if end > layout::IDENTITY_END {
+                return None
+            }
+
+            let fits = self.available().any(|(start, area_end)| addr >= start && end <= area_end);
+            if !fits {
+                // Jumping to the next available area.
+                addr = self.available()
+                    .map(|(start, _)| start)
+                    .filter(|&start| start > addr)
+                    .min()?
+                    .checked_next_multiple_of(align)?;
+                continue
+            }
+
+            if let Some(&(_, reserved_end)) = [self.kernel, self.multiboot].iter()
+                .find(|&&(start, reserved_end)| addr < reserved_end && start < end)
+            {
+                addr = reserved_end.checked_next_multiple_of(align)?;
+                continue
+            }
+
+            self.start.get_or_insert(addr);
+            self.next = end;
+            return Some(addr)
+        }
+    }
+
+    /// Allocates size bytes aligned to the alignment and fills them with zeroes.
+    pub fn alloc_zeroed(&mut self, size: usize, align: usize) -> Option<PhysicalAddress> {
+        let addr = self.alloc(size, align)?;
+        unsafe { core::ptr::write_bytes(addr as *mut u8, 0, size) };
+        Some(addr)
+    }
+
+    /// Allocates a single frame.
+    pub fn alloc_frame(&mut self) -> Option<Frame> {
+        self.alloc(PAGE_SIZE, PAGE_SIZE).map(Frame::info_address)
+    }
+
+    /// Returns the range of physical memory used by the early allocations. The end is exclusive.
+    pub fn allocated(&self) -> Option<(PhysicalAddress, PhysicalAddress)> {
+        self.start.map(|start| (start, self.next))
+    }
+
+    /// Returns the amount of bytes used by the early allocations, including the alignment gaps.
+    pub fn used(&self) -> usize {
+        self.allocated().map_or(0, |(start, end)| end - start)
+    }
+
+    /// Turns the boot allocator into the frame allocator, which never returns the frames used by
+    /// the early allocations.
+    pub fn into_frame_allocator(self) -> AreaFrameAllocator {
+        let mut allocator = AreaFrameAllocator::new(
+            self.kernel.0,
+            self.kernel.1 - 1,
+            self.multiboot.0,
+            self.multiboot.1 - 1,
+            self.areas,
+        );
+        if self.start.is_some() {
+            allocator.skip_to(Frame::info_address(self.next.next_multiple_of(PAGE_SIZE)));
+        }
+        allocator
+    }
+
+    /// Iterates over the available areas as ranges with exclusive ends.
+    fn available(&self) -> impl Iterator<Item = (PhysicalAddress, PhysicalAddress)> {
+        self.areas.clone()
+            .filter(|area| area.area_type() == MemoryAreaType::Available)
+            .map(|area| (area.start_address() as usize, area.end_address() as usize))
+    }
+}
+
+/// Creates the boot allocator from the multiboot information structure.
+///
+/// Does nothing if the boot allocator exists already or the memory is initialized.
+pub fn init(multiboot_information_address: usize) {
+    let bootmem = unsafe { &mut *BOOTMEM };
+    if bootmem.is_some() || unsafe { super::MEMORY_MANAGEMENT_UNIT.is_initialized() } {
+        return
+    }
+
+    let boot_info = unsafe {
+        InfoPointer::load(multiboot_information_address as *const BootInfoHeader)
+    }.expect("Invalid multiboot information structure.");
+    *bootmem = BootMem::from_boot_info(&boot_info);
+}
+
+/// Allocates early boot memory.
+///
+/// Returns None once the memory is initialized, the heap and frame allocator must be used then.
+pub fn alloc(size: usize, align: usize) -> Option<PhysicalAddress> {
+    unsafe { BOOTMEM.as_mut()?.alloc(size, align) }
+}
+
+/// Removes the boot allocator so no more early allocations can be made.
+pub(crate) fn take() -> Option<BootMem> {
+    unsafe { BOOTMEM.take() }
+}
+
+#[test_case]
+fn bootmem_skips_reserved_memory() {
+    use alloc::{boxed::Box, vec};
+    use super::memory_map::MemoryArea;
+
+    let areas = vec![
+        MemoryArea::new(0x1000, 0x3000, MemoryAreaType::Available),
+        MemoryArea::new(0x4000, 0x4000, MemoryAreaType::Reserved),
+        MemoryArea::new(0x10000, 0x10000, MemoryAreaType::Available),
+    ].into_boxed_slice();
+    // Kernel occupies the beginning of the second available area.
+    let areas = MemoryAreaIter::from_areas(Box::leak(areas));
+    let mut bootmem = BootMem::new(0x10000, 0x11fff, 0x1000, 0x1fff, areas);
+
+    assert_eq!(bootmem.alloc(0x800, 8), Some(0x2000));
+    assert_eq!(bootmem.alloc(0x1000, PAGE_SIZE), Some(0x3000));
+    assert_eq!(bootmem.alloc(0x1000, PAGE_SIZE), Some(0x12000));
+    assert_eq!(bootmem.allocated(), Some((0x2000, 0x13000)));
+    assert_eq!(bootmem.alloc(0x100000, PAGE_SIZE), None);
+    assert_eq!(bootmem.alloc(1, 3), None);
+}
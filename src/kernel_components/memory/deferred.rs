@@ -0,0 +1,183 @@
+//! Frames freed after a grace period.
+//!
+//! A frame which was mapped a moment ago is not free yet for the other processors. Within a
+//! shootdown [`Batch`] their TLBs still hold the old translation until the batch is dropped, and an
+//! interrupt handler running with interrupts disabled may be in the middle of a walk through the
+//! tables right now. Frames unmapped this way are passed to [`defer`] instead of being returned to
+//! the frame allocator at once.
+//!
+//! Deferred frames are tagged with a new epoch. Each processor records the latest epoch whenever
+//! it passes a quiescent state, which is any point where it is outside of a page table walk: the
+//! timer tick, the shootdown IPI and the idle loop. Once every online processor saw the epoch of a
+//! frame and no batch is open anymore, nothing can reach the frame and [`reclaim`] returns it to
+//! the frame allocator. Reclaiming happens on the next frame allocation.
+//!
+//! With a single processor the frame is freed right away. If the queue is full, the expired frames
+//! are reclaimed first. If that does not make room, every TLB is flushed synchronously and the
+//! oldest frame is freed once the other processors passed a quiescent state, see [`stalls`].
+//!
+//! [`Batch`]: crate::kernel_components::arch_x86_64::shootdown::Batch
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::kernel_components::arch_x86_64::shootdown::{self, Batch};
+use crate::kernel_components::arch_x86_64::smp::{self, MAX_CPUS};
+use crate::kernel_components::sync::IrqSpinlock;
+
+use super::frames::Frame;
+use super::memory_module::MMU;
+
+/// Maximal amount of frames waiting for their grace period.
+pub const DEFERRED_CAPACITY: usize = 256;
+
+/// Epoch of the latest deferred frame.
+static EPOCH: AtomicU64 = AtomicU64::new(0);
+/// Latest epoch seen by each processor in a quiescent state.
+static SEEN: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+/// Frames waiting for their grace period, ordered by the epoch.
+static QUEUE: IrqSpinlock<Retired> = IrqSpinlock::new(Retired::new());
+/// Amount of frames in the queue, read without the lock.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+/// Times the queue was full and the grace period of the oldest frame was awaited synchronously.
+static STALLS: AtomicU64 = AtomicU64::new(0);
+
+/// Ring of the numbers of deferred frames with their epochs.
+struct Retired {
+    frames: [(usize, u64); DEFERRED_CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+impl Retired {
+    const fn new() -> Self {
+        Self { frames: [(0, 0); DEFERRED_CAPACITY], head: 0, len: 0 }
+    }
+
+    /// Adds the frame, returning it back if the ring is full.
+    fn push(&mut self, frame: Frame, epoch: u64) -> Result<(), Frame> {
+        if self.len == DEFERRED_CAPACITY {
+            return Err(frame)
+        }
+        self.frames[(self.head + self.len) % DEFERRED_CAPACITY] = (frame.num, epoch);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Takes the oldest frame if it's epoch passes the check.
+    fn pop_if(&mut self, expired: impl FnOnce(u64) -> bool) -> Option<Frame> {
+        let (num, epoch) = self.frames[self.head];
+        if self.len == 0 || !expired(epoch) {
+            return None
+        }
+        self.head = (self.head + 1) % DEFERRED_CAPACITY;
+        self.len -= 1;
+        Some(Frame { num })
+    }
+}
+
+/// Records a quiescent state of the running processor.
+///
+/// Called with interrupts enabled or from an interrupt taken with them enabled, never while
+/// walking the page tables.
+pub fn quiescent() {
+    SEEN[smp::cpu_id()].store(EPOCH.load(Ordering::Acquire), Ordering::Release);
+}
+
+/// Checks if every other online processor passed a quiescent state after the epoch began.
+fn grace_period_over(epoch: u64) -> bool {
+    let cpu = smp::cpu_id();
+    (0..smp::cpu_count())
+        .filter(|&other| other != cpu && !smp::is_parked(other))
+        .all(|other| SEEN[other].load(Ordering::Acquire) >= epoch)
+}
+
+/// Frees the unmapped frame once no processor can reach it anymore.
+pub fn defer(mmu: &mut MMU, frame: Frame) {
+    if smp::cpu_count() <= 1 {
+        return mmu.deallocate_frame(frame)
+    }
+
+    let Err(frame) = enqueue(frame) else { return };
+    reclaim(mmu);
+    let (oldest, epoch) = {
+        let mut queue = QUEUE.lock();
+        let epoch = EPOCH.fetch_add(1, Ordering::AcqRel) + 1;
+        let Err(frame) = queue.push(frame, epoch) else {
+            PENDING.fetch_add(1, Ordering::Relaxed);
+            return
+        };
+        let mut oldest_epoch = 0;
+        let oldest = queue.pop_if(|epoch| { oldest_epoch = epoch; true }).unwrap();
+        let _ = queue.push(frame, epoch);
+        (oldest, oldest_epoch)
+    };
+
+    // Nothing expired, because a batch is open or a processor did not pass a quiescent state yet.
+    // The flush drops the translations of the open batches as well, so only the walks in progress
+    // are waited for. The shootdown IPI records a quiescent state on each target.
+    STALLS.fetch_add(1, Ordering::Relaxed);
+    shootdown::flush_all_now();
+    while !grace_period_over(epoch) {
+        shootdown::poll();
+        core::hint::spin_loop();
+    }
+    mmu.deallocate_frame(oldest);
+}
+
+/// Adds the frame to the queue with a new epoch, returning it back if the queue is full.
+fn enqueue(frame: Frame) -> Result<(), Frame> {
+    let mut queue = QUEUE.lock();
+    // The epoch is taken under the lock, so the queue stays ordered.
+    let epoch = EPOCH.fetch_add(1, Ordering::AcqRel) + 1;
+    queue.push(frame, epoch)?;
+    PENDING.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns the frames whose grace period ended to the frame allocator.
+///
+/// Returns the amount of freed frames.
+pub fn reclaim(mmu: &mut MMU) -> usize {
+    quiescent();
+    let mut freed = 0;
+    loop {
+        // Frames unmapped within an open batch may still be cached by the other TLBs.
+        let expired = |epoch| !Batch::any_open() && grace_period_over(epoch);
+        let Some(frame) = QUEUE.lock().pop_if(expired) else { break };
+        PENDING.fetch_sub(1, Ordering::Relaxed);
+        mmu.deallocate_frame(frame);
+        freed += 1;
+    }
+    freed
+}
+
+/// Returns the amount of frames waiting for their grace period.
+pub fn pending() -> usize {
+    PENDING.load(Ordering::Relaxed)
+}
+
+/// Returns the amount of times a frame was deferred to a full queue and had to wait synchronously.
+pub fn stalls() -> u64 {
+    STALLS.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn retired_frames_leave_in_order() {
+    let mut retired = Retired::new();
+    assert!(retired.pop_if(|_| true).is_none());
+    for num in 0..DEFERRED_CAPACITY {
+        assert!(retired.push(Frame { num }, num as u64 + 1).is_ok());
+    }
+    assert_eq!(retired.push(Frame { num: 0 }, 0), Err(Frame { num: 0 }));
+
+    assert_eq!(retired.pop_if(|epoch| epoch <= 1), Some(Frame { num: 0 }));
+    assert!(retired.pop_if(|epoch| epoch <= 1).is_none());
+    assert!(retired.push(Frame { num: DEFERRED_CAPACITY }, DEFERRED_CAPACITY as u64 + 1).is_ok());
+    assert_eq!(retired.len, DEFERRED_CAPACITY);
+    assert_eq!(retired.pop_if(|_| true), Some(Frame { num: 1 }));
+
+    // Without other processors every grace period is over.
+    if smp::cpu_count() <= 1 {
+        assert!(grace_period_over(u64::MAX));
+    }
+}
@@ -0,0 +1,123 @@
+//! Demand paging of kernel regions.
+//!
+//! A region reserved with [`MMU::reserve_lazy`] is not backed by any frames. The first access
+//! to each of it's pages raises a page fault, the handler allocates a zeroed frame, maps it with
+//! the flags of the region and resumes the faulting instruction.
+//!
+//! The heap arena is reserved this way at boot instead of mapping every page of it eagerly.
+//! Stacks are still mapped eagerly: the processor pushes the exception frame onto the faulting
+//! stack, so a fault on an unmapped stack page turns into a double fault.
+//!
+//! [`MMU::reserve_lazy`]: super::MMU::reserve_lazy
+
+use core::ops::Range;
+
+use crate::VirtualAddress;
+
+use super::frames::PAGE_SIZE;
+use super::memory_module::MemError;
+
+/// Maximal amount of lazily mapped regions.
+pub const MAX_LAZY_REGIONS: usize = 16;
+
+/// Range of virtual addresses which pages are mapped on the first access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LazyRegion {
+    pub start: VirtualAddress,
+    /// Exclusive end of the region.
+    pub end: VirtualAddress,
+    /// Raw entry flags of the populated pages.
+    pub flags: u64,
+}
+
+impl LazyRegion {
+    /// Checks if the region contains the address.
+    #[inline]
+    pub fn contains(&self, addr: VirtualAddress) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+}
+
+/// Fixed table of the lazily mapped regions.
+///
+/// The table never allocates, as it is searched by the page fault handler, which may run in
+/// the middle of a heap allocation.
+#[derive(Debug)]
+pub struct LazyRegions {
+    regions: [Option<LazyRegion>; MAX_LAZY_REGIONS],
+    /// Amount of pages populated on demand so far.
+    populated: usize,
+}
+
+impl LazyRegions {
+    /// Creates an empty table.
+    pub const fn new() -> Self {
+        Self { regions: [None; MAX_LAZY_REGIONS], populated: 0 }
+    }
+
+    /// Adds the page aligned range which does not overlap any other region.
+    pub fn insert(&mut self, range: Range<VirtualAddress>, flags: u64) -> Result<(), MemError> {
+        let aligned = range.start % PAGE_SIZE == 0 && range.end % PAGE_SIZE == 0;
+        let overlaps = self.iter().any(|region| range.start < region.end && region.start < range.end);
+        if !aligned || range.is_empty() || overlaps {
+            return Err(MemError::InvalidRegion(range.start))
+        }
+
+        let slot = self.regions.iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(MemError::LazyRegionsFull)?;
+        *slot = Some(LazyRegion { start: range.start, end: range.end, flags });
+        Ok(())
+    }
+
+    /// Removes the region starting at the address.
+    pub fn remove(&mut self, start: VirtualAddress) -> Option<LazyRegion> {
+        self.regions.iter_mut()
+            .find(|slot| slot.is_some_and(|region| region.start == start))
+            .and_then(Option::take)
+    }
+
+    /// Returns the region containing the address.
+    pub fn find(&self, addr: VirtualAddress) -> Option<LazyRegion> {
+        self.iter().find(|region| region.contains(addr))
+    }
+
+    /// Iterates over the reserved regions.
+    pub fn iter(&self) -> impl Iterator<Item = LazyRegion> + '_ {
+        self.regions.iter().flatten().copied()
+    }
+
+    /// Amount of pages populated on demand so far.
+    pub fn populated(&self) -> usize {
+        self.populated
+    }
+
+    /// Counts a page populated on demand.
+    pub(super) fn count_populated(&mut self) {
+        self.populated += 1;
+    }
+}
+
+#[test_case]
+fn lazy_regions_do_not_overlap() {
+    let mut regions = LazyRegions::new();
+    let base = 0x4000_0000;
+
+    assert_eq!(regions.insert(base..base + 4 * PAGE_SIZE, 0b11), Ok(()));
+    assert_eq!(regions.insert(base + PAGE_SIZE..base + 8 * PAGE_SIZE, 0b11), Err(MemError::InvalidRegion(base + PAGE_SIZE)));
+    assert_eq!(regions.insert(base + 4 * PAGE_SIZE + 1..base + 8 * PAGE_SIZE, 0b11), Err(MemError::InvalidRegion(base + 4 * PAGE_SIZE + 1)));
+    assert_eq!(regions.insert(base..base, 0b11), Err(MemError::InvalidRegion(base)));
+
+    assert_eq!(regions.find(base + 4 * PAGE_SIZE - 1).map(|region| region.start), Some(base));
+    assert_eq!(regions.find(base + 4 * PAGE_SIZE), None);
+
+    for i in 1..MAX_LAZY_REGIONS {
+        let start = base + 8 * i * PAGE_SIZE;
+        assert_eq!(regions.insert(start..start + PAGE_SIZE, 0b11), Ok(()));
+    }
+    assert_eq!(regions.insert(0..PAGE_SIZE, 0b11), Err(MemError::LazyRegionsFull));
+
+    assert!(regions.remove(base).is_some());
+    assert_eq!(regions.find(base), None);
+    assert_eq!(regions.insert(0..PAGE_SIZE, 0b11), Ok(()));
+}
@@ -1,10 +1,13 @@
 /// Physical memory management. Frames and allocation.
 
-use super::memory_map::{MemoryArea, MemoryAreaIter};
+use super::memory_map::{MemoryArea, MemoryAreaIter, MemoryAreaType};
 use crate::PhysicalAddress;
 
 /// The size of each individual page chunk.
 pub const PAGE_SIZE: usize = 4096;
+/// Maximal amount of ranges of deallocated frames kept for reuse. Frames freed past this limit are
+/// leaked and counted, see [`AreaFrameAllocator::leaked`].
+pub const FREED_RANGES_CAPACITY: usize = 1024;
 
 /// A frame structure, which is just a pointer counter to the next frame
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -38,6 +41,13 @@ impl Frame {
     }
 }
 
+/// Consecutive deallocated frames, starting at the frame number.
+#[derive(Debug, Clone, Copy)]
+struct FreedRange {
+    start: usize,
+    count: usize,
+}
+
 /// Iterator over frames.
 pub struct FrameIter {
     start: Frame,
@@ -72,6 +82,11 @@ pub struct AreaFrameAllocator {
     kernel_end: Frame,
     multiboot_start: Frame,
     multiboot_end: Frame,
+    /// Stack of ranges of deallocated frames, which are reused before any new frame.
+    freed: [FreedRange; FREED_RANGES_CAPACITY],
+    freed_len: usize,
+    /// Frames which did not fit into the freed stack.
+    leaked: usize,
 }
 
 impl AreaFrameAllocator {
@@ -91,17 +106,83 @@ impl AreaFrameAllocator {
             kernel_end: Frame::info_address(kernel_end),
             multiboot_start: Frame::info_address(multiboot_start),
             multiboot_end: Frame::info_address(multiboot_end),
+            freed: [FreedRange { start: 0, count: 0 }; FREED_RANGES_CAPACITY],
+            freed_len: 0,
+            leaked: 0,
         };
         allocator.choose_next_area();
         allocator
     }
 
+    /// Creates a frame allocator without any memory areas. It never allocates new frames.
+    pub fn empty() -> Self {
+        Self::new(0, 0, 0, 0, MemoryAreaIter::from_areas(&[]))
+    }
+
+    /// Marks every frame below the provided one as used.
+    pub fn skip_to(&mut self, frame: Frame) {
+        if frame > self.next_free_frame {
+            self.next_free_frame = frame;
+        }
+    }
+
+    /// Allocates consecutive new frames, the first one aligned to their amount, which must be a
+    /// power of two.
+    ///
+    /// Frames freed before are never used, since they are scattered. New frames skipped to reach
+    /// the alignment are returned with [`FrameAlloc::dealloc_range`] instead, so they are reused
+    /// by [`FrameAlloc::alloc`] unless the freed stack is full, see [`AreaFrameAllocator::leaked`].
+    pub fn alloc_aligned(&mut self, count: usize) -> Option<Frame> {
+        loop {
+            let area = self.current_area?;
+            let last = Frame::info_address((area.base_addr + area.length - 1) as usize).num;
+            let next = self.next_free_frame.num;
+            let start = next.next_multiple_of(count);
+            let end = start + count - 1;
+
+            let reserved = [(&self.kernel_start, &self.kernel_end), (&self.multiboot_start, &self.multiboot_end)]
+                .into_iter()
+                .filter(|(first, last_used)| first.num <= end.min(last) && last_used.num >= next)
+                .map(|(first, last_used)| (first.num, last_used.num))
+                .min();
+
+            if let Some((first, last_used)) = reserved {
+                self.release_until(first);
+                self.next_free_frame = Frame { num: last_used + 1 };
+            } else if end > last {
+                self.release_until(last + 1);
+                self.choose_next_area();
+            } else {
+                self.release_until(start);
+                self.next_free_frame = Frame { num: end + 1 };
+                return Some(Frame { num: start })
+            }
+        }
+    }
+
+    /// Gives the new frames up to the provided one back for reuse as a single range.
+    fn release_until(&mut self, num: usize) {
+        if self.next_free_frame.num < num {
+            self.dealloc_range(self.next_free_frame.clone(), num - self.next_free_frame.num);
+            self.next_free_frame.num = num;
+        }
+    }
+
+    /// Returns the amount of deallocated frames, which were leaked because every slot of the
+    /// freed stack was taken.
+    pub fn leaked(&self) -> usize {
+        self.leaked
+    }
+
     /// Chooses the next free memory area to allocate a frame.
+    ///
+    /// Only available RAM is used, ACPI and reserved areas are never allocated.
     fn choose_next_area(&mut self) {
         self.areas.next();
         self.current_area = self.areas.clone().filter(|area| {
             let address = area.base_addr + area.length - 1;
-            Frame::info_address(address as usize) >= self.next_free_frame
+            area.area_type() == MemoryAreaType::Available &&
+                Frame::info_address(address as usize) >= self.next_free_frame
         }).min_by_key(|area| area.base_addr);
 
         if let Some(area) = self.current_area {
@@ -117,11 +198,25 @@ impl AreaFrameAllocator {
 pub trait FrameAlloc {
     fn alloc(&mut self) -> Option<Frame>;
     fn dealloc(&mut self, frame: Frame);
+
+    /// Deallocates the amount of consecutive frames, starting at the provided one.
+    fn dealloc_range(&mut self, start: Frame, count: usize) {
+        (start.num..start.num + count).for_each(|num| self.dealloc(Frame { num }));
+    }
 }
 
 impl FrameAlloc for AreaFrameAllocator {
     /// Allocates the frame in the memory area. Returns the allocated Frame.
     fn alloc(&mut self) -> Option<Frame> {
+        if let Some(top) = self.freed[..self.freed_len].last_mut() {
+            top.count -= 1;
+            let num = top.start + top.count;
+            if top.count == 0 {
+                self.freed_len -= 1;
+            }
+            return Some(Frame { num });
+        }
+
         if let Some(area) = self.current_area {
             let frame = self.next_free_frame.clone();
 
@@ -153,10 +248,53 @@ impl FrameAlloc for AreaFrameAllocator {
         }
     }
 
+    /// Returns the frame for reuse. The frame is leaked if too many ranges are freed already.
     fn dealloc(&mut self, frame: Frame) {
-        unimplemented!()
+        self.dealloc_range(frame, 1)
+    }
+
+    /// Returns the frames for reuse with a single slot of the freed stack.
+    ///
+    /// A range adjacent to the last returned one is merged with it. The frames are leaked and
+    /// counted if every slot is taken already.
+    fn dealloc_range(&mut self, start: Frame, count: usize) {
+        if count == 0 {
+            return
+        }
+        if let Some(top) = self.freed[..self.freed_len].last_mut() {
+            if top.start + top.count == start.num {
+                top.count += count;
+                return
+            }
+            if start.num + count == top.start {
+                (top.start, top.count) = (start.num, top.count + count);
+                return
+            }
+        }
+        if self.freed_len < FREED_RANGES_CAPACITY {
+            self.freed[self.freed_len] = FreedRange { start: start.num, count };
+            self.freed_len += 1;
+        } else {
+            self.leaked += count;
+        }
     }
 }
 
 
 
+
+#[test_case]
+fn freed_ranges_are_merged_and_overflow_is_counted() {
+    let mut allocator = AreaFrameAllocator::empty();
+    allocator.dealloc_range(Frame { num: 10 }, 4);
+    allocator.dealloc(Frame { num: 14 });
+    allocator.dealloc(Frame { num: 9 });
+    assert_eq!(allocator.freed_len, 1);
+    assert_eq!((0..6).filter_map(|_| allocator.alloc()).map(|frame| frame.num).max(), Some(14));
+    assert!(allocator.alloc().is_none());
+
+    for num in 0..=FREED_RANGES_CAPACITY {
+        allocator.dealloc_range(Frame { num: num * 4 }, 2);
+    }
+    assert_eq!((allocator.freed_len, allocator.leaked()), (FREED_RANGES_CAPACITY, 2));
+}
@@ -1,7 +1,12 @@
 /// Inactive page tables give the possibility of using 'ActivePageTable's
 /// methods on inactive pages, in order to remap inactive pages.
 
-use super::{frames::Frame, temporary_pages::TempPage, ActivePageTable};
+use core::ops::Range;
+use alloc::vec::Vec;
+
+use super::{frames::Frame, temporary_pages::TempPage, ActivePageTable, owned_tables::Mapping};
+use super::layout::RECURSIVE_INDEX;
+use crate::VirtualAddress;
 
 /// The main struct for inactive pages.
 pub struct InactivePageTable {
@@ -16,7 +21,7 @@ impl InactivePageTable {
 
             table.zero();
             // This sets up recursive mapping for the table.
-            table[511].set(frame.clone(), PRESENT | WRITABLE);
+            table[RECURSIVE_INDEX].set(frame.clone(), PRESENT | WRITABLE);
         }
         temp_page.unmap(active_table);
 
@@ -26,4 +31,91 @@ impl InactivePageTable {
     pub fn get_clone(&self) -> Frame {
         self.p4_frame.clone()
     }
+
+    /// Collects every leaf mapping of this table within the provided range of virtual addresses.
+    ///
+    /// The table is temporary made reachable through the recursive entry of the active table.
+    pub fn mappings(
+        &mut self,
+        range: Range<VirtualAddress>,
+        active_table: &mut ActivePageTable,
+        temp_page: &mut TempPage
+    ) -> Vec<Mapping> {
+        let mut mappings = Vec::new();
+        active_table.with(self, temp_page, |mapper| mappings = mapper.mappings(range));
+        mappings
+    }
+
+    /// Compares mappings of two tables within the provided range of virtual addresses.
+    ///
+    /// The differences are described from the point of view of self, i.e Added means that
+    /// the other table has a mapping which is missing here.
+    pub fn diff(
+        &mut self,
+        other: &mut InactivePageTable,
+        range: Range<VirtualAddress>,
+        active_table: &mut ActivePageTable,
+        temp_page: &mut TempPage
+    ) -> Vec<MappingDiff> {
+        let old = self.mappings(range.clone(), active_table, temp_page);
+        let new = other.mappings(range, active_table, temp_page);
+        diff_mappings(&old, &new)
+    }
+}
+
+/// A single difference between two sets of mappings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingDiff {
+    /// The page is only mapped in the new set.
+    Added(Mapping),
+    /// The page is only mapped in the old set.
+    Removed(Mapping),
+    /// The page is mapped in both sets, but to a different frame, with other flags or size.
+    Changed { old: Mapping, new: Mapping },
+}
+
+/// Compares two sorted lists of mappings, as returned by mappings().
+pub fn diff_mappings(old: &[Mapping], new: &[Mapping]) -> Vec<MappingDiff> {
+    let mut diff = Vec::new();
+    let (mut old, mut new) = (old.iter().peekable(), new.iter().peekable());
+
+    loop {
+        match (old.peek(), new.peek()) {
+            (Some(&&o), Some(&&n)) if o.virt == n.virt => {
+                if o != n {
+                    diff.push(MappingDiff::Changed { old: o, new: n });
+                }
+                old.next();
+                new.next();
+            },
+            (Some(&&o), Some(&&n)) if o.virt < n.virt => {
+                diff.push(MappingDiff::Removed(o));
+                old.next();
+            },
+            (Some(_), Some(&&n)) | (None, Some(&&n)) => {
+                diff.push(MappingDiff::Added(n));
+                new.next();
+            },
+            (Some(&&o), None) => {
+                diff.push(MappingDiff::Removed(o));
+                old.next();
+            },
+            (None, None) => break,
+        }
+    }
+    diff
+}
+
+#[test_case]
+fn diff_detects_changes() {
+    let page = |virt, phys| Mapping { virt, phys, size: 4096, flags: 0b11 };
+
+    let old = [page(0x1000, 0x5000), page(0x2000, 0x6000), page(0x3000, 0x7000)];
+    let new = [page(0x2000, 0x6000), page(0x3000, 0x8000), page(0x4000, 0x9000)];
+
+    assert_eq!(diff_mappings(&old, &new), [
+        MappingDiff::Removed(old[0]),
+        MappingDiff::Changed { old: old[2], new: new[1] },
+        MappingDiff::Added(new[2]),
+    ]);
 }
\ No newline at end of file
@@ -0,0 +1,157 @@
+//! Frames shared between anonymous pages.
+//!
+//! Untouched anonymous pages are mapped read-only to a single zero frame and receive their own
+//! frame only on the first write (copy on write). The optional background scanner goes further and
+//! merges pages with identical content: anonymous pages and pages of read-only file mappings are
+//! hashed, equal pages are remapped read-only to one frame and the spare frames are freed. A write
+//! into a merged anonymous page copies it again.
+//!
+//! Merged frames are reference counted, the zero frame is never freed.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::kernel_components::task_virtualization::{Thread, PROCESS_MANAGEMENT_UNIT};
+use crate::{single, VirtualAddress};
+
+use super::frames::{Frame, PAGE_SIZE};
+use super::memory_module::{MMU, MEMORY_MANAGEMENT_UNIT};
+
+/// Interval between two scans of the background scanner in milliseconds.
+pub const SCAN_INTERVAL_MS: u32 = 2000;
+
+/// Enables merging of identical pages by the background scanner. Tunable with "mm.ksm".
+pub static KSM_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Number of the zero frame, once it is allocated.
+single! {
+    pub mut ZERO_FRAME: Option<usize> = None;
+}
+
+/// Reference counts of merged frames by the frame number.
+single! {
+    pub mut SHARED_FRAMES: BTreeMap<usize, usize> = BTreeMap::new();
+}
+
+/// Returns the zero frame if it is allocated already.
+pub fn zero_frame() -> Option<Frame> {
+    unsafe { ZERO_FRAME.map(|num| Frame { num }) }
+}
+
+/// Makes the zeroed frame the shared zero frame.
+///
+/// # Panics
+///
+/// Panics if the zero frame is set already.
+pub fn set_zero_frame(frame: Frame) {
+    unsafe {
+        assert!(ZERO_FRAME.is_none(), "The zero frame is already allocated.");
+        *ZERO_FRAME = Some(frame.num);
+    }
+}
+
+/// Checks if the frame is the zero frame.
+#[inline]
+pub fn is_zero_frame(frame: &Frame) -> bool {
+    unsafe { *ZERO_FRAME == Some(frame.num) }
+}
+
+/// Adds a reference to the shared frame.
+pub fn get(frame: &Frame) {
+    if !is_zero_frame(frame) {
+        unsafe { *SHARED_FRAMES.entry(frame.num).or_insert(0) += 1 };
+    }
+}
+
+/// Drops a reference to the shared frame. The last reference frees the frame.
+pub fn put(mmu: &mut MMU, frame: Frame) {
+    if is_zero_frame(&frame) {
+        return
+    }
+
+    let shared = unsafe { &mut *SHARED_FRAMES };
+    match shared.get_mut(&frame.num) {
+        Some(refs) if *refs > 1 => *refs -= 1,
+        _ => {
+            shared.remove(&frame.num);
+            mmu.deallocate_frame_deferred(frame);
+        },
+    }
+}
+
+/// Returns the amount of frames which are shared by more than one page.
+pub fn shared_frames() -> usize {
+    unsafe { SHARED_FRAMES.values().filter(|&&refs| refs > 1).count() }
+}
+
+/// Hashes the content of the mapped page (FNV-1a over 64-bit words).
+pub fn page_hash(addr: VirtualAddress) -> u64 {
+    let words = unsafe { core::slice::from_raw_parts(addr as *const u64, PAGE_SIZE / 8) };
+    words.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &word| (hash ^ word).wrapping_mul(0x100_0000_01b3))
+}
+
+fn same_content(a: VirtualAddress, b: VirtualAddress) -> bool {
+    unsafe {
+        core::slice::from_raw_parts(a as *const u8, PAGE_SIZE) ==
+            core::slice::from_raw_parts(b as *const u8, PAGE_SIZE)
+    }
+}
+
+/// Merges identical candidate pages of every process.
+///
+/// Returns the amount of freed frames.
+pub fn scan() -> usize {
+    unsafe {
+        let mut list = PROCESS_MANAGEMENT_UNIT.process_list.lock();
+        let mmu = &mut *MEMORY_MANAGEMENT_UNIT;
+
+        let mut candidates: Vec<(u64, VirtualAddress)> = Vec::new();
+        list.for_each_mut(|process| process.address_space.merge_candidates(&mut candidates));
+        candidates.sort_unstable();
+
+        // Pages are merged into the first page of each group of equal hashes.
+        let mut merges = Vec::new();
+        for group in candidates.chunk_by(|a, b| a.0 == b.0) {
+            let canonical = group[0].1;
+            for &(_, addr) in &group[1..] {
+                if same_content(canonical, addr) {
+                    merges.push((canonical, addr));
+                }
+            }
+        }
+
+        let mut freed = 0;
+        for (canonical, addr) in merges {
+            let Some(frame) = mmu.translate(canonical).map(Frame::info_address) else { continue };
+            list.for_each_mut(|process| {
+                process.address_space.share(mmu, canonical, Frame { num: frame.num });
+                freed += process.address_space.share(mmu, addr, Frame { num: frame.num }) as usize;
+            });
+        }
+        freed
+    }
+}
+
+/// Background thread that periodically merges identical pages while KSM is enabled.
+pub fn scanner(_: &mut Thread) {
+    loop {
+        if KSM_ENABLED.load(Ordering::Relaxed) {
+            scan();
+        }
+        Thread::sleep(SCAN_INTERVAL_MS);
+    }
+}
+
+#[test_case]
+fn shared_frames_are_reference_counted() {
+    let frame = Frame { num: usize::MAX - 1 };
+    get(&frame);
+    get(&frame);
+    assert_eq!(unsafe { SHARED_FRAMES.get(&frame.num).copied() }, Some(2));
+    assert!(shared_frames() >= 1);
+
+    unsafe { SHARED_FRAMES.remove(&frame.num) };
+    assert_ne!(page_hash([0u64; PAGE_SIZE / 8].as_ptr() as usize), page_hash([1u64; PAGE_SIZE / 8].as_ptr() as usize));
+}
@@ -0,0 +1,233 @@
+//! Layout of the kernel virtual address space.
+//!
+//! Every fixed virtual region used by the kernel is defined here, so that new regions can be
+//! checked against the existing ones instead of picking another magic address. All processes share
+//! one page table, therefore the per-process regions are part of this layout too.
+//!
+//! The kernel lives in the last P4 entry. The image is linked at [`KERNEL_OFFSET`] in the top
+//! 2 GiB, with the heap, the stacks and the scratch pages below it. The lower half is left to the
+//! processes, apart from the firmware and device memory, which is still reached by it's physical
+//! address, and the vDSO page the processes read. Upper half addresses are written without the
+//! sign extension.
+//!
+//! | Region            | Start                        | Size      | Purpose                                  |
+//! |-------------------|------------------------------|-----------|------------------------------------------|
+//! | Identity          | `0x0`                        | 1 GiB     | Boot info, VGA, ACPI, early allocations  |
+//! | vDSO              | `0o_000_002_000_001_0000`    | 4 KiB     | Read-only time data shared with users    |
+//! | MMIO              | `0xc000_0000`                | 1 GiB     | Identity mapped device memory            |
+//! | Mmap              | `0o_001_000_000_000_0000`    | 512 GiB   | Per-process mmap windows                 |
+//! | Physmap           | `0o_400_000_000_000_0000`    | 512 GiB   | Reserved for a direct physical map       |
+//! | Recursive P4      | `0o_776_000_000_000_0000`    | 512 GiB   | Recursive mapping of the page tables     |
+//! | Heap              | `0o_777_001_000_000_0000`    | 512 MiB   | Arena of the global allocator            |
+//! | Stacks            | `0o_777_001_400_000_0000`    | 512 MiB   | Stacks from the stack allocator          |
+//! | Temporary page    | `0o_777_002_000_000_0000`    | 4 KiB     | Page used while editing inactive tables  |
+//! | Zeroing page      | `0o_777_002_000_002_0000`    | 4 KiB     | Frames cleared by the zeroing thread     |
+//! | Framebuffer       | `0o_777_002_001_000_0000`    | 64 MiB    | Linear framebuffer of the console        |
+//! | Kernel image      | `0o_777_776_000_000_0000`    | 2 GiB     | Kernel image at [`KERNEL_OFFSET`]        |
+//!
+//! Overlaps between the static regions are rejected at compile time, [`validate`] additionally
+//! checks the heap arena of the selected allocator at boot.
+
+use core::fmt::Display;
+
+use crate::{PhysicalAddress, VirtualAddress};
+
+use super::allocators::GLOBAL_ALLOCATOR;
+use super::frames::PAGE_SIZE;
+
+/// Sign extension of the addresses within the upper half of the canonical address space.
+const SIGN_EXTENSION: usize = 0o177777_000_000_000_000_0000;
+
+/// Last P4 entry, which holds the kernel image and every region private to the kernel.
+const KERNEL_SPACE: VirtualAddress = SIGN_EXTENSION | 0o_777_000_000_000_0000;
+
+/// Low memory, identity mapped while remapping the kernel.
+pub const IDENTITY_START: VirtualAddress = 0;
+pub const IDENTITY_END: VirtualAddress = 0o_000_001_000_000_0000;
+
+/// Read-only alias of the kernel maintained time data, mapped for every process.
+pub const VDSO_PAGE: VirtualAddress = 0o_000_002_000_001_0000;
+
+/// Identity mapped device memory (PCI BARs, local APIC, I/O APIC, HPET).
+pub const MMIO_START: VirtualAddress = 0xc000_0000;
+pub const MMIO_END: VirtualAddress = 0x1_0000_0000;
+
+/// Mmap windows of processes. It is the whole second P4 entry.
+pub const MMAP_START: VirtualAddress = 0o_001_000_000_000_0000;
+pub const MMAP_END: VirtualAddress = 0o_002_000_000_000_0000;
+
+/// Reserved for a direct mapping of the whole physical memory. Not mapped yet.
+pub const PHYSMAP_OFFSET: VirtualAddress = SIGN_EXTENSION | 0o_400_000_000_000_0000;
+pub const PHYSMAP_END: VirtualAddress = SIGN_EXTENSION | 0o_401_000_000_000_0000;
+
+/// P4 entry, which points to the P4 table itself. The last one is taken by the kernel.
+pub const RECURSIVE_INDEX: usize = 0o776;
+pub const RECURSIVE_START: VirtualAddress = SIGN_EXTENSION | RECURSIVE_INDEX << 39;
+pub const RECURSIVE_END: VirtualAddress = KERNEL_SPACE;
+
+/// Heap region. The arena of every allocator must fit in here.
+pub const HEAP_START: VirtualAddress = KERNEL_SPACE | 0o_001_000_000_0000;
+pub const HEAP_END: VirtualAddress = KERNEL_SPACE | 0o_001_400_000_0000;
+
+/// Region of the stack allocator (privilege and interrupt stacks, process stacks).
+pub const STACKS_START: VirtualAddress = KERNEL_SPACE | 0o_001_400_000_0000;
+pub const STACKS_END: VirtualAddress = KERNEL_SPACE | 0o_002_000_000_0000;
+
+/// Page temporarily mapped to the frames of inactive page tables.
+pub const TEMP_PAGE: VirtualAddress = KERNEL_SPACE | 0o_002_000_000_0000;
+
+/// Page the zeroing thread maps free frames to while clearing and scrubbing them.
+pub const ZEROING_PAGE: VirtualAddress = KERNEL_SPACE | 0o_002_000_002_0000;
+
+/// Linear framebuffer set up by the bootloader, mapped write combining.
+pub const FRAMEBUFFER_START: VirtualAddress = KERNEL_SPACE | 0o_002_001_000_0000;
+pub const FRAMEBUFFER_END: VirtualAddress = KERNEL_SPACE | 0o_002_041_000_0000;
+
+/// Link address of the physical memory the kernel is loaded to, so the image starts at
+/// 1 MiB above it. Must match `linker.ld` and the boot assembly.
+pub const KERNEL_OFFSET: VirtualAddress = KERNEL_SPACE | 0o_776_000_000_0000;
+
+/// A named range of virtual addresses. The end is exclusive, zero end means the end of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub name: &'static str,
+    pub start: VirtualAddress,
+    pub end: VirtualAddress,
+}
+
+impl Region {
+    const fn new(name: &'static str, start: VirtualAddress, end: VirtualAddress) -> Self {
+        Self { name, start, end }
+    }
+
+    /// Returns the last address within the region.
+    #[inline]
+    pub const fn last(&self) -> VirtualAddress {
+        self.end.wrapping_sub(1)
+    }
+
+    /// Checks if the region contains the address.
+    #[inline]
+    pub const fn contains(&self, addr: VirtualAddress) -> bool {
+        addr >= self.start && addr <= self.last()
+    }
+
+    /// Checks if two regions share at least one address.
+    #[inline]
+    pub const fn overlaps(&self, other: &Region) -> bool {
+        self.start <= other.last() && other.start <= self.last()
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:<16} {:#018x} - {:#018x}", self.name, self.start, self.last())
+    }
+}
+
+/// Every fixed region of the kernel address space.
+pub const REGIONS: [Region; 12] = [
+    Region::new("identity", IDENTITY_START, IDENTITY_END),
+    Region::new("vdso", VDSO_PAGE, VDSO_PAGE + PAGE_SIZE),
+    Region::new("mmio", MMIO_START, MMIO_END),
+    Region::new("mmap", MMAP_START, MMAP_END),
+    Region::new("physmap", PHYSMAP_OFFSET, PHYSMAP_END),
+    Region::new("recursive p4", RECURSIVE_START, RECURSIVE_END),
+    Region::new("heap", HEAP_START, HEAP_END),
+    Region::new("stacks", STACKS_START, STACKS_END),
+    Region::new("temporary page", TEMP_PAGE, TEMP_PAGE + PAGE_SIZE),
+    Region::new("zeroing page", ZEROING_PAGE, ZEROING_PAGE + PAGE_SIZE),
+    Region::new("framebuffer", FRAMEBUFFER_START, FRAMEBUFFER_END),
+    Region::new("kernel image", KERNEL_OFFSET, 0),
+];
+
+/// Finds the first pair of overlapping regions, misaligned or non-canonical region.
+///
+/// Returns the indices of the colliding regions, an invalid region collides with itself.
+pub const fn first_collision(regions: &[Region]) -> Option<(usize, usize)> {
+    let mut i = 0;
+    while i < regions.len() {
+        let region = &regions[i];
+        let aligned = region.start % PAGE_SIZE == 0 && region.end % PAGE_SIZE == 0;
+        if !aligned || region.start > region.last() || !is_canonical(region.start) || !is_canonical(region.last()) {
+            return Some((i, i))
+        }
+
+        let mut j = i + 1;
+        while j < regions.len() {
+            if region.overlaps(&regions[j]) {
+                return Some((i, j))
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Checks if the address is canonical (bits 48..64 are copies of bit 47).
+#[inline]
+pub const fn is_canonical(addr: VirtualAddress) -> bool {
+    matches!(addr >> 47, 0 | 0x1ffff)
+}
+
+/// Copies bit 47 into the upper bits, which makes an address built from table indexes canonical.
+#[inline]
+pub const fn sign_extend(addr: VirtualAddress) -> VirtualAddress {
+    ((addr << 16) as isize >> 16) as usize
+}
+
+/// Returns the physical address of an address within the kernel image.
+///
+/// Images linked to the lower half, like the EFI application, are identity mapped.
+#[inline]
+pub const fn kernel_phys(addr: VirtualAddress) -> PhysicalAddress {
+    if addr >= KERNEL_OFFSET { addr - KERNEL_OFFSET } else { addr }
+}
+
+/// Returns the region which contains the address.
+pub fn region_of(addr: VirtualAddress) -> Option<&'static Region> {
+    REGIONS.iter().find(|region| region.contains(addr))
+}
+
+const _: () = assert!(first_collision(&REGIONS).is_none(), "Kernel address space regions collide.");
+
+/// Asserts that the layout is consistent with the selected heap allocator.
+///
+/// # Panics
+///
+/// Panics if any regions overlap, or the arena of the global allocator does not fit into the heap
+/// region.
+pub fn validate() {
+    if let Some((i, j)) = first_collision(&REGIONS) {
+        panic!("Kernel address space regions collide:\n{}\n{}", REGIONS[i], REGIONS[j]);
+    }
+
+    let (heap_addr, arena_size) = unsafe { (GLOBAL_ALLOCATOR.heap_addr, GLOBAL_ALLOCATOR.arena_size) };
+    assert!(
+        heap_addr >= HEAP_START && heap_addr.checked_add(arena_size).is_some_and(|end| end <= HEAP_END),
+        "Heap arena {:#x} - {:#x} does not fit into the heap region.", heap_addr, heap_addr + arena_size,
+    );
+}
+
+#[test_case]
+fn layout_regions_are_disjoint() {
+    validate();
+
+    assert_eq!(region_of(HEAP_START).map(|r| r.name), Some("heap"));
+    assert_eq!(region_of(usize::MAX).map(|r| r.name), Some("kernel image"));
+    assert_eq!(region_of(0o_003_000_000_000_0000), None);
+    assert_eq!(region_of(super::paging::P4 as usize).map(|r| r.name), Some("recursive p4"));
+    // The EFI application runs identity mapped where the firmware loaded it.
+    let image_region = if cfg!(feature = "uefi") { "identity" } else { "kernel image" };
+    assert_eq!(region_of(super::memory_module::linker_bounds().0 as usize).map(|r| r.name), Some(image_region));
+
+    assert_eq!(kernel_phys(KERNEL_OFFSET + 0x100000), 0x100000);
+    assert_eq!(kernel_phys(0x7c00), 0x7c00);
+    assert_eq!(sign_extend(RECURSIVE_INDEX << 39), RECURSIVE_START);
+    assert_eq!(sign_extend(0o_001_000_000_000_0000), MMAP_START);
+
+    let colliding = [Region::new("a", 0, 2 * PAGE_SIZE), Region::new("b", PAGE_SIZE, 3 * PAGE_SIZE)];
+    assert_eq!(first_collision(&colliding), Some((0, 1)));
+    assert_eq!(first_collision(&[Region::new("odd", 1, PAGE_SIZE)]), Some((0, 0)));
+}
@@ -83,11 +83,9 @@ impl MemoryMapTag {
 impl TagTrait for MemoryMapTag {
     const ID: TagType = TagType::Mmap;
 
-    fn dst_size(base_tag: &Tag) -> usize {
-        assert!(base_tag.size as usize >= METADATA_SIZE);
-        let size = base_tag.size as usize - METADATA_SIZE;
-        assert_eq!(size % mem::size_of::<MemoryArea>(), 0);
-        size / mem::size_of::<MemoryArea>()
+    fn dst_size(base_tag: &Tag) -> Option<usize> {
+        let size = (base_tag.size as usize).checked_sub(METADATA_SIZE)?;
+        (size % mem::size_of::<MemoryArea>() == 0).then_some(size / mem::size_of::<MemoryArea>())
     }
 }
 
@@ -134,6 +132,11 @@ impl MemoryArea {
     pub fn typ(&self) -> MemoryAreaTypeId {
         self.typ
     }
+
+    /// The type of the memory region as one of the defined types.
+    pub fn area_type(&self) -> MemoryAreaType {
+        self.typ.into()
+    }
 }
 
 impl Debug for MemoryArea {
@@ -179,7 +182,8 @@ pub enum MemoryAreaType {
     Available,
     /// A reserved area that must not be used.
     Reserved,
-    /// Usable memory holding ACPI information.
+    /// Usable memory holding ACPI information. Also called ACPI reclaim memory, which can be
+    /// used by the OS after the ACPI tables are parsed.
     AcpiAvailable,
     /// Reserved memory which needs to be preserved on hibernation.
     /// Also called NVS in spec, which stands for "Non-Volatile Sleep/Storage",
@@ -199,6 +203,19 @@ pub struct MemoryAreaIter {
     phantom: PhantomData<&'static MemoryArea>
 }
 
+impl MemoryAreaIter {
+    /// Creates an iterator over the provided areas instead of the multiboot memory map.
+    pub fn from_areas(areas: &'static [MemoryArea]) -> Self {
+        let start = areas.as_ptr() as u64;
+        Self {
+            current_area: start,
+            last_area: (start + mem::size_of_val(areas) as u64).wrapping_sub(1),
+            entry_size: mem::size_of::<MemoryArea>() as u32,
+            phantom: PhantomData,
+        }
+    }
+}
+
 impl Iterator for MemoryAreaIter {
     type Item = &'static MemoryArea;
     fn next(&mut self) -> Option<Self::Item> {
@@ -214,8 +231,9 @@ impl Iterator for MemoryAreaIter {
 
 impl From<MemoryAreaTypeId> for MemoryAreaType {
     fn from(value: MemoryAreaTypeId) -> Self {
+        // Defined types are numbered from 1.
         match value.0 {
-            0..=5 => Self::get_variant(value.0 as usize),
+            1..=5 => Self::get_variant(value.0 as usize - 1),
             val => Self::Custom(val),
         }
     }
@@ -225,7 +243,7 @@ impl From<MemoryAreaType> for MemoryAreaTypeId {
     fn from(value: MemoryAreaType) -> Self {
         let integer = match value {
             MemoryAreaType::Custom(val) => val,
-            _ => MemoryAreaType::get_index(value) as u32,
+            _ => MemoryAreaType::get_index(value) as u32 + 1,
         };
         integer.into()
     }
@@ -246,3 +264,12 @@ impl PartialEq<MemoryAreaTypeId> for MemoryAreaType {
         other.0.eq(&val)
     }
 }
+
+#[test_case]
+fn memory_area_types() {
+    assert_eq!(MemoryArea::new(0, 0x1000, 1).area_type(), MemoryAreaType::Available);
+    assert_eq!(MemoryArea::new(0, 0x1000, 3).area_type(), MemoryAreaType::AcpiAvailable);
+    assert_eq!(MemoryArea::new(0, 0x1000, 4).area_type(), MemoryAreaType::ReservedHibernate);
+    assert_eq!(MemoryArea::new(0, 0x1000, 0).area_type(), MemoryAreaType::Custom(0));
+    assert_eq!(u32::from(MemoryAreaTypeId::from(MemoryAreaType::Defective)), 5);
+}
@@ -1,18 +1,25 @@
 // Memory module for memory management. This is the entry point of memory functions and structs. 
 
 use core::alloc::Allocator;
-use core::mem::{self, size_of, MaybeUninit};
+use core::mem::{self, size_of};
 use core::fmt::{Debug, Display};
 use core::error::Error;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::vec::Vec;
 
 use crate::kernel_components::arch_x86_64::acpi::rsdt::SDTPointer;
 use crate::kernel_components::arch_x86_64::{
     segmentation::TSS,
     acpi::rsdt::{ACPITagOld, ACPITagNew},
-    acpi::{RSDT, XSDT, acpi::ACPISDTHeader},
+    acpi::{RSDT, XSDT, acpi::{report_invalid, ACPISDTHeader, MAX_TABLE_LENGTH}},
+    smp,
 };
 use crate::kernel_components::memory::frames::PAGE_SIZE;
+use crate::kernel_components::boot::progress::{progress, Stage};
+use crate::kernel_components::boot::info::Framebuffer;
+use crate::kernel_components::registers::ms::Pat;
+use crate::kernel_components::drivers::accounting;
 use crate::{VirtualAddress, PhysicalAddress, println};
 use crate::single;
 
@@ -21,13 +28,19 @@ use super::owned_tables::InnerMapper;
 use super::EntryFlags;
 use super::{
     Page, ActivePageTable,
-    tags::{EndTag, TagTrait, TagIter}, 
-    memory_map::MemoryMapTag,
-    sections::{SectionsTag, SectionIter}, 
+    tags::{EndTag, TagTrait, TagType, TagIter, CommandLineTag, FramebufferTag}, 
+    memory_map::{MemoryMapTag, MemoryAreaType},
+    sections::{SectionsTag, SectionIter, ElfSection}, 
     frames::{Frame, FrameAlloc, AreaFrameAllocator}, 
     temporary_pages::TempPage, 
     inactive_tables::InactivePageTable,
     stack_allocator::{Stack, StackAlloc},
+    bootmem::{self, BootMem},
+    prezero,
+    deferred,
+    demand::LazyRegions,
+    page_blocks::{PageBlocks, PhysRegion, MAX_ORDER},
+    layout,
 };
 
 type MMUResult = Result<(), MemError>;
@@ -39,13 +52,23 @@ type MMUResult = Result<(), MemError>;
 #[derive(Debug)]
 pub struct MMU {
     /// Grub's multiboot info structure.
-    info_pointer: InfoPointer<'static>,
+    info_pointer: Option<InfoPointer<'static>>,
     /// Active table 
     active_table: Option<ActivePageTable>,
     /// Area frame allocator instance, for allocating frames.
     frame_allocator: AreaFrameAllocator,
     /// Stack allocator instance, which allocates custom OS stacks.
     stack_allocator: StackAlloc,
+    /// Mark that the ACPI tables are identity mapped.
+    acpi_mapped: bool,
+    /// Mark that the ACPI reclaim areas were returned to the frame allocator.
+    acpi_reclaimed: bool,
+    /// Regions which pages are mapped on the first access.
+    lazy_regions: LazyRegions,
+    /// Processor populating a lazy page plus one, or zero.
+    lazy_fault_owner: AtomicUsize,
+    /// Free blocks of contiguous frames for drivers.
+    page_blocks: PageBlocks,
 
     /// Amount of allocated frames.
     frames_allocated: usize,
@@ -55,22 +78,24 @@ pub struct MMU {
 
 /// A static MMU instance.
 /// 
-/// Note that it does not have a proper multiboot structure pointer, and must be initialized with
-/// init() method.
+/// Note that it does not have a multiboot structure pointer nor any memory areas to allocate
+/// frames from, and must be initialized with init() method.
 single! {
-    pub mut MEMORY_MANAGEMENT_UNIT: MMU = unsafe {
-            #[allow(invalid_value)]
-            MMU {
-                info_pointer: MaybeUninit::uninit().assume_init(),
-                active_table: None,
-                
-                frame_allocator: MaybeUninit::uninit().assume_init(),
-                stack_allocator: MaybeUninit::uninit().assume_init(),
-    
-                frames_allocated: 0,
-                is_mem_init: AtomicBool::new(false),
-            }
-        }
+    pub mut MEMORY_MANAGEMENT_UNIT: MMU = MMU {
+        info_pointer: None,
+        active_table: None,
+
+        frame_allocator: AreaFrameAllocator::empty(),
+        stack_allocator: MMU::kernel_stacks(),
+        acpi_mapped: false,
+        acpi_reclaimed: false,
+        lazy_regions: LazyRegions::new(),
+        lazy_fault_owner: AtomicUsize::new(0),
+        page_blocks: PageBlocks::new(),
+
+        frames_allocated: 0,
+        is_mem_init: AtomicBool::new(false),
+    };
 }
 
 impl MMU {
@@ -85,13 +110,18 @@ impl MMU {
     /// This function only does memory based operations without changing states of any register or
     /// global state. Any specific properties of registers related to memory must be set manually
     /// via kernel_components::registers::control.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the multiboot structure is invalid, required tags are missing or no frames are
+    /// left to remap the kernel. Missing or broken ACPI tables are not an error.
     #[inline]
-    pub fn new_init(multiboot_information_address: usize) -> Self {
+    pub fn new_init(multiboot_information_address: usize) -> Result<Self, MemError> {
         let boot_info = unsafe { 
             InfoPointer::load(
                 multiboot_information_address as *const BootInfoHeader 
             ) 
-        }.unwrap();
+        }.map_err(MemError::BootInfo)?;
 
         use crate::kernel_components::arch_x86_64::acpi::{XSDT, RSDT};
         use crate::kernel_components::memory::{
@@ -100,13 +130,15 @@ impl MMU {
             EntryFlags,
         };
 
-        // Getting the MMAP tag.
-        let memory_map_tag = boot_info.memory_map_tag()
-        .expect("Memory map tag required.");
+        layout::validate();
+
+        // Frames are allocated after the ones used by the early boot allocations.
+        let bootmem = match bootmem::take() {
+            Some(bootmem) => bootmem,
+            None => BootMem::from_boot_info(&boot_info).ok_or(MemError::MissingTag(TagType::Mmap))?,
+        };
+        let early_allocations = bootmem.allocated();
 
-        // Getting kernel boundaries
-        let kernel_start = boot_info.kstart();
-        let kernel_end = boot_info.kend();
         // Getting multiboot2 boundaries.
         let multiboot_start = boot_info.mstart();
         let multiboot_end = boot_info.mend();
@@ -114,43 +146,52 @@ impl MMU {
         let heap_start = unsafe { GLOBAL_ALLOCATOR.heap_addr };
         let heap_end = heap_start + unsafe{ GLOBAL_ALLOCATOR.arena_size };
 
-        let mut frame_allocator = AreaFrameAllocator::new(
-            kernel_start as usize, 
-            kernel_end as usize, 
-            multiboot_start, 
-            multiboot_end,
-            memory_map_tag.memory_map_iter(),
-        );
+        let mut frame_allocator = bootmem.into_frame_allocator();
 
-        #[cfg(debug_assertions)] { println!("Remapping start"); }
+        crate::trace!(PAGING; "Remapping start");
         
         // remaping the kernel
-        let mut active_table = MMU::remap_kernel(&mut frame_allocator, &boot_info);
-        #[cfg(debug_assertions)] { println!("Remapping complete!"); }
+        let (mut active_table, acpi_mapped) = MMU::remap_kernel(&mut frame_allocator, &boot_info, early_allocations)?;
+        crate::trace!(PAGING; "Remapping complete!");
 
+        // The heap pages are mapped on the first access by the page fault handler.
         let heap_start_page = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
-
-        #[cfg(debug_assertions)] { println!("Mapping the heap pages."); }
-
-        for page in Page::range_inclusive(heap_start_page, heap_end_page) {
-            active_table.map(page, EntryFlags::WRITABLE, &mut frame_allocator);
-            #[cfg(debug_assertions)]
-            println!(crate::Color::LIGHTGRAY; "Mapping page at address {:#x}", page.start_address());
-        }   
-        #[cfg(debug_assertions)] { println!("Mapping complete."); }
-
-        let stack_allocator = StackAlloc::new(heap_end_page + 1);
-
-        Self {
-            info_pointer: boot_info,
+        let mut lazy_regions = LazyRegions::new();
+        lazy_regions.insert(
+            heap_start_page.start_address()..heap_end_page.start_address() + PAGE_SIZE,
+            EntryFlags::WRITABLE.bits(),
+        )?;
+        crate::trace!(PAGING; "Heap pages are reserved for demand paging.");
+
+        Ok(Self {
+            info_pointer: Some(boot_info),
             active_table: Some(active_table),
             frame_allocator: frame_allocator,
-            stack_allocator: stack_allocator,
+            stack_allocator: MMU::kernel_stacks(),
+            acpi_mapped,
+            acpi_reclaimed: false,
+            lazy_regions,
+            lazy_fault_owner: AtomicUsize::new(0),
+            page_blocks: PageBlocks::new(),
 
             frames_allocated: (multiboot_end - multiboot_start) / PAGE_SIZE,
             is_mem_init: AtomicBool::new(true),
-        }
+        })
+    }
+
+    /// Returns the stack allocator over the stacks region of the kernel address space.
+    fn kernel_stacks() -> StackAlloc {
+        StackAlloc::new(
+            Page::containing_address(layout::STACKS_START),
+            Page::containing_address(layout::STACKS_END - 1),
+        )
+    }
+
+    /// Checks if the memory is initialized already.
+    #[inline]
+    pub fn is_initialized(&self) -> bool {
+        self.is_mem_init.load(Ordering::Acquire)
     }
 
     /// Initiates the memory.
@@ -162,8 +203,12 @@ impl MMU {
     /// # Panics
     /// 
     /// Panics if the memory is already initialized at least once.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of [`MMU::new_init`], the MMU stays uninitialized then.
     #[inline]
-    pub fn init(&mut self, multiboot_information_address: usize) {
+    pub fn init(&mut self, multiboot_information_address: usize) -> MMUResult {
         assert!(
             !self.is_mem_init.load(Ordering::Acquire),
             "The memory is initialized already."
@@ -171,7 +216,55 @@ impl MMU {
 
         *self = MMU::new_init(
             multiboot_information_address
-        );
+        )?;
+        Ok(())
+    }
+
+    /// Checks if the ACPI tables were identity mapped during the initialization and are not
+    /// reclaimed yet.
+    #[inline]
+    pub fn acpi_available(&self) -> bool {
+        self.acpi_mapped
+    }
+
+    /// Returns the frames of the ACPI reclaim areas to the frame allocator.
+    ///
+    /// Those areas only hold the ACPI tables, so this must be called after every table is
+    /// parsed. The areas are unmapped and ACPI is reported as unavailable afterwards. NVS areas
+    /// must be preserved and are never reclaimed. Returns the amount of reclaimed frames, which is
+    /// zero on every following call.
+    pub fn reclaim_acpi(&mut self) -> Result<usize, MemError> {
+        let active_table = self.active_table.as_mut().ok_or(MemError::NoFrameAlloc)?;
+        if self.acpi_reclaimed {
+            return Ok(0)
+        }
+
+        let areas = self.info_pointer.as_ref()
+            .and_then(|boot_info| boot_info.memory_map_tag())
+            .ok_or(MemError::MissingTag(TagType::Mmap))?
+            .memory_map_iter()
+            .filter(|area| area.area_type() == MemoryAreaType::AcpiAvailable && area.size() != 0);
+
+        let mut reclaimed = 0;
+        for area in areas {
+            let start = Frame::info_address(area.start_address() as usize);
+            let end = Frame::info_address(area.end_address() as usize - 1);
+
+            let (first, count) = (start.num, end.num - start.num + 1);
+            for frame in Frame::range_inclusive(start, end) {
+                let page = Page::containing_address(frame.start_address());
+                if active_table.translate_page(page).is_some() {
+                    active_table.unmap(page, &mut self.frame_allocator);
+                }
+            }
+            // Whole areas go back at once, so they take a single slot of the freed stack.
+            self.frame_allocator.dealloc_range(Frame { num: first }, count);
+            reclaimed += count;
+        }
+
+        self.acpi_reclaimed = true;
+        self.acpi_mapped = false;
+        Ok(reclaimed)
     }
 
     /// Maps a page to the frame at the same physical address as the page by a provided pointer
@@ -187,6 +280,48 @@ impl MMU {
         )
     }
 
+    /// Identity maps the physical range read-only. Pages mapped already are left untouched.
+    ///
+    /// Used to read firmware structures (ACPI, SMBIOS), which are not mapped after the kernel is
+    /// remapped. The end is exclusive.
+    pub fn map_firmware(&mut self, start: PhysicalAddress, end: PhysicalAddress) -> MMUResult {
+        let last = Frame::info_address(end.max(start + 1) - 1);
+        for frame in Frame::range_inclusive(Frame::info_address(start), last) {
+            if self.translate(frame.start_address()).is_none() {
+                self.map_to(Page::containing_address(frame.start_address()), frame, EntryFlags::PRESENT)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps the framebuffer into the framebuffer region and returns it's virtual address.
+    ///
+    /// The pages are write combining when the processor supports the PAT, so the writes of a
+    /// whole row of pixels leave the processor in a few bursts. Uncached pages are used otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MemError::FramebufferTooLarge`] if the framebuffer does not fit into the region.
+    pub fn map_framebuffer(&mut self, fb: &Framebuffer) -> Result<VirtualAddress, MemError> {
+        let offset = fb.addr as usize % PAGE_SIZE;
+        let size = fb.pitch as usize * fb.height as usize;
+        if size == 0 || offset + size > layout::FRAMEBUFFER_END - layout::FRAMEBUFFER_START {
+            return Err(MemError::FramebufferTooLarge(size))
+        }
+
+        let caching = match Pat::is_enabled() {
+            true => EntryFlags::WRITE_COMBINING,
+            false => EntryFlags::NO_CACHE | EntryFlags::WRITE_THROUGH,
+        };
+        let first = Frame::info_address(fb.addr as usize);
+        let last = Frame::info_address(fb.addr as usize + size - 1);
+        for (i, frame) in Frame::range_inclusive(first, last).enumerate() {
+            let page = Page::containing_address(layout::FRAMEBUFFER_START + i * PAGE_SIZE);
+            self.map_to(page, frame, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE | caching)?;
+        }
+        Ok(layout::FRAMEBUFFER_START + offset)
+    }
+
     /// Maps the page to some free frame with the provided flags.
     ///
     /// This function is a wrapped interface that abstracts the need of providing frame allocator.
@@ -207,13 +342,257 @@ impl MMU {
         self.unmap(Page::containing_address(ptr as usize))
     }
 
-    /// Unmaps the given page and adds all freed frames to the area frame allocator.
+    /// Unmaps the given page. The frame it was mapped to stays allocated.
     ///
     /// This function is a wrapped interface that abstracts the need of providing frame allocator.
     pub fn unmap(&mut self, page: Page) -> MMUResult { 
         self.with_active_table(|at, fa| at.unmap(page, fa))
     }
 
+    /// Unlinks the empty page tables within the gigabyte of the address, freeing their frames once
+    /// no processor can walk through them anymore.
+    ///
+    /// Returns the amount of freed tables.
+    pub fn free_page_tables(&mut self, addr: VirtualAddress) -> usize {
+        let mut unlinked = Vec::new();
+        let _ = self.with_active_table(|at, _| unlinked = at.unlink_empty_tables(Page::containing_address(addr)));
+        let freed = unlinked.len();
+        unlinked.into_iter().for_each(|frame| self.deallocate_frame_deferred(frame));
+        freed
+    }
+
+    /// Returns the multiboot information structure provided by the bootloader.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the memory is not initialized yet.
+    pub fn boot_info(&self) -> &InfoPointer<'static> {
+        self.info_pointer.as_ref().expect("The memory is not initialized.")
+    }
+
+    /// Returns the amount of frames allocated by the MMU.
+    pub fn frames_allocated(&self) -> usize {
+        self.frames_allocated
+    }
+
+    /// Allocates a single physical frame without mapping it.
+    ///
+    /// Returns None if the memory is not initialized yet or no free frames are left.
+    pub fn allocate_frame(&mut self) -> Option<Frame> {
+        self.active_table.as_ref()?;
+        if deferred::pending() != 0 {
+            deferred::reclaim(self);
+        }
+        let frame = self.frame_allocator.alloc()?;
+        self.frames_allocated += 1;
+        Some(frame)
+    }
+
+    /// Allocates a frame for a page which must start zeroed.
+    ///
+    /// A frame of the zeroing thread is taken when one is available, otherwise a regular frame is
+    /// allocated. The flag tells if the frame is zeroed already, the caller must clear it if not.
+    pub fn allocate_zeroed_frame(&mut self) -> Option<(Frame, bool)> {
+        self.active_table.as_ref()?;
+        match prezero::take() {
+            Some(frame) => Some((frame, true)),
+            None => self.allocate_frame().map(|frame| (frame, false)),
+        }
+    }
+
+    /// Returns the unmapped frame to the frame allocator.
+    ///
+    /// Frames which were mapped while other processors run must go through
+    /// [`MMU::deallocate_frame_deferred`] instead.
+    pub fn deallocate_frame(&mut self, frame: Frame) {
+        if self.active_table.is_some() {
+            self.frame_allocator.dealloc(frame);
+            self.frames_allocated = self.frames_allocated.saturating_sub(1);
+        }
+    }
+
+    /// Allocates a naturally aligned block of 2^order physically contiguous frames without mapping
+    /// it, for drivers sharing memory with devices. The block is charged to the driver running at
+    /// the moment, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MemError::OrderTooLarge`] if the order is above [`MAX_ORDER`] and
+    /// [`MemError::OutOfFrames`] if no free block is large enough.
+    pub fn alloc_pages(&mut self, order: usize) -> Result<PhysRegion, MemError> {
+        if order > MAX_ORDER {
+            return Err(MemError::OrderTooLarge(order))
+        }
+        self.active_table.as_ref().ok_or(MemError::NoFrameAlloc)?;
+        if deferred::pending() != 0 {
+            deferred::reclaim(self);
+        }
+
+        let region = match self.page_blocks.take(order, &mut self.frame_allocator) {
+            Some(region) => region,
+            None => {
+                let first = self.frame_allocator.alloc_aligned(1 << order).ok_or(MemError::OutOfFrames)?;
+                PhysRegion::new(first.start_address(), order)
+            },
+        };
+        self.frames_allocated += 1 << order;
+        accounting::charge_frames(1 << order);
+        Ok(region)
+    }
+
+    /// Returns the block allocated with [`MMU::alloc_pages`].
+    ///
+    /// The block must not be mapped anymore. Like with [`MMU::deallocate_frame`], a block which
+    /// was mapped while other processors run is only freed after the shootdown of the mapping.
+    pub fn free_pages(&mut self, region: PhysRegion) {
+        if self.active_table.is_some() {
+            self.page_blocks.insert(region, &mut self.frame_allocator);
+            self.frames_allocated = self.frames_allocated.saturating_sub(1 << region.order());
+            accounting::charge_frames(-(1 << region.order()));
+        }
+    }
+
+    /// Returns the unmapped frame to the frame allocator, once no other processor can reach it
+    /// through a stale TLB entry or a page table walk in progress.
+    pub fn deallocate_frame_deferred(&mut self, frame: Frame) {
+        deferred::defer(self, frame)
+    }
+
+    /// Changes the flags of the mapped page, keeping the frame it is mapped to.
+    ///
+    /// The `PRESENT` flag is added by default and the TLB entry of the page is flushed on every
+    /// processor.
+    pub fn update_flags(&mut self, page: Page, flags: EntryFlags) -> MMUResult {
+        let mut updated = false;
+        self.with_active_table(|at, _| updated = at.update_flags(page, flags))?;
+        match updated {
+            true => Ok(()),
+            false => Err(MemError::NotMapped),
+        }
+    }
+
+    /// Translates the virtual address to the physical one with the current active table.
+    ///
+    /// Returns None if the address is not mapped or the memory is not initialized yet.
+    pub fn translate(&self, addr: VirtualAddress) -> Option<PhysicalAddress> {
+        self.active_table.as_ref().and_then(|at| at.translate(addr))
+    }
+
+    /// Returns the raw flags of the entry which maps the page.
+    ///
+    /// Returns None if the page is not mapped or the memory is not initialized yet.
+    pub fn page_flags(&self, page: Page) -> Option<u64> {
+        let start = page.start_address();
+        self.active_table.as_ref()?
+            .mappings(start..start + PAGE_SIZE)
+            .first()
+            .map(|mapping| mapping.flags)
+    }
+
+    /// Prints heap and physical frame usage.
+    pub fn dump_stats(&self) {
+        use crate::GLOBAL_ALLOCATOR;
+
+        unsafe {
+            println!("heap:   {:#x} ({} KiB)", GLOBAL_ALLOCATOR.heap_addr, GLOBAL_ALLOCATOR.arena_size / 1024);
+        }
+        println!("frames: {} ({} leaked)", self.frames_allocated(), self.frame_allocator.leaked());
+        println!(
+            "lazy:   {} regions, {} pages populated",
+            self.lazy_regions.iter().count(), self.lazy_regions.populated(),
+        );
+        println!("zeroed: {} frames pooled, {} scrub errors", prezero::pooled(), prezero::scrub_errors());
+        println!("deferred: {} frames pending, {} synchronous waits", deferred::pending(), deferred::stalls());
+        println!("blocks: {} frames in free contiguous blocks", self.page_blocks.free_frames());
+    }
+
+    /// Reserves the page aligned range of virtual addresses to be mapped on demand.
+    ///
+    /// No frames are allocated here. Each page is mapped to a zeroed frame with the provided flags
+    /// by [`MMU::handle_lazy_fault`] on the first access. The range must not be used for stacks,
+    /// because the page fault itself pushes the exception frame on the faulting stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MemError::InvalidRegion`] if the range is empty, unaligned or overlaps another
+    /// lazy region and [`MemError::LazyRegionsFull`] if no more regions can be reserved.
+    pub fn reserve_lazy(&mut self, range: core::ops::Range<VirtualAddress>, flags: EntryFlags) -> MMUResult {
+        self.lazy_regions.insert(range, flags.bits())
+    }
+
+    /// Populates the page of a lazy region which contains the faulting address.
+    ///
+    /// Called by the page fault handler for faults on pages which are not present. Returns false
+    /// if the address is outside of every lazy region or no frames are left, in which case the
+    /// fault is a real one. The fault may come from the middle of a heap allocation, so nothing
+    /// here touches the heap. A fault taken while the same processor is already populating a page
+    /// is returned as a real one, instead of reentering the frame allocator.
+    pub fn handle_lazy_fault(&mut self, addr: VirtualAddress) -> bool {
+        let Some(region) = self.lazy_regions.find(addr) else { return false };
+
+        // Other processors wait for their turn, as the tables are not locked.
+        let cpu = smp::cpu_id() + 1;
+        loop {
+            match self.lazy_fault_owner.compare_exchange(0, cpu, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(owner) if owner == cpu => return false,
+                Err(_) => core::hint::spin_loop(),
+            }
+        }
+        let populated = self.populate_lazy(Page::containing_address(addr), region.flags);
+        self.lazy_fault_owner.store(0, Ordering::Release);
+        populated
+    }
+
+    /// Maps the page of a lazy region to a zeroed frame with the flags of the region.
+    fn populate_lazy(&mut self, page: Page, region_flags: u64) -> bool {
+        // Another processor populated the page while this one waited.
+        if self.translate(page.start_address()).is_some() {
+            return true
+        }
+        let Some((frame, zeroed)) = self.allocate_zeroed_frame() else { return false };
+
+        // The page is writable until it is zeroed.
+        let flags = EntryFlags::Custom(region_flags);
+        if self.map_to(page, frame.clone(), flags | EntryFlags::WRITABLE).is_err() {
+            self.deallocate_frame(frame);
+            return false
+        }
+        if !zeroed {
+            unsafe { core::ptr::write_bytes(page.start_address() as *mut u8, 0, PAGE_SIZE) };
+        }
+        if !EntryFlags::WRITABLE.is_in(region_flags) && self.update_flags(page, flags).is_err() {
+            return false
+        }
+
+        self.lazy_regions.count_populated();
+        true
+    }
+
+    /// Prints every mapping of the active page table.
+    ///
+    /// Does nothing if the memory is not initialized yet.
+    pub fn dump_mappings(&self) {
+        if let Some(at) = self.active_table.as_ref() {
+            at.dump(0..usize::MAX);
+        }
+    }
+
+    /// Checks if every page within the provided range of virtual addresses is mapped.
+    ///
+    /// Returns false for empty ranges and when the memory is not initialized yet.
+    pub fn is_mapped(&self, range: core::ops::Range<VirtualAddress>) -> bool {
+        if range.is_empty() {
+            return false
+        }
+
+        let start = Page::containing_address(range.start);
+        let end = Page::containing_address(range.end - 1);
+
+        Page::range_inclusive(start, end)
+            .all(|page| self.translate(page.start_address()).is_some())
+    }
+
     fn with_active_table<F>(&mut self, f: F) -> MMUResult 
         where F: FnOnce(&mut ActivePageTable, &mut AreaFrameAllocator)
     {
@@ -227,21 +606,51 @@ impl MMU {
 
     /// Initiates and returns a new custom stack, with the current active page table.
     /// 
-    /// You have to obtain the active page table, before you can. If it will be unable 
-    /// to allocate guard page, starting page or the end page it will return None.
+    /// You have to obtain the active page table, before you can.
     ///
-    /// # Panics
+    /// # Errors
     /// 
-    /// Panics if the main memory is not initialized.
+    /// Returns [`MemError::NoFrameAlloc`] if the main memory is not initialized and
+    /// [`MemError::StackExhausted`] if it will be unable to allocate guard page, starting page
+    /// or the end page within the stacks region.
+    ///
+    /// # Note
+    ///
+    /// Unlike the heap, stacks are mapped eagerly. A page fault on an unmapped stack page would
+    /// push it's exception frame on the same page and end up as a double fault.
     #[inline]
-    pub fn allocate_stack(&mut self, size: usize) -> Option<Stack> {
-        assert!(self.active_table.is_some(), "Cannot allocate stack, before the active page becomes available.");
+    pub fn allocate_stack(&mut self, size: usize) -> Result<Stack, MemError> {
+        let active_table = self.active_table.as_mut().ok_or(MemError::NoFrameAlloc)?;
 
         self.stack_allocator.alloc_stack(
-            self.active_table.as_mut().unwrap(),
+            active_table,
             &mut self.frame_allocator, 
             size
-        )
+        ).ok_or(MemError::StackExhausted)
+    }
+
+    /// Unmaps the stack and frees it's frames.
+    ///
+    /// The frames return to the frame allocator and the pages are kept by the stack allocator for
+    /// the following stacks. The stack must not be in use anymore, not even by the running thread.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MemError::NotMapped`] if the stack does not lie within the stacks region.
+    pub fn deallocate_stack(&mut self, stack: Stack) -> MMUResult {
+        if stack.bottom < layout::STACKS_START || stack.top > layout::STACKS_END {
+            return Err(MemError::NotMapped)
+        }
+
+        let start = Page::containing_address(stack.bottom);
+        let end = Page::containing_address(stack.top - 1);
+        for page in Page::range_inclusive(start, end) {
+            let Some(addr) = self.translate(page.start_address()) else { continue };
+            self.unmap(page)?;
+            self.deallocate_frame_deferred(Frame::info_address(addr));
+        }
+        self.stack_allocator.release(stack);
+        Ok(())
     }
 
     /// Sets up a stack for interrupt stack.
@@ -257,12 +666,11 @@ impl MMU {
     /// This should be done before loading the GDT table to the architecture. After setting the
     /// stack, the TSS must be loaded to the GDT in order to work properly.
     #[inline]
-    pub fn set_interrupt_stack(&mut self, tss: &mut TSS, index: usize, size: usize) {
+    pub fn set_interrupt_stack(&mut self, tss: &mut TSS, index: usize, size: usize) -> MMUResult {
         assert!(index < 7, "The IST is only 7 entries long.");
 
-        tss.interrupt_stack_pointers_table[index] = self
-            .allocate_stack(size)
-            .expect("Unable to allocate memory for IST.").top;
+        tss.interrupt_stack_pointers_table[index] = self.allocate_stack(size)?.top;
+        Ok(())
     }
 
     /// Sets up a stack for privilege stack table.
@@ -278,12 +686,11 @@ impl MMU {
     /// This should be done before loading the GDT table to the architecture. After setting the
     /// stack, the TSS must be loaded to the GDT in order to work properly.
     #[inline]
-    pub fn set_privilege_stack(&mut self, tss: &mut TSS, index: usize, size: usize) {
+    pub fn set_privilege_stack(&mut self, tss: &mut TSS, index: usize, size: usize) -> MMUResult {
         assert!(index < 3, "The PST is only 3 entries long.");
 
-        tss.privilege_stack_pointers_table[index] = self
-            .allocate_stack(size)
-            .expect("Unable to allocate memory for PST.").top;
+        tss.privilege_stack_pointers_table[index] = self.allocate_stack(size)?.top;
+        Ok(())
     }
 
     /// Returns the page address in memory where some item is located.
@@ -302,29 +709,9 @@ impl MMU {
         A: FrameAlloc
     {
         use crate::Color;
-        use EntryFlags::*;
-
-        for (mut h1, h2) in rsdt.pointers().iter().map(|(ptr)| unsafe {  
-            let h1 = (ptr.v1[0] as *mut u32).cast::<ACPISDTHeader>(); 
-            let h2 = (ptr.v1[1] as *mut u32).cast::<ACPISDTHeader>(); 
-            (h1, h2)
-        }) {
-            // Performing twice due to non aligned 32-bit pointers.
-            for _ in 0..2 {
-                unsafe {
-                    if !h1.is_null() {
-                        println!("Mapping ACPI table: {:?}", h1.read_unaligned().signature);
-                        let start = h1 as usize;
-                        let start_frame = Frame::info_address(start);
-                        let end_frame = Frame::info_address(start + h1.read_unaligned().length as usize);
-
-                        for frame in Frame::range_inclusive(start_frame, end_frame) {
-                            mapper.indentity_map(frame, WRITABLE, allocator);
-                        }
-                    }
-                }
-                h1 = h2;
-            }
+
+        for header in rsdt.tables() {
+            MMU::map_acpi_table(header, mapper, allocator);
         }
     }
 
@@ -338,172 +725,301 @@ impl MMU {
         A: FrameAlloc
     {
         use crate::Color;
-        use EntryFlags::*;
-
-        for header in xsdt.pointers().iter().map(|(ptr)| unsafe {(ptr.v2 as *mut usize).cast::<ACPISDTHeader>()}) {
-            unsafe {
-                if !header.is_null() {
-                    println!("Mapping ACPI table: {:?}", header.read_unaligned().signature);
-                    let start = header as usize;
-                    let start_frame = Frame::info_address(start);
-                    let end_frame = Frame::info_address(start + header.read_unaligned().length as usize);
-
-                    for frame in Frame::range_inclusive(start_frame, end_frame) {
-                        mapper.indentity_map(frame, WRITABLE, allocator);
-                    }
-                }
-            }
+
+        for header in xsdt.tables() {
+            MMU::map_acpi_table(header, mapper, allocator);
         }
     }
 
     /// Remaps sections of kernel.
+    ///
+    /// Returns the new active table and whether the ACPI tables were mapped. Failing to map the
+    /// ACPI tables is not fatal, the kernel continues without ACPI then.
     #[inline]
-    fn remap_kernel<A>(allocator: &mut A, boot_info: &InfoPointer) -> ActivePageTable
+    fn remap_kernel<A>(
+        allocator: &mut A,
+        boot_info: &InfoPointer,
+        early_allocations: Option<(PhysicalAddress, PhysicalAddress)>,
+    ) -> Result<(ActivePageTable, bool), MemError>
         where A: FrameAlloc
     {
-        use crate::kernel_components::arch_x86_64::acpi::rsdp::{RSDP, XSDP};
-        use crate::Color;
-
-        let mut temporary_page = TempPage::new( Page::containing_address(0xdeadbeef), allocator);
+        let mut temporary_page = TempPage::new( Page::containing_address(layout::TEMP_PAGE), allocator);
         let mut active_table = unsafe { ActivePageTable::new() };
         let mut new_table = {
-            let frame = allocator.alloc().expect("no more frames to allocate.");
+            let frame = allocator.alloc().ok_or(MemError::OutOfFrames)?;
             InactivePageTable::new(frame, &mut active_table, &mut temporary_page)
         };
 
+        let mut mapped = Ok(());
+        let mut acpi = Err(MemError::AcpiMapFailed);
         active_table.with(&mut new_table, &mut temporary_page, |mapper| {
-            use super::EntryFlags::{*, self};
-            
-            let elf_sections_tag = boot_info
-                .elf_sections_tag()
-                .expect("Elf-sections tag required.");
+            progress(Stage::RemapKernel);
+            mapped = MMU::map_kernel_sections(mapper, allocator, boot_info, early_allocations);
+            if mapped.is_ok() {
+                progress(Stage::MapAcpi);
+                acpi = MMU::map_acpi(mapper, allocator, boot_info);
+            }
+        });
+        mapped?;
 
-            for section in elf_sections_tag {
-                use super::frames::PAGE_SIZE;
+        if let Err(err) = acpi {
+            crate::warn!("{} Continuing without ACPI.", err);
+        }
 
-                if !section.is_allocated() {
-                    continue
-                }
+        progress(Stage::SwitchTable);
+        let old_table = active_table.switch(new_table);
+        let old_p4_page = Page::containing_address(
+            old_table.p4_frame.start_address()
+        );
 
-                if section.start_address() % PAGE_SIZE as u64 != 0 {
-                    panic!("Sections must be page aligned! 
-                        Expected section to be {PAGE_SIZE} bytes aligned. 
-                        Received value: {:#x} which is not properly aligned.\n
-                        Section data:\n
-                                name: {}, 
-                                type: {:?}, 
-                                addr: {:#x},
-                                size: {:#x},
-                                alignment constraints: {}, 
-                                flags: {:#x}.\n", 
-                            section.start_address(), 
-                            section.name().unwrap_or("No name"),
-                            section.section_type(),
-                            section.start_address(), 
-                            section.size(),
-                            section.addralign(),
-                            section.get().flags());
-                }
+        active_table.unmap(old_p4_page, allocator);
+        crate::trace!(PAGING; "Guard page at {:#x}", old_p4_page.start_address());
 
-                #[cfg(debug_assertions)] {
-                    println!(Color::LIGHTGREEN; "Mapping section at addr: {:#x}, size: {:#x}", section.start_address(), section.size());
-                }
-                
-                let flags = EntryFlags::from_elf_section_flags(&section);
-
-                let start_frame = Frame::info_address(section.start_address() as usize);
-                let end_frame = Frame::info_address(section.end_address() as usize - 1);
-                
-                for frame in Frame::range_inclusive(start_frame, end_frame) {
-                    mapper.indentity_map(frame, flags, allocator);
-                }
-            }
+        Ok((active_table, acpi.is_ok()))
+    }
+
+    /// Maps the kernel sections at their link addresses, and identity maps the multiboot structure,
+    /// the VGA buffer and the memory of early boot allocations into the new table.
+    fn map_kernel_sections<A>(
+        mapper: &mut InnerMapper,
+        allocator: &mut A,
+        boot_info: &InfoPointer,
+        early_allocations: Option<(PhysicalAddress, PhysicalAddress)>,
+    ) -> MMUResult where A: FrameAlloc {
+        use super::EntryFlags::{*, self};
+
+        let Some(elf_sections_tag) = boot_info.elf_sections_tag() else {
+            // Without section headers the whole image is mapped writable, as there is no way to
+            // tell code and read only data apart.
+            let (kernel_start, kernel_end) = linker_bounds();
+            crate::warn!(
+                "No ELF-sections tag provided, mapping the kernel image {:#x} - {:#x} as a whole.",
+                kernel_start, kernel_end,
+            );
+            Self::map_kernel_range(mapper, allocator, kernel_start as usize, kernel_end as usize, WRITABLE);
+            return Self::map_boot_regions(mapper, allocator, boot_info, early_allocations)
+        };
 
-            // identity map the multiboot info structure.
-            let multiboot_start = Frame::info_address(boot_info.mstart());
-            let multiboot_end = Frame::info_address(boot_info.mend());
+        for section in elf_sections_tag {
+            if !section.is_allocated() {
+                continue
+            }
 
-            for frame in Frame::range_inclusive(multiboot_start, multiboot_end) {
-                mapper.indentity_map(frame, PRESENT, allocator);
+            if section.start_address() % PAGE_SIZE as u64 != 0 {
+                crate::warn!(
+                    "Sections must be {} bytes aligned. Section: {}, type: {:?}, addr: {:#x}, size: {:#x}, alignment: {}, flags: {:#x}.",
+                    PAGE_SIZE,
+                    section.name().unwrap_or("No name"),
+                    section.section_type(),
+                    section.start_address(),
+                    section.size(),
+                    section.addralign(),
+                    section.get().flags(),
+                );
+                return Err(MemError::UnalignedSection(section.start_address()))
             }
+
+            crate::trace!(PAGING; "Mapping section at addr: {:#x}, size: {:#x}", section.start_address(), section.size());
             
-            // identity map the VGA text buffer.
-            let vga_buffer_frame = Frame::info_address(0xb8000);
-            mapper.indentity_map(vga_buffer_frame, WRITABLE, allocator);
+            let flags = EntryFlags::from_elf_section_flags(&section);
+            let (start, end) = (section.start_address() as usize, section.end_address() as usize);
+            Self::map_kernel_range(mapper, allocator, start, end - 1, flags);
+        }
 
-            #[cfg(debug_assertions)] {
-                println!(Color::LIGHTGREEN; "Mapping ACPI tables.");
-            }
+        Self::map_boot_regions(mapper, allocator, boot_info, early_allocations)
+    }
 
-            // identity map the XSDT/RSDT and the rest of ACPI tables.
-            //
-            // We would have to go one by one for each ACPI table. Because of that
-            // RSDT is necessary to obtain all pointers to all tables and map them
-            // individually.
-            if let Some(x) = boot_info.get_tag::<ACPITagNew>() {
-                    // Have to firstly map the table before actually using it.
-                    let xsdt = unsafe { XSDT::from_xsdp(x.xsdp.clone()) };
-                    let xsdt_start = Frame::info_address(x.xsdp.ptr as usize);
-                    let xsdt_end = Frame::info_address(xsdt_start.num + xsdt.header.length as usize);
-
-                    // Mapping the RSDT itself
-                    for frame in Frame::range_inclusive(xsdt_start, xsdt_end) {
-                        mapper.indentity_map(frame, PRESENT, allocator);
-                    }
-
-                    // Mapping each ACPI table.
-                    MMU::map_acpi_xsdt(xsdt, mapper, allocator);
-            } else {
-                crate::warn!("XSDT is not present, mapping the legacy RSDT instead.");
-                if let Some(r) = boot_info.get_tag::<ACPITagOld>() {
-                    // Have to firstly map the table before actually using it.
-                    let rsdt = unsafe { RSDT::from_rsdp(r.rsdp.clone()) };
-                    let rsdt_start = Frame::info_address(r.rsdp.ptr as usize);
-                    let rsdt_end = Frame::info_address((r.rsdp.ptr + rsdt.header.length) as usize);
-
-                    // Mapping the RSDT itself
-                    for frame in Frame::range_inclusive(rsdt_start, rsdt_end) {
-                        mapper.indentity_map(frame, PRESENT, allocator);
-                    }
-
-                    // Mapping each ACPI table.
-                    MMU::map_acpi_rsdt(rsdt, mapper, allocator);
-                } else {
-                    panic!("RSDT is not present. Unable to identity map.");
-                }
+    /// Maps the pages of the kernel image between the addresses, both inclusive, to the frames the
+    /// image was loaded to.
+    fn map_kernel_range<A>(
+        mapper: &mut InnerMapper,
+        allocator: &mut A,
+        start: VirtualAddress,
+        last: VirtualAddress,
+        flags: EntryFlags,
+    ) where A: FrameAlloc {
+        for page in Page::range_inclusive(Page::containing_address(start), Page::containing_address(last)) {
+            let frame = Frame::info_address(layout::kernel_phys(page.start_address()));
+            mapper.map_to(page, frame, flags, allocator);
+        }
+    }
+
+    /// Identity maps the multiboot information, the VGA buffer and the early boot allocations.
+    fn map_boot_regions<A>(
+        mapper: &mut InnerMapper,
+        allocator: &mut A,
+        boot_info: &InfoPointer,
+        early_allocations: Option<(PhysicalAddress, PhysicalAddress)>,
+    ) -> MMUResult where A: FrameAlloc {
+        use super::EntryFlags::*;
+
+        // identity map the multiboot info structure.
+        let multiboot_start = Frame::info_address(boot_info.mstart());
+        let multiboot_end = Frame::info_address(boot_info.mend());
+
+        for frame in Frame::range_inclusive(multiboot_start, multiboot_end) {
+            mapper.indentity_map(frame, PRESENT, allocator);
+        }
+        
+        // identity map the VGA text buffer.
+        let vga_buffer_frame = Frame::info_address(0xb8000);
+        mapper.indentity_map(vga_buffer_frame, WRITABLE, allocator);
+
+        // identity map the memory of early boot allocations.
+        if let Some((start, end)) = early_allocations {
+            for frame in Frame::range_inclusive(Frame::info_address(start), Frame::info_address(end - 1)) {
+                mapper.indentity_map(frame, WRITABLE, allocator);
             }
-        });
+        }
+        Ok(())
+    }
 
-        let old_table = active_table.switch(new_table);
-        let old_p4_page = Page::containing_address(
-            old_table.p4_frame.start_address()
-        );
+    /// Identity maps the XSDT/RSDT and the rest of ACPI tables into the new table.
+    ///
+    /// We would have to go one by one for each ACPI table. Because of that
+    /// RSDT is necessary to obtain all pointers to all tables and map them
+    /// individually.
+    fn map_acpi<A>(mapper: &mut InnerMapper, allocator: &mut A, boot_info: &InfoPointer) -> MMUResult
+        where A: FrameAlloc
+    {
+        use super::EntryFlags::*;
 
-        active_table.unmap(old_p4_page, allocator);
-        #[cfg(debug_assertions)] {
-            println!(Color::LIGHTGRAY; "Guard page at {:#x}", old_p4_page.start_address());
+        crate::trace!(ACPI; "Mapping ACPI tables.");
+
+        // Invalid XSDP or XSDT is logged by validation, the legacy RSDT is used then.
+        let xsdt = boot_info.get_tag::<ACPITagNew>()
+            .filter(|x| x.xsdp.ptr != 0)
+            .filter(|x| x.xsdp.validate().map_err(|e| report_invalid("XSDP", &x.xsdp as *const _ as usize, e)).is_ok())
+            .and_then(|x| Some((x.xsdp.ptr as usize, unsafe { XSDT::from_xsdp(x.xsdp.clone()) }.ok()?)));
+
+        if let Some((xsdt_start, xsdt)) = xsdt {
+            // Mapping the XSDT itself
+            MMU::map_acpi_range(xsdt_start, xsdt_start + xsdt.header.length as usize, mapper, allocator);
+
+            // Mapping each ACPI table.
+            MMU::map_acpi_xsdt(xsdt, mapper, allocator);
+        } else {
+            crate::warn!("XSDT is not usable, mapping the legacy RSDT instead.");
+            let r = boot_info.get_tag::<ACPITagOld>()
+                .filter(|r| r.rsdp.ptr != 0)
+                .ok_or(MemError::AcpiMapFailed)?;
+            r.rsdp.validate().map_err(|e| {
+                report_invalid("RSDP", &r.rsdp as *const _ as usize, e);
+                MemError::AcpiMapFailed
+            })?;
+
+            // Have to firstly map the table before actually using it.
+            let rsdt = unsafe { RSDT::from_rsdp(r.rsdp.clone()) }.map_err(|_| MemError::AcpiMapFailed)?;
+
+            // Mapping the RSDT itself
+            let rsdt_start = r.rsdp.ptr as usize;
+            MMU::map_acpi_range(rsdt_start, rsdt_start + rsdt.header.length as usize, mapper, allocator);
+
+            // Mapping each ACPI table.
+            MMU::map_acpi_rsdt(rsdt, mapper, allocator);
         }
 
-        active_table
+        // Firmware may keep more data in the ACPI reclaim and NVS areas than the tables point to.
+        for area in boot_info.memory_map_tag().into_iter().flat_map(|tag| tag.memory_map_iter()) {
+            if matches!(area.area_type(), MemoryAreaType::AcpiAvailable | MemoryAreaType::ReservedHibernate) {
+                MMU::map_acpi_range(area.start_address() as usize, area.end_address() as usize, mapper, allocator);
+            }
+        }
+        Ok(())
+    }
+
+    /// Identity maps a single ACPI table read-only.
+    ///
+    /// Lengths of corrupted headers are capped, the table is validated later when it is used.
+    fn map_acpi_table<A>(header: *const ACPISDTHeader, mapper: &mut InnerMapper, allocator: &mut A)
+        where A: FrameAlloc
+    {
+        let header_copy = unsafe { header.read_unaligned() };
+        crate::trace!(ACPI; "Mapping ACPI table: {:?}", header_copy.signature);
+
+        let start = header as usize;
+        let length = header_copy.length.clamp(mem::size_of::<ACPISDTHeader>() as u32, MAX_TABLE_LENGTH);
+        MMU::map_acpi_range(start, start + length as usize, mapper, allocator);
+    }
+
+    /// Identity maps the ACPI memory range read-only. Pages mapped already are left untouched, since
+    /// tables often share pages.
+    fn map_acpi_range<A>(start: PhysicalAddress, end: PhysicalAddress, mapper: &mut InnerMapper, allocator: &mut A)
+        where A: FrameAlloc
+    {
+        let last = Frame::info_address(end.max(start + 1) - 1);
+        for frame in Frame::range_inclusive(Frame::info_address(start), last) {
+            if mapper.translate_page(Page::containing_address(frame.start_address())).is_none() {
+                mapper.indentity_map(frame, EntryFlags::PRESENT, allocator);
+            }
+        }
     }
 
     /// Gets a tag of the rsdp pointer, which is copied by multiboot2 during boot process.
+    ///
+    /// Returns None if the ACPI tables are not mapped.
     pub(crate) fn get_rsdp(&self) -> Option<&ACPITagOld> {
-        self.info_pointer.get_tag::<ACPITagOld>()
+        self.info_pointer.as_ref().filter(|_| self.acpi_mapped)?.get_tag::<ACPITagOld>()
     }
 
     /// Gets a tag of the xsdp pointer, which is copied by multiboot2 during boot process.
+    ///
+    /// Returns None if the ACPI tables are not mapped.
     pub(crate) fn get_xsdp(&self) -> Option<&ACPITagNew> {
-        self.info_pointer.get_tag::<ACPITagNew>()
+        self.info_pointer.as_ref().filter(|_| self.acpi_mapped)?.get_tag::<ACPITagNew>()
     }
 }
 
 /// Enum that defines memory related errors.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum MemError {
+    /// The memory is not initialized, so there is no frame allocator.
     NoFrameAlloc,
+    /// The page is not mapped.
+    NotMapped,
+    /// The multiboot information structure is invalid.
+    BootInfo(MbiLoadError),
+    /// The bootloader did not provide a required multiboot tag.
+    MissingTag(TagType),
+    /// The kernel section at the address is not page aligned.
+    UnalignedSection(u64),
+    /// No free frames are left.
+    OutOfFrames,
+    /// The ACPI tables are not provided or cannot be mapped.
+    AcpiMapFailed,
+    /// The stacks region of the address space is used up.
+    StackExhausted,
+    /// The lazy region at the address is empty, unaligned or overlaps another one.
+    InvalidRegion(VirtualAddress),
+    /// The framebuffer of the size in bytes does not fit into the framebuffer region.
+    FramebufferTooLarge(usize),
+    /// No more lazy regions can be reserved.
+    LazyRegionsFull,
+    /// Blocks of contiguous frames of this order are larger than the largest block.
+    OrderTooLarge(usize),
 }
 
+impl Display for MemError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MemError::NoFrameAlloc => write!(f, "The memory is not initialized."),
+            MemError::NotMapped => write!(f, "The page is not mapped."),
+            MemError::BootInfo(err) => write!(f, "Invalid multiboot information: {}", err),
+            MemError::MissingTag(tag) => write!(f, "Required multiboot tag is missing: {:?}.", tag),
+            MemError::UnalignedSection(addr) => write!(f, "Kernel section at {:#x} is not page aligned.", addr),
+            MemError::OutOfFrames => write!(f, "No more frames to allocate."),
+            MemError::AcpiMapFailed => write!(f, "Unable to map the ACPI tables."),
+            MemError::StackExhausted => write!(f, "The stacks region is exhausted."),
+            MemError::InvalidRegion(addr) => write!(f, "Invalid lazy region at {:#x}.", addr),
+            MemError::FramebufferTooLarge(size) => write!(f, "The framebuffer of {} bytes is too large.", size),
+            MemError::LazyRegionsFull => write!(f, "No more lazy regions can be reserved."),
+            MemError::OrderTooLarge(order) => write!(f, "No blocks of order {} can be allocated.", order),
+        }
+    }
+}
+
+impl Error for MemError {}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(C)]
 pub struct BootInfoHeader {
@@ -592,8 +1108,8 @@ impl<'a> InfoPointer<'a> {
 
     pub fn get_tag<TagT: TagTrait + ?Sized + 'a>(&'a self) -> Option<&'a TagT> {
         self.tags()
-            .find(|tag| tag.tag_type == TagT::ID.into())
-            .map(|tag| tag.cast_tag::<TagT>())
+            .filter(|tag| tag.tag_type == TagT::ID.into())
+            .find_map(|tag| tag.cast_tag::<TagT>())
     }
 
     /// Returns the total size of boot info header.
@@ -601,24 +1117,28 @@ impl<'a> InfoPointer<'a> {
         self.0.header.total
     }
 
-    /// Returns the start of the kernel
+    /// Returns the physical start of the kernel.
     pub fn kstart(&self) -> u64 {
-        self
-            .elf_sections_tag()
-            .expect("Elf-sections tag required.")
-            .map(|s| s.get().addr())
+        self.allocated_sections()
+            .map(|s| layout::kernel_phys(s.start_address() as usize) as u64)
             .min()
-            .unwrap()
+            .unwrap_or_else(|| layout::kernel_phys(linker_bounds().0 as usize) as u64)
     }
 
-    /// Returns the end of the kernel.
+    /// Returns the physical address of the last byte of the kernel.
     pub fn kend(&self) -> u64 {
-        self
-            .elf_sections_tag()
-            .expect("Elf-sections tag required.")
-            .map(|s| s.get().addr())
+        self.allocated_sections()
+            .map(|s| layout::kernel_phys(s.end_address() as usize - 1) as u64)
             .max()
-            .unwrap()
+            .unwrap_or_else(|| layout::kernel_phys(linker_bounds().1 as usize) as u64)
+    }
+
+    /// Iterates over sections loaded into memory. Empty when the loader provided no ELF-sections tag.
+    fn allocated_sections(&self) -> impl Iterator<Item = ElfSection> {
+        self.elf_sections_tag()
+            .into_iter()
+            .flatten()
+            .filter(|s| s.is_allocated() && s.size() > 0)
     }
 
     /// Returns the start of multiboot structure.
@@ -636,12 +1156,25 @@ impl<'a> InfoPointer<'a> {
         self.get_tag::<ACPITagOld>()
     }
 
+    /// Returns the kernel command line provided by the bootloader.
+    pub fn command_line(&self) -> Option<&str> {
+        self.get_tag::<CommandLineTag>()
+            .and_then(|tag| tag.cmdline().ok())
+    }
+
+    /// Returns the direct RGB framebuffer set up by the bootloader. None in text mode.
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        self.get_tag::<FramebufferTag>()
+            .and_then(FramebufferTag::framebuffer)
+    }
+
     /// Returns a new ACPI tag, which caintains XSDP pinter of ACPI v2.0
     pub fn acpi_new(&self) -> Option<&ACPITagNew> {
         self.get_tag::<ACPITagNew>()
     }
 
-    fn tags(&self) -> TagIter {
+    /// Returns an iterator over all tags of the boot information.
+    pub fn tags(&self) -> TagIter {
         TagIter::new(&self.0.tags)
     }
 }
@@ -656,6 +1189,34 @@ impl<'a> Clone for InfoPointer<'a> {
     }
 }
 
+#[cfg(not(feature = "uefi"))]
+extern "C" {
+    static __kernel_start: u8;
+    static __kernel_end: u8;
+}
+
+/// Returns the first and the last byte of the kernel image, as placed by the linker script.
+///
+/// Used instead of the section headers, when the loader does not provide the ELF-sections tag.
+#[cfg(not(feature = "uefi"))]
+pub fn linker_bounds() -> (u64, u64) {
+    unsafe {
+        let start = &__kernel_start as *const u8 as u64;
+        let end = &__kernel_end as *const u8 as u64;
+        (start, end - 1)
+    }
+}
+
+/// Returns the first and the last byte of the kernel image, as loaded by the firmware.
+///
+/// The EFI application is not linked with the linker script, so the bounds come from the loaded
+/// image protocol instead.
+#[cfg(feature = "uefi")]
+pub fn linker_bounds() -> (u64, u64) {
+    let (start, end) = crate::kernel_components::boot::uefi::image_bounds();
+    (start, end.saturating_sub(1))
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum MbiLoadError {
     IllegalAddress,
@@ -812,3 +1373,34 @@ fn mapping_kernel() {
     page_table.unmap(Page::containing_address(addr), &mut frame_allocator);
     println!(Color::MAGENTA; "None = {:?}", page_table.translate(addr));
 }
+
+#[test_case]
+fn kernel_bounds_fall_back_to_linker_symbols() {
+    use crate::kernel_components::boot::BootInfoBuilder;
+    use super::sections::{ElfSectionInner64, ElfSectionFlags, ElfSectionType};
+
+    #[repr(C, align(8))]
+    struct Buffer([u8; 256]);
+    let mut buf = Buffer([0; 256]);
+
+    let builder = BootInfoBuilder::new(&mut buf.0).unwrap();
+    let mbi = builder.finish().unwrap();
+    let boot_info = unsafe { InfoPointer::load(mbi.as_ptr() as *const BootInfoHeader) }.unwrap();
+    let (start, end) = linker_bounds();
+    assert!(start < end);
+    let phys = |addr: u64| layout::kernel_phys(addr as usize) as u64;
+    assert_eq!((boot_info.kstart(), boot_info.kend()), (phys(start), phys(end)));
+
+    let sections = [
+        ElfSectionInner64::new(0, ElfSectionType::Unused, ElfSectionFlags::empty(), 0, 0),
+        ElfSectionInner64::new(0, ElfSectionType::ProgramSection, ElfSectionFlags::ALLOCATED, 0x200000, 0x3000),
+        ElfSectionInner64::new(0, ElfSectionType::ProgramSection, ElfSectionFlags::ALLOCATED, 0x204000, 0x1800),
+        // Debug sections are not loaded and have no address.
+        ElfSectionInner64::new(0, ElfSectionType::ProgramSection, ElfSectionFlags::empty(), 0, 0x9000),
+    ];
+    let mut builder = BootInfoBuilder::new(&mut buf.0).unwrap();
+    builder.elf_sections(sections, 0).unwrap();
+    let mbi = builder.finish().unwrap();
+    let boot_info = unsafe { InfoPointer::load(mbi.as_ptr() as *const BootInfoHeader) }.unwrap();
+    assert_eq!((boot_info.kstart(), boot_info.kend()), (0x200000, 0x2057ff));
+}
@@ -9,11 +9,14 @@ use super::{
     frames::{Frame, FrameAlloc, PAGE_SIZE}, 
     inactive_tables::InactivePageTable, 
     temporary_pages::TempPage,
+    layout::RECURSIVE_INDEX,
 };
 use crate::{VirtualAddress, PhysicalAddress, println};
-use crate::kernel_components::arch_x86_64::TLB;
+use crate::kernel_components::arch_x86_64::{shootdown, TLB};
 use core::ptr::NonNull;
-use core::ops::{Deref, DerefMut};
+use core::ops::{Deref, DerefMut, Range};
+use core::fmt::Display;
+use alloc::vec::Vec;
 
 /// This struct is a wrapper over the mapper struct. 
 #[derive(Debug)]
@@ -30,7 +33,7 @@ impl ActivePageTable {
 
     /// Temporary changes the recursive mapping and executes a given closure in the new context.
     /// 
-    /// It overwrites the 511th P4 entry and points it to the inactive table frame. 
+    /// It overwrites the recursive P4 entry and points it to the inactive table frame. 
     /// Then it flushes the translation lookaside buffer, which still contains some 
     /// old translations.
     pub fn with<F>(&mut self, table: &mut InactivePageTable, temp_page: &mut TempPage, f: F) 
@@ -45,14 +48,14 @@ impl ActivePageTable {
             let p4_table = temp_page.map_table_frame(backup_frame.clone(), self);
 
             // overwrite recursive mapping.
-            self.get_mut()[511].set(table.get_clone(), PRESENT | WRITABLE);
+            self.get_mut()[RECURSIVE_INDEX].set(table.get_clone(), PRESENT | WRITABLE);
             TLB::flush_all();
 
             // execute f in the new context.
             f(self);
 
             // restore recursive mapping to original p4 table.
-            p4_table[511].set(backup_frame, PRESENT | WRITABLE);
+            p4_table[RECURSIVE_INDEX].set(backup_frame, PRESENT | WRITABLE);
             TLB::flush_all();
         }
 
@@ -76,6 +79,39 @@ impl ActivePageTable {
         old_table
     }
 
+    /// Prints all mappings within the provided range of virtual addresses.
+    ///
+    /// Neighboring pages that map contiguous physical memory with the same flags are printed
+    /// as a single line:
+    ///
+    /// `virt_start-virt_end -> phys_start page_size xcount flags`
+    ///
+    /// See [`Mapping`] for the meaning of the flags column.
+    pub fn dump(&self, range: Range<VirtualAddress>) {
+        let mappings = self.mappings(range);
+        let mut iter = mappings.iter().peekable();
+
+        while let Some(first) = iter.next() {
+            let mut count = 1;
+            let mut last = first;
+
+            while let Some(next) = iter.next_if(|m| {
+                m.size == first.size && m.flags == first.flags &&
+                m.virt == last.virt + last.size && m.phys == last.phys + last.size
+            }) {
+                last = next;
+                count += 1;
+            }
+
+            println!(
+                "{:#018x}-{:#018x} -> {:#014x} {} x{} {}",
+                first.virt, last.virt + last.size, first.phys,
+                Mapping::size_str(first.size), count,
+                core::str::from_utf8(&Mapping::flags_str(first.flags)).unwrap()
+            );
+        }
+    }
+
     fn get(&self) -> &Table<Level4> {
         unsafe { self.p4.as_ref() }
     }
@@ -85,6 +121,69 @@ impl ActivePageTable {
     }
 }
 
+/// A single leaf translation of the page table.
+///
+/// When displayed, flags are shown as a compact column of letters, where '-' means that
+/// the flag is not set:
+/// - W: writable (R otherwise);
+/// - U: user accessible (K otherwise);
+/// - X: executable;
+/// - G: global;
+/// - A: accessed;
+/// - D: dirty;
+/// - C: caching disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    /// Start of the page.
+    pub virt: VirtualAddress,
+    /// Start of the frame the page is mapped to.
+    pub phys: PhysicalAddress,
+    /// Size of the page: 4 KiB, 2 MiB or 1 GiB.
+    pub size: usize,
+    /// Raw entry flags.
+    pub flags: u64,
+}
+
+impl Mapping {
+    /// Page size of 2 MiB huge pages.
+    pub const SIZE_2M: usize = PAGE_SIZE * ENTRY_COUNT;
+    /// Page size of 1 GiB huge pages.
+    pub const SIZE_1G: usize = Self::SIZE_2M * ENTRY_COUNT;
+
+    fn size_str(size: usize) -> &'static str {
+        match size {
+            Self::SIZE_1G => "1G",
+            Self::SIZE_2M => "2M",
+            _ => "4K",
+        }
+    }
+
+    fn flags_str(flags: u64) -> [u8; 7] {
+        use EntryFlags::*;
+        let bit = |flag: EntryFlags, c: u8, no: u8| if flag.is_in(flags) { c } else { no };
+
+        [
+            bit(WRITABLE, b'W', b'R'),
+            bit(USER_ACCESSIBLE, b'U', b'K'),
+            if NO_EXECUTE.is_in(flags) { b'-' } else { b'X' },
+            bit(GLOBAL, b'G', b'-'),
+            bit(ACCESSED, b'A', b'-'),
+            bit(DIRTY, b'D', b'-'),
+            bit(NO_CACHE, b'C', b'-'),
+        ]
+    }
+}
+
+impl Display for Mapping {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f, "{:#018x} -> {:#014x} {} {}",
+            self.virt, self.phys, Self::size_str(self.size),
+            core::str::from_utf8(&Self::flags_str(self.flags)).unwrap()
+        )
+    }
+}
+
 impl Deref for ActivePageTable {
     type Target = InnerMapper;
     fn deref(&self) -> &Self::Target {
@@ -160,6 +259,70 @@ impl InnerMapper {
         .or_else(huge_page)
     }
     
+    /// Collects every leaf mapping that intersects the provided range of virtual addresses.
+    ///
+    /// The recursive entry of the P4 table is skipped. Mappings are returned sorted by their
+    /// virtual address.
+    pub fn mappings(&self, range: Range<VirtualAddress>) -> Vec<Mapping> {
+        use EntryFlags::*;
+
+        // Canonical virtual address from table indexes.
+        let address = |i4: usize, i3: usize, i2: usize, i1: usize| {
+            let addr = (i4 << 39) | (i3 << 30) | (i2 << 21) | (i1 << 12);
+            if i4 >= ENTRY_COUNT / 2 { addr | 0xffff_0000_0000_0000 } else { addr }
+        };
+        let intersects = |start: usize, size: usize| start < range.end && range.start < start + size;
+
+        let mut mappings = Vec::new();
+        let mut push = |virt: usize, size: usize, flags: u64, frame: Frame| {
+            if intersects(virt, size) {
+                mappings.push(Mapping { virt, phys: frame.start_address(), size, flags });
+            }
+        };
+
+        let p4 = self.get();
+        for i4 in (0..ENTRY_COUNT).filter(|&i4| i4 != RECURSIVE_INDEX) {
+            let Some(p3) = p4.next_table(i4) else { continue };
+            if !intersects(address(i4, 0, 0, 0), Mapping::SIZE_1G * ENTRY_COUNT) {
+                continue
+            }
+
+            for i3 in 0..ENTRY_COUNT {
+                let entry = &p3[i3];
+                let Some(frame) = entry.pointed_frame() else { continue };
+                if HUGE_PAGE.is_in(entry.flags()) {
+                    push(address(i4, i3, 0, 0), Mapping::SIZE_1G, entry.flags(), frame);
+                    continue
+                }
+                let Some(p2) = p3.next_table(i3) else { continue };
+                if !intersects(address(i4, i3, 0, 0), Mapping::SIZE_1G) {
+                    continue
+                }
+
+                for i2 in 0..ENTRY_COUNT {
+                    let entry = &p2[i2];
+                    let Some(frame) = entry.pointed_frame() else { continue };
+                    if HUGE_PAGE.is_in(entry.flags()) {
+                        push(address(i4, i3, i2, 0), Mapping::SIZE_2M, entry.flags(), frame);
+                        continue
+                    }
+                    let Some(p1) = p2.next_table(i2) else { continue };
+                    if !intersects(address(i4, i3, i2, 0), Mapping::SIZE_2M) {
+                        continue
+                    }
+
+                    for i1 in 0..ENTRY_COUNT {
+                        let entry = &p1[i1];
+                        if let Some(frame) = entry.pointed_frame() {
+                            push(address(i4, i3, i2, i1), PAGE_SIZE, entry.flags(), frame);
+                        }
+                    }
+                }
+            }
+        }
+        mappings
+    }
+
     /// Maps the page to the frame with the provided flags.
     /// The `PRESENT` flag is added by default. Needs a
     /// `FrameAllocator` as it might need to create new page tables.
@@ -215,13 +378,60 @@ impl InnerMapper {
         let frame = p1[page.p1_index()].pointed_frame().unwrap();
         p1[page.p1_index()].set_unused();
         
-        // Flushing the given address in the TLB via 'invlpg' asm instruction. 
-        TLB::flush(page.start_address());
+        // Flushing the given address in the TLB of every processor.
+        shootdown::flush(page.start_address());
         
         // TODO free p(1,2,3) table if empty
         // allocator.dealloc(frame);
     }
 
+    /// Unlinks the P1 tables without mappings below the P3 entry of the page, and the P2 table
+    /// itself once it is left empty.
+    ///
+    /// Returns the frames of the unlinked tables, which the caller must free only after the other
+    /// processors flushed their paging structure caches.
+    pub fn unlink_empty_tables(&mut self, page: Page) -> Vec<Frame> {
+        let mut unlinked = Vec::new();
+        let Some(p3) = self.get_mut().next_table_mut(page.p4_index()) else { return unlinked };
+        let Some(p2) = p3.next_table_mut(page.p3_index()) else { return unlinked };
+
+        for i2 in 0..ENTRY_COUNT {
+            let Some(p1) = p2.next_table(i2) else { continue };
+            if (0..ENTRY_COUNT).all(|i1| p1[i1].is_unused()) {
+                unlinked.push(p2[i2].pointed_frame().unwrap());
+                p2[i2].set_unused();
+            }
+        }
+        if (0..ENTRY_COUNT).all(|i2| p2[i2].is_unused()) {
+            unlinked.push(p3[page.p3_index()].pointed_frame().unwrap());
+            p3[page.p3_index()].set_unused();
+        }
+
+        if !unlinked.is_empty() {
+            shootdown::flush_all();
+        }
+        unlinked
+    }
+
+    /// Changes the flags of the mapped page in place, keeping the frame it is mapped to.
+    /// The `PRESENT` flag is added by default.
+    ///
+    /// Returns false if the page is not mapped by a 4 KiB entry.
+    pub fn update_flags(&mut self, page: Page, flags: EntryFlags) -> bool {
+        use EntryFlags::*;
+
+        let p1 = self.get_mut()
+            .next_table_mut(page.p4_index())
+            .and_then(|p3| p3.next_table_mut(page.p3_index()))
+            .and_then(|p2| p2.next_table_mut(page.p2_index()));
+        let Some(p1) = p1 else { return false };
+        let Some(frame) = p1[page.p1_index()].pointed_frame() else { return false };
+
+        p1[page.p1_index()].set(frame, flags | PRESENT);
+        shootdown::flush(page.start_address());
+        true
+    }
+
     fn get(&self) -> &Table<Level4> {
         unsafe { self.p4.as_ref() }
     }
@@ -244,3 +454,14 @@ impl DerefMut for InnerMapper {
     }
 }
 
+
+#[test_case]
+fn mappings_match_translate() {
+    let table = unsafe { ActivePageTable::new() };
+    let mappings = table.mappings(0xb8000..0xb9000);
+
+    assert!(!mappings.is_empty());
+    for mapping in mappings {
+        assert_eq!(table.translate(mapping.virt), Some(mapping.phys));
+    }
+}
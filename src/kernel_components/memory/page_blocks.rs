@@ -0,0 +1,169 @@
+//! Naturally aligned blocks of physically contiguous frames.
+//!
+//! Drivers handing memory to a device, like AHCI command lists or the rings of a network card,
+//! need a physically contiguous buffer, which the heap does not guarantee. [`MMU::alloc_pages`]
+//! returns a block of 2^order frames whose physical address is aligned to it's size, and
+//! [`MMU::free_pages`] returns it.
+//!
+//! Freed blocks are kept in lists per order. A block is merged with it's buddy, the other half of
+//! the block one order above, whenever both are free, so freeing every block restores the large
+//! ones. Requests are served from the smallest free block, splitting it in halves until the order
+//! fits. Only if no block is large enough, a new one is carved from the frame allocator. Blocks
+//! which do not fit into their list are returned to the frame allocator as a single range, they
+//! are never merged again.
+//!
+//! The frames of a block are not mapped. They are within the identity mapped region if below
+//! 1 GiB, otherwise the caller maps them where it needs.
+//!
+//! [`MMU::alloc_pages`]: super::MMU::alloc_pages
+//! [`MMU::free_pages`]: super::MMU::free_pages
+
+use super::frames::{Frame, FrameAlloc, FrameIter, PAGE_SIZE};
+use crate::PhysicalAddress;
+
+/// Largest order of a block, 4 MiB.
+pub const MAX_ORDER: usize = 10;
+/// Maximal amount of free blocks kept per order.
+pub const FREE_BLOCKS_CAPACITY: usize = 32;
+
+/// Physically contiguous block of 2^order frames, aligned to it's size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysRegion {
+    start: PhysicalAddress,
+    order: usize,
+}
+
+impl PhysRegion {
+    /// Describes the block at the address, which must be aligned to it's size.
+    pub(crate) fn new(start: PhysicalAddress, order: usize) -> Self {
+        Self { start, order }
+    }
+
+    /// Physical address of the first byte.
+    pub fn start(&self) -> PhysicalAddress {
+        self.start
+    }
+
+    /// Physical address right after the last byte.
+    pub fn end(&self) -> PhysicalAddress {
+        self.start + self.size()
+    }
+
+    /// Size of the block in bytes.
+    pub fn size(&self) -> usize {
+        PAGE_SIZE << self.order
+    }
+
+    /// Amount of frames in the block as a power of two.
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    /// Returns an iterator over the frames of the block.
+    pub fn frames(&self) -> FrameIter {
+        let first = Frame::info_address(self.start);
+        let last = Frame { num: first.num + (1 << self.order) - 1 };
+        Frame::range_inclusive(first, last)
+    }
+}
+
+/// Returns the smallest order of a block with at least the provided size.
+pub const fn order_for(size: usize) -> usize {
+    size.div_ceil(PAGE_SIZE).next_power_of_two().trailing_zeros() as usize
+}
+
+/// Lists of the first frame numbers of free blocks, one per order.
+#[derive(Debug)]
+pub struct PageBlocks {
+    free: [[usize; FREE_BLOCKS_CAPACITY]; MAX_ORDER + 1],
+    len: [usize; MAX_ORDER + 1],
+}
+
+impl PageBlocks {
+    pub const fn new() -> Self {
+        Self { free: [[0; FREE_BLOCKS_CAPACITY]; MAX_ORDER + 1], len: [0; MAX_ORDER + 1] }
+    }
+
+    /// Takes a free block of the order, splitting a larger one if needed.
+    ///
+    /// Halves which do not fit into their list go to the allocator.
+    pub fn take(&mut self, order: usize, allocator: &mut impl FrameAlloc) -> Option<PhysRegion> {
+        let mut found = (order..=MAX_ORDER).find(|&found| self.len[found] != 0)?;
+        self.len[found] -= 1;
+        let num = self.free[found][self.len[found]];
+
+        while found > order {
+            found -= 1;
+            let upper = num + (1 << found);
+            if !self.push(upper, found) {
+                release(upper, found, allocator);
+            }
+        }
+        Some(PhysRegion { start: num * PAGE_SIZE, order })
+    }
+
+    /// Adds the block, merging it with it's free buddies.
+    pub fn insert(&mut self, region: PhysRegion, allocator: &mut impl FrameAlloc) {
+        let (mut num, mut order) = (region.start / PAGE_SIZE, region.order);
+        while order < MAX_ORDER {
+            let buddy = num ^ (1 << order);
+            let Some(index) = self.free[order][..self.len[order]].iter().position(|&free| free == buddy) else { break };
+            self.len[order] -= 1;
+            self.free[order][index] = self.free[order][self.len[order]];
+            num &= !(1 << order);
+            order += 1;
+        }
+
+        if !self.push(num, order) {
+            release(num, order, allocator);
+        }
+    }
+
+    /// Returns the amount of frames in free blocks.
+    pub fn free_frames(&self) -> usize {
+        self.len.iter().enumerate().map(|(order, len)| len << order).sum()
+    }
+
+    fn push(&mut self, num: usize, order: usize) -> bool {
+        let Some(slot) = self.free[order].get_mut(self.len[order]) else { return false };
+        *slot = num;
+        self.len[order] += 1;
+        true
+    }
+}
+
+/// Returns every frame of the block to the allocator.
+fn release(num: usize, order: usize, allocator: &mut impl FrameAlloc) {
+    allocator.dealloc_range(Frame { num }, 1 << order);
+}
+
+#[test_case]
+fn blocks_split_and_merge_with_buddies() {
+    struct Counter(usize);
+    impl FrameAlloc for Counter {
+        fn alloc(&mut self) -> Option<Frame> { None }
+        fn dealloc(&mut self, _: Frame) { self.0 += 1 }
+    }
+
+    assert_eq!((order_for(1), order_for(PAGE_SIZE + 1), order_for(5 * PAGE_SIZE)), (0, 1, 3));
+
+    let (mut blocks, mut allocator) = (PageBlocks::new(), Counter(0));
+    assert!(blocks.take(0, &mut allocator).is_none());
+    blocks.insert(PhysRegion { start: 8 * PAGE_SIZE, order: 3 }, &mut allocator);
+
+    let first = blocks.take(0, &mut allocator).unwrap();
+    let second = blocks.take(1, &mut allocator).unwrap();
+    assert_eq!((first.start(), second.start()), (8 * PAGE_SIZE, 10 * PAGE_SIZE));
+    assert_eq!(second.frames().map(|frame| frame.num).collect::<alloc::vec::Vec<_>>(), [10, 11]);
+    assert_eq!(blocks.free_frames(), 5);
+
+    blocks.insert(second, &mut allocator);
+    blocks.insert(first, &mut allocator);
+    assert_eq!((blocks.len[3], blocks.free_frames(), allocator.0), (1, 8, 0));
+
+    // Blocks past the capacity of their list are released to the frame allocator.
+    for num in 0..=FREE_BLOCKS_CAPACITY {
+        blocks.insert(PhysRegion { start: (1024 + num * 2) * PAGE_SIZE, order: 0 }, &mut allocator);
+    }
+    assert_eq!((blocks.len[0], allocator.0), (FREE_BLOCKS_CAPACITY, 1));
+}
@@ -5,6 +5,7 @@
 use super::{
     frames::{Frame, FrameAlloc, PAGE_SIZE},
     sections::{ElfSection, ElfSectionFlags},
+    layout::{self, RECURSIVE_INDEX},
 };
 use crate::{
     PhysicalAddress, VirtualAddress,
@@ -19,8 +20,10 @@ pub(crate) const ENTRY_COUNT: usize = 512;
 /// A mask that masks bits 12-51.
 pub const BIT_MASK: usize = 0x000fffff_fffff000;
 
-/// The address of P4 table in the kernel.
-pub const P4: *mut Table<Level4> = 0o177777_777_777_777_777_0000 as *mut _;
+/// The address of P4 table in the kernel, reached through the recursive entry on every level.
+pub const P4: *mut Table<Level4> = (
+    layout::RECURSIVE_START | RECURSIVE_INDEX << 30 | RECURSIVE_INDEX << 21 | RECURSIVE_INDEX << 12
+) as *mut _;
 
 /// Table levels. The use of type system to guarantee that the next_table methods
 /// can only be called on P4, P3 and P2 tables.
@@ -207,7 +210,8 @@ impl<L: HierarchicalLevel> Table<L> {
         let entry_flags = self[index].flags();
         if PRESENT.is_in(entry_flags) && !HUGE_PAGE.is_in(entry_flags) {
             let table_address = self as *const _ as usize;
-            Some((table_address << 9) | (index << 12))
+            // The shift drops the sign extension, unless the recursive entry is the last one.
+            Some(layout::sign_extend((table_address << 9) | (index << 12)))
         } else {
             None
         }
@@ -259,6 +263,13 @@ bitflags! {
 }
 
 impl EntryFlags {
+    /// Selects the write combining memory type of a 4 KiB page, once the PAT is programmed with
+    /// [`Pat::enable_write_combining`]. Bit 7 is the PAT bit in P1 entries, while it marks huge
+    /// pages in the upper levels.
+    ///
+    /// [`Pat::enable_write_combining`]: crate::kernel_components::registers::ms::Pat::enable_write_combining
+    pub const WRITE_COMBINING: EntryFlags = EntryFlags::HUGE_PAGE;
+
     pub fn from_elf_section_flags(section: &ElfSection) -> Self {
         let mut return_flags = Self::empty();
         let section_flags = section.flags().into();
@@ -0,0 +1,185 @@
+//! Memory pressure notifications.
+//!
+//! Subsystems that keep memory only to be faster (caches, buffer pools) register shrinkers,
+//! which release some of that memory when asked. The pressure level is computed from the heap
+//! usage of the global allocator against the thresholds tunable with the "mm.pressure_*" sysctl
+//! entries.
+//!
+//! Shrinkers are invoked by [`check`] and by the global allocator right before an allocation
+//! would fail, so caches are dropped before the kernel actually runs out of memory. Pages of the
+//! process mappings are only evicted by [`check`], which runs periodically on the
+//! [`monitor_thread`], as writing them to swap must not happen within an allocation.
+
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+use crate::kernel_components::task_virtualization::{Thread, PROCESS_MANAGEMENT_UNIT};
+use crate::{single, critical_section, GLOBAL_ALLOCATOR};
+
+use super::frames::PAGE_SIZE;
+
+/// Interval between two checks of the monitor thread in milliseconds.
+pub const CHECK_INTERVAL_MS: u32 = 500;
+/// Default heap usage in percent, starting from which the pressure is low.
+pub const DEFAULT_LOW_PERCENT: u8 = 70;
+/// Default heap usage in percent, starting from which the pressure is medium.
+pub const DEFAULT_MEDIUM_PERCENT: u8 = 85;
+/// Default heap usage in percent, starting from which the pressure is critical.
+pub const DEFAULT_CRITICAL_PERCENT: u8 = 95;
+
+/// Thresholds of each pressure level in percent of the heap arena.
+pub static LOW_PERCENT: AtomicU8 = AtomicU8::new(DEFAULT_LOW_PERCENT);
+pub static MEDIUM_PERCENT: AtomicU8 = AtomicU8::new(DEFAULT_MEDIUM_PERCENT);
+pub static CRITICAL_PERCENT: AtomicU8 = AtomicU8::new(DEFAULT_CRITICAL_PERCENT);
+
+/// Marks that the shrinkers are running, so allocation failures inside of them do not recurse.
+static SHRINKING: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Registered shrinkers.
+single! {
+    pub mut SHRINKERS: Vec<Shrinker> = Vec::new();
+}
+
+/// Severity of the memory shortage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PressureLevel {
+    /// Caches may release memory which was not used for a while.
+    Low,
+    /// Caches should release most of their memory.
+    Medium,
+    /// Allocations are failing. Everything that can be released must be released.
+    Critical,
+}
+
+/// Callback which releases memory according to the pressure level.
+///
+/// Returns the amount of released bytes.
+pub type ShrinkerFn = fn(PressureLevel) -> usize;
+
+/// Handle of the registered shrinker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShrinkerId(usize);
+
+/// Registered shrinker callback.
+#[derive(Debug, Clone, Copy)]
+pub struct Shrinker {
+    id: usize,
+    name: &'static str,
+    callback: ShrinkerFn,
+}
+
+/// Registers the shrinker under the name.
+pub fn register_shrinker(name: &'static str, callback: ShrinkerFn) -> ShrinkerId {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    critical_section!(|| unsafe { SHRINKERS.push(Shrinker { id, name, callback }) });
+    ShrinkerId(id)
+}
+
+/// Removes the shrinker. Returns false if it was not registered.
+pub fn unregister_shrinker(id: ShrinkerId) -> bool {
+    critical_section!(|| unsafe {
+        let len = SHRINKERS.len();
+        SHRINKERS.retain(|s| s.id != id.0);
+        SHRINKERS.len() != len
+    })
+}
+
+/// Returns the names of the registered shrinkers.
+pub fn shrinkers() -> Vec<&'static str> {
+    critical_section!(|| unsafe { SHRINKERS.iter().map(|s| s.name).collect() })
+}
+
+/// Computes the pressure level of the arena with the provided amount of used bytes.
+///
+/// Returns None if the usage is below every threshold.
+pub fn level_for(used: usize, total: usize) -> Option<PressureLevel> {
+    let percent = (used as u128 * 100 / total.max(1) as u128) as usize;
+
+    [
+        (PressureLevel::Critical, &CRITICAL_PERCENT),
+        (PressureLevel::Medium, &MEDIUM_PERCENT),
+        (PressureLevel::Low, &LOW_PERCENT),
+    ].into_iter()
+        .find(|(_, threshold)| percent >= threshold.load(Ordering::Relaxed) as usize)
+        .map(|(level, _)| level)
+}
+
+/// Returns the current pressure level of the kernel heap.
+pub fn current_level() -> Option<PressureLevel> {
+    unsafe { level_for(GLOBAL_ALLOCATOR.used(), GLOBAL_ALLOCATOR.arena_size) }
+}
+
+/// Invokes every registered shrinker with the level.
+///
+/// Returns the total amount of released bytes. Nested calls, made when a shrinker itself runs
+/// out of memory, release nothing.
+pub fn shrink(level: PressureLevel) -> usize {
+    if SHRINKING.swap(true, Ordering::Acquire) {
+        return 0
+    }
+
+    let mut released = 0;
+    let mut index = 0;
+    // Shrinkers may allocate, so the list is not borrowed while one of them runs.
+    while let Some(shrinker) = critical_section!(|| unsafe { SHRINKERS.get(index).copied() }) {
+        released += (shrinker.callback)(level);
+        index += 1;
+    }
+
+    SHRINKING.store(false, Ordering::Release);
+    released
+}
+
+/// Invokes the shrinkers and evicts pages of the process mappings if the heap is under pressure.
+///
+/// Must be called periodically. Returns the current pressure level.
+pub fn check() -> Option<PressureLevel> {
+    let level = current_level()?;
+    shrink(level);
+    shrink_page_cache(level);
+    Some(level)
+}
+
+/// Monitor thread, which checks the pressure periodically.
+pub fn monitor_thread(_: &mut Thread) {
+    loop {
+        check();
+        Thread::sleep(CHECK_INTERVAL_MS);
+    }
+}
+
+/// Evicts pages of the process mappings. More pages are evicted at higher levels.
+fn shrink_page_cache(level: PressureLevel) -> usize {
+    if unsafe { PROCESS_MANAGEMENT_UNIT.process_list.is_locked() } {
+        return 0
+    }
+
+    let target = match level {
+        PressureLevel::Low => 16,
+        PressureLevel::Medium => 64,
+        PressureLevel::Critical => 256,
+    };
+    super::swap::reclaim(target) * PAGE_SIZE
+}
+
+#[test_case]
+fn pressure_levels_and_shrinkers() {
+    use PressureLevel::*;
+
+    assert_eq!(level_for(10, 100), None);
+    assert_eq!(level_for(70, 100), Some(Low));
+    assert_eq!(level_for(90, 100), Some(Medium));
+    assert_eq!(level_for(100, 100), Some(Critical));
+
+    fn fake(level: PressureLevel) -> usize {
+        if level == Critical { 4096 } else { 0 }
+    }
+
+    let id = register_shrinker("test", fake);
+    assert!(shrinkers().contains(&"test"));
+    assert!(shrink(Critical) >= 4096);
+    assert!(unregister_shrinker(id));
+    assert!(!unregister_shrinker(id));
+}
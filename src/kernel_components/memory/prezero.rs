@@ -0,0 +1,187 @@
+//! Pool of frames zeroed ahead of time.
+//!
+//! Pages populated on demand must be cleared before the faulting code sees them. Instead of
+//! clearing a whole frame on the hot path, [`zeroing_thread`] keeps a small pool of frames which
+//! are zeroed already. It runs with the lowest priority, so frames are cleared while the processor
+//! would idle otherwise. [`MMU::allocate_zeroed_frame`] takes a frame from the pool and only falls
+//! back to a regular frame, which the caller clears itself, when the pool is empty or busy.
+//!
+//! Pooled frames are free memory nobody touches for a long time. With the "mm.scrub" tunable, the
+//! thread reads them back after every refill. On machines with ECC memory the reads let the memory
+//! controller correct single bit errors before they pile up, while any nonzero word found is an
+//! error the ECC did not catch. It is reported and the frame is cleared again.
+//!
+//! [`MMU::allocate_zeroed_frame`]: super::MMU::allocate_zeroed_frame
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use crate::kernel_components::sync::IrqSpinlock;
+use crate::kernel_components::task_virtualization::Thread;
+use crate::{critical_section, log};
+
+use super::frames::{Frame, PAGE_SIZE};
+use super::layout::ZEROING_PAGE;
+use super::memory_module::{MemError, MMU, MEMORY_MANAGEMENT_UNIT};
+use super::{EntryFlags, Page};
+
+/// Maximal amount of pooled frames.
+pub const POOL_CAPACITY: usize = 64;
+/// Amount of frames kept in the pool until changed with "mm.prezero_frames".
+pub const DEFAULT_POOL_TARGET: usize = 32;
+/// Interval between two refills of the pool in milliseconds.
+pub const REFILL_INTERVAL_MS: u32 = 200;
+
+/// Amount of frames the thread keeps in the pool. Zero returns every pooled frame.
+pub static POOL_TARGET: AtomicUsize = AtomicUsize::new(DEFAULT_POOL_TARGET);
+/// Enables reading back the pooled frames. Tunable with "mm.scrub".
+pub static SCRUB_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Frames found with nonzero content by the scrubbing.
+static SCRUB_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Pooled frames, shared with the page fault handler, which only ever tries to take the lock.
+static POOL: IrqSpinlock<FramePool> = IrqSpinlock::new(FramePool::new());
+
+/// Stack of the numbers of zeroed frames.
+struct FramePool {
+    frames: [usize; POOL_CAPACITY],
+    len: usize,
+}
+
+impl FramePool {
+    const fn new() -> Self {
+        Self { frames: [0; POOL_CAPACITY], len: 0 }
+    }
+
+    /// Adds the frame, returning it back if the pool is full.
+    fn push(&mut self, frame: Frame) -> Result<(), Frame> {
+        match self.frames.get_mut(self.len) {
+            Some(slot) => {
+                *slot = frame.num;
+                self.len += 1;
+                Ok(())
+            },
+            None => Err(frame),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Frame> {
+        self.len = self.len.checked_sub(1)?;
+        Some(Frame { num: self.frames[self.len] })
+    }
+}
+
+/// Takes a zeroed frame from the pool.
+///
+/// Never waits, so it is safe to call from the page fault handler. Returns None if the pool is
+/// empty or used by the zeroing thread right now.
+pub fn take() -> Option<Frame> {
+    POOL.try_lock()?.pop()
+}
+
+/// Returns the amount of pooled frames.
+pub fn pooled() -> usize {
+    POOL.lock().len
+}
+
+/// Returns the amount of pooled frames, which were found with nonzero content.
+pub fn scrub_errors() -> u64 {
+    SCRUB_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Maps the frame at the zeroing page for the duration of the function.
+fn with_mapped<T>(mmu: &mut MMU, frame: &Frame, f: impl FnOnce(&mut [u64]) -> T) -> Result<T, MemError> {
+    let page = Page::containing_address(ZEROING_PAGE);
+    mmu.map_to(page, frame.clone(), EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE)?;
+    let output = f(unsafe { core::slice::from_raw_parts_mut(ZEROING_PAGE as *mut u64, PAGE_SIZE / 8) });
+    mmu.unmap(page)?;
+    Ok(output)
+}
+
+/// Zeroes a new frame and adds it to the pool. Returns false if no frame was added.
+fn refill_one() -> bool {
+    critical_section!(|| {
+        let mmu = &mut *MEMORY_MANAGEMENT_UNIT;
+        let Some(frame) = mmu.allocate_frame() else { return false };
+        if with_mapped(mmu, &frame, |words| words.fill(0)).is_err() {
+            mmu.deallocate_frame(frame);
+            return false
+        }
+        match POOL.lock().push(frame) {
+            Ok(()) => true,
+            Err(frame) => {
+                mmu.deallocate_frame(frame);
+                false
+            },
+        }
+    })
+}
+
+/// Returns pooled frames above the target to the frame allocator.
+fn shrink_to(target: usize) {
+    critical_section!(|| {
+        let mut pool = POOL.lock();
+        while pool.len > target {
+            let frame = pool.pop().unwrap();
+            MEMORY_MANAGEMENT_UNIT.deallocate_frame(frame);
+        }
+    })
+}
+
+/// Reads every pooled frame back, clearing the ones which are not zero anymore.
+///
+/// The pool stays locked while a frame is checked, so the frame is never handed out meanwhile.
+/// Returns the amount of corrupted frames found.
+pub fn scrub() -> usize {
+    let mut corrupted = 0;
+    for index in 0..POOL_CAPACITY {
+        let checked = critical_section!(|| {
+            let pool = POOL.lock();
+            let frame = Frame { num: *pool.frames[..pool.len].get(index)? };
+            with_mapped(&mut *MEMORY_MANAGEMENT_UNIT, &frame, |words| {
+                let dirty = words.iter().filter(|&&word| word != 0).count();
+                if dirty != 0 {
+                    words.fill(0);
+                }
+                (frame.start_address(), dirty)
+            }).ok()
+        });
+
+        match checked {
+            Some((_, 0)) => (),
+            Some((addr, dirty)) => {
+                corrupted += 1;
+                SCRUB_ERRORS.fetch_add(1, Ordering::Relaxed);
+                log!(Error; "Scrubbing: {} corrupted words in the free frame at {:#x}, cleared.", dirty, addr);
+            },
+            None => break,
+        }
+    }
+    corrupted
+}
+
+/// Background thread, which keeps the pool filled and scrubs it when enabled.
+pub fn zeroing_thread(_: &mut Thread) {
+    loop {
+        let target = POOL_TARGET.load(Ordering::Relaxed).min(POOL_CAPACITY);
+        while pooled() < target && refill_one() {}
+        shrink_to(target);
+
+        if SCRUB_ENABLED.load(Ordering::Relaxed) {
+            scrub();
+        }
+        Thread::sleep(REFILL_INTERVAL_MS);
+    }
+}
+
+#[test_case]
+fn pool_is_a_bounded_stack() {
+    let mut pool = FramePool::new();
+    assert!(pool.pop().is_none());
+    for num in 0..POOL_CAPACITY {
+        assert!(pool.push(Frame { num }).is_ok());
+    }
+    assert_eq!(pool.push(Frame { num: POOL_CAPACITY }), Err(Frame { num: POOL_CAPACITY }));
+    assert_eq!(pool.pop(), Some(Frame { num: POOL_CAPACITY - 1 }));
+    assert_eq!(pool.len, POOL_CAPACITY - 1);
+}
@@ -71,9 +71,8 @@ impl SectionsTag {
 impl TagTrait for SectionsTag {
     const ID: TagType = TagType::ElfSections;
 
-    fn dst_size(tag: &Tag) -> Self::Metadata {
-        assert!(tag.size as usize >= METADATA_SIZE);
-        tag.size as usize - METADATA_SIZE
+    fn dst_size(tag: &Tag) -> Option<Self::Metadata> {
+        (tag.size as usize).checked_sub(METADATA_SIZE)
     }
 }
 
@@ -350,6 +349,44 @@ pub struct ElfSectionInner64 {
     entry_size: u64,
 }
 
+impl ElfSectionInner64 {
+    /// Creates a section header for boot paths, which describe the kernel image without an ELF
+    /// file at hand. Offset, link, info and entry size are zeroed.
+    pub const fn new(
+        name_index: u32,
+        typ: ElfSectionType,
+        flags: ElfSectionFlags,
+        addr: u64,
+        size: u64,
+    ) -> Self {
+        Self {
+            name_index,
+            typ: typ as u32,
+            flags: flags.bits(),
+            addr,
+            offset: 0,
+            size,
+            link: 0,
+            info: 0,
+            addralign: 0,
+            entry_size: 0,
+        }
+    }
+
+    /// Changes the address of the section, e.g. when a boot path moves the string table.
+    pub fn set_addr(&mut self, addr: u64) {
+        self.addr = addr;
+    }
+
+    /// Returns the offset of the section within the ELF file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+/// Implements byte representation of a 64-bit section header.
+impl AsBytes for ElfSectionInner64 {}
+
 impl ElfSectionInner for ElfSectionInner32 {
     fn name_index(&self) -> u32 {
         self.name_index
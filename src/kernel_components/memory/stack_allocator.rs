@@ -4,11 +4,13 @@
 /// and interrupt stack table. Personal tables for software use can be also
 /// allocated via this module.
  
+use alloc::vec::Vec;
+
 use super::frames::{PAGE_SIZE, FrameAlloc};
 use super::paging::{self, Page, PageIter};
 use super::owned_tables::ActivePageTable;
 use super::EntryFlags::WRITABLE;
-use crate::{println, Color};
+use crate::trace;
 
 /// An allocators struct.
 /// 
@@ -18,21 +20,24 @@ use crate::{println, Color};
 #[derive(Debug)]
 pub struct StackAlloc {
     next_page: Page,
+    last_page: Page,
+    /// Page ranges of released stacks, each with an unmapped guard page below it.
+    freed: Vec<(Page, Page)>,
 }
 
 impl StackAlloc {
     /// Creates a new stack allocator.
     /// 
-    /// The range must be an iterator over the pages, that you wish to allocate
-    /// for a custom stack. The address must be unused unmapped memory location.
-    pub fn new(page: Page) -> Self {
-        Self { next_page: page }
+    /// Stacks are allocated from the first page up to the last page inclusive. The
+    /// range must be unused unmapped memory location.
+    pub fn new(page: Page, last_page: Page) -> Self {
+        Self { next_page: page, last_page, freed: Vec::new() }
     }
 
     /// Allocates the stack and returns it.
     /// 
-    /// If it will be unable to allocate guard page, starting page or the end page,
-    /// it will return None.
+    /// Released stacks are reused first. If it will be unable to allocate guard page, starting
+    /// page or the end page, it will return None.
     pub fn alloc_stack<A>(
         &mut self,
         active_table: &mut ActivePageTable,
@@ -42,6 +47,17 @@ impl StackAlloc {
         if size == 0 {
             return None
         }
+        if let Some(index) = self.freed.iter().position(|&(start, end)| start + (size - 1) <= end) {
+            let (start, end) = self.freed.swap_remove(index);
+            // The page above the stack becomes the guard page of the rest of the range.
+            if start + (size + 1) <= end {
+                self.freed.push((start + (size + 1), end));
+            }
+            return Some(Self::map_stack(active_table, frame_allocator, start, start + (size - 1)))
+        }
+        if self.next_page + size > self.last_page {
+            return None
+        }
         
         let mut range = {
             let start = self.next_page;
@@ -60,19 +76,9 @@ impl StackAlloc {
 
             match (stack_start, stack_end) {
                 (Some(start), Some(end)) => {
-                    self.next_page = end;
-
-                    for page in Page::range_inclusive(start, end) {
-                        active_table.map(page, WRITABLE, frame_allocator);
-
-                        #[cfg(debug_assertions)]
-                        println!(Color::LIGHTGRAY; "Mapping stack page at address {:#x}", page.start_address());
-                    }
-
-                    Some(Stack::new(
-                        end.start_address() + PAGE_SIZE,
-                        start.start_address(),
-                    ))
+                    // The page after the stack is the guard page of the next one.
+                    self.next_page = end + 1;
+                    Some(Self::map_stack(active_table, frame_allocator, start, end))
                 }
                 _ => None,
             }
@@ -80,6 +86,40 @@ impl StackAlloc {
             None
         }
     }
+
+    /// Keeps the pages of the unmapped stack for reuse by the following allocations.
+    ///
+    /// Ranges separated only by a guard page are merged, the guard page joins the merged range.
+    pub fn release(&mut self, stack: Stack) {
+        let mut start = Page::containing_address(stack.bottom);
+        let mut end = Page::containing_address(stack.top - 1);
+
+        self.freed.retain(|&(first, last)| {
+            if last + 2 == start {
+                start = first;
+                false
+            } else if end + 2 == first {
+                end = last;
+                false
+            } else {
+                true
+            }
+        });
+        self.freed.push((start, end));
+    }
+
+    /// Maps the pages of the stack from the start to the end inclusive.
+    fn map_stack<A>(active_table: &mut ActivePageTable, frame_allocator: &mut A, start: Page, end: Page) -> Stack where
+        A: FrameAlloc
+    {
+        for page in Page::range_inclusive(start, end) {
+            active_table.map(page, WRITABLE, frame_allocator);
+
+            trace!(STACKS; "Mapping stack page at address {:#x}", page.start_address());
+        }
+
+        Stack::new(end.start_address() + PAGE_SIZE, start.start_address())
+    }
 }
 
 /// A struct representing the stack.
@@ -0,0 +1,236 @@
+//! Swap space.
+//!
+//! A block device activated with [`swapon`] is split into page sized slots, tracked with a
+//! bitmap. Anonymous pages evicted by [`reclaim`] are written into free slots and read back by the
+//! page fault handler, after which the slot is freed again.
+//!
+//! Reclaim is a second chance (clock) approximation of LRU based on the accessed bit of the
+//! page table entries: pages touched since the previous scan only lose their accessed bit, while
+//! untouched ones are evicted. Clean file pages are evicted first, since they cost nothing to
+//! drop, and only then dirty file pages and anonymous pages, which must be written out.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::fmt::Display;
+use core::error::Error;
+
+use crate::kernel_components::drivers::storage::{BlockDevice, BlockError, SECTOR_SIZE};
+use crate::kernel_components::task_virtualization::PROCESS_MANAGEMENT_UNIT;
+use crate::single;
+
+use super::frames::PAGE_SIZE;
+use super::memory_module::MEMORY_MANAGEMENT_UNIT;
+
+/// Amount of device sectors in one swap slot.
+pub const SLOT_SECTORS: u32 = (PAGE_SIZE / SECTOR_SIZE) as u32;
+
+/// Swap space activated with swapon.
+single! {
+    pub mut SWAP: Option<SwapSpace> = None;
+}
+
+/// Errors of the swap subsystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapError {
+    /// No swap space is active.
+    NoSwap,
+    /// The swap space is active already.
+    AlreadyActive,
+    /// Every slot is used.
+    Full,
+    /// Some pages are still swapped out.
+    InUse(usize),
+    /// The device is read-only or too small to hold a single slot.
+    InvalidDevice,
+    /// The swap device failed.
+    Io(BlockError),
+}
+
+impl From<BlockError> for SwapError {
+    fn from(value: BlockError) -> Self {
+        SwapError::Io(value)
+    }
+}
+
+impl Display for SwapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SwapError::NoSwap => write!(f, "No swap space is active."),
+            SwapError::AlreadyActive => write!(f, "Swap space is active already."),
+            SwapError::Full => write!(f, "Swap space is full."),
+            SwapError::InUse(used) => write!(f, "{} page(s) are still swapped out.", used),
+            SwapError::InvalidDevice => write!(f, "Device cannot be used as a swap space."),
+            SwapError::Io(err) => write!(f, "Swap device error: {:?}.", err),
+        }
+    }
+}
+
+impl Error for SwapError {}
+
+/// Location of a swapped out page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SwapSlot(u32);
+
+impl SwapSlot {
+    /// Returns the first sector of the slot on the swap device.
+    #[inline]
+    pub fn lba(&self) -> u64 {
+        self.0 as u64 * SLOT_SECTORS as u64
+    }
+}
+
+/// A block device divided into page sized slots.
+pub struct SwapSpace {
+    device: Box<dyn BlockDevice>,
+    bitmap: Vec<u64>,
+    slots: usize,
+    used: usize,
+}
+
+impl SwapSpace {
+    /// Uses the whole device as a swap space. The previous content of the device is ignored.
+    pub fn new(device: Box<dyn BlockDevice>) -> Result<Self, SwapError> {
+        let slots = (device.sector_count() / SLOT_SECTORS as u64).min(u32::MAX as u64) as usize;
+        if device.is_read_only() || slots == 0 {
+            return Err(SwapError::InvalidDevice)
+        }
+
+        Ok(Self {
+            device,
+            bitmap: vec![0; slots.div_ceil(64)],
+            slots,
+            used: 0,
+        })
+    }
+
+    /// Returns the total amount of slots.
+    #[inline]
+    pub fn slots(&self) -> usize {
+        self.slots
+    }
+
+    /// Returns the amount of used slots.
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Marks the first free slot as used.
+    pub fn alloc(&mut self) -> Option<SwapSlot> {
+        let (word, bits) = self.bitmap.iter_mut().enumerate().find(|(_, bits)| **bits != u64::MAX)?;
+        let slot = word * 64 + bits.trailing_ones() as usize;
+        if slot >= self.slots {
+            return None
+        }
+
+        *bits |= 1 << (slot % 64);
+        self.used += 1;
+        Some(SwapSlot(slot as u32))
+    }
+
+    /// Marks the slot as free.
+    pub fn free(&mut self, slot: SwapSlot) {
+        let (word, bit) = (slot.0 as usize / 64, slot.0 % 64);
+        if self.bitmap[word] & (1 << bit) != 0 {
+            self.bitmap[word] &= !(1 << bit);
+            self.used -= 1;
+        }
+    }
+
+    /// Writes the page into a free slot.
+    pub fn write_page(&mut self, data: &[u8]) -> Result<SwapSlot, SwapError> {
+        let slot = self.alloc().ok_or(SwapError::Full)?;
+        if let Err(err) = self.device.write_blocking(slot.lba(), data.to_vec()) {
+            self.free(slot);
+            return Err(err.into())
+        }
+        Ok(slot)
+    }
+
+    /// Reads the page from the slot and frees the slot.
+    pub fn read_page(&mut self, slot: SwapSlot, buffer: &mut [u8]) -> Result<(), SwapError> {
+        let data = self.device.read_blocking(slot.lba(), SLOT_SECTORS)?;
+        buffer.copy_from_slice(&data[..buffer.len()]);
+        self.free(slot);
+        Ok(())
+    }
+}
+
+/// Activates the swap space on the device.
+pub fn swapon(device: Box<dyn BlockDevice>) -> Result<(), SwapError> {
+    let swap = unsafe { &mut *SWAP };
+    if swap.is_some() {
+        return Err(SwapError::AlreadyActive)
+    }
+    *swap = Some(SwapSpace::new(device)?);
+    Ok(())
+}
+
+/// Deactivates the swap space and returns the device.
+///
+/// Fails if some pages are still swapped out.
+pub fn swapoff() -> Result<Box<dyn BlockDevice>, SwapError> {
+    let swap = unsafe { &mut *SWAP };
+    match swap.as_ref().map(SwapSpace::used) {
+        None => Err(SwapError::NoSwap),
+        Some(0) => Ok(swap.take().unwrap().device),
+        Some(used) => Err(SwapError::InUse(used)),
+    }
+}
+
+/// Evicts up to target pages from the address spaces of all processes.
+///
+/// Returns the amount of freed frames.
+pub fn reclaim(target: usize) -> usize {
+    unsafe {
+        let mut list = PROCESS_MANAGEMENT_UNIT.process_list.lock();
+        let mmu = &mut *MEMORY_MANAGEMENT_UNIT;
+        let mut swap = SWAP.as_mut();
+        let mut freed = 0;
+
+        // Two rounds, so pages which only got their second chance in the first one may go too.
+        for _ in 0..2 {
+            for pass in [ReclaimPass::CleanFile, ReclaimPass::Writeback] {
+                list.for_each_mut(|process| if freed < target {
+                    freed += process.address_space.reclaim(mmu, swap.as_deref_mut(), pass, target - freed);
+                });
+            }
+        }
+        freed
+    }
+}
+
+/// Kind of pages evicted within one reclaim pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReclaimPass {
+    /// Only clean file pages, which are dropped without any I/O.
+    CleanFile,
+    /// Dirty file pages are written back and anonymous pages are written to swap.
+    Writeback,
+}
+
+#[test_case]
+fn swap_slots_round_trip() {
+    use crate::kernel_components::drivers::storage::RamDisk;
+
+    let mut swap = SwapSpace::new(Box::new(RamDisk::new(SLOT_SECTORS as usize * 3))).unwrap();
+    assert_eq!(swap.slots(), 3);
+
+    let page = [0x5au8; PAGE_SIZE];
+    let slot = swap.write_page(&page).unwrap();
+    let other = swap.alloc().unwrap();
+    assert_ne!(slot, other);
+    assert_eq!(swap.used(), 2);
+
+    let mut buffer = [0u8; PAGE_SIZE];
+    swap.read_page(slot, &mut buffer).unwrap();
+    assert_eq!(buffer, page);
+    assert_eq!(swap.used(), 1);
+
+    swap.alloc().unwrap();
+    swap.alloc().unwrap();
+    assert_eq!(swap.alloc(), None);
+    assert_eq!(swap.write_page(&page).unwrap_err(), SwapError::Full);
+}
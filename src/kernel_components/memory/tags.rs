@@ -9,6 +9,8 @@ use core::marker::PhantomData;
 use core::str::Utf8Error;
 use proc_macros::Iternum;
 
+use crate::kernel_components::boot::info::Framebuffer;
+
 // Tag type is a wrapper around u32 for convenient use of binary representation.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
@@ -28,7 +30,7 @@ impl Debug for TagTypeId {
 }
 
 // Tag type is a enum, representing the unique identifier for each tag type. 
-#[derive(Iternum, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Iternum, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TagType {
     End, Cmd, Name, Module, MemInfo, BootDev, Mmap,
     Vbe, FrameBuf, ElfSections, Apm, Efi32, Efi64,
@@ -48,8 +50,9 @@ impl TagType {
 pub trait TagTrait: Pointee {
     // Id of the specific tag
     const ID: TagType;
-    // Returns empty for sized tags. Returns usize for DSTs. This must be implemented for a custom DST
-    fn dst_size(tag: &Tag) -> Self::Metadata;
+    // Returns empty for sized tags. Returns usize for DSTs. This must be implemented for a custom DST.
+    // Returns None if the tag is too short for it's fixed fields, such tags are skipped.
+    fn dst_size(tag: &Tag) -> Option<Self::Metadata>;
     // Returns the size of the tag.
     fn size(&self) -> usize {
         self.base().size as usize
@@ -64,11 +67,11 @@ pub trait TagTrait: Pointee {
         let ptr = addr_of!(*self);
         unsafe { &*ptr.cast::<Tag>() }
     }
-    // Returns a ref to a dynamically sized tag.
-    unsafe fn from_base_tag<'a>(tag: &Tag) -> &'a Self {
+    // Returns a ref to a dynamically sized tag, or None if the tag is malformed.
+    unsafe fn from_base_tag<'a>(tag: &Tag) -> Option<&'a Self> {
         let ptr = core::ptr::addr_of!(*tag);
-        let ptr = core::ptr::from_raw_parts(ptr, Self::dst_size(tag));
-        &*ptr
+        let ptr = core::ptr::from_raw_parts(ptr, Self::dst_size(tag)?);
+        Some(&*ptr)
     }}
 
 // The main tag struct for passing it into MBI
@@ -84,7 +87,7 @@ impl Tag {
         self.tag_type.into()
     }
     // Casts the base tag to the specific tag type
-    pub fn cast_tag<'a, T: TagTrait + ?Sized + 'a>(&'a self) -> &'a T {
+    pub fn cast_tag<'a, T: TagTrait + ?Sized + 'a>(&'a self) -> Option<&'a T> {
         assert_eq!(self.get_type(), T::ID);
         unsafe { TagTrait::from_base_tag(self) }
     }
@@ -121,7 +124,100 @@ impl Default for EndTag {
 
 impl TagTrait for EndTag {
     const ID: TagType = TagType::End;
-    fn dst_size(tag: &Tag) -> Self::Metadata {}
+    fn dst_size(_: &Tag) -> Option<Self::Metadata> {
+        Some(())
+    }
+}
+
+// The command line tag contains the null-terminated string passed to the kernel by the bootloader
+#[derive(Debug)]
+#[repr(C)]
+pub struct CommandLineTag {
+    pub tag_type: TagTypeId,
+    pub size: u32,
+    cmdline: [u8],
+}
+
+impl CommandLineTag {
+    // Returns the command line as a str slice
+    pub fn cmdline(&self) -> Result<&str, Utf8Error> {
+        Tag::get_dst_str_slice(&self.cmdline)
+    }
+}
+
+impl TagTrait for CommandLineTag {
+    const ID: TagType = TagType::Cmd;
+    fn dst_size(base_tag: &Tag) -> Option<usize> {
+        (base_tag.size as usize).checked_sub(8)
+    }
+}
+
+// Pixel format of the framebuffer described by the framebuffer tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramebufferType {
+    // Colors are indices into a palette
+    Indexed,
+    // Direct RGB colors, the color info describes the channels
+    Rgb,
+    // VGA text mode, the address points to the text buffer
+    EgaText,
+    Unknown(u8),
+}
+
+// The framebuffer tag describes the video mode set up by the bootloader
+#[derive(Debug)]
+#[repr(C)]
+pub struct FramebufferTag {
+    pub tag_type: TagTypeId,
+    pub size: u32,
+    pub addr: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    fb_type: u8,
+    _reserved: u16,
+    color_info: [u8],
+}
+
+impl FramebufferTag {
+    // Returns the pixel format of the framebuffer
+    pub fn fb_type(&self) -> FramebufferType {
+        match self.fb_type {
+            0 => FramebufferType::Indexed,
+            1 => FramebufferType::Rgb,
+            2 => FramebufferType::EgaText,
+            other => FramebufferType::Unknown(other),
+        }
+    }
+
+    // Returns the description of a direct RGB framebuffer. Palettes, text modes and pixel sizes
+    // other than 16, 24 and 32 bits are not supported
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        if self.fb_type() != FramebufferType::Rgb || !matches!(self.bpp, 16 | 24 | 32) {
+            return None
+        }
+        let &[red_pos, red_size, green_pos, green_size, blue_pos, blue_size, ..] = &self.color_info else {
+            return None
+        };
+        Some(Framebuffer {
+            addr: self.addr,
+            pitch: self.pitch,
+            width: self.width,
+            height: self.height,
+            bpp: self.bpp,
+            red: (red_pos, red_size),
+            green: (green_pos, green_size),
+            blue: (blue_pos, blue_size),
+        })
+    }
+}
+
+impl TagTrait for FramebufferTag {
+    const ID: TagType = TagType::FrameBuf;
+    fn dst_size(base_tag: &Tag) -> Option<usize> {
+        (base_tag.size as usize).checked_sub(32)
+    }
 }
 
 // Conversion between types
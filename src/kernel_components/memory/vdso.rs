@@ -0,0 +1,165 @@
+//! Time data shared with user programs.
+//!
+//! The kernel keeps the TSC calibration and the latest wall clock snapshot in a page of it's own
+//! image. [`init`] maps the same frame read-only at [`VDSO_PAGE`], which is visible to every
+//! process as all of them share one page table, so the time of the day is computed from the TSC
+//! without a syscall (see [`VdsoData::now`]).
+//!
+//! The data is published with [`publish`] by the clock which calibrated the TSC. Readers never
+//! block the writer: a sequence counter is odd while the data changes and readers retry until
+//! they see the same even value before and after reading.
+
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+use super::frames::Frame;
+use super::layout::VDSO_PAGE;
+use super::memory_module::{MemError, MMU};
+use super::{Page, EntryFlags};
+
+/// Milliseconds in one day.
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Kernel side of the shared page. The page is not shared with any other kernel data.
+static VDSO: VdsoPage = VdsoPage(VdsoData::new());
+
+#[repr(C, align(4096))]
+struct VdsoPage(VdsoData);
+
+/// Consistent copy of the shared data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSnapshot {
+    /// TSC cycles per millisecond. Zero until a clock calibrates the TSC.
+    pub tsc_per_ms: u64,
+    /// TSC value at the moment of the snapshot.
+    pub base_tsc: u64,
+    /// Time of the day in milliseconds at the moment of the snapshot.
+    pub wall_ms: u32,
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+/// Layout of the shared page.
+#[repr(C)]
+#[derive(Debug)]
+pub struct VdsoData {
+    seq: AtomicU32,
+    /// Year, month and day packed as `year << 16 | month << 8 | day`.
+    date: AtomicU32,
+    tsc_per_ms: AtomicU64,
+    base_tsc: AtomicU64,
+    wall_ms: AtomicU64,
+}
+
+impl VdsoData {
+    const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            date: AtomicU32::new(0),
+            tsc_per_ms: AtomicU64::new(0),
+            base_tsc: AtomicU64::new(0),
+            wall_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Replaces the data. Only a single writer is expected.
+    fn write(&self, snapshot: &TimeSnapshot) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        let date = (snapshot.year as u32) << 16 | (snapshot.month as u32) << 8 | snapshot.day as u32;
+        self.date.store(date, Ordering::Relaxed);
+        self.tsc_per_ms.store(snapshot.tsc_per_ms, Ordering::Relaxed);
+        self.base_tsc.store(snapshot.base_tsc, Ordering::Relaxed);
+        self.wall_ms.store(snapshot.wall_ms as u64, Ordering::Relaxed);
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Reads a consistent copy of the data.
+    pub fn snapshot(&self) -> TimeSnapshot {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq % 2 == 1 {
+                core::hint::spin_loop();
+                continue
+            }
+
+            let date = self.date.load(Ordering::Relaxed);
+            let snapshot = TimeSnapshot {
+                tsc_per_ms: self.tsc_per_ms.load(Ordering::Relaxed),
+                base_tsc: self.base_tsc.load(Ordering::Relaxed),
+                wall_ms: self.wall_ms.load(Ordering::Relaxed) as u32,
+                year: (date >> 16) as u16,
+                month: (date >> 8) as u8,
+                day: date as u8,
+            };
+
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return snapshot
+            }
+        }
+    }
+
+    /// Returns the current time of the day in milliseconds, or None before the TSC is calibrated.
+    pub fn now(&self) -> Option<u32> {
+        let snapshot = self.snapshot();
+        if snapshot.tsc_per_ms == 0 {
+            return None
+        }
+        let passed = unsafe { _rdtsc() }.wrapping_sub(snapshot.base_tsc) / snapshot.tsc_per_ms;
+        Some(((snapshot.wall_ms as u64 + passed) % DAY_MS) as u32)
+    }
+}
+
+/// Maps the shared page read-only for the processes.
+pub fn init(mmu: &mut MMU) -> Result<(), MemError> {
+    let phys = mmu.translate(&VDSO as *const VdsoPage as usize).ok_or(MemError::NotMapped)?;
+    mmu.map_to(
+        Page::containing_address(VDSO_PAGE),
+        Frame::info_address(phys),
+        EntryFlags::PRESENT | EntryFlags::USER_ACCESSIBLE | EntryFlags::NO_EXECUTE,
+    )
+}
+
+/// Publishes the new TSC calibration and wall clock snapshot.
+pub fn publish(snapshot: TimeSnapshot) {
+    VDSO.0.write(&snapshot);
+}
+
+/// Returns the kernel side of the shared data.
+pub fn data() -> &'static VdsoData {
+    &VDSO.0
+}
+
+/// Returns the data through the read-only mapping used by the processes.
+///
+/// # Safety
+///
+/// The page must be mapped with [`init`].
+pub unsafe fn user_data() -> &'static VdsoData {
+    unsafe { &*(VDSO_PAGE as *const VdsoData) }
+}
+
+#[test_case]
+fn vdso_snapshot_is_consistent() {
+    let data = VdsoData::new();
+    assert_eq!(data.now(), None);
+
+    let snapshot = TimeSnapshot {
+        tsc_per_ms: 1_000_000,
+        base_tsc: unsafe { _rdtsc() },
+        wall_ms: DAY_MS as u32 - 1,
+        year: 2024,
+        month: 2,
+        day: 29,
+    };
+    data.write(&snapshot);
+    assert_eq!(data.snapshot(), snapshot);
+    assert_eq!(data.seq.load(Ordering::Relaxed), 2);
+    // The time of the day wraps around at midnight.
+    assert!(data.now().is_some_and(|ms| ms < DAY_MS as u32));
+}
@@ -0,0 +1,731 @@
+//! Virtual memory areas of process address spaces.
+//!
+//! Every process owns an [`AddressSpace`], which is a list of virtual memory areas within the
+//! mmap window of that process. All processes share one page table for now, therefore each of
+//! them receives its own 1 GiB window inside the P4 entry at [`MMAP_BASE`], selected by the pid.
+//!
+//! File mappings are populated lazily: [`map_file`] only reserves the virtual range, while the
+//! frames are allocated and filled from the file on the first page fault. Populated pages form
+//! the page cache of the area and pages marked dirty by the CPU are written back to the file on
+//! [`msync`] and [`unmap`].
+//!
+//! Anonymous areas start zero filled: reads map the shared zero frame and only writes allocate
+//! a private frame (see the ksm module). Under memory pressure their pages are evicted into the
+//! swap space and read back on the next page fault (see the swap module).
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use core::fmt::{Debug, Display};
+use core::error::Error;
+
+use crate::kernel_components::arch_x86_64::shootdown;
+use crate::kernel_components::drivers::storage::{BackingFile, BlockError};
+use crate::kernel_components::task_virtualization::{Scheduler, ROUND_ROBIN, PROCESS_MANAGEMENT_UNIT};
+use crate::{bitflags, VirtualAddress};
+
+use super::frames::{Frame, PAGE_SIZE};
+use super::memory_module::{MMU, MEMORY_MANAGEMENT_UNIT};
+use super::paging::BIT_MASK;
+use super::swap::{SwapSpace, SwapSlot, SwapError, ReclaimPass, SWAP};
+use super::{ksm, layout};
+use super::{Page, EntryFlags};
+
+/// Start of the mmap windows. It is the whole second P4 entry (512 GiB).
+pub const MMAP_BASE: VirtualAddress = layout::MMAP_START;
+/// Size of the mmap window of one process.
+pub const MMAP_WINDOW: usize = 0o_000_001_000_000_0000;
+/// Amount of windows within the mmap area. Pids are wrapped around this value.
+pub const MMAP_WINDOWS: usize = 512;
+
+const _: () = assert!(MMAP_BASE + MMAP_WINDOWS * MMAP_WINDOW == layout::MMAP_END);
+
+bitflags! {
+    /// Access rights of a mapped area.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Protection: u8 {
+        const READ      = 1 << 0,
+        const WRITE     = 1 << 1,
+        const EXEC      = 1 << 2,
+    };
+}
+
+/// Errors of virtual memory area operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// Zero length mappings are not allowed.
+    InvalidLength,
+    /// The file offset is not page aligned.
+    Unaligned(u64),
+    /// No gap in the window of the process is large enough for the mapping.
+    NoVirtualSpace,
+    /// The address does not belong to any area.
+    NotMapped(VirtualAddress),
+    /// The area is mapped, but the access is not permitted by its protection.
+    AccessViolation(VirtualAddress),
+    /// Writable mapping of a read-only file.
+    ReadOnlyFile,
+    /// No process is scheduled or the process list is unavailable.
+    NoProcess,
+    /// No free frames left.
+    OutOfMemory,
+    /// The backing file failed.
+    Io(BlockError),
+    /// Swapping the page in or out failed.
+    Swap(SwapError),
+}
+
+impl From<BlockError> for VmaError {
+    fn from(value: BlockError) -> Self {
+        VmaError::Io(value)
+    }
+}
+
+impl From<SwapError> for VmaError {
+    fn from(value: SwapError) -> Self {
+        VmaError::Swap(value)
+    }
+}
+
+impl Display for VmaError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            VmaError::InvalidLength => write!(f, "Mapping length must not be zero."),
+            VmaError::Unaligned(offset) => write!(f, "File offset {:#x} is not page aligned.", offset),
+            VmaError::NoVirtualSpace => write!(f, "No free virtual space within the mmap window."),
+            VmaError::NotMapped(addr) => write!(f, "Address {:#x} is not mapped.", addr),
+            VmaError::AccessViolation(addr) => write!(f, "Access to {:#x} violates the area protection.", addr),
+            VmaError::ReadOnlyFile => write!(f, "Cannot map a read-only file as writable."),
+            VmaError::NoProcess => write!(f, "No current process."),
+            VmaError::OutOfMemory => write!(f, "Out of physical frames."),
+            VmaError::Io(err) => write!(f, "Backing file error: {:?}.", err),
+            VmaError::Swap(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for VmaError {}
+
+/// A virtual memory area backed by a file or anonymous memory.
+pub struct Vma {
+    start: VirtualAddress,
+    len: usize,
+    prot: u8,
+    offset: u64,
+    /// Backing file. None for anonymous areas.
+    file: Option<Box<dyn BackingFile>>,
+    /// Populated pages of the area, by the page index within the area.
+    cache: BTreeMap<usize, Frame>,
+    /// Swapped out pages of anonymous areas, by the page index within the area.
+    swapped: BTreeMap<usize, SwapSlot>,
+    /// Pages mapped read-only to the zero frame or to a merged frame.
+    shared: BTreeMap<usize, Frame>,
+}
+
+impl Vma {
+    /// Returns the first address of the area.
+    #[inline]
+    pub fn start(&self) -> VirtualAddress {
+        self.start
+    }
+
+    /// Returns the address right after the end of the area.
+    #[inline]
+    pub fn end(&self) -> VirtualAddress {
+        self.start + self.len
+    }
+
+    /// Checks if the address belongs to the area.
+    #[inline]
+    pub fn contains(&self, addr: VirtualAddress) -> bool {
+        (self.start..self.end()).contains(&addr)
+    }
+
+    /// Returns the protection of the area.
+    #[inline]
+    pub fn protection(&self) -> u8 {
+        self.prot
+    }
+
+    /// Checks if the area is not backed by a file.
+    #[inline]
+    pub fn is_anonymous(&self) -> bool {
+        self.file.is_none()
+    }
+
+    /// Returns the amount of pages currently present in the page cache of the area.
+    #[inline]
+    pub fn resident_pages(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns the amount of pages currently mapped to shared frames.
+    #[inline]
+    pub fn shared_pages(&self) -> usize {
+        self.shared.len()
+    }
+
+    /// Returns the amount of pages currently written to the swap space.
+    #[inline]
+    pub fn swapped_pages(&self) -> usize {
+        self.swapped.len()
+    }
+
+    fn entry_flags(&self) -> EntryFlags {
+        let mut flags = EntryFlags::empty();
+        if Protection::WRITE.is_in(self.prot) {
+            flags |= EntryFlags::WRITABLE;
+        }
+        if !Protection::EXEC.is_in(self.prot) {
+            flags |= EntryFlags::NO_EXECUTE;
+        }
+        flags
+    }
+
+    /// Flags of pages mapped to shared frames. Such pages are never writable.
+    fn shared_flags(&self) -> EntryFlags {
+        match Protection::EXEC.is_in(self.prot) {
+            true => EntryFlags::empty(),
+            false => EntryFlags::NO_EXECUTE,
+        }
+    }
+
+    /// Checks if the page can be merged with other pages of the same content.
+    ///
+    /// Writes into merged pages of writable file mappings would never reach the file, therefore
+    /// only anonymous pages and pages of read-only file mappings qualify.
+    fn is_mergeable(&self) -> bool {
+        self.is_anonymous() || !Protection::WRITE.is_in(self.prot)
+    }
+
+    fn page(&self, index: usize) -> Page {
+        Page::containing_address(self.start + index * PAGE_SIZE)
+    }
+
+    fn page_data(&self, index: usize) -> &'static mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.page(index).start_address() as *mut u8, PAGE_SIZE) }
+    }
+
+    /// Returns the amount of file bytes covered by the page.
+    fn file_bytes(&mut self, index: usize) -> Result<usize, VmaError> {
+        let offset = self.offset + (index * PAGE_SIZE) as u64;
+        let size = match self.file.as_mut() {
+            Some(file) => file.size()?,
+            None => 0,
+        };
+        Ok(size.saturating_sub(offset).min(PAGE_SIZE as u64) as usize)
+    }
+
+    /// Allocates a frame for the page and fills it from the swap space or from the file.
+    ///
+    /// Reads of untouched anonymous pages map the shared zero frame instead.
+    fn populate(&mut self, mmu: &mut MMU, swap: Option<&mut SwapSpace>, index: usize, write: bool) -> Result<(), VmaError> {
+        let page = self.page(index);
+        let untouched = self.is_anonymous() && !self.swapped.contains_key(&index);
+
+        if untouched && !write {
+            if let Some(zero) = ksm::zero_frame() {
+                mmu.map_to(page, zero, self.shared_flags()).map_err(|_| VmaError::OutOfMemory)?;
+                self.shared.insert(index, ksm::zero_frame().unwrap());
+                return Ok(())
+            }
+        }
+
+        let (frame, zeroed) = mmu.allocate_zeroed_frame().ok_or(VmaError::OutOfMemory)?;
+        mmu.map_to(page, Frame { num: frame.num }, EntryFlags::WRITABLE).map_err(|_| VmaError::OutOfMemory)?;
+
+        let data = self.page_data(index);
+        if !zeroed {
+            data.fill(0);
+        }
+
+        let filled = match (self.swapped.get(&index).copied(), self.file.is_some()) {
+            (Some(slot), _) => swap.ok_or(VmaError::Swap(SwapError::NoSwap))
+                .and_then(|swap| swap.read_page(slot, data).map_err(VmaError::from))
+                .map(|_| { self.swapped.remove(&index); }),
+            (None, true) => self.file_bytes(index).and_then(|len| {
+                let offset = self.offset + (index * PAGE_SIZE) as u64;
+                self.file.as_mut().unwrap().read_at(offset, &mut data[..len]).map_err(VmaError::from)
+            }),
+            (None, false) => Ok(()),
+        };
+        if let Err(err) = filled {
+            let _ = mmu.unmap(page);
+            mmu.deallocate_frame_deferred(frame);
+            return Err(err)
+        }
+
+        // The first zeroed page read becomes the zero frame itself.
+        if untouched && !write && ksm::zero_frame().is_none() {
+            mmu.update_flags(page, self.shared_flags()).map_err(|_| VmaError::OutOfMemory)?;
+            ksm::set_zero_frame(Frame { num: frame.num });
+            self.shared.insert(index, frame);
+            return Ok(())
+        }
+
+        // Setting the final protection also clears the dirty bit set by filling.
+        mmu.update_flags(page, self.entry_flags()).map_err(|_| VmaError::OutOfMemory)?;
+        self.cache.insert(index, frame);
+        Ok(())
+    }
+
+    /// Gives the page mapped to a shared frame its own copy of the frame.
+    fn copy_on_write(&mut self, mmu: &mut MMU, index: usize) -> Result<(), VmaError> {
+        let page = self.page(index);
+        let frame = mmu.allocate_frame().ok_or(VmaError::OutOfMemory)?;
+        let content = self.page_data(index).to_vec();
+
+        let shared = self.shared.remove(&index).unwrap();
+        let _ = mmu.unmap(page);
+        mmu.map_to(page, Frame { num: frame.num }, EntryFlags::WRITABLE).map_err(|_| VmaError::OutOfMemory)?;
+        self.page_data(index).copy_from_slice(&content);
+        mmu.update_flags(page, self.entry_flags()).map_err(|_| VmaError::OutOfMemory)?;
+
+        ksm::put(mmu, shared);
+        self.cache.insert(index, frame);
+        Ok(())
+    }
+
+    /// Remaps the page read-only to the shared frame and drops the previous frame of the page.
+    ///
+    /// Returns true if a private frame of the page was freed.
+    fn share(&mut self, mmu: &mut MMU, index: usize, frame: Frame) -> bool {
+        let page = self.page(index);
+        let (previous, private) = match (self.cache.remove(&index), self.shared.remove(&index)) {
+            (Some(previous), _) => (previous, true),
+            (None, Some(previous)) if previous.num != frame.num => (previous, false),
+            (None, Some(previous)) => {
+                self.shared.insert(index, previous);
+                return false
+            },
+            (None, None) => return false,
+        };
+
+        let _ = mmu.unmap(page);
+        let _ = mmu.map_to(page, Frame { num: frame.num }, self.shared_flags());
+        ksm::get(&frame);
+
+        let freed = match (private, previous.num == frame.num) {
+            (_, true) => false,
+            (true, false) => { mmu.deallocate_frame_deferred(previous); true },
+            (false, false) => { ksm::put(mmu, previous); false },
+        };
+        self.shared.insert(index, frame);
+        freed
+    }
+
+    /// Writes the page back to the file if it is dirty.
+    ///
+    /// Returns true if the page was written.
+    fn writeback(&mut self, mmu: &mut MMU, index: usize) -> Result<bool, VmaError> {
+        let page = self.page(index);
+        let dirty = mmu.page_flags(page).is_some_and(|flags| EntryFlags::DIRTY.is_in(flags));
+        if !dirty || self.file.is_none() {
+            return Ok(false)
+        }
+
+        let len = self.file_bytes(index)?;
+        let data = &self.page_data(index)[..len];
+        self.file.as_mut().unwrap().write_at(self.offset + (index * PAGE_SIZE) as u64, data)?;
+
+        mmu.update_flags(page, self.entry_flags()).map_err(|_| VmaError::OutOfMemory)?;
+        Ok(true)
+    }
+
+    /// Writes every dirty page back to the file.
+    ///
+    /// Returns the amount of written pages.
+    pub fn sync(&mut self, mmu: &mut MMU) -> Result<usize, VmaError> {
+        let indices: Vec<usize> = self.cache.keys().copied().collect();
+        let mut written = 0;
+        for index in indices {
+            written += self.writeback(mmu, index)? as usize;
+        }
+        Ok(written)
+    }
+
+    /// Unmaps the populated page and returns its frame to the frame allocator after the grace
+    /// period.
+    fn evict(&mut self, mmu: &mut MMU, index: usize) {
+        if let Some(frame) = self.cache.remove(&index) {
+            let _ = mmu.unmap(self.page(index));
+            mmu.deallocate_frame_deferred(frame);
+        }
+    }
+
+    /// Evicts up to target pages which were not accessed since the previous scan.
+    ///
+    /// Accessed pages only lose their accessed bit. Returns the amount of evicted pages.
+    fn reclaim(&mut self, mmu: &mut MMU, mut swap: Option<&mut SwapSpace>, pass: ReclaimPass, target: usize) -> usize {
+        let indices: Vec<usize> = self.cache.keys().copied().collect();
+        let mut freed = 0;
+
+        for index in indices {
+            if freed >= target {
+                break
+            }
+            let page = self.page(index);
+            let Some(flags) = mmu.page_flags(page) else { continue };
+
+            if EntryFlags::ACCESSED.is_in(flags) {
+                let flags = flags & !(BIT_MASK as u64) & !EntryFlags::ACCESSED.bits();
+                let _ = mmu.update_flags(page, EntryFlags::Custom(flags));
+                continue
+            }
+
+            let dirty = EntryFlags::DIRTY.is_in(flags);
+            let evictable = match (pass, self.file.is_some()) {
+                (ReclaimPass::CleanFile, true) => !dirty,
+                (ReclaimPass::CleanFile, false) => false,
+                (ReclaimPass::Writeback, true) => self.writeback(mmu, index).is_ok(),
+                (ReclaimPass::Writeback, false) => match swap.as_deref_mut() {
+                    Some(swap) => swap.write_page(self.page_data(index))
+                        .map(|slot| { self.swapped.insert(index, slot); })
+                        .is_ok(),
+                    None => false,
+                },
+            };
+
+            if evictable {
+                self.evict(mmu, index);
+                freed += 1;
+            }
+        }
+        freed
+    }
+}
+
+impl Debug for Vma {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Vma")
+            .field("start", &format_args!("{:#x}", self.start))
+            .field("len", &self.len)
+            .field("prot", &self.prot)
+            .field("anonymous", &self.is_anonymous())
+            .field("offset", &self.offset)
+            .field("resident", &self.cache.len())
+            .field("swapped", &self.swapped.len())
+            .field("shared", &self.shared.len())
+            .finish()
+    }
+}
+
+/// Set of virtual memory areas of one process.
+#[derive(Debug)]
+pub struct AddressSpace {
+    base: VirtualAddress,
+    /// Areas sorted by their start address.
+    areas: Vec<Vma>,
+}
+
+impl AddressSpace {
+    /// Creates an empty address space within the mmap window of the process.
+    pub const fn new(pid: usize) -> Self {
+        Self {
+            base: MMAP_BASE + (pid % MMAP_WINDOWS) * MMAP_WINDOW,
+            areas: Vec::new(),
+        }
+    }
+
+    /// Returns the areas of the address space.
+    #[inline]
+    pub fn areas(&self) -> &[Vma] {
+        &self.areas
+    }
+
+    /// Checks if the address is within the mmap window of the process.
+    pub fn owns(&self, addr: VirtualAddress) -> bool {
+        (self.base..self.base + MMAP_WINDOW).contains(&addr)
+    }
+
+    /// Finds the area that contains the address.
+    pub fn find(&self, addr: VirtualAddress) -> Option<&Vma> {
+        self.areas.iter().find(|vma| vma.contains(addr))
+    }
+
+    fn position(&self, addr: VirtualAddress) -> Result<usize, VmaError> {
+        self.areas.iter()
+            .position(|vma| vma.contains(addr))
+            .ok_or(VmaError::NotMapped(addr))
+    }
+
+    /// Reserves a virtual range for the part of the file, starting at the offset.
+    ///
+    /// Nothing is mapped until the range is accessed. The length is rounded up to whole pages;
+    /// bytes past the end of the file read as zeroes and are never written back.
+    pub fn map_file(
+        &mut self,
+        file: Box<dyn BackingFile>,
+        offset: u64,
+        len: usize,
+        prot: Protection,
+    ) -> Result<VirtualAddress, VmaError> {
+        if offset % PAGE_SIZE as u64 != 0 {
+            return Err(VmaError::Unaligned(offset))
+        }
+        if Protection::WRITE.is_in(prot.bits()) && file.is_read_only() {
+            return Err(VmaError::ReadOnlyFile)
+        }
+        self.insert(len, prot, offset, Some(file))
+    }
+
+    /// Reserves a virtual range of zero filled memory.
+    ///
+    /// Nothing is mapped until the range is accessed. The length is rounded up to whole pages.
+    pub fn map_anonymous(&mut self, len: usize, prot: Protection) -> Result<VirtualAddress, VmaError> {
+        self.insert(len, prot, 0, None)
+    }
+
+    fn insert(
+        &mut self,
+        len: usize,
+        prot: Protection,
+        offset: u64,
+        file: Option<Box<dyn BackingFile>>,
+    ) -> Result<VirtualAddress, VmaError> {
+        if len == 0 {
+            return Err(VmaError::InvalidLength)
+        }
+
+        let len = len.next_multiple_of(PAGE_SIZE);
+        let window_end = self.base + MMAP_WINDOW;
+
+        // First fit over the sorted areas.
+        let mut start = self.base;
+        let mut position = self.areas.len();
+        for (i, vma) in self.areas.iter().enumerate() {
+            if start + len <= vma.start {
+                position = i;
+                break
+            }
+            start = vma.end();
+        }
+        if start + len > window_end {
+            return Err(VmaError::NoVirtualSpace)
+        }
+
+        self.areas.insert(position, Vma {
+            start, len, offset, file,
+            prot: prot.bits(),
+            cache: BTreeMap::new(),
+            swapped: BTreeMap::new(),
+            shared: BTreeMap::new(),
+        });
+        Ok(start)
+    }
+
+    /// Populates the faulting page from the swap space or the file of its area, or copies the
+    /// shared frame on a write into the page mapped to it.
+    ///
+    /// When no free frames are left, pages of the other areas are reclaimed and the fault is
+    /// retried once.
+    pub fn handle_fault(
+        &mut self,
+        mmu: &mut MMU,
+        mut swap: Option<&mut SwapSpace>,
+        addr: VirtualAddress,
+        write: bool,
+        present: bool,
+    ) -> Result<(), VmaError> {
+        let position = self.position(addr)?;
+        let vma = &self.areas[position];
+        let index = (addr - vma.start) / PAGE_SIZE;
+        let writable = Protection::WRITE.is_in(vma.prot);
+
+        if vma.shared.contains_key(&index) && write && writable {
+            return self.areas[position].copy_on_write(mmu, index)
+        }
+        if present || vma.shared.contains_key(&index) || vma.cache.contains_key(&index) || (write && !writable) {
+            return Err(VmaError::AccessViolation(addr))
+        }
+
+        match self.areas[position].populate(mmu, swap.as_deref_mut(), index, write) {
+            Err(VmaError::OutOfMemory) => {
+                for pass in [ReclaimPass::CleanFile, ReclaimPass::Writeback, ReclaimPass::Writeback] {
+                    if self.reclaim(mmu, swap.as_deref_mut(), pass, 1) != 0 {
+                        break
+                    }
+                }
+                self.areas[position].populate(mmu, swap, index, write)
+            },
+            result => result,
+        }
+    }
+
+    /// Evicts up to target pages of the address space within the reclaim pass.
+    ///
+    /// Returns the amount of evicted pages.
+    pub fn reclaim(&mut self, mmu: &mut MMU, mut swap: Option<&mut SwapSpace>, pass: ReclaimPass, target: usize) -> usize {
+        let mut freed = 0;
+        for vma in self.areas.iter_mut() {
+            if freed >= target {
+                break
+            }
+            freed += vma.reclaim(mmu, swap.as_deref_mut(), pass, target - freed);
+        }
+        freed
+    }
+
+    /// Collects the hashes and addresses of pages which may be merged with identical pages.
+    pub fn merge_candidates(&self, candidates: &mut Vec<(u64, VirtualAddress)>) {
+        for vma in self.areas.iter().filter(|vma| vma.is_mergeable()) {
+            let zero = |frame: &Frame| ksm::is_zero_frame(frame);
+            let pages = vma.cache.keys().chain(vma.shared.iter().filter(|(_, f)| !zero(f)).map(|(i, _)| i));
+            for &index in pages {
+                let addr = vma.page(index).start_address();
+                candidates.push((ksm::page_hash(addr), addr));
+            }
+        }
+    }
+
+    /// Maps the page at the address read-only to the shared frame.
+    ///
+    /// Returns true if a private frame of the page was freed. Does nothing if the address does
+    /// not belong to a populated page of this address space.
+    pub fn share(&mut self, mmu: &mut MMU, addr: VirtualAddress, frame: Frame) -> bool {
+        let Ok(position) = self.position(addr) else { return false };
+        let vma = &mut self.areas[position];
+        let index = (addr - vma.start) / PAGE_SIZE;
+        vma.share(mmu, index, frame)
+    }
+
+    /// Writes the dirty pages of the area which contains the address back to the file.
+    ///
+    /// Returns the amount of written pages.
+    pub fn msync(&mut self, mmu: &mut MMU, addr: VirtualAddress) -> Result<usize, VmaError> {
+        let position = self.position(addr)?;
+        self.areas[position].sync(mmu)
+    }
+
+    /// Writes the dirty pages of every file backed area back to the file.
+    ///
+    /// Areas whose file fails to write are skipped. Returns the amount of written pages.
+    pub fn sync_all(&mut self, mmu: &mut MMU) -> usize {
+        self.areas.iter_mut().filter_map(|vma| vma.sync(mmu).ok()).sum()
+    }
+
+    /// Writes back and removes the area which contains the address.
+    ///
+    /// The file is closed by dropping it after all dirty pages are written. Swap slots of the
+    /// anonymous areas are freed.
+    pub fn unmap(&mut self, mmu: &mut MMU, swap: Option<&mut SwapSpace>, addr: VirtualAddress) -> Result<(), VmaError> {
+        let position = self.position(addr)?;
+        self.areas[position].sync(mmu)?;
+
+        // The other processors are interrupted once for the whole area.
+        let _batch = shootdown::Batch::begin();
+        Self::release(mmu, swap, self.areas.remove(position));
+        Ok(())
+    }
+
+    /// Removes every area and frees the page tables left empty in the mmap window.
+    ///
+    /// Dirty pages are written back on the best effort basis, an area whose file fails to write is
+    /// dropped anyway. Private frames are freed and shared ones lose a reference, so a frame
+    /// merged with other processes stays mapped there. Returns the amount of freed page tables.
+    pub fn teardown(&mut self, mmu: &mut MMU, mut swap: Option<&mut SwapSpace>) -> usize {
+        let batch = shootdown::Batch::begin();
+        for mut vma in core::mem::take(&mut self.areas) {
+            let _ = vma.sync(mmu);
+            Self::release(mmu, swap.as_deref_mut(), vma);
+        }
+        // Freed tables must not be reused before every TLB dropped the old translations.
+        drop(batch);
+        mmu.free_page_tables(self.base)
+    }
+
+    /// Unmaps the pages of the removed area, freeing it's private frames and swap slots.
+    fn release(mmu: &mut MMU, swap: Option<&mut SwapSpace>, mut vma: Vma) {
+        let indices: Vec<usize> = vma.cache.keys().copied().collect();
+        for index in indices {
+            vma.evict(mmu, index);
+        }
+        for (index, frame) in core::mem::take(&mut vma.shared) {
+            let _ = mmu.unmap(vma.page(index));
+            ksm::put(mmu, frame);
+        }
+        if let Some(swap) = swap {
+            vma.swapped.values().for_each(|&slot| swap.free(slot));
+        }
+    }
+}
+
+/// Calls the closure with the address space of the currently scheduled process.
+fn with_current<F, R>(f: F) -> Result<R, VmaError> where
+    F: FnOnce(&mut AddressSpace, &mut MMU, Option<&mut SwapSpace>) -> Result<R, VmaError>
+{
+    unsafe {
+        let task = *ROUND_ROBIN.current().ok_or(VmaError::NoProcess)?;
+        let mut list = PROCESS_MANAGEMENT_UNIT.process_list.lock();
+        let process = list.get_mut(task.pid).ok_or(VmaError::NoProcess)?;
+        f(&mut process.address_space, &mut MEMORY_MANAGEMENT_UNIT, SWAP.as_mut())
+    }
+}
+
+/// Maps the part of the file into the address space of the current process.
+///
+/// Returns the start of the mapping. See [`AddressSpace::map_file`].
+pub fn map_file(
+    file: Box<dyn BackingFile>,
+    offset: u64,
+    len: usize,
+    prot: Protection,
+) -> Result<VirtualAddress, VmaError> {
+    with_current(|space, _, _| space.map_file(file, offset, len, prot))
+}
+
+/// Maps zero filled memory into the address space of the current process.
+///
+/// Returns the start of the mapping. See [`AddressSpace::map_anonymous`].
+pub fn map_anonymous(len: usize, prot: Protection) -> Result<VirtualAddress, VmaError> {
+    with_current(|space, _, _| space.map_anonymous(len, prot))
+}
+
+/// Writes back the dirty pages of the mapping of the current process which contains the address.
+pub fn msync(addr: VirtualAddress) -> Result<usize, VmaError> {
+    with_current(|space, mmu, _| space.msync(mmu, addr))
+}
+
+/// Unmaps the mapping of the current process which contains the address.
+pub fn unmap(addr: VirtualAddress) -> Result<(), VmaError> {
+    with_current(|space, mmu, swap| space.unmap(mmu, swap, addr))
+}
+
+/// Resolves the page fault within the mappings of the current process.
+///
+/// Must be called from the page fault handler. Fails if the fault happened while the process list
+/// is locked, since waiting for the lock inside of the handler would never end.
+pub fn handle_page_fault(addr: VirtualAddress, write: bool, present: bool) -> Result<(), VmaError> {
+    if unsafe { PROCESS_MANAGEMENT_UNIT.process_list.is_locked() } {
+        return Err(VmaError::NoProcess)
+    }
+    with_current(|space, mmu, swap| space.handle_fault(mmu, swap, addr, write, present))
+}
+
+#[test_case]
+fn file_mappings_reserve_ranges() {
+    use alloc::vec;
+
+    let mut space = AddressSpace::new(3);
+    let base = MMAP_BASE + 3 * MMAP_WINDOW;
+
+    let first = space.map_file(Box::new(vec![1u8; 5000]), 0, 5000, Protection::READ).unwrap();
+    let second = space.map_file(Box::new(vec![2u8; 100]), 0, 100, Protection::READ | Protection::WRITE).unwrap();
+    assert_eq!(first, base);
+    assert_eq!(second, base + 2 * PAGE_SIZE);
+    assert_eq!(space.find(base + PAGE_SIZE + 1).map(Vma::start), Some(first));
+    let anon = space.map_anonymous(1, Protection::READ | Protection::WRITE).unwrap();
+    assert_eq!(anon, base + 3 * PAGE_SIZE);
+    assert!(space.find(anon).unwrap().is_anonymous());
+
+    assert_eq!(space.map_file(Box::new(vec![0u8; 1]), 1, 1, Protection::READ).unwrap_err(), VmaError::Unaligned(1));
+    assert_eq!(space.map_file(Box::new(vec![0u8; 1]), 0, 0, Protection::READ).unwrap_err(), VmaError::InvalidLength);
+    assert_eq!(
+        space.map_file(Box::new(vec![0u8; 1]), 0, MMAP_WINDOW, Protection::READ).unwrap_err(),
+        VmaError::NoVirtualSpace
+    );
+
+    unsafe { space.teardown(&mut MEMORY_MANAGEMENT_UNIT, None) };
+    assert!(space.areas().is_empty());
+}
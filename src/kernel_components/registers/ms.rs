@@ -5,9 +5,10 @@
 /// performance monitoring, and toggling certain CPU features.
 
 
-use crate::{bitflags, VirtualAddress};
+use crate::{bitflags, VirtualAddress, PhysicalAddress};
 use crate::kernel_components::memory::Page;
 use core::arch::asm;
+use core::arch::x86_64::__cpuid;
 
 /// Extended Feature Enable Register (EFER)
 ///
@@ -69,7 +70,116 @@ pub struct UCet; impl Msr for UCet { const MSR: u32 = 0xC0000084; }
 #[derive(Debug)]
 pub struct SCet; impl Msr for SCet { const MSR: u32 = 0xC0000085; }
 
+/// KVM paravirtual system time MSR.
+///
+/// Provides the guest physical address of the pvclock structure, which is updated by the host.
+/// Bit 0 enables the updates.
+#[derive(Debug)]
+pub struct KvmSystemTime; impl Msr for KvmSystemTime { const MSR: u32 = 0x4b564d01; }
+
+/// KVM paravirtual end of interrupt MSR.
+///
+/// Provides the guest physical address of the PV EOI flag. Bit 0 enables the feature.
+#[derive(Debug)]
+pub struct KvmPvEoi; impl Msr for KvmPvEoi { const MSR: u32 = 0x4b564d04; }
+
+/// IA32_APIC_BASE MSR
+///
+/// Holds the physical base address of the local APIC registers and enables or disables the local
+/// APIC of the current CPU.
+#[derive(Debug)]
+pub struct ApicBase; impl Msr for ApicBase { const MSR: u32 = 0x1b; }
+
+/// IA32_TSC_DEADLINE MSR
+///
+/// Arms the local APIC timer in the TSC-deadline mode. The timer fires once the TSC reaches the
+/// written value, writing zero disarms it.
+#[derive(Debug)]
+pub struct TscDeadline; impl Msr for TscDeadline { const MSR: u32 = 0x6e0; }
+
+/// IA32_PERF_STATUS MSR
+///
+/// Reports the current performance state value of the processor.
+#[derive(Debug)]
+pub struct PerfStatus; impl Msr for PerfStatus { const MSR: u32 = 0x198; }
+
+/// IA32_PERF_CTL MSR
+///
+/// Requests the performance state, which value is provided by the ACPI _PSS object.
+#[derive(Debug)]
+pub struct PerfCtl; impl Msr for PerfCtl { const MSR: u32 = 0x199; }
+
+/// IA32_THERM_INTERRUPT MSR
+///
+/// Enables the thermal interrupts of the core: high and low temperature, PROCHOT, critical
+/// temperature and two programmable thresholds.
+#[derive(Debug)]
+pub struct ThermInterrupt; impl Msr for ThermInterrupt { const MSR: u32 = 0x19b; }
+
+/// IA32_THERM_STATUS MSR
+///
+/// Thermal status of the core. Bits 22:16 hold the digital readout, which is the distance to the
+/// maximal junction temperature in degrees Celsius.
+#[derive(Debug)]
+pub struct ThermStatus; impl Msr for ThermStatus { const MSR: u32 = 0x19c; }
+
+/// MSR_TEMPERATURE_TARGET MSR
+///
+/// Bits 23:16 hold the maximal junction temperature (TjMax) in degrees Celsius.
+#[derive(Debug)]
+pub struct TemperatureTarget; impl Msr for TemperatureTarget { const MSR: u32 = 0x1a2; }
+
+/// IA32_PACKAGE_THERM_STATUS MSR
+///
+/// Thermal status of the whole package, with the same layout as IA32_THERM_STATUS.
+#[derive(Debug)]
+pub struct PackageThermStatus; impl Msr for PackageThermStatus { const MSR: u32 = 0x1b1; }
+
+/// IA32_PACKAGE_THERM_INTERRUPT MSR
+///
+/// Enables the thermal interrupts of the package, with the same layout as IA32_THERM_INTERRUPT.
+#[derive(Debug)]
+pub struct PackageThermInterrupt; impl Msr for PackageThermInterrupt { const MSR: u32 = 0x1b2; }
+
+/// IA32_PM_ENABLE MSR
+///
+/// Bit 0 enables the hardware controlled performance states (HWP). Once set, it can only be
+/// cleared by a reset.
+#[derive(Debug)]
+pub struct PmEnable; impl Msr for PmEnable { const MSR: u32 = 0x770; }
+
+/// IA32_HWP_CAPABILITIES MSR
+///
+/// Reports the highest, guaranteed, most efficient and lowest performance levels in bytes 0 to 3.
+#[derive(Debug)]
+pub struct HwpCapabilities; impl Msr for HwpCapabilities { const MSR: u32 = 0x771; }
+
+/// IA32_HWP_REQUEST MSR
+///
+/// Holds the minimum, maximum and desired performance levels and the energy performance
+/// preference in bytes 0 to 3.
+#[derive(Debug)]
+pub struct HwpRequest; impl Msr for HwpRequest { const MSR: u32 = 0x774; }
+
+/// Page Attribute Table (PAT)
+///
+/// Eight memory types, one of which is selected for every 4 KiB page by the PAT, PCD and PWT bits
+/// of it's page table entry.
+#[derive(Debug)]
+pub struct Pat; impl Msr for Pat { const MSR: u32 = 0x277; }
+
 bitflags! {
+    /// Flags of the IA32_APIC_BASE register.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct ApicBaseFlags: u64 {
+        /// Set on the bootstrap processor. Read only.
+        const BOOTSTRAP_PROCESSOR =             1 << 8,
+        /// Enables the x2APIC mode, where registers are accessed as MSRs.
+        const X2APIC_ENABLE =                   1 << 10,
+        /// Enables the local APIC. Once cleared, it can only be enabled again after a reset.
+        const GLOBAL_ENABLE =                   1 << 11,
+    };
+
     /// Config of EFER.
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
     pub struct EFERFlags: u64 {
@@ -230,6 +340,40 @@ impl EFER {
     }
 }
 
+impl Pat {
+    /// Entry programmed to write combining, selected by the PAT bit alone.
+    pub const WRITE_COMBINING_ENTRY: u32 = 4;
+    /// Memory type encoding of write combining.
+    const WRITE_COMBINING: u64 = 0x01;
+
+    /// Checks if the processor has the PAT.
+    #[inline]
+    pub fn is_supported() -> bool {
+        unsafe { __cpuid(1) }.edx & 1 << 16 != 0
+    }
+
+    /// Checks if the write combining entry is programmed on this processor.
+    pub fn is_enabled() -> bool {
+        Self::is_supported()
+            && unsafe { Self::read_raw() } >> (Self::WRITE_COMBINING_ENTRY * 8) & 0xff == Self::WRITE_COMBINING
+    }
+
+    /// Changes the entry 4, which is a copy of the write back entry 0 after reset, to write
+    /// combining. Does nothing without the PAT.
+    ///
+    /// Every processor must do it before any page selects the entry, as the processors must agree
+    /// on the memory type of each page.
+    pub fn enable_write_combining() {
+        if !Self::is_supported() {
+            return
+        }
+        let shift = Self::WRITE_COMBINING_ENTRY * 8;
+        let pat = unsafe { Self::read_raw() } & !(0xff << shift) | Self::WRITE_COMBINING << shift;
+
+        unsafe { Self::write_raw(pat) }
+    }
+}
+
 impl FSBase {
     /// Reads the current value of FsBase register.
     #[inline]
@@ -273,6 +417,32 @@ impl KernelGSBase {
 }
 
 
+impl ApicBase {
+    /// Mask of the base address within the register.
+    const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+    /// Reads the flags and the physical base address of the local APIC.
+    #[inline]
+    pub fn read() -> (ApicBaseFlags, PhysicalAddress) {
+        let raw_value = unsafe { Self::read_raw() };
+        let flags = ApicBaseFlags::from(raw_value & !Self::ADDRESS_MASK);
+
+        (flags, (raw_value & Self::ADDRESS_MASK) as PhysicalAddress)
+    }
+
+    /// Writes the flags and the physical base address of the local APIC.
+    ///
+    /// # Unsafe
+    ///
+    /// Moving the registers or disabling the local APIC affects all interrupt handling.
+    #[inline]
+    pub unsafe fn write(flags: ApicBaseFlags, base: PhysicalAddress) {
+        let reserved = unsafe { Self::read_raw() } & !Self::ADDRESS_MASK & !ApicBaseFlags::all().bits();
+        unsafe {
+            Self::write_raw(reserved | flags.bits() | (base as u64 & Self::ADDRESS_MASK))
+        };
+    }
+}
 
 impl UCet {
     /// Reads the current CET values of UCet register and the address to the legacy code page.
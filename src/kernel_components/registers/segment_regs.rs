@@ -230,3 +230,40 @@ pub struct FunctionSegment; implement_segment_u64!(FunctionSegment, "fs", FSBase
 #[derive(Debug)]
 pub struct GeneralSegment; implement_segment_u64!(GeneralSegment, "gs", GSBase);
 
+/// Snapshot of the data segment registers together with the bases of FS and GS.
+///
+/// Interrupts and exceptions in long mode only switch CS and SS, so reading the other registers
+/// inside of a handler shows the state of the interrupted code. The bases are read from the MSRs,
+/// which works without the FSGSBASE extension enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentRegisters {
+    pub ds: SegmentSelector,
+    pub es: SegmentSelector,
+    pub fs: SegmentSelector,
+    pub gs: SegmentSelector,
+    pub fs_base: VirtualAddress,
+    pub gs_base: VirtualAddress,
+}
+
+impl SegmentRegisters {
+    /// Reads the current state of the registers.
+    pub fn read() -> Self {
+        Self {
+            ds: DataSegment::read(),
+            es: ExtraSegment::read(),
+            fs: FunctionSegment::read(),
+            gs: GeneralSegment::read(),
+            fs_base: FSBase::read(),
+            gs_base: GSBase::read(),
+        }
+    }
+}
+
+impl core::fmt::Display for SegmentRegisters {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f, "DS {:#06x} ES {:#06x} FS {:#06x} (base {:#x}) GS {:#06x} (base {:#x})",
+            self.ds.0, self.es.0, self.fs.0, self.fs_base, self.gs.0, self.gs_base,
+        )
+    }
+}
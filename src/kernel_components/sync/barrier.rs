@@ -37,7 +37,7 @@ impl Barrier {
         // Arrived
         if self.0.fetch_sub(1, Ordering::SeqCst) > 1 {
             while self.0.load(Ordering::Acquire) > 0 {
-                Thread::r#yield();
+                Thread::yield_now();
             }
         }
     }
@@ -0,0 +1,116 @@
+/// Spinlock which keeps interrupts disabled while held.
+///
+/// A regular [`Mutex`] yields the CPU while waiting, which is not allowed within interrupt
+/// handlers, and a handler which interrupts the holder of the lock on the same CPU would wait
+/// forever. This lock enters a critical section before taking the lock, so no interrupt handler
+/// can run on the CPU while it is held, and waits by spinning instead of yielding.
+///
+/// Non-maskable interrupts and exceptions are still delivered. Code reachable from them must use
+/// [`IrqSpinlock::try_lock`] and handle the failure.
+///
+/// [`Mutex`]: super::Mutex
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::kernel_components::arch_x86_64::interrupts::interrupt::CriticalGuard;
+
+/// Spinlock which disables interrupts for the whole time it is held.
+pub struct IrqSpinlock<T: ?Sized> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+/// Guard of the [`IrqSpinlock`]. Interrupts are restored after the lock is released.
+pub struct IrqSpinlockGuard<'a, T: 'a + ?Sized> {
+    lock: &'a IrqSpinlock<T>,
+    // Dropped after the lock is released.
+    _critical: CriticalGuard,
+}
+
+impl<T> IrqSpinlock<T> {
+    /// Creates a new instance of the 'IrqSpinlock'
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Disables interrupts and spins until the lock is obtained.
+    #[inline(always)]
+    pub fn lock(&self) -> IrqSpinlockGuard<'_, T> {
+        let critical = unsafe { CriticalGuard::enter() };
+        while self.locked.swap(true, Ordering::Acquire) {
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+        IrqSpinlockGuard { lock: self, _critical: critical }
+    }
+
+    /// Tries to obtain the lock once. Interrupts stay as they were on failure.
+    #[inline(always)]
+    pub fn try_lock(&self) -> Option<IrqSpinlockGuard<'_, T>> {
+        let critical = unsafe { CriticalGuard::enter() };
+        match self.locked.swap(true, Ordering::Acquire) {
+            true => None,
+            false => Some(IrqSpinlockGuard { lock: self, _critical: critical }),
+        }
+    }
+
+    /// Returns the current state of the lock.
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Forcefully unlocks the spinlock.
+    ///
+    /// # Unsafe
+    ///
+    /// The current holder keeps it's guard, so the data might be accessed by two owners at once.
+    /// Only useful on paths which never return to the holder, like panics.
+    pub unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Drop for IrqSpinlockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+impl<'a, T> Deref for IrqSpinlockGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for IrqSpinlockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+unsafe impl<T: Send> Sync for IrqSpinlock<T> {}
+unsafe impl<T: Send> Send for IrqSpinlock<T> {}
+
+#[test_case]
+fn irq_spinlock_disables_interrupts() {
+    let lock = IrqSpinlock::new(0u32);
+    let depth = CriticalGuard::depth();
+
+    {
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert_eq!(CriticalGuard::depth(), depth + 1);
+        assert!(lock.try_lock().is_none());
+        assert_eq!(CriticalGuard::depth(), depth + 1);
+    }
+
+    assert_eq!(CriticalGuard::depth(), depth);
+    assert_eq!(*lock.try_lock().unwrap(), 1);
+}
@@ -0,0 +1,432 @@
+//! Runtime configuration registry.
+//!
+//! Subsystems register their tunables under dotted names (i.e "sched.time_slice_ms"). Each
+//! tunable has a typed value, an optional validation callback and an optional callback that
+//! applies the new value. Values can be changed from the kernel shell or the kernel command line.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use crate::kernel_components::sync::Mutex;
+use crate::{single, critical_section};
+
+/// Global tunables registry.
+single! {
+    pub mut SYSCTL: Mutex<Sysctl> = Mutex::new(Sysctl::new());
+}
+
+/// Sets the tunable of the global registry and applies the new value without holding it's lock.
+pub fn set(name: &str, value: &str) -> Result<(), SysctlError> {
+    let pending = unsafe { SYSCTL.lock() }.set(name, value)?;
+    pending.apply();
+    Ok(())
+}
+
+/// Applies all "name=value" pairs from the kernel command line to the global registry.
+///
+/// Words which are not registered tunables are ignored, so the command line can be shared
+/// with other consumers. Returns the names of tunables that failed to be set.
+pub fn apply_cmdline(cmdline: &str) -> Vec<(String, SysctlError)> {
+    let mut failed = Vec::new();
+
+    for (name, value) in cmdline.split_whitespace().filter_map(|word| word.split_once('=')) {
+        match set(name, value) {
+            Ok(()) | Err(SysctlError::NotFound) => (),
+            Err(err) => failed.push((String::from(name), err)),
+        }
+    }
+
+    failed
+}
+
+/// Typed value of the tunable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SysctlValue {
+    Int(i64),
+    Bool(bool),
+    Str(String),
+}
+
+impl SysctlValue {
+    /// Parses the string into the value of the same type as self.
+    fn parse_as(&self, s: &str) -> Option<Self> {
+        match self {
+            Self::Int(_) => {
+                match s.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16).ok(),
+                    None => s.parse().ok(),
+                }.map(Self::Int)
+            },
+            Self::Bool(_) => match s {
+                "1" | "true" | "on" | "yes" => Some(Self::Bool(true)),
+                "0" | "false" | "off" | "no" => Some(Self::Bool(false)),
+                _ => None,
+            },
+            Self::Str(_) => Some(Self::Str(String::from(s))),
+        }
+    }
+
+    /// Returns the integer value, if the value is an integer.
+    pub fn as_int(&self) -> Option<i64> {
+        if let Self::Int(i) = self { Some(*i) } else { None }
+    }
+
+    /// Returns the boolean value, if the value is a boolean.
+    pub fn as_bool(&self) -> Option<bool> {
+        if let Self::Bool(b) = self { Some(*b) } else { None }
+    }
+
+    /// Returns the string value, if the value is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        if let Self::Str(s) = self { Some(s.as_str()) } else { None }
+    }
+}
+
+impl Display for SysctlValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Int(i) => write!(f, "{}", i),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// Validation callback. Returns true if the value is acceptable.
+pub type SysctlValidate = fn(&SysctlValue) -> bool;
+/// Callback which applies the new value to the subsystem.
+pub type SysctlApply = fn(&SysctlValue);
+
+/// Apply callback of a changed tunable, which was not called yet.
+///
+/// Returned by [`Sysctl::set`], so the callback runs once the lock of the registry is dropped, as
+/// it may wait for the hardware or read the registry itself.
+#[must_use = "the new value only takes effect once applied"]
+pub struct PendingApply(Option<(SysctlApply, SysctlValue)>);
+
+impl PendingApply {
+    /// Calls the apply callback with the new value.
+    pub fn apply(self) {
+        if let Some((apply, value)) = self.0 {
+            apply(&value);
+        }
+    }
+}
+
+/// Single registered tunable.
+#[derive(Debug, Clone)]
+pub struct SysctlEntry {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub value: SysctlValue,
+    validate: Option<SysctlValidate>,
+    apply: Option<SysctlApply>,
+}
+
+/// Errors that may occur when working with the registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SysctlError {
+    /// No tunable with such name.
+    NotFound,
+    /// The tunable with such name is already registered.
+    AlreadyRegistered,
+    /// The value cannot be parsed as the type of tunable.
+    TypeMismatch,
+    /// The validation callback rejected the value.
+    Rejected,
+}
+
+/// Key-value registry of tunables.
+#[derive(Debug, Default)]
+pub struct Sysctl {
+    entries: BTreeMap<&'static str, SysctlEntry>,
+}
+
+impl Sysctl {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        Self { entries: BTreeMap::new() }
+    }
+
+    /// Registers a new tunable with the default value.
+    ///
+    /// The default value defines the type of the tunable. The apply callback is not called for
+    /// the default value.
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        description: &'static str,
+        default: SysctlValue,
+        validate: Option<SysctlValidate>,
+        apply: Option<SysctlApply>,
+    ) -> Result<(), SysctlError> {
+        if self.entries.contains_key(name) {
+            return Err(SysctlError::AlreadyRegistered)
+        }
+
+        self.entries.insert(name, SysctlEntry { name, description, value: default, validate, apply });
+        Ok(())
+    }
+
+    /// Returns the current value of the tunable.
+    pub fn get(&self, name: &str) -> Option<&SysctlValue> {
+        self.entries.get(name).map(|e| &e.value)
+    }
+
+    /// Parses, validates and stores the new value of the tunable.
+    ///
+    /// The value is applied to the subsystem with the returned [`PendingApply`], after the lock
+    /// of the registry is dropped.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<PendingApply, SysctlError> {
+        let entry = self.entries.get_mut(name).ok_or(SysctlError::NotFound)?;
+        let value = entry.value.parse_as(value).ok_or(SysctlError::TypeMismatch)?;
+
+        if let Some(validate) = entry.validate {
+            if !validate(&value) {
+                return Err(SysctlError::Rejected)
+            }
+        }
+
+        entry.value = value.clone();
+        Ok(PendingApply(entry.apply.map(|apply| (apply, value))))
+    }
+
+    /// Returns all registered tunables sorted by name.
+    pub fn list(&self) -> Vec<&SysctlEntry> {
+        self.entries.values().collect()
+    }
+
+    /// Registers the tunables of the core kernel subsystems.
+    pub fn register_defaults(&mut self) {
+        use crate::kernel_components::arch_x86_64::controllers::{PIT, PITCommand, PS2};
+        use crate::kernel_components::memory::allocators::free_list_alloc::SearchStrategy;
+        use crate::kernel_components::memory::{pressure, ksm, aslr, prezero};
+        use crate::kernel_components::arch_x86_64::cpufreq::{Governor, CPUFREQ, DEFAULT_UP_THRESHOLD};
+        use crate::kernel_components::{klog, logging, vga_buffer};
+        use crate::kernel_components::arch_x86_64::interrupts::interrupt::{
+            CRITICAL_WARN_CYCLES, DEFAULT_CRITICAL_WARN_CYCLES,
+        };
+        use crate::kernel_components::arch_x86_64::interrupts::def_exceptions::{
+            DoubleFaultPolicy, DOUBLE_FAULT_REBOOT_SECS, DEFAULT_DOUBLE_FAULT_REBOOT_SECS,
+        };
+        use crate::FREE_LIST_ALLOC;
+        use core::sync::atomic::Ordering;
+
+        let _ = self.register(
+            "sched.time_slice_ms",
+            "scheduler time slice (PIT channel 0 period) in milliseconds",
+            SysctlValue::Int(55),
+            Some(|v| matches!(v.as_int(), Some(1..=55))),
+            Some(|v| {
+                let hz = 1000 / v.as_int().unwrap() as u32;
+                let divisor = (1_193_182 / hz).min(u16::MAX as u32) as u16;
+                let mut pit = PIT::new();
+                unsafe {
+                    pit.command(PITCommand::CHANNEL0 | PITCommand::FULL_WORD | PITCommand::SQUARE_WAVE_GENERATOR);
+                }
+                pit.channel0.write(divisor);
+            }),
+        );
+
+        let _ = self.register(
+            "mm.alloc_strategy",
+            "free list allocator strategy: first, best or worst",
+            SysctlValue::Str(String::from("best")),
+            Some(|v| matches!(v.as_str(), Some("first" | "best" | "worst"))),
+            Some(|v| unsafe {
+                FREE_LIST_ALLOC.change_strategy(match v.as_str() {
+                    Some("first") => SearchStrategy::FIRST_FIT,
+                    Some("worst") => SearchStrategy::WORST_FIT,
+                    _ => SearchStrategy::BEST_FIT,
+                })
+            }),
+        );
+
+        let _ = self.register(
+            "kbd.repeat",
+            "PS/2 typematic byte: bits 0-4 rate, bits 5-6 delay",
+            SysctlValue::Int(0x0b),
+            Some(|v| matches!(v.as_int(), Some(0..=0x7f))),
+            Some(|v| {
+                // The keyboard interrupt handler would consume the acknowledges otherwise.
+                let acknowledged = critical_section!(|| unsafe {
+                    let mut ps2 = PS2::new();
+                    ps2.write_data_acknowledged(0xf3) && ps2.write_data_acknowledged(v.as_int().unwrap() as u8)
+                });
+                if !acknowledged {
+                    crate::warn!("The keyboard did not accept the typematic byte {:#x}.", v.as_int().unwrap());
+                }
+            }),
+        );
+
+        let _ = self.register(
+            "kernel.critical_warn_cycles",
+            "warn when interrupts stay disabled longer than this amount of TSC cycles (0 - off)",
+            SysctlValue::Int(DEFAULT_CRITICAL_WARN_CYCLES as i64),
+            Some(|v| matches!(v.as_int(), Some(0..))),
+            Some(|v| CRITICAL_WARN_CYCLES.store(v.as_int().unwrap() as u64, Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "kernel.double_fault",
+            "action after the double fault dump: halt, reboot or debug",
+            SysctlValue::Str(String::from("halt")),
+            Some(|v| v.as_str().and_then(DoubleFaultPolicy::from_name).is_some()),
+            Some(|v| v.as_str().and_then(DoubleFaultPolicy::from_name).unwrap().set()),
+        );
+
+        let _ = self.register(
+            "kernel.double_fault_reboot_secs",
+            "seconds between the double fault dump and the reboot with the reboot action",
+            SysctlValue::Int(DEFAULT_DOUBLE_FAULT_REBOOT_SECS as i64),
+            Some(|v| matches!(v.as_int(), Some(0..=3600))),
+            Some(|v| DOUBLE_FAULT_REBOOT_SECS.store(v.as_int().unwrap() as u32, Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "kernel.printk_ratelimit_cycles",
+            "length of the printk rate limiting interval in TSC cycles",
+            SysctlValue::Int(klog::DEFAULT_INTERVAL_CYCLES as i64),
+            Some(|v| matches!(v.as_int(), Some(1..))),
+            Some(|v| klog::INTERVAL_CYCLES.store(v.as_int().unwrap() as u64, Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "kernel.printk_ratelimit_burst",
+            "messages a single printk call site may print within the interval (0 - unlimited)",
+            SysctlValue::Int(klog::DEFAULT_BURST as i64),
+            Some(|v| matches!(v.as_int(), Some(0..=0xffff))),
+            Some(|v| klog::BURST.store(v.as_int().unwrap() as u32, Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "kernel.trace",
+            "comma separated trace categories to print: heap, paging, stacks, acpi, all or none",
+            SysctlValue::Str(String::from("none")),
+            Some(|v| v.as_str().and_then(klog::parse_trace_mask).is_some()),
+            Some(|v| klog::set_trace_mask(v.as_str().and_then(klog::parse_trace_mask).unwrap())),
+        );
+
+        let _ = self.register(
+            "kernel.log_level",
+            "most verbose level logged: off, error, warn, success, info, debug or trace",
+            SysctlValue::Str(String::from("trace")),
+            Some(|v| v.as_str().and_then(logging::parse_level).is_some()),
+            Some(|v| logging::set_max_level(v.as_str().and_then(logging::parse_level).unwrap())),
+        );
+
+        let _ = self.register(
+            "kernel.log_targets",
+            "comma separated target=level filters overriding the log level, i.e. memory::allocators=warn",
+            SysctlValue::Str(String::new()),
+            Some(|v| v.as_str().and_then(logging::parse_target_filters).is_some()),
+            Some(|v| logging::set_target_filters(v.as_str().and_then(logging::parse_target_filters).unwrap())),
+        );
+
+        let _ = self.register(
+            "kernel.log_serial",
+            "most verbose level written to the serial port, or off",
+            SysctlValue::Str(String::from(logging::DEFAULT_SERIAL_LEVEL.name())),
+            Some(|v| v.as_str().and_then(logging::parse_level).is_some()),
+            Some(|v| { let _ = logging::set_sink_level("serial", v.as_str().and_then(logging::parse_level).unwrap()); }),
+        );
+
+        let _ = self.register(
+            "mm.pressure_low",
+            "heap usage in percent starting from which the memory pressure is low",
+            SysctlValue::Int(pressure::DEFAULT_LOW_PERCENT as i64),
+            Some(|v| matches!(v.as_int(), Some(1..=100))),
+            Some(|v| pressure::LOW_PERCENT.store(v.as_int().unwrap() as u8, Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "mm.pressure_medium",
+            "heap usage in percent starting from which the memory pressure is medium",
+            SysctlValue::Int(pressure::DEFAULT_MEDIUM_PERCENT as i64),
+            Some(|v| matches!(v.as_int(), Some(1..=100))),
+            Some(|v| pressure::MEDIUM_PERCENT.store(v.as_int().unwrap() as u8, Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "mm.pressure_critical",
+            "heap usage in percent starting from which the memory pressure is critical",
+            SysctlValue::Int(pressure::DEFAULT_CRITICAL_PERCENT as i64),
+            Some(|v| matches!(v.as_int(), Some(1..=100))),
+            Some(|v| pressure::CRITICAL_PERCENT.store(v.as_int().unwrap() as u8, Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "mm.ksm",
+            "merge identical anonymous and read-only file pages in the background",
+            SysctlValue::Bool(false),
+            None,
+            Some(|v| ksm::KSM_ENABLED.store(v.as_bool().unwrap(), Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "mm.prezero_frames",
+            "free frames kept zeroed ahead of time for demand paging (0 - disabled)",
+            SysctlValue::Int(prezero::DEFAULT_POOL_TARGET as i64),
+            Some(|v| v.as_int().is_some_and(|n| (0..=prezero::POOL_CAPACITY as i64).contains(&n))),
+            Some(|v| prezero::POOL_TARGET.store(v.as_int().unwrap() as usize, Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "mm.scrub",
+            "read back the zeroed free frames to catch memory errors",
+            SysctlValue::Bool(false),
+            None,
+            Some(|v| prezero::SCRUB_ENABLED.store(v.as_bool().unwrap(), Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "mm.randomize_va_space",
+            "randomize the image base, stack top and heap start of loaded user programs",
+            SysctlValue::Bool(true),
+            None,
+            Some(|v| aslr::ASLR_ENABLED.store(v.as_bool().unwrap(), Ordering::Relaxed)),
+        );
+
+        let _ = self.register(
+            "cpufreq.governor",
+            "CPU frequency governor: ondemand, performance, powersave or manual",
+            SysctlValue::Str(String::from("ondemand")),
+            Some(|v| v.as_str().and_then(Governor::from_name).is_some()),
+            Some(|v| CPUFREQ.lock().set_governor(v.as_str().and_then(Governor::from_name).unwrap())),
+        );
+
+        let _ = self.register(
+            "cpufreq.manual_percent",
+            "performance level in percent used by the manual CPU frequency governor",
+            SysctlValue::Int(100),
+            Some(|v| matches!(v.as_int(), Some(0..=100))),
+            Some(|v| CPUFREQ.lock().set_manual_level(v.as_int().unwrap() as u8)),
+        );
+
+        let _ = self.register(
+            "cpufreq.up_threshold",
+            "load in percent above which the ondemand governor runs at the full performance",
+            SysctlValue::Int(DEFAULT_UP_THRESHOLD as i64),
+            Some(|v| matches!(v.as_int(), Some(1..=100))),
+            Some(|v| CPUFREQ.lock().set_up_threshold(v.as_int().unwrap() as u8)),
+        );
+
+        let _ = self.register(
+            "console.scrollback_pages",
+            "screens of output kept for Shift+PageUp on the screen logger (0 - disabled)",
+            SysctlValue::Int(vga_buffer::DEFAULT_SCROLLBACK_PAGES as i64),
+            Some(|v| v.as_int().is_some_and(|n| (0..=vga_buffer::MAX_SCROLLBACK_PAGES as i64).contains(&n))),
+            Some(|v| vga_buffer::set_scrollback_pages(v.as_int().unwrap() as usize)),
+        );
+
+        let _ = self.register(
+            "boot.hwinfo",
+            "print the hardware inventory report at boot",
+            SysctlValue::Bool(cfg!(debug_assertions)),
+            None,
+            None,
+        );
+    }
+}
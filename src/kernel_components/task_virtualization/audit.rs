@@ -0,0 +1,191 @@
+/// Syscall auditing of traced processes.
+///
+/// Tracing is enabled per process with [`trace`] or the "strace" shell command. The syscall
+/// dispatcher wraps every handler call into [`audited`], which records the syscall number, the
+/// arguments, the result and the amount of TSC cycles spent in the handler into a ring buffer
+/// of the last [`AUDIT_CAPACITY`] records. Once the buffer is full, the oldest records are
+/// overwritten and counted as dropped. Untraced processes only pay for a single atomic load.
+///
+/// The kernel has no syscall entry yet, so nothing calls [`audited`] for now. The records are
+/// meant to be consumed by an strace-like utility through [`drain`].
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::arch::x86_64::_rdtsc;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::kernel_components::sync::IrqSpinlock;
+
+/// Amount of records kept in the ring buffer.
+pub const AUDIT_CAPACITY: usize = 256;
+/// Amount of syscall arguments passed in registers.
+pub const SYSCALL_ARGS: usize = 6;
+
+/// Ring buffer of the audit records.
+static AUDIT_LOG: IrqSpinlock<AuditLog> = IrqSpinlock::new(AuditLog::new());
+/// Pids of the traced processes.
+static TRACED: IrqSpinlock<Vec<usize>> = IrqSpinlock::new(Vec::new());
+/// Amount of traced processes, checked before taking any lock.
+static TRACED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Single audited syscall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallRecord {
+    pub pid: usize,
+    pub tid: usize,
+    /// Syscall number.
+    pub number: u64,
+    pub args: [u64; SYSCALL_ARGS],
+    /// Value returned to the caller.
+    pub result: i64,
+    /// TSC value on the syscall entry.
+    pub timestamp: u64,
+    /// TSC cycles spent in the handler.
+    pub cycles: u64,
+}
+
+impl fmt::Display for SyscallRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}:{}] syscall {}(", self.pid, self.tid, self.number)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            write!(f, "{}{:#x}", if i == 0 { "" } else { ", " }, arg)?;
+        }
+        write!(f, ") = {} <{} cyc>", self.result, self.cycles)
+    }
+}
+
+/// Ring buffer of the syscall records.
+#[derive(Debug)]
+pub struct AuditLog {
+    records: VecDeque<SyscallRecord>,
+    /// Records overwritten before they were drained.
+    dropped: u64,
+}
+
+impl AuditLog {
+    /// Creates an empty log.
+    pub const fn new() -> Self {
+        Self { records: VecDeque::new(), dropped: 0 }
+    }
+
+    /// Appends the record, overwriting the oldest one if the log is full.
+    pub fn push(&mut self, record: SyscallRecord) {
+        if self.records.len() == AUDIT_CAPACITY {
+            self.records.pop_front();
+            self.dropped += 1;
+        }
+        self.records.push_back(record);
+    }
+
+    /// Amount of records overwritten before they were drained.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Iterates over the records from the oldest one.
+    pub fn records(&self) -> impl Iterator<Item = &SyscallRecord> {
+        self.records.iter()
+    }
+
+    /// Removes and returns the records of the process, or all records if no pid is provided.
+    pub fn drain(&mut self, pid: Option<usize>) -> Vec<SyscallRecord> {
+        let mut taken = Vec::new();
+        self.records.retain(|r| match pid.map_or(true, |pid| r.pid == pid) {
+            true => { taken.push(*r); false },
+            false => true,
+        });
+        taken
+    }
+
+    /// Removes all records and resets the dropped counter.
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.dropped = 0;
+    }
+}
+
+/// Enables or disables the auditing of the process.
+pub fn trace(pid: usize, enabled: bool) {
+    let mut traced = TRACED.lock();
+    match traced.iter().position(|&p| p == pid) {
+        Some(i) if !enabled => { traced.swap_remove(i); },
+        None if enabled => traced.push(pid),
+        _ => (),
+    }
+    TRACED_COUNT.store(traced.len(), Ordering::Relaxed);
+}
+
+/// Checks if the process is audited.
+#[inline]
+pub fn is_traced(pid: usize) -> bool {
+    TRACED_COUNT.load(Ordering::Relaxed) != 0 && TRACED.lock().contains(&pid)
+}
+
+/// Returns the pids of all audited processes.
+pub fn traced() -> Vec<usize> {
+    TRACED.lock().clone()
+}
+
+/// Calls the syscall handler and records the call if the process is audited.
+///
+/// Must be used by the syscall dispatcher around every handler.
+#[inline]
+pub fn audited<F>(pid: usize, tid: usize, number: u64, args: [u64; SYSCALL_ARGS], handler: F) -> i64 where
+    F: FnOnce() -> i64,
+{
+    if !is_traced(pid) {
+        return handler()
+    }
+
+    let timestamp = unsafe { _rdtsc() };
+    let result = handler();
+    let cycles = unsafe { _rdtsc() }.wrapping_sub(timestamp);
+    AUDIT_LOG.lock().push(SyscallRecord { pid, tid, number, args, result, timestamp, cycles });
+    result
+}
+
+/// Removes and returns the recorded syscalls of the process, or all records if no pid is
+/// provided.
+pub fn drain(pid: Option<usize>) -> Vec<SyscallRecord> {
+    AUDIT_LOG.lock().drain(pid)
+}
+
+/// Amount of records overwritten before they were drained.
+pub fn dropped() -> u64 {
+    AUDIT_LOG.lock().dropped()
+}
+
+/// Removes all records.
+pub fn clear() {
+    AUDIT_LOG.lock().clear()
+}
+
+#[test_case]
+fn audit_log_overwrites_oldest_records() {
+    let record = |pid, number| SyscallRecord {
+        pid, tid: 0, number, args: [0; SYSCALL_ARGS], result: 0, timestamp: 0, cycles: 0,
+    };
+
+    let mut log = AuditLog::new();
+    for number in 0..AUDIT_CAPACITY as u64 + 2 {
+        log.push(record(number as usize % 2, number));
+    }
+    assert_eq!(log.dropped(), 2);
+    assert_eq!(log.records().next().map(|r| r.number), Some(2));
+
+    let odd = log.drain(Some(1));
+    assert!(odd.iter().all(|r| r.pid == 1));
+    assert_eq!(odd.len() + log.records().count(), AUDIT_CAPACITY);
+
+    trace(usize::MAX, true);
+    assert_eq!(audited(usize::MAX, 1, 60, [1, 2, 3, 4, 5, 6], || -22), -22);
+    assert_eq!(audited(usize::MAX - 1, 1, 60, [0; SYSCALL_ARGS], || 0), 0);
+    trace(usize::MAX, false);
+    assert!(!is_traced(usize::MAX));
+
+    let records = drain(Some(usize::MAX));
+    assert_eq!(records.len(), 1);
+    assert_eq!((records[0].number, records[0].result, records[0].args[5]), (60, -22, 6));
+    assert!(drain(Some(usize::MAX - 1)).is_empty());
+}
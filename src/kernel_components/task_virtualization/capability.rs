@@ -0,0 +1,100 @@
+/// Capabilities of processes.
+///
+/// Every process carries a mask of capabilities, which are checked by privileged kernel services
+/// with [`require`] before touching the hardware on behalf of the process. Processes without a
+/// parent are started by the kernel itself and get every capability, child processes inherit the
+/// mask of their parent. A process may drop capabilities at any time, but only kernel level code
+/// may grant them back.
+///
+/// Code running outside of any scheduled process, like the early boot or interrupt handlers, is
+/// never restricted.
+
+use core::{error::Error, fmt::Display};
+
+use crate::kernel_components::arch_x86_64::PrivilegeLevel;
+use crate::kernel_components::task_virtualization::{Scheduler, ROUND_ROBIN, PROCESS_MANAGEMENT_UNIT};
+use crate::bitflags;
+
+bitflags! {
+    /// Privileged operations a process may perform.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Capability: u32 {
+        /// Direct access to IO ports and physical memory.
+        const RAW_IO = 1,
+        /// Loading and unloading of drivers.
+        const DRIVER_LOAD = 1 << 1,
+        /// Resetting and powering off the machine.
+        const REBOOT = 1 << 2,
+        /// Configuration of network interfaces.
+        const NET_ADMIN = 1 << 3,
+    };
+}
+
+/// Every capability, given to the processes started by the kernel.
+pub const KERNEL_CAPABILITIES: Capability = Capability::Custom(
+    Capability::RAW_IO.bits() | Capability::DRIVER_LOAD.bits() | Capability::REBOOT.bits() | Capability::NET_ADMIN.bits()
+);
+
+/// Errors of the capability checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapabilityError {
+    /// The process lacks the capability.
+    Missing(Capability),
+    /// Only kernel level code may grant capabilities.
+    PermissionDenied,
+    /// No such process.
+    NoSuchProcess,
+}
+
+impl Error for CapabilityError {}
+
+impl Display for CapabilityError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use CapabilityError::*;
+        match self {
+            Missing(cap) => write!(f, "The process lacks the capability {:#x}.", cap.bits()),
+            PermissionDenied => write!(f, "Only kernel level code may grant capabilities."),
+            NoSuchProcess => write!(f, "No such process."),
+        }
+    }
+}
+
+/// Returns true if every capability of the required mask is within the provided one.
+#[inline]
+pub fn contains(mask: Capability, required: Capability) -> bool {
+    mask.bits() & required.bits() == required.bits()
+}
+
+/// Checks whether the caller may change the capabilities from the current mask to the new one.
+///
+/// Dropping capabilities is always allowed.
+pub fn check_grant(current: Capability, new: Capability, caller: PrivilegeLevel) -> Result<(), CapabilityError> {
+    if !contains(current, new) && caller != PrivilegeLevel::KernelLevel {
+        return Err(CapabilityError::PermissionDenied)
+    }
+    Ok(())
+}
+
+/// Checks whether the currently scheduled process has the capability.
+///
+/// Always succeeds if no process is scheduled.
+pub fn require(cap: Capability) -> Result<(), CapabilityError> {
+    let Some(task) = (unsafe { ROUND_ROBIN.current().copied() }) else { return Ok(()) };
+    let mut list = unsafe { PROCESS_MANAGEMENT_UNIT.process_list.lock() };
+    match list.get(task.pid) {
+        Some(process) if !process.has_capability(cap) => Err(CapabilityError::Missing(cap)),
+        _ => Ok(()),
+    }
+}
+
+#[test_case]
+fn capabilities_are_only_dropped() {
+    let io = Capability::RAW_IO | Capability::REBOOT;
+    assert!(contains(KERNEL_CAPABILITIES, io));
+    assert!(contains(io, Capability::REBOOT));
+    assert!(!contains(io, Capability::DRIVER_LOAD));
+
+    assert_eq!(check_grant(io, Capability::REBOOT, PrivilegeLevel::UserLevel), Ok(()));
+    assert_eq!(check_grant(io, KERNEL_CAPABILITIES, PrivilegeLevel::SystemLevel), Err(CapabilityError::PermissionDenied));
+    assert_eq!(check_grant(io, KERNEL_CAPABILITIES, PrivilegeLevel::KernelLevel), Ok(()));
+}
@@ -0,0 +1,306 @@
+/// Idle loop with a power management governor.
+///
+/// When no task has anything to do, the processor is put into one of the idle states known to the
+/// governor. HLT is always available. MWAIT states are added if the processor supports them, and
+/// deeper C-states described by the ACPI _CST object can be added with
+/// [`IdleGovernor::add_cst`].
+///
+/// Deeper states save more power, but take longer to leave and are only worth it if the processor
+/// stays idle long enough. The governor predicts the length of the next idle period from the
+/// recent idle periods, bounded by the next timer event if the timer code announced one with
+/// [`IdleGovernor::set_next_event`], and picks the deepest state whose target residency fits into
+/// the prediction and whose exit latency stays within the latency limit.
+///
+/// Under virtualization HLT and MWAIT exit to the host, so the host CPU is released as well
+/// instead of being burned by a spinning guest.
+
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count, _rdtsc};
+
+use crate::kernel_components::arch_x86_64::interrupts::interrupt;
+use crate::kernel_components::arch_x86_64::ports::Port;
+use crate::kernel_components::memory::deferred;
+use crate::kernel_components::sync::Mutex;
+use crate::critical_section;
+
+/// MONITOR/MWAIT support bit in CPUID leaf 1 ECX.
+const CPUID_MONITOR: u32 = 1 << 3;
+/// Enumeration of MWAIT extensions bit in CPUID leaf 5 ECX.
+const CPUID_MWAIT_EXTENSIONS: u32 = 1 << 0;
+/// Frequency assumed when the processor does not report the TSC frequency.
+const DEFAULT_TSC_MHZ: u64 = 1000;
+/// Weight of the newest idle period in the prediction, as a power of two.
+const PREDICTION_SHIFT: u32 = 3;
+
+/// Exit latency and target residency in microseconds of the MWAIT C-states, starting with C1.
+const MWAIT_LATENCIES: [(u32, u32); 7] = [
+    (1, 2), (10, 20), (80, 200), (100, 300), (150, 500), (200, 800), (250, 1000),
+];
+
+/// Idle governor of the bootstrap processor.
+pub static IDLE_GOVERNOR: Mutex<IdleGovernor> = Mutex::new(IdleGovernor::new());
+
+/// The way an idle state is entered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleMethod {
+    /// HLT instruction.
+    Halt,
+    /// MWAIT instruction with the provided C-state hint.
+    Mwait { hint: u32 },
+    /// Read of the ACPI P_LVLx port.
+    IoPort { port: u16 },
+}
+
+/// Single idle state known to the governor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdleState {
+    /// Short name of the state, like "C1".
+    pub name: &'static str,
+    /// How the state is entered.
+    pub method: IdleMethod,
+    /// Time needed to leave the state in microseconds.
+    pub exit_latency: u32,
+    /// Minimal idle time in microseconds, for which the state saves power.
+    pub target_residency: u32,
+}
+
+/// Register of a _CST entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CstRegister {
+    /// Functional fixed hardware, which is MWAIT with the provided hint on x86.
+    FixedHardware { hint: u32 },
+    /// System IO port, the state is entered by reading it.
+    SystemIo { port: u16 },
+}
+
+/// Single C-state described by the ACPI _CST object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CstEntry {
+    /// Register used to enter the state.
+    pub register: CstRegister,
+    /// C-state type, 1 to 3.
+    pub typ: u8,
+    /// Worst case exit latency in microseconds.
+    pub latency: u16,
+}
+
+/// Idle governor.
+#[derive(Debug)]
+pub struct IdleGovernor {
+    /// Known states sorted from the shallowest one.
+    states: Vec<IdleState>,
+    /// Amount of times each state was entered.
+    usage: Vec<u64>,
+    /// Predicted length of the next idle period in microseconds.
+    predicted: u64,
+    /// Total time spent idle in microseconds.
+    idle_time: u64,
+    /// TSC deadline of the next timer event.
+    next_event: Option<u64>,
+    /// Maximal exit latency allowed in microseconds.
+    latency_limit: u32,
+    tsc_mhz: u64,
+}
+
+impl IdleGovernor {
+    /// Creates a governor without any states. HLT is used until [`IdleGovernor::detect`] is called.
+    pub const fn new() -> Self {
+        Self {
+            states: Vec::new(),
+            usage: Vec::new(),
+            predicted: 0,
+            idle_time: 0,
+            next_event: None,
+            latency_limit: u32::MAX,
+            tsc_mhz: DEFAULT_TSC_MHZ,
+        }
+    }
+
+    /// Detects the TSC frequency and the idle states supported by the processor.
+    pub fn detect(&mut self) {
+        self.tsc_mhz = tsc_mhz().unwrap_or(DEFAULT_TSC_MHZ);
+        self.add_state(IdleState { name: "HLT", method: IdleMethod::Halt, exit_latency: 1, target_residency: 1 });
+
+        if unsafe { __cpuid(0) }.eax < 5 || unsafe { __cpuid(1) }.ecx & CPUID_MONITOR == 0 {
+            return
+        }
+        let leaf = unsafe { __cpuid(5) };
+        if leaf.ecx & CPUID_MWAIT_EXTENSIONS == 0 {
+            return
+        }
+
+        // EDX holds the amount of sub-states of each C-state in nibbles, starting with C0.
+        const NAMES: [&str; 7] = ["C1", "C2", "C3", "C4", "C5", "C6", "C7"];
+        for (cstate, &(exit_latency, target_residency)) in MWAIT_LATENCIES.iter().enumerate() {
+            if leaf.edx >> ((cstate + 1) * 4) & 0xf != 0 {
+                self.add_state(IdleState {
+                    name: NAMES[cstate],
+                    method: IdleMethod::Mwait { hint: (cstate as u32) << 4 },
+                    exit_latency,
+                    target_residency,
+                });
+            }
+        }
+    }
+
+    /// Adds the C-states described by the ACPI _CST object.
+    ///
+    /// The target residency of those is estimated as three times the exit latency.
+    pub fn add_cst(&mut self, entries: &[CstEntry]) {
+        const NAMES: [&str; 3] = ["ACPI C1", "ACPI C2", "ACPI C3"];
+        for entry in entries.iter().filter(|entry| matches!(entry.typ, 1..=3)) {
+            let method = match entry.register {
+                CstRegister::FixedHardware { hint } => IdleMethod::Mwait { hint },
+                CstRegister::SystemIo { port } => IdleMethod::IoPort { port },
+            };
+            self.add_state(IdleState {
+                name: NAMES[entry.typ as usize - 1],
+                method,
+                exit_latency: entry.latency as u32,
+                target_residency: entry.latency as u32 * 3,
+            });
+        }
+    }
+
+    /// Adds the state, keeping the states sorted by their target residency.
+    pub fn add_state(&mut self, state: IdleState) {
+        let at = self.states.partition_point(|s| s.target_residency <= state.target_residency);
+        self.states.insert(at, state);
+        self.usage.insert(at, 0);
+    }
+
+    /// Returns the known states sorted from the shallowest one, together with the amount of
+    /// times each one was entered.
+    pub fn states(&self) -> impl Iterator<Item = (&IdleState, u64)> {
+        self.states.iter().zip(self.usage.iter().copied())
+    }
+
+    /// Returns the total time spent idle in microseconds. Used as the load statistics by the
+    /// frequency governor.
+    pub fn idle_time(&self) -> u64 {
+        self.idle_time
+    }
+
+    /// Returns the TSC frequency in MHz used by the governor.
+    pub fn tsc_mhz(&self) -> u64 {
+        self.tsc_mhz
+    }
+
+    /// Limits the exit latency of the chosen states in microseconds.
+    pub fn set_latency_limit(&mut self, limit: u32) {
+        self.latency_limit = limit;
+    }
+
+    /// Announces the TSC deadline of the next timer event, which bounds the next idle period.
+    pub fn set_next_event(&mut self, deadline: Option<u64>) {
+        self.next_event = deadline;
+    }
+
+    /// Returns the predicted length of the next idle period in microseconds.
+    pub fn predicted(&self) -> u64 {
+        let until_event = self.next_event.map(|deadline| deadline.saturating_sub(unsafe { _rdtsc() }) / self.tsc_mhz);
+        until_event.map_or(self.predicted, |until| until.min(self.predicted))
+    }
+
+    /// Selects the state for the predicted idle length in microseconds. None if no state is
+    /// known.
+    pub fn select(&self, predicted: u64) -> Option<usize> {
+        let fitting = self.states.iter().rposition(|state| {
+            state.target_residency as u64 <= predicted && state.exit_latency <= self.latency_limit
+        });
+        fitting.or((!self.states.is_empty()).then_some(0))
+    }
+
+    /// Updates the prediction with the measured idle period in microseconds.
+    fn update(&mut self, state: usize, idle: u64) {
+        self.usage[state] += 1;
+        self.idle_time += idle;
+        self.predicted = self.predicted - (self.predicted >> PREDICTION_SHIFT) + (idle >> PREDICTION_SHIFT);
+    }
+}
+
+/// Enters the idle state selected by the governor and returns after the next interrupt.
+///
+/// Interrupts are enabled on return.
+pub fn idle() {
+    deferred::quiescent();
+    let selected = critical_section!(|| {
+        let governor = IDLE_GOVERNOR.lock();
+        governor.select(governor.predicted()).map(|index| (index, governor.states[index].method, governor.tsc_mhz))
+    });
+    let Some((index, method, tsc_mhz)) = selected else {
+        return interrupt::wait_for_interrupt()
+    };
+
+    let start = unsafe { _rdtsc() };
+    unsafe { enter(method) };
+    let idle = unsafe { _rdtsc() }.wrapping_sub(start) / tsc_mhz;
+
+    critical_section!(|| IDLE_GOVERNOR.lock().update(index, idle));
+}
+
+/// Idle loop of the processor. Never returns.
+pub fn idle_loop() -> ! {
+    loop {
+        idle();
+    }
+}
+
+/// Enters the idle state with interrupts enabled.
+unsafe fn enter(method: IdleMethod) {
+    match method {
+        IdleMethod::Halt => interrupt::wait_for_interrupt(),
+        IdleMethod::Mwait { hint } => {
+            // Any write to the monitored line wakes the processor as well, the stack is good enough.
+            let line = 0u64;
+            asm!("monitor", in("rax") &line as *const u64, in("ecx") 0, in("edx") 0, options(nostack));
+            // STI takes effect after the next instruction, so no interrupt is lost in between.
+            asm!("sti", "mwait", in("eax") hint, in("ecx") 0, options(nomem, nostack));
+        },
+        IdleMethod::IoPort { port } => {
+            interrupt::enable();
+            u8::read(port);
+        },
+    }
+}
+
+/// Returns the TSC frequency in MHz reported by the processor or the hypervisor.
+fn tsc_mhz() -> Option<u64> {
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    if max_leaf >= 0x15 {
+        let leaf = unsafe { __cpuid_count(0x15, 0) };
+        if leaf.eax != 0 && leaf.ecx != 0 {
+            return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64 / 1_000_000)
+        }
+    }
+    if max_leaf >= 0x16 && unsafe { __cpuid(0x16) }.eax != 0 {
+        return Some(unsafe { __cpuid(0x16) }.eax as u64)
+    }
+    // Timing leaf of KVM and VMware reports the frequency in kHz.
+    let hypervisor = unsafe { __cpuid(0x4000_0000) }.eax;
+    if hypervisor >= 0x4000_0010 {
+        let khz = unsafe { __cpuid(0x4000_0010) }.eax as u64;
+        return (khz >= 1000).then_some(khz / 1000)
+    }
+    None
+}
+
+#[test_case]
+fn deepest_fitting_state_is_selected() {
+    let mut governor = IdleGovernor::new();
+    governor.add_state(IdleState { name: "HLT", method: IdleMethod::Halt, exit_latency: 1, target_residency: 1 });
+    governor.add_cst(&[
+        CstEntry { register: CstRegister::SystemIo { port: 0x415 }, typ: 3, latency: 100 },
+        CstEntry { register: CstRegister::FixedHardware { hint: 0x10 }, typ: 2, latency: 20 },
+    ]);
+
+    let names: Vec<_> = governor.states().map(|(state, _)| state.name).collect();
+    assert_eq!(names, ["HLT", "ACPI C2", "ACPI C3"]);
+    assert_eq!(governor.select(0), Some(0));
+    assert_eq!(governor.select(100), Some(1));
+    assert_eq!(governor.select(10_000), Some(2));
+
+    governor.set_latency_limit(50);
+    assert_eq!(governor.select(10_000), Some(1));
+}
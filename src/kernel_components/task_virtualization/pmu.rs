@@ -6,12 +6,17 @@ use crate::kernel_components::sync::Mutex;
 use crate::kernel_components::arch_x86_64::controllers::PROGRAMMABLE_INTERRUPT_CONTROLLER;
 
 use crate::single;
+use crate::kernel_components::arch_x86_64::PrivilegeLevel;
+use super::priority::PriorityError;
+use super::capability::{Capability, CapabilityError};
 use super::{Process, ProcState, Task, Thread, ThreadState, Scheduler, ROUND_ROBIN, PRIORITY_SCHEDULER};
+use super::workqueue;
 
 use core::arch::asm;
 use core::alloc::{GlobalAlloc, Allocator, Layout};
 use core::mem::{self, MaybeUninit, ManuallyDrop};
 use core::ptr::{self, NonNull};
+use core::ops::RangeInclusive;
 
 /// The main static structure, that contain all processes in the system
 single! {
@@ -27,6 +32,8 @@ pub struct PMU<'a> {
     pub process_list: Mutex<PMUList>,
     // The queue which adds new processes to the process list.
     process_queue: ConcurrentQueue<Process<'a>>,
+    // Finished processes removed from the list, whose memory is not freed yet.
+    zombies: ConcurrentQueue<Process<'a>>,
 }
 
 impl<'a> PMU<'a> {
@@ -38,6 +45,7 @@ impl<'a> PMU<'a> {
         Self {
             process_list: Mutex::new(PMUList::new(unsafe{ &mut GLOBAL_ALLOCATOR })),
             process_queue: ConcurrentQueue::new(unsafe{ &mut GLOBAL_ALLOCATOR }),
+            zombies: ConcurrentQueue::new(unsafe{ &mut GLOBAL_ALLOCATOR }),
         }
     }
 
@@ -75,9 +83,14 @@ impl<'a> PMU<'a> {
     /// Works as a wrapper for process_list.lock().remove_proc(pid)
     /// # Note
     ///
-    /// This function does not always remove the process.
+    /// This function does not always remove the process. A removed process still owns it's
+    /// memory, which is freed later by the worker thread, see [`PMU::reap`].
     pub fn remove(&mut self, pid: usize) -> Result<(), ()> {
-        self.process_list.lock().remove_proc(pid)
+        let removed = self.process_list.lock().remove_proc(pid)?;
+        if let Some(proc) = removed {
+            self.bury(proc);
+        }
+        Ok(())
     }
 
     /// Does something as the chosen process and then removes it. This function is frees the heap
@@ -93,7 +106,129 @@ impl<'a> PMU<'a> {
     pub fn do_then_remove<F, T>(&mut self, pid: usize, fun: F) -> Result<T, ()> where
         F: FnOnce(&mut Process) -> T
     {
-        self.process_list.lock().do_then_remove_proc(pid, fun)
+        let (out, removed) = self.process_list.lock().do_then_remove_proc(pid, fun)?;
+        if let Some(proc) = removed {
+            self.bury(proc);
+        }
+        Ok(out)
+    }
+
+    /// Queues the removed process to be torn down by the worker thread.
+    ///
+    /// The process cannot be torn down right away, because it is removed by it's own last thread,
+    /// which still runs on the stack of the process. The worker is scheduled only after that
+    /// thread was switched away for good.
+    fn bury(&mut self, proc: Process<'a>) {
+        self.zombies.enqueue(proc);
+        // A full workqueue only delays the teardown until the next removal.
+        workqueue::schedule(reap_zombies);
+    }
+
+    /// Frees the memory of every removed process: mappings, page tables and stacks.
+    ///
+    /// Returns the amount of processes torn down.
+    pub fn reap(&mut self) -> usize {
+        let mut reaped = 0;
+        while let Some(mut proc) = self.zombies.dequeue() {
+            unsafe { proc.teardown() };
+            reaped += 1;
+        }
+        reaped
+    }
+
+    /// Changes the priority of the process with the provided pid.
+    pub fn set_priority(&mut self, pid: usize, priority: u8, caller: PrivilegeLevel) -> Result<(), PriorityError> {
+        self.process_list.lock()
+            .get_mut(pid)
+            .ok_or(PriorityError::NoSuchTask)?
+            .set_priority(priority, caller)
+    }
+
+    /// Changes the nice value of the thread within the process with the provided pid.
+    pub fn set_nice(&mut self, pid: usize, tid: usize, nice: i8, caller: PrivilegeLevel) -> Result<(), PriorityError> {
+        self.process_list.lock()
+            .get_mut(pid)
+            .and_then(|proc| proc.find_thread_mut(tid))
+            .ok_or(PriorityError::NoSuchTask)?
+            .set_nice(nice, caller)
+    }
+
+    /// Replaces the capabilities of the process with the provided pid.
+    pub fn set_capabilities(&mut self, pid: usize, caps: Capability, caller: PrivilegeLevel) -> Result<(), CapabilityError> {
+        self.process_list.lock()
+            .get_mut(pid)
+            .ok_or(CapabilityError::NoSuchProcess)?
+            .set_capabilities(caps, caller)
+    }
+
+    /// Allows the process with the provided pid to access the range of I/O ports directly.
+    ///
+    /// See [`Process::grant_io_ports`].
+    pub fn grant_io_ports(&mut self, pid: usize, ports: RangeInclusive<u16>) -> Result<(), CapabilityError> {
+        self.process_list.lock()
+            .get_mut(pid)
+            .ok_or(CapabilityError::NoSuchProcess)?
+            .grant_io_ports(ports)
+    }
+
+    /// Kills the process with the provided pid after a fatal exception in one of it's threads.
+    ///
+    /// See [`Process::kill_faulted`]. The memory of the process is unmapped by the worker thread.
+    pub fn kill_faulted(&mut self, pid: usize) -> Result<(), ()> {
+        self.process_list.lock()
+            .get_mut(pid)
+            .ok_or(())?
+            .kill_faulted();
+        // A full workqueue only delays the unmapping until the next fault or removal.
+        workqueue::schedule(unmap_faulted);
+        Ok(())
+    }
+
+    /// Unmaps the memory of every process killed by a fault.
+    ///
+    /// Returns the amount of unmapped processes.
+    pub fn unmap_faulted(&mut self) -> usize {
+        let mut list = self.process_list.lock();
+        let pids: alloc::vec::Vec<usize> = list.iter().map(|proc| proc.pid).collect();
+        let mut unmapped = 0;
+        for pid in pids {
+            if list.get_mut(pid).is_some_and(|proc| proc.unmap_faulted()) {
+                unmapped += 1;
+            }
+        }
+        unmapped
+    }
+
+    /// Writes the dirty pages of the file mappings of every process back to their files.
+    ///
+    /// Returns the amount of written pages.
+    pub fn sync_all(&mut self) -> usize {
+        use crate::kernel_components::memory::MEMORY_MANAGEMENT_UNIT;
+
+        let mut list = self.process_list.lock();
+        let pids: alloc::vec::Vec<usize> = list.iter().map(|proc| proc.pid).collect();
+        let mut written = 0;
+        for pid in pids {
+            if let Some(proc) = list.get_mut(pid) {
+                written += proc.address_space.sync_all(unsafe { &mut MEMORY_MANAGEMENT_UNIT });
+            }
+        }
+        written
+    }
+
+    /// Prints every process in the list together with it's priority, capabilities, threads and
+    /// state.
+    pub fn dump_tasks(&self) {
+        crate::println!("{:>5} {:>4} {:>4} {:>8}  {}", "PID", "PRI", "CAPS", "THREADS", "STATE");
+        for proc in self.process_list.lock().iter() {
+            crate::println!(
+                "{:>5} {:>4} {:>4x} {:>8}  {:?}",
+                proc.pid, proc.priority, proc.capabilities.bits(), proc.threads.len(), proc.proc_state
+            );
+            for thread in proc.threads.iter().filter(|t| t.name.is_some()) {
+                crate::println!("{:>5} {:>4} {:>4} {:>8}  {}", "", "", "", thread.tid, thread.name().unwrap_or_default());
+            }
+        }
     }
 }
 
@@ -170,52 +305,36 @@ impl<A: Allocator> PMUList<A> {
             }
         }
 
-        self.len.saturating_add(1);
+        self.len += 1;
     }
 
     /// Removes the process from the list based my it's pid.
     ///
+    /// Only a process in the FINAL state is unlinked. It is returned with it's ownership, as it's
+    /// memory must still be freed by the caller.
+    ///
     /// # Note
     ///
-    /// This function will return Ok(()) if it will manage to find the desired process under the
+    /// This function will return Ok(_) if it will manage to find the desired process under the
     /// provided pid, not necessary to remove it.
-    pub fn remove_proc(&mut self, pid: usize) -> Result<(), ()> {
-        let mut next = self.head;
-
-        while let Some(node) = unsafe { (next as *mut PMUListNode).as_mut() } {
-            // Searching for the process.
-            if node.process.pid == pid {
-                // Doing some changes based on the current state of the process.
-                match node.process.proc_state {
-                    ProcState::FINAL => {
-                        node.node_dealloc(self.alloc);
-                        self.len.saturating_sub(1);
-                    },
-                    _ => (),
-                }
-                
-                return Ok(())
-            }
-
-            next = node.next;
-        }
-
-        Err(())
+    pub fn remove_proc<'p>(&mut self, pid: usize) -> Result<Option<Process<'p>>, ()> {
+        self.do_then_remove_proc(pid, |_| ()).map(|((), removed)| removed)
     }
 
     /// Does something as a process and then removes it from the list.
     ///
     /// The provided function can be anything. The main idea is to do something as a process before
     /// the potential possibility of being cleared out. If the function returns T, the T will be an
-    /// output of the returned Result.
+    /// output of the returned Result, together with the process if it was unlinked.
     ///
     /// # Note
     ///
-    /// This function will return Ok(T) if it will manage to find the desired process under the
+    /// This function will return Ok(_) if it will manage to find the desired process under the
     /// provided pid, not necessary to remove it.
-    pub fn do_then_remove_proc<F, T>(&mut self, pid: usize, fun: F) -> Result<T, ()> where
+    pub fn do_then_remove_proc<'p, F, T>(&mut self, pid: usize, fun: F) -> Result<(T, Option<Process<'p>>), ()> where
         F: FnOnce(&mut Process<'_>) -> T
     {
+        let mut prev: *mut PMUListNode = ptr::null_mut();
         let mut next = self.head;
 
         while let Some(node) = unsafe { (next as *mut PMUListNode).as_mut() } {
@@ -224,19 +343,22 @@ impl<A: Allocator> PMUList<A> {
                 // Running the requested function
                 let out = fun(node.get_proc_mut());
 
-                // Doing some changes based on the current state of the process.
-                match node.process.proc_state {
-                    ProcState::FINAL => {
-                        node.node_dealloc(self.alloc);
-                        self.len.saturating_sub(1);
-                    },
-                    _ => (),
+                // Only finished processes leave the list.
+                if node.process.proc_state != ProcState::FINAL {
+                    return Ok((out, None))
+                }
+
+                match unsafe { prev.as_mut() } {
+                    Some(prev) => prev.next = node.next,
+                    None => self.head = node.next,
                 }
-                
-                return Ok(out)
+                self.len = self.len.saturating_sub(1);
+
+                return Ok((out, Some(unsafe { PMUListNode::node_take(node, self.alloc) })))
             }
 
             next = node.next;
+            prev = node;
         }
 
         Err(())
@@ -246,6 +368,36 @@ impl<A: Allocator> PMUList<A> {
     pub const fn len(&self) -> usize {
         self.len
     }
+
+    /// Calls the closure with every process within the list as a mutable reference.
+    pub fn for_each_mut<F>(&mut self, mut f: F) where F: FnMut(&mut Process) {
+        let mut next = self.head;
+        while let Some(node) = unsafe { (next as *mut PMUListNode).as_mut() } {
+            f(node.get_proc_mut());
+            next = node.next;
+        }
+    }
+
+    /// Returns an iterator over all processes within the list.
+    pub fn iter(&self) -> PMUListIter<'_> {
+        PMUListIter { next: self.head, _phantom: core::marker::PhantomData }
+    }
+}
+
+/// Iterator over the processes of PMUList.
+pub struct PMUListIter<'a> {
+    next: usize,
+    _phantom: core::marker::PhantomData<&'a Process<'a>>,
+}
+
+impl<'a> Iterator for PMUListIter<'a> {
+    type Item = &'a Process<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = unsafe { (self.next as *const PMUListNode<'a>).as_ref() }?;
+        self.next = node.next;
+        Some(node.get_proc())
+    }
 }
 
 struct PMUListNode<'a> {
@@ -288,17 +440,24 @@ impl<'a> PMUListNode<'a> {
         ptr
     }
 
-    // Deallocates the node at given ptr
-    fn node_dealloc<A>(&mut self, alloc: &mut A) where A: Allocator {
+    /// Deallocates the unlinked node and returns the process within with it's ownership.
+    unsafe fn node_take<A>(node: *mut Self, alloc: &mut A) -> Process<'a> where A: Allocator {
         let content_size = mem::size_of::<Self>();
         let content_align = mem::align_of::<Self>();
         let layout = Layout::from_size_align(content_size, content_align).unwrap();
 
-        self.process.threads.clear();
-
-        unsafe { alloc.deallocate(
-            NonNull::new(self as *mut Self as *mut u8).unwrap(),
-            layout) 
-        }
+        let process = ptr::read(&(*node).process);
+        alloc.deallocate(NonNull::new(node as *mut u8).unwrap(), layout);
+        process
     }
 }
+
+/// Work item of the worker thread, which tears down the removed processes.
+fn reap_zombies() {
+    unsafe { PROCESS_MANAGEMENT_UNIT.reap(); }
+}
+
+/// Work item of the worker thread, which unmaps the memory of the processes killed by a fault.
+fn unmap_faulted() {
+    unsafe { PROCESS_MANAGEMENT_UNIT.unmap_faulted(); }
+}
@@ -0,0 +1,119 @@
+/// Process priorities and thread nice values.
+///
+/// Every process has a priority from 0 to 127, where 0 is the most significant process. It is
+/// used by the priority based scheduler to choose the next process. Every thread additionally has
+/// a nice value from -20 to 19, which orders threads of equally prioritized processes and is
+/// converted into a weight for fair share scheduling.
+///
+/// # Privileges
+///
+/// Code running in the kernel or system rings may set any value. Less privileged callers are only
+/// allowed to make their tasks less significant, so they can never starve the rest of the system.
+
+use core::{error::Error, fmt::Display};
+
+use crate::kernel_components::arch_x86_64::PrivilegeLevel;
+
+/// The most significant process priority.
+pub const PRIORITY_HIGHEST: u8 = 0;
+/// The least significant process priority.
+pub const PRIORITY_LOWEST: u8 = 127;
+/// The lowest nice value (the most favorable scheduling).
+pub const NICE_MIN: i8 = -20;
+/// The highest nice value (the least favorable scheduling).
+pub const NICE_MAX: i8 = 19;
+/// Weight of the thread with nice value of 0.
+pub const NICE_0_WEIGHT: u32 = 1024;
+
+/// Weights of nice values from -20 to 19. Each nice level changes the share of the processor by
+/// about 10 percent.
+const NICE_TO_WEIGHT: [u32; 40] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548,  7620,  6100,  4904,  3906,
+    3121,  2501,  1991,  1586,  1277,
+    1024,  820,   655,   526,   423,
+    335,   272,   215,   172,   137,
+    110,   87,    70,    56,    45,
+    36,    29,    23,    18,    15,
+];
+
+/// Errors that might occur when changing priorities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriorityError {
+    /// The priority is not within 0..=127.
+    InvalidPriority(u8),
+    /// The nice value is not within -20..=19.
+    InvalidNice(i8),
+    /// The caller is only allowed to lower the significance of the task.
+    PermissionDenied,
+    /// No such process or thread.
+    NoSuchTask,
+}
+
+impl Error for PriorityError {}
+
+impl Display for PriorityError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use PriorityError::*;
+        match self {
+            InvalidPriority(p) => write!(f, "Priority {} is out of range {}..={}.", p, PRIORITY_HIGHEST, PRIORITY_LOWEST),
+            InvalidNice(n) => write!(f, "Nice value {} is out of range {}..={}.", n, NICE_MIN, NICE_MAX),
+            PermissionDenied => write!(f, "Only privileged code may raise the priority of a task."),
+            NoSuchTask => write!(f, "No such process or thread."),
+        }
+    }
+}
+
+/// Returns true if the caller may make tasks more significant.
+#[inline]
+fn may_raise(caller: PrivilegeLevel) -> bool {
+    caller <= PrivilegeLevel::SystemLevel
+}
+
+/// Checks whether the caller is allowed to change the process priority.
+pub fn check_priority(current: u8, new: u8, caller: PrivilegeLevel) -> Result<(), PriorityError> {
+    if new > PRIORITY_LOWEST {
+        return Err(PriorityError::InvalidPriority(new))
+    }
+    // Lower numbers are more significant.
+    if new < current && !may_raise(caller) {
+        return Err(PriorityError::PermissionDenied)
+    }
+    Ok(())
+}
+
+/// Checks whether the caller is allowed to change the nice value of a thread.
+pub fn check_nice(current: i8, new: i8, caller: PrivilegeLevel) -> Result<(), PriorityError> {
+    if !(NICE_MIN..=NICE_MAX).contains(&new) {
+        return Err(PriorityError::InvalidNice(new))
+    }
+    if new < current && !may_raise(caller) {
+        return Err(PriorityError::PermissionDenied)
+    }
+    Ok(())
+}
+
+/// Converts the nice value into the scheduling weight.
+///
+/// Values outside of the valid range are clamped.
+pub fn nice_to_weight(nice: i8) -> u32 {
+    NICE_TO_WEIGHT[(nice.clamp(NICE_MIN, NICE_MAX) - NICE_MIN) as usize]
+}
+
+#[test_case]
+fn priority_bounds_per_privilege() {
+    use PrivilegeLevel::*;
+
+    assert_eq!(check_priority(10, 0, KernelLevel), Ok(()));
+    assert_eq!(check_priority(10, 20, UserLevel), Ok(()));
+    assert_eq!(check_priority(10, 5, UserLevel), Err(PriorityError::PermissionDenied));
+    assert_eq!(check_priority(10, 128, KernelLevel), Err(PriorityError::InvalidPriority(128)));
+
+    assert_eq!(check_nice(0, -20, SystemLevel), Ok(()));
+    assert_eq!(check_nice(0, -1, DriverLevel), Err(PriorityError::PermissionDenied));
+    assert_eq!(check_nice(0, 20, KernelLevel), Err(PriorityError::InvalidNice(20)));
+
+    assert_eq!(nice_to_weight(0), NICE_0_WEIGHT);
+    assert!(nice_to_weight(-20) > nice_to_weight(19));
+}
@@ -9,6 +9,7 @@ use crate::kernel_components::structures::thread_safe::ConcurrentList;
 use crate::kernel_components::sync::Mutex;
 use crate::{single, GLOBAL_ALLOCATOR};
 
+use super::priority;
 use super::{Scheduler, Task, Thread, ThreadFn, Process, PROCESS_MANAGEMENT_UNIT};
 
 single! {
@@ -21,6 +22,9 @@ single! {
 /// affect individual threads. If a high priority process have only one thread
 /// to execute, it will not schedule until the process is done executing.
 ///
+/// Threads of equally prioritized processes are ordered by their nice values. Tasks that are
+/// equally significant are served in turns.
+///
 /// This is a more RTOS styled scheduling algorithm.
 pub struct PriorityScheduler {
     /// Index of the current running task
@@ -78,31 +82,32 @@ impl Scheduler for PriorityScheduler {
             index = 0
         }
 
-        if let Some(mut current_task) = self.list.get(index) {
-            let mut pri = u8::MAX;
-
-            for (i, task) in self.list.iter().enumerate() {
-                unsafe {
-                    if let Some(proc) = PROCESS_MANAGEMENT_UNIT.process_list.lock().get(task.pid) {
-                        if proc.priority < pri {
-                            current_task = task;
-                            index = i;
-                            pri = proc.priority;
-                        } else if proc.priority == 0 {
-                            current_task = task;
-                            index = i;
+        let len = self.list.len();
+        let mut pri = (u8::MAX, i8::MAX);
+
+        // The search starts right after the current task, so equally significant tasks are served
+        // in turns instead of starving all but the first one.
+        for i in (index..len).chain(0..index) {
+            let Some(task) = self.list.get(i) else { continue };
+
+            unsafe {
+                if let Some(proc) = PROCESS_MANAGEMENT_UNIT.process_list.lock().get(task.pid) {
+                    let nice = proc.find_thread(task.tid).map_or(0, Thread::nice);
+
+                    if (proc.priority, nice) < pri {
+                        index = i;
+                        pri = (proc.priority, nice);
+
+                        if pri == (0, priority::NICE_MIN) {
                             break
                         }
                     }
                 }
             }
-
-            self.current_task.store(index, Ordering::Release);
-
-            return Some(current_task)
         }
- 
-        None
+
+        self.current_task.store(index, Ordering::Release);
+        self.list.get(index)
     }
 
     unsafe fn clear(&mut self) {
@@ -2,20 +2,27 @@
 
 use super::{join_handle::{ThreadOutput, JoinHandle, WriterReference}, PRIORITY_SCHEDULER, ROUND_ROBIN, PROCESS_MANAGEMENT_UNIT};
 use super::thread::{Thread, ThreadFn};
+use super::priority::{self, PriorityError};
+use super::capability::{self, Capability, CapabilityError};
+use super::thread_builder::DEFAULT_STACK_PAGES;
 
 use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::vec::Vec;
 
 use core::sync::atomic::{AtomicUsize, Ordering};
-use core::ops::{Deref, DerefMut, Drop};
+use core::ops::{Deref, DerefMut, Drop, RangeInclusive};
 use core::borrow::BorrowMut;
 use core::fmt::Debug;
 use core::any::Any;
 use core::mem;
 
 use crate::{GLOBAL_ALLOCATOR, critical_section};
-use crate::kernel_components::arch_x86_64::{RdRand, RdSeed};
+use crate::kernel_components::arch_x86_64::{RdRand, RdSeed, PrivilegeLevel};
+use crate::kernel_components::arch_x86_64::segmentation::{task_state_segment as tss, IoBitmap};
 use crate::kernel_components::memory::stack_allocator::Stack;
+use crate::kernel_components::memory::vma::AddressSpace;
+use crate::kernel_components::memory::{swap::SWAP, frames::PAGE_SIZE, memory_module::MemError, MEMORY_MANAGEMENT_UNIT};
 use crate::kernel_components::structures::thread_safe::ConcurrentList;
 
 /// All states in which the process can be. Processes may behave differently
@@ -45,6 +52,8 @@ pub enum ProcState {
     PANICKED,
     /// The process was lost due to some unknown reasons.
     FORBIDDEN,
+    /// One of the threads caused a fatal exception, like touching memory the process does not own.
+    FAULTED,
 }
 
 /// The container of all individual threads. The process should contain one or
@@ -58,6 +67,8 @@ pub struct Process<'a> {
     /// Priority number of the underline process. It should range from 0 to 127, where 0 is the
     /// most significant process.
     pub priority: u8,
+    /// Privileged operations the process may perform.
+    pub(crate) capabilities: Capability,
     /// Current state of the process.
     pub proc_state: ProcState,
     /// A parent of the current process (if exist).
@@ -68,6 +79,10 @@ pub struct Process<'a> {
     pub(crate) stack: Stack,
     /// A list of all threads in the current process.
     pub(crate) threads: ConcurrentList<Thread<'a>>,
+    /// Memory mappings of the process.
+    pub(crate) address_space: AddressSpace,
+    /// I/O ports the process may access directly. Every port is denied without a bitmap.
+    pub(crate) io_bitmap: Option<Box<IoBitmap>>,
 }
 
 impl<'a> Process<'a> {
@@ -136,12 +151,16 @@ impl<'a> Process<'a> {
             stack,
             memory_size,
             priority,
+            // Processes started by the kernel itself are fully privileged.
+            capabilities: parent_process.map_or(capability::KERNEL_CAPABILITIES, |parent| parent.capabilities),
     
             pid,
             proc_state: ProcState::INITIAL,
             parent: parent_process,
    
             threads: ConcurrentList::new(unsafe {&mut GLOBAL_ALLOCATOR }),
+            address_space: AddressSpace::new(pid),
+            io_bitmap: None,
         }
     }
 
@@ -178,9 +197,38 @@ impl<'a> Process<'a> {
     /// 
     /// Each thread will obtain an individual random id. The thread parameter within the
     /// function is the thread itself that is about to spawn.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stack of the process has no room for another thread.
     pub fn spawn<F>(&mut self, writer_ref: Option<&'a mut WriterReference>, thread_function: F) where
         F: ThreadFn + Send
     {
+        self.spawn_configured(writer_ref, None, DEFAULT_STACK_PAGES, 0, thread_function)
+            .expect("The stack of the process has no room for another thread.")
+    }
+
+    /// Spawns a new named thread with the provided amount of stack pages and nice value.
+    ///
+    /// Used by the ThreadBuilder. Threads without the writer reference are detached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MemError::StackExhausted`] if the stack of the process has no room for the
+    /// thread.
+    pub(crate) fn spawn_configured<F>(
+        &mut self,
+        writer_ref: Option<&'a mut WriterReference>,
+        name: Option<String>,
+        stack_pages: usize,
+        nice: i8,
+        thread_function: F
+    ) -> Result<(), MemError> where
+        F: ThreadFn + Send
+    {
+        // Allocating the stack for the thread.
+        let thread_stack = self.alloc_stack_pages(stack_pages)?;
+
         // Getting the ids of all current threads
         let threads_ids: Vec<usize> = self.threads
                                 .iter()
@@ -196,9 +244,6 @@ impl<'a> Process<'a> {
             thread_id += 1;
         }
 
-        // Allocating the stack for the thread.
-        let thread_stack = self.alloc_stack();
-
         // Creating the new instance of the thread.
         let mut thread = Thread::new(
             self.pid,
@@ -207,6 +252,8 @@ impl<'a> Process<'a> {
             thread_function,
             writer_ref,
         );
+        thread.name = name;
+        thread.nice = nice;
 
         unsafe {
             // The new thread must be append to the scheduler right away. TODO! Add a more advanced
@@ -215,6 +262,7 @@ impl<'a> Process<'a> {
             // Finally push the thread to the list for future contain.
             self.threads.push(thread);
         }
+        Ok(())
     }
 
     /// Allocates the stack for a new thread.
@@ -222,19 +270,28 @@ impl<'a> Process<'a> {
     /// This function allocates the stack for a new thread request based on the current state of
     /// all existing threads and the function that is being used within the requested thread.
     #[inline]
-    pub fn alloc_stack(&mut self) -> Stack {
-        // For now allocating 4096 bytes for every thread. TODO! Get rid of this voodoo constant.
-        const STARTING_OFFSET: usize = 2 * 4096;
-        let mut stack = self.stack.clone();
-
-        // crate::println!("{0:x?}", stack);
-        
-        stack.top = stack.bottom + STARTING_OFFSET;
-        stack.shift_right(STARTING_OFFSET * self.threads.len());
-
-        // crate::println!("{0:x?}", stack);
+    pub fn alloc_stack(&mut self) -> Result<Stack, MemError> {
+        self.alloc_stack_pages(DEFAULT_STACK_PAGES)
+    }
 
-        stack
+    /// Allocates the stack of the provided amount of pages for a new thread.
+    ///
+    /// The stack is placed right above the highest stack of all existing threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MemError::StackExhausted`] if the stack would reach past the top of the stack of
+    /// the process.
+    pub fn alloc_stack_pages(&mut self, pages: usize) -> Result<Stack, MemError> {
+        let bottom = self.threads.iter()
+            .map(|t| t.stack.top)
+            .max()
+            .unwrap_or(self.stack.bottom);
+
+        match pages.checked_mul(PAGE_SIZE).and_then(|size| size.checked_add(bottom)) {
+            Some(top) if pages != 0 && top <= self.stack.top => Ok(Stack::new(top, bottom)),
+            _ => Err(MemError::StackExhausted),
+        }
     }
 
     /// Spawns the main thread.
@@ -299,6 +356,64 @@ impl<'a> Process<'a> {
         });
     }
 
+    /// Changes the priority of the process.
+    ///
+    /// The priority based scheduler picks it up on the next task switch. Callers outside of the
+    /// kernel and system rings can only lower the priority, i.e increase the number.
+    pub fn set_priority(&mut self, priority: u8, caller: PrivilegeLevel) -> Result<(), PriorityError> {
+        priority::check_priority(self.priority, priority, caller)?;
+        self.priority = priority;
+        Ok(())
+    }
+
+    /// Returns the capabilities of the process.
+    #[inline]
+    pub fn capabilities(&self) -> Capability {
+        self.capabilities
+    }
+
+    /// Returns true if the process has every provided capability.
+    #[inline]
+    pub fn has_capability(&self, cap: Capability) -> bool {
+        capability::contains(self.capabilities, cap)
+    }
+
+    /// Replaces the capabilities of the process.
+    ///
+    /// Any caller may drop capabilities, only kernel level code may grant new ones.
+    pub fn set_capabilities(&mut self, caps: Capability, caller: PrivilegeLevel) -> Result<(), CapabilityError> {
+        capability::check_grant(self.capabilities, caps, caller)?;
+        self.capabilities = caps;
+        Ok(())
+    }
+
+    /// Allows the process to access the range of I/O ports directly.
+    ///
+    /// Only processes with the RAW_IO capability may be granted ports. The bitmap is loaded into
+    /// the TSS when a thread of the process is scheduled.
+    pub fn grant_io_ports(&mut self, ports: RangeInclusive<u16>) -> Result<(), CapabilityError> {
+        if !self.has_capability(Capability::RAW_IO) {
+            return Err(CapabilityError::Missing(Capability::RAW_IO))
+        }
+        self.io_bitmap.get_or_insert_with(|| Box::new(IoBitmap::new())).allow(ports);
+        tss::invalidate_io_bitmap();
+        Ok(())
+    }
+
+    /// Denies the range of I/O ports to the process.
+    pub fn revoke_io_ports(&mut self, ports: RangeInclusive<u16>) {
+        if let Some(bitmap) = self.io_bitmap.as_mut() {
+            bitmap.deny(ports);
+            tss::invalidate_io_bitmap();
+        }
+    }
+
+    /// Returns the I/O permission bitmap of the process, if any ports were granted.
+    #[inline]
+    pub fn io_bitmap(&self) -> Option<&IoBitmap> {
+        self.io_bitmap.as_deref()
+    }
+
     /// Finds the thread within process' scope by it's tid as a reference.
     pub fn find_thread(&self, tid: usize) -> Option<&Thread> {
         if let Some(thread) = self.threads.iter()
@@ -320,6 +435,53 @@ impl<'a> Process<'a> {
         }
         None
     }
+
+    /// Terminates the process after a fatal exception in one of it's threads.
+    ///
+    /// Every thread is marked as panicked, which is also seen by their join handles, so the
+    /// scheduler drops their tasks instead of running them again. Nothing is unmapped here, as
+    /// this is called from the exception handler. The mappings are unmapped later by
+    /// [`Process::unmap_faulted`], while the threads and their stacks are kept until the process
+    /// is removed.
+    pub fn kill_faulted(&mut self) {
+        self.proc_state = ProcState::FAULTED;
+
+        let tids: Vec<usize> = self.threads.iter().map(|t| t.tid).collect();
+        for tid in tids {
+            if let Some(thread) = self.find_thread_mut(tid) {
+                unsafe { thread._panicked() };
+            }
+        }
+    }
+
+    /// Unmaps every mapping of the process killed by [`Process::kill_faulted`].
+    ///
+    /// Returns false if the process did not fault or is unmapped already.
+    pub fn unmap_faulted(&mut self) -> bool {
+        if self.proc_state != ProcState::FAULTED || self.address_space.areas().is_empty() {
+            return false
+        }
+        unsafe { self.address_space.teardown(&mut MEMORY_MANAGEMENT_UNIT, SWAP.as_mut()) };
+        true
+    }
+
+    /// Returns every piece of memory of the finished process to the frame allocator.
+    ///
+    /// The mappings are removed together with the page tables left empty, and the stack which
+    /// holds the stacks of all threads is unmapped. Frames shared with other processes only lose
+    /// a reference.
+    ///
+    /// # Safety
+    ///
+    /// None of the threads may run anymore and the caller must not be running on the stack of the
+    /// process, which is why processes are torn down by the worker thread rather than by their own
+    /// last thread.
+    pub(crate) unsafe fn teardown(&mut self) {
+        self.threads.clear();
+        let mmu = &mut *MEMORY_MANAGEMENT_UNIT;
+        self.address_space.teardown(mmu, SWAP.as_mut());
+        let _ = mmu.deallocate_stack(self.stack);
+    }
 }
 
 impl<'a> Drop for Process<'a> {
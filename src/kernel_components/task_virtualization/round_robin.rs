@@ -46,6 +46,31 @@ impl RoundRobin {
             self.append_thread(thread)
         }
     }
+
+    /// Rebuilds the task list from the processes currently known to the PMU.
+    ///
+    /// Tasks of vanished threads are dropped and missing tasks are appended. The current task
+    /// stays the running thread, since the next tick saves the context of the running thread into
+    /// it. It is kept even if it's thread vanished, the tick then finds nothing to save. Must be
+    /// called within a critical section.
+    pub fn rebalance(&mut self) {
+        use super::PROCESS_MANAGEMENT_UNIT;
+
+        let running = self.current().copied();
+        unsafe { self.clear() };
+        for proc in unsafe { PROCESS_MANAGEMENT_UNIT.process_list.lock() }.iter() {
+            self.append_process(proc);
+        }
+
+        let index = match running {
+            Some(task) => self.list.index_of(task).unwrap_or_else(|| {
+                self.list.push(task);
+                self.list.len() - 1
+            }),
+            None => 0,
+        };
+        self.current_task.store(index, Ordering::Release);
+    }
 }
 
 impl Scheduler for RoundRobin {
@@ -5,9 +5,10 @@ use core::any::{Any, TypeId};
 use core::ptr::NonNull;
 use core::fmt::Debug;
 use core::cell::RefCell;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::sync::Arc;
 
@@ -20,8 +21,16 @@ use crate::kernel_components::arch_x86_64::{
     controllers::PROGRAMMABLE_INTERRUPT_CONTROLLER, interrupts,
 };
 use crate::kernel_components::task_virtualization::{Scheduler, ROUND_ROBIN};
+use super::priority::{self, PriorityError};
+use crate::kernel_components::arch_x86_64::PrivilegeLevel;
 use super::{Process, join_handle::{JoinHandle, HandleStack, WriterReference}, PROCESS_MANAGEMENT_UNIT};
 
+/// Marks that the next task switch was requested by the running thread itself.
+///
+/// Set right before the software interrupt is raised, so the task switching handler knows that no
+/// IRQ is in service and the end of interrupt must not be sent.
+pub(crate) static YIELD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
 /// A custom trait for thread functions.
 /// 
 /// Basically they are just normal closures that take the thread
@@ -82,6 +91,10 @@ pub struct Thread<'a> {
     pub pid: usize,
     /// The current state of the thread.
     pub thread_state: ThreadState,
+    /// Nice value of the thread from -20 to 19. Lower values are scheduled more favorably.
+    pub(crate) nice: i8,
+    /// Optional name of the thread.
+    pub(crate) name: Option<String>,
     /// An overall stack allocated for the current thread.
     pub(crate) stack: Stack, 
     /// An instruction pointer of the thread.
@@ -97,11 +110,13 @@ pub struct Thread<'a> {
 impl Debug for Thread<'_> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("Thread")
+            .field("name", &self.name)
             .field("tid", &self.tid)
             .field("pid", &self.pid)
             .field("instruction_pointer", &self.instruction_ptr)
             .field("stack_pointer", &self.stack_ptr)
             .field("thread_state", &self.thread_state)
+            .field("nice", &self.nice)
             .finish()
     }
 }
@@ -128,6 +143,8 @@ impl<'a> Thread<'a> {
             stack_ptr: AtomicUsize::new(stack.top),
             stack: stack,
             thread_state: ThreadState::INIT,
+            nice: 0,
+            name: None,
             output: writer_ref,
             fun: Box::new(function),
         }
@@ -260,6 +277,49 @@ impl<'a> Thread<'a> {
         });
     }
 
+    /// Returns the name of the thread if it was named with the ThreadBuilder.
+    #[inline]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Calls the closure with the currently scheduled thread.
+    ///
+    /// Returns None if no thread is scheduled or the process list is locked at the moment, so it
+    /// is safe to use on fatal paths.
+    pub fn with_current<F, R>(f: F) -> Option<R> where F: FnOnce(&Thread) -> R {
+        unsafe {
+            let task = *ROUND_ROBIN.current()?;
+            if PROCESS_MANAGEMENT_UNIT.process_list.is_locked() {
+                return None
+            }
+            let mut list = PROCESS_MANAGEMENT_UNIT.process_list.lock();
+            let thread = list.get(task.pid)?.find_thread(task.tid)?;
+            Some(f(thread))
+        }
+    }
+
+    /// Changes the nice value of the thread.
+    ///
+    /// Callers outside of the kernel and system rings can only increase the value.
+    pub fn set_nice(&mut self, nice: i8, caller: PrivilegeLevel) -> Result<(), PriorityError> {
+        priority::check_nice(self.nice, nice, caller)?;
+        self.nice = nice;
+        Ok(())
+    }
+
+    /// Returns the nice value of the thread.
+    #[inline]
+    pub fn nice(&self) -> i8 {
+        self.nice
+    }
+
+    /// Returns the scheduling weight derived from the nice value.
+    #[inline]
+    pub fn weight(&self) -> u32 {
+        priority::nice_to_weight(self.nice)
+    }
+
     /// Returns true if thread's status is running.
     pub fn is_running(&self) -> bool {
         self.thread_state == ThreadState::RUNNING
@@ -294,18 +354,58 @@ impl<'a> Thread<'a> {
     #[inline(never)]
     pub fn r#yield() {
         if interrupt::is_interrupts_enabled() {
-            let timer_interrupt_int = PROGRAMMABLE_INTERRUPT_CONTROLLER
-                .lock()
-                .as_mut()
-                .map(|pic| pic.master.offset)
-                .expect("PIC must be initialized for this function.");
-            
-            interrupt::cause_interrupt(timer_interrupt_int);
+            assert!(
+                PROGRAMMABLE_INTERRUPT_CONTROLLER.lock().is_some(),
+                "PIC must be initialized for this function."
+            );
+            Thread::yield_now();
         } else {
             panic!("The thread yielded while interrupts are disabled.");
         }
     }
 
+    /// Gives up the processor right away, without waiting for the next timer tick.
+    ///
+    /// The context of the current thread is saved and the scheduler picks the next task
+    /// immediately. The thread continues from this point once it is scheduled again.
+    ///
+    /// Unlike [`Thread::r#yield`] this function never panics. With interrupts disabled no task
+    /// switch is possible, so it returns at once and the caller decides how to wait. If the
+    /// interrupt controller is not initialized yet or busy, it only hints the processor that the
+    /// caller is spinning.
+    ///
+    /// # Return
+    ///
+    /// Returns true if the task switch was performed.
+    #[inline(never)]
+    pub fn yield_now() -> bool {
+        if !interrupt::is_interrupts_enabled() {
+            return false
+        }
+
+        // The controller lock itself yields on contention, so it must never be waited for here.
+        let vector = match !PROGRAMMABLE_INTERRUPT_CONTROLLER.is_locked() {
+            true => PROGRAMMABLE_INTERRUPT_CONTROLLER.lock().as_ref().map(|pic| pic.master.offset),
+            false => None,
+        };
+
+        match vector {
+            Some(vector) => unsafe {
+                // No hardware timer interrupt may consume the request between the two steps.
+                interrupt::disable();
+                YIELD_REQUESTED.store(true, Ordering::SeqCst);
+                interrupt::cause_interrupt(vector);
+                // The thread is resumed with the flags of the interrupted context.
+                interrupt::enable();
+                true
+            },
+            None => {
+                core::hint::spin_loop();
+                false
+            },
+        }
+    }
+
     /// Halts the thread until certain condition is met.
     ///
     /// It will mark the thread as halted and yield the execution. The thread will be
@@ -323,6 +423,14 @@ impl<'a> Thread<'a> {
         self._mark_state(ThreadState::FINAL)
     }
 
+    /// Marks the state as panicked.
+    ///
+    /// Used when the thread is killed by a fatal exception. The scheduler never runs such
+    /// threads again. This flag is also written to the join handle.
+    pub unsafe fn _panicked(&mut self) {
+        self._mark_state(ThreadState::PANICKED)
+    }
+
     /// Marks the state as running.
     ///
     /// Must be used by task switching handler function, i.e clock interrupts.
@@ -388,3 +496,13 @@ pub(crate) unsafe fn task_switch_call(t: &mut Thread) -> ! {
         interrupt::wait_for_interrupt();
     }
 }
+
+/// Entry point of the threads killed by an exception.
+///
+/// The exception handler returns here instead of to the faulting instruction. The thread is
+/// already marked as panicked, so it only waits until the scheduler drops it's task.
+pub(crate) extern "C" fn killed_thread_exit() -> ! {
+    loop {
+        interrupt::wait_for_interrupt();
+    }
+}
@@ -0,0 +1,148 @@
+/// Thread factory with additional configuration.
+///
+/// [`Thread::spawn`] always creates an anonymous thread with the default stack size and a join
+/// handle. The builder allows to name the thread, so scheduler dumps and panic messages can
+/// attribute activity to it, choose the amount of stack pages and the nice value, and spawn
+/// detached threads, which never write their output anywhere.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::any::Any;
+
+use crate::critical_section;
+use crate::kernel_components::memory::memory_module::MemError;
+use super::{Process, Thread, JoinHandle, PROCESS_MANAGEMENT_UNIT};
+use super::join_handle::WriterReference;
+use super::priority::{NICE_MIN, NICE_MAX};
+
+/// Default amount of stack pages of a thread.
+pub const DEFAULT_STACK_PAGES: usize = 2;
+
+/// Configuration of a new thread.
+///
+/// # Examples
+///
+/// ```ignore
+/// ThreadBuilder::new()
+///     .name("flusher")
+///     .stack_size(4)
+///     .nice(5)
+///     .detached()
+///     .spawn(t, |_| loop { flush() })?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ThreadBuilder {
+    name: Option<String>,
+    stack_pages: Option<usize>,
+    nice: i8,
+    detached: bool,
+}
+
+impl ThreadBuilder {
+    /// Creates a builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Names the thread.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(String::from(name));
+        self
+    }
+
+    /// Sets the amount of stack pages of the thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the amount is zero.
+    pub fn stack_size(mut self, pages: usize) -> Self {
+        assert!(pages != 0, "The thread must have at least one stack page.");
+        self.stack_pages = Some(pages);
+        self
+    }
+
+    /// Sets the nice value of the thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is outside of [`NICE_MIN`] and [`NICE_MAX`].
+    pub fn nice(mut self, nice: i8) -> Self {
+        assert!((NICE_MIN..=NICE_MAX).contains(&nice), "The nice value {} is out of range.", nice);
+        self.nice = nice;
+        self
+    }
+
+    /// Makes the thread detached.
+    ///
+    /// The output of detached threads is dropped right away and no join handle is returned.
+    pub fn detached(mut self) -> Self {
+        self.detached = true;
+        self
+    }
+
+    /// Spawns the thread within the process of the current thread.
+    ///
+    /// # Return
+    ///
+    /// Returns None for detached threads and the join handle otherwise, or
+    /// [`MemError::StackExhausted`] if the stack of the process has no room for the thread.
+    pub fn spawn<F, T>(self, current: &mut Thread, thread_function: F) -> Result<Option<JoinHandle<T>>, MemError> where
+        F: (Fn(&mut Thread) -> T) + Send + 'static, T: 'static,
+    {
+        critical_section!(|| {
+            let mut list = PROCESS_MANAGEMENT_UNIT.process_list.lock();
+            let process = list.get_mut(current.pid).expect("The current thread has no process.");
+            self.spawn_in(process, thread_function)
+        })
+    }
+
+    /// Spawns the thread within the provided process.
+    ///
+    /// # Return
+    ///
+    /// Returns None for detached threads and the join handle otherwise, or
+    /// [`MemError::StackExhausted`] if the stack of the process has no room for the thread.
+    pub fn spawn_in<F, T>(self, process: &mut Process, thread_function: F) -> Result<Option<JoinHandle<T>>, MemError> where
+        F: (Fn(&mut Thread) -> T) + Send + 'static, T: 'static,
+    {
+        let stack_pages = self.stack_pages.unwrap_or(DEFAULT_STACK_PAGES);
+        let function = move |t: &mut Thread| -> Box<dyn Any> { Box::new(thread_function(t)) };
+
+        if self.detached {
+            process.spawn_configured(None, self.name, stack_pages, self.nice, function)?;
+            Ok(None)
+        } else {
+            let mut handle = JoinHandle::new();
+            // The handle does not own the data, therefore the writer may outlive this borrow.
+            let writer = unsafe { (handle.writer() as *mut WriterReference).as_mut() };
+            process.spawn_configured(writer, self.name, stack_pages, self.nice, function)?;
+            Ok(Some(handle))
+        }
+    }
+}
+
+#[test_case]
+fn builder_collects_options() {
+    use super::{Scheduler, Task, ROUND_ROBIN};
+    use crate::kernel_components::memory::{frames::PAGE_SIZE, stack_allocator::Stack};
+
+    assert!(!ThreadBuilder::new().detached);
+
+    // The stacks are never touched before the thread runs, so the addresses are made up.
+    let bottom = 0x4000_0000;
+    let mut process = Process::new_nomain(Stack::new(bottom + 6 * PAGE_SIZE, bottom), 0, 0xb11d, 1, None);
+    let builder = ThreadBuilder::new().name("worker").stack_size(4).nice(5).detached();
+    assert!(builder.spawn_in(&mut process, |_| ()).unwrap().is_none());
+    // The thread must never be picked by the timer of the test kernel.
+    unsafe { ROUND_ROBIN.delete(Task::new(0xb11d, 0)) };
+
+    let thread = process.find_thread(0).unwrap();
+    assert_eq!(thread.name(), Some("worker"));
+    assert_eq!(thread.stack.size(), 4 * PAGE_SIZE);
+    assert_eq!(thread.nice(), 5);
+
+    // Only two pages are left in the stack of the process.
+    let result = ThreadBuilder::new().stack_size(4).spawn_in(&mut process, |_| ());
+    assert_eq!(result.err(), Some(MemError::StackExhausted));
+    assert_eq!(process.alloc_stack_pages(2).map(|stack| stack.size()), Ok(2 * PAGE_SIZE));
+}
@@ -0,0 +1,99 @@
+/// Deferred work executed outside of the interrupt context.
+///
+/// Interrupt handlers must stay short and are not allowed to block, therefore anything more
+/// complex than acknowledging the device is scheduled here and executed later by the kernel
+/// worker thread. The queue is a fixed size ring of function pointers, so scheduling never
+/// allocates and is safe to use from any interrupt handler.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::Thread;
+use crate::critical_section;
+
+/// Maximal amount of pending work items.
+pub const WORKQUEUE_SIZE: usize = 64;
+/// Interval between two checks of the worker thread in milliseconds.
+pub const WORKER_INTERVAL_MS: u32 = 10;
+
+/// A deferred work item.
+pub type Work = fn();
+
+/// Pending work items stored as raw function pointers.
+static SLOTS: [AtomicUsize; WORKQUEUE_SIZE] = [const { AtomicUsize::new(0) }; WORKQUEUE_SIZE];
+/// Index of the next item to be executed.
+static HEAD: AtomicUsize = AtomicUsize::new(0);
+/// Index of the next free slot.
+static TAIL: AtomicUsize = AtomicUsize::new(0);
+/// Prevents several threads from draining the queue at once.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Schedules the work to be executed by the worker thread.
+///
+/// Returns false if the queue is full and the work was dropped.
+pub fn schedule(work: Work) -> bool {
+    critical_section!(|| {
+        let tail = TAIL.load(Ordering::Relaxed);
+        if tail.wrapping_sub(HEAD.load(Ordering::Acquire)) == WORKQUEUE_SIZE {
+            return false;
+        }
+
+        SLOTS[tail % WORKQUEUE_SIZE].store(work as usize, Ordering::Relaxed);
+        TAIL.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    })
+}
+
+/// Returns the amount of pending work items.
+pub fn pending() -> usize {
+    TAIL.load(Ordering::Acquire).wrapping_sub(HEAD.load(Ordering::Acquire))
+}
+
+/// Executes every pending work item.
+///
+/// Returns the amount of executed items. Does nothing if the queue is being drained by another
+/// thread already.
+pub fn run_pending() -> usize {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+
+    let mut count = 0;
+    loop {
+        let head = HEAD.load(Ordering::Relaxed);
+        if head == TAIL.load(Ordering::Acquire) {
+            break;
+        }
+
+        let raw = SLOTS[head % WORKQUEUE_SIZE].load(Ordering::Relaxed);
+        HEAD.store(head.wrapping_add(1), Ordering::Release);
+
+        let work: Work = unsafe { core::mem::transmute::<usize, Work>(raw) };
+        work();
+        count += 1;
+    }
+
+    RUNNING.store(false, Ordering::Release);
+    count
+}
+
+/// Kernel worker thread that executes the deferred work.
+pub fn worker(_: &mut Thread) {
+    loop {
+        run_pending();
+        Thread::sleep(WORKER_INTERVAL_MS);
+    }
+}
+
+#[test_case]
+fn deferred_work_runs_in_order() {
+    static ORDER: AtomicUsize = AtomicUsize::new(0);
+
+    run_pending();
+    assert!(schedule(|| { ORDER.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).unwrap(); }));
+    assert!(schedule(|| { ORDER.compare_exchange(1, 2, Ordering::SeqCst, Ordering::SeqCst).unwrap(); }));
+    assert_eq!(pending(), 2);
+
+    assert_eq!(run_pending(), 2);
+    assert_eq!(ORDER.load(Ordering::SeqCst), 2);
+    assert_eq!(pending(), 0);
+}
@@ -0,0 +1,313 @@
+//! Kernel test framework.
+//!
+//! Every `#[test_case]` is collected by the compiler and passed to [`test_runner`]. Plain
+//! functions are run as they are, while a [`TestCase`] allows to mark the test as expected to
+//! panic or to change it's timeout:
+//!
+//! ```ignore
+//! #[test_case]
+//! static POP_EMPTY_PANICS: TestCase = TestCase::new("stack::pop_empty_panics", pop_empty).expect_panic();
+//! ```
+//!
+//! There is no unwinding, so a panic never returns into the runner. Instead, the panic handler
+//! reports the outcome and [`resume`] continues with the next test on the stack of the runner.
+//! Everything the panicked test held stays as it was, including locks and the depth of critical
+//! sections.
+//!
+//! Each test runs with interrupts enabled under a watchdog: the PIT ticks at [`WATCHDOG_HZ`] and
+//! a test which runs longer than it's timeout is failed by redirecting the tick back into the
+//! runner. The watchdog loads it's own IDT, so the tests run with the default exception handlers.
+//! A test hanging with interrupts disabled is not caught.
+//!
+//! Test binaries which set up their own interrupt handling and enable interrupts before the
+//! runner starts keep it as it is, and their tests run without the watchdog.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use crate::{log, print, single};
+use crate::kernel_components::arch_x86_64::controllers::{PIT, PITCommand};
+use crate::kernel_components::arch_x86_64::controllers::pic::{ChainedPics, IrqMask};
+use crate::kernel_components::arch_x86_64::interrupts::{
+    self, def_exceptions, GateDescriptor, InterruptStackFrame, InterruptVector, IDT,
+};
+use crate::kernel_components::arch_x86_64::interrupts::handler_functions::HandlerFunction;
+
+/// Timeout of the tests which do not provide their own.
+pub const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+/// Frequency of the watchdog ticks.
+pub const WATCHDOG_HZ: u64 = 100;
+
+/// Offsets of the PICs while the tests run.
+const MASTER_OFFSET: u8 = 0x20;
+const SLAVE_OFFSET: u8 = 0x28;
+
+/// Custom trait for tests. It is only used when testing and do not affect overall performance.
+pub trait Testable {
+    /// Runs the body of the test.
+    fn run(&self) -> ();
+
+    /// Name printed before the outcome.
+    fn name(&self) -> &'static str;
+
+    /// The test passes only if it panics.
+    fn should_panic(&self) -> bool {
+        false
+    }
+
+    /// Time after which the test is failed.
+    fn timeout_ms(&self) -> u64 {
+        DEFAULT_TIMEOUT_MS
+    }
+}
+
+impl<T> Testable for T where T: Fn(), {
+    fn run(&self) {
+        self();
+    }
+
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+}
+
+/// Test with options, declared as a `#[test_case]` static.
+#[derive(Debug, Clone, Copy)]
+pub struct TestCase {
+    name: &'static str,
+    func: fn(),
+    should_panic: bool,
+    timeout_ms: u64,
+}
+
+impl TestCase {
+    /// Creates a test which must return within the default timeout.
+    pub const fn new(name: &'static str, func: fn()) -> Self {
+        Self { name, func, should_panic: false, timeout_ms: DEFAULT_TIMEOUT_MS }
+    }
+
+    /// Makes the test pass only if it panics.
+    pub const fn expect_panic(mut self) -> Self {
+        self.should_panic = true;
+        self
+    }
+
+    /// Changes the time after which the test is failed.
+    pub const fn with_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.timeout_ms = timeout_ms;
+        self
+    }
+}
+
+impl Testable for TestCase {
+    fn run(&self) {
+        (self.func)()
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn should_panic(&self) -> bool {
+        self.should_panic
+    }
+
+    fn timeout_ms(&self) -> u64 {
+        self.timeout_ms
+    }
+}
+
+/// Tests passed to the runner.
+static TESTS: AtomicPtr<&'static dyn Testable> = AtomicPtr::new(core::ptr::null_mut());
+static TESTS_LEN: AtomicUsize = AtomicUsize::new(0);
+/// Index of the next test to run.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+/// Set while the body of a test runs.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static PASSED: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+/// Set if the tests run under the watchdog.
+static WATCHDOG: AtomicBool = AtomicBool::new(false);
+/// Aligned stack pointer of the runner, on which the remaining tests are resumed.
+static RUNNER_STACK: AtomicUsize = AtomicUsize::new(0);
+
+/// Amount of watchdog ticks so far.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Tick at which the running test times out, zero while disarmed.
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+single! {
+    mut WATCHDOG_IDT: IDT = IDT::new_empty();
+}
+
+/// Test runner for testing kernel components. It wil run all unit tests as well as integrated one.
+///
+/// The list of tests may live on the stack of the caller. It stays valid, as the runner never
+/// returns and the tests are only ever resumed below it's own frame.
+pub fn test_runner(tests: &[&dyn Testable]) -> ! {
+    log!(Info; "Running {} tests:", tests.len());
+    TESTS.store(tests.as_ptr() as *mut &'static dyn Testable, Ordering::Release);
+    TESTS_LEN.store(tests.len(), Ordering::Release);
+
+    let rsp: usize;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags)) };
+    RUNNER_STACK.store(rsp & !0xf, Ordering::Release);
+
+    if !interrupts::interrupt::is_interrupts_enabled() {
+        install_watchdog();
+    }
+    resume()
+}
+
+fn tests() -> &'static [&'static dyn Testable] {
+    let ptr = TESTS.load(Ordering::Acquire);
+    match ptr.is_null() {
+        true => &[],
+        false => unsafe { core::slice::from_raw_parts(ptr, TESTS_LEN.load(Ordering::Acquire)) },
+    }
+}
+
+/// Returns the test which body is running.
+fn current() -> Option<&'static dyn Testable> {
+    match RUNNING.load(Ordering::Acquire) {
+        true => tests().get(NEXT.load(Ordering::Acquire).checked_sub(1)?).copied(),
+        false => None,
+    }
+}
+
+/// Checks if the body of a test is running.
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Acquire)
+}
+
+/// Continues with the remaining tests on the stack of the runner, dropping the current stack.
+pub fn resume() -> ! {
+    unsafe {
+        asm!(
+            "mov rsp, {stack}",
+            "call {run}",
+            stack = in(reg) RUNNER_STACK.load(Ordering::Acquire),
+            run = sym run_remaining,
+            options(noreturn),
+        )
+    }
+}
+
+/// Ends the running test. Under the watchdog, interrupts stay disabled until the next one starts.
+fn finish(passed: bool) {
+    if WATCHDOG.load(Ordering::Acquire) {
+        unsafe { interrupts::disable() };
+    }
+    DEADLINE.store(0, Ordering::Release);
+    RUNNING.store(false, Ordering::Release);
+    match passed {
+        true => PASSED.fetch_add(1, Ordering::Relaxed),
+        false => FAILED.fetch_add(1, Ordering::Relaxed),
+    };
+}
+
+extern "C" fn run_remaining() -> ! {
+    let tests = tests();
+    while let Some(test) = tests.get(NEXT.load(Ordering::Acquire)) {
+        print!("{}...    ", test.name());
+        NEXT.fetch_add(1, Ordering::AcqRel);
+        RUNNING.store(true, Ordering::Release);
+        if WATCHDOG.load(Ordering::Acquire) {
+            arm_watchdog(test.timeout_ms());
+            unsafe { interrupts::enable() };
+        }
+
+        test.run();
+
+        finish(!test.should_panic());
+        match test.should_panic() {
+            true => log!(Error; "[failed] (no panic)"),
+            false => log!(Success; "[ok]"),
+        }
+    }
+
+    let failed = FAILED.load(Ordering::Relaxed);
+    match failed {
+        0 => log!(Success; "All {} tests passed.", PASSED.load(Ordering::Relaxed)),
+        _ => log!(Error; "{} passed, {} failed.", PASSED.load(Ordering::Relaxed), failed),
+    }
+    loop {
+        interrupts::hlt();
+    }
+}
+
+/// Called first by the panic handler. Reports the panic of a test which should panic as a pass
+/// and continues with the next test.
+pub fn catch_expected_panic() {
+    if current().is_some_and(|test| test.should_panic()) {
+        finish(true);
+        log!(Success; "[ok]");
+        resume()
+    }
+}
+
+/// Called last by the panic handler, once the panic is reported. Fails the running test and
+/// continues with the next one.
+pub fn continue_after_failure() {
+    if is_running() {
+        finish(false);
+        resume()
+    }
+}
+
+/// Loads the IDT of the tests and starts the PIT.
+fn install_watchdog() {
+    unsafe {
+        def_exceptions::idt_install_default_exceptions(&mut WATCHDOG_IDT);
+        // No TSS with an interrupt stack is loaded while testing.
+        WATCHDOG_IDT.push(InterruptVector::DOUBLE_FAULT, GateDescriptor::new_trap(def_exceptions::DOUBLE_FAULT));
+        WATCHDOG_IDT.push(InterruptVector::Custom(MASTER_OFFSET as usize), GateDescriptor::new_interrupt(WATCHDOG_TICK));
+        WATCHDOG_IDT.fill_unhandled();
+        WATCHDOG_IDT.load_table();
+    }
+    WATCHDOG.store(true, Ordering::Release);
+}
+
+/// Prepares the PIC and the PIT for the next test, as the previous one may have reprogrammed
+/// them, and sets the deadline.
+fn arm_watchdog(timeout_ms: u64) {
+    let mut pics = ChainedPics::new(MASTER_OFFSET, SLAVE_OFFSET);
+    pics.initialize();
+    unsafe { pics.write_mask(IrqMask::all() & !IrqMask::IRQ0_TIMER) };
+
+    let mut pit = PIT::new();
+    unsafe { pit.command(PITCommand::CHANNEL0 | PITCommand::FULL_WORD | PITCommand::SQUARE_WAVE_GENERATOR) };
+    pit.channel0.write((1_193_182 / WATCHDOG_HZ) as u16);
+
+    let ticks = (timeout_ms * WATCHDOG_HZ / 1000).max(1);
+    DEADLINE.store(TICKS.load(Ordering::Acquire) + ticks, Ordering::Release);
+}
+
+/// Handler of the PIT ticks while testing.
+const WATCHDOG_TICK: HandlerFunction = watchdog_tick;
+
+unsafe extern "x86-interrupt" fn watchdog_tick(mut stack_frame: InterruptStackFrame) {
+    let now = TICKS.fetch_add(1, Ordering::AcqRel) + 1;
+    unsafe { ChainedPics::new(MASTER_OFFSET, SLAVE_OFFSET).notify_end_of_interrupt(MASTER_OFFSET) };
+
+    let deadline = DEADLINE.load(Ordering::Acquire);
+    if deadline != 0 && now >= deadline && is_running() {
+        // The handler returns right into the runner, as if it called the function.
+        stack_frame.redirect(test_timed_out as *const () as usize, RUNNER_STACK.load(Ordering::Acquire) - 8);
+    }
+}
+
+extern "C" fn test_timed_out() -> ! {
+    let timeout = current().map_or(DEFAULT_TIMEOUT_MS, |test| test.timeout_ms());
+    finish(false);
+    log!(Error; "[failed] (timed out after {} ms)", timeout);
+    resume()
+}
+
+#[test_case]
+static TEST_CASE_OPTIONS: TestCase = TestCase::new("testing::test_case_options", || {
+    let test = TestCase::new("panics", || panic!()).expect_panic().with_timeout_ms(20);
+    assert!(test.should_panic() && test.timeout_ms() == 20);
+    assert!(!TestCase::new("runs", || ()).should_panic());
+    assert!(is_running());
+}).with_timeout_ms(1000);
@@ -0,0 +1,202 @@
+//! Line discipline between the keyboard input and terminal readers.
+//!
+//! Characters obtained from the keyboard are fed into a [`LineDiscipline`], which cooks them
+//! before any reader sees them. In the canonical mode the line is edited in place and only handed
+//! to readers once it is finished:
+//!
+//! - printable characters are appended to the line and echoed;
+//! - backspace removes the last character of the line;
+//! - enter finishes the line, the reader gets it with the trailing new line;
+//! - Ctrl+C discards the line and reports [`Signal::Interrupt`] to the caller;
+//! - Ctrl+D hands the unfinished line to the reader without a new line. On an empty line the
+//! reader gets an empty read, which means end of file.
+//!
+//! In the raw mode every character is passed to readers as is, without echo or editing.
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+
+use crate::print;
+
+/// Maximal length of the edited line. Further characters are ignored until the line is finished.
+pub const MAX_LINE: usize = 255;
+
+/// End of text, sent by Ctrl+C.
+const ETX: char = '\u{3}';
+/// End of transmission, sent by Ctrl+D.
+const EOT: char = '\u{4}';
+/// Backspace.
+const BS: char = '\u{8}';
+/// Delete, sent by some keyboards instead of backspace.
+const DEL: char = '\u{7f}';
+
+/// Input mode of the line discipline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtyMode {
+    /// Line editing with special characters.
+    Canonical,
+    /// Characters are passed to readers immediately and as is.
+    Raw,
+}
+
+/// Signal generated by a special character in the canonical mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Ctrl+C was pressed. The current line was discarded.
+    Interrupt,
+}
+
+/// Canonical mode line discipline of a single terminal.
+#[derive(Debug)]
+pub struct LineDiscipline {
+    mode: TtyMode,
+    echo: bool,
+    /// Line being edited.
+    line: String,
+    /// Finished reads. An empty one is the end of file.
+    ready: VecDeque<String>,
+}
+
+impl LineDiscipline {
+    /// Creates a new line discipline in the canonical mode with echo.
+    pub const fn new() -> Self {
+        Self {
+            mode: TtyMode::Canonical,
+            echo: true,
+            line: String::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Returns the current input mode.
+    pub fn mode(&self) -> TtyMode {
+        self.mode
+    }
+
+    /// Switches the input mode.
+    ///
+    /// Switching to the raw mode hands the unfinished line to readers, so no input is lost.
+    pub fn set_mode(&mut self, mode: TtyMode) {
+        if mode == TtyMode::Raw && !self.line.is_empty() {
+            let line = core::mem::take(&mut self.line);
+            self.ready.push_back(line);
+        }
+        self.mode = mode;
+    }
+
+    /// Enables or disables echo of the input in the canonical mode.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    /// Processes a single character obtained from the keyboard.
+    ///
+    /// Returns the signal generated by the character, if any.
+    pub fn input(&mut self, c: char) -> Option<Signal> {
+        if self.mode == TtyMode::Raw {
+            self.ready.push_back(String::from(c));
+            return None
+        }
+
+        match c {
+            '\n' => {
+                self.line.push('\n');
+                self.echo("\n");
+                let line = core::mem::take(&mut self.line);
+                self.ready.push_back(line);
+            },
+            BS | DEL => {
+                if self.line.pop().is_some() {
+                    self.echo("\u{8}");
+                }
+            },
+            ETX => {
+                self.line.clear();
+                self.echo("^C\n");
+                return Some(Signal::Interrupt)
+            },
+            EOT => {
+                let line = core::mem::take(&mut self.line);
+                self.ready.push_back(line);
+            },
+            c if (c.is_ascii_graphic() || c == ' ') && self.line.len() < MAX_LINE => {
+                self.line.push(c);
+                if self.echo {
+                    print!("{}", c);
+                }
+            },
+            _ => (),
+        }
+        None
+    }
+
+    /// Returns true if a reader would get data or the end of file.
+    pub fn has_input(&self) -> bool {
+        !self.ready.is_empty()
+    }
+
+    /// Takes the next finished read.
+    ///
+    /// In the canonical mode it is a whole line ending with a new line, an unfinished line sent
+    /// with Ctrl+D or an empty string for the end of file. In the raw mode all pending characters
+    /// are returned. None if there is nothing to read yet.
+    pub fn read_line(&mut self) -> Option<String> {
+        match self.mode {
+            TtyMode::Canonical => self.ready.pop_front(),
+            TtyMode::Raw if self.ready.is_empty() => None,
+            TtyMode::Raw => Some(self.ready.drain(..).collect()),
+        }
+    }
+
+    /// Reads bytes of the next finished read into the buffer.
+    ///
+    /// Returns the amount of read bytes, zero at the end of file, or None if there is nothing to
+    /// read yet. The rest of a read, which does not fit into the buffer, is kept for the next call.
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let mut data = self.read_line()?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data.as_bytes()[..len]);
+
+        if len < data.len() {
+            self.ready.push_front(data.split_off(len));
+        }
+        Some(len)
+    }
+
+    fn echo(&self, s: &str) {
+        if self.echo {
+            print!("{}", s);
+        }
+    }
+}
+
+#[test_case]
+fn canonical_input_is_cooked() {
+    let mut tty = LineDiscipline::new();
+    tty.set_echo(false);
+
+    for c in "lx\u{8}s -a\n".chars() {
+        assert_eq!(tty.input(c), None);
+    }
+    assert_eq!(tty.read_line().as_deref(), Some("ls -a\n"));
+
+    "rm -rf".chars().for_each(|c| { tty.input(c); });
+    assert!(!tty.has_input());
+    assert_eq!(tty.input(ETX), Some(Signal::Interrupt));
+    assert_eq!(tty.read_line(), None);
+
+    "exit".chars().for_each(|c| { tty.input(c); });
+    tty.input(EOT);
+    tty.input(EOT);
+    let mut buf = [0; 3];
+    assert_eq!(tty.read(&mut buf), Some(3));
+    assert_eq!(tty.read(&mut buf), Some(1));
+    assert_eq!(&buf[..1], b"t");
+    assert_eq!(tty.read(&mut buf), Some(0));
+    assert_eq!(tty.read(&mut buf), None);
+
+    tty.set_mode(TtyMode::Raw);
+    tty.input(ETX);
+    tty.input('q');
+    assert_eq!(tty.read_line().as_deref(), Some("\u{3}q"));
+}
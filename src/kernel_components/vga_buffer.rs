@@ -3,37 +3,145 @@
 ///
 /// This module provides a simple Logger structure and macros for printing formatted output
 /// to a VGA buffer, simulating output on the screen in a basic operating system environment.
+///
+/// # Printing from interrupt handlers
+///
+/// The logger is guarded by an [`IrqSpinlock`], so no maskable interrupt can arrive while it is
+/// held on the same CPU. Printing never waits for the lock though: if it is taken (i.e. by the
+/// code interrupted with an NMI or an exception), the output goes into a small staging buffer of
+/// the CPU instead, which is flushed by the worker thread or the next print.
+///
+/// Interrupt handlers must log with [`isr_println!`], which always stages the output. Scrolling
+/// the whole screen is way too slow for a handler, while the staging is a plain copy.
+///
+/// # Framebuffer
+///
+/// When the bootloader sets up a graphics mode, there is no text buffer to write to. Once
+/// [`use_framebuffer`] is called, the text buffer is kept in memory only and every character is
+/// also written to a console on the linear framebuffer.
+///
+/// # Scrollback
+///
+/// Lines scrolled off the top of the screen are kept in a scrollback of up to
+/// [`MAX_SCROLLBACK_PAGES`] screens, tunable with "console.scrollback_pages". Shift+PageUp and
+/// Shift+PageDown move the view through it, see [`register_shortcuts`]. While the view is moved,
+/// the output keeps being written into the screen kept in memory and the view stays on the same
+/// lines, so a running boot log or allocator trace can be read back. Typing in the terminal
+/// returns to the bottom. The hardware cursor follows the output and is hidden meanwhile.
+
+use core::{fmt, ptr};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use alloc::string::String;
+use crate::kernel_components::arch_x86_64::ports::{GenericPort, PortAccessType};
+use crate::kernel_components::arch_x86_64::smp::{self, MAX_CPUS};
+use crate::kernel_components::drivers::keyboards::{keyboard::register_shortcut, Key, ShortcutModifiers};
+use crate::kernel_components::gfx::{FbConsole, LinearFramebuffer, PsfFont, DEFAULT_FONT};
+use crate::kernel_components::sync::IrqSpinlock;
+use crate::kernel_components::task_virtualization::workqueue;
+use crate::{single, critical_section, bitflags};
 
-use core::fmt;
-use crate::{kernel_components::sync::Mutex, single, critical_section};
+pub const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
+pub(crate) const BUFFER_ADDR: usize = 0xb8000;
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
-const BUFFER_ADDR: usize = 0xb8000;
+/// Attribute bits inverted under the mouse pointer.
+const POINTER_MASK: u8 = 0x77;
+/// Attribute bits inverted within the selection.
+const SELECTION_MASK: u8 = 0x70;
+
+/// Size of the staging buffer in characters.
+const STAGING_SIZE: usize = 1024;
+
+/// Largest scrollback in screens.
+pub const MAX_SCROLLBACK_PAGES: usize = 16;
+/// Screens kept in the scrollback until changed with "console.scrollback_pages".
+pub const DEFAULT_SCROLLBACK_PAGES: usize = 4;
+/// Amount of lines the scrollback can hold.
+const SCROLLBACK_CAPACITY: usize = MAX_SCROLLBACK_PAGES * BUFFER_HEIGHT;
+
+/// Index port of the CRT controller.
+const CRTC_INDEX: u16 = 0x3d4;
+/// Data port of the CRT controller.
+const CRTC_DATA: u16 = 0x3d5;
+/// CRT controller register with the first scan line of the cursor and the disable bit.
+const CURSOR_START: u8 = 0x0a;
+/// CRT controller register with the last scan line of the cursor.
+const CURSOR_END: u8 = 0x0b;
+/// CRT controller registers with the high and low byte of the cursor cell.
+const CURSOR_HIGH: u8 = 0x0e;
+const CURSOR_LOW: u8 = 0x0f;
+/// Disables the cursor when set in the cursor start register.
+const CURSOR_DISABLE: u8 = 1 << 5;
+
+/// Screens kept in the scrollback.
+static SCROLLBACK_PAGES: AtomicUsize = AtomicUsize::new(DEFAULT_SCROLLBACK_PAGES);
+
+/// Text buffer in memory, which replaces the VGA one when the output moves to a framebuffer.
+static mut SHADOW: Buffer = Buffer::blank();
+/// Text buffer in memory, which receives the output while the VGA one shows the scrollback.
+static mut LIVE: Buffer = Buffer::blank();
+/// Lines of the scrollback.
+static mut HISTORY: [[Char; BUFFER_WIDTH]; SCROLLBACK_CAPACITY] =
+    [[Char { ascii_char: b' ', color_code: ColorCode(0) }; BUFFER_WIDTH]; SCROLLBACK_CAPACITY];
 
 /// Creates a lazy initialization of a static Logger instance.
 single! {
-    pub LOGGER: Mutex<Logger> = Mutex::new(Logger {
+    pub LOGGER: IrqSpinlock<Logger> = IrqSpinlock::new(Logger {
         pos: 0,
-        color_code: ColorCode::new(Color::WHITE, Color::BLACK),
-        buf: unsafe { &mut *(BUFFER_ADDR as *mut Buffer) } 
+        color_code: ColorCode::styled(Theme::DEFAULT.text),
+        theme: Theme::DEFAULT,
+        buf: unsafe { &mut *(BUFFER_ADDR as *mut Buffer) },
+        pointer: None,
+        selection: None,
+        fb: None,
+        history: Scrollback::new(unsafe { &mut *ptr::addr_of_mut!(HISTORY) }),
+        view: 0,
     })
 }
 
 /// Represents the Logger structure responsible for writing to the VGA buffer.
+///
+/// # Overlays
+///
+/// The mouse pointer and the selection are drawn by inverting attribute bits of the cells. The
+/// overlays are hidden while the text is written or scrolled, so they never leave artifacts
+/// behind and always stay on the same screen cells. They are not drawn on the framebuffer.
 pub struct Logger {
     pos: usize,
     color_code: ColorCode,
+    /// Styles used by the printing macros.
+    theme: Theme,
     buf: &'static mut Buffer,
+    /// Cell under the mouse pointer.
+    pointer: Option<usize>,
+    /// Inclusive range of selected cells.
+    selection: Option<(usize, usize)>,
+    /// Console which shows the output when the screen is in a graphics mode.
+    fb: Option<FbConsole<PsfFont>>,
+    /// Lines scrolled off the top of the screen.
+    history: Scrollback,
+    /// Amount of lines the view is moved back into the scrollback, zero shows the output.
+    view: usize,
 }
 
 #[allow(dead_code)]
 impl Logger {
     /// Writes a single byte to the VGA buffer, handling newline characters.
     pub(self) fn write(&mut self, byte: u8) {
+        self.write_framebuffer(byte);
         match byte {
             b'\n' => self.new_line(),
             b'\x7f' => self.pos = 0,
+            b'\x08' => {
+                if self.pos > 0 {
+                    self.pos -= 1;
+                    self.buf.str[BUFFER_HEIGHT - 1][self.pos] = Char {
+                        ascii_char: b' ',
+                        color_code: self.color_code,
+                    };
+                }
+            },
             _ => {
                 if self.pos >= BUFFER_WIDTH {
                     self.new_line()
@@ -54,17 +162,242 @@ impl Logger {
 
     /// Writes a string to the VGA buffer using the `write` method.
     pub(self) fn write_str(&mut self, s: &str) {
+        self.toggle_overlays();
         for byte in s.bytes() {
             match byte {
-                0x20..=0x7e | b'\n' | b'\x7f' => self.write(byte),
+                0x20..=0x7e | b'\n' | b'\x7f' | b'\x08' => self.write(byte),
                 _ => self.write(0xfe),
             }
         }
+        self.toggle_overlays();
+        if let Some(fb) = &mut self.fb {
+            fb.flush();
+        }
+        self.update_cursor();
+    }
+
+    /// Writes the byte to the framebuffer console, unless it shows the scrollback.
+    fn write_framebuffer(&mut self, byte: u8) {
+        if self.view != 0 {
+            return
+        }
+        let Some(fb) = &mut self.fb else { return };
+        fb.set_style(self.color_code.style());
+        match byte {
+            b'\x7f' => fb.carriage_return(),
+            b'\x08' => fb.backspace(),
+            byte => fb.write_char(Char::glyph(byte)),
+        }
+    }
+
+    /// Moves the output to a console on the framebuffer.
+    ///
+    /// The text on the screen and in the scrollback so far is written to the console first. From
+    /// now on the text buffer is only kept in memory for the selection.
+    fn attach_framebuffer(&mut self, fb: LinearFramebuffer) {
+        self.scroll_view(-(self.view as isize));
+        let mut console = FbConsole::new(fb, DEFAULT_FONT);
+        self.toggle_overlays();
+        let shadow = unsafe { &mut *ptr::addr_of_mut!(SHADOW) };
+        shadow.str = self.buf.str;
+        self.buf = shadow;
+
+        self.replay(&mut console, 0);
+        self.fb = Some(console);
+        self.toggle_overlays();
+    }
+
+    /// Redraws the console with the lines ending the provided amount of lines above the bottom.
+    fn replay(&self, console: &mut FbConsole<PsfFont>, view: usize) {
+        let end = self.history.len + BUFFER_HEIGHT - view;
+        let start = end.saturating_sub(console.size().1);
+
+        // Text is always written to the bottom line, so the blank lines at the top are skipped.
+        let blank = |c: &Char| c.ascii_char == b' ';
+        let first = (start..end).find(|&line| !self.line(line).iter().all(blank)).unwrap_or(end - 1);
+        console.set_style(self.color_code.style());
+        console.clear();
+        for line in first..end {
+            if line != first {
+                console.write_char('\n');
+            }
+            let chars = self.line(line);
+            let len = match line {
+                line if view == 0 && line == end - 1 => self.pos,
+                _ => chars.iter().rposition(|c| !blank(c)).map_or(0, |last| last + 1),
+            };
+            for c in &chars[..len] {
+                console.set_style(c.color_code.style());
+                console.write_char(Char::glyph(c.ascii_char));
+            }
+        }
+        console.set_style(self.color_code.style());
+        console.flush();
     }
 
-    /// Changes the color of the text in the VGA buffer.
-    pub(self) fn change_color(&mut self, fr: Color, bg: Color) {
-        self.color_code = ColorCode::new(fr, bg);
+    /// Returns the line of the scrollback followed by the screen, the oldest line is zero.
+    fn line(&self, line: usize) -> &[Char; BUFFER_WIDTH] {
+        match line.checked_sub(self.history.len) {
+            Some(row) => &self.buf.str[row],
+            None => self.history.line(self.history.len - line).unwrap(),
+        }
+    }
+
+    /// Moves the view by the amount of lines, back into the scrollback if positive.
+    pub fn scroll_view(&mut self, lines: isize) {
+        let view = self.view.saturating_add_signed(lines).min(self.history.len);
+        if view == self.view {
+            return
+        }
+
+        if self.view == 0 && self.fb.is_none() {
+            // The output goes on in memory, while the VGA buffer shows the scrollback.
+            let live = unsafe { &mut *ptr::addr_of_mut!(LIVE) };
+            live.str = self.buf.str;
+            self.buf = live;
+        }
+        self.view = view;
+
+        match (self.fb.take(), view) {
+            (Some(mut fb), view) => {
+                self.replay(&mut fb, view);
+                self.fb = Some(fb);
+            },
+            (None, 0) => {
+                let screen = unsafe { &mut *(BUFFER_ADDR as *mut Buffer) };
+                screen.str = self.buf.str;
+                self.buf = screen;
+            },
+            (None, view) => {
+                let screen = unsafe { &mut *(BUFFER_ADDR as *mut Buffer) };
+                let end = self.history.len + BUFFER_HEIGHT - view;
+                for (row, line) in (end - BUFFER_HEIGHT..end).enumerate() {
+                    screen.str[row] = *self.line(line);
+                }
+            },
+        }
+        self.update_cursor();
+    }
+
+    /// Returns the amount of lines moved by a page up or down, one less than the screen has.
+    fn page_lines(&self) -> usize {
+        self.fb.as_ref().map_or(BUFFER_HEIGHT, |fb| fb.size().1).max(2) - 1
+    }
+
+    /// Limits the scrollback to the amount of screens.
+    fn set_scrollback_pages(&mut self, pages: usize) {
+        SCROLLBACK_PAGES.store(pages.min(MAX_SCROLLBACK_PAGES), Ordering::Relaxed);
+        self.scroll_view(-(self.view as isize));
+        self.history.truncate(pages * BUFFER_HEIGHT);
+    }
+
+    /// Moves the hardware cursor behind the last written character, or hides it while the
+    /// scrollback is shown. Does nothing on the framebuffer.
+    fn update_cursor(&self) {
+        if self.fb.is_some() {
+            return
+        }
+        let index = GenericPort::<u8>::new(CRTC_INDEX, PortAccessType::WRITEONLY);
+        let data = GenericPort::<u8>::new(CRTC_DATA, PortAccessType::READWRITE);
+        let mut write = |register, value| {
+            index.write(register);
+            data.write(value);
+        };
+
+        if self.view != 0 {
+            return write(CURSOR_START, CURSOR_DISABLE)
+        }
+        // Underline cursor on the last two scan lines of the 16 lines high cells.
+        write(CURSOR_START, 14);
+        write(CURSOR_END, 15);
+        let cell = (BUFFER_HEIGHT - 1) * BUFFER_WIDTH + self.pos.min(BUFFER_WIDTH - 1);
+        write(CURSOR_HIGH, (cell >> 8) as u8);
+        write(CURSOR_LOW, cell as u8);
+    }
+
+    /// Writes characters with their own colors, which were staged while the logger was busy.
+    fn write_staged(&mut self, chars: &[Char]) {
+        let color_code = self.color_code;
+        self.toggle_overlays();
+        for c in chars {
+            self.color_code = c.color_code;
+            self.write(c.ascii_char);
+        }
+        self.toggle_overlays();
+        self.color_code = color_code;
+        if let Some(fb) = &mut self.fb {
+            fb.flush();
+        }
+        self.update_cursor();
+    }
+
+    /// Moves the mouse pointer to the provided cell. None hides the pointer.
+    pub fn set_pointer(&mut self, cell: Option<usize>) {
+        self.toggle_overlays();
+        self.pointer = cell.filter(|&c| c < BUFFER_WIDTH * BUFFER_HEIGHT);
+        self.toggle_overlays();
+    }
+
+    /// Highlights the inclusive range of cells. None clears the selection.
+    pub fn set_selection(&mut self, range: Option<(usize, usize)>) {
+        self.toggle_overlays();
+        self.selection = range.map(|(start, end)| (start.min(end), start.max(end).min(BUFFER_WIDTH * BUFFER_HEIGHT - 1)));
+        self.toggle_overlays();
+    }
+
+    /// Returns the text within the selection.
+    ///
+    /// Trailing spaces of each row are dropped and rows are separated with new lines.
+    pub fn selected_text(&self) -> String {
+        let mut text = String::new();
+        let Some((start, end)) = self.selection else { return text };
+
+        for row in start / BUFFER_WIDTH..=end / BUFFER_WIDTH {
+            let from = if row == start / BUFFER_WIDTH { start % BUFFER_WIDTH } else { 0 };
+            let to = if row == end / BUFFER_WIDTH { end % BUFFER_WIDTH } else { BUFFER_WIDTH - 1 };
+
+            if row != start / BUFFER_WIDTH {
+                text.push('\n');
+            }
+            let line: String = self.buf.str[row][from..=to].iter().map(|c| c.ascii_char as char).collect();
+            text.push_str(line.trim_end());
+        }
+        text
+    }
+
+    /// Inverts attribute bits of all overlays. Applying it twice restores the original cells.
+    fn toggle_overlays(&mut self) {
+        if let Some(cell) = self.pointer {
+            self.xor_cell(cell, POINTER_MASK);
+        }
+        if let Some((start, end)) = self.selection {
+            for cell in start..=end {
+                self.xor_cell(cell, SELECTION_MASK);
+            }
+        }
+    }
+
+    fn xor_cell(&mut self, cell: usize, mask: u8) {
+        let c = &mut self.buf.str[cell / BUFFER_WIDTH][cell % BUFFER_WIDTH];
+        c.color_code = ColorCode(c.color_code.0 ^ mask);
+    }
+
+    /// Changes the style of the following text in the VGA buffer.
+    pub(self) fn change_style(&mut self, style: Style) {
+        self.color_code = ColorCode::styled(style);
+    }
+
+    /// Replaces the theme used by the printing macros.
+    ///
+    /// Text already on the screen keeps it's colors, only the following output is affected.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        self.color_code = ColorCode::styled(theme.text);
+    }
+
+    /// Returns the current theme.
+    pub fn theme(&self) -> Theme {
+        self.theme
     }
     
     fn prev_line(&mut self) {
@@ -79,6 +412,13 @@ impl Logger {
     }
 
     fn new_line(&mut self) {
+        let limit = SCROLLBACK_PAGES.load(Ordering::Relaxed) * BUFFER_HEIGHT;
+        self.history.push(self.buf.str[0], limit);
+        // The view stays on the same lines, while the output moves on.
+        if self.view != 0 {
+            self.view = (self.view + 1).min(self.history.len);
+        }
+
         for row in 1..BUFFER_HEIGHT {
             for col in 0..BUFFER_WIDTH {
                 let character: Char = self.buf.str[row][col];
@@ -132,6 +472,161 @@ pub enum Color {
     WHITE = 15,
 }
 
+impl Color {
+    /// Every color, indexed by it's value.
+    const ALL: [Color; 16] = [
+        Color::BLACK, Color::BLUE, Color::GREEN, Color::CYAN,
+        Color::RED, Color::MAGENTA, Color::BROWN, Color::LIGHTGRAY,
+        Color::DARKGRAY, Color::LIGHTBLUE, Color::LIGHTGREEN, Color::LIGHTCYAN,
+        Color::LIGHTRED, Color::PINK, Color::YELLOW, Color::WHITE,
+    ];
+}
+
+bitflags! {
+    /// Text attributes of the VGA text mode, which are encoded within the color code.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Attributes: u8 {
+        /// Bright variant of the foreground color, which is how the text mode shows bold text.
+        const BOLD = 1 << 3,
+        /// Blinking text. With blinking disabled in the attribute controller, the hardware shows
+        /// a bright background instead.
+        const BLINK = 1 << 7,
+    }
+}
+
+/// Foreground, background and attributes of printed text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub foreground: Color,
+    pub background: Color,
+    pub attributes: Attributes,
+}
+
+impl Style {
+    /// Creates a new style without any attributes.
+    pub const fn new(foreground: Color, background: Color) -> Self {
+        Self { foreground, background, attributes: Attributes::EMPTY }
+    }
+
+    /// Returns the same style with the provided foreground.
+    pub const fn foreground(self, foreground: Color) -> Self {
+        Self { foreground, ..self }
+    }
+
+    /// Returns the same style with the provided background.
+    pub const fn background(self, background: Color) -> Self {
+        Self { background, ..self }
+    }
+
+    /// Returns the same style with bold text.
+    pub const fn bold(self) -> Self {
+        self.attributes(Attributes::BOLD)
+    }
+
+    /// Returns the same style with blinking text.
+    pub const fn blink(self) -> Self {
+        self.attributes(Attributes::BLINK)
+    }
+
+    /// Returns the same style with the attributes added.
+    pub const fn attributes(self, attributes: Attributes) -> Self {
+        Self {
+            attributes: Attributes::Custom(self.attributes.bits() | attributes.bits()),
+            ..self
+        }
+    }
+}
+
+/// Levels of kernel messages, each printed with it's own style of the [`Theme`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Success,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// Every level, from the most to the least severe.
+    pub const ALL: [LogLevel; 6] = [
+        LogLevel::Error, LogLevel::Warning, LogLevel::Success, LogLevel::Info, LogLevel::Debug, LogLevel::Trace,
+    ];
+
+    /// Name used by the tunables and the serial output.
+    pub const fn name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warning => "warn",
+            LogLevel::Success => "success",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+
+    /// Parses the name of the level.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "warning" => Some(LogLevel::Warning),
+            name => Self::ALL.into_iter().find(|level| level.name() == name),
+        }
+    }
+}
+
+/// Styles of the printing macros.
+///
+/// Plain [`print!`] and [`println!`] use the text style, while explicit colors only override it's
+/// foreground and background. The [`log!`] family of macros picks the style of the message level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// Style of plain text.
+    pub text: Style,
+    pub error: Style,
+    pub warning: Style,
+    pub success: Style,
+    pub info: Style,
+    pub debug: Style,
+    pub trace: Style,
+}
+
+impl Theme {
+    /// Theme used since boot.
+    pub const DEFAULT: Theme = Theme {
+        text: Style::new(Color::WHITE, Color::BLACK),
+        error: Style::new(Color::RED, Color::BLACK),
+        warning: Style::new(Color::YELLOW, Color::BLACK),
+        success: Style::new(Color::GREEN, Color::BLACK),
+        info: Style::new(Color::LIGHTBLUE, Color::BLACK),
+        debug: Style::new(Color::LIGHTCYAN, Color::BLACK),
+        trace: Style::new(Color::LIGHTGRAY, Color::BLACK),
+    };
+
+    /// Light theme, more readable on bright screens.
+    pub const LIGHT: Theme = Theme {
+        text: Style::new(Color::BLACK, Color::LIGHTGRAY),
+        error: Style::new(Color::RED, Color::LIGHTGRAY),
+        warning: Style::new(Color::BROWN, Color::LIGHTGRAY),
+        success: Style::new(Color::GREEN, Color::LIGHTGRAY),
+        info: Style::new(Color::BLUE, Color::LIGHTGRAY),
+        debug: Style::new(Color::CYAN, Color::LIGHTGRAY),
+        trace: Style::new(Color::DARKGRAY, Color::LIGHTGRAY),
+    };
+
+    /// Returns the style of messages with the provided level.
+    pub const fn style(&self, level: LogLevel) -> Style {
+        match level {
+            LogLevel::Error => self.error,
+            LogLevel::Warning => self.warning,
+            LogLevel::Success => self.success,
+            LogLevel::Info => self.info,
+            LogLevel::Debug => self.debug,
+            LogLevel::Trace => self.trace,
+        }
+    }
+}
+
 /// The color code structure is a number that contain a pair of background and 
 /// foreground that decide which colors will be used on printing. 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -141,9 +636,20 @@ struct ColorCode(u8);
 impl ColorCode {
     /// Creates a new ColorCode, in which fr is a foreground color and bg is a background.
     /// Arguments must be values from Color enum in order to properly generate a real color pair
-    fn new(fr: Color, bg: Color) -> ColorCode {
+    const fn new(fr: Color, bg: Color) -> ColorCode {
         ColorCode((bg as u8) << 4 | (fr as u8))
     }
+
+    /// Creates a ColorCode of the style, with the attribute bits set on top of the colors.
+    const fn styled(style: Style) -> ColorCode {
+        ColorCode(Self::new(style.foreground, style.background).0 | style.attributes.bits())
+    }
+
+    /// Returns the colors the text mode shows for the color code. Bold text and blinking, which
+    /// is shown as a bright background, become the bright colors.
+    const fn style(self) -> Style {
+        Style::new(Color::ALL[(self.0 & 0xf) as usize], Color::ALL[(self.0 >> 4) as usize])
+    }
 }
 
 /// A character representation in a VGA buffer.
@@ -154,12 +660,145 @@ struct Char {
     color_code: ColorCode,
 }
 
+impl Char {
+    /// Returns the character shown for the byte of the text buffer.
+    fn glyph(byte: u8) -> char {
+        match byte {
+            0xfe => char::REPLACEMENT_CHARACTER,
+            byte => byte as char,
+        }
+    }
+}
+
 /// A buffer that represents a whole string for printing.
 #[repr(transparent)]
 struct Buffer {
     str: [[Char; BUFFER_WIDTH]; BUFFER_HEIGHT]
 }
 
+impl Buffer {
+    const fn blank() -> Self {
+        Self { str: [[Char { ascii_char: b' ', color_code: ColorCode(0) }; BUFFER_WIDTH]; BUFFER_HEIGHT] }
+    }
+}
+
+/// Ring of the lines scrolled off the top of the screen.
+struct Scrollback {
+    lines: &'static mut [[Char; BUFFER_WIDTH]],
+    /// Slot of the next line.
+    head: usize,
+    len: usize,
+}
+
+impl Scrollback {
+    const fn new(lines: &'static mut [[Char; BUFFER_WIDTH]]) -> Self {
+        Self { lines, head: 0, len: 0 }
+    }
+
+    /// Adds the line, dropping the oldest ones above the limit.
+    fn push(&mut self, line: [Char; BUFFER_WIDTH], limit: usize) {
+        let capacity = self.lines.len();
+        if limit == 0 || capacity == 0 {
+            self.len = 0;
+            return
+        }
+        self.lines[self.head] = line;
+        self.head = (self.head + 1) % capacity;
+        self.len = (self.len + 1).min(limit).min(capacity);
+    }
+
+    /// Returns the line the amount of lines above the screen, the latest one is 1.
+    fn line(&self, back: usize) -> Option<&[Char; BUFFER_WIDTH]> {
+        if back == 0 || back > self.len {
+            return None
+        }
+        let capacity = self.lines.len();
+        Some(&self.lines[(self.head + capacity - back) % capacity])
+    }
+
+    /// Drops the oldest lines above the limit.
+    fn truncate(&mut self, limit: usize) {
+        self.len = self.len.min(limit);
+    }
+}
+
+/// Output which could not be written to the screen immediately.
+///
+/// Every CPU stages into it's own instance, see [`staging`]. Interrupts are disabled while it is
+/// accessed, the busy flag protects it from NMIs and exceptions and from a flush running on another
+/// CPU.
+struct Staging {
+    busy: AtomicBool,
+    inner: UnsafeCell<StagingBuffer>,
+}
+
+struct StagingBuffer {
+    chars: [Char; STAGING_SIZE],
+    len: usize,
+    /// Characters dropped because the buffer was full.
+    dropped: usize,
+}
+
+unsafe impl Sync for Staging {}
+
+static STAGING: [Staging; MAX_CPUS] = [const {
+    Staging {
+        busy: AtomicBool::new(false),
+        inner: UnsafeCell::new(StagingBuffer {
+            chars: [Char { ascii_char: 0, color_code: ColorCode(0) }; STAGING_SIZE],
+            len: 0,
+            dropped: 0,
+        }),
+    }
+}; MAX_CPUS];
+
+/// Returns the staging buffer of the current CPU.
+fn staging() -> &'static Staging {
+    &STAGING[smp::cpu_id()]
+}
+
+impl Staging {
+    /// Runs the function on the buffer. Returns None if the buffer is already in use on this CPU.
+    fn with<T>(&self, f: impl FnOnce(&mut StagingBuffer) -> T) -> Option<T> {
+        critical_section!(|| {
+            if self.busy.swap(true, Ordering::Acquire) {
+                return None
+            }
+            let output = f(unsafe { &mut *self.inner.get() });
+            self.busy.store(false, Ordering::Release);
+            Some(output)
+        })
+    }
+}
+
+impl StagingBuffer {
+    /// Appends the formatted output with the color code.
+    fn push(&mut self, color_code: ColorCode, args: fmt::Arguments) {
+        struct Writer<'a>(&'a mut StagingBuffer, ColorCode);
+
+        impl fmt::Write for Writer<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                for byte in s.bytes() {
+                    let ascii_char = match byte {
+                        0x20..=0x7e | b'\n' | b'\x7f' | b'\x08' => byte,
+                        _ => 0xfe,
+                    };
+                    match self.0.chars.get_mut(self.0.len) {
+                        Some(c) => {
+                            *c = Char { ascii_char, color_code: self.1 };
+                            self.0.len += 1;
+                        },
+                        None => self.0.dropped += 1,
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        let _ = fmt::Write::write_fmt(&mut Writer(self, color_code), args);
+    }
+}
+
 /// # Macros
 
 /// Prints the content to the screen via VGA buffer. It does support coloring
@@ -169,22 +808,25 @@ struct Buffer {
 /// If needed the coloring can be changed with Color enum. The colors are separated
 /// with ; while other args are separated with a comma. The first color is always considered
 /// as a foreground while the second is considered as background. By default it uses white foreground
-/// and black background
+/// and black background of the default [`Theme`]. A whole [`Style`] with attributes is provided
+/// before =>.
 /// 
 /// # Examples
 /// ''' 
-/// use notOS::{print, Color};
+/// use notOS::{print, Color, Style};
 /// 
 /// fn main() -> ! {
 ///     print!(Color::BLUE; "This text's foreground will be blue. {}", "Yes it is indeed.\n");
 ///     print!(Color::RED; Color::GREEN; "I am red and angry, but green inside.\n");
 ///     print!("I am the most default dude out there.");
+///     print!(Style::new(Color::WHITE, Color::BLUE).bold().blink() => "Look at me!\n");
 ///
 ///     loop {}
 /// }
 /// '''
 #[macro_export]
 macro_rules! print {
+    ($style:expr => $($arg:tt)*) => ($crate::kernel_components::vga_buffer::_print_style($style, format_args!($($arg)*)));
     ($fr:expr; $bg:expr; $($arg:tt)*) => ($crate::kernel_components::vga_buffer::_print(Some($fr), Some($bg), format_args!($($arg)*)));
     ($fr:expr; $($arg:tt)*) => ($crate::kernel_components::vga_buffer::_print(Some($fr), None, format_args!($($arg)*)));
     ($($arg:tt)*) => ($crate::kernel_components::vga_buffer::_print(None, None, format_args!($($arg)*)));
@@ -216,29 +858,62 @@ macro_rules! println {
     ($fmt:expr) => ($crate::print!(concat!($fmt, '\n')));
     ($fmt:expr, $($arg:tt)*) => ($crate::print!(concat!($fmt, '\n'), $($arg)*)); 
     ($fr:expr; $fmt:expr) => ($crate::print!($fr; concat!($fmt, '\n')));
-    ($fr:expr; $bg:expr; $fmt:expr) => ($crate::print!($fr, $bg, concat!($fmt, '\n')));
+    ($style:expr => $fmt:expr) => ($crate::print!($style => concat!($fmt, '\n')));
+    ($style:expr => $fmt:expr, $($arg:tt)*) => ($crate::print!($style => concat!($fmt, '\n'), $($arg)*));
+    ($fr:expr; $bg:expr; $fmt:expr) => ($crate::print!($fr; $bg; concat!($fmt, '\n')));
     ($fr:expr; $fmt:expr, $($arg:tt)*) => ($crate::print!($fr; concat!($fmt, '\n'), $($arg)*));   
     ($fr:expr; $bg:expr; $fmt:expr, $($arg:tt)*) => ($crate::print!($fr; $bg; concat!($fmt, '\n'), $($arg)*)); 
 }
 
-/// Writes an error message to the screen in red.
+/// Logs a message with the level and moves the cursor to new line.
 ///
-/// Works like println, but do not accept the color argument. The output will always be in red.
+/// The message is a log record of the calling module, which reaches the screen with the style of
+/// the level in the current [`Theme`] and the other sinks, unless filtered. Call sites disabled
+/// by the compile time filters are left out of the build. See the logging module for details.
+///
+/// # Examples
+/// '''
+/// use notOS::log;
+///
+/// log!(Success; "[ok]");
+/// log!(Error; "{}: command not found", "foo");
+/// '''
+#[macro_export]
+macro_rules! log {
+    ($level:ident; $fmt:expr) => ($crate::log!($level; $fmt,));
+    ($level:ident; $fmt:expr, $($arg:tt)*) => ({
+        const ENABLED: bool = $crate::kernel_components::logging::static_enabled(
+            $crate::kernel_components::vga_buffer::LogLevel::$level,
+            module_path!(),
+        );
+        if ENABLED {
+            $crate::kernel_components::logging::_log(
+                $crate::kernel_components::vga_buffer::LogLevel::$level,
+                module_path!(),
+                format_args!(concat!($fmt, '\n'), $($arg)*),
+            )
+        }
+    });
+}
+
+/// Writes an error message to the screen with the error style of the theme.
+///
+/// Works like println, but do not accept the color argument.
 #[macro_export]
 macro_rules! error {
     () => ($crate::println!('\n'));
-    ($fmt:expr) => ($crate::println!($crate::Color::RED; concat!("ERROR!! " ,$fmt, '\n')));
-    ($fmt:expr, $($arg:tt)*) => ($crate::println!($crate::Color::RED; concat!("ERROR!! ", $fmt, '\n'), $($arg)*));
+    ($fmt:expr) => ($crate::log!(Error; concat!("ERROR!! " ,$fmt, '\n')));
+    ($fmt:expr, $($arg:tt)*) => ($crate::log!(Error; concat!("ERROR!! ", $fmt, '\n'), $($arg)*));
 }
 
-/// Writes a warning message to the screen in yellow.
+/// Writes a warning message to the screen with the warning style of the theme.
 /// 
-/// Works like println, but do not accept the color argument. The output will always be in yellow.
+/// Works like println, but do not accept the color argument.
 #[macro_export]
 macro_rules! warn {
     () => ($crate::println!('\n'));
-    ($fmt:expr) => ($crate::println!($crate::Color::YELLOW; concat!("WARNING! " ,$fmt, '\n')));
-    ($fmt:expr, $($arg:tt)*) => ($crate::println!($crate::Color::YELLOW; concat!("WARNING! ", $fmt, '\n'), $($arg)*));
+    ($fmt:expr) => ($crate::log!(Warning; concat!("WARNING! " ,$fmt, '\n')));
+    ($fmt:expr, $($arg:tt)*) => ($crate::log!(Warning; concat!("WARNING! ", $fmt, '\n'), $($arg)*));
 }
 
 /// A fast macro to show the debug information about the item (in pretty print).
@@ -249,33 +924,227 @@ macro_rules! debug {
     () => ();
     ($fmt:expr) => (
        #[cfg(debug_assertions)]
-        $crate::println!($crate::Color::LIGHTCYAN; concat!("DEBUG: " ,$fmt))
+        $crate::log!(Debug; concat!("DEBUG: " ,$fmt))
     );
     ($fmt:expr, $($arg:tt)*) => (
         #[cfg(debug_assertions)]
-        $crate::println!($crate::Color::LIGHTCYAN; concat!("DEBUG: ", $fmt), $($arg)*)
+        $crate::log!(Debug; concat!("DEBUG: ", $fmt), $($arg)*)
     );
 }
 
+/// Prints from an interrupt handler and moves the cursor to new line.
+///
+/// The output is copied into the staging buffer and written to the screen later by the worker
+/// thread, so the handler never waits for the logger nor scrolls the screen. An optional log
+/// level selects the style of the current theme.
+///
+/// # Examples
+/// '''
+/// use notOS::isr_println;
+///
+/// isr_println!("Spurious interrupt on IRQ {}", 7);
+/// isr_println!(Warning; "Keyboard input ignored.");
+/// '''
+#[macro_export]
+macro_rules! isr_println {
+    ($level:ident; $fmt:expr) => ($crate::isr_println!($level; $fmt,));
+    ($level:ident; $fmt:expr, $($arg:tt)*) => (
+        $crate::kernel_components::vga_buffer::_isr_print(
+            Some($crate::kernel_components::vga_buffer::LogLevel::$level),
+            format_args!(concat!($fmt, '\n'), $($arg)*),
+        )
+    );
+    ($fmt:expr) => ($crate::isr_println!($fmt,));
+    ($fmt:expr, $($arg:tt)*) => (
+        $crate::kernel_components::vga_buffer::_isr_print(None, format_args!(concat!($fmt, '\n'), $($arg)*))
+    );
+}
+
+/// Moves the screen output to a console on the framebuffer.
+///
+/// Used when the bootloader set up a graphics mode, which leaves no VGA text buffer behind.
+pub fn use_framebuffer(fb: LinearFramebuffer) {
+    LOGGER.lock().attach_framebuffer(fb);
+}
+
+/// Moves the view one screen back into the scrollback.
+pub fn page_up() {
+    let mut logger = LOGGER.lock();
+    let lines = logger.page_lines();
+    logger.scroll_view(lines as isize);
+}
+
+/// Moves the view one screen forward towards the output.
+pub fn page_down() {
+    let mut logger = LOGGER.lock();
+    let lines = logger.page_lines();
+    logger.scroll_view(-(lines as isize));
+}
+
+/// Returns the view from the scrollback to the output.
+pub fn scroll_to_bottom() {
+    let mut logger = LOGGER.lock();
+    let view = logger.view;
+    logger.scroll_view(-(view as isize));
+}
+
+/// Limits the scrollback to the amount of screens, at most [`MAX_SCROLLBACK_PAGES`].
+pub fn set_scrollback_pages(pages: usize) {
+    LOGGER.lock().set_scrollback_pages(pages);
+}
+
+/// Registers Shift+PageUp and Shift+PageDown to move through the scrollback.
+pub fn register_shortcuts() {
+    register_shortcut(ShortcutModifiers::SHIFT, Key::PageUp, page_up);
+    register_shortcut(ShortcutModifiers::SHIFT, Key::PageDown, page_down);
+}
+
+/// Replaces the theme of the screen logger.
+pub fn set_theme(theme: Theme) {
+    LOGGER.lock().set_theme(theme);
+}
+
+/// Returns the theme of the screen logger.
+pub fn theme() -> Theme {
+    LOGGER.lock().theme()
+}
+
+/// Returns the theme without waiting for the logger. The default one is used while it is busy.
+fn current_theme() -> Theme {
+    LOGGER.try_lock().map(|logger| logger.theme).unwrap_or(Theme::DEFAULT)
+}
+
 #[doc(hidden)]
 pub fn _print(fr: Option<Color>, bg: Option<Color>, args: fmt::Arguments) {
+    let mut style = current_theme().text;
+    if let Some(fr) = fr {
+        style = style.foreground(fr);
+    }
+    if let Some(bg) = bg {
+        style = style.background(bg);
+    }
+    _print_style(style, args);
+}
+
+#[doc(hidden)]
+pub fn _print_level(level: LogLevel, args: fmt::Arguments) {
+    _print_style(current_theme().style(level), args);
+}
+
+#[doc(hidden)]
+pub fn _print_style(style: Style, args: fmt::Arguments) {
     use core::fmt::Write;
-    critical_section!(|| {
-        _coloring(fr, bg);
-        LOGGER.lock().write_fmt(args).unwrap();
-        _coloring(None, None);
+
+    let Some(mut logger) = LOGGER.try_lock() else {
+        return _stage(style, args)
+    };
+    flush_staged_into(&mut logger);
+    logger.change_style(style);
+    logger.write_fmt(args).unwrap();
+    let text = logger.theme.text;
+    logger.change_style(text);
+}
+
+/// Stages the output to be written later by the worker thread. Never waits for the logger.
+#[doc(hidden)]
+pub fn _stage(style: Style, args: fmt::Arguments) {
+    let was_empty = staging().with(|staging| {
+        let was_empty = staging.len == 0 && staging.dropped == 0;
+        staging.push(ColorCode::styled(style), args);
+        was_empty
     });
+    // The flush is scheduled once, until it runs the output is only appended.
+    if was_empty == Some(true) {
+        workqueue::schedule(flush_staged);
+    }
 }
 
 #[doc(hidden)]
-pub fn _coloring(fr: Option<Color>, bg: Option<Color>) {
-    if let Some(bg) = bg {
-        LOGGER.lock().change_color(fr.unwrap(), bg);        
-    } else {
-        if let Some(fr) = fr {
-            LOGGER.lock().change_color(fr, Color::BLACK);
-        } else {
-            LOGGER.lock().change_color(Color::WHITE, Color::BLACK);
-        }
+pub fn _isr_print(level: Option<LogLevel>, args: fmt::Arguments) {
+    let theme = current_theme();
+    _stage(level.map(|level| theme.style(level)).unwrap_or(theme.text), args);
+}
+
+/// Writes the staged output of every CPU to the screen.
+pub fn flush_staged() {
+    if let Some(mut logger) = LOGGER.try_lock() {
+        flush_staged_into(&mut logger);
     }
 }
+
+/// Writes the staged output of every CPU with the logger. A buffer which is in use right now is
+/// flushed again later, as it's next output would not schedule the flush.
+fn flush_staged_into(logger: &mut Logger) {
+    let mut busy = false;
+    for staging in STAGING.iter().take(smp::cpu_count().max(1)) {
+        busy |= staging.with(|staging| {
+            if staging.len == 0 && staging.dropped == 0 {
+                return
+            }
+
+            logger.write_staged(&staging.chars[..staging.len]);
+            if staging.dropped != 0 {
+                use core::fmt::Write;
+                let _ = writeln!(logger, "[{} characters of staged output dropped]", staging.dropped);
+            }
+            staging.len = 0;
+            staging.dropped = 0;
+        }).is_none();
+    }
+    if busy {
+        workqueue::schedule(flush_staged);
+    }
+}
+
+#[test_case]
+fn theme_styles_are_encoded() {
+    let style = Style::new(Color::RED, Color::BLUE);
+    assert_eq!(ColorCode::styled(style).0, 0x14);
+    assert_eq!(ColorCode::styled(style.bold()).0, 0x1c);
+    assert_eq!(ColorCode::styled(style.bold().blink()).0, 0x9c);
+    assert_eq!(Theme::DEFAULT.style(LogLevel::Error), Theme::DEFAULT.error);
+
+    let previous = theme();
+    set_theme(Theme::LIGHT);
+    assert_eq!(theme(), Theme::LIGHT);
+    crate::log!(Info; "themed");
+    assert_eq!(LOGGER.lock().color_code, ColorCode::styled(Theme::LIGHT.text));
+    set_theme(previous);
+}
+
+#[test_case]
+fn contended_output_is_staged() {
+    flush_staged();
+    {
+        let _logger = LOGGER.lock();
+        crate::println!("staged");
+        crate::isr_println!(Warning; "staged from an interrupt handler");
+        assert_eq!(staging().with(|s| s.len), Some(40));
+    }
+    flush_staged();
+    assert_eq!(staging().with(|s| s.len), Some(0));
+}
+
+#[test_case]
+fn scrollback_keeps_the_latest_lines() {
+    let line = |byte| [Char { ascii_char: byte, color_code: ColorCode(0) }; BUFFER_WIDTH];
+    let mut history = Scrollback::new(alloc::vec![line(b' '); 4].leak());
+    history.push(line(b'x'), 0);
+    assert!(history.line(1).is_none());
+
+    // The limit and the capacity both drop the oldest lines.
+    for byte in b'a'..=b'j' {
+        history.push(line(byte), 3);
+    }
+    assert_eq!(history.len, 3);
+    assert_eq!(history.line(1).map(|line| line[0].ascii_char), Some(b'j'));
+    assert_eq!(history.line(3).map(|line| line[0].ascii_char), Some(b'h'));
+    assert!(history.line(0).is_none() && history.line(4).is_none());
+
+    history.push(line(b'k'), 8);
+    history.push(line(b'l'), 8);
+    assert_eq!((history.len, history.line(4).map(|line| line[0].ascii_char)), (4, Some(b'i')));
+
+    history.truncate(1);
+    assert!(history.line(2).is_none());
+}
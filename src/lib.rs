@@ -48,10 +48,51 @@ pub mod programs;
 pub mod kernel_components {
     /// I/O operation on VGA buffer (Basic TUI)
     pub mod vga_buffer;
+    /// Kernel log layer with per call site rate limiting and collapsing of repeated messages.
+    pub mod klog;
+    /// Log records with levels and module targets, dispatched to pluggable sinks.
+    pub mod logging;
+    /// Lock free ring buffer of timestamped kernel log lines.
+    pub mod dmesg;
+    /// Lock free and allocation free output used by panic, double fault and NMI paths.
+    pub mod emergency;
     /// OS specific helper types.
     pub mod os;
+    /// Crate-wide kernel error type with errno codes.
+    pub mod error;
+    /// Runtime configuration registry for subsystem tunables.
+    pub mod sysctl;
+    /// Exported kernel symbol table used to resolve references of loadable modules.
+    pub mod ksyms;
+    /// Full kernel symbol table embedded after linking, used to symbolize backtraces.
+    pub mod kallsyms;
+    /// Test runner with expected panics and a per test watchdog.
+    pub mod testing;
+    /// Booting a new kernel image from the running one.
+    pub mod kexec;
+    /// Hardware inventory report combining CPUID, SMBIOS, ACPI, PCI and driver bindings.
+    pub mod hwinfo;
     /// Defines keyboard readonly interface for user-space programs to use. 
     pub mod keyboard_interface;
+    /// Canonical mode line discipline between the keyboard input and terminal readers.
+    pub mod tty;
+    /// Kernel clipboard shared between terminals.
+    pub mod clipboard;
+    /// Graphical output on linear framebuffers.
+    pub mod gfx {
+        /// Linear framebuffer access.
+        pub mod framebuffer;
+        /// Text console on a linear framebuffer with cheap scrolling.
+        pub mod fb_console;
+        /// PC Screen Fonts of the framebuffer console.
+        pub mod font;
+
+        pub use framebuffer::{LinearFramebuffer, Rect, PALETTE};
+        pub use fb_console::{FbConsole, Font};
+        pub use font::{PsfFont, PsfError, DEFAULT_FONT};
+    }
+    /// Snapshots of the console output sent to the serial port for bug reports.
+    pub mod console;
 
     /// Custom data structures and types for operating on OS resources.
     ///
@@ -104,6 +145,22 @@ pub mod kernel_components {
         pub mod ports;
         /// Manipulations with the Transition Lookaside Buffer.
         pub mod TLB;
+        /// PCI configuration space access and bus enumeration.
+        pub mod pci;
+        /// Hypervisor detection and paravirtual interfaces.
+        pub mod hypervisor;
+        /// System Management BIOS tables: system, board and memory module descriptions.
+        pub mod smbios;
+        /// CPU frequency scaling through HWP or ACPI P-states with load based governors.
+        pub mod cpufreq;
+        /// Application processor bringup through INIT/SIPI and the processor numbering.
+        pub mod smp;
+        /// TLB shootdown of the other processors through IPIs.
+        pub mod shootdown;
+        /// Thermal throttling policy reacting to the thermal sensor and the ACPI thermal zones.
+        pub mod thermal;
+        /// Stack walking through the saved frame pointers, used by the fault handlers.
+        pub mod backtrace;
 
         /// This module defines all ACPI related structures and procedures.
         ///
@@ -122,6 +179,10 @@ pub mod kernel_components {
             /// Main ACPI tables that defines hardware features and allows to manipulate with it
             /// via it's mapped registers. 
             pub mod fadt;
+            /// Multiple APIC description table, which describes the interrupt controllers.
+            pub mod madt;
+            /// Sleep states entered through the PM1 control registers.
+            pub mod sleep;
 
             /// This module defines differentiated ACPI tables and AML language interpreter.
             pub mod diff {
@@ -133,6 +194,9 @@ pub mod kernel_components {
                     mod aml_parser;
                     /// Defines AML definitions, like Names, Scopes, Aliases, etc.
                     mod definitions;
+                    /// Deterministic fuzzing harness, which feeds mutated AML to the parser.
+                    #[cfg(test)]
+                    mod fuzz;
 
                     pub use aml_parser::{AMLParser, AMLParserError, AMLParserResult};
                     pub use pkg::PkgLength;
@@ -153,7 +217,7 @@ pub mod kernel_components {
 
                 pub use aml::{AMLStream, AMLResult};
                 pub use dsdt::DSDT;
-                pub use interpreter::{AMLInterpreter, AMLInterpreterError};
+                pub use interpreter::{AMLInterpreter, AMLInterpreterError, AML_INTERPRETER};
                 pub use parser::{AMLParser, AMLParserError};
             }
 
@@ -179,12 +243,22 @@ pub mod kernel_components {
             pub mod def_exceptions;
             /// A set of predefines interrupts.
             pub mod def_interrupts; 
+            /// Per vector interrupt handler latency instrumentation.
+            pub mod latency;
+            /// Runtime registration of shared IRQ handlers with centralized end of interrupt.
+            pub mod irq_manager;
+            /// Default gate of every vector without a handler, which reports stray interrupts.
+            pub mod unhandled;
+            /// Software interrupts raised with a self IPI to defer work into interrupt context.
+            pub mod softint;
 
             pub use handler_functions::HandlerFn;
-            pub use interrupt_descriptor_table::{GateDescriptor, IDT, GateType, INTERRUPT_DESCRIPTOR_TABLE};
+            pub use interrupt_descriptor_table::{GateDescriptor, IDT, GateType, VectorError, INTERRUPT_DESCRIPTOR_TABLE};
+            pub use irq_manager::{IrqManager, IrqHandle, IrqReturn, IrqError, IRQ_MANAGER};
+            pub use softint::{raise_softint, raise_softint_on, end_of_softint, SoftintError};
             pub use interrupt::{
-                InterruptVector,
-                cause_interrupt, cause_interrupt_unsafe,
+                InterruptVector, IsaIrq, InterruptStackFrame, ExceptionFrame,
+                cause_interrupt, cause_interrupt_unsafe, int_imm, int_imm_unsafe,
                 enable, disable, with_int_disabled, with_int_enabled,
                 wait_for_interrupt,
                 breakpoint, 
@@ -198,6 +272,8 @@ pub mod kernel_components {
         pub mod controllers {
             /// Real Time Clock chip management.
             pub mod rtc;
+            /// Checksum protected OS configuration in the CMOS memory.
+            pub mod nvram;
             /// Programmable Interval Timer management.
             pub mod pit;
             /// PS/2 controller management (Keyboard controller for old keyboards.)
@@ -206,12 +282,16 @@ pub mod kernel_components {
             pub mod pic;
             /// Advanced Programmable Interrupt Controller management.
             pub mod apic;
+            /// IO APIC management driven by the ACPI MADT.
+            pub mod ioapic;
             /// Defines command words for PIC controllers for easy management.
             pub mod pic_command_words;
 
             pub use pit::{PIT, PITReadbackCMD, PITReadback, PITCommand};
             pub use rtc::{RTC, CMOSAddr};
             pub use pic::{Pic, PROGRAMMABLE_INTERRUPT_CONTROLLER};
+            pub use apic::{LocalApic, Lvt, LvtEntry, DeliveryMode, TimerMode, ApicError, LOCAL_APIC};
+            pub use ioapic::{IoApic, IoApics, IoApicError, RedirectionEntry, Polarity, TriggerMode, IO_APIC};
             pub use ps_2::{PS2, PSControllerCommand, PSControllerConfiguration};
         }
 
@@ -222,8 +302,8 @@ pub mod kernel_components {
             /// Minimal Task State Segment implementation for Long Mode.
             pub mod task_state_segment;
 
-            pub use task_state_segment::TSS;
-            pub use global_descriptor_table::{SegmentDescriptor, SegmentSelector, GDT, GLOBAL_DESCRIPTOR_TABLE};
+            pub use task_state_segment::{TSS, IoBitmap};
+            pub use global_descriptor_table::{SegmentDescriptor, SegmentSelector, SegmentRegister, GdtError, GDT, GLOBAL_DESCRIPTOR_TABLE};
         }
 
         pub use descriptor_table::DTPointer;
@@ -246,6 +326,24 @@ pub mod kernel_components {
         pub mod ms;
     }
 
+    /// Filesystem implementations.
+    pub mod fs {
+        /// FAT32 filesystem.
+        pub mod fat {
+            /// On-disk structures: boot sector, allocation table and directory entries.
+            pub mod volume;
+            /// Consistency checker with dry-run and repair modes.
+            pub mod fsck;
+
+            pub use volume::{FatVolume, FatError, Bpb, DirEntry};
+            pub use fsck::{fsck, FsckMode, FsckProblem, FsckReport};
+        }
+        /// In-memory filesystem.
+        pub mod tmpfs;
+
+        pub use tmpfs::{TmpFs, TmpfsError, TMPFS};
+    }
+
     /// Custom module for driver interface.
     ///
     /// Such interfaces define code that must run in kernel-space. Basically a bridge between
@@ -260,12 +358,32 @@ pub mod kernel_components {
         pub mod semaphore;
         /// Thread barrier for OS. Only works for threads within one Process.
         pub mod barrier;
+        /// Spinlock which keeps interrupts disabled while held. Usable from interrupt handlers.
+        pub mod irq_spinlock;
 
         pub use mutex::{Mutex, MutexGuard};
+        pub use irq_spinlock::{IrqSpinlock, IrqSpinlockGuard};
         pub use semaphore::{Semaphore};
         pub use barrier::Barrier;
     }
 
+    /// Boot front-ends other than multiboot2.
+    pub mod boot {
+        /// Builder of the multiboot2 boot information consumed by the rest of the kernel.
+        pub mod info;
+        /// Page tables and the stack switch shared by the front-ends.
+        pub mod entry;
+        /// Entry point of the kernel built as an EFI application.
+        pub mod uefi;
+        /// Translation of Limine boot protocol responses.
+        pub mod limine;
+        /// Progress bar and stage timestamps of the kernel initialization.
+        pub mod progress;
+
+        pub use info::{BootInfoBuilder, BootInfoError, Framebuffer};
+        pub use progress::{progress, BootProfile, Stage};
+    }
+
     /// Module for all memory related manipulations.
     pub mod memory {
         /// Different heap memory allocators implementations.
@@ -288,6 +406,8 @@ pub mod kernel_components {
             pub mod free_list_alloc;
             /// Buddy Allocator implementation. (Very solid choice ^-^)
             pub mod buddy_alloc;
+            /// Redzone checking of heap allocations in debug builds.
+            pub mod redzone;
 
             pub use global_alloc::{GAllocator, SubAllocator, GLOBAL_ALLOCATOR};
             pub use leak_alloc::{LeakAlloc, LEAK_ALLOC};
@@ -310,6 +430,10 @@ pub mod kernel_components {
         /// Defines interface to GRUB's tags. 
         pub mod tags;
 
+        /// Named regions of the kernel virtual address space and their overlap checks.
+        pub mod layout;
+        /// Bump allocator of physical memory for the early boot, before the kernel is remapped.
+        pub mod bootmem;
         /// Physical memory management.
         pub mod frames;
         /// Paging memory model management.
@@ -320,15 +444,40 @@ pub mod kernel_components {
         pub mod temporary_pages;
         /// Inactive page tables.
         pub mod inactive_tables;
+        /// Virtual memory areas of processes and lazily populated file mappings.
+        pub mod vma;
+        /// Swap space and reclaim of process pages under memory pressure.
+        pub mod swap;
+        /// Memory pressure levels and shrinker callbacks of caching subsystems.
+        pub mod pressure;
+        /// Shared zero frame and merging of identical anonymous pages.
+        pub mod ksm;
+        /// Randomized placement of user program images, stacks and heaps.
+        pub mod aslr;
+        /// Read-only page with the time data shared with user programs.
+        pub mod vdso;
+        /// Kernel regions which pages are mapped on the first access.
+        pub mod demand;
+        /// Pool of frames zeroed ahead of time by a low priority thread.
+        pub mod prezero;
+        /// Frames unmapped on one processor, freed once every processor passed a quiescent state.
+        pub mod deferred;
+        /// Naturally aligned blocks of contiguous frames for drivers, merged with their buddies.
+        pub mod page_blocks;
 
         pub use memory_module::{MMU, InfoPointer, BootInfoHeader, MEMORY_MANAGEMENT_UNIT};
         pub use frames::AreaFrameAllocator;
+        pub use page_blocks::{PhysRegion, MAX_ORDER};
         pub use stack_allocator::StackAlloc;
+        pub use bootmem::{BootMem, BOOTMEM};
         
         pub use paging::{Page, Table, Entry, EntryFlags};
-        pub use owned_tables::ActivePageTable;
+        pub use owned_tables::{ActivePageTable, Mapping};
         pub use temporary_pages::TempPage;
-        pub use inactive_tables::InactivePageTable;
+        pub use inactive_tables::{InactivePageTable, MappingDiff};
+        pub use vma::{AddressSpace, Vma, Protection, VmaError};
+        pub use swap::{SwapSpace, SwapSlot, SwapError, SWAP};
+        pub use pressure::{PressureLevel, ShrinkerFn, ShrinkerId};
     }
 
     /// IPC and multithreading implementation.
@@ -339,6 +488,10 @@ pub mod kernel_components {
         pub mod round_robin;
         /// Scheduler based on process' priority.
         pub mod priority_based_scheduling;
+        /// Process priorities, thread nice values and their privilege checks.
+        pub mod priority;
+        /// Capability masks of processes checked by privileged kernel services.
+        pub mod capability;
         
         /// Implementation of Process. A container of threads that hold their local and shared
         /// environment. Defines most important functions to run scheduled code. 
@@ -346,17 +499,29 @@ pub mod kernel_components {
         /// Thread implementation. A simple unit that performs defined code and saves local
         /// environment before task switch.
         pub mod thread;
+        /// Builder for named, detached and custom sized threads.
+        pub mod thread_builder;
         /// Kernel level Join Handle for threads. Allows for synchronization without primitives.
         pub mod join_handle;
         /// Process Management Unit structure. Main structure that holds information about
         /// running/queued processes and schedules them.
         pub mod pmu;
+        /// Deferred work executed by the kernel worker thread instead of interrupt handlers.
+        pub mod workqueue;
+        /// Idle loop choosing between HLT, MWAIT and ACPI C-states by the predicted idle time.
+        pub mod idle;
+        /// Per process syscall auditing into a ring buffer, the kernel side of strace.
+        pub mod audit;
 
         pub use pmu::{PMU, PROCESS_MANAGEMENT_UNIT};
         pub use process::{Process, ProcState};
         pub use thread::{Thread, ThreadFn, ThreadState};
+        pub use thread_builder::ThreadBuilder;
         pub use scheduler::{Scheduler, Task};
+        pub use priority::PriorityError;
+        pub use capability::{Capability, CapabilityError};
         pub use join_handle::{JoinHandle, HandleStack};
+        pub use idle::{IdleGovernor, IdleState, IdleMethod, CstEntry, CstRegister, IDLE_GOVERNOR};
 
         pub use round_robin::{ROUND_ROBIN, RoundRobin};
         pub use priority_based_scheduling::{PRIORITY_SCHEDULER, PriorityScheduler};
@@ -382,39 +547,32 @@ pub use kernel_components::{
         allocators::{GLOBAL_ALLOCATOR, LEAK_ALLOC, BUMP_ALLOC, NODE_ALLOC, FREE_LIST_ALLOC, BUDDY_ALLOC},
     },
 
-    vga_buffer::Color,
+    vga_buffer::{Color, Style, Theme, LogLevel},
+
+    testing::{Testable, TestCase, test_runner},
 };
 
 /// This function will be called on fatal errors in the system.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    #[cfg(test)]
-    println!(Color::RED; "[failed]");
-    println!(Color::RED; "{}", info);
-    
-    loop {}
-}
-
-/// Custom trait for tests. It is only used when testing and do not affect overall performance.
-pub trait Testable {
-    fn run(&self) -> ();
-}
-
-impl<T> Testable for T where T: Fn(), {
-    fn run(&self) {
-        print!("{}...    ", core::any::type_name::<T>());
-        self();
-        println!(Color::GREEN; "[ok]");
-    }
-}
-
-/// Test runner for testing kernel components. It wil run all unit tests as well as integrated one.
-/// TODO! Fix the way how printing works, to make it readable.
-pub fn test_runner(tests: &[&dyn Fn()]) -> ! {
-    println!(Color::LIGHTBLUE; "Running {} tests:", tests.len());
-    for test in tests {
-        test.run();
+    use kernel_components::arch_x86_64::backtrace::{self, StackWalker};
+    use kernel_components::testing;
+
+    // Expected panics of tests are not reported at all.
+    testing::catch_expected_panic();
+    // The regular printing path might be the reason of the panic.
+    // Screen content is captured before the emergency output overwrites it.
+    kernel_components::console::snapshot_on_panic();
+    if testing::is_running() {
+        emergency_println!("[failed]");
     }
+    emergency_println!("{}", info);
+    kernel_components::task_virtualization::Thread::with_current(|t| {
+        emergency_println!("in thread '{}' (pid {}, tid {})", t.name().unwrap_or("<unnamed>"), t.pid, t.tid)
+    });
+    backtrace::dump(None, StackWalker::current());
+    testing::continue_after_failure();
 
     loop {}
 }
+
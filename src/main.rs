@@ -5,6 +5,7 @@
 #![test_runner(notOS::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+#[cfg(not(feature = "uefi"))]
 #[link(name = "bootloader")]
 extern "C" {
     fn initiate();
@@ -15,18 +16,45 @@ extern "C" {
 // The alloc crate will be useful for the main kernel binary no matter what.
 extern crate alloc;
 
+#[cfg(not(feature = "uefi"))]
 #[used]
 static INITIATE_FUNC: unsafe extern "C" fn() = initiate;
+#[cfg(not(feature = "uefi"))]
 #[used(linker)]
 static HEADER_START_FUNC: unsafe extern "C" fn() = header_start;
+#[cfg(not(feature = "uefi"))]
 #[used(linker)]
 static HEADER_END_FUNC: unsafe extern "C" fn() = header_end;
 
+/// Entry point of the kernel built as an EFI application. Enters [`_start`] the same way the
+/// multiboot path does.
+#[cfg(feature = "uefi")]
+#[no_mangle]
+pub extern "efiapi" fn efi_main(
+    image: notOS::kernel_components::boot::uefi::Handle,
+    system_table: *const notOS::kernel_components::boot::uefi::SystemTable,
+) -> notOS::kernel_components::boot::uefi::Status {
+    unsafe { notOS::kernel_components::boot::uefi::boot(image, system_table, _start) }
+}
+
+/// Replaces the entry point of the ELF file when the kernel is loaded by Limine.
+#[cfg(feature = "limine")]
+#[used]
+static LIMINE_ENTRY: notOS::kernel_components::boot::limine::EntryPointRequest =
+    notOS::kernel_components::boot::limine::EntryPointRequest::new(limine_main);
+
+/// Entry point of the kernel loaded by Limine. Enters [`_start`] the same way the multiboot path
+/// does.
+#[cfg(feature = "limine")]
+extern "C" fn limine_main() -> ! {
+    unsafe { notOS::kernel_components::boot::limine::boot(_start) }
+}
+
 use alloc::boxed::Box;
 /// This is the main binary (kernel) space. As the library will build in, ew features will be added further.
 use notOS::{
     kernel_components::{
-        arch_x86_64::{controllers::pic::ChainedPics, interrupts}, drivers::{keyboards::{KeyboardDriver, PS2Keyboard}, timers::ClockDriver}, memory::MEMORY_MANAGEMENT_UNIT, registers::{control, ms}
+        arch_x86_64::controllers::pic::ChainedPics, drivers::{keyboards::{KeyboardDriver, PS2Keyboard}, timers::ClockDriver}, memory::MEMORY_MANAGEMENT_UNIT, registers::{control, ms}
     }, print, println, single, warn, BUDDY_ALLOC, FREE_LIST_ALLOC, GLOBAL_ALLOCATOR
 };
 
@@ -56,28 +84,62 @@ pub extern "C" fn _start(_multiboot_information_address: usize) {
         def_exceptions::*,
         def_interrupts::*,
         INTERRUPT_DESCRIPTOR_TABLE,
-        InterruptVector, 
+        InterruptVector, IsaIrq,
         GateDescriptor,
+        IrqReturn,
     };
 
     use notOS::kernel_components::drivers::{
-        DRIVER_MANAGER, DriverType,
-        timers::RealTimeClock,
+        DRIVER_MANAGER, DriverType, DriverError,
+        timers::{RealTimeClock, KvmClock, ApicTimer, apic_timer::TICK_HZ},
+        serial::{Uart16550, COM1},
+        keyboards::{Key, ShortcutModifiers},
+        mouse::{pointer, MouseDriver, PS2Mouse},
+        storage::BlockDevice,
+        virtio::{block, VirtioBlock, VirtioError},
+        interrupts::with_controller,
     };
+    use notOS::kernel_components::arch_x86_64::controllers::PS2;
 
     use notOS::kernel_components::arch_x86_64::controllers::PROGRAMMABLE_INTERRUPT_CONTROLLER;
+    use notOS::kernel_components::arch_x86_64::controllers::{apic::SPURIOUS_VECTOR, ioapic::init_from_madt};
+    use notOS::kernel_components::arch_x86_64::thermal::THERMAL_VECTOR;
+    use notOS::kernel_components::arch_x86_64::shootdown::SHOOTDOWN_VECTOR;
+    use notOS::kernel_components::boot::{progress, Stage};
 
     // Memory initialization.
     // The global allocator is a mutable static that do not use any locking 
     // algorithm, so any operation on it, is unsafe.
+    progress(Stage::EarlyMemory);
     unsafe { 
+        // Early boot allocations are possible from here until the MMU takes the memory over.
+        notOS::kernel_components::memory::bootmem::init(_multiboot_information_address);
+        // The low page for the AP trampoline is taken before the frame allocator can hand it out.
+        notOS::kernel_components::arch_x86_64::smp::reserve_trampoline();
+
         GLOBAL_ALLOCATOR.r#use(&FREE_LIST_ALLOC);
         FREE_LIST_ALLOC.change_strategy(
             notOS::kernel_components::memory::allocators::free_list_alloc::SearchStrategy::BEST_FIT
         );
    
         // The MMU structure makes it easier to handle memory related commands.
-        MEMORY_MANAGEMENT_UNIT.init(_multiboot_information_address);
+        if let Err(err) = MEMORY_MANAGEMENT_UNIT.init(_multiboot_information_address) {
+            panic!("Unable to initialize the memory: {}", err);
+        }
+        if let Err(err) = notOS::kernel_components::memory::vdso::init(&mut MEMORY_MANAGEMENT_UNIT) {
+            warn!("Unable to map the vDSO page: {}", err);
+        }
+
+        // With a graphics mode set by the bootloader, there is no VGA text buffer to print to.
+        ms::Pat::enable_write_combining();
+        if let Some(fb) = MEMORY_MANAGEMENT_UNIT.boot_info().framebuffer() {
+            match MEMORY_MANAGEMENT_UNIT.map_framebuffer(&fb) {
+                Ok(base) => notOS::kernel_components::vga_buffer::use_framebuffer(
+                    notOS::kernel_components::gfx::LinearFramebuffer::new(base as *mut u8, fb)
+                ),
+                Err(err) => warn!("Unable to map the framebuffer: {}", err),
+            }
+        }
     };
     
     // Enabling the nxe bit and write protect bit.
@@ -88,13 +150,15 @@ pub extern "C" fn _start(_multiboot_information_address: usize) {
         mut TASK_STATE_SEGMENT: TSS = TSS::new();
     }
 
+    progress(Stage::Descriptors);
     unsafe {
         // Setting up the stack for IST.
-        MEMORY_MANAGEMENT_UNIT.set_interrupt_stack(&mut TASK_STATE_SEGMENT,0,1);
+        MEMORY_MANAGEMENT_UNIT.set_interrupt_stack(&mut TASK_STATE_SEGMENT, DOUBLE_FAULT_IST_INDEX, 1)
+            .expect("Unable to allocate memory for IST.");
 
         // Rewrite the static GDT. It will use the flat setup.
-        GLOBAL_DESCRIPTOR_TABLE.reinit(GDT::flat_setup(&TASK_STATE_SEGMENT));
-        GLOBAL_DESCRIPTOR_TABLE.load_table(); // Loads the table to the CPU.
+        GLOBAL_DESCRIPTOR_TABLE.replace(GDT::flat_setup(&TASK_STATE_SEGMENT))
+            .expect("The boot segments are not valid in the flat GDT.");
 
         // Reloading the CS segment.
         CodeSegment::write(
@@ -111,61 +175,303 @@ pub extern "C" fn _start(_multiboot_information_address: usize) {
             SegmentSelector::new(5, false, PrivilegeLevel::KernelLevel)
         );
 
-        // Exception gates.
-        let gate_div = GateDescriptor::new_trap(DIVISION_BY_ZERO);
-        let gate_break = GateDescriptor::new_trap(BREAKPOINT);
-        let gate_double_fault = GateDescriptor::new_trap(DOUBLE_FAULT);
-        let gate_page_fault = GateDescriptor::new_trap(PAGE_FAULT);
-
         // Interrupt gates.
         let gate_timer = GateDescriptor::new_interrupt(TIMER_INTERRUPT);
 
         let gate_keyboard = GateDescriptor::new_interrupt(KEYBOARD_INTERRUPT);
 
+        let gate_rtc = GateDescriptor::new_interrupt(RTC_INTERRUPT);
+
+        let gate_apic_spurious = GateDescriptor::new_interrupt(APIC_SPURIOUS_INTERRUPT);
+
+        let gate_thermal = GateDescriptor::new_interrupt(THERMAL_INTERRUPT);
+
+        let gate_tlb_shootdown = GateDescriptor::new_interrupt(TLB_SHOOTDOWN_INTERRUPT);
+
         // Pushing the gates into the IDT.
-        INTERRUPT_DESCRIPTOR_TABLE.push(InterruptVector::DIVIDE_BY_ZERO, gate_div);
-        INTERRUPT_DESCRIPTOR_TABLE.push(InterruptVector::BREAKPOINT, gate_break);
-        INTERRUPT_DESCRIPTOR_TABLE.push(InterruptVector::DOUBLE_FAULT, gate_double_fault);
-        INTERRUPT_DESCRIPTOR_TABLE.push(InterruptVector::PAGE_FAULT, gate_page_fault);
+        idt_install_default_exceptions(&mut INTERRUPT_DESCRIPTOR_TABLE);
 
         INTERRUPT_DESCRIPTOR_TABLE.push(
-            InterruptVector::PICMappings(32), gate_timer
+            InterruptVector::APICMappings(SPURIOUS_VECTOR as usize), gate_apic_spurious
         );
         INTERRUPT_DESCRIPTOR_TABLE.push(
-            InterruptVector::PICMappings(33), gate_keyboard
+            InterruptVector::APICMappings(THERMAL_VECTOR as usize), gate_thermal
         );
+        INTERRUPT_DESCRIPTOR_TABLE.push(
+            InterruptVector::APICMappings(SHOOTDOWN_VECTOR as usize), gate_tlb_shootdown
+        );
+        // Every other vector reports stray interrupts instead of faulting on an empty gate.
+        INTERRUPT_DESCRIPTOR_TABLE.fill_unhandled();
 
         // Loading the IDT table to the CPU.
         INTERRUPT_DESCRIPTOR_TABLE.load_table();
 
         // Remapping the PIC controller.
+        progress(Stage::InterruptController);
         let mut pics = ChainedPics::new_contiguous(32);
         pics.initialize();
 
         PROGRAMMABLE_INTERRUPT_CONTROLLER.lock().replace(pics);
+
+        // The IO APIC takes the same vectors over, when the firmware describes one.
+        if let Err(err) = init_from_madt(32) {
+            warn!("Using the legacy PIC: {}", err);
+        }
+
+        // The ISA lines are pushed to the vectors the active controller delivers them on.
+        for (irq, gate) in [(IsaIrq::Timer, gate_timer), (IsaIrq::Keyboard, gate_keyboard), (IsaIrq::Rtc, gate_rtc)] {
+            let vector = InterruptVector::irq(irq.line())
+                .expect("The ISA lines are mapped by every interrupt controller.");
+            INTERRUPT_DESCRIPTOR_TABLE.push(vector, gate);
+        }
    
         // Loading drivers
+        progress(Stage::Drivers);
         {
-            let clock_driver: Box<dyn ClockDriver> = Box::new(RealTimeClock::new()); 
-            let keyboard_driver: Box<dyn KeyboardDriver> = Box::new(PS2Keyboard::default());
+            // The local APIC timer replaces IRQ0 of the PIT as the scheduler tick, when available.
+            // IRQ0 is masked first, so it is never acknowledged to the wrong controller.
+            let _ = with_controller(|ctrl| ctrl.mask(0));
+            let apic_timer = ApicTimer::new(32).and_then(|mut timer| timer.start_periodic(TICK_HZ).map(|_| timer));
+
+            let clock_driver: Box<dyn ClockDriver> = match apic_timer {
+                Ok(apic_timer) => Box::new(apic_timer),
+                Err(err) => {
+                    warn!("Using the PIT as the scheduler tick: {}", err);
+                    let _ = with_controller(|ctrl| ctrl.unmask(0));
+
+                    // Paravirtual clock is preferred when running under KVM.
+                    match KvmClock::new() {
+                        Some(kvm_clock) => Box::new(kvm_clock),
+                        None => Box::new(RealTimeClock::new()),
+                    }
+                },
+            };
+            let mut keyboard_driver: Box<dyn KeyboardDriver> = Box::new(PS2Keyboard::default());
+            keyboard_driver.register_shortcut(
+                ShortcutModifiers::CTRL | ShortcutModifiers::ALT, Key::Delete, || PS2::new().reset_cpu()
+            );
+
+            // Copying the mouse selection and pasting into the terminal input.
+            notOS::kernel_components::clipboard::register_shortcuts();
+            // Moving through the scrollback of the screen.
+            notOS::kernel_components::vga_buffer::register_shortcuts();
 
             let _ = DRIVER_MANAGER.load(clock_driver, DriverType::Clock);
             let _ = DRIVER_MANAGER.load(keyboard_driver, DriverType::Keyboard); 
+
+            // Serial port for file transfers with the "rx" shell command.
+            let mut uart = Uart16550::new(COM1);
+            match uart.init(115_200) {
+                Ok(()) => { let _ = DRIVER_MANAGER.load(uart, DriverType::Serial); },
+                Err(err) => warn!("Serial port is not available: {}", err),
+            }
+
+            let mut mouse = PS2Mouse::new();
+            match mouse.init() {
+                Ok(()) => {
+                    let mouse_driver: Box<dyn MouseDriver> = Box::new(mouse);
+                    let _ = DRIVER_MANAGER.load(mouse_driver, DriverType::Mouse);
+                    let handler = DRIVER_MANAGER.register_irq(DriverType::Mouse, PS2Mouse::IRQ, "ps2-mouse", || {
+                        let mouse = DRIVER_MANAGER.driver::<Box<dyn MouseDriver>>(DriverType::Mouse);
+                        if let Some(event) = mouse.and_then(|mouse| mouse.read()) {
+                            pointer::handle_event(event);
+                        }
+                        IrqReturn::Handled
+                    });
+                    if let Err(err) = handler {
+                        warn!("PS/2 mouse interrupts are not available: {}", err);
+                    }
+                },
+                Err(err) => warn!("PS/2 mouse is not available: {:?}", err),
+            }
+
+            // Virtio disk, whose finished requests are completed from it's interrupt.
+            let (mut irq, mut failure) = (None, None);
+            let loaded = DRIVER_MANAGER.probe(DriverType::Storage, || match VirtioBlock::new() {
+                Ok(disk) => {
+                    irq = disk.irq();
+                    Ok(Box::new(disk) as Box<dyn BlockDevice>)
+                },
+                Err(err) => {
+                    failure = Some(err);
+                    Err(DriverError::ProbeFailed)
+                },
+            });
+            match (loaded, failure) {
+                (Ok(_), _) => {
+                    let handler = irq.map(|irq| {
+                        DRIVER_MANAGER.register_irq(DriverType::Storage, irq, "virtio-blk", block::interrupt_handler)
+                    });
+                    if let Some(Err(err)) = handler {
+                        warn!("Virtio disk interrupts are not available: {}", err);
+                    }
+                },
+                (Err(_), Some(VirtioError::DeviceNotFound)) => (),
+                (Err(_), Some(err)) => warn!("Virtio disk is not available: {:?}", err),
+                (Err(err), None) => warn!("Virtio disk is not loaded: {:?}", err),
+            }
+        }
+
+
+        // Verifying the configuration stored in the CMOS memory.
+        {
+            use notOS::kernel_components::arch_x86_64::controllers::nvram;
+
+            let _config = nvram::check_at_boot();
+            notOS::debug!("CMOS configuration: {:?}", _config);
+        }
+
+        // Registering runtime tunables and applying the kernel command line.
+        progress(Stage::Configuration);
+        {
+            use notOS::kernel_components::sysctl::{self, SYSCTL};
+
+            SYSCTL.lock().register_defaults();
+
+            if let Some(cmdline) = MEMORY_MANAGEMENT_UNIT.boot_info().command_line() {
+                for (name, err) in sysctl::apply_cmdline(cmdline) {
+                    warn!("Unable to apply tunable \"{}\": {:?}", name, err);
+                }
+            }
+        }
+
+        // Detecting the idle states of the processor.
+        {
+            use notOS::kernel_components::task_virtualization::IDLE_GOVERNOR;
+
+            IDLE_GOVERNOR.lock().detect();
+        }
+
+        // Frequency scaling through HWP, when the processor supports it.
+        {
+            use notOS::kernel_components::arch_x86_64::cpufreq::CPUFREQ;
+
+            if !CPUFREQ.lock().detect() {
+                notOS::debug!("HWP is not supported, the CPU frequency is left to the firmware.");
+            }
         }
 
+        // Starting the other processors, which wait in their idle loops for now.
+        progress(Stage::Processors);
+        {
+            use notOS::kernel_components::arch_x86_64::smp;
+
+            match smp::start_aps() {
+                Ok(count) => { notOS::debug!("{} processors online.", count); },
+                Err(err) => warn!("Running on the bootstrap processor only: {}", err),
+            }
+        }
+
+        // Thermal sensor of the processor, which drives the throttling policy.
+        {
+            use notOS::kernel_components::arch_x86_64::thermal::THERMAL;
+
+            if !THERMAL.lock().detect() {
+                notOS::debug!("No digital thermal sensor, only ACPI thermal zones are handled.");
+            }
+        }
+
+        // Reporting the detected hardware.
+        progress(Stage::Hardware);
+        {
+            use notOS::kernel_components::{hwinfo::HwInfo, sysctl::{SYSCTL, SysctlValue}};
+
+            let report = HwInfo::collect();
+            if SYSCTL.lock().get("boot.hwinfo") == Some(&SysctlValue::Bool(true)) {
+                println!("{}", report);
+            } else if report.unbound().next().is_some() {
+                warn!("{} PCI functions lack a driver, see \"hwinfo\".", report.unbound().count());
+            }
+        }
+
+        // Every ACPI table is parsed by now, the reclaimable firmware memory can be used as RAM.
+        match MEMORY_MANAGEMENT_UNIT.reclaim_acpi() {
+            Ok(frames) => { notOS::debug!("{} frames of ACPI tables reclaimed.", frames); },
+            Err(err) => warn!("Unable to reclaim the ACPI tables: {}", err),
+        }
         
+        progress(Stage::FirstProcess);
         use notOS::kernel_components::task_virtualization::{Process, PROCESS_MANAGEMENT_UNIT};
         let stack1 = MEMORY_MANAGEMENT_UNIT.allocate_stack(16).unwrap();
 
-        // Using library shell program.
-        let shell = Process::new_void(stack1, 0, 1, 1, None, notOS::programs::shell);
+        // Using library kernel shell program.
+        let shell = Process::new_void(stack1, 0, 1, 1, None, notOS::programs::kshell);
 
         // Pushing the process to the queue.
         PROCESS_MANAGEMENT_UNIT.queue(shell);
-    }
 
-    loop {
-        // Waiting for interrupts to happen.
-        interrupts::wait_for_interrupt();
+        // Worker thread for the deferred work.
+        {
+            use notOS::kernel_components::task_virtualization::workqueue;
+
+            let stack = MEMORY_MANAGEMENT_UNIT.allocate_stack(4).unwrap();
+            let worker = Process::new_void(stack, 0, 3, 1, None, workqueue::worker);
+            PROCESS_MANAGEMENT_UNIT.queue(worker);
+        }
+
+        // Zeroing free frames ahead of the page faults.
+        {
+            use notOS::kernel_components::memory::prezero;
+            use notOS::kernel_components::task_virtualization::priority::PRIORITY_LOWEST;
+
+            let stack = MEMORY_MANAGEMENT_UNIT.allocate_stack(4).unwrap();
+            let zeroing = Process::new_void(stack, 0, 3, PRIORITY_LOWEST, None, prezero::zeroing_thread);
+            PROCESS_MANAGEMENT_UNIT.queue(zeroing);
+        }
+
+        // Load sampling of the CPU frequency governor.
+        {
+            use notOS::kernel_components::arch_x86_64::cpufreq::{self, CPUFREQ};
+
+            if CPUFREQ.lock().is_available() {
+                let stack = MEMORY_MANAGEMENT_UNIT.allocate_stack(4).unwrap();
+                let governor = Process::new_void(stack, 0, 3, 1, None, cpufreq::governor_thread);
+                PROCESS_MANAGEMENT_UNIT.queue(governor);
+            }
+        }
+
+        // Polling of the thermal zones.
+        {
+            use notOS::kernel_components::arch_x86_64::thermal::{self, THERMAL};
+
+            if !THERMAL.lock().zones().is_empty() {
+                let stack = MEMORY_MANAGEMENT_UNIT.allocate_stack(4).unwrap();
+                let monitor = Process::new_void(stack, 0, 2, 1, None, thermal::monitor_thread);
+                PROCESS_MANAGEMENT_UNIT.queue(monitor);
+            }
+        }
+
+        // Eviction of the process mappings under memory pressure.
+        {
+            use notOS::kernel_components::memory::pressure;
+
+            let stack = MEMORY_MANAGEMENT_UNIT.allocate_stack(4).unwrap();
+            let monitor = Process::new_void(stack, 0, 3, 1, None, pressure::monitor_thread);
+            PROCESS_MANAGEMENT_UNIT.queue(monitor);
+        }
+
+        // Merging of identical pages, which only scans while "mm.ksm" is enabled.
+        {
+            use notOS::kernel_components::memory::ksm;
+
+            let stack = MEMORY_MANAGEMENT_UNIT.allocate_stack(4).unwrap();
+            let scanner = Process::new_void(stack, 0, 3, 1, None, ksm::scanner);
+            PROCESS_MANAGEMENT_UNIT.queue(scanner);
+        }
+
+        // Periodic heap redzone verification in debug builds.
+        #[cfg(debug_assertions)] {
+            use notOS::kernel_components::memory::allocators::redzone;
+
+            let stack2 = MEMORY_MANAGEMENT_UNIT.allocate_stack(4).unwrap();
+            let checker = Process::new_void(stack2, 0, 2, 1, None, redzone::checker);
+            PROCESS_MANAGEMENT_UNIT.queue(checker);
+        }
     }
+
+    // Time spent in each stage of the initialization.
+    notOS::kernel_components::boot::progress::finish();
+
+    // Waiting for interrupts to happen in the deepest idle state worth entering.
+    notOS::kernel_components::task_virtualization::idle::idle_loop()
 }
@@ -1,6 +1,7 @@
 /// This module defines static programs compatible with notOS system.
 
 pub use shell::shell;
+pub use kshell::kshell;
 
 /// Basic notOS shell module.
 pub mod shell {
@@ -22,3 +23,518 @@ pub mod shell {
         loop {}
     }
 }
+
+/// Interactive kernel shell with line editing, history and built-in commands.
+pub mod kshell {
+    use alloc::string::String;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+
+    use crate::kernel_components::keyboard_interface::KeyboardInterface;
+    use crate::kernel_components::task_virtualization::{audit, capability, Capability, Thread, PROCESS_MANAGEMENT_UNIT};
+    use crate::kernel_components::drivers::{DRIVER_MANAGER, DriverType, serial::{xmodem, Uart16550}};
+    use crate::kernel_components::drivers::storage::{RamDisk, SECTOR_SIZE};
+    use crate::kernel_components::fs::{tmpfs, TMPFS};
+    use crate::kernel_components::logging;
+    use crate::kernel_components::arch_x86_64::{pci, controllers::PS2};
+    use crate::kernel_components::memory::{swap, EntryFlags, Page, MEMORY_MANAGEMENT_UNIT, SWAP};
+    use crate::kernel_components::sync::Mutex;
+    use crate::kernel_components::sysctl::{self, SYSCTL, SysctlError};
+    use crate::kernel_components::hwinfo::HwInfo;
+    use crate::kernel_components::boot::BootProfile;
+    use crate::kernel_components::tty::{LineDiscipline, Signal};
+    use crate::kernel_components::{clipboard, console, kexec};
+    use crate::kernel_components::dmesg::DMESG;
+    use crate::kernel_components::vga_buffer::{self, LogLevel};
+    use crate::{print, println, log, GLOBAL_ALLOCATOR};
+
+    /// Maximal amount of lines kept in history.
+    const HISTORY_SIZE: usize = 32;
+    /// Shell prompt.
+    const PROMPT: &str = "kshell> ";
+
+    /// Built-in command signature. Gets the arguments without the command name.
+    type Command = fn(&mut KShell, &[&str]);
+
+    /// Table of all built-in commands with their short descriptions.
+    const COMMANDS: &[(&str, &str, Command)] = &[
+        ("help",    "list available commands",          KShell::help),
+        ("history", "show command history",             KShell::history),
+        ("echo",    "print arguments",                  KShell::echo),
+        ("mem",     "show memory usage",                KShell::mem),
+        ("ps",      "list running processes",           KShell::ps),
+        ("lsdrv",   "list loaded drivers and their memory", KShell::lsdrv),
+        ("lspci",   "list PCI devices",                 KShell::lspci),
+        ("hwinfo",  "show detected hardware and drivers", KShell::hwinfo),
+        ("bootprof", "show time spent in each boot stage", KShell::bootprof),
+        ("peek",    "read a byte: peek <addr>",         KShell::peek),
+        ("poke",    "write a byte: poke <addr> <val>",  KShell::poke),
+        ("hexdump", "dump memory: hexdump <addr> <len>", KShell::hexdump),
+        ("pagemap", "dump page mappings: pagemap [start end]", KShell::pagemap),
+        ("irqlat",  "show interrupt handler latency",   KShell::irqlat),
+        ("dmesg",   "print kernel log: dmesg [level|clear]", KShell::dmesg),
+        ("strace",  "audit syscalls: strace [on|off <pid>|log [pid]|clear]", KShell::strace),
+        ("snapshot", "dump the screen: snapshot [serial|clip]", KShell::snapshot),
+        ("sysctl",  "show or set tunables: sysctl [name[=value]]", KShell::sysctl),
+        ("rx",      "receive a file over serial with XMODEM: rx <name>", KShell::rx),
+        ("ls",      "list files in tmpfs",              KShell::ls),
+        ("rm",      "remove a file from tmpfs: rm <name>", KShell::rm),
+        ("swapon",  "show swap or enable it on a RAM disk: swapon [size_kib]", KShell::swapon),
+        ("swapoff", "disable swap and release it's device", KShell::swapoff),
+        ("reboot",  "reset the machine",                KShell::reboot),
+        ("run",     "boot a multiboot2 ELF image from tmpfs: run <name> [cmdline]", KShell::run),
+    ];
+
+    /// Kernel shell state.
+    ///
+    /// The line is edited by the line discipline of the terminal, the shell only gets finished
+    /// lines. Ctrl+C discards the line and Ctrl+D executes the unfinished one. Previous lines can
+    /// be repeated with '!!' or '!N'.
+    pub struct KShell {
+        tty: LineDiscipline,
+        history: Vec<String>,
+    }
+
+    impl KShell {
+        /// Creates a new shell with empty history.
+        pub fn new() -> Self {
+            Self { tty: LineDiscipline::new(), history: Vec::new() }
+        }
+
+        /// Handles a single character obtained from the keyboard.
+        pub fn input(&mut self, c: char) {
+            // Typing returns the screen from the scrollback to the prompt.
+            vga_buffer::scroll_to_bottom();
+            if let Some(Signal::Interrupt) = self.tty.input(c) {
+                print!("{}", PROMPT);
+            }
+
+            while let Some(line) = self.tty.read_line() {
+                // The end of file has no meaning for the kernel shell.
+                if line.is_empty() {
+                    continue
+                }
+                if !line.ends_with('\n') {
+                    println!();
+                }
+                self.execute(line.trim());
+                print!("{}", PROMPT);
+            }
+        }
+
+        /// Executes the command line and stores it in the history.
+        pub fn execute(&mut self, line: &str) {
+            if line.is_empty() {
+                return
+            }
+
+            // History expansion.
+            let line = if let Some(rest) = line.strip_prefix('!') {
+                let entry = if rest == "!" {
+                    self.history.last()
+                } else {
+                    rest.parse::<usize>().ok().and_then(|n| self.history.get(n))
+                };
+
+                match entry {
+                    Some(entry) => {
+                        println!("{}", entry);
+                        entry.clone()
+                    },
+                    None => {
+                        log!(Error; "{}: event not found", line);
+                        return
+                    },
+                }
+            } else {
+                String::from(line)
+            };
+
+            if self.history.len() == HISTORY_SIZE {
+                self.history.remove(0);
+            }
+            self.history.push(line.clone());
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            match COMMANDS.iter().find(|(name, _, _)| *name == args[0]) {
+                Some((_, _, cmd)) => cmd(self, &args[1..]),
+                None => log!(Error; "{}: command not found", args[0]),
+            }
+        }
+
+        fn help(&mut self, _: &[&str]) {
+            for (name, desc, _) in COMMANDS {
+                println!("{:<10}{}", name, desc);
+            }
+        }
+
+        fn history(&mut self, _: &[&str]) {
+            for (i, line) in self.history.iter().enumerate() {
+                println!("{:>4}  {}", i, line);
+            }
+        }
+
+        fn echo(&mut self, args: &[&str]) {
+            println!("{}", args.join(" "));
+        }
+
+        fn mem(&mut self, _: &[&str]) {
+            unsafe { MEMORY_MANAGEMENT_UNIT.dump_stats() }
+        }
+
+        fn ps(&mut self, _: &[&str]) {
+            unsafe { PROCESS_MANAGEMENT_UNIT.dump_tasks() }
+        }
+
+        fn lsdrv(&mut self, _: &[&str]) {
+            for (dtype, info) in unsafe { DRIVER_MANAGER.list() } {
+                let (major, minor, patch) = info.version;
+                let usage = unsafe { DRIVER_MANAGER.memory_usage(dtype) };
+                println!(
+                    "{:<10} {} v{}.{}.{} heap {} B (peak {} B, {} allocs, {} frees) frames {}",
+                    alloc::format!("{:?}", dtype), info.name, major, minor, patch,
+                    usage.heap_bytes, usage.peak_bytes, usage.allocations, usage.frees, usage.frames,
+                );
+            }
+        }
+
+        fn lspci(&mut self, _: &[&str]) {
+            for dev in pci::scan() {
+                println!(
+                    "{:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}{:02x}",
+                    dev.bus, dev.device, dev.function,
+                    dev.vendor_id(), dev.device_id(), dev.class(), dev.subclass(),
+                );
+            }
+        }
+
+        fn hwinfo(&mut self, _: &[&str]) {
+            println!("{}", HwInfo::collect());
+        }
+
+        fn bootprof(&mut self, _: &[&str]) {
+            println!("{}", BootProfile::collect());
+        }
+
+        fn peek(&mut self, args: &[&str]) {
+            if !Self::check_capability("peek", Capability::RAW_IO) {
+                return
+            }
+            let Some(addr) = args.first().and_then(|a| parse_number(a)) else {
+                return println!("usage: peek <addr>")
+            };
+
+            if Self::check_mapped(addr, 1) {
+                let value = unsafe { core::ptr::read_volatile(addr as *const u8) };
+                println!("{:#x}: {:#04x}", addr, value);
+            }
+        }
+
+        fn poke(&mut self, args: &[&str]) {
+            if !Self::check_capability("poke", Capability::RAW_IO) {
+                return
+            }
+            let (Some(addr), Some(value)) = (
+                args.get(0).and_then(|a| parse_number(a)),
+                args.get(1).and_then(|v| parse_number(v)),
+            ) else {
+                return println!("usage: poke <addr> <value>")
+            };
+            let Ok(value) = u8::try_from(value) else {
+                return log!(Error; "poke: {:#x} does not fit into a byte", value)
+            };
+
+            if !Self::check_mapped(addr, 1) {
+                return
+            }
+            // Writing into a read-only page would fault and kill the shell.
+            let writable = unsafe { MEMORY_MANAGEMENT_UNIT.page_flags(Page::containing_address(addr)) }
+                .is_some_and(|flags| EntryFlags::WRITABLE.is_in(flags));
+            if !writable {
+                return log!(Error; "{:#x}: address is not writable", addr)
+            }
+            unsafe { core::ptr::write_volatile(addr as *mut u8, value) };
+        }
+
+        fn hexdump(&mut self, args: &[&str]) {
+            if !Self::check_capability("hexdump", Capability::RAW_IO) {
+                return
+            }
+            let (Some(addr), Some(len)) = (
+                args.get(0).and_then(|a| parse_number(a)),
+                args.get(1).and_then(|l| parse_number(l)).or(Some(64)),
+            ) else {
+                return println!("usage: hexdump <addr> <len>")
+            };
+
+            if !Self::check_mapped(addr, len) {
+                return
+            }
+
+            for line in (addr..addr + len).step_by(16) {
+                print!("{:016x}  ", line);
+                let end = (line + 16).min(addr + len);
+                for byte in line..end {
+                    print!("{:02x} ", unsafe { core::ptr::read_volatile(byte as *const u8) });
+                }
+                for _ in end..line + 16 {
+                    print!("   ");
+                }
+                print!(" |");
+                for byte in line..end {
+                    let c = unsafe { core::ptr::read_volatile(byte as *const u8) };
+                    print!("{}", if c.is_ascii_graphic() { c as char } else { '.' });
+                }
+                println!("|");
+            }
+        }
+
+        fn pagemap(&mut self, args: &[&str]) {
+            match (args.get(0).and_then(|a| parse_number(a)), args.get(1).and_then(|a| parse_number(a))) {
+                (Some(start), Some(end)) => unsafe {
+                    crate::kernel_components::memory::ActivePageTable::new().dump(start..end)
+                },
+                (None, None) => unsafe { MEMORY_MANAGEMENT_UNIT.dump_mappings() },
+                _ => println!("usage: pagemap [start end]"),
+            }
+        }
+
+        /// Prints an error and returns false if the range is not mapped.
+        fn check_mapped(addr: usize, len: usize) -> bool {
+            let mapped = addr.checked_add(len)
+                .map(|end| unsafe { MEMORY_MANAGEMENT_UNIT.is_mapped(addr..end) })
+                .unwrap_or(false);
+
+            if !mapped {
+                log!(Error; "{:#x}..+{:#x}: address is not mapped", addr, len);
+            }
+            mapped
+        }
+
+        /// Prints an error and returns false if the shell process lacks the capability.
+        fn check_capability(command: &str, cap: Capability) -> bool {
+            match capability::require(cap) {
+                Ok(()) => true,
+                Err(err) => {
+                    log!(Error; "{}: {}", command, err);
+                    false
+                },
+            }
+        }
+
+        fn sysctl(&mut self, args: &[&str]) {
+            match args.first() {
+                None => for entry in unsafe { SYSCTL.lock() }.list() {
+                    println!("{} = {}", entry.name, entry.value);
+                },
+                Some(arg) => match arg.split_once('=') {
+                    Some((name, value)) => if let Err(err) = sysctl::set(name, value) {
+                        log!(Error; "sysctl: {}: {:?}", name, err);
+                    },
+                    None => match unsafe { SYSCTL.lock() }.get(arg) {
+                        Some(value) => println!("{} = {}", arg, value),
+                        None => log!(Error; "sysctl: {}: {:?}", arg, SysctlError::NotFound),
+                    },
+                },
+            }
+        }
+
+        fn rx(&mut self, args: &[&str]) {
+            let name = match args.first() {
+                Some(name) if tmpfs::is_valid_name(name) => *name,
+                Some(name) => return log!(Error; "rx: invalid file name '{}'", name),
+                None => return println!("usage: rx <name>"),
+            };
+            let Some(uart) = (unsafe { DRIVER_MANAGER.driver::<Uart16550>(DriverType::Serial) }) else {
+                return log!(Error; "rx: no serial port available")
+            };
+
+            println!("rx: waiting for the XMODEM sender on port {:#x}...", uart.base());
+            // The serial log would be mixed into the transfer.
+            let level = logging::sink_level("serial");
+            let _ = logging::set_sink_level("serial", None);
+            let limit = {
+                let fs = TMPFS.lock();
+                fs.available() + fs.read(name).map_or(0, <[u8]>::len)
+            };
+            let received = xmodem::receive(uart, limit);
+            if let Ok(level) = level {
+                let _ = logging::set_sink_level("serial", level);
+            }
+
+            match received {
+                Ok(data) => {
+                    let len = data.len();
+                    match TMPFS.lock().write(name, data) {
+                        Ok(()) => println!("rx: received {} bytes into {}", len, name),
+                        Err(err) => log!(Error; "rx: {}: {}", name, err),
+                    }
+                },
+                Err(err) => log!(Error; "rx: {}", err),
+            }
+        }
+
+        fn ls(&mut self, _: &[&str]) {
+            let fs = TMPFS.lock();
+            for (name, size) in fs.files() {
+                println!("{:>10}  {}", size, name);
+            }
+            println!("{} bytes used, {} bytes available", fs.used(), fs.available());
+        }
+
+        fn rm(&mut self, args: &[&str]) {
+            match args.first() {
+                Some(name) => if let Err(err) = TMPFS.lock().remove(name) {
+                    log!(Error; "rm: {}: {}", name, err);
+                },
+                None => println!("usage: rm <name>"),
+            }
+        }
+
+        fn swapon(&mut self, args: &[&str]) {
+            let kib = match args {
+                [] => return match unsafe { SWAP.as_ref() } {
+                    Some(space) => println!("{} of {} slots used", space.used(), space.slots()),
+                    None => println!("swap is off"),
+                },
+                [size] => match parse_number(size) {
+                    Some(kib) if kib > 0 => kib,
+                    _ => return println!("usage: swapon [size_kib]"),
+                },
+                _ => return println!("usage: swapon [size_kib]"),
+            };
+
+            let disk = RamDisk::new((kib * 1024).div_ceil(SECTOR_SIZE));
+            match swap::swapon(alloc::boxed::Box::new(disk)) {
+                Ok(()) => {
+                    let slots = unsafe { SWAP.as_ref() }.map_or(0, |space| space.slots());
+                    println!("swap enabled with {} slots", slots);
+                },
+                Err(err) => log!(Error; "swapon: {}", err),
+            }
+        }
+
+        fn swapoff(&mut self, _: &[&str]) {
+            if let Err(err) = swap::swapoff() {
+                log!(Error; "swapoff: {}", err);
+            }
+        }
+
+        fn irqlat(&mut self, args: &[&str]) {
+            match args.first() {
+                Some(&"reset") => crate::kernel_components::arch_x86_64::interrupts::latency::reset(),
+                _ => crate::print_irq_latency!(),
+            }
+        }
+
+        fn dmesg(&mut self, args: &[&str]) {
+            let max = match args {
+                [] => LogLevel::Trace,
+                ["clear"] => return DMESG.clear(),
+                [level] => match LogLevel::from_name(level) {
+                    Some(level) => level,
+                    None => return log!(Error; "dmesg: unknown level '{}'", level),
+                },
+                _ => return println!("usage: dmesg [level|clear]"),
+            };
+
+            DMESG.for_each(|message| if message.level <= max {
+                println!("{}", message);
+            });
+            if DMESG.dropped() != 0 {
+                println!("dmesg: {} lines dropped", DMESG.dropped());
+            }
+        }
+
+        fn strace(&mut self, args: &[&str]) {
+            match args {
+                [] => println!("traced: {:?}", audit::traced()),
+                [toggle @ ("on" | "off"), pid] => match parse_number(pid) {
+                    Some(pid) => audit::trace(pid, *toggle == "on"),
+                    None => log!(Error; "strace: invalid pid '{}'", pid),
+                },
+                ["log", rest @ ..] if rest.len() < 2 => {
+                    let pid = rest.first().and_then(|pid| parse_number(pid));
+                    if !rest.is_empty() && pid.is_none() {
+                        return log!(Error; "strace: invalid pid '{}'", rest[0])
+                    }
+                    audit::drain(pid).iter().for_each(|record| println!("{}", record));
+                    match audit::dropped() {
+                        0 => (),
+                        dropped => log!(Warning; "strace: {} records were dropped", dropped),
+                    }
+                },
+                ["clear"] => audit::clear(),
+                _ => println!("usage: strace [on|off <pid>|log [pid]|clear]"),
+            }
+        }
+
+        fn snapshot(&mut self, args: &[&str]) {
+            match args.first() {
+                None | Some(&"serial") => {
+                    console::snapshot();
+                    println!("snapshot: sent to the serial port");
+                },
+                Some(&"clip") => {
+                    let len = clipboard::copy(&console::text());
+                    println!("snapshot: copied {} bytes to the clipboard", len);
+                },
+                Some(target) => log!(Error; "snapshot: unknown target '{}'", target),
+            }
+        }
+
+        fn reboot(&mut self, _: &[&str]) {
+            if !Self::check_capability("reboot", Capability::REBOOT) {
+                return
+            }
+            println!("Rebooting...");
+            unsafe { PS2::new().reset_cpu() };
+        }
+
+        fn run(&mut self, args: &[&str]) {
+            let Some((name, cmdline)) = args.split_first() else {
+                return println!("usage: run <name> [cmdline]")
+            };
+            if !Self::check_capability("run", Capability::REBOOT) {
+                return
+            }
+
+            let loaded = match TMPFS.lock().read(name) {
+                Ok(image) if cmdline.is_empty() => kexec::load(image),
+                Ok(image) => kexec::load_with_cmdline(image, &cmdline.join(" ")),
+                Err(err) => return log!(Error; "run: {}: {}", name, err),
+            };
+            if let Err(err) = loaded {
+                return log!(Error; "run: {}: {}", name, err)
+            }
+            println!("Booting {}...", name);
+            let Err(err) = kexec::execute();
+            log!(Error; "run: {}", err);
+        }
+    }
+
+    /// Parses decimal or hexadecimal (0x prefixed) number.
+    fn parse_number(s: &str) -> Option<usize> {
+        match s.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        }
+    }
+
+    /// Kernel shell program.
+    ///
+    /// Spawns the keyboard listener thread which feeds every pressed key into the shell.
+    pub fn kshell(t: &mut Thread) {
+        let shell = Arc::new(Mutex::new(KShell::new()));
+        let mut k_interface = KeyboardInterface::new();
+
+        print!("{}", PROMPT);
+        k_interface.on_click(t, move |_, c| {
+            if let Some(c) = c {
+                shell.lock().input(*c);
+            }
+        });
+
+        loop {}
+    }
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, used_with_arg)]
+#![test_runner(notOS::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+//! Descriptor table pointer round-trip tests.
+//!
+//! Every test loads a freshly built table, reads the pointer back from the processor with "sgdt"
+//! or "sidt" and compares it with the table. A limit off by one is accepted by the processor and
+//! only faults much later on some far descriptor or vector, so it is checked right here. The
+//! tables of the boot code are restored after each test.
+
+use notOS::kernel_components::arch_x86_64::descriptor_table::{lgdt, lidt, sgdt, sidt};
+use notOS::kernel_components::arch_x86_64::interrupts::{GateDescriptor, IDT};
+use notOS::kernel_components::arch_x86_64::segmentation::{GDT, TSS};
+use notOS::{critical_section, single};
+
+#[link(name = "bootloader")]
+extern "C" {
+    fn initiate();
+    fn header_start();
+    fn header_end();
+}
+
+#[used]
+static INITIATE_FUNC: unsafe extern "C" fn() = initiate;
+#[used(linker)]
+static HEADER_START_FUNC: unsafe extern "C" fn() = header_start;
+#[used(linker)]
+static HEADER_END_FUNC: unsafe extern "C" fn() = header_end;
+
+single! {
+    TEST_TSS: TSS = TSS::new();
+    TEST_GDT: GDT = GDT::flat_setup(&TEST_TSS);
+    TEST_IDT: IDT = IDT::new_empty();
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    #[cfg(test)]
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn gdt_pointer_round_trip() {
+    let boot = sgdt();
+
+    TEST_GDT.load_table();
+    let loaded = sgdt();
+    unsafe { lgdt(&boot) };
+
+    assert_eq!(loaded, TEST_GDT.as_dt_ptr());
+    // Null, kernel and user code and data, and the two slots of the TSS descriptor.
+    assert_eq!(loaded.entries(8), Some(TEST_GDT.len()));
+    assert_eq!(TEST_GDT.len(), 7);
+}
+
+#[test_case]
+fn idt_pointer_round_trip() {
+    let boot = sidt();
+
+    // The empty table must not receive the ticks of the test watchdog.
+    let loaded = critical_section!(|| {
+        TEST_IDT.load_table();
+        let loaded = sidt();
+        lidt(&boot);
+        loaded
+    });
+
+    assert_eq!(loaded, TEST_IDT.as_dt_ptr());
+    assert_eq!({ loaded.addr }, TEST_IDT.addr() as u64);
+    assert_eq!({ loaded.size }, 0xfff);
+    assert_eq!(loaded.entries(core::mem::size_of::<GateDescriptor>()), Some(256));
+}
+
+#[test_case]
+fn limits_end_at_the_last_byte() {
+    let boot = sgdt();
+    assert_eq!(boot.entries(8).map(|entries| entries * 8 - 1), Some(boot.size as usize));
+
+    let ptr = notOS::kernel_components::arch_x86_64::DTPointer::new(0x1000, 3, 8);
+    assert_eq!({ ptr.size }, 23);
+    assert_eq!(ptr.entries(8), Some(3));
+    assert_eq!(ptr.entries(16), None);
+}
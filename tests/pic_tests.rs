@@ -0,0 +1,118 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, used_with_arg)]
+#![test_runner(notOS::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use notOS::kernel_components::arch_x86_64::controllers::pic::{
+    self, ChainedPics, IrqMask, PicOperationMode,
+};
+
+#[link(name = "bootloader")]
+extern "C" {
+    fn initiate();
+    fn header_start();
+    fn header_end();
+}
+
+#[used]
+static INITIATE_FUNC: unsafe extern "C" fn() = initiate;
+#[used(linker)]
+static HEADER_START_FUNC: unsafe extern "C" fn() = header_start;
+#[used(linker)]
+static HEADER_END_FUNC: unsafe extern "C" fn() = header_end;
+
+const MASTER_OFFSET: u8 = 32;
+const SLAVE_OFFSET: u8 = 40;
+
+const MODES: [PicOperationMode; 4] = [
+    PicOperationMode::FullyNested,
+    PicOperationMode::AutomaticRotation,
+    PicOperationMode::SpecialMask,
+    PicOperationMode::PolledMode,
+];
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    #[cfg(test)]
+    test_main();
+    loop {}
+}
+
+/// Initializes the chained PICs with every line masked, so no real IRQ disturbs the tests.
+fn pics() -> ChainedPics {
+    let mut pics = ChainedPics::new(MASTER_OFFSET, SLAVE_OFFSET);
+    pics.disable();
+    pics.initialize();
+    pics
+}
+
+#[test_case]
+fn every_operation_mode_transition() {
+    let mut pics = pics();
+
+    for from in MODES {
+        for to in MODES {
+            pics.operation_mode_change(from);
+            pics.operation_mode_change(to);
+
+            assert_eq!(pics.master.operation_mode_current(), to);
+            assert_eq!(pics.slave.operation_mode_current(), to);
+        }
+    }
+
+    pics.operation_mode_change(PicOperationMode::FullyNested);
+    pics.disable();
+    assert_eq!(pics.get_mask().bits(), IrqMask::all().bits());
+}
+
+#[test_case]
+fn polled_mode_masks_all_lines() {
+    let mut pics = pics();
+
+    pics.operation_mode_change(PicOperationMode::PolledMode);
+    assert_eq!(pics.get_mask().bits(), IrqMask::all().bits());
+    assert_eq!(pics.master.read_isr().bits(), 0);
+
+    pics.operation_mode_change(PicOperationMode::FullyNested);
+    assert!(pics.master.poll().is_none());
+}
+
+#[test_case]
+fn mask_survives_initialization() {
+    let mut pics = pics();
+    let mask = IrqMask::all() & !(IrqMask::IRQ3_SERIAL_PORT2 | IrqMask::IRQ10_FREE_1);
+
+    unsafe { pics.write_mask(mask) };
+    pics.initialize();
+    assert_eq!(pics.get_mask().bits(), mask.bits());
+
+    pics.disable();
+}
+
+#[test_case]
+fn injected_spurious_master_irq() {
+    let mut pics = pics();
+    let count = pic::spurious_count();
+
+    pic::inject_spurious(7);
+    assert!(IrqMask::IRQ7_PARALLEL_PORT1.is_in(pic::injected_spurious().bits()));
+    assert!(unsafe { pics.is_spurious(MASTER_OFFSET + 7) });
+
+    assert_eq!(pic::injected_spurious().bits(), 0);
+    assert_eq!(pic::spurious_count(), count + 1);
+}
+
+#[test_case]
+fn injected_spurious_slave_irq() {
+    let mut pics = pics();
+    let count = pic::spurious_count();
+
+    pic::inject_spurious(15);
+    assert!(unsafe { pics.is_spurious(SLAVE_OFFSET + 7) });
+
+    // The master received the EOI for the cascade line, so nothing stays in service.
+    assert_eq!(pic::injected_spurious().bits(), 0);
+    assert_eq!(pics.master.read_isr().bits(), 0);
+    assert_eq!(pic::spurious_count(), count + 1);
+}
@@ -0,0 +1,180 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, used_with_arg, abi_x86_interrupt)]
+#![test_runner(notOS::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+//! Suspend to RAM loopback tests.
+//!
+//! The machine is suspended with the RTC alarm armed as the wake source, so the firmware resumes
+//! it a few seconds later through the waking vector. The tests then check that the resume path
+//! brought the drivers, the timer and the wall clock back.
+//!
+//! QEMU hides S3 by default, so the tests must run with `-global PIIX4_PM.disable_s3=0`.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::any::Any;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use notOS::kernel_components::arch_x86_64::PrivilegeLevel;
+use notOS::kernel_components::arch_x86_64::acpi::sleep::suspend_to_ram;
+use notOS::kernel_components::arch_x86_64::controllers::{CMOSAddr, RTC, PROGRAMMABLE_INTERRUPT_CONTROLLER};
+use notOS::kernel_components::arch_x86_64::controllers::pic::ChainedPics;
+use notOS::kernel_components::arch_x86_64::controllers::apic::{LOCAL_APIC, SPURIOUS_VECTOR};
+use notOS::kernel_components::arch_x86_64::controllers::ioapic::init_from_madt;
+use notOS::kernel_components::arch_x86_64::interrupts::{
+    def_exceptions::{DOUBLE_FAULT, PAGE_FAULT},
+    def_interrupts::APIC_SPURIOUS_INTERRUPT,
+    handler_functions::{HandlerFunction, InterruptStackFrame},
+    GateDescriptor, InterruptVector, INTERRUPT_DESCRIPTOR_TABLE, enable,
+};
+use notOS::kernel_components::arch_x86_64::segmentation::{TSS, GDT, GLOBAL_DESCRIPTOR_TABLE};
+use notOS::kernel_components::arch_x86_64::smp;
+use notOS::kernel_components::drivers::{Driver, DriverType, DRIVER_MANAGER};
+use notOS::kernel_components::drivers::timers::{ApicTimer, ClockDriver, RealTimeClock, apic_timer::TICK_HZ};
+use notOS::kernel_components::memory::{bootmem, MEMORY_MANAGEMENT_UNIT};
+use notOS::kernel_components::registers::segment_regs::{CodeSegment, Segment, SegmentSelector, StackSegment};
+use notOS::{single, GLOBAL_ALLOCATOR, FREE_LIST_ALLOC};
+
+#[link(name = "bootloader")]
+extern "C" {
+    fn initiate();
+    fn header_start();
+    fn header_end();
+}
+
+#[used]
+static INITIATE_FUNC: unsafe extern "C" fn() = initiate;
+#[used(linker)]
+static HEADER_START_FUNC: unsafe extern "C" fn() = header_start;
+#[used(linker)]
+static HEADER_END_FUNC: unsafe extern "C" fn() = header_end;
+
+/// Vector of the local APIC timer.
+const TICK_VECTOR: u8 = 0x40;
+/// Seconds until the RTC alarm wakes the machine.
+const WAKE_AFTER_S: u32 = 3;
+
+/// Amount of calls of the suspend and resume hooks of the test driver.
+static SUSPENDED: AtomicUsize = AtomicUsize::new(0);
+static RESUMED: AtomicUsize = AtomicUsize::new(0);
+
+single! {
+    mut TASK_STATE_SEGMENT: TSS = TSS::new();
+}
+
+/// Driver counting the calls of it's power management hooks.
+struct HookCounter;
+
+impl Driver for HookCounter {
+    fn as_driver(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "HookCounter"
+    }
+
+    fn suspend(&mut self) {
+        SUSPENDED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn resume(&mut self) {
+        RESUMED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+extern "x86-interrupt" fn tick_handler(_: InterruptStackFrame) {
+    ApicTimer::handle_interrupt();
+    LOCAL_APIC.end_of_interrupt();
+}
+
+const TICK_INTERRUPT: HandlerFunction = tick_handler;
+
+#[no_mangle]
+pub extern "C" fn _start(multiboot_information_address: usize) -> ! {
+    unsafe {
+        // The waking vector lives in the low page reserved for the AP trampoline.
+        bootmem::init(multiboot_information_address);
+        smp::reserve_trampoline();
+        GLOBAL_ALLOCATOR.r#use(&FREE_LIST_ALLOC);
+        MEMORY_MANAGEMENT_UNIT.init(multiboot_information_address).unwrap();
+
+        // The resume path reloads the GDT and the TSS of the BSP.
+        MEMORY_MANAGEMENT_UNIT.set_interrupt_stack(&mut TASK_STATE_SEGMENT, 0, 1).unwrap();
+        GLOBAL_DESCRIPTOR_TABLE.reinit(GDT::flat_setup(&TASK_STATE_SEGMENT));
+        GLOBAL_DESCRIPTOR_TABLE.load_table();
+        CodeSegment::write(SegmentSelector::new(1, false, PrivilegeLevel::KernelLevel));
+        StackSegment::write(SegmentSelector::new(2, false, PrivilegeLevel::KernelLevel));
+        TSS::write(SegmentSelector::new(5, false, PrivilegeLevel::KernelLevel));
+
+        INTERRUPT_DESCRIPTOR_TABLE.push(InterruptVector::DOUBLE_FAULT, GateDescriptor::new_trap(DOUBLE_FAULT));
+        INTERRUPT_DESCRIPTOR_TABLE.push(InterruptVector::PAGE_FAULT, GateDescriptor::new_trap(PAGE_FAULT));
+        INTERRUPT_DESCRIPTOR_TABLE.push(
+            InterruptVector::APICMappings(TICK_VECTOR as usize), GateDescriptor::new_interrupt(TICK_INTERRUPT)
+        );
+        INTERRUPT_DESCRIPTOR_TABLE.push(
+            InterruptVector::APICMappings(SPURIOUS_VECTOR as usize), GateDescriptor::new_interrupt(APIC_SPURIOUS_INTERRUPT)
+        );
+        INTERRUPT_DESCRIPTOR_TABLE.load_table();
+
+        // Every IRQ line stays masked, only the local APIC timer ticks.
+        let mut pics = ChainedPics::new_contiguous(32);
+        pics.initialize();
+        pics.disable();
+        PROGRAMMABLE_INTERRUPT_CONTROLLER.lock().replace(pics);
+        if init_from_madt(32).is_err() {
+            LOCAL_APIC.init(SPURIOUS_VECTOR).unwrap();
+        }
+
+        let mut timer = ApicTimer::new(TICK_VECTOR).unwrap();
+        timer.start_periodic(TICK_HZ).unwrap();
+        let clock: Box<dyn ClockDriver> = Box::new(timer);
+        DRIVER_MANAGER.load(clock, DriverType::Clock).unwrap();
+        DRIVER_MANAGER.load(HookCounter, DriverType::Power).unwrap();
+        enable();
+    }
+
+    #[cfg(test)]
+    test_main();
+    loop {}
+}
+
+/// Returns the clock driver loaded on startup.
+fn clock() -> &'static mut Box<dyn ClockDriver> {
+    unsafe { DRIVER_MANAGER.driver::<Box<dyn ClockDriver>>(DriverType::Clock) }.unwrap()
+}
+
+#[test_case]
+fn suspend_to_ram_runs_hooks_and_resyncs_clock() {
+    let mut rtc = RealTimeClock::new();
+    rtc.set_alarm(WAKE_AFTER_S);
+    let result = suspend_to_ram();
+    // Both are read right after the resync, so they differ by less than the RTC resolution,
+    // while a clock which was not resynced lags behind by the time spent asleep.
+    let (now, rtc_now) = (clock().now(), rtc.now());
+    rtc.clear_alarm();
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(SUSPENDED.load(Ordering::SeqCst), 1);
+    assert_eq!(RESUMED.load(Ordering::SeqCst), 1);
+    assert!(now.abs_diff(rtc_now) < 1000, "The clock tells {} ms, while the RTC tells {} ms.", now, rtc_now);
+}
+
+#[test_case]
+fn timer_ticks_after_resume() {
+    let ticks = ApicTimer::periodic_ticks();
+    assert!(ApicTimer::is_running());
+
+    // Waiting for two updates of the RTC gives the timer at least a second.
+    let rtc = RTC::new();
+    for _ in 0..2 {
+        let second = rtc.read(CMOSAddr::RTC_SECONDS);
+        while rtc.read(CMOSAddr::RTC_SECONDS) == second {
+            core::hint::spin_loop();
+        }
+    }
+    assert!(ApicTimer::periodic_ticks() > ticks, "No timer ticks after the resume.");
+}
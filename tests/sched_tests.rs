@@ -0,0 +1,339 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, used_with_arg)]
+#![test_runner(notOS::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+//! Scheduler fairness tests.
+//!
+//! Every test registers a set of CPU bound tasks that never block and drives the scheduler the
+//! same way the timer interrupt does, one schedule() call per tick. The amount of ticks received by
+//! each task is counted and compared to its fair share, so starvation regressions of the
+//! schedulers are caught without relying on the timing of the machine.
+//!
+//! The last test runs real threads instead, which are preempted by the ticks of the timer handler
+//! of the kernel, and counts the time slices each of them received.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::any::Any;
+use core::arch::asm;
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use notOS::kernel_components::arch_x86_64::controllers::{pic::ChainedPics, PROGRAMMABLE_INTERRUPT_CONTROLLER};
+use notOS::kernel_components::arch_x86_64::interrupts::{
+    self, def_exceptions::{idt_install_default_exceptions, DOUBLE_FAULT}, def_interrupts::TIMER_INTERRUPT,
+    GateDescriptor, InterruptVector, INTERRUPT_DESCRIPTOR_TABLE,
+};
+use notOS::kernel_components::memory::{MEMORY_MANAGEMENT_UNIT, stack_allocator::Stack};
+use notOS::kernel_components::task_virtualization::{
+    Scheduler, Task, Process, ProcState, Thread, ThreadState, RoundRobin, PriorityScheduler,
+    PROCESS_MANAGEMENT_UNIT, ROUND_ROBIN,
+};
+use notOS::{GLOBAL_ALLOCATOR, FREE_LIST_ALLOC};
+
+#[link(name = "bootloader")]
+extern "C" {
+    fn initiate();
+    fn header_start();
+    fn header_end();
+}
+
+#[used]
+static INITIATE_FUNC: unsafe extern "C" fn() = initiate;
+#[used(linker)]
+static HEADER_START_FUNC: unsafe extern "C" fn() = header_start;
+#[used(linker)]
+static HEADER_END_FUNC: unsafe extern "C" fn() = header_end;
+
+/// Amount of simulated timer ticks per test.
+const TICKS: usize = 1200;
+/// Amount of CPU bound tasks.
+const TASKS: usize = 6;
+/// Allowed deviation from the fair share in percents.
+const TOLERANCE_PERCENT: usize = 5;
+/// Amount of threads preempted by the timer.
+const BUSY_THREADS: usize = 3;
+/// Time slices every preempted thread must receive.
+const SLICES: u64 = 20;
+/// TSC cycles after which the preempted threads are given up on.
+const PREEMPTION_TIMEOUT_CYCLES: u64 = 1 << 35;
+
+/// Time slices received by each preempted thread.
+static BUSY_SLICES: [AtomicU64; BUSY_THREADS] = [const { AtomicU64::new(0) }; BUSY_THREADS];
+/// Index of the thread which ran last, all ones for the body of the test.
+static LAST_RUNNING: AtomicU64 = AtomicU64::new(u64::MAX);
+/// TSC value at which the body of the test stops waiting for the threads.
+static PREEMPTION_DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+/// Defines a thread which counts it's time slices forever.
+///
+/// A task switch only restores the instruction and stack pointers, so the loop keeps nothing in
+/// the registers. A new slice is noticed by another thread having run last.
+macro_rules! busy_thread {
+    ($name:ident, $index:literal) => {
+        fn $name(_: &mut Thread) {
+            unsafe {
+                asm!(
+                    "2:",
+                    "cmp qword ptr [rip + {last}], {index}",
+                    "je 2b",
+                    "mov qword ptr [rip + {last}], {index}",
+                    "lock inc qword ptr [rip + {slices} + {offset}]",
+                    "jmp 2b",
+                    last = sym LAST_RUNNING,
+                    slices = sym BUSY_SLICES,
+                    index = const $index,
+                    offset = const $index * 8,
+                    options(noreturn),
+                )
+            }
+        }
+    };
+}
+
+busy_thread!(busy_0, 0);
+busy_thread!(busy_1, 1);
+busy_thread!(busy_2, 2);
+
+#[no_mangle]
+pub extern "C" fn _start(multiboot_information_address: usize) -> ! {
+    // Schedulers keep their tasks on the heap.
+    unsafe {
+        GLOBAL_ALLOCATOR.r#use(&FREE_LIST_ALLOC);
+        MEMORY_MANAGEMENT_UNIT.init(multiboot_information_address).unwrap();
+    }
+
+    #[cfg(test)]
+    test_main();
+    loop {}
+}
+
+/// Drives the scheduler for the provided amount of ticks and counts ticks of every task.
+fn run_ticks<S: Scheduler>(scheduler: &mut S, ticks: usize) -> BTreeMap<Task, usize> {
+    let mut counters = BTreeMap::new();
+    for _ in 0..ticks {
+        let task = *scheduler.schedule().expect("The scheduler has no tasks.");
+        *counters.entry(task).or_insert(0) += 1;
+    }
+    counters
+}
+
+/// Asserts that every task received its fair share within the tolerance.
+fn assert_fair(counters: &BTreeMap<Task, usize>, tasks: usize, ticks: usize) {
+    let share = ticks / tasks;
+    let tolerance = share * TOLERANCE_PERCENT / 100;
+
+    assert_eq!(counters.len(), tasks, "Some tasks were starved: {:?}", counters);
+    for (task, &count) in counters {
+        assert!(
+            count.abs_diff(share) <= tolerance,
+            "{:?} received {} ticks, while the fair share is {}.", task, count, share
+        );
+    }
+}
+
+/// Queues processes with the provided priorities and returns their pids.
+fn spawn_processes(first_pid: usize, priorities: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    priorities.iter().enumerate().map(move |(i, &priority)| {
+        let pid = first_pid + i;
+        // The processes never run, so the stack is never touched.
+        let stack = Stack { top: 0x2000, bottom: 0x1000 };
+
+        unsafe {
+            PROCESS_MANAGEMENT_UNIT.queue(Process::new_nomain(stack, 0, pid, priority, None));
+            PROCESS_MANAGEMENT_UNIT.dequeue();
+        }
+        pid
+    })
+}
+
+fn remove_processes(pids: impl Iterator<Item = usize>) {
+    for pid in pids {
+        let _ = unsafe { PROCESS_MANAGEMENT_UNIT.remove(pid) };
+    }
+}
+
+#[test_case]
+fn round_robin_fair_share() {
+    let mut scheduler = RoundRobin::new();
+    for tid in 0..TASKS {
+        scheduler.append(unsafe { Task::new(1, tid) });
+    }
+
+    assert_fair(&run_ticks(&mut scheduler, TICKS), TASKS, TICKS);
+}
+
+#[test_case]
+fn round_robin_fair_share_after_removal() {
+    let mut scheduler = RoundRobin::new();
+    for tid in 0..TASKS {
+        scheduler.append(unsafe { Task::new(1, tid) });
+    }
+    run_ticks(&mut scheduler, TICKS / 2);
+    scheduler.delete(unsafe { Task::new(1, 0) });
+
+    assert_fair(&run_ticks(&mut scheduler, TICKS), TASKS - 1, TICKS);
+}
+
+#[test_case]
+fn priority_scheduler_fair_share_with_equal_priorities() {
+    let mut scheduler = PriorityScheduler::new();
+    let pids: alloc::vec::Vec<_> = spawn_processes(100, &[10; TASKS]).collect();
+    for &pid in pids.iter() {
+        scheduler.append(unsafe { Task::new(pid, 0) });
+    }
+
+    assert_fair(&run_ticks(&mut scheduler, TICKS), TASKS, TICKS);
+    remove_processes(pids.into_iter());
+}
+
+#[test_case]
+fn priority_scheduler_serves_most_significant() {
+    let mut scheduler = PriorityScheduler::new();
+    let pids: alloc::vec::Vec<_> = spawn_processes(200, &[20, 5, 5, 20]).collect();
+    for &pid in pids.iter() {
+        scheduler.append(unsafe { Task::new(pid, 0) });
+    }
+
+    let counters = run_ticks(&mut scheduler, TICKS);
+    // Only the two processes with priority 5 are served, each one half of the time.
+    assert_fair(&counters, 2, TICKS);
+    assert!(counters.keys().all(|task| *task == unsafe { Task::new(201, 0) } || *task == unsafe { Task::new(202, 0) }));
+    remove_processes(pids.into_iter());
+}
+
+#[test_case]
+fn faulted_process_threads_never_run_again() {
+    let pid = spawn_processes(300, &[10]).next().unwrap();
+
+    unsafe {
+        PROCESS_MANAGEMENT_UNIT.process_list.lock()
+            .get_mut(pid)
+            .unwrap()
+            .spawn(None, |_: &mut Thread| Box::new(()) as Box<dyn Any>);
+        // The thread must never be picked by the timer of the test kernel.
+        ROUND_ROBIN.delete(Task::new(pid, 0));
+
+        assert_eq!(PROCESS_MANAGEMENT_UNIT.kill_faulted(pid), Ok(()));
+        assert_eq!(PROCESS_MANAGEMENT_UNIT.kill_faulted(pid + 1), Err(()));
+
+        let mut list = PROCESS_MANAGEMENT_UNIT.process_list.lock();
+        let process = list.get(pid).unwrap();
+        assert_eq!(process.proc_state, ProcState::FAULTED);
+        assert_eq!(process.find_thread(0).map(|thread| thread.thread_state.clone()), Some(ThreadState::PANICKED));
+    }
+    remove_processes(core::iter::once(pid));
+}
+
+#[test_case]
+fn finished_process_is_unlinked_and_reaped() {
+    let pids: alloc::vec::Vec<_> = spawn_processes(400, &[10, 10, 10]).collect();
+
+    unsafe {
+        let len = PROCESS_MANAGEMENT_UNIT.process_list.lock().len();
+        PROCESS_MANAGEMENT_UNIT.process_list.lock().get_mut(pids[1]).unwrap().proc_state = ProcState::FINAL;
+        assert_eq!(PROCESS_MANAGEMENT_UNIT.remove(pids[1]), Ok(()));
+        assert_eq!(PROCESS_MANAGEMENT_UNIT.remove(pids[1]), Err(()));
+
+        let mut list = PROCESS_MANAGEMENT_UNIT.process_list.lock();
+        assert_eq!(list.len(), len - 1);
+        assert!(list.get(pids[0]).is_some() && list.get(pids[2]).is_some());
+        assert!(list.get(pids[1]).is_none());
+        drop(list);
+
+        assert_eq!(PROCESS_MANAGEMENT_UNIT.reap(), 1);
+        assert_eq!(PROCESS_MANAGEMENT_UNIT.reap(), 0);
+    }
+    remove_processes([pids[0], pids[2]].into_iter());
+}
+
+// Must stay the last test, as the timer handler of the kernel replaces the watchdog of the runner.
+#[test_case]
+fn timer_preempts_busy_threads_in_turns() {
+    let pid = spawn_processes(500, &[1]).next().unwrap();
+
+    unsafe {
+        interrupts::disable();
+        assert!(ROUND_ROBIN.current().is_none(), "Tasks of the previous tests were left.");
+
+        // The body of the test runs as the current task, so the timer switches back to it.
+        let mut list = PROCESS_MANAGEMENT_UNIT.process_list.lock();
+        let process = list.get_mut(pid).unwrap();
+        process.spawn(None, |_: &mut Thread| Box::new(()) as Box<dyn Any>);
+        process.find_thread_mut(0).unwrap().thread_state = ThreadState::RUNNING;
+        drop(list);
+        ROUND_ROBIN.schedule();
+
+        let busy: [fn(&mut Thread); BUSY_THREADS] = [busy_0, busy_1, busy_2];
+        for (i, thread) in busy.into_iter().enumerate() {
+            let stack = MEMORY_MANAGEMENT_UNIT.allocate_stack(4).unwrap();
+            PROCESS_MANAGEMENT_UNIT.queue(Process::new_void(stack, 0, pid + 1 + i, 1, None, thread));
+            PROCESS_MANAGEMENT_UNIT.dequeue();
+        }
+
+        // The runner left the PIC on the same vectors with only the PIT unmasked, ticking at the
+        // frequency of the watchdog.
+        idt_install_default_exceptions(&mut INTERRUPT_DESCRIPTOR_TABLE);
+        INTERRUPT_DESCRIPTOR_TABLE.push(InterruptVector::DOUBLE_FAULT, GateDescriptor::new_trap(DOUBLE_FAULT));
+        INTERRUPT_DESCRIPTOR_TABLE.push(InterruptVector::Custom(32), GateDescriptor::new_interrupt(TIMER_INTERRUPT));
+        INTERRUPT_DESCRIPTOR_TABLE.fill_unhandled();
+        INTERRUPT_DESCRIPTOR_TABLE.load_table();
+        PROGRAMMABLE_INTERRUPT_CONTROLLER.lock().replace(ChainedPics::new_contiguous(32));
+
+        // Interrupts are only enabled while nothing is kept in the scratch registers, the callee
+        // saved ones are kept on the stack. The loop exits with interrupts disabled.
+        PREEMPTION_DEADLINE.store(_rdtsc() + PREEMPTION_TIMEOUT_CYCLES, Ordering::Release);
+        asm!(
+            "push rbx",
+            "push rbp",
+            "push r12",
+            "push r13",
+            "push r14",
+            "push r15",
+            "2:",
+            "mov qword ptr [rip + {last}], -1",
+            "sti",
+            "pause",
+            "cli",
+            "rdtsc",
+            "shl rdx, 32",
+            "or rax, rdx",
+            "cmp rax, qword ptr [rip + {deadline}]",
+            "jae 3f",
+            "cmp qword ptr [rip + {slices}], {count}",
+            "jb 2b",
+            "cmp qword ptr [rip + {slices} + 8], {count}",
+            "jb 2b",
+            "cmp qword ptr [rip + {slices} + 16], {count}",
+            "jb 2b",
+            "3:",
+            "pop r15",
+            "pop r14",
+            "pop r13",
+            "pop r12",
+            "pop rbp",
+            "pop rbx",
+            last = sym LAST_RUNNING,
+            deadline = sym PREEMPTION_DEADLINE,
+            slices = sym BUSY_SLICES,
+            count = const SLICES,
+            out("rax") _,
+            out("rdx") _,
+        );
+
+        for pid in pid..=pid + BUSY_THREADS {
+            ROUND_ROBIN.delete(Task::new(pid, 0));
+        }
+    }
+    remove_processes(pid..=pid + BUSY_THREADS);
+
+    let slices = BUSY_SLICES.each_ref().map(|slices| slices.load(Ordering::Acquire));
+    let (min, max) = (*slices.iter().min().unwrap(), *slices.iter().max().unwrap());
+    assert!(min >= SLICES, "Some threads were starved: {:?}", slices);
+    // Every thread is served once per round, so none of them gets ahead.
+    assert!(max - min <= SLICES / 4, "The threads were not served in turns: {:?}", slices);
+}